@@ -0,0 +1,297 @@
+/* bookkeeping of qq alarms in binary heap keyed by wake time, alternative to `AlarmTable` for larger number of alarms */
+
+use heapless::Vec;
+
+use crate::alarm_table::{next_periodic_wake_at, AlarmBook, OwnerTag, QQAlarmError, TargetChange};
+
+
+
+#[derive(Debug, Clone, Copy)]
+struct HeapEntry {
+    id: usize,
+    owner: OwnerTag,
+    wake_at: u64,
+    period: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct PendingAlarm {
+    id: usize,
+    owner: OwnerTag,
+    /// periodic alarm has its entry in heap even when it is pending
+    periodic: bool,
+}
+
+
+/// Waiting alarms are kept in min heap (by `wake_at`), fired alarms are moved to pending list.
+///
+/// Adding alarm and firing alarm is O(log n), next wake time is O(1) (top of the heap).
+/// Removing alarm has to find it first (O(n)), it is expected to be less common than firing.
+pub struct AlarmHeap<const N: usize> {
+    heap: Vec<HeapEntry, N>,
+    pending: Vec<PendingAlarm, N>,
+    next_id: usize,
+    overflow_count: u32,
+}
+
+impl<const N: usize> AlarmHeap<N> {
+    pub fn new() -> Self {
+        Self {
+            heap: Vec::new(),
+            pending: Vec::new(),
+            next_id: 0,
+            overflow_count: 0,
+        }
+    }
+
+    fn next_wakeup(&self) -> Option<u64> {
+        self.heap.first().map(|entry| entry.wake_at)
+    }
+
+    fn target_change(before: Option<u64>, after: Option<u64>) -> TargetChange {
+        match (before, after) {
+            (None, Some(wake_at)) => TargetChange::Enable(wake_at),
+            (Some(before), Some(after)) if before != after => TargetChange::Set(after),
+            (Some(_), None) => TargetChange::Disable,
+            _ => TargetChange::Keep,
+        }
+    }
+
+    fn sift_up(&mut self, mut i: usize) {
+        while i > 0 {
+            let parent = (i - 1) / 2;
+
+            if self.heap[parent].wake_at <= self.heap[i].wake_at {
+                break;
+            }
+
+            self.heap.swap(parent, i);
+            i = parent;
+        }
+    }
+
+    fn sift_down(&mut self, mut i: usize) {
+        loop {
+            let min = [2 * i + 1, 2 * i + 2].into_iter()
+                .filter(|&child| child < self.heap.len())
+                .fold(i, |min, child| if self.heap[child].wake_at < self.heap[min].wake_at { child } else { min });
+
+            if min == i {
+                break;
+            }
+
+            self.heap.swap(min, i);
+            i = min;
+        }
+    }
+
+    fn push(&mut self, entry: HeapEntry) {
+        // cannot fail, number of entries is checked by `insert`
+        let _ = self.heap.push(entry);
+        self.sift_up(self.heap.len() - 1);
+    }
+
+    fn remove_at(&mut self, i: usize) -> HeapEntry {
+        let entry = self.heap.swap_remove(i);
+
+        if i < self.heap.len() {
+            self.sift_up(i);
+            self.sift_down(i);
+        }
+
+        entry
+    }
+
+    fn insert(&mut self, owner: OwnerTag, wake_at: u64, period: Option<u64>) -> Result<(usize, TargetChange), QQAlarmError> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        if self.len() == N {
+            self.overflow_count = self.overflow_count.saturating_add(1);
+            return Err(QQAlarmError::QueueFull);
+        }
+
+        let before = self.next_wakeup();
+        self.push(HeapEntry { id, owner, wake_at, period });
+
+        Ok((id, Self::target_change(before, self.next_wakeup())))
+    }
+
+    pub fn add(&mut self, owner: OwnerTag, wake_at: u64) -> Result<(usize, TargetChange), QQAlarmError> {
+        self.insert(owner, wake_at, None)
+    }
+
+    /// see `AlarmTable::add_periodic`
+    pub fn add_periodic(&mut self, owner: OwnerTag, now: u64, period: u64, phase: u64) -> Result<(usize, TargetChange), QQAlarmError> {
+        let period = period.max(1);
+        self.insert(owner, next_periodic_wake_at(now, period, phase), Some(period))
+    }
+
+    pub fn remove(&mut self, id: usize) -> Result<TargetChange, QQAlarmError> {
+        let before = self.next_wakeup();
+
+        let in_heap = self.heap.iter().position(|entry| entry.id == id).map(|i| self.remove_at(i)).is_some();
+        let in_pending = self.pending.iter().position(|pending| pending.id == id).map(|i| self.pending.swap_remove(i)).is_some();
+
+        if !in_heap && !in_pending {
+            return Err(QQAlarmError::IdNotFound);
+        }
+
+        Ok(Self::target_change(before, self.next_wakeup()))
+    }
+
+    /// Called when timer alarm fired, alarms with `wake_at <= now` become pending, periodic alarms are re-armed for their next period.
+    /// Returns `Set` (with the earliest remaining wake time) or `Disable`.
+    pub fn on_timer(&mut self, now: u64) -> TargetChange {
+        while let Some(&entry) = self.heap.first() && entry.wake_at <= now {
+            self.remove_at(0);
+
+            if !self.pending.iter().any(|pending| pending.id == entry.id) {
+                // cannot fail, every alarm is at most once in pending list
+                let _ = self.pending.push(PendingAlarm { id: entry.id, owner: entry.owner, periodic: entry.period.is_some() });
+            }
+
+            if let Some(period) = entry.period {
+                self.push(HeapEntry { wake_at: next_periodic_wake_at(now, period, entry.wake_at), ..entry });
+            }
+        }
+
+        match self.next_wakeup() {
+            Some(min_wake_at) => TargetChange::Set(min_wake_at),
+            None => TargetChange::Disable,
+        }
+    }
+
+    /// returned iterator should be fully consumed to free up space in queue (see `AlarmTable::consume_pending`)
+    pub fn consume_pending(&mut self) -> Option<impl Iterator<Item = (OwnerTag, usize)> + '_> {
+        if self.pending.is_empty() {
+            return None;
+        }
+
+        Some(core::iter::from_fn(|| self.pending.pop().map(|pending| (pending.owner, pending.id))))
+    }
+
+    /// number of alarms (waiting and pending)
+    pub fn len(&self) -> usize {
+        self.heap.len() + self.pending.iter().filter(|pending| !pending.periodic).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    /// number of `add` calls which failed because the heap was full
+    pub fn overflow_count(&self) -> u32 {
+        self.overflow_count
+    }
+}
+
+impl<const N: usize> Default for AlarmHeap<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> AlarmBook for AlarmHeap<N> {
+    fn add(&mut self, owner: OwnerTag, wake_at: u64) -> Result<(usize, TargetChange), QQAlarmError> {
+        AlarmHeap::add(self, owner, wake_at)
+    }
+
+    fn add_periodic(&mut self, owner: OwnerTag, now: u64, period: u64, phase: u64) -> Result<(usize, TargetChange), QQAlarmError> {
+        AlarmHeap::add_periodic(self, owner, now, period, phase)
+    }
+
+    fn remove(&mut self, id: usize) -> Result<TargetChange, QQAlarmError> {
+        AlarmHeap::remove(self, id)
+    }
+
+    fn on_timer(&mut self, now: u64) -> TargetChange {
+        AlarmHeap::on_timer(self, now)
+    }
+
+    fn consume_pending(&mut self) -> Option<impl Iterator<Item = (OwnerTag, usize)> + '_> {
+        AlarmHeap::consume_pending(self)
+    }
+
+    fn len(&self) -> usize {
+        AlarmHeap::len(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        AlarmHeap::is_empty(self)
+    }
+
+    fn capacity(&self) -> usize {
+        AlarmHeap::capacity(self)
+    }
+
+    fn overflow_count(&self) -> u32 {
+        AlarmHeap::overflow_count(self)
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+
+    #[test]
+    fn heap_fires_alarms_in_wake_order() {
+        let mut heap = AlarmHeap::<8>::new();
+
+        for wake_at in [500, 100, 400, 200, 300] {
+            heap.add(0, wake_at).unwrap();
+        }
+
+        assert_eq!(heap.on_timer(250), TargetChange::Set(300));
+
+        let mut fired = heap.consume_pending().unwrap().map(|(_, id)| id).collect::<Vec<_>>();
+        fired.sort();
+        assert_eq!(fired, [1, 3]);
+
+        assert!(heap.consume_pending().is_none());
+        assert_eq!(heap.len(), 3);
+    }
+
+    #[test]
+    fn heap_matches_table_target_changes() {
+        let mut heap = AlarmHeap::<2>::new();
+
+        assert_eq!(heap.add(0, 100).unwrap(), (0, TargetChange::Enable(100)));
+        assert_eq!(heap.add(0, 200).unwrap(), (1, TargetChange::Keep));
+        assert_eq!(heap.add(0, 50), Err(QQAlarmError::QueueFull));
+        assert_eq!(heap.overflow_count(), 1);
+
+        assert_eq!(heap.remove(0), Ok(TargetChange::Set(200)));
+        assert_eq!(heap.remove(0), Err(QQAlarmError::IdNotFound));
+        assert_eq!(heap.remove(1), Ok(TargetChange::Disable));
+    }
+
+    #[test]
+    fn heap_periodic_alarm_is_rearmed_until_removed() {
+        let mut heap = AlarmHeap::<2>::new();
+
+        let (id, _) = heap.add_periodic(0, 130, 100, 0).unwrap();
+        heap.add(0, 250).unwrap();
+
+        assert_eq!(heap.on_timer(320), TargetChange::Set(400));
+        assert_eq!(heap.len(), 2);
+
+        let mut fired = heap.consume_pending().unwrap().map(|(_, id)| id).collect::<Vec<_>>();
+        fired.sort();
+        assert_eq!(fired, [id, 1]);
+        assert_eq!(heap.len(), 1);
+
+        assert_eq!(heap.remove(id), Ok(TargetChange::Disable));
+        assert_eq!(heap.len(), 0);
+        assert!(heap.is_empty());
+    }
+}
@@ -0,0 +1,410 @@
+/* bookkeeping of qq alarms (ids, wake times, pending state), timer hardware is driven by the caller (see `TargetChange`) */
+
+use core::iter;
+
+use crate::invariants::invariant;
+
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QQAlarmError {
+    QueueFull,
+    IdNotFound,
+}
+
+
+/// Tag of machine which added the alarm, it is returned together with alarm id by `consume_pending` (meaning of tags is up to the caller).
+pub type OwnerTag = u8;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum QQAlarmState { Waiting, Pending }
+
+#[derive(Debug, Clone, Copy)]
+struct QQAlarm {
+    id: usize,
+    owner: OwnerTag,
+    wake_at: u64,
+    state: QQAlarmState,
+    /// periodic alarm is re-armed when it fires and it is kept in table after it is consumed (until it is removed)
+    period: Option<u64>,
+}
+
+impl QQAlarm {
+    /// Periodic alarm is always waiting for its next period (even when it is pending).
+    fn is_waiting(&self) -> bool {
+        self.state == QQAlarmState::Waiting || self.period.is_some()
+    }
+}
+
+
+/// First wake time after `now` of periodic alarm, wake times are `phase + k * period` (period has to be non zero).
+pub fn next_periodic_wake_at(now: u64, period: u64, phase: u64) -> u64 {
+    if now < phase {
+        phase
+    } else {
+        phase + ((now - phase) / period + 1) * period
+    }
+}
+
+
+/// What has to be done with the timer alarm after the table was changed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TargetChange {
+    Keep,
+    /// timer alarm was disabled, clear its interrupt, enable it and set target
+    Enable(u64),
+    Set(u64),
+    Disable,
+}
+
+
+/// Bookkeeping of qq alarms used by qq alarm queues (`AlarmTable` or `AlarmHeap`), see methods of `AlarmTable` for description.
+pub trait AlarmBook: Default {
+    fn add(&mut self, owner: OwnerTag, wake_at: u64) -> Result<(usize, TargetChange), QQAlarmError>;
+    fn add_periodic(&mut self, owner: OwnerTag, now: u64, period: u64, phase: u64) -> Result<(usize, TargetChange), QQAlarmError>;
+    fn remove(&mut self, id: usize) -> Result<TargetChange, QQAlarmError>;
+    fn on_timer(&mut self, now: u64) -> TargetChange;
+    fn consume_pending(&mut self) -> Option<impl Iterator<Item = (OwnerTag, usize)> + '_>;
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool;
+    fn capacity(&self) -> usize;
+    fn overflow_count(&self) -> u32;
+}
+
+
+/// Fixed size table of alarms, no algorithms are used for optimaztion (e.g.: priority queues, ...)
+pub struct AlarmTable<const N: usize> {
+    queue: [Option<QQAlarm>; N],
+    next_wakeup: Option<u64>,
+    next_id: usize,
+    any_pending: bool,
+    overflow_count: u32,
+}
+
+impl<const N: usize> AlarmTable<N> {
+    pub fn new() -> Self {
+        Self {
+            queue: [None; N],
+            next_wakeup: None,
+            next_id: 0,
+            any_pending: false,
+            overflow_count: 0,
+        }
+    }
+
+    fn min_waiting_wake_at(&self) -> Option<u64> {
+        self.queue.iter()
+            .filter_map(|qq_alarm| qq_alarm.as_ref())
+            .filter(|qq_alarm| qq_alarm.is_waiting())
+            .map(|qq_alarm| qq_alarm.wake_at)
+            .min()
+    }
+
+    pub fn add(&mut self, owner: OwnerTag, wake_at: u64) -> Result<(usize, TargetChange), QQAlarmError> {
+        self.insert(owner, wake_at, None)
+    }
+
+    /// Adds alarm which fires at `phase + k * period` (first time after `now`), its id is reported by `consume_pending` once per period
+    /// (periods which passed before the alarm was consumed are merged). Repetition is cancelled by `remove`.
+    pub fn add_periodic(&mut self, owner: OwnerTag, now: u64, period: u64, phase: u64) -> Result<(usize, TargetChange), QQAlarmError> {
+        let period = period.max(1);
+        self.insert(owner, next_periodic_wake_at(now, period, phase), Some(period))
+    }
+
+    fn insert(&mut self, owner: OwnerTag, wake_at: u64, period: Option<u64>) -> Result<(usize, TargetChange), QQAlarmError> {
+        // assuming wake_at is less than now (if it is not it is ok alarm will cause interrupt instantly)
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let Some(empty_alarm) = self.queue.iter_mut().find(|alarm| alarm.is_none()) else {
+            self.overflow_count = self.overflow_count.saturating_add(1);
+            return Err(QQAlarmError::QueueFull);
+        };
+        *empty_alarm = Some(QQAlarm {
+            id,
+            owner,
+            wake_at,
+            state: QQAlarmState::Waiting,
+            period,
+        });
+
+        let target_change = match self.next_wakeup {
+            Some(next_wakeup) if wake_at < next_wakeup => TargetChange::Set(wake_at),
+            Some(_) => TargetChange::Keep,
+            None => TargetChange::Enable(wake_at),
+        };
+
+        if target_change != TargetChange::Keep {
+            self.next_wakeup = Some(wake_at);
+        }
+
+        Ok((id, target_change))
+    }
+
+    pub fn remove(&mut self, id: usize) -> Result<TargetChange, QQAlarmError> {
+        let qq_alarm_opt = self.queue.iter_mut()
+            .find(|qq_alarm_opt| qq_alarm_opt.is_some_and(|qq_alarm| qq_alarm.id == id))
+            .ok_or(QQAlarmError::IdNotFound)?;
+        *qq_alarm_opt = None;
+
+        let target_change = match self.min_waiting_wake_at() {
+            None => {
+                if self.next_wakeup.is_some() {
+                    self.next_wakeup = None;
+                    TargetChange::Disable
+                } else {
+                    TargetChange::Keep
+                }
+            },
+            Some(min_wake_at) => {
+                // `next_wakeup` cannot be `None` because there are some waiting alarms
+                invariant!(self.next_wakeup.is_some(), "waiting qq alarms but next wakeup is None");
+
+                if Some(min_wake_at) != self.next_wakeup {
+                    self.next_wakeup = Some(min_wake_at);
+                    TargetChange::Set(min_wake_at)
+                } else {
+                    TargetChange::Keep
+                }
+            },
+        };
+
+        // update to `any_pending` is needed when deleted alarm was pending alarm and all other alarms were not pending (`any_pending` is changed from `true` to `false`)
+        self.any_pending = self.queue.iter().flatten().any(|qq_alarm| qq_alarm.state == QQAlarmState::Pending);
+
+        Ok(target_change)
+    }
+
+    /// Called when timer alarm fired, alarms with `wake_at <= now` become pending, periodic alarms are re-armed for their next period.
+    /// Returns `Set` (with the earliest remaining wake time) or `Disable`.
+    pub fn on_timer(&mut self, now: u64) -> TargetChange {
+        for qq_alarm in self.queue.iter_mut().flatten().filter(|qq_alarm| qq_alarm.is_waiting()) {
+            if qq_alarm.wake_at <= now {
+                self.any_pending = true;
+                qq_alarm.state = QQAlarmState::Pending;
+
+                if let Some(period) = qq_alarm.period {
+                    qq_alarm.wake_at = next_periodic_wake_at(now, period, qq_alarm.wake_at);
+                }
+            }
+        }
+
+        self.next_wakeup = self.min_waiting_wake_at();
+
+        match self.next_wakeup {
+            Some(min_wake_at) => TargetChange::Set(min_wake_at),
+            None => TargetChange::Disable,
+        }
+    }
+
+    /// returned iterator should be fully consumed to free up space in queue (periodic alarms are only set back to waiting)
+    /// e.g. `queue.consume_pending().unwrap().take(3)` will cause problems, because fourth pending alarm in iterator will never be consumed and therefore not freed
+    /// if you do not consume whole iterator at one time, be sure to call `consume_pending` again
+    pub fn consume_pending(&mut self) -> Option<impl Iterator<Item = (OwnerTag, usize)> + '_> {
+        if !self.any_pending {
+            return None;
+        }
+
+        Some(self.queue.iter_mut()
+            .map(|qq_alarm_opt| {
+                if let Some(qq_alarm) = qq_alarm_opt && qq_alarm.state == QQAlarmState::Pending {
+                    let alarm = (qq_alarm.owner, qq_alarm.id);
+                    if qq_alarm.period.is_some() {
+                        qq_alarm.state = QQAlarmState::Waiting;
+                    } else {
+                        *qq_alarm_opt = None;
+                    }
+                    Some(alarm)
+                } else {
+                    None
+                }
+            })
+            .chain(iter::once_with(|| {
+                // sets `any_pending` to false after all pending alarms are set to `None`
+                self.any_pending = false;
+                None
+            }))
+            .flatten()
+        )
+    }
+
+    /// number of alarms (waiting and pending)
+    pub fn len(&self) -> usize {
+        self.queue.iter().filter(|qq_alarm| qq_alarm.is_some()).count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.queue.iter().all(Option::is_none)
+    }
+
+    pub fn capacity(&self) -> usize {
+        N
+    }
+
+    /// number of `add` calls which failed because the table was full
+    pub fn overflow_count(&self) -> u32 {
+        self.overflow_count
+    }
+}
+
+impl<const N: usize> Default for AlarmTable<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> AlarmBook for AlarmTable<N> {
+    fn add(&mut self, owner: OwnerTag, wake_at: u64) -> Result<(usize, TargetChange), QQAlarmError> {
+        AlarmTable::add(self, owner, wake_at)
+    }
+
+    fn add_periodic(&mut self, owner: OwnerTag, now: u64, period: u64, phase: u64) -> Result<(usize, TargetChange), QQAlarmError> {
+        AlarmTable::add_periodic(self, owner, now, period, phase)
+    }
+
+    fn remove(&mut self, id: usize) -> Result<TargetChange, QQAlarmError> {
+        AlarmTable::remove(self, id)
+    }
+
+    fn on_timer(&mut self, now: u64) -> TargetChange {
+        AlarmTable::on_timer(self, now)
+    }
+
+    fn consume_pending(&mut self) -> Option<impl Iterator<Item = (OwnerTag, usize)> + '_> {
+        AlarmTable::consume_pending(self)
+    }
+
+    fn len(&self) -> usize {
+        AlarmTable::len(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        AlarmTable::is_empty(self)
+    }
+
+    fn capacity(&self) -> usize {
+        AlarmTable::capacity(self)
+    }
+
+    fn overflow_count(&self) -> u32 {
+        AlarmTable::overflow_count(self)
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+
+    #[test]
+    fn add_sets_target_only_when_earlier() {
+        let mut table = AlarmTable::<4>::new();
+
+        assert_eq!(table.add(0, 100).unwrap(), (0, TargetChange::Enable(100)));
+        assert_eq!(table.add(0, 200).unwrap(), (1, TargetChange::Keep));
+        assert_eq!(table.add(0, 50).unwrap(), (2, TargetChange::Set(50)));
+        assert_eq!(table.len(), 3);
+    }
+
+    #[test]
+    fn full_table_counts_overflows() {
+        let mut table = AlarmTable::<2>::new();
+
+        table.add(0, 1).unwrap();
+        table.add(0, 2).unwrap();
+
+        assert_eq!(table.add(0, 3), Err(QQAlarmError::QueueFull));
+        assert_eq!(table.overflow_count(), 1);
+        assert_eq!(table.len(), table.capacity());
+    }
+
+    #[test]
+    fn remove_recomputes_target() {
+        let mut table = AlarmTable::<4>::new();
+
+        let (first, _) = table.add(0, 100).unwrap();
+        let (second, _) = table.add(0, 200).unwrap();
+
+        assert_eq!(table.remove(first), Ok(TargetChange::Set(200)));
+        assert_eq!(table.remove(first), Err(QQAlarmError::IdNotFound));
+        assert_eq!(table.remove(second), Ok(TargetChange::Disable));
+        assert_eq!(table.add(0, 300).unwrap().1, TargetChange::Enable(300));
+    }
+
+    #[test]
+    fn fired_alarms_are_consumed_in_one_pass() {
+        let mut table = AlarmTable::<4>::new();
+
+        table.add(0, 100).unwrap();
+        table.add(0, 300).unwrap();
+        table.add(0, 200).unwrap();
+
+        assert!(table.consume_pending().is_none());
+        assert_eq!(table.on_timer(250), TargetChange::Set(300));
+
+        let mut fired = table.consume_pending().unwrap().map(|(_, id)| id).collect::<Vec<_>>();
+        fired.sort();
+        assert_eq!(fired, [0, 2]);
+
+        assert!(table.consume_pending().is_none());
+        assert_eq!(table.len(), 1);
+        assert_eq!(table.on_timer(300), TargetChange::Disable);
+        assert_eq!(table.consume_pending().unwrap().map(|(_, id)| id).collect::<Vec<_>>(), [1]);
+    }
+
+    #[test]
+    fn periodic_alarm_is_rearmed_until_removed() {
+        let mut table = AlarmTable::<4>::new();
+
+        let (id, target_change) = table.add_periodic(0, 130, 100, 0).unwrap();
+        assert_eq!(target_change, TargetChange::Enable(200));
+
+        assert_eq!(table.on_timer(200), TargetChange::Set(300));
+        assert_eq!(table.consume_pending().unwrap().map(|(_, id)| id).collect::<Vec<_>>(), [id]);
+        assert_eq!(table.len(), 1);
+
+        // missed periods are merged, next wake time stays in phase
+        assert_eq!(table.on_timer(320), TargetChange::Set(400));
+        assert_eq!(table.on_timer(450), TargetChange::Set(500));
+        assert_eq!(table.consume_pending().unwrap().map(|(_, id)| id).collect::<Vec<_>>(), [id]);
+
+        assert_eq!(table.remove(id), Ok(TargetChange::Disable));
+        assert_eq!(table.len(), 0);
+    }
+
+    #[test]
+    fn next_periodic_wake_at_keeps_phase() {
+        assert_eq!(next_periodic_wake_at(50, 100, 70), 70);
+        assert_eq!(next_periodic_wake_at(70, 100, 70), 170);
+        assert_eq!(next_periodic_wake_at(1_234, 100, 70), 1_270);
+    }
+
+    #[test]
+    fn pending_alarms_carry_owner() {
+        let mut table = AlarmTable::<4>::new();
+
+        let (first, _) = table.add(3, 100).unwrap();
+        let (second, _) = table.add_periodic(7, 0, 50, 0).unwrap();
+        table.on_timer(100);
+
+        let mut fired = table.consume_pending().unwrap().collect::<Vec<_>>();
+        fired.sort();
+        assert_eq!(fired, [(3, first), (7, second)]);
+    }
+
+    #[test]
+    fn removing_pending_alarm_clears_pending_flag() {
+        let mut table = AlarmTable::<4>::new();
+
+        let (id, _) = table.add(0, 100).unwrap();
+        table.on_timer(100);
+
+        assert_eq!(table.remove(id), Ok(TargetChange::Keep));
+        assert!(table.consume_pending().is_none());
+    }
+}
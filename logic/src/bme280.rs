@@ -0,0 +1,205 @@
+/* Bosch BME280 / BMP280 calibration data parsing and integer compensation (from the BME280 datasheet, section 4.2.3) */
+
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Calibration {
+    t1: u16,
+    t2: i16,
+    t3: i16,
+    p1: u16,
+    p2: i16,
+    p3: i16,
+    p4: i16,
+    p5: i16,
+    p6: i16,
+    p7: i16,
+    p8: i16,
+    p9: i16,
+    /// `None` for BMP280 (no humidity sensor)
+    humidity: Option<HumidityCalibration>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct HumidityCalibration {
+    h1: u8,
+    h2: i16,
+    h3: u8,
+    h4: i16,
+    h5: i16,
+    h6: i8,
+}
+
+impl Calibration {
+    /// Length of calibration registers `0x88 - 0xa1`.
+    pub const TP_LEN: usize = 26;
+    /// Length of calibration registers `0xe1 - 0xe7` (BME280 only).
+    pub const H_LEN: usize = 7;
+
+
+    pub fn has_humidity(&self) -> bool {
+        self.humidity.is_some()
+    }
+
+    /// `tp` are registers `0x88 - 0xa1`, `h` are registers `0xe1 - 0xe7` (`None` for BMP280).
+    pub fn from_registers(tp: &[u8; Self::TP_LEN], h: Option<&[u8; Self::H_LEN]>) -> Calibration {
+        let u = |i: usize| u16::from_le_bytes([tp[i], tp[i + 1]]);
+        let s = |i: usize| i16::from_le_bytes([tp[i], tp[i + 1]]);
+
+        Calibration {
+            t1: u(0),
+            t2: s(2),
+            t3: s(4),
+            p1: u(6),
+            p2: s(8),
+            p3: s(10),
+            p4: s(12),
+            p5: s(14),
+            p6: s(16),
+            p7: s(18),
+            p8: s(20),
+            p9: s(22),
+            humidity: h.map(|h| HumidityCalibration {
+                h1: tp[25],
+                h2: i16::from_le_bytes([h[0], h[1]]),
+                h3: h[2],
+                // 12 bit values sharing register 0xe5
+                h4: ((h[3] as i8 as i16) << 4) | (h[4] & 0x0f) as i16,
+                h5: ((h[5] as i8 as i16) << 4) | (h[4] >> 4) as i16,
+                h6: h[6] as i8,
+            }),
+        }
+    }
+}
+
+
+/// Raw adc values from data registers `0xf7 - 0xfe` (pressure, temperature and humidity).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawReading {
+    pub pressure: i32,
+    pub temperature: i32,
+    pub humidity: i32,
+}
+
+impl RawReading {
+    pub const LEN: usize = 8;
+
+
+    pub fn from_registers(r: &[u8; Self::LEN]) -> RawReading {
+        let adc20 = |i: usize| ((r[i] as i32) << 12) | ((r[i + 1] as i32) << 4) | ((r[i + 2] as i32) >> 4);
+
+        RawReading {
+            pressure: adc20(0),
+            temperature: adc20(3),
+            humidity: ((r[6] as i32) << 8) | r[7] as i32,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reading {
+    /// in m°C
+    pub temperature: i32,
+    /// in Pa
+    pub pressure: u32,
+    /// in m%, `None` for BMP280
+    pub humidity: Option<i32>,
+}
+
+
+/// Integer compensation formulas from the datasheet, pressure is `None` when calibration is invalid (division by zero).
+pub fn compensate(calibration: &Calibration, raw: RawReading) -> Option<Reading> {
+    let c = calibration;
+
+    // temperature, t_fine is shared with pressure and humidity
+    let adc_t = raw.temperature;
+    let var1 = (((adc_t >> 3) - ((c.t1 as i32) << 1)) * (c.t2 as i32)) >> 11;
+    let var2 = (((((adc_t >> 4) - (c.t1 as i32)) * ((adc_t >> 4) - (c.t1 as i32))) >> 12) * (c.t3 as i32)) >> 14;
+    let t_fine = var1 + var2;
+    // in 10^-2 °C
+    let temperature = (t_fine * 5 + 128) >> 8;
+
+    // pressure, result in Q24.8 Pa
+    let mut var1 = t_fine as i64 - 128_000;
+    let mut var2 = var1 * var1 * c.p6 as i64;
+    var2 += (var1 * c.p5 as i64) << 17;
+    var2 += (c.p4 as i64) << 35;
+    var1 = ((var1 * var1 * c.p3 as i64) >> 8) + ((var1 * c.p2 as i64) << 12);
+    var1 = (((1i64 << 47) + var1) * c.p1 as i64) >> 33;
+
+    if var1 == 0 {
+        return None;
+    }
+
+    let mut p = 1_048_576 - raw.pressure as i64;
+    p = (((p << 31) - var2) * 3125) / var1;
+    let var1 = ((c.p9 as i64) * (p >> 13) * (p >> 13)) >> 25;
+    let var2 = ((c.p8 as i64) * p) >> 19;
+    p = ((p + var1 + var2) >> 8) + ((c.p7 as i64) << 4);
+
+    // humidity, result in Q22.10 %
+    let humidity = c.humidity.map(|h| {
+        let adc_h = raw.humidity;
+        let mut v = t_fine - 76_800;
+        v = ((((adc_h << 14) - ((h.h4 as i32) << 20) - ((h.h5 as i32) * v)) + 16_384) >> 15)
+            * (((((((v * h.h6 as i32) >> 10) * (((v * h.h3 as i32) >> 11) + 32_768)) >> 10) + 2_097_152) * (h.h2 as i32) + 8_192) >> 14);
+        v -= ((((v >> 15) * (v >> 15)) >> 7) * h.h1 as i32) >> 4;
+        v = v.clamp(0, 419_430_400);
+
+        (((v >> 12) as i64 * 1000) >> 10) as i32
+    });
+
+    Some(Reading {
+        temperature: temperature * 10,
+        pressure: (p >> 8) as u32,
+        humidity,
+    })
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// example values from the BMP280 datasheet (section 3.12)
+    fn datasheet_calibration() -> Calibration {
+        let words: [u16; 12] = [
+            27504, 26435, (-1000i16) as u16,
+            36477, (-10685i16) as u16, 3024, 2855, 140, (-7i16) as u16, 15500, (-14600i16) as u16, 6000,
+        ];
+
+        let mut tp = [0u8; Calibration::TP_LEN];
+        words.iter().enumerate().for_each(|(i, word)| tp[(2 * i)..(2 * i + 2)].copy_from_slice(&word.to_le_bytes()));
+
+        Calibration::from_registers(&tp, None)
+    }
+
+    #[test]
+    fn datasheet_example() {
+        let raw = RawReading { pressure: 415_148, temperature: 519_888, humidity: 0 };
+        let reading = compensate(&datasheet_calibration(), raw).unwrap();
+
+        assert_eq!(reading.temperature, 25_080);
+        // datasheet: 100653.27 Pa (floating point compensation)
+        assert!((100_652..=100_654).contains(&reading.pressure));
+        assert_eq!(reading.humidity, None);
+    }
+
+    #[test]
+    fn raw_from_registers() {
+        let raw = RawReading::from_registers(&[0x65, 0x5a, 0xc0, 0x7e, 0xed, 0x00, 0x6b, 0x38]);
+
+        assert_eq!(raw, RawReading { pressure: 415_148, temperature: 519_888, humidity: 0x6b38 });
+    }
+
+    #[test]
+    fn humidity_registers() {
+        let tp = [0u8; Calibration::TP_LEN];
+        let calibration = Calibration::from_registers(&tp, Some(&[0x6a, 0x01, 0x00, 0x13, 0x25, 0x03, 0x1e]));
+        let h = calibration.humidity.unwrap();
+
+        assert_eq!((h.h2, h.h3, h.h4, h.h5, h.h6), (362, 0, 309, 50, 30));
+    }
+}
@@ -0,0 +1,35 @@
+/* time limits of i2c bus ownership in ms (users convert them to system timer ticks), kept together so the test below
+   checks that legitimate ownership of the bus is never reported by watchdog */
+
+
+
+/// Longest time the i2c bus can be owned by one user, longer ownership is reported by watchdog (see `I2CBus`).
+pub const I2C_HEARTBEAT_TIMEOUT_MS: u64 = 2_000;
+
+/// From scd30 documentation: sensor boots in less than 2 s.
+///
+/// Does not fit into one bus ownership, scd30 reset releases the bus while waiting (see `sdc::machines::Reset`).
+pub const SDC_BOOT_DELAY_MS: u64 = 2_000;
+
+/// Upper bound of one scd30 command, write, delay before read (5 ms by default) and read.
+pub const SDC_COMMAND_MS: u64 = 25;
+/// Commands sent by scd30 driver in one bus ownership (firmware version, init settings, interval set and read back, start).
+pub const SDC_COMMANDS_PER_OWNERSHIP: u64 = 8;
+
+/// Longest time scd30 driver owns the bus.
+pub const SDC_LONGEST_OWNERSHIP_MS: u64 = SDC_COMMAND_MS * SDC_COMMANDS_PER_OWNERSHIP;
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sdc_ownership_fits_heartbeat_timeout() {
+        assert!(SDC_LONGEST_OWNERSHIP_MS * 4 <= I2C_HEARTBEAT_TIMEOUT_MS);
+
+        // holding the bus through boot delay would starve the watchdog
+        assert!(SDC_BOOT_DELAY_MS + SDC_LONGEST_OWNERSHIP_MS > I2C_HEARTBEAT_TIMEOUT_MS);
+    }
+}
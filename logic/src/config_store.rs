@@ -0,0 +1,475 @@
+/* persistent configuration in two flash sectors, written alternately (newest valid copy by sequence number is loaded) */
+
+use crate::{flash::{SectorFlash, SECTOR_SIZE}, framing::crc16, ir::IrProtocol};
+
+
+
+/// Magic + sequence number + config + crc + padding (length has to be multiple of 4 for rom flash functions).
+pub const SLOT_LEN: usize = 4 + 4 + Config::ENCODED_LEN + 2 + 2;
+
+const _: () = assert!(SLOT_LEN % 4 == 0, "config slot length has to be multiple of 4");
+
+/// Identifies stored config, has to be changed when `Config` encoding changes (old copy is then ignored).
+const MAGIC: u32 = 0x4346_4704;
+
+/// Number of ir key bindings kept in config.
+pub const MAX_IR_BINDINGS: usize = 32;
+
+/// Maximum length of console command of ir key binding.
+pub const IR_COMMAND_LEN: usize = 22;
+
+
+/// Ir key bound to console command, received key is handled same as the command entered into console (see
+/// `IrRxDispatch`). Command is kept as text, it is parsed by the firmware when the key is received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IrKeyBinding {
+    pub protocol: IrProtocol,
+    /// raw frames - number of pulses (see `IrEvent`)
+    pub address: u8,
+    /// raw frames - hash of pulse lengths
+    pub ir_command: u16,
+    /// command is given again for each repeat while key is held (otherwise only when key is pressed)
+    pub repeat: bool,
+    command: [u8; IR_COMMAND_LEN],
+    command_len: u8,
+}
+
+impl IrKeyBinding {
+    const ENCODED_LEN: usize = 6 + IR_COMMAND_LEN;
+
+    /// `None` when `command` is longer than `IR_COMMAND_LEN`.
+    pub const fn new(protocol: IrProtocol, address: u8, ir_command: u16, command: &str, repeat: bool) -> Option<Self> {
+        let text = command.as_bytes();
+        if text.len() > IR_COMMAND_LEN {
+            return None;
+        }
+
+        // `copy_from_slice` is not const
+        let mut command = [0; IR_COMMAND_LEN];
+        let mut i = 0;
+        while i < text.len() {
+            command[i] = text[i];
+            i += 1;
+        }
+
+        Some(Self { protocol, address, ir_command, repeat, command, command_len: text.len() as u8 })
+    }
+
+    /// Console command line.
+    pub fn command(&self) -> &str {
+        // only whole `str` is stored (see `new` and `decode`)
+        core::str::from_utf8(&self.command[..self.command_len as usize]).unwrap_or("")
+    }
+
+    /// Binding is for the key (`repeat` and command are not compared).
+    pub fn is_key(&self, protocol: IrProtocol, address: u8, ir_command: u16) -> bool {
+        (self.protocol, self.address, self.ir_command) == (protocol, address, ir_command)
+    }
+
+    fn encode(&self) -> [u8; Self::ENCODED_LEN] {
+        let mut bytes = [0; Self::ENCODED_LEN];
+
+        bytes[0] = self.protocol as u8;
+        bytes[1] = self.address;
+        bytes[2..4].copy_from_slice(&self.ir_command.to_le_bytes());
+        bytes[4] = self.repeat as u8;
+        bytes[5] = self.command_len;
+        bytes[6..].copy_from_slice(&self.command);
+
+        bytes
+    }
+
+    fn decode(bytes: &[u8; Self::ENCODED_LEN]) -> Option<Self> {
+        let command = core::str::from_utf8(bytes[6..].get(..bytes[5] as usize)?).ok()?;
+
+        Self::new(IrProtocol::from_u8(bytes[0])?, bytes[1], u16::from_le_bytes([bytes[2], bytes[3]]), command, bytes[4] != 0)
+    }
+}
+
+
+/// Table of ir key bindings, at most one binding for each key and at most `MAX_IR_BINDINGS` bindings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IrBindings {
+    bindings: [Option<IrKeyBinding>; MAX_IR_BINDINGS],
+}
+
+impl IrBindings {
+    /// Unused entry is encoded as this protocol byte.
+    const EMPTY: u8 = 0xff;
+    const ENCODED_LEN: usize = MAX_IR_BINDINGS * IrKeyBinding::ENCODED_LEN;
+
+
+    /// Table of `bindings` (default table of the firmware), there has to be at most `MAX_IR_BINDINGS` of them.
+    pub const fn new(bindings: &[IrKeyBinding]) -> Self {
+        assert!(bindings.len() <= MAX_IR_BINDINGS, "too many ir key bindings");
+
+        let mut table = [None; MAX_IR_BINDINGS];
+        let mut i = 0;
+        while i < bindings.len() {
+            table[i] = Some(bindings[i]);
+            i += 1;
+        }
+
+        Self { bindings: table }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &IrKeyBinding> {
+        self.bindings.iter().flatten()
+    }
+
+    /// Adds `binding` or replaces binding of the same key, `false` when the table is full.
+    pub fn bind(&mut self, binding: IrKeyBinding) -> bool {
+        let key = |entry: &Option<IrKeyBinding>| entry.is_some_and(|entry| entry.is_key(binding.protocol, binding.address, binding.ir_command));
+
+        match self.bindings.iter().position(key).or_else(|| self.bindings.iter().position(Option::is_none)) {
+            Some(i) => {
+                self.bindings[i] = Some(binding);
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Removes binding of the key, `false` when the key is not bound.
+    pub fn unbind(&mut self, protocol: IrProtocol, address: u8, ir_command: u16) -> bool {
+        self.bindings.iter_mut()
+            .find(|entry| entry.is_some_and(|entry| entry.is_key(protocol, address, ir_command)))
+            .map(|entry| *entry = None)
+            .is_some()
+    }
+
+    fn encode(&self) -> [u8; Self::ENCODED_LEN] {
+        let mut bytes = [0; Self::ENCODED_LEN];
+
+        for (entry, chunk) in self.bindings.iter().zip(bytes.chunks_exact_mut(IrKeyBinding::ENCODED_LEN)) {
+            match entry {
+                Some(binding) => chunk.copy_from_slice(&binding.encode()),
+                None => chunk[0] = Self::EMPTY,
+            }
+        }
+
+        bytes
+    }
+
+    fn decode(bytes: &[u8; Self::ENCODED_LEN]) -> Option<Self> {
+        let mut bindings = [None; MAX_IR_BINDINGS];
+
+        for (entry, chunk) in bindings.iter_mut().zip(bytes.chunks_exact(IrKeyBinding::ENCODED_LEN)) {
+            if chunk[0] != Self::EMPTY {
+                *entry = Some(IrKeyBinding::decode(chunk.try_into().ok()?)?);
+            }
+        }
+
+        Some(Self { bindings })
+    }
+}
+
+
+/// Configuration which survives power cycles, changed at runtime by console and ir commands.
+///
+/// Calibration of scd30 (forced recalibration, automatic self calibration, temperature offset, altitude) is not part of
+/// it, scd30 keeps it in its own non-volatile memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Config {
+    /// scd30 measurment interval (in seconds)
+    pub interval_secs: u32,
+    /// co2 alert thresholds and hysteresis (in 10^-3 ppm, see `AlertConfig`)
+    pub alert_warning: i32,
+    pub alert_critical: i32,
+    pub alert_hysteresis: i32,
+    /// ventilation is turned on at or above `ventilation_on` and off below `ventilation_off` (in 10^-3 ppm, see
+    /// `VentilationConfig`)
+    pub ventilation_on: i32,
+    pub ventilation_off: i32,
+    /// receiver of measurment udp packets (ipv4 address, see `WifiReporter`)
+    pub report_host: [u8; 4],
+    /// keys of remotes bound to console commands (see `IrRxDispatch`)
+    pub ir_bindings: IrBindings,
+}
+
+impl Config {
+    const ENCODED_LEN: usize = 28 + IrBindings::ENCODED_LEN;
+
+
+    /// Alert levels are ordered and cleared below their thresholds (`0 <= hysteresis < warning < critical`).
+    pub fn alert_thresholds_valid(warning: i32, critical: i32, hysteresis: i32) -> bool {
+        0 <= hysteresis && hysteresis < warning && warning < critical
+    }
+
+    /// Ventilation is turned off below the threshold it is turned on at (`0 <= off < on`).
+    pub fn ventilation_thresholds_valid(on: i32, off: i32) -> bool {
+        0 <= off && off < on
+    }
+
+    /// Thresholds are valid (see `alert_thresholds_valid` and `ventilation_thresholds_valid`), interval is checked by
+    /// the firmware.
+    pub fn thresholds_valid(&self) -> bool {
+        Self::alert_thresholds_valid(self.alert_warning, self.alert_critical, self.alert_hysteresis)
+            && Self::ventilation_thresholds_valid(self.ventilation_on, self.ventilation_off)
+    }
+
+    fn encode(&self) -> [u8; Self::ENCODED_LEN] {
+        let mut bytes = [0; Self::ENCODED_LEN];
+
+        bytes[0..4].copy_from_slice(&self.interval_secs.to_le_bytes());
+        bytes[4..8].copy_from_slice(&self.alert_warning.to_le_bytes());
+        bytes[8..12].copy_from_slice(&self.alert_critical.to_le_bytes());
+        bytes[12..16].copy_from_slice(&self.alert_hysteresis.to_le_bytes());
+        bytes[16..20].copy_from_slice(&self.ventilation_on.to_le_bytes());
+        bytes[20..24].copy_from_slice(&self.ventilation_off.to_le_bytes());
+        bytes[24..28].copy_from_slice(&self.report_host);
+        bytes[28..].copy_from_slice(&self.ir_bindings.encode());
+
+        bytes
+    }
+
+    /// `None` when ir bindings are not valid.
+    fn decode(bytes: &[u8; Self::ENCODED_LEN]) -> Option<Self> {
+        let word = |i: usize| [bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]];
+
+        Some(Self {
+            interval_secs: u32::from_le_bytes(word(0)),
+            alert_warning: i32::from_le_bytes(word(4)),
+            alert_critical: i32::from_le_bytes(word(8)),
+            alert_hysteresis: i32::from_le_bytes(word(12)),
+            ventilation_on: i32::from_le_bytes(word(16)),
+            ventilation_off: i32::from_le_bytes(word(20)),
+            report_host: word(24),
+            ir_bindings: IrBindings::decode(bytes[28..].try_into().ok()?)?,
+        })
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigStoreError<E> {
+    Flash(E),
+    /// read back copy does not match written config
+    VerifyFailed,
+}
+
+impl<E> From<E> for ConfigStoreError<E> {
+    fn from(err: E) -> Self {
+        ConfigStoreError::Flash(err)
+    }
+}
+
+
+
+/// Slot bytes of `config` with `sequence` number.
+pub fn encode_slot(config: &Config, sequence: u32) -> [u8; SLOT_LEN] {
+    let mut slot = [0xff; SLOT_LEN];
+
+    slot[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+    slot[4..8].copy_from_slice(&sequence.to_le_bytes());
+    slot[8..(8 + Config::ENCODED_LEN)].copy_from_slice(&config.encode());
+
+    let crc = crc16(&slot[..(8 + Config::ENCODED_LEN)]);
+    slot[(8 + Config::ENCODED_LEN)..(10 + Config::ENCODED_LEN)].copy_from_slice(&crc.to_le_bytes());
+
+    slot
+}
+
+/// Sequence number and config of slot, `None` for erased, corrupted (crc), incompatible (magic) or invalid slot.
+pub fn decode_slot(slot: &[u8; SLOT_LEN]) -> Option<(u32, Config)> {
+    let magic = u32::from_le_bytes(slot[0..4].try_into().ok()?);
+    let crc = u16::from_le_bytes(slot[(8 + Config::ENCODED_LEN)..(10 + Config::ENCODED_LEN)].try_into().ok()?);
+
+    if magic != MAGIC || crc != crc16(&slot[..(8 + Config::ENCODED_LEN)]) {
+        return None;
+    }
+
+    let sequence = u32::from_le_bytes(slot[4..8].try_into().ok()?);
+    let config = Config::decode(slot[8..(8 + Config::ENCODED_LEN)].try_into().ok()?)?;
+
+    Some((sequence, config))
+}
+
+/// Index of the newer of two slots (sequence numbers compared with wrapping), `None` when neither is valid.
+fn newest_slot(sequences: [Option<u32>; 2]) -> Option<usize> {
+    match sequences {
+        [Some(a), Some(b)] => Some(if (b.wrapping_sub(a) as i32) > 0 { 1 } else { 0 }),
+        [Some(_), None] => Some(0),
+        [None, Some(_)] => Some(1),
+        [None, None] => None,
+    }
+}
+
+
+
+/// Keeps config in two flash sectors (each slot occupies one sector), each save erases and writes the sector not holding the newest copy.
+///
+/// Sectors are worn evenly and a power loss during save leaves the previous copy loadable.
+pub struct ConfigStore<F> {
+    flash: F,
+}
+
+impl<F: SectorFlash> ConfigStore<F> {
+    pub fn new(flash: F) -> Self {
+        Self { flash }
+    }
+
+    fn read_slots(&mut self) -> Result<[Option<(u32, Config)>; 2], F::Error> {
+        let mut slots = [None; 2];
+
+        for (sector, slot) in slots.iter_mut().enumerate() {
+            let mut bytes = [0; SLOT_LEN];
+            self.flash.read(sector as u32 * SECTOR_SIZE, &mut bytes)?;
+            *slot = decode_slot(&bytes);
+        }
+
+        Ok(slots)
+    }
+
+    /// Newest valid config, `None` when no valid copy is stored (first boot, both copies corrupted or incompatible).
+    pub fn load(&mut self) -> Result<Option<Config>, F::Error> {
+        let slots = self.read_slots()?;
+
+        Ok(newest_slot(slots.map(|slot| slot.map(|(sequence, _)| sequence))).and_then(|i| slots[i]).map(|(_, config)| config))
+    }
+
+    /// Writes `config` to the older slot and reads it back.
+    pub fn save(&mut self, config: &Config) -> Result<(), ConfigStoreError<F::Error>> {
+        let slots = self.read_slots()?;
+
+        let (sector, sequence) = match newest_slot(slots.map(|slot| slot.map(|(sequence, _)| sequence))) {
+            Some(newest) => (1 - newest, slots[newest].map_or(0, |(sequence, _)| sequence.wrapping_add(1))),
+            None => (0, 0),
+        };
+
+        let offset = sector as u32 * SECTOR_SIZE;
+
+        self.flash.erase_sector(sector as u32)?;
+        self.flash.write(offset, &encode_slot(config, sequence))?;
+
+        let mut bytes = [0; SLOT_LEN];
+        self.flash.read(offset, &mut bytes)?;
+
+        match decode_slot(&bytes) {
+            Some((read_sequence, read_config)) if read_sequence == sequence && read_config == *config => Ok(()),
+            _ => Err(ConfigStoreError::VerifyFailed),
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flash::RamFlash;
+
+
+    const fn binding(protocol: IrProtocol, ir_command: u16, command: &str) -> IrKeyBinding {
+        match IrKeyBinding::new(protocol, 0, ir_command, command, false) {
+            Some(binding) => binding,
+            None => panic!("command too long"),
+        }
+    }
+
+    const CONFIG: Config = Config {
+        interval_secs: 10,
+        alert_warning: 1_200_000,
+        alert_critical: 2_000_000,
+        alert_hysteresis: 100_000,
+        ventilation_on: 1_000_000,
+        ventilation_off: 800_000,
+        report_host: [192, 168, 1, 4],
+        ir_bindings: IrBindings::new(&[binding(IrProtocol::Nec, 0x45, "scd30 toggle"), binding(IrProtocol::Rc5, 0x0c, "interval 10")]),
+    };
+
+
+    #[test]
+    fn slot_roundtrip_and_corruption() {
+        let mut slot = encode_slot(&CONFIG, 7);
+
+        assert_eq!(decode_slot(&slot), Some((7, CONFIG)));
+        assert_eq!(decode_slot(&[0xff; SLOT_LEN]), None);
+
+        slot[9] ^= 0x01;
+        assert_eq!(decode_slot(&slot), None);
+    }
+
+    #[test]
+    fn invalid_bindings_are_not_loaded() {
+        // unknown protocol of the first binding (bindings start after 28 bytes of config)
+        let mut slot = encode_slot(&CONFIG, 1);
+        slot[8 + 28] = 9;
+        let crc = crc16(&slot[..(8 + Config::ENCODED_LEN)]);
+        slot[(8 + Config::ENCODED_LEN)..(10 + Config::ENCODED_LEN)].copy_from_slice(&crc.to_le_bytes());
+
+        assert_eq!(decode_slot(&slot), None);
+    }
+
+    #[test]
+    fn bindings_are_replaced_per_key() {
+        let mut bindings = CONFIG.ir_bindings;
+
+        assert!(bindings.bind(binding(IrProtocol::Nec, 0x45, "flush")));
+        assert!(bindings.bind(binding(IrProtocol::Nec, 0x46, "alert silence")));
+        let commands: Vec<_> = bindings.iter().map(IrKeyBinding::command).collect();
+        assert_eq!(commands, ["flush", "interval 10", "alert silence"]);
+
+        assert!(bindings.unbind(IrProtocol::Rc5, 0, 0x0c));
+        assert!(!bindings.unbind(IrProtocol::Rc5, 0, 0x0c));
+        assert_eq!(bindings.iter().count(), 2);
+
+        for ir_command in 0..(MAX_IR_BINDINGS as u16 - 2) {
+            assert!(bindings.bind(binding(IrProtocol::Sirc, ir_command, "summary")));
+        }
+        assert!(!bindings.bind(binding(IrProtocol::Sirc, 0x100, "summary")));
+        // key which is already bound can be changed in full table
+        assert!(bindings.bind(binding(IrProtocol::Sirc, 0, "stats")));
+    }
+
+    #[test]
+    fn binding_command_length() {
+        assert!(IrKeyBinding::new(IrProtocol::Nec, 0, 0, &"x".repeat(IR_COMMAND_LEN), false).is_some());
+        assert!(IrKeyBinding::new(IrProtocol::Nec, 0, 0, &"x".repeat(IR_COMMAND_LEN + 1), false).is_none());
+    }
+
+    #[test]
+    fn thresholds_are_ordered() {
+        assert!(CONFIG.thresholds_valid());
+
+        assert!(!Config { alert_warning: 2_000_000, ..CONFIG }.thresholds_valid());
+        assert!(!Config { alert_hysteresis: 1_200_000, ..CONFIG }.thresholds_valid());
+        assert!(!Config { alert_hysteresis: -1, ..CONFIG }.thresholds_valid());
+        assert!(!Config { ventilation_off: 1_000_000, ..CONFIG }.thresholds_valid());
+    }
+
+    #[test]
+    fn newest_slot_handles_sequence_wrap() {
+        assert_eq!(newest_slot([Some(1), Some(2)]), Some(1));
+        assert_eq!(newest_slot([Some(u32::MAX), Some(0)]), Some(1));
+        assert_eq!(newest_slot([None, Some(0)]), Some(1));
+        assert_eq!(newest_slot([None, None]), None);
+    }
+
+    #[test]
+    fn store_alternates_sectors_and_loads_newest() {
+        let mut flash = RamFlash::new(2);
+        let mut store = ConfigStore::new(&mut flash);
+
+        assert_eq!(store.load(), Ok(None));
+
+        for interval_secs in [2, 10, 60, 120] {
+            store.save(&Config { interval_secs, ..CONFIG }).unwrap();
+        }
+
+        assert_eq!(store.load(), Ok(Some(Config { interval_secs: 120, ..CONFIG })));
+        assert_eq!(flash.erase_count, [2, 2]);
+    }
+
+    #[test]
+    fn interrupted_save_keeps_previous_copy() {
+        let mut flash = RamFlash::new(2);
+
+        ConfigStore::new(&mut flash).save(&CONFIG).unwrap();
+
+        // power lost after erasing the other sector, before the write
+        flash.erase_sector(1).unwrap();
+
+        assert_eq!(ConfigStore::new(&mut flash).load(), Ok(Some(CONFIG)));
+    }
+}
@@ -0,0 +1,171 @@
+/* notifications between machines: static capacity bus with a queue of events for each subscriber (events and
+   subscriptions are defined by the firmware, see `Subscription`) */
+
+use core::marker::PhantomData;
+
+use crate::ring_buffer::{Ignore, RingBuffer};
+
+
+
+/// Machine receiving events of type `E`, each subscriber has its own queue.
+pub trait Subscription<E>: Copy + Sized + 'static {
+    /// every subscriber once
+    const ALL: &'static [Self];
+
+    /// Index of subscriber's queue, smaller than number of queues of the bus.
+    fn index(&self) -> usize;
+
+    /// Whether `event` is copied into subscriber's queue.
+    fn wants(&self, event: &E) -> bool;
+}
+
+
+/// Static capacity event bus, replaces references between machines (machines borrow the bus in their `update` methods,
+/// same as qq alarm queue).
+///
+/// Event is copied into queue of each subscriber which wants it (see `Subscription::wants`), publisher does not know
+/// its subscribers. Each of `SUBSCRIBERS` queues holds `QUEUE_LEN` events, events published when the queue is full
+/// are dropped (for that subscriber only).
+pub struct EventBus<E, S, const SUBSCRIBERS: usize, const QUEUE_LEN: usize> {
+    queues: [RingBuffer<E, QUEUE_LEN, Ignore>; SUBSCRIBERS],
+    dropped: u32,
+    subscription: PhantomData<S>,
+}
+
+impl<E: Copy, S: Subscription<E>, const SUBSCRIBERS: usize, const QUEUE_LEN: usize> EventBus<E, S, SUBSCRIBERS, QUEUE_LEN> {
+    pub fn new() -> Self {
+        Self {
+            queues: [const { RingBuffer::new() }; SUBSCRIBERS],
+            dropped: 0,
+            subscription: PhantomData,
+        }
+    }
+
+    pub fn publish(&mut self, event: E) {
+        for subscriber in S::ALL.iter().filter(|subscriber| subscriber.wants(&event)) {
+            if self.queues[subscriber.index()].push_back(event).is_err() {
+                self.dropped = self.dropped.saturating_add(1);
+            }
+        }
+    }
+
+    /// Oldest event of `subscriber`'s queue.
+    pub fn poll(&mut self, subscriber: S) -> Option<E> {
+        self.queues[subscriber.index()].pop_front()
+    }
+
+    /// Number of events dropped because queue of subscriber was full.
+    pub fn dropped_count(&self) -> u32 {
+        self.dropped
+    }
+}
+
+impl<E: Copy, S: Subscription<E>, const SUBSCRIBERS: usize, const QUEUE_LEN: usize> Default for EventBus<E, S, SUBSCRIBERS, QUEUE_LEN> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Event {
+        Measurment(u32),
+        Error,
+        Command(u8),
+    }
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    enum Subscriber {
+        Controller,
+        Monitor,
+        Commands,
+    }
+
+    impl Subscription<Event> for Subscriber {
+        const ALL: &'static [Self] = &[Subscriber::Controller, Subscriber::Monitor, Subscriber::Commands];
+
+        fn index(&self) -> usize {
+            *self as usize
+        }
+
+        fn wants(&self, event: &Event) -> bool {
+            match self {
+                Subscriber::Controller => matches!(event, Event::Measurment(_) | Event::Error),
+                Subscriber::Monitor => matches!(event, Event::Measurment(_)),
+                Subscriber::Commands => matches!(event, Event::Command(_)),
+            }
+        }
+    }
+
+    type Bus = EventBus<Event, Subscriber, 3, 4>;
+
+
+    #[test]
+    fn event_is_copied_to_each_subscriber_which_wants_it() {
+        let mut bus = Bus::new();
+
+        bus.publish(Event::Measurment(400));
+        bus.publish(Event::Error);
+
+        assert_eq!(bus.poll(Subscriber::Controller), Some(Event::Measurment(400)));
+        assert_eq!(bus.poll(Subscriber::Controller), Some(Event::Error));
+        assert_eq!(bus.poll(Subscriber::Controller), None);
+
+        assert_eq!(bus.poll(Subscriber::Monitor), Some(Event::Measurment(400)));
+        assert_eq!(bus.poll(Subscriber::Monitor), None);
+
+        assert_eq!(bus.poll(Subscriber::Commands), None);
+    }
+
+    #[test]
+    fn events_are_polled_in_order() {
+        let mut bus = Bus::new();
+
+        for command in 0..3 {
+            bus.publish(Event::Command(command));
+        }
+        assert_eq!(bus.poll(Subscriber::Commands), Some(Event::Command(0)));
+
+        bus.publish(Event::Command(3));
+        let commands: Vec<_> = core::iter::from_fn(|| bus.poll(Subscriber::Commands)).collect();
+        assert_eq!(commands, [Event::Command(1), Event::Command(2), Event::Command(3)]);
+    }
+
+    #[test]
+    fn full_queue_drops_event_for_its_subscriber_only() {
+        let mut bus = Bus::new();
+
+        for _ in 0..4 {
+            bus.publish(Event::Error);
+        }
+        assert_eq!(bus.dropped_count(), 0);
+
+        // controller's queue is full, monitor still gets the measurment
+        bus.publish(Event::Measurment(800));
+        assert_eq!(bus.dropped_count(), 1);
+        assert_eq!(bus.poll(Subscriber::Monitor), Some(Event::Measurment(800)));
+
+        let events: Vec<_> = core::iter::from_fn(|| bus.poll(Subscriber::Controller)).collect();
+        assert_eq!(events, [Event::Error; 4]);
+    }
+
+    #[test]
+    fn unwanted_events_do_not_fill_queue() {
+        let mut bus = Bus::new();
+
+        for command in 0..8 {
+            bus.publish(Event::Command(command));
+        }
+        bus.publish(Event::Measurment(400));
+
+        // only commands over the capacity of commands' queue are dropped
+        assert_eq!(bus.dropped_count(), 4);
+        assert_eq!(bus.poll(Subscriber::Controller), Some(Event::Measurment(400)));
+        assert_eq!(bus.poll(Subscriber::Commands), Some(Event::Command(0)));
+    }
+}
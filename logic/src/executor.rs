@@ -0,0 +1,187 @@
+/* minimal executor of async tasks without allocation, tasks are woken by interrupts (see `interrupts::register_waker`),
+   qq alarms (see `async_io::AsyncIo::on_alarm`) and release of the i2c bus */
+
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicU32, Ordering},
+    task::{Context, Poll, RawWaker, RawWakerVTable, Waker}
+};
+
+
+
+/// Maximum number of tasks (bits of `READY`).
+pub const MAX_TASKS: usize = 8;
+
+
+/// Future pinned by its owner (e.g. with `core::pin::pin!` in main), executor only borrows it.
+pub type Task<'a> = Pin<&'a mut dyn Future<Output = ()>>;
+
+
+/// Bit for each task slot, set by wakers (also from interrupt handlers) and cleared when the task is polled.
+///
+/// Ready flags are global, so there can be only one executor.
+static READY: AtomicU32 = AtomicU32::new(0);
+
+static VTABLE: RawWakerVTable = RawWakerVTable::new(waker_clone, waker_wake, waker_wake, waker_drop);
+
+// waker data is index of the task slot, it is not a pointer
+unsafe fn waker_clone(data: *const ()) -> RawWaker {
+    RawWaker::new(data, &VTABLE)
+}
+
+unsafe fn waker_wake(data: *const ()) {
+    READY.fetch_or(1 << data as usize, Ordering::Release);
+}
+
+unsafe fn waker_drop(_data: *const ()) {}
+
+fn waker(index: usize) -> Waker {
+    // SAFETY: vtable functions only set bit of `READY` (safe from any context), data is not dereferenced
+    unsafe { Waker::from_raw(RawWaker::new(index as *const (), &VTABLE)) }
+}
+
+
+/// `true` when some task was woken and was not polled yet, main loop must not go idle then (task can be woken
+/// by other machines, not only by interrupts).
+pub fn has_ready_tasks() -> bool {
+    READY.load(Ordering::Acquire) != 0
+}
+
+
+/// Polls woken tasks from the main loop (see `poll`), tasks share the main loop with other machines.
+///
+/// Tasks can use resources of the main loop (i2c bus, usb writer, ...) only while they are polled (see `lend::Lend`).
+pub struct Executor<'a> {
+    tasks: [Option<Task<'a>>; MAX_TASKS],
+}
+
+impl<'a> Executor<'a> {
+    pub fn new() -> Self {
+        Self {
+            tasks: [const { None }; MAX_TASKS],
+        }
+    }
+
+    /// Task is polled first in the next `poll`, returns the task back when all slots are used.
+    pub fn spawn(&mut self, task: Task<'a>) -> Result<(), Task<'a>> {
+        let Some(index) = self.tasks.iter().position(Option::is_none) else {
+            return Err(task);
+        };
+
+        self.tasks[index] = Some(task);
+        waker(index).wake();
+
+        Ok(())
+    }
+
+    /// Polls each woken task once, finished tasks are dropped. Returns `true` when any task was polled.
+    pub fn poll(&mut self) -> bool {
+        let ready = READY.swap(0, Ordering::Acquire);
+
+        for (index, slot) in self.tasks.iter_mut().enumerate().filter(|(index, _)| ready & (1 << index) != 0) {
+            let Some(task) = slot else {
+                continue;
+            };
+
+            let waker = waker(index);
+            if let Poll::Ready(()) = task.as_mut().poll(&mut Context::from_waker(&waker)) {
+                *slot = None;
+            }
+        }
+
+        ready != 0
+    }
+}
+
+impl Default for Executor<'_> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, sync::Mutex, task::Waker};
+
+    use super::*;
+
+    /// `READY` is global, tests using executor must not run in parallel.
+    static LOCK: Mutex<()> = Mutex::new(());
+
+    /// Counts polls, pending until `pending` polls were made, keeps the last waker.
+    struct Counter<'a> {
+        polls: &'a Cell<usize>,
+        pending: usize,
+        waker: &'a Cell<Option<Waker>>,
+    }
+
+    impl Future for Counter<'_> {
+        type Output = ();
+
+        fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+            self.polls.set(self.polls.get() + 1);
+            self.waker.set(Some(cx.waker().clone()));
+
+            if self.polls.get() > self.pending {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        }
+    }
+
+    #[test]
+    fn task_polled_only_when_woken() {
+        let _lock = LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        READY.store(0, Ordering::Relaxed);
+
+        let polls = Cell::new(0);
+        let waker = Cell::new(None);
+        let mut task = core::pin::pin!(Counter { polls: &polls, pending: 1, waker: &waker });
+
+        let mut executor = Executor::new();
+        assert!(executor.spawn(task.as_mut()).is_ok());
+        assert!(has_ready_tasks());
+
+        assert!(executor.poll());
+        assert_eq!(polls.get(), 1);
+        assert!(!has_ready_tasks());
+
+        assert!(!executor.poll());
+        assert_eq!(polls.get(), 1);
+
+        waker.take().unwrap().wake();
+        assert!(has_ready_tasks());
+        assert!(executor.poll());
+        assert_eq!(polls.get(), 2);
+
+        // finished task is dropped, late wake does not poll it again
+        waker.take().unwrap().wake();
+        assert!(executor.poll());
+        assert_eq!(polls.get(), 2);
+        assert!(executor.tasks.iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn spawn_fails_when_slots_are_used() {
+        let _lock = LOCK.lock().unwrap_or_else(|err| err.into_inner());
+        READY.store(0, Ordering::Relaxed);
+
+        let polls = Cell::new(0);
+        let waker = Cell::new(None);
+        let mut tasks: [_; MAX_TASKS + 1] = core::array::from_fn(|_| Counter { polls: &polls, pending: usize::MAX, waker: &waker });
+
+        let mut executor = Executor::new();
+        let (last, rest) = tasks.split_last_mut().unwrap();
+        for task in rest {
+            assert!(executor.spawn(Pin::<&mut Counter>::new(task)).is_ok());
+        }
+        assert!(executor.spawn(Pin::<&mut Counter>::new(last)).is_err());
+
+        assert!(executor.poll());
+        assert_eq!(polls.get(), MAX_TASKS);
+    }
+}
@@ -0,0 +1,118 @@
+/* filters of fixed point measurment values (moving average, median despiking) */
+
+
+
+/// Average (rounded towards zero) of `values`, `None` when there are no values.
+pub fn mean(values: impl Iterator<Item = i32>) -> Option<i32> {
+    let (sum, count) = values.fold((0i64, 0i64), |(sum, count), value| (sum + value as i64, count + 1));
+
+    (count != 0).then(|| (sum / count) as i32)
+}
+
+
+/// Median of the last 5 values, removes single spikes (up to two consecutive outliers) without delaying steps
+/// by more than 2 values.
+///
+/// Before 5 values were pushed median of pushed values is used (average of the two middle values for even count).
+#[derive(Debug, Clone, Copy)]
+pub struct Median5 {
+    /// circular, `next` is the oldest value when full
+    values: [i32; Median5::LEN],
+    len: usize,
+    next: usize,
+}
+
+impl Median5 {
+    const LEN: usize = 5;
+
+
+    pub const fn new() -> Self {
+        Self {
+            values: [0; Self::LEN],
+            len: 0,
+            next: 0,
+        }
+    }
+
+    /// Adds `value` and returns median of the last values.
+    pub fn push(&mut self, value: i32) -> i32 {
+        self.values[self.next] = value;
+        self.next = (self.next + 1) % Self::LEN;
+        self.len = (self.len + 1).min(Self::LEN);
+
+        let mut sorted = self.values;
+        let sorted = &mut sorted[..self.len];
+        sorted.sort_unstable();
+
+        if self.len % 2 == 1 {
+            sorted[self.len / 2]
+        } else {
+            ((sorted[self.len / 2 - 1] as i64 + sorted[self.len / 2] as i64) / 2) as i32
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.len = 0;
+        self.next = 0;
+    }
+}
+
+impl Default for Median5 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    #[test]
+    fn mean_of_values() {
+        assert_eq!(mean([].into_iter()), None);
+        assert_eq!(mean([415_000, 420_000, 431_000].into_iter()), Some(422_000));
+        assert_eq!(mean([-1, -2].into_iter()), Some(-1));
+        assert_eq!(mean([i32::MAX, i32::MAX].into_iter()), Some(i32::MAX));
+    }
+
+    #[test]
+    fn median_warm_up() {
+        let mut median = Median5::new();
+
+        assert_eq!(median.push(10), 10);
+        assert_eq!(median.push(20), 15);
+        assert_eq!(median.push(0), 10);
+        assert_eq!(median.push(30), 15);
+        assert_eq!(median.push(40), 20);
+    }
+
+    #[test]
+    fn median_removes_spikes() {
+        let mut median = Median5::new();
+        let filtered = [500, 510, 5000, 505, 495, 500, 6000, 7000, 500, 510]
+            .into_iter()
+            .map(|value| median.push(value))
+            .collect::<Vec<_>>();
+
+        assert!(filtered.iter().all(|value| (495..=510).contains(value)), "{:?}", filtered);
+    }
+
+    #[test]
+    fn median_follows_step() {
+        let mut median = Median5::new();
+        for _ in 0..5 {
+            median.push(400);
+        }
+
+        assert_eq!(median.push(1000), 400);
+        assert_eq!(median.push(1000), 400);
+        assert_eq!(median.push(1000), 1000);
+
+        median.reset();
+        assert_eq!(median.push(-5), -5);
+    }
+}
@@ -0,0 +1,306 @@
+/* conversion into and formatting of fixed point values (value * 10^3 stored as integer) without heap */
+
+use core::fmt;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseFloatE3Error {
+    NotFinite,
+    TooBig,
+}
+
+impl ParseFloatE3Error {
+    /// Code for error registry (`ErrorCode` is not implemented here, so this module does not depend on the rest of the firmware).
+    pub fn error_code(&self) -> u16 {
+        match self {
+            ParseFloatE3Error::NotFinite => 0x03,
+            ParseFloatE3Error::TooBig => 0x04,
+        }
+    }
+}
+
+/// Converts IEEE 754 single precision float (given as bits) into fixed point value `round(f * 1000)`.
+///
+/// Works for negative values (e.g. temperatures below 0 °C), values with absolute value smaller than `0.0005` are converted to `0`.
+/// Fails when value is NaN or infinity or when the result does not fit into `i32`.
+pub fn parse_float_e3(f: u32) -> Result<i32, ParseFloatE3Error> {
+    let negative = f >> 31 == 1;
+    let exp = ((f >> 23) & 0xff) as i32;
+    let frac = f & 0x7f_ffff;
+
+    if exp == 0xff {
+        return Err(ParseFloatE3Error::NotFinite);
+    }
+
+    if exp == 0 {
+        // zero or subnormal (absolute value smaller than 2^-126)
+        return Ok(0);
+    }
+
+    // f = mantissa * 2^(exp - 127 - 23)
+    let mantissa = (frac | (1 << 23)) as u64;
+    let shift = exp - 127 - 23;
+
+    let milli = if shift >= 0 {
+        // mantissa * 1000 >= 2^33, so any left shift would not fit into `i32`
+        return Err(ParseFloatE3Error::TooBig);
+    } else if shift <= -64 {
+        0
+    } else {
+        // rounding half away from zero, `mantissa * 1000 < 2^34` so no overflow happens
+        let shift = -shift as u32;
+        (mantissa * 1000 + (1 << (shift - 1))) >> shift
+    };
+
+    let milli = i32::try_from(milli).map_err(|_| ParseFloatE3Error::TooBig)?;
+
+    Ok(if negative { -milli } else { milli })
+}
+
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormatMilliError {
+    BufferTooSmall,
+    InvalidPrecision,
+}
+
+
+/// Maximum length of value formatted by `format_milli` (sign + 16 integer digits + dot + 3 decimals).
+pub const FORMAT_MILLI_MAX_LEN: usize = 21;
+
+/// Maximum precision (number of decimal places) supported by `format_milli`.
+pub const MILLI_MAX_PRECISION: u8 = 3;
+
+
+/// Formats fixed point `value` (`value / 1000` is the real value) into `buf` with `precision` decimal places.
+///
+/// Value is rounded (half away from zero), not truncated, e.g. `21_050` with precision 1 is `"21.1"`.
+/// Negative values which round to zero are formatted without sign (`"0.0"` instead of `"-0.0"`).
+///
+/// Returns formatted value as `&str` borrowed from `buf`.
+pub fn format_milli(value: i64, precision: u8, buf: &mut [u8]) -> Result<&str, FormatMilliError> {
+    if precision > MILLI_MAX_PRECISION {
+        return Err(FormatMilliError::InvalidPrecision);
+    }
+
+    let divisor = 10u64.pow((MILLI_MAX_PRECISION - precision) as u32);
+    let rounded = (value.unsigned_abs() + divisor / 2) / divisor;
+
+    let negative = value < 0 && rounded != 0;
+
+    // digits are written from the end
+    let mut tmp = [0u8; FORMAT_MILLI_MAX_LEN];
+    let mut start = tmp.len();
+
+    let mut rest = rounded;
+    for _ in 0..precision {
+        start -= 1;
+        tmp[start] = b'0' + (rest % 10) as u8;
+        rest /= 10;
+    }
+
+    if precision != 0 {
+        start -= 1;
+        tmp[start] = b'.';
+    }
+
+    loop {
+        start -= 1;
+        tmp[start] = b'0' + (rest % 10) as u8;
+        rest /= 10;
+
+        if rest == 0 {
+            break;
+        }
+    }
+
+    if negative {
+        start -= 1;
+        tmp[start] = b'-';
+    }
+
+    let formatted = &tmp[start..];
+    let out = buf.get_mut(..formatted.len()).ok_or(FormatMilliError::BufferTooSmall)?;
+    out.copy_from_slice(formatted);
+
+    // SAFETY: only ascii digits, '.' and '-' were written
+    Ok(unsafe { core::str::from_utf8_unchecked(out) })
+}
+
+
+/// Fixed point value (`value / 1000` is the real value) which can be used directly in `write!` (formatted by `format_milli`).
+///
+/// Precision is number of decimal places (`{:.1}`), default and maximum is `MILLI_MAX_PRECISION` (larger precision is clamped).
+/// Width, alignment, sign and zero padding work as for integers (e.g. `{:>7.1}`, `{:+.2}`, `{:06.1}`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Milli(pub i64);
+
+impl From<i32> for Milli {
+    fn from(value: i32) -> Self {
+        Milli(value.into())
+    }
+}
+
+impl From<u32> for Milli {
+    fn from(value: u32) -> Self {
+        Milli(value.into())
+    }
+}
+
+impl fmt::Display for Milli {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let precision = f.precision().map_or(MILLI_MAX_PRECISION, |precision| precision.min(MILLI_MAX_PRECISION as usize) as u8);
+
+        // cannot fail, precision is valid and buffer is large enough
+        let mut buf = [0u8; FORMAT_MILLI_MAX_LEN];
+        let formatted = format_milli(self.0, precision, &mut buf).map_err(|_| fmt::Error)?;
+
+        match formatted.strip_prefix('-') {
+            Some(digits) => f.pad_integral(false, "", digits),
+            None => f.pad_integral(true, "", formatted),
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_positive() {
+        assert_eq!(parse_float_e3(0.0f32.to_bits()), Ok(0));
+        assert_eq!(parse_float_e3(1.0f32.to_bits()), Ok(1_000));
+        assert_eq!(parse_float_e3(21.3f32.to_bits()), Ok(21_300));
+        assert_eq!(parse_float_e3(415.0625f32.to_bits()), Ok(415_063));
+        assert_eq!(parse_float_e3(100.0f32.to_bits()), Ok(100_000));
+        assert_eq!(parse_float_e3(40_000.0f32.to_bits()), Ok(40_000_000));
+    }
+
+    #[test]
+    fn parse_scd30_response() {
+        // co2, temperature and humidity of measurment example in scd30 interface description
+        assert_eq!(parse_float_e3(0x43db_8c2e), Ok(439_095));
+        assert_eq!(parse_float_e3(0x41d9_e7ff), Ok(27_238));
+        assert_eq!(parse_float_e3(0x4243_3a1b), Ok(48_807));
+    }
+
+    #[test]
+    fn parse_negative() {
+        assert_eq!(parse_float_e3((-0.0f32).to_bits()), Ok(0));
+        assert_eq!(parse_float_e3((-0.1f32).to_bits()), Ok(-100));
+        assert_eq!(parse_float_e3((-0.3f32).to_bits()), Ok(-300));
+        assert_eq!(parse_float_e3((-0.0004f32).to_bits()), Ok(0));
+        assert_eq!(parse_float_e3((-0.0005f32).to_bits()), Ok(-1));
+        assert_eq!(parse_float_e3((-12.75f32).to_bits()), Ok(-12_750));
+        // lower bound of scd30 temperature range
+        assert_eq!(parse_float_e3((-40.0f32).to_bits()), Ok(-40_000));
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(parse_float_e3(f32::NAN.to_bits()), Err(ParseFloatE3Error::NotFinite));
+        assert_eq!(parse_float_e3(f32::INFINITY.to_bits()), Err(ParseFloatE3Error::NotFinite));
+        assert_eq!(parse_float_e3(f32::NEG_INFINITY.to_bits()), Err(ParseFloatE3Error::NotFinite));
+        assert_eq!(parse_float_e3(3_000_000.0f32.to_bits()), Err(ParseFloatE3Error::TooBig));
+        assert_eq!(parse_float_e3(f32::MAX.to_bits()), Err(ParseFloatE3Error::TooBig));
+        assert_eq!(parse_float_e3(f32::MIN_POSITIVE.to_bits()), Ok(0));
+    }
+
+    #[test]
+    fn parse_subnormal_and_bounds() {
+        // smallest and largest subnormal
+        assert_eq!(parse_float_e3(0x0000_0001), Ok(0));
+        assert_eq!(parse_float_e3(0x807f_ffff), Ok(0));
+
+        assert_eq!(parse_float_e3(2_147_483.5f32.to_bits()), Ok(2_147_483_500));
+        assert_eq!(parse_float_e3((-2_147_483.5f32).to_bits()), Ok(-2_147_483_500));
+        assert_eq!(parse_float_e3(2_147_484.0f32.to_bits()), Err(ParseFloatE3Error::TooBig));
+    }
+
+    #[test]
+    fn parse_matches_f64_rounding() {
+        // every 4099th bit pattern of finite floats which fit, compared with rounding in f64 (exact for products of f32 and 1000)
+        for f in (0..=u32::MAX).step_by(4099) {
+            let value = f32::from_bits(f);
+            if !value.is_finite() || (value as f64).abs() >= 2_147_483.0 {
+                continue;
+            }
+
+            assert_eq!(parse_float_e3(f), Ok((value as f64 * 1000.0).round() as i32), "{:#010x}", f);
+        }
+    }
+
+    #[test]
+    fn parse_then_format_negative() {
+        let mut buf = [0u8; FORMAT_MILLI_MAX_LEN];
+        let milli = parse_float_e3((-0.25f32).to_bits()).unwrap();
+        assert_eq!(format_milli(milli.into(), 1, &mut buf), Ok("-0.3"));
+    }
+
+    fn format(value: i64, precision: u8) -> Result<String, FormatMilliError> {
+        let mut buf = [0u8; FORMAT_MILLI_MAX_LEN];
+        format_milli(value, precision, &mut buf).map(String::from)
+    }
+
+    #[test]
+    fn rounds_instead_of_truncating() {
+        assert_eq!(format(21_050, 1).unwrap(), "21.1");
+        assert_eq!(format(21_049, 1).unwrap(), "21.0");
+        assert_eq!(format(415_499, 0).unwrap(), "415");
+        assert_eq!(format(415_500, 0).unwrap(), "416");
+        assert_eq!(format(1_005, 2).unwrap(), "1.01");
+    }
+
+    #[test]
+    fn pads_decimals() {
+        assert_eq!(format(21_005, 3).unwrap(), "21.005");
+        assert_eq!(format(7, 3).unwrap(), "0.007");
+        assert_eq!(format(0, 2).unwrap(), "0.00");
+    }
+
+    #[test]
+    fn negative_temperatures() {
+        assert_eq!(format(-40_000, 1).unwrap(), "-40.0");
+        assert_eq!(format(-1_250, 1).unwrap(), "-1.3");
+        assert_eq!(format(-50, 1).unwrap(), "-0.1");
+        assert_eq!(format(-49, 1).unwrap(), "0.0");
+        assert_eq!(format(-1, 3).unwrap(), "-0.001");
+    }
+
+    #[test]
+    fn full_humidity() {
+        assert_eq!(format(100_000, 1).unwrap(), "100.0");
+        assert_eq!(format(99_950, 1).unwrap(), "100.0");
+        assert_eq!(format(99_949, 1).unwrap(), "99.9");
+        assert_eq!(format(99_999, 0).unwrap(), "100");
+    }
+
+    #[test]
+    fn extreme_values() {
+        assert_eq!(format(i64::MAX, 3).unwrap(), "9223372036854775.807");
+        assert_eq!(format(i64::MIN, 3).unwrap(), "-9223372036854775.808");
+        assert_eq!(format(i64::MIN, 0).unwrap(), "-9223372036854776");
+    }
+
+    #[test]
+    fn milli_display() {
+        assert_eq!(format!("{}", Milli(21_005)), "21.005");
+        assert_eq!(format!("{:.1}", Milli::from(-1_250)), "-1.3");
+        assert_eq!(format!("{:.1}", Milli(-49)), "0.0");
+        assert_eq!(format!("{:.6}", Milli(7)), "0.007");
+        assert_eq!(format!("{:>7.1}|{:<7.0}|", Milli(415_063), Milli(-2_500)), "  415.1|-3     |");
+        assert_eq!(format!("{:+.2} {:06.1}", Milli(1_005), Milli(-1_000)), "+1.01 -001.0");
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(format(1_000, 4), Err(FormatMilliError::InvalidPrecision));
+
+        let mut buf = [0u8; 3];
+        assert_eq!(format_milli(10_000, 1, &mut buf), Err(FormatMilliError::BufferTooSmall));
+        assert_eq!(format_milli(1_000, 1, &mut buf), Ok("1.0"));
+    }
+}
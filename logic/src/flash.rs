@@ -0,0 +1,78 @@
+/* sector based access to a flash region (erase sector, then write), shared by config store and measurment log */
+
+
+
+/// Size of erase unit of the flash.
+pub const SECTOR_SIZE: u32 = 4096;
+
+
+/// Flash region made of whole sectors, offsets are relative to its start.
+///
+/// Erased flash reads as `0xff`, write can only clear bits.
+pub trait SectorFlash {
+    type Error;
+
+    /// `bytes` length is multiple of 4
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error>;
+    /// `sector` is index of sector inside the region
+    fn erase_sector(&mut self, sector: u32) -> Result<(), Self::Error>;
+    /// `bytes` length is multiple of 4, written range has to be erased
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error>;
+}
+
+impl<T: SectorFlash> SectorFlash for &mut T {
+    type Error = T::Error;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+        T::read(self, offset, bytes)
+    }
+
+    fn erase_sector(&mut self, sector: u32) -> Result<(), Self::Error> {
+        T::erase_sector(self, sector)
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+        T::write(self, offset, bytes)
+    }
+}
+
+
+
+/// Flash region in ram for tests, counts erases of each sector.
+#[cfg(test)]
+pub(crate) struct RamFlash {
+    pub bytes: std::vec::Vec<u8>,
+    pub erase_count: std::vec::Vec<u32>,
+}
+
+#[cfg(test)]
+impl RamFlash {
+    pub fn new(sectors: u32) -> Self {
+        Self {
+            bytes: std::vec![0xff; (sectors * SECTOR_SIZE) as usize],
+            erase_count: std::vec![0; sectors as usize],
+        }
+    }
+}
+
+#[cfg(test)]
+impl SectorFlash for RamFlash {
+    type Error = ();
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), ()> {
+        bytes.copy_from_slice(&self.bytes[(offset as usize)..(offset as usize + bytes.len())]);
+        Ok(())
+    }
+
+    fn erase_sector(&mut self, sector: u32) -> Result<(), ()> {
+        let start = (sector * SECTOR_SIZE) as usize;
+        self.bytes[start..(start + SECTOR_SIZE as usize)].fill(0xff);
+        self.erase_count[sector as usize] += 1;
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), ()> {
+        self.bytes[(offset as usize)..].iter_mut().zip(bytes).for_each(|(flash, byte)| *flash &= byte);
+        Ok(())
+    }
+}
@@ -0,0 +1,340 @@
+/* circular log of compact measurment records in flash sectors, oldest sector is erased when the log is full */
+
+use crate::{flash::{SectorFlash, SECTOR_SIZE}, framing::crc16};
+
+
+
+/// Magic + sequence number of the sector.
+const HEADER_LEN: u32 = 8;
+
+/// Identifies log sector, has to be changed when `LogRecord` encoding changes.
+const MAGIC: u32 = 0x4c4f_4701;
+
+/// Encoded record + crc16.
+const SLOT_LEN: u32 = LogRecord::ENCODED_LEN as u32 + 2;
+
+pub const RECORDS_PER_SECTOR: u32 = (SECTOR_SIZE - HEADER_LEN) / SLOT_LEN;
+
+
+/// Time of the record, wall clock time is not known until it is synchronized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogTime {
+    /// unix time (in seconds)
+    Unix(u32),
+    /// seconds since boot (boot itself is unknown)
+    SinceBoot(u32),
+}
+
+/// Compact measurment record (10 bytes): time (u32, highest bit set for `LogTime::SinceBoot`),
+/// co2 in ppm (u16), temperature in c°C (i16), humidity in d% (u16), all little endian.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogRecord {
+    pub time: LogTime,
+    pub co2: u16,
+    pub temperature: i16,
+    pub humidity: u16,
+}
+
+impl LogRecord {
+    pub const ENCODED_LEN: usize = 10;
+
+    const SINCE_BOOT_FLAG: u32 = 1 << 31;
+
+
+    pub fn encode(&self) -> [u8; Self::ENCODED_LEN] {
+        let time = match self.time {
+            LogTime::Unix(seconds) => seconds & !Self::SINCE_BOOT_FLAG,
+            LogTime::SinceBoot(seconds) => seconds | Self::SINCE_BOOT_FLAG,
+        };
+
+        let mut bytes = [0; Self::ENCODED_LEN];
+
+        bytes[0..4].copy_from_slice(&time.to_le_bytes());
+        bytes[4..6].copy_from_slice(&self.co2.to_le_bytes());
+        bytes[6..8].copy_from_slice(&self.temperature.to_le_bytes());
+        bytes[8..10].copy_from_slice(&self.humidity.to_le_bytes());
+
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8; Self::ENCODED_LEN]) -> Self {
+        let time = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+
+        Self {
+            time: if time & Self::SINCE_BOOT_FLAG != 0 { LogTime::SinceBoot(time & !Self::SINCE_BOOT_FLAG) } else { LogTime::Unix(time) },
+            co2: u16::from_le_bytes([bytes[4], bytes[5]]),
+            temperature: i16::from_le_bytes([bytes[6], bytes[7]]),
+            humidity: u16::from_le_bytes([bytes[8], bytes[9]]),
+        }
+    }
+}
+
+
+/// Position of reading, starts at the oldest record (see `FlashLog::cursor`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LogCursor {
+    /// sectors from the oldest one
+    step: u32,
+    index: u32,
+}
+
+
+
+/// Circular log in `sectors` flash sectors, each sector has a header with sequence number followed by record slots.
+///
+/// Head (sector being written) is the sector with the newest sequence number, it is found by `open`.
+/// When the head is full, the next sector is erased and becomes the head, so the log keeps `sectors - 1` to `sectors` sectors of records.
+/// Slot with wrong crc (power lost during write) is skipped.
+pub struct FlashLog<F> {
+    flash: F,
+    sectors: u32,
+    /// sector, its sequence number and index of the next free slot, `None` when log is empty (no valid sector)
+    head: Option<(u32, u32, u32)>,
+}
+
+impl<F: SectorFlash> FlashLog<F> {
+    /// Finds the head of the log stored in `flash` (at least 2 sectors).
+    pub fn open(mut flash: F, sectors: u32) -> Result<Self, F::Error> {
+        let mut head: Option<(u32, u32)> = None;
+
+        for sector in 0..sectors {
+            if let Some(sequence) = Self::read_header(&mut flash, sector)?
+                && head.map_or(true, |(_, head_sequence)| (sequence.wrapping_sub(head_sequence) as i32) > 0)
+            {
+                head = Some((sector, sequence));
+            }
+        }
+
+        let head = match head {
+            Some((sector, sequence)) => {
+                let mut index = 0;
+
+                while index < RECORDS_PER_SECTOR && !Self::slot_is_erased(&mut flash, sector, index)? {
+                    index += 1;
+                }
+
+                Some((sector, sequence, index))
+            },
+            None => None,
+        };
+
+        Ok(Self { flash, sectors, head })
+    }
+
+    fn read_header(flash: &mut F, sector: u32) -> Result<Option<u32>, F::Error> {
+        let mut header = [0; HEADER_LEN as usize];
+        flash.read(sector * SECTOR_SIZE, &mut header)?;
+
+        let magic = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let sequence = u32::from_le_bytes([header[4], header[5], header[6], header[7]]);
+
+        Ok((magic == MAGIC).then_some(sequence))
+    }
+
+    fn slot_offset(sector: u32, index: u32) -> u32 {
+        sector * SECTOR_SIZE + HEADER_LEN + index * SLOT_LEN
+    }
+
+    fn read_slot(flash: &mut F, sector: u32, index: u32) -> Result<[u8; SLOT_LEN as usize], F::Error> {
+        let mut slot = [0; SLOT_LEN as usize];
+        flash.read(Self::slot_offset(sector, index), &mut slot)?;
+
+        Ok(slot)
+    }
+
+    fn slot_is_erased(flash: &mut F, sector: u32, index: u32) -> Result<bool, F::Error> {
+        Ok(Self::read_slot(flash, sector, index)?.iter().all(|byte| *byte == 0xff))
+    }
+
+    /// Number of records which fit into the log (when it is full, the oldest sector is erased by next append).
+    pub fn capacity(&self) -> u32 {
+        self.sectors * RECORDS_PER_SECTOR
+    }
+
+    /// Head is full (or log is empty), next `append` has to erase a sector (see `prepare_head`).
+    pub fn needs_erase(&self) -> bool {
+        self.head.map_or(true, |(_, _, index)| index == RECORDS_PER_SECTOR)
+    }
+
+    /// Erases the next sector and makes it the head when the head is full (blocks for the duration of sector erase), so
+    /// the erase can be done ahead of `append` when it does not disturb anything else.
+    pub fn prepare_head(&mut self) -> Result<(), F::Error> {
+        if !self.needs_erase() {
+            return Ok(());
+        }
+
+        let (sector, sequence) = self.head.map_or((0, 0), |(sector, sequence, _)| ((sector + 1) % self.sectors, sequence.wrapping_add(1)));
+
+        // head is moved only after the sector is ready, failed erase is retried by next call
+        self.flash.erase_sector(sector)?;
+
+        let mut header = [0; HEADER_LEN as usize];
+        header[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+        header[4..8].copy_from_slice(&sequence.to_le_bytes());
+        self.flash.write(sector * SECTOR_SIZE, &header)?;
+
+        self.head = Some((sector, sequence, 0));
+
+        Ok(())
+    }
+
+    /// Appends record, erases the next sector first when the head is full (see `prepare_head`).
+    pub fn append(&mut self, record: &LogRecord) -> Result<(), F::Error> {
+        self.prepare_head()?;
+
+        // head is set by `prepare_head`
+        let Some((sector, sequence, index)) = self.head else {
+            return Ok(());
+        };
+
+        let mut slot = [0xff; SLOT_LEN as usize];
+        slot[..LogRecord::ENCODED_LEN].copy_from_slice(&record.encode());
+        let crc = crc16(&slot[..LogRecord::ENCODED_LEN]);
+        slot[LogRecord::ENCODED_LEN..].copy_from_slice(&crc.to_le_bytes());
+
+        // slot is used even when the write fails (it may be partially written)
+        self.head = Some((sector, sequence, index + 1));
+
+        self.flash.write(Self::slot_offset(sector, index), &slot)
+    }
+
+    /// Cursor at the oldest record.
+    pub fn cursor(&self) -> LogCursor {
+        LogCursor { step: 0, index: 0 }
+    }
+
+    /// Reads record at `cursor` and moves cursor after it, `None` after the newest record.
+    ///
+    /// Cursor is not adjusted by `append`, when the oldest sector is erased during reading, records of the erased sector are missed.
+    pub fn read_next(&mut self, cursor: &mut LogCursor) -> Result<Option<LogRecord>, F::Error> {
+        let Some((head, _, _)) = self.head else {
+            return Ok(None);
+        };
+
+        while cursor.step < self.sectors {
+            // oldest sector is the one after the head (it may be not used yet, then it has no valid header)
+            let sector = (head + 1 + cursor.step) % self.sectors;
+
+            if cursor.index == 0 && Self::read_header(&mut self.flash, sector)?.is_none() {
+                cursor.step += 1;
+                continue;
+            }
+
+            if cursor.index == RECORDS_PER_SECTOR || Self::slot_is_erased(&mut self.flash, sector, cursor.index)? {
+                cursor.step += 1;
+                cursor.index = 0;
+                continue;
+            }
+
+            let slot = Self::read_slot(&mut self.flash, sector, cursor.index)?;
+            cursor.index += 1;
+
+            let (encoded, crc) = slot.split_at(LogRecord::ENCODED_LEN);
+
+            if crc16(encoded) == u16::from_le_bytes([crc[0], crc[1]]) {
+                // cannot fail, `encoded` has `ENCODED_LEN` bytes
+                if let Ok(encoded) = encoded.try_into() {
+                    return Ok(Some(LogRecord::decode(encoded)));
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Iterator over stored records from the oldest one.
+    pub fn records(&mut self) -> impl Iterator<Item = Result<LogRecord, F::Error>> + '_ {
+        let mut cursor = self.cursor();
+
+        core::iter::from_fn(move || self.read_next(&mut cursor).transpose())
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+    use crate::flash::RamFlash;
+
+
+    fn record(seconds: u32) -> LogRecord {
+        LogRecord { time: LogTime::Unix(seconds), co2: 450, temperature: 2150, humidity: 455 }
+    }
+
+    fn stored_seconds(log: &mut FlashLog<&mut RamFlash>) -> Vec<u32> {
+        log.records().map(|record| match record.unwrap().time {
+            LogTime::Unix(seconds) | LogTime::SinceBoot(seconds) => seconds,
+        }).collect()
+    }
+
+
+    #[test]
+    fn record_roundtrip_keeps_time_kind() {
+        let since_boot = LogRecord { time: LogTime::SinceBoot(42), co2: 2_000, temperature: -150, humidity: 1_000 };
+
+        assert_eq!(LogRecord::decode(&since_boot.encode()), since_boot);
+        assert_eq!(LogRecord::decode(&record(1_700_000_000).encode()), record(1_700_000_000));
+    }
+
+    #[test]
+    fn reopened_log_continues_after_last_record() {
+        let mut flash = RamFlash::new(3);
+
+        let mut log = FlashLog::open(&mut flash, 3).unwrap();
+        assert!(log.records().next().is_none());
+        (0..5).for_each(|seconds| log.append(&record(seconds)).unwrap());
+
+        let mut log = FlashLog::open(&mut flash, 3).unwrap();
+        log.append(&record(5)).unwrap();
+
+        assert_eq!(stored_seconds(&mut log), (0..6).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn full_log_erases_oldest_sector() {
+        let mut flash = RamFlash::new(2);
+        let total = 2 * RECORDS_PER_SECTOR + 3;
+
+        let mut log = FlashLog::open(&mut flash, 2).unwrap();
+        (0..total).for_each(|seconds| log.append(&record(seconds)).unwrap());
+
+        let mut log = FlashLog::open(&mut flash, 2).unwrap();
+        assert_eq!(stored_seconds(&mut log), (RECORDS_PER_SECTOR..total).collect::<Vec<_>>());
+        assert_eq!(flash.erase_count, [2, 1]);
+    }
+
+    #[test]
+    fn prepared_head_is_not_erased_by_append() {
+        let mut flash = RamFlash::new(2);
+
+        let mut log = FlashLog::open(&mut flash, 2).unwrap();
+        assert!(log.needs_erase());
+        (0..RECORDS_PER_SECTOR).for_each(|seconds| log.append(&record(seconds)).unwrap());
+        assert!(log.needs_erase());
+
+        log.prepare_head().unwrap();
+        assert!(!log.needs_erase());
+        log.append(&record(RECORDS_PER_SECTOR)).unwrap();
+
+        let mut log = FlashLog::open(&mut flash, 2).unwrap();
+        assert_eq!(stored_seconds(&mut log), (0..=RECORDS_PER_SECTOR).collect::<Vec<_>>());
+        assert_eq!(flash.erase_count, [1, 1]);
+    }
+
+    #[test]
+    fn corrupted_record_is_skipped() {
+        let mut flash = RamFlash::new(2);
+
+        let mut log = FlashLog::open(&mut flash, 2).unwrap();
+        (0..3).for_each(|seconds| log.append(&record(seconds)).unwrap());
+
+        // power lost during write of the second record
+        flash.bytes[(HEADER_LEN + SLOT_LEN) as usize] = 0x00;
+
+        let mut log = FlashLog::open(&mut flash, 2).unwrap();
+        assert_eq!(stored_seconds(&mut log), [0, 2]);
+    }
+}
@@ -0,0 +1,215 @@
+/* monochrome framebuffer in page layout of ssd1306 / sh1106 oled controllers, with small 5x7 font */
+
+
+
+pub const WIDTH: usize = 128;
+pub const HEIGHT: usize = 64;
+/// Each page is 8 rows high, one byte is one column of a page (least significant bit is the top row).
+pub const PAGES: usize = HEIGHT / 8;
+
+/// Width of glyph (without spacing), glyphs are 7 rows high.
+pub const GLYPH_WIDTH: usize = 5;
+pub const GLYPH_HEIGHT: usize = 7;
+
+
+/// Columns of 5x7 glyph (least significant bit is the top row), lowercase letters are drawn as uppercase.
+///
+/// Only characters used by the display are included, `None` for other characters.
+pub fn glyph(c: char) -> Option<[u8; GLYPH_WIDTH]> {
+    let columns = match c.to_ascii_uppercase() {
+        ' ' => [0x00, 0x00, 0x00, 0x00, 0x00],
+        '0' => [0x3e, 0x51, 0x49, 0x45, 0x3e],
+        '1' => [0x00, 0x42, 0x7f, 0x40, 0x00],
+        '2' => [0x42, 0x61, 0x51, 0x49, 0x46],
+        '3' => [0x21, 0x41, 0x45, 0x4b, 0x31],
+        '4' => [0x18, 0x14, 0x12, 0x7f, 0x10],
+        '5' => [0x27, 0x45, 0x45, 0x45, 0x39],
+        '6' => [0x3c, 0x4a, 0x49, 0x49, 0x30],
+        '7' => [0x01, 0x71, 0x09, 0x05, 0x03],
+        '8' => [0x36, 0x49, 0x49, 0x49, 0x36],
+        '9' => [0x06, 0x49, 0x49, 0x29, 0x1e],
+        '.' => [0x00, 0x60, 0x60, 0x00, 0x00],
+        '-' => [0x08, 0x08, 0x08, 0x08, 0x08],
+        '%' => [0x23, 0x13, 0x08, 0x64, 0x62],
+        ':' => [0x00, 0x36, 0x36, 0x00, 0x00],
+        '/' => [0x20, 0x10, 0x08, 0x04, 0x02],
+        '?' => [0x02, 0x01, 0x51, 0x09, 0x06],
+        '°' => [0x00, 0x06, 0x09, 0x09, 0x06],
+        'C' => [0x3e, 0x41, 0x41, 0x41, 0x22],
+        'H' => [0x7f, 0x08, 0x08, 0x08, 0x7f],
+        'M' => [0x7f, 0x02, 0x0c, 0x02, 0x7f],
+        'O' => [0x3e, 0x41, 0x41, 0x41, 0x3e],
+        'P' => [0x7f, 0x09, 0x09, 0x09, 0x06],
+        'R' => [0x7f, 0x09, 0x19, 0x29, 0x46],
+        'T' => [0x01, 0x01, 0x7f, 0x01, 0x01],
+        _ => return None,
+    };
+
+    Some(columns)
+}
+
+
+/// 128x64 framebuffer, pixels outside of the screen are clipped.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Framebuffer {
+    pages: [[u8; WIDTH]; PAGES],
+}
+
+impl Framebuffer {
+    pub const fn new() -> Self {
+        Self {
+            pages: [[0; WIDTH]; PAGES],
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.pages = [[0; WIDTH]; PAGES];
+    }
+
+    /// Columns of page `page` (`< PAGES`), in the order sent to the display.
+    pub fn page(&self, page: usize) -> &[u8; WIDTH] {
+        &self.pages[page]
+    }
+
+    /// Bit mask of pages (bit `n` is page `n`) which differ from `other`.
+    pub fn changed_pages(&self, other: &Framebuffer) -> u8 {
+        (0..PAGES).filter(|&page| self.pages[page] != other.pages[page]).fold(0, |mask, page| mask | (1 << page))
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, on: bool) {
+        if x >= WIDTH || y >= HEIGHT {
+            return;
+        }
+
+        let byte = &mut self.pages[y / 8][x];
+        if on {
+            *byte |= 1 << (y % 8);
+        } else {
+            *byte &= !(1 << (y % 8));
+        }
+    }
+
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, on: bool) {
+        for y in y..(y + height).min(HEIGHT) {
+            for x in x..(x + width).min(WIDTH) {
+                self.set_pixel(x, y, on);
+            }
+        }
+    }
+
+    /// Draws bitmap given by 8 row columns (least significant bit is the top row), each pixel is `scale` x `scale` square.
+    ///
+    /// Only set bits are drawn (background is not cleared).
+    pub fn draw_columns(&mut self, x: usize, y: usize, columns: &[u8], scale: usize) {
+        for (column_x, column) in columns.iter().enumerate() {
+            for row in (0..8).filter(|row| column & (1 << row) != 0) {
+                self.fill_rect(x + column_x * scale, y + row * scale, scale, scale, true);
+            }
+        }
+    }
+
+    /// Width of `text` drawn by `draw_text`, characters are separated by one (scaled) empty column.
+    pub fn text_width(text: &str, scale: usize) -> usize {
+        let len = text.chars().count();
+        (len * (GLYPH_WIDTH + 1)).saturating_sub(1) * scale
+    }
+
+    /// Draws `text` with top left corner at `x`, `y`, characters without glyph are drawn as `?`.
+    ///
+    /// Returns `x` after the last character (including spacing).
+    pub fn draw_text(&mut self, x: usize, y: usize, text: &str, scale: usize) -> usize {
+        let mut x = x;
+
+        for c in text.chars() {
+            // cannot fail, '?' has glyph
+            let columns = glyph(c).or(glyph('?')).unwrap_or_default();
+            self.draw_columns(x, y, &columns, scale);
+            x += (GLYPH_WIDTH + 1) * scale;
+        }
+
+        x
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixel_page_layout() {
+        let mut framebuffer = Framebuffer::new();
+        framebuffer.set_pixel(3, 0, true);
+        framebuffer.set_pixel(3, 7, true);
+        framebuffer.set_pixel(127, 63, true);
+
+        assert_eq!(framebuffer.page(0)[3], 0x81);
+        assert_eq!(framebuffer.page(7)[127], 0x80);
+
+        framebuffer.set_pixel(3, 0, false);
+        assert_eq!(framebuffer.page(0)[3], 0x80);
+    }
+
+    #[test]
+    fn clipping() {
+        let mut framebuffer = Framebuffer::new();
+        framebuffer.set_pixel(WIDTH, 0, true);
+        framebuffer.set_pixel(0, HEIGHT, true);
+        framebuffer.fill_rect(120, 60, 20, 20, true);
+
+        assert_eq!(framebuffer.page(7)[119], 0);
+        assert_eq!(framebuffer.page(7)[120], 0xf0);
+        assert_eq!(framebuffer.page(7)[127], 0xf0);
+    }
+
+    #[test]
+    fn text() {
+        let mut framebuffer = Framebuffer::new();
+        let end = framebuffer.draw_text(0, 0, "1.", 1);
+
+        assert_eq!(end, 12);
+        assert_eq!(framebuffer.page(0)[..12], [0x00, 0x42, 0x7f, 0x40, 0x00, 0x00, 0x00, 0x60, 0x60, 0x00, 0x00, 0x00]);
+        assert_eq!(Framebuffer::text_width("1.", 1), 11);
+        assert_eq!(Framebuffer::text_width("", 2), 0);
+    }
+
+    #[test]
+    fn scaled_text_across_pages() {
+        let mut framebuffer = Framebuffer::new();
+        // third column of '1' is full (rows 0 - 6), scaled rows 0 - 20
+        framebuffer.draw_text(0, 0, "1", 3);
+
+        assert_eq!(framebuffer.page(0)[2], 0);
+        assert_eq!([framebuffer.page(0)[6], framebuffer.page(1)[6], framebuffer.page(2)[6]], [0xff, 0xff, 0x1f]);
+        assert_eq!([framebuffer.page(0)[8], framebuffer.page(1)[8], framebuffer.page(2)[8]], [0xff, 0xff, 0x1f]);
+        assert_eq!([framebuffer.page(0)[9], framebuffer.page(1)[9], framebuffer.page(2)[9]], [0x00, 0x00, 0x1c]);
+    }
+
+    #[test]
+    fn unknown_character() {
+        let mut unknown = Framebuffer::new();
+        unknown.draw_text(0, 0, "#", 1);
+        let mut question = Framebuffer::new();
+        question.draw_text(0, 0, "?", 1);
+
+        assert_eq!(unknown, question);
+    }
+
+    #[test]
+    fn changed_pages() {
+        let mut a = Framebuffer::new();
+        let b = Framebuffer::new();
+        assert_eq!(a.changed_pages(&b), 0);
+
+        a.set_pixel(0, 9, true);
+        a.set_pixel(50, 63, true);
+        assert_eq!(a.changed_pages(&b), 0b1000_0010);
+    }
+}
@@ -0,0 +1,179 @@
+/* binary framed protocol: frame = cobs(type, sequence number, payload, crc16) + 0x00 delimiter */
+
+
+
+/// Type of the frame, so host can demultiplex data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FrameType {
+    /// `sinks::BinaryEncoding` record
+    Measurment = 1,
+    /// one line of text output
+    Log = 2,
+    /// periodic status (see `DebugPrint`)
+    Health = 3,
+    /// description of one metric (see `metrics`)
+    Metadata = 4,
+    /// decoded ir message (see `ir::IrMessage::encode`)
+    IrCode = 5,
+    /// error recorded by a subsystem (see `error_registry`)
+    Error = 6,
+}
+
+
+/// Maximum length of frame payload.
+pub const MAX_PAYLOAD_LEN: usize = 128;
+
+/// Type + sequence number + payload + crc.
+const MAX_RAW_LEN: usize = 2 + MAX_PAYLOAD_LEN + 2;
+
+/// Maximum length of encoded frame (cobs adds 1 byte per 254 bytes (at least 1) and delimiter).
+pub const MAX_FRAME_LEN: usize = MAX_RAW_LEN + MAX_RAW_LEN / 254 + 1 + 1;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameError {
+    PayloadTooLong,
+}
+
+
+
+/// CRC-16/CCITT-FALSE (polynomial 0x1021, initial value 0xffff).
+pub fn crc16(bytes: &[u8]) -> u16 {
+    bytes.iter().fold(0xffff, |crc, byte| {
+        (0..8).fold(crc ^ ((*byte as u16) << 8), |crc, _| {
+            if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 }
+        })
+    })
+}
+
+/// Consistent overhead byte stuffing, `out` has to be at least `bytes.len() + bytes.len() / 254 + 1` long.
+/// Encoded data does not contain zeros, returns length of encoded data (without delimiter).
+pub fn cobs_encode(bytes: &[u8], out: &mut [u8]) -> usize {
+    let mut code_index = 0;
+    let mut out_index = 1;
+    let mut code = 1u8;
+
+    for byte in bytes {
+        if *byte == 0 {
+            out[code_index] = code;
+            code_index = out_index;
+            out_index += 1;
+            code = 1;
+        } else {
+            out[out_index] = *byte;
+            out_index += 1;
+            code += 1;
+
+            if code == 0xff {
+                out[code_index] = code;
+                code_index = out_index;
+                out_index += 1;
+                code = 1;
+            }
+        }
+    }
+
+    out[code_index] = code;
+
+    out_index
+}
+
+/// Encodes whole frame (including `0x00` delimiter) into `out`, returns length of the frame.
+pub fn encode_frame(frame_type: FrameType, sequence: u8, payload: &[u8], out: &mut [u8; MAX_FRAME_LEN]) -> Result<usize, FrameError> {
+    if payload.len() > MAX_PAYLOAD_LEN {
+        return Err(FrameError::PayloadTooLong);
+    }
+
+    let mut raw = [0u8; MAX_RAW_LEN];
+    raw[0] = frame_type as u8;
+    raw[1] = sequence;
+    raw[2..(2 + payload.len())].copy_from_slice(payload);
+
+    let crc = crc16(&raw[..(2 + payload.len())]);
+    raw[(2 + payload.len())..(4 + payload.len())].copy_from_slice(&crc.to_le_bytes());
+
+    let len = cobs_encode(&raw[..(4 + payload.len())], out);
+    out[len] = 0;
+
+    Ok(len + 1)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    fn cobs_decode(encoded: &[u8]) -> std::vec::Vec<u8> {
+        let mut decoded = std::vec::Vec::new();
+        let mut i = 0;
+
+        while i < encoded.len() {
+            let code = encoded[i] as usize;
+            decoded.extend_from_slice(&encoded[(i + 1)..(i + code)]);
+            i += code;
+
+            if code != 0xff && i < encoded.len() {
+                decoded.push(0);
+            }
+        }
+
+        decoded
+    }
+
+
+    #[test]
+    fn crc16_check_value() {
+        // standard check value of CRC-16/CCITT-FALSE
+        assert_eq!(crc16(b"123456789"), 0x29b1);
+        assert_eq!(crc16(&[]), 0xffff);
+    }
+
+    #[test]
+    fn cobs_removes_zeros() {
+        let mut out = [0u8; 8];
+
+        let len = cobs_encode(&[0x11, 0x00, 0x22, 0x00], &mut out);
+        assert_eq!(&out[..len], &[0x02, 0x11, 0x02, 0x22, 0x01]);
+
+        let len = cobs_encode(&[], &mut out);
+        assert_eq!(&out[..len], &[0x01]);
+    }
+
+    #[test]
+    fn cobs_long_run_without_zeros() {
+        let bytes = [0x42u8; 300];
+        let mut out = [0u8; 310];
+
+        let len = cobs_encode(&bytes, &mut out);
+
+        assert_eq!(len, 300 + 2);
+        assert!(!out[..len].contains(&0));
+        assert_eq!(cobs_decode(&out[..len]), bytes);
+    }
+
+    #[test]
+    fn frame_roundtrip() {
+        let payload = [0x00, 0x01, 0x00, 0xfe];
+        let mut frame = [0u8; MAX_FRAME_LEN];
+
+        let len = encode_frame(FrameType::Health, 7, &payload, &mut frame).unwrap();
+
+        assert_eq!(frame[len - 1], 0);
+        assert!(!frame[..(len - 1)].contains(&0));
+
+        let raw = cobs_decode(&frame[..(len - 1)]);
+        assert_eq!(&raw[..2], &[FrameType::Health as u8, 7]);
+        assert_eq!(&raw[2..6], &payload);
+        assert_eq!(u16::from_le_bytes([raw[6], raw[7]]), crc16(&raw[..6]));
+    }
+
+    #[test]
+    fn frame_payload_too_long() {
+        let mut frame = [0u8; MAX_FRAME_LEN];
+
+        assert_eq!(encode_frame(FrameType::Log, 0, &[1; MAX_PAYLOAD_LEN + 1], &mut frame), Err(FrameError::PayloadTooLong));
+    }
+}
@@ -0,0 +1,138 @@
+/* minimal http/1.0 server side: request line parsing and response head (one request per connection) */
+
+use core::fmt;
+
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Head,
+    /// method not supported by the server (responded by 405)
+    Other,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RequestLine<'a> {
+    pub method: Method,
+    /// path without query string
+    pub path: &'a str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HttpError {
+    /// request line is not `<method> <target> HTTP/<version>` (or not utf8)
+    MalformedRequestLine,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    BadRequest,
+    NotFound,
+    MethodNotAllowed,
+    InternalServerError,
+}
+
+impl Status {
+    pub fn code(&self) -> u16 {
+        match self {
+            Status::Ok => 200,
+            Status::BadRequest => 400,
+            Status::NotFound => 404,
+            Status::MethodNotAllowed => 405,
+            Status::InternalServerError => 500,
+        }
+    }
+
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Status::Ok => "OK",
+            Status::BadRequest => "Bad Request",
+            Status::NotFound => "Not Found",
+            Status::MethodNotAllowed => "Method Not Allowed",
+            Status::InternalServerError => "Internal Server Error",
+        }
+    }
+}
+
+
+
+/// Length of request head (request line and headers) including the terminating empty line, `None` when it is not complete yet.
+pub fn head_len(bytes: &[u8]) -> Option<usize> {
+    bytes.windows(4).position(|window| window == b"\r\n\r\n").map(|position| position + 4)
+}
+
+/// Parses the first line of request `head` (headers are ignored).
+pub fn parse_request_line(head: &[u8]) -> Result<RequestLine<'_>, HttpError> {
+    let line_len = head.windows(2).position(|window| window == b"\r\n").unwrap_or(head.len());
+    let line = core::str::from_utf8(&head[..line_len]).map_err(|_| HttpError::MalformedRequestLine)?;
+
+    let mut parts = line.split(' ');
+    let (Some(method), Some(target), Some(version), None) = (parts.next(), parts.next(), parts.next(), parts.next()) else {
+        return Err(HttpError::MalformedRequestLine);
+    };
+
+    if !version.starts_with("HTTP/") || !target.starts_with('/') {
+        return Err(HttpError::MalformedRequestLine);
+    }
+
+    let method = match method {
+        "GET" => Method::Get,
+        "HEAD" => Method::Head,
+        _ => Method::Other,
+    };
+    let path = target.split_once('?').map_or(target, |(path, _)| path);
+
+    Ok(RequestLine { method, path })
+}
+
+/// Writes status line and headers of response with body of `content_len` bytes, connection is closed after the response.
+pub fn write_response_head(out: &mut impl fmt::Write, status: Status, content_type: &str, content_len: usize) -> fmt::Result {
+    write!(out, "HTTP/1.0 {} {}\r\n", status.code(), status.reason())?;
+    write!(out, "Content-Type: {}\r\n", content_type)?;
+    write!(out, "Content-Length: {}\r\n", content_len)?;
+    write!(out, "Connection: close\r\n\r\n")
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::string::String;
+
+    use super::*;
+
+
+    #[test]
+    fn head_is_complete_after_empty_line() {
+        let request = b"GET / HTTP/1.1\r\nHost: esp\r\n\r\nbody";
+
+        assert_eq!(head_len(&request[..20]), None);
+        assert_eq!(head_len(request), Some(request.len() - 4));
+    }
+
+    #[test]
+    fn request_line_is_parsed_without_query() {
+        assert_eq!(parse_request_line(b"GET /status?pretty=1 HTTP/1.1\r\nHost: esp\r\n\r\n"), Ok(RequestLine { method: Method::Get, path: "/status" }));
+        assert_eq!(parse_request_line(b"POST / HTTP/1.0\r\n\r\n"), Ok(RequestLine { method: Method::Other, path: "/" }));
+    }
+
+    #[test]
+    fn malformed_request_line_is_rejected() {
+        assert_eq!(parse_request_line(b"GET /\r\n\r\n"), Err(HttpError::MalformedRequestLine));
+        assert_eq!(parse_request_line(b"GET  / HTTP/1.0\r\n\r\n"), Err(HttpError::MalformedRequestLine));
+        assert_eq!(parse_request_line(b"GET / FTP/1.0\r\n\r\n"), Err(HttpError::MalformedRequestLine));
+        assert_eq!(parse_request_line(b"GET \xff HTTP/1.0\r\n\r\n"), Err(HttpError::MalformedRequestLine));
+    }
+
+    #[test]
+    fn response_head() {
+        let mut out = String::new();
+
+        write_response_head(&mut out, Status::NotFound, "application/json", 12).unwrap();
+
+        assert_eq!(out, "HTTP/1.0 404 Not Found\r\nContent-Type: application/json\r\nContent-Length: 12\r\nConnection: close\r\n\r\n");
+    }
+}
@@ -0,0 +1,149 @@
+/* dew point and absolute humidity from temperature and relative humidity (magnus formula in integer fixed point math) */
+
+
+
+/// fractional bits of internal fixed point values
+const FRAC_BITS: u32 = 24;
+const ONE: i64 = 1 << FRAC_BITS;
+/// ln(2) in fixed point
+const LN_2: i64 = 11_629_080;
+
+/// magnus coefficients over water (Sonntag 1990), `B` is dimensionless and `C` is in m°C
+const MAGNUS_B: i64 = 17_620;
+const MAGNUS_C: i64 = 243_120;
+
+/// saturation vapour pressure at 0 °C (6.112 hPa) multiplied by 2.1674 (100 / specific gas constant of water vapour
+/// in g K / J), in 10^-4 of g K / m³
+const ABSOLUTE_HUMIDITY_FACTOR: i64 = 132_471;
+/// 0 °C in m°C above absolute zero
+const ZERO_CELSIUS: i64 = 273_150;
+
+
+/// Natural logarithm of fixed point `x` (`x > 0`), binary logarithm is computed bit by bit by repeated squaring.
+fn ln(x: i64) -> i64 {
+    let exponent = (63 - x.leading_zeros()) as i64 - FRAC_BITS as i64;
+
+    // mantissa in [1, 2)
+    let mut mantissa = if exponent >= 0 { x >> exponent } else { x << -exponent };
+    let mut log2 = exponent << FRAC_BITS;
+
+    for bit in (0..FRAC_BITS).rev() {
+        mantissa = (mantissa * mantissa) >> FRAC_BITS;
+        if mantissa >= 2 * ONE {
+            mantissa >>= 1;
+            log2 |= 1 << bit;
+        }
+    }
+
+    ((log2 as i128 * LN_2 as i128) >> FRAC_BITS) as i64
+}
+
+/// Exponential of fixed point `x` (`|x| < 20`), `x = k ln(2) + r` where `exp(r)` is taylor series and `2^k` is shift.
+fn exp(x: i64) -> i64 {
+    let k = x.div_euclid(LN_2);
+    let r = x.rem_euclid(LN_2);
+
+    let mut term = ONE;
+    let mut sum = ONE;
+    for i in 1..=12 {
+        term = ((term * r) >> FRAC_BITS) / i;
+        sum += term;
+    }
+
+    if k >= 0 { sum << k } else { sum >> -k }
+}
+
+/// `b T / (c + T)` of magnus formula in fixed point (temperature in m°C, above -243 °C).
+fn magnus_ratio(temperature: i32) -> i64 {
+    let temperature = temperature as i64;
+    ((MAGNUS_B * temperature) << FRAC_BITS) / ((MAGNUS_C + temperature) * 1_000)
+}
+
+
+/// Dew point (in m°C) of air with `temperature` (in m°C) and relative `humidity` (in m%).
+///
+/// `None` when humidity is not positive (there is no dew point of dry air). Approximations of `ln` and `exp` add less
+/// than 0.02 °C within scd30 range (-40 - 70 °C).
+pub fn dew_point(temperature: i32, humidity: i32) -> Option<i32> {
+    if humidity <= 0 {
+        return None;
+    }
+
+    let gamma = ln((humidity as i64) * ONE / 100_000) + magnus_ratio(temperature);
+    let denominator = (MAGNUS_B * ONE) / 1_000 - gamma;
+
+    Some((MAGNUS_C * gamma / denominator) as i32)
+}
+
+/// Absolute humidity (in mg/m³, 10^-3 g/m³) of air with `temperature` (in m°C) and relative `humidity` (in m%).
+///
+/// Vapour pressure from magnus formula and ideal gas law, negative humidity is treated as zero.
+pub fn absolute_humidity(temperature: i32, humidity: i32) -> i32 {
+    let saturation = exp(magnus_ratio(temperature)) as i128;
+    let humidity = humidity.max(0) as i128;
+
+    // (6.112 hPa * e^x * rh / 100) / (R_w * T) in g/m³, humidity and temperature are in milli units, result is too
+    let value = ((saturation * humidity * ABSOLUTE_HUMIDITY_FACTOR as i128) >> FRAC_BITS) / (10 * (ZERO_CELSIUS + temperature as i64) as i128);
+
+    value as i32
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(value: i32, expected: i32, tolerance: i32) {
+        assert!((value - expected).abs() <= tolerance, "{} is not {} +- {}", value, expected, tolerance);
+    }
+
+    #[test]
+    fn ln_and_exp() {
+        assert_near(ln(ONE) as i32, 0, 16);
+        assert_near(ln(2 * ONE) as i32, LN_2 as i32, 16);
+        // ln(0.001)
+        assert_near(ln(ONE / 1_000) as i32, -115_892_902, 2_000);
+        assert_eq!(exp(0), ONE);
+        // e^4, e^-5.2
+        assert_near(exp(4 * ONE) as i32, 916_004_956, 2_000);
+        assert_near(exp(-52 * ONE / 10) as i32, 92_553, 16);
+    }
+
+    #[test]
+    fn dew_point_of_typical_air() {
+        assert_near(dew_point(20_000, 50_000).unwrap(), 9_255, 20);
+        assert_near(dew_point(0, 80_000).unwrap(), -3_040, 20);
+        assert_near(dew_point(-20_000, 30_000).unwrap(), -33_166, 20);
+        assert_near(dew_point(30_000, 5_000).unwrap(), -13_800, 20);
+    }
+
+    #[test]
+    fn dew_point_of_saturated_air_is_temperature() {
+        for temperature in [-40_000, -5_000, 0, 25_000, 70_000] {
+            assert_near(dew_point(temperature, 100_000).unwrap(), temperature, 5);
+        }
+    }
+
+    #[test]
+    fn dew_point_of_dry_air() {
+        assert_eq!(dew_point(20_000, 0), None);
+        assert_eq!(dew_point(20_000, -100), None);
+        assert!(dew_point(-40_000, 100).unwrap() < -85_000);
+    }
+
+    #[test]
+    fn absolute_humidity_of_typical_air() {
+        assert_near(absolute_humidity(20_000, 50_000), 8_623, 10);
+        assert_near(absolute_humidity(25_000, 100_000), 22_972, 20);
+        assert_near(absolute_humidity(0, 80_000), 3_880, 10);
+        assert_near(absolute_humidity(-20_000, 30_000), 324, 5);
+        assert_near(absolute_humidity(70_000, 100_000), 198_314, 200);
+    }
+
+    #[test]
+    fn absolute_humidity_of_dry_air() {
+        assert_eq!(absolute_humidity(20_000, 0), 0);
+        assert_eq!(absolute_humidity(20_000, -100), 0);
+    }
+}
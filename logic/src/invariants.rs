@@ -0,0 +1,59 @@
+/* checks of conditions which should always hold, violations are counted (used by the firmware and data structures of
+   this crate) */
+
+use core::{cell::Cell, sync::atomic::{AtomicU32, Ordering}};
+
+use critical_section::Mutex;
+
+
+
+/// Where and why was some invariant violated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvariantViolation {
+    pub message: &'static str,
+    pub file: &'static str,
+    pub line: u32,
+}
+
+
+static VIOLATION_COUNT: AtomicU32 = AtomicU32::new(0);
+static LAST_VIOLATION: Mutex<Cell<Option<InvariantViolation>>> = Mutex::new(Cell::new(None));
+
+
+
+/// Checks condition which should always hold (conditions marked as "cannot happen").
+///
+/// When the condition is false, violation is recorded (counter + last violation location, see `violation_count` and `last_violation`)
+/// and in debug builds `debug_assert` panics, so the problem is found early during development.
+/// In release builds execution continues, so caller should handle the violation (macro returns value of the condition).
+#[macro_export]
+macro_rules! invariant {
+    ($cond:expr, $msg:literal) => {{
+        let holds: bool = $cond;
+        if !holds {
+            $crate::invariants::record_violation($msg, file!(), line!());
+            debug_assert!(false, concat!("invariant violated: ", $msg));
+        }
+        holds
+    }};
+}
+
+pub use crate::invariant;
+
+
+/// Use `invariant!` macro instead of calling this directly.
+pub fn record_violation(message: &'static str, file: &'static str, line: u32) {
+    VIOLATION_COUNT.fetch_add(1, Ordering::Relaxed);
+
+    critical_section::with(|cs| {
+        LAST_VIOLATION.borrow(cs).set(Some(InvariantViolation { message, file, line }));
+    });
+}
+
+pub fn violation_count() -> u32 {
+    VIOLATION_COUNT.load(Ordering::Relaxed)
+}
+
+pub fn last_violation() -> Option<InvariantViolation> {
+    critical_section::with(|cs| LAST_VIOLATION.borrow(cs).get())
+}
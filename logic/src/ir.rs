@@ -0,0 +1,173 @@
+/* ir protocol decoders, all decoders accept pulse lengths (starting with mark, alternating mark / space, ending with last mark) */
+
+use self::{
+    nec::{NecDecodeError, NecDecoder, NecIrTimingConfig, NecMessage},
+    rc5::{Rc5DecodeError, Rc5Decoder, Rc5IrTimingConfig, Rc5Message},
+    rc6::{Rc6DecodeError, Rc6Decoder, Rc6IrTimingConfig, Rc6Message},
+    sirc::{SircDecodeError, SircDecoder, SircIrTimingConfig, SircMessage}
+};
+
+
+
+pub mod event;
+pub mod nec;
+pub mod rc5;
+pub mod rc6;
+pub mod sirc;
+
+
+
+fn in_range(value: u16, min: u16, max: u16) -> bool {
+    min <= value && value <= max
+}
+
+
+
+/// Discriminant is the protocol byte of encoded message (see `IrMessage::encode`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum IrProtocol {
+    /// frame which none of the decoders understood (see `event::IrEventTracker::on_raw`), never encoded
+    Raw = 0,
+    Nec = 1,
+    Sirc = 2,
+    Rc5 = 3,
+    Rc6 = 4,
+}
+
+impl IrProtocol {
+    pub fn name(&self) -> &'static str {
+        match self {
+            IrProtocol::Raw => "raw",
+            IrProtocol::Nec => "nec",
+            IrProtocol::Sirc => "sony sirc",
+            IrProtocol::Rc5 => "rc5",
+            IrProtocol::Rc6 => "rc6",
+        }
+    }
+
+    pub fn from_u8(value: u8) -> Option<IrProtocol> {
+        [IrProtocol::Raw, IrProtocol::Nec, IrProtocol::Sirc, IrProtocol::Rc5, IrProtocol::Rc6].into_iter().find(|protocol| *protocol as u8 == value)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum IrMessage {
+    Nec(NecMessage),
+    Sirc(SircMessage),
+    Rc5(Rc5Message),
+    Rc6(Rc6Message),
+}
+
+impl IrMessage {
+    pub const ENCODED_LEN: usize = 5;
+
+
+    pub fn protocol(&self) -> IrProtocol {
+        match self {
+            IrMessage::Nec(_) => IrProtocol::Nec,
+            IrMessage::Sirc(_) => IrProtocol::Sirc,
+            IrMessage::Rc5(_) => IrProtocol::Rc5,
+            IrMessage::Rc6(_) => IrProtocol::Rc6,
+        }
+    }
+
+    /// Protocol (u8), flags (u8), address (u8), command (u8), extended (u8).
+    ///
+    /// Flags are protocol specific: nec repeat (bit 0), sirc number of bits, rc5 and rc6 toggle (bit 0).
+    /// Nec message is in command, extended is used only by sirc (0 otherwise).
+    pub fn encode(&self) -> [u8; Self::ENCODED_LEN] {
+        let (flags, address, command, extended) = match *self {
+            IrMessage::Nec(NecMessage::Message { address, message }) => (0, address, message, 0),
+            IrMessage::Nec(NecMessage::Repeat) => (1, 0, 0, 0),
+            IrMessage::Sirc(message) => (message.bits, message.address, message.command, message.extended),
+            IrMessage::Rc5(message) => (message.toggle as u8, message.address, message.command, 0),
+            IrMessage::Rc6(message) => (message.toggle as u8, message.address, message.command, 0),
+        };
+
+        [self.protocol() as u8, flags, address, command, extended]
+    }
+}
+
+
+/// None of the decoders matched, contains error of each decoder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IrDecodeError {
+    pub nec: NecDecodeError,
+    pub sirc: SircDecodeError,
+    pub rc5: Rc5DecodeError,
+    pub rc6: Rc6DecodeError,
+}
+
+/// Timing of all supported protocols, lengths are in rmt ticks.
+#[derive(Debug, Clone, Copy)]
+pub struct IrTimingConfig {
+    pub nec: NecIrTimingConfig,
+    pub sirc: SircIrTimingConfig,
+    pub rc5: Rc5IrTimingConfig,
+    pub rc6: Rc6IrTimingConfig,
+}
+
+/// Tries NEC, Sony SIRC, RC6 and RC5 decoders in turn on the same pulse sequence, first successful decoder wins.
+///
+/// Protocol is effectively selected by the leading pulses, each decoder rejects a frame at its first pulses (nec 9 ms
+/// mark, sirc 2.4 ms mark, rc6 2.7 ms mark followed by 0.9 ms space, rc5 starts with 0.9 or 1.8 ms mark).
+pub struct IrDispatchDecoder {
+    nec: NecDecoder,
+    sirc: SircDecoder,
+    rc5: Rc5Decoder,
+    rc6: Rc6Decoder,
+}
+
+impl IrDispatchDecoder {
+    pub fn new(config: IrTimingConfig) -> Self {
+        Self {
+            nec: NecDecoder::new(config.nec),
+            sirc: SircDecoder::new(config.sirc),
+            rc5: Rc5Decoder::new(config.rc5),
+            rc6: Rc6Decoder::new(config.rc6),
+        }
+    }
+
+    /// Tries all decoders on `pulses` (each decoder gets its own copy of the iterator).
+    pub fn decode(&self, pulses: impl Iterator<Item = u16> + Clone) -> Result<IrMessage, IrDecodeError> {
+        let nec = match self.nec.decode(pulses.clone()) {
+            Ok(message) => return Ok(IrMessage::Nec(message)),
+            Err(err) => err,
+        };
+
+        let sirc = match self.sirc.decode(pulses.clone()) {
+            Ok(message) => return Ok(IrMessage::Sirc(message)),
+            Err(err) => err,
+        };
+
+        let rc6 = match self.rc6.decode(pulses.clone()) {
+            Ok(message) => return Ok(IrMessage::Rc6(message)),
+            Err(err) => err,
+        };
+
+        let rc5 = match self.rc5.decode(pulses) {
+            Ok(message) => return Ok(IrMessage::Rc5(message)),
+            Err(err) => err,
+        };
+
+        Err(IrDecodeError { nec, sirc, rc5, rc6 })
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn encode_message() {
+        assert_eq!(IrMessage::Nec(NecMessage::Message { address: 0x04, message: 0x09 }).encode(), [1, 0, 0x04, 0x09, 0]);
+        assert_eq!(IrMessage::Nec(NecMessage::Repeat).encode(), [1, 1, 0, 0, 0]);
+        assert_eq!(IrMessage::Sirc(SircMessage { bits: 20, command: 0x12, address: 0x01, extended: 0x5a }).encode(), [2, 20, 0x01, 0x12, 0x5a]);
+        assert_eq!(IrMessage::Rc5(Rc5Message { toggle: true, address: 0x00, command: 0x0c }).encode(), [3, 1, 0x00, 0x0c, 0]);
+        assert_eq!(IrMessage::Rc6(Rc6Message { toggle: false, address: 0x04, command: 0x10 }).encode(), [4, 0, 0x04, 0x10, 0]);
+    }
+}
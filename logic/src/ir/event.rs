@@ -0,0 +1,177 @@
+use super::{nec::{NecKeyEvent, NecKeyTracker}, IrMessage, IrProtocol};
+
+
+
+/// Key press (or repeat while key is held) of any remote, so consumers do not depend on the protocol.
+///
+/// Produced by `IrEventTracker` from decoded messages and from frames none of the decoders understood
+/// (`IrProtocol::Raw`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IrEvent {
+    pub protocol: IrProtocol,
+    /// raw frames - number of pulses (saturated)
+    pub address: u8,
+    /// raw frames - hash of pulse lengths (see `raw_hash`)
+    pub command: u16,
+    /// key is held, remote sent the same code again (or repeat code)
+    pub repeat: bool,
+    /// time of the frame, in ticks given by the caller
+    pub at: u64,
+}
+
+/// When a frame is a repeat of the previous one, in ticks given to `IrEventTracker`.
+#[derive(Debug, Clone, Copy)]
+pub struct IrRepeatConfig {
+    /// held nec key is released when no repeat code comes in this time
+    pub nec_timeout: u64,
+    /// sirc frame same as the previous one is a new press only after this gap
+    pub sirc_gap: u64,
+    /// rc5 / rc6 frame with unchanged toggle bit is a new press only after this gap, used for raw frames too
+    pub rc_gap: u64,
+}
+
+
+/// Hash of raw frame of unknown protocol, it does not depend on reciever jitter or timing unit.
+///
+/// Each pulse is compared with the next pulse of the same kind (mark or space) as shorter, similar (within 25%) or
+/// longer, only results of comparisons are hashed.
+pub fn raw_hash(pulses: impl Iterator<Item = u16> + Clone) -> u16 {
+    const FNV_OFFSET: u32 = 0x811c_9dc5;
+    const FNV_PRIME: u32 = 0x0100_0193;
+
+    let hash = pulses.clone().zip(pulses.skip(2)).fold(FNV_OFFSET, |hash, (pulse, next)| {
+        let (pulse, next) = (pulse as u32, next as u32);
+        let comparison = if next * 5 < pulse * 4 { 0 } else if next * 4 > pulse * 5 { 2 } else { 1 };
+
+        (hash ^ comparison).wrapping_mul(FNV_PRIME)
+    });
+
+    (hash ^ (hash >> 16)) as u16
+}
+
+
+/// Turns messages of all decoders into `IrEvent`s, deciding per protocol whether a frame is a repeat.
+///
+/// Nec repeat codes belong to the held key (see `NecKeyTracker`, `on_timeout` has to be called at `deadline`), sirc
+/// and raw frames are repeated whole while key is held, rc5 / rc6 frames keep toggle bit while key is held.
+pub struct IrEventTracker {
+    config: IrRepeatConfig,
+    nec_keys: NecKeyTracker,
+    /// protocol, address, command, toggle (rc5 / rc6 only) and time of the last frame of other protocol than nec
+    last: Option<(IrProtocol, u8, u16, bool, u64)>,
+}
+
+impl IrEventTracker {
+    pub fn new(config: IrRepeatConfig) -> Self {
+        Self {
+            config,
+            nec_keys: NecKeyTracker::new(config.nec_timeout),
+            last: None,
+        }
+    }
+
+    /// Time when held nec key is released unless repeat comes.
+    pub fn deadline(&self) -> Option<u64> {
+        self.nec_keys.deadline()
+    }
+
+    /// Releases held nec key when deadline passed, returns the released key event.
+    pub fn on_timeout(&mut self, now: u64) -> Option<NecKeyEvent> {
+        self.nec_keys.on_timeout(now)
+    }
+
+    fn on_frame(&mut self, protocol: IrProtocol, address: u8, command: u16, toggle: bool, now: u64) -> IrEvent {
+        let gap = match protocol {
+            IrProtocol::Sirc => self.config.sirc_gap,
+            _ => self.config.rc_gap,
+        };
+        let repeat = self.last.is_some_and(|(last_protocol, last_address, last_command, last_toggle, at)| {
+            (last_protocol, last_address, last_command, last_toggle) == (protocol, address, command, toggle) && now - at < gap
+        });
+
+        self.last = Some((protocol, address, command, toggle, now));
+
+        IrEvent { protocol, address, command, repeat, at: now }
+    }
+
+    /// `None` for nec repeat code without held key.
+    pub fn on_message(&mut self, message: IrMessage, now: u64) -> Option<IrEvent> {
+        match message {
+            // released key (by timeout or by different key) is not an event
+            IrMessage::Nec(message) => self.nec_keys.on_message(message, now).find_map(|event| match event {
+                NecKeyEvent::Pressed { address, message } => Some(IrEvent { protocol: IrProtocol::Nec, address, command: message as u16, repeat: false, at: now }),
+                NecKeyEvent::Held { address, message, .. } => Some(IrEvent { protocol: IrProtocol::Nec, address, command: message as u16, repeat: true, at: now }),
+                NecKeyEvent::Released { .. } => None,
+            }),
+            IrMessage::Sirc(message) => Some(self.on_frame(IrProtocol::Sirc, message.address, message.command as u16, false, now)),
+            IrMessage::Rc5(message) => Some(self.on_frame(IrProtocol::Rc5, message.address, message.command as u16, message.toggle, now)),
+            IrMessage::Rc6(message) => Some(self.on_frame(IrProtocol::Rc6, message.address, message.command as u16, message.toggle, now)),
+        }
+    }
+
+    /// Frame which none of the decoders understood, identified by number of pulses and `raw_hash`.
+    pub fn on_raw(&mut self, pulses: impl ExactSizeIterator<Item = u16> + Clone, now: u64) -> IrEvent {
+        let address = pulses.len().min(u8::MAX as usize) as u8;
+
+        self.on_frame(IrProtocol::Raw, address, raw_hash(pulses), false, now)
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{nec::NecMessage, rc5::Rc5Message, sirc::SircMessage};
+
+
+    const CONFIG: IrRepeatConfig = IrRepeatConfig { nec_timeout: 150, sirc_gap: 100, rc_gap: 250 };
+
+    fn event(protocol: IrProtocol, address: u8, command: u16, repeat: bool, at: u64) -> Option<IrEvent> {
+        Some(IrEvent { protocol, address, command, repeat, at })
+    }
+
+
+    #[test]
+    fn nec_repeat_codes_are_repeats_of_held_key() {
+        let mut tracker = IrEventTracker::new(CONFIG);
+
+        assert_eq!(tracker.on_message(IrMessage::Nec(NecMessage::Repeat), 0), None);
+        assert_eq!(tracker.on_message(IrMessage::Nec(NecMessage::Message { address: 0, message: 0x45 }), 10), event(IrProtocol::Nec, 0, 0x45, false, 10));
+        assert_eq!(tracker.on_message(IrMessage::Nec(NecMessage::Repeat), 118), event(IrProtocol::Nec, 0, 0x45, true, 118));
+        // different key releases the held one
+        assert_eq!(tracker.on_message(IrMessage::Nec(NecMessage::Message { address: 0, message: 0x47 }), 200), event(IrProtocol::Nec, 0, 0x47, false, 200));
+        assert_eq!(tracker.on_message(IrMessage::Nec(NecMessage::Repeat), 500), None);
+    }
+
+    #[test]
+    fn repeated_frames_within_gap_are_repeats() {
+        let mut tracker = IrEventTracker::new(CONFIG);
+        let sirc = IrMessage::Sirc(SircMessage { bits: 12, command: 0x15, address: 0x01, extended: 0 });
+
+        assert_eq!(tracker.on_message(sirc, 0), event(IrProtocol::Sirc, 0x01, 0x15, false, 0));
+        assert_eq!(tracker.on_message(sirc, 45), event(IrProtocol::Sirc, 0x01, 0x15, true, 45));
+        assert_eq!(tracker.on_message(sirc, 200), event(IrProtocol::Sirc, 0x01, 0x15, false, 200));
+
+        let rc5 = |toggle| IrMessage::Rc5(Rc5Message { toggle, address: 0x00, command: 0x0c });
+
+        assert_eq!(tracker.on_message(rc5(false), 300), event(IrProtocol::Rc5, 0x00, 0x0c, false, 300));
+        assert_eq!(tracker.on_message(rc5(false), 414), event(IrProtocol::Rc5, 0x00, 0x0c, true, 414));
+        // new press flips toggle bit
+        assert_eq!(tracker.on_message(rc5(true), 528), event(IrProtocol::Rc5, 0x00, 0x0c, false, 528));
+    }
+
+    #[test]
+    fn raw_frames_are_identified_by_hash() {
+        let mut tracker = IrEventTracker::new(CONFIG);
+        let pulses = [3000, 1500, 400, 400, 400, 1200, 400, 400, 400];
+        // same shape with reciever jitter
+        let jittered = [3100, 1450, 420, 380, 390, 1250, 410, 420, 400];
+        let other = [3000, 1500, 400, 1200, 400, 400, 400, 400, 400];
+
+        let first = tracker.on_raw(pulses.into_iter(), 0);
+        assert_eq!(first, IrEvent { protocol: IrProtocol::Raw, address: 9, command: raw_hash(pulses.into_iter()), repeat: false, at: 0 });
+        assert_eq!(tracker.on_raw(jittered.into_iter(), 100), IrEvent { repeat: true, at: 100, ..first });
+        assert_ne!(raw_hash(other.into_iter()), first.command);
+    }
+}
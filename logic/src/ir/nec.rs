@@ -0,0 +1,383 @@
+use core::iter;
+
+use super::in_range;
+
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NecDecodeError {
+    InvalidPulseCountTooShort,
+    InvalidPulseCountTooLong,
+    Start1InvalidLength,
+    Start0InvalidLength,
+    Data1InvalidLength(u16),
+    Data0InvalidLength,
+    Last1InvalidLength,
+    AddressInvertedNotMatching,
+    MessageInvertedNotMatching,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum NecMessage {
+    Message {
+        address: u8,
+        message: u8,
+    },
+    Repeat,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct NecIrTimingConfig {
+    pub short: u16, // duration of shortest nec pulse (560 us),
+    pub tol_div: u16,
+    pub tol_num: u16,
+}
+
+pub struct NecDecoder {
+    short_min: u16,
+    short_max: u16,
+    long_min: u16,
+    long_max: u16,
+    start_1_min: u16,
+    start_1_max: u16,
+    start_0_min: u16,
+    start_0_max: u16,
+    repeat_min: u16,
+    repeat_max: u16,
+}
+
+impl NecDecoder {
+    const LONG_MUL: u16 = 3;
+    const START_1_MUL: u16 = 16;
+    const START_0_MUL: u16 = 8;
+    const REPEAT_MUL: u16 = 4;
+
+    const MS_1: u8 = 0b1000_0000;
+
+
+    pub fn new(config: NecIrTimingConfig) -> Self {
+        Self {
+            short_min:   config.short *                     (config.tol_div - config.tol_num) / config.tol_div,
+            short_max:   config.short *                     (config.tol_div + config.tol_num) / config.tol_div,
+            long_min:    config.short * Self::LONG_MUL    * (config.tol_div - config.tol_num) / config.tol_div,
+            long_max:    config.short * Self::LONG_MUL    * (config.tol_div + config.tol_num) / config.tol_div,
+            start_1_min: config.short * Self::START_1_MUL * (config.tol_div - config.tol_num) / config.tol_div,
+            start_1_max: config.short * Self::START_1_MUL * (config.tol_div + config.tol_num) / config.tol_div,
+            start_0_min: config.short * Self::START_0_MUL * (config.tol_div - config.tol_num) / config.tol_div,
+            start_0_max: config.short * Self::START_0_MUL * (config.tol_div + config.tol_num) / config.tol_div,
+            repeat_min:  config.short * Self::REPEAT_MUL  * (config.tol_div - config.tol_num) / config.tol_div,
+            repeat_max:  config.short * Self::REPEAT_MUL  * (config.tol_div + config.tol_num) / config.tol_div,
+        }
+    }
+
+    fn decode_u8(&self, pulses: impl Iterator<Item = u16>) -> Result<u8, NecDecodeError> {
+        let (n, counter) = pulses.take(16).array_chunks::<2>().try_fold((0u8, 0usize), |(n, counter), [pulse1, pulse0]| {
+            if !in_range(pulse1, self.short_min, self.short_max) {
+                return Err(NecDecodeError::Data1InvalidLength(pulse1));
+            }
+
+            if in_range(pulse0, self.short_min, self.short_max) {
+                Ok((n >> 1, counter + 1))
+            } else if in_range(pulse0, self.long_min, self.long_max) {
+                Ok(((n >> 1) | Self::MS_1, counter + 1))
+            } else {
+                Err(NecDecodeError::Data0InvalidLength)
+            }
+        })?;
+
+        if counter != 8 {
+            Err(NecDecodeError::InvalidPulseCountTooShort)
+        } else {
+            Ok(n)
+        }
+    }
+
+    pub fn decode(&self, mut pulses: impl Iterator<Item = u16>) -> Result<NecMessage, NecDecodeError> {
+        let start1 = pulses.next().ok_or(NecDecodeError::InvalidPulseCountTooShort)?;
+
+        if !in_range(start1, self.start_1_min, self.start_1_max) {
+            return Err(NecDecodeError::Start1InvalidLength);
+        }
+
+        let start0 = pulses.next().ok_or(NecDecodeError::InvalidPulseCountTooShort)?;
+
+        if in_range(start0, self.repeat_min, self.repeat_max) {
+            return Ok(NecMessage::Repeat);
+        } else if !in_range(start0, self.start_0_min, self.start_0_max) {
+            return Err(NecDecodeError::Start0InvalidLength);
+        }
+
+        let address = self.decode_u8(pulses.by_ref())?;
+        let address_inverted = self.decode_u8(pulses.by_ref())?;
+
+        if address ^ address_inverted != 0b1111_1111 {
+            return Err(NecDecodeError::AddressInvertedNotMatching);
+        }
+
+        let message = self.decode_u8(pulses.by_ref())?;
+        let message_inverted = self.decode_u8(pulses.by_ref())?;
+
+        if message ^ message_inverted != 0b1111_1111 {
+            return Err(NecDecodeError::MessageInvertedNotMatching);
+        }
+
+        let last = pulses.next().ok_or(NecDecodeError::InvalidPulseCountTooShort)?;
+
+        if !in_range(last, self.short_min, self.short_max) {
+            return Err(NecDecodeError::Last1InvalidLength);
+        }
+
+        if pulses.next().is_some() {
+            return Err(NecDecodeError::InvalidPulseCountTooLong);
+        }
+
+        Ok(NecMessage::Message {
+            address,
+            message,
+        })
+    }
+}
+
+
+
+/// Pulses of message frame as `(mark, space)` pairs in units (1 unit = 562.5 us), bytes and bits are sent lsb first.
+///
+/// Space after the last mark is `0` (rmt end marker), frame has 34 pulses.
+pub fn pulses(address: u8, message: u8) -> impl Iterator<Item = (u16, u16)> {
+    let data = u32::from_le_bytes([address, !address, message, !message]);
+
+    iter::once((NecDecoder::START_1_MUL, NecDecoder::START_0_MUL))
+        .chain((0..32).map(move |bit| (1, if (data >> bit) & 0b1 == 1 { NecDecoder::LONG_MUL } else { 1 })))
+        .chain(iter::once((1, 0)))
+}
+
+/// Pulses of repeat frame (sent every 108 ms while key is held), same units as `pulses`.
+pub fn repeat_pulses() -> impl Iterator<Item = (u16, u16)> {
+    [(NecDecoder::START_1_MUL, NecDecoder::REPEAT_MUL), (1, 0)].into_iter()
+}
+
+
+/// Key (nec code) event derived from messages and repeat codes (see `NecKeyTracker`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NecKeyEvent {
+    Pressed {
+        address: u8,
+        message: u8,
+    },
+    /// key is still held, `repeats` counts repeat codes (or repeated messages) since press
+    Held {
+        address: u8,
+        message: u8,
+        repeats: u16,
+    },
+    Released {
+        address: u8,
+        message: u8,
+    },
+}
+
+/// Tracks held key, remote sends repeat code (without key) every ~108 ms while key is held.
+///
+/// Repeat belongs to the last message when it comes before `repeat_timeout` from the last message (or repeat),
+/// otherwise the key is released (`on_timeout` has to be called at `deadline`). Time is in arbitrary ticks given by the caller.
+pub struct NecKeyTracker {
+    repeat_timeout: u64,
+    /// address, message
+    key: Option<(u8, u8)>,
+    repeats: u16,
+    last_at: u64,
+}
+
+impl NecKeyTracker {
+    pub fn new(repeat_timeout: u64) -> Self {
+        Self {
+            repeat_timeout,
+            key: None,
+            repeats: 0,
+            last_at: 0,
+        }
+    }
+
+    /// Time when held key is released unless repeat comes, `None` when no key is held.
+    pub fn deadline(&self) -> Option<u64> {
+        self.key.map(|_| self.last_at + self.repeat_timeout)
+    }
+
+    fn release(&mut self) -> Option<NecKeyEvent> {
+        self.key.take().map(|(address, message)| NecKeyEvent::Released { address, message })
+    }
+
+    /// Held key is released when deadline passed.
+    pub fn on_timeout(&mut self, now: u64) -> Option<NecKeyEvent> {
+        if self.deadline().is_some_and(|deadline| now >= deadline) {
+            self.release()
+        } else {
+            None
+        }
+    }
+
+    /// Returns up to two events, different key (or the same key after timeout) releases previous key first.
+    pub fn on_message(&mut self, message: NecMessage, now: u64) -> impl Iterator<Item = NecKeyEvent> {
+        let released = self.on_timeout(now);
+
+        let event = match (message, self.key) {
+            (NecMessage::Repeat, None) => None,
+            (NecMessage::Repeat, Some((address, message))) => {
+                self.repeats = self.repeats.saturating_add(1);
+                Some(NecKeyEvent::Held { address, message, repeats: self.repeats })
+            },
+            // some remotes send whole message again instead of repeat code
+            (NecMessage::Message { address, message }, Some(key)) if key == (address, message) => {
+                self.repeats = self.repeats.saturating_add(1);
+                Some(NecKeyEvent::Held { address, message, repeats: self.repeats })
+            },
+            (NecMessage::Message { address, message }, _) => {
+                // key was released by timeout or it is released by different key
+                let released = released.or_else(|| self.release());
+                self.key = Some((address, message));
+                self.repeats = 0;
+                self.last_at = now;
+                return released.into_iter().chain(Some(NecKeyEvent::Pressed { address, message }));
+            },
+        };
+
+        if event.is_some() {
+            self.last_at = now;
+        }
+
+        released.into_iter().chain(event)
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+
+    const SHORT: u16 = 20;
+
+    fn decoder() -> NecDecoder {
+        NecDecoder::new(NecIrTimingConfig { short: SHORT, tol_div: 2, tol_num: 1 })
+    }
+
+    fn encode(address: u8, message: u8) -> Vec<u16> {
+        let mut pulses = std::vec![SHORT * 16, SHORT * 8];
+
+        for byte in [address, !address, message, !message] {
+            for bit in 0..8 {
+                pulses.push(SHORT);
+                pulses.push(if (byte >> bit) & 1 == 1 { SHORT * 3 } else { SHORT });
+            }
+        }
+
+        pulses.push(SHORT);
+        pulses
+    }
+
+
+    #[test]
+    fn decodes_message() {
+        let message = decoder().decode(encode(0x12, 0xa5).into_iter());
+
+        assert!(matches!(message, Ok(NecMessage::Message { address: 0x12, message: 0xa5 })));
+    }
+
+    #[test]
+    fn decodes_distorted_frame() {
+        // address 0x00, message 0x45, in 28 us ticks of ir reciever, marks are stretched and spaces shortened
+        // by demodulator as by real receivers
+        let pulses = [
+            324, 158,
+            24, 19, 21, 19, 23, 19, 22, 19, 21, 16, 24, 19, 22, 19, 24, 19,
+            21, 58, 21, 56, 21, 58, 21, 58, 23, 56, 22, 59, 23, 58, 21, 58,
+            23, 59, 21, 19, 22, 56, 24, 17, 24, 16, 23, 17, 22, 58, 22, 19,
+            23, 16, 23, 56, 23, 19, 21, 56, 22, 57, 22, 56, 24, 19, 21, 57,
+            23,
+        ];
+
+        let message = decoder().decode(pulses.into_iter());
+
+        assert!(matches!(message, Ok(NecMessage::Message { address: 0x00, message: 0x45 })));
+    }
+
+    #[test]
+    fn decodes_repeat() {
+        let message = decoder().decode([SHORT * 16, SHORT * 4, SHORT].into_iter());
+
+        assert!(matches!(message, Ok(NecMessage::Repeat)));
+    }
+
+    #[test]
+    fn rejects_corrupted_inverted_byte() {
+        let mut pulses = encode(0x12, 0xa5);
+        // first bit of inverted address
+        pulses[2 + 16 + 1] = if pulses[2 + 16 + 1] == SHORT { SHORT * 3 } else { SHORT };
+
+        assert!(matches!(decoder().decode(pulses.into_iter()), Err(NecDecodeError::AddressInvertedNotMatching)));
+    }
+
+    #[test]
+    fn rejects_truncated_and_extra_pulses() {
+        let mut pulses = encode(0x12, 0xa5);
+        pulses.pop();
+        assert!(matches!(decoder().decode(pulses.into_iter()), Err(NecDecodeError::InvalidPulseCountTooShort)));
+
+        let mut pulses = encode(0x12, 0xa5);
+        pulses.push(SHORT);
+        assert!(matches!(decoder().decode(pulses.into_iter()), Err(NecDecodeError::InvalidPulseCountTooLong)));
+    }
+
+    fn flatten(pulses: impl Iterator<Item = (u16, u16)>) -> Vec<u16> {
+        pulses
+            .flat_map(|(mark, space)| [mark * SHORT, space * SHORT])
+            .filter(|pulse| *pulse != 0)
+            .collect()
+    }
+
+    #[test]
+    fn encoded_frames_are_decoded() {
+        assert_eq!(pulses(0x12, 0xa5).count(), 34);
+        assert_eq!(flatten(pulses(0x12, 0xa5)), encode(0x12, 0xa5));
+
+        assert!(matches!(decoder().decode(flatten(pulses(0x12, 0xa5)).into_iter()), Ok(NecMessage::Message { address: 0x12, message: 0xa5 })));
+        assert!(matches!(decoder().decode(flatten(repeat_pulses()).into_iter()), Ok(NecMessage::Repeat)));
+    }
+
+    #[test]
+    fn key_tracker_press_hold_release() {
+        let mut tracker = NecKeyTracker::new(150);
+        let message = NecMessage::Message { address: 0x00, message: 0x45 };
+
+        assert_eq!(tracker.on_message(message, 0).collect::<Vec<_>>(), [NecKeyEvent::Pressed { address: 0x00, message: 0x45 }]);
+        assert_eq!(tracker.on_message(NecMessage::Repeat, 108).collect::<Vec<_>>(), [NecKeyEvent::Held { address: 0x00, message: 0x45, repeats: 1 }]);
+        assert_eq!(tracker.on_message(NecMessage::Repeat, 216).collect::<Vec<_>>(), [NecKeyEvent::Held { address: 0x00, message: 0x45, repeats: 2 }]);
+        assert_eq!(tracker.deadline(), Some(366));
+        assert_eq!(tracker.on_timeout(300), None);
+        assert_eq!(tracker.on_timeout(366), Some(NecKeyEvent::Released { address: 0x00, message: 0x45 }));
+        assert_eq!(tracker.deadline(), None);
+    }
+
+    #[test]
+    fn key_tracker_late_repeat_and_other_key() {
+        let mut tracker = NecKeyTracker::new(150);
+
+        let _ = tracker.on_message(NecMessage::Message { address: 0x00, message: 0x45 }, 0).count();
+        // repeat after timeout (`on_timeout` was not called) releases key and is ignored
+        assert_eq!(tracker.on_message(NecMessage::Repeat, 200).collect::<Vec<_>>(), [NecKeyEvent::Released { address: 0x00, message: 0x45 }]);
+        assert_eq!(tracker.on_message(NecMessage::Repeat, 300).count(), 0);
+
+        let _ = tracker.on_message(NecMessage::Message { address: 0x00, message: 0x45 }, 400).count();
+        assert_eq!(
+            tracker.on_message(NecMessage::Message { address: 0x00, message: 0x46 }, 450).collect::<Vec<_>>(),
+            [NecKeyEvent::Released { address: 0x00, message: 0x45 }, NecKeyEvent::Pressed { address: 0x00, message: 0x46 }],
+        );
+        assert_eq!(
+            tracker.on_message(NecMessage::Message { address: 0x00, message: 0x47 }, 1000).collect::<Vec<_>>(),
+            [NecKeyEvent::Released { address: 0x00, message: 0x46 }, NecKeyEvent::Pressed { address: 0x00, message: 0x47 }],
+        );
+    }
+}
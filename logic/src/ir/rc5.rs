@@ -0,0 +1,168 @@
+use super::in_range;
+
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rc5DecodeError {
+    InvalidPulseCountTooShort,
+    InvalidPulseCountTooLong,
+    PulseInvalidLength(u16),
+    /// both halves of a bit have same level
+    InvalidManchester,
+    Start1Invalid,
+}
+
+/// Philips RC5 message (with RC5X extension - second start bit is inverted 7th bit of `command`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rc5Message {
+    pub toggle: bool,
+    pub address: u8,
+    pub command: u8,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Rc5IrTimingConfig {
+    pub half: u16, // duration of half of a bit (889 us)
+    pub tol_div: u16,
+    pub tol_num: u16,
+}
+
+/// Decodes pulse lengths (starting with mark, alternating mark / space, ending with last mark) into `Rc5Message`.
+///
+/// Bits are manchester encoded (msb first), 1 is space + mark, 0 is mark + space, so pulses are one or two halves long.
+/// First half (space) of the first start bit and last half (space) of the last bit are not part of the recieved pulses.
+pub struct Rc5Decoder {
+    half_min: u16,
+    half_max: u16,
+    full_min: u16,
+    full_max: u16,
+}
+
+impl Rc5Decoder {
+    const FULL_MUL: u16 = 2;
+
+    const BITS: u8 = 14;
+
+
+    pub fn new(config: Rc5IrTimingConfig) -> Self {
+        Self {
+            half_min: config.half *                  (config.tol_div - config.tol_num) / config.tol_div,
+            half_max: config.half *                  (config.tol_div + config.tol_num) / config.tol_div,
+            full_min: config.half * Self::FULL_MUL * (config.tol_div - config.tol_num) / config.tol_div,
+            full_max: config.half * Self::FULL_MUL * (config.tol_div + config.tol_num) / config.tol_div,
+        }
+    }
+
+    pub fn decode(&self, pulses: impl Iterator<Item = u16>) -> Result<Rc5Message, Rc5DecodeError> {
+        let mut data = 0u16;
+        let mut bits = 0u8;
+        // level of the first half of currently decoded bit (`true` is mark), first start bit starts with space
+        let mut first_half = Some(false);
+
+        for (i, pulse) in pulses.enumerate() {
+            let level = i % 2 == 0;
+
+            let halves = if in_range(pulse, self.half_min, self.half_max) {
+                1
+            } else if in_range(pulse, self.full_min, self.full_max) {
+                2
+            } else {
+                return Err(Rc5DecodeError::PulseInvalidLength(pulse));
+            };
+
+            for _ in 0..halves {
+                match first_half.take() {
+                    None => first_half = Some(level),
+                    Some(first_level) => {
+                        if first_level == level {
+                            return Err(Rc5DecodeError::InvalidManchester);
+                        }
+
+                        if bits == Self::BITS {
+                            return Err(Rc5DecodeError::InvalidPulseCountTooLong);
+                        }
+
+                        data = (data << 1) | (level as u16);
+                        bits += 1;
+                    },
+                }
+            }
+        }
+
+        // last space is not recieved
+        if first_half == Some(true) {
+            data <<= 1;
+            bits += 1;
+        }
+
+        if bits != Self::BITS {
+            return Err(Rc5DecodeError::InvalidPulseCountTooShort);
+        }
+
+        if data >> 13 != 1 {
+            return Err(Rc5DecodeError::Start1Invalid);
+        }
+
+        let field = (data >> 12) & 1 == 1;
+
+        Ok(Rc5Message {
+            toggle: (data >> 11) & 1 == 1,
+            address: ((data >> 6) & 0b1_1111) as u8,
+            command: (data & 0b11_1111) as u8 | if field { 0 } else { 0b100_0000 },
+        })
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+
+    const HALF: u16 = 32;
+
+    fn decoder() -> Rc5Decoder {
+        Rc5Decoder::new(Rc5IrTimingConfig { half: HALF, tol_div: 4, tol_num: 1 })
+    }
+
+    /// Manchester encodes 14 bits (msb first) into pulses as they are recieved (without first and trailing space).
+    fn encode(data: u16) -> Vec<u16> {
+        let halves = (0..14).rev()
+            .flat_map(|bit| if (data >> bit) & 1 == 1 { [false, true] } else { [true, false] })
+            .collect::<Vec<_>>();
+
+        let first_mark = halves.iter().position(|level| *level).unwrap();
+        let last_mark = halves.iter().rposition(|level| *level).unwrap();
+
+        halves[first_mark..=last_mark]
+            .chunk_by(|a, b| a == b)
+            .map(|run| run.len() as u16 * HALF)
+            .collect()
+    }
+
+
+    #[test]
+    fn decodes_message() {
+        // start, field 1, toggle 1, address 5, command 12
+        let data = (1 << 13) | (1 << 12) | (1 << 11) | (5 << 6) | 12;
+
+        assert_eq!(decoder().decode(encode(data).into_iter()), Ok(Rc5Message { toggle: true, address: 5, command: 12 }));
+    }
+
+    #[test]
+    fn decodes_extended_command_and_trailing_zero() {
+        // field 0 (command + 64), last bit 0 (ends with mark)
+        let data = (1 << 13) | (0b1_1111 << 6) | 0b10_1010;
+
+        assert_eq!(decoder().decode(encode(data).into_iter()), Ok(Rc5Message { toggle: false, address: 0b1_1111, command: 0b110_1010 }));
+    }
+
+    #[test]
+    fn rejects_invalid_lengths() {
+        assert_eq!(decoder().decode([HALF * 3].into_iter()), Err(Rc5DecodeError::PulseInvalidLength(HALF * 3)));
+        assert_eq!(decoder().decode([HALF, HALF].into_iter()), Err(Rc5DecodeError::InvalidPulseCountTooShort));
+    }
+}
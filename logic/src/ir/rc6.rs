@@ -0,0 +1,192 @@
+use super::in_range;
+
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rc6DecodeError {
+    InvalidPulseCountTooShort,
+    InvalidPulseCountTooLong,
+    LeaderMarkInvalidLength(u16),
+    LeaderSpaceInvalidLength(u16),
+    PulseInvalidLength(u16),
+    /// both halves of a bit have same level
+    InvalidManchester,
+    StartInvalid,
+    /// only mode 0 (consumer devices, 8 bit address and command) is supported
+    UnsupportedMode(u8),
+}
+
+/// Philips RC6 mode 0 message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rc6Message {
+    pub toggle: bool,
+    pub address: u8,
+    pub command: u8,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Rc6IrTimingConfig {
+    pub unit: u16, // duration of half of a normal bit (444 us)
+    pub tol_div: u16,
+    pub tol_num: u16,
+}
+
+/// Decodes pulse lengths (starting with mark, alternating mark / space, ending with last mark) into `Rc6Message`.
+///
+/// Frame is leader (6 units mark, 2 units space), start bit (1), 3 mode bits, trailer (toggle) bit of double length,
+/// 8 address bits and 8 command bits (msb first). Bits are manchester encoded with opposite polarity than rc5, 1 is
+/// mark + space, 0 is space + mark, so pulses are one to three units long (trailer bit has two units long halves).
+/// Last half (space) of the last bit is not part of the recieved pulses.
+pub struct Rc6Decoder {
+    leader_mark_min: u16,
+    leader_mark_max: u16,
+    leader_space_min: u16,
+    leader_space_max: u16,
+    /// pulse length range of 1, 2 and 3 units
+    units: [(u16, u16); 3],
+}
+
+impl Rc6Decoder {
+    const LEADER_MARK_MUL: u16 = 6;
+    const LEADER_SPACE_MUL: u16 = 2;
+
+    /// start bit, mode, trailer (4 units), address and command
+    const HALVES: usize = 2 + 2 * 3 + 4 + 2 * 16;
+    const TRAILER: usize = 8;
+    const ADDRESS: usize = 12;
+    const COMMAND: usize = 28;
+
+
+    pub fn new(config: Rc6IrTimingConfig) -> Self {
+        let range = |mul: u16| (
+            config.unit * mul * (config.tol_div - config.tol_num) / config.tol_div,
+            config.unit * mul * (config.tol_div + config.tol_num) / config.tol_div,
+        );
+
+        let (leader_mark_min, leader_mark_max) = range(Self::LEADER_MARK_MUL);
+        let (leader_space_min, leader_space_max) = range(Self::LEADER_SPACE_MUL);
+
+        Self {
+            leader_mark_min,
+            leader_mark_max,
+            leader_space_min,
+            leader_space_max,
+            units: [range(1), range(2), range(3)],
+        }
+    }
+
+    pub fn decode(&self, mut pulses: impl Iterator<Item = u16>) -> Result<Rc6Message, Rc6DecodeError> {
+        let leader_mark = pulses.next().ok_or(Rc6DecodeError::InvalidPulseCountTooShort)?;
+        if !in_range(leader_mark, self.leader_mark_min, self.leader_mark_max) {
+            return Err(Rc6DecodeError::LeaderMarkInvalidLength(leader_mark));
+        }
+
+        let leader_space = pulses.next().ok_or(Rc6DecodeError::InvalidPulseCountTooShort)?;
+        if !in_range(leader_space, self.leader_space_min, self.leader_space_max) {
+            return Err(Rc6DecodeError::LeaderSpaceInvalidLength(leader_space));
+        }
+
+        // level of each half unit (`true` is mark)
+        let mut halves = [false; Self::HALVES];
+        let mut len = 0;
+
+        for (i, pulse) in pulses.enumerate() {
+            let level = i % 2 == 0;
+
+            let units = self.units.iter().position(|(min, max)| in_range(pulse, *min, *max))
+                .ok_or(Rc6DecodeError::PulseInvalidLength(pulse))? + 1;
+
+            if len + units > Self::HALVES {
+                return Err(Rc6DecodeError::InvalidPulseCountTooLong);
+            }
+
+            halves[len..(len + units)].fill(level);
+            len += units;
+        }
+
+        // last space is not recieved (last bit is 1), the rest of `halves` is already space
+        if len + 1 < Self::HALVES {
+            return Err(Rc6DecodeError::InvalidPulseCountTooShort);
+        }
+
+        let bit = |half: usize| if halves[half] != halves[half + 1] { Ok(halves[half]) } else { Err(Rc6DecodeError::InvalidManchester) };
+        let bits = |from: usize, count: usize| (0..count).try_fold(0u8, |data, i| Ok((data << 1) | bit(from + 2 * i)? as u8));
+
+        if !bit(0)? {
+            return Err(Rc6DecodeError::StartInvalid);
+        }
+
+        let mode = bits(2, 3)?;
+        if mode != 0 {
+            return Err(Rc6DecodeError::UnsupportedMode(mode));
+        }
+
+        let trailer = &halves[Self::TRAILER..(Self::TRAILER + 4)];
+        if trailer[0] != trailer[1] || trailer[2] != trailer[3] || trailer[0] == trailer[2] {
+            return Err(Rc6DecodeError::InvalidManchester);
+        }
+
+        Ok(Rc6Message {
+            toggle: trailer[0],
+            address: bits(Self::ADDRESS, 8)?,
+            command: bits(Self::COMMAND, 8)?,
+        })
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+
+    const UNIT: u16 = 16;
+
+    fn decoder() -> Rc6Decoder {
+        Rc6Decoder::new(Rc6IrTimingConfig { unit: UNIT, tol_div: 4, tol_num: 1 })
+    }
+
+    /// Encodes mode 0 frame into pulses as they are recieved (without trailing space).
+    fn encode(mode: u8, toggle: bool, address: u8, command: u8) -> Vec<u16> {
+        let bit = |value: bool| if value { [true, false] } else { [false, true] };
+
+        let mut halves = std::vec![true; 6];
+        halves.extend([false; 2]);
+        halves.extend(bit(true));
+        (0..3).rev().for_each(|i| halves.extend(bit((mode >> i) & 1 == 1)));
+        let [first, second] = bit(toggle);
+        halves.extend([first, first, second, second]);
+        (0..8).rev().for_each(|i| halves.extend(bit((address >> i) & 1 == 1)));
+        (0..8).rev().for_each(|i| halves.extend(bit((command >> i) & 1 == 1)));
+
+        let last_mark = halves.iter().rposition(|level| *level).unwrap();
+
+        halves[..=last_mark]
+            .chunk_by(|a, b| a == b)
+            .map(|run| run.len() as u16 * UNIT)
+            .collect()
+    }
+
+
+    #[test]
+    fn decodes_message() {
+        assert_eq!(decoder().decode(encode(0, true, 0x00, 0x0c).into_iter()), Ok(Rc6Message { toggle: true, address: 0x00, command: 0x0c }));
+        assert_eq!(decoder().decode(encode(0, false, 0xa5, 0x81).into_iter()), Ok(Rc6Message { toggle: false, address: 0xa5, command: 0x81 }));
+    }
+
+    #[test]
+    fn decodes_last_bit_0() {
+        // ends with mark, no missing space
+        assert_eq!(decoder().decode(encode(0, false, 0x01, 0xfe).into_iter()), Ok(Rc6Message { toggle: false, address: 0x01, command: 0xfe }));
+    }
+
+    #[test]
+    fn rejects_other_modes_and_invalid_leader() {
+        assert_eq!(decoder().decode(encode(6, false, 0x01, 0x02).into_iter()), Err(Rc6DecodeError::UnsupportedMode(6)));
+        assert_eq!(decoder().decode([UNIT * 4, UNIT * 2].into_iter()), Err(Rc6DecodeError::LeaderMarkInvalidLength(UNIT * 4)));
+        assert_eq!(decoder().decode([UNIT * 6, UNIT * 2, UNIT].into_iter()), Err(Rc6DecodeError::InvalidPulseCountTooShort));
+    }
+}
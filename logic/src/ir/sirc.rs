@@ -0,0 +1,155 @@
+use super::in_range;
+
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SircDecodeError {
+    InvalidPulseCountTooShort,
+    InvalidPulseCountTooLong,
+    Start1InvalidLength,
+    Space0InvalidLength,
+    Data1InvalidLength(u16),
+    InvalidBitCount(u8),
+}
+
+/// Sony SIRC message, 12 bit (`extended` is 0), 15 bit (8 bit `address`) or 20 bit (5 bit `address` + 8 bit `extended`) version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SircMessage {
+    pub bits: u8,
+    pub command: u8,
+    pub address: u8,
+    pub extended: u8,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct SircIrTimingConfig {
+    pub unit: u16, // duration of sirc time unit (600 us)
+    pub tol_div: u16,
+    pub tol_num: u16,
+}
+
+/// Decodes pulse lengths (starting with mark, alternating mark / space, ending with last mark) into `SircMessage`.
+///
+/// Sequence: start mark (4 units), then for each bit (lsb first) space (1 unit) and mark (2 units for 1, 1 unit for 0).
+pub struct SircDecoder {
+    unit_min: u16,
+    unit_max: u16,
+    double_min: u16,
+    double_max: u16,
+    start_1_min: u16,
+    start_1_max: u16,
+}
+
+impl SircDecoder {
+    const DOUBLE_MUL: u16 = 2;
+    const START_1_MUL: u16 = 4;
+
+    const MAX_BITS: u8 = 20;
+
+
+    pub fn new(config: SircIrTimingConfig) -> Self {
+        Self {
+            unit_min:    config.unit *                     (config.tol_div - config.tol_num) / config.tol_div,
+            unit_max:    config.unit *                     (config.tol_div + config.tol_num) / config.tol_div,
+            double_min:  config.unit * Self::DOUBLE_MUL  * (config.tol_div - config.tol_num) / config.tol_div,
+            double_max:  config.unit * Self::DOUBLE_MUL  * (config.tol_div + config.tol_num) / config.tol_div,
+            start_1_min: config.unit * Self::START_1_MUL * (config.tol_div - config.tol_num) / config.tol_div,
+            start_1_max: config.unit * Self::START_1_MUL * (config.tol_div + config.tol_num) / config.tol_div,
+        }
+    }
+
+    pub fn decode(&self, mut pulses: impl Iterator<Item = u16>) -> Result<SircMessage, SircDecodeError> {
+        let start1 = pulses.next().ok_or(SircDecodeError::InvalidPulseCountTooShort)?;
+
+        if !in_range(start1, self.start_1_min, self.start_1_max) {
+            return Err(SircDecodeError::Start1InvalidLength);
+        }
+
+        let mut data = 0u32;
+        let mut bits = 0u8;
+
+        while let Some(pulse0) = pulses.next() {
+            if !in_range(pulse0, self.unit_min, self.unit_max) {
+                return Err(SircDecodeError::Space0InvalidLength);
+            }
+
+            let pulse1 = pulses.next().ok_or(SircDecodeError::InvalidPulseCountTooShort)?;
+
+            if bits == Self::MAX_BITS {
+                return Err(SircDecodeError::InvalidPulseCountTooLong);
+            }
+
+            if in_range(pulse1, self.double_min, self.double_max) {
+                data |= 1 << bits;
+            } else if !in_range(pulse1, self.unit_min, self.unit_max) {
+                return Err(SircDecodeError::Data1InvalidLength(pulse1));
+            }
+
+            bits += 1;
+        }
+
+        let command = (data & 0b0111_1111) as u8;
+
+        match bits {
+            12 => Ok(SircMessage { bits, command, address: ((data >> 7) & 0b1_1111) as u8, extended: 0 }),
+            15 => Ok(SircMessage { bits, command, address: (data >> 7) as u8, extended: 0 }),
+            20 => Ok(SircMessage { bits, command, address: ((data >> 7) & 0b1_1111) as u8, extended: (data >> 12) as u8 }),
+            _ => Err(SircDecodeError::InvalidBitCount(bits)),
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+
+    const UNIT: u16 = 21;
+
+    fn decoder() -> SircDecoder {
+        SircDecoder::new(SircIrTimingConfig { unit: UNIT, tol_div: 4, tol_num: 1 })
+    }
+
+    fn encode(data: u32, bits: u8) -> Vec<u16> {
+        let mut pulses = std::vec![UNIT * 4];
+
+        for bit in 0..bits {
+            pulses.push(UNIT);
+            pulses.push(if (data >> bit) & 1 == 1 { UNIT * 2 } else { UNIT });
+        }
+
+        pulses
+    }
+
+
+    #[test]
+    fn decodes_all_versions() {
+        assert_eq!(
+            decoder().decode(encode((0b1_0001 << 7) | 0x15, 12).into_iter()),
+            Ok(SircMessage { bits: 12, command: 0x15, address: 0b1_0001, extended: 0 })
+        );
+        assert_eq!(
+            decoder().decode(encode((0xa4 << 7) | 0x7f, 15).into_iter()),
+            Ok(SircMessage { bits: 15, command: 0x7f, address: 0xa4, extended: 0 })
+        );
+        assert_eq!(
+            decoder().decode(encode((0x5a << 12) | (0b0_0011 << 7) | 0x01, 20).into_iter()),
+            Ok(SircMessage { bits: 20, command: 0x01, address: 0b0_0011, extended: 0x5a })
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_bit_count() {
+        assert_eq!(decoder().decode(encode(0, 13).into_iter()), Err(SircDecodeError::InvalidBitCount(13)));
+        assert_eq!(decoder().decode(encode(0, 21).into_iter()), Err(SircDecodeError::InvalidPulseCountTooLong));
+    }
+
+    #[test]
+    fn rejects_nec_start() {
+        assert_eq!(decoder().decode([20 * 16, 20 * 8].into_iter()), Err(SircDecodeError::Start1InvalidLength));
+    }
+}
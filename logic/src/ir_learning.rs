@@ -0,0 +1,287 @@
+/* learned ir codes: raw pulse trains of any remote, normalized and kept in named flash slots (one sector each) */
+
+use heapless::Vec;
+
+use crate::{flash::{SectorFlash, SECTOR_SIZE}, framing::crc16};
+
+
+
+/// Longest learned code (in pulses), one rmt tx channel memory block (48 pulse codes, mark + space each, last space
+/// is the end marker).
+pub const MAX_PULSES: usize = 95;
+/// Pulse codes of one rmt tx channel memory block.
+pub const MAX_TX_CODES: usize = 48;
+/// Length of slot name (in bytes), shorter names are padded with zeros.
+pub const NAME_LEN: usize = 8;
+
+/// Magic + name + number of pulses + crc + pulses (length has to be multiple of 4 for rom flash functions).
+pub const SLOT_LEN: usize = (4 + NAME_LEN + 2 + 2 + 2 * MAX_PULSES + 3) / 4 * 4;
+
+/// Identifies stored code, has to be changed when slot encoding changes.
+const MAGIC: u32 = 0x4952_4c01;
+const HEADER_LEN: usize = 4 + NAME_LEN + 2 + 2;
+
+/// Maximum number of distinct pulse lengths merged by `normalize`, other lengths are kept as they are.
+const MAX_CLUSTERS: usize = 16;
+
+
+pub type Name = [u8; NAME_LEN];
+
+/// Name from ascii text, `None` when it is empty, too long or not printable.
+pub fn name_from_str(s: &str) -> Option<Name> {
+    if s.is_empty() || s.len() > NAME_LEN || !s.bytes().all(|byte| byte.is_ascii_graphic()) {
+        return None;
+    }
+
+    let mut name = [0; NAME_LEN];
+    name[..s.len()].copy_from_slice(s.as_bytes());
+
+    Some(name)
+}
+
+pub fn name_as_str(name: &Name) -> &str {
+    let len = name.iter().position(|byte| *byte == 0).unwrap_or(NAME_LEN);
+
+    core::str::from_utf8(&name[..len]).unwrap_or("?")
+}
+
+
+/// Number of rmt pulse codes (mark + space each) sending `pulses` pulses including the end marker (zero length).
+///
+/// Code ending with mark (odd number of pulses) uses space of the last pulse code as the end marker, otherwise the end
+/// marker is an extra pulse code.
+pub fn tx_codes(pulses: usize) -> usize {
+    pulses / 2 + 1
+}
+
+/// Replaces each pulse length by average of its cluster, so jitter of the reciever is not replayed.
+///
+/// Cluster are lengths within tolerance (`tol_num / tol_div`) of its first length, marks and spaces are clustered
+/// separately.
+pub fn normalize(pulses: &mut [u16], tol_num: u32, tol_div: u32) {
+    for parity in 0..2 {
+        // first length, sum, count
+        let mut clusters: Vec<(u16, u32, u32), MAX_CLUSTERS> = Vec::new();
+        let within = |first: u16, pulse: u16| (pulse as u32).abs_diff(first as u32) * tol_div <= first as u32 * tol_num;
+
+        for pulse in pulses.iter().skip(parity).step_by(2) {
+            match clusters.iter_mut().find(|(first, _, _)| within(*first, *pulse)) {
+                Some((_, sum, count)) => {
+                    *sum += *pulse as u32;
+                    *count += 1;
+                },
+                None => { let _ = clusters.push((*pulse, *pulse as u32, 1)); },
+            }
+        }
+
+        for pulse in pulses.iter_mut().skip(parity).step_by(2) {
+            if let Some((_, sum, count)) = clusters.iter().find(|(first, _, _)| within(*first, *pulse)) {
+                *pulse = ((sum + count / 2) / count) as u16;
+            }
+        }
+    }
+}
+
+
+/// Pulse train of a remote key (starting with mark, alternating mark / space, ending with last mark), in us.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LearnedCode {
+    pub name: Name,
+    pub pulses: Vec<u16, MAX_PULSES>,
+}
+
+impl LearnedCode {
+    /// Total length of the pulse train (in us).
+    pub fn duration(&self) -> u32 {
+        self.pulses.iter().map(|pulse| *pulse as u32).sum()
+    }
+}
+
+
+/// Slot bytes of `code`.
+pub fn encode_slot(code: &LearnedCode) -> [u8; SLOT_LEN] {
+    let mut slot = [0xff; SLOT_LEN];
+
+    slot[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+    slot[4..(4 + NAME_LEN)].copy_from_slice(&code.name);
+    slot[(4 + NAME_LEN)..(6 + NAME_LEN)].copy_from_slice(&(code.pulses.len() as u16).to_le_bytes());
+
+    for (bytes, pulse) in slot[HEADER_LEN..].chunks_exact_mut(2).zip(code.pulses.iter()) {
+        bytes.copy_from_slice(&pulse.to_le_bytes());
+    }
+
+    let crc = crc16(&slot[HEADER_LEN..(HEADER_LEN + 2 * code.pulses.len())]) ^ crc16(&slot[..(6 + NAME_LEN)]);
+    slot[(6 + NAME_LEN)..HEADER_LEN].copy_from_slice(&crc.to_le_bytes());
+
+    slot
+}
+
+/// `None` for erased, corrupted (crc) or incompatible (magic) slot.
+pub fn decode_slot(slot: &[u8; SLOT_LEN]) -> Option<LearnedCode> {
+    let magic = u32::from_le_bytes(slot[0..4].try_into().ok()?);
+    let len = u16::from_le_bytes(slot[(4 + NAME_LEN)..(6 + NAME_LEN)].try_into().ok()?) as usize;
+
+    if magic != MAGIC || len > MAX_PULSES {
+        return None;
+    }
+
+    let crc = u16::from_le_bytes(slot[(6 + NAME_LEN)..HEADER_LEN].try_into().ok()?);
+    if crc != crc16(&slot[HEADER_LEN..(HEADER_LEN + 2 * len)]) ^ crc16(&slot[..(6 + NAME_LEN)]) {
+        return None;
+    }
+
+    Some(LearnedCode {
+        name: slot[4..(4 + NAME_LEN)].try_into().ok()?,
+        pulses: slot[HEADER_LEN..(HEADER_LEN + 2 * len)].chunks_exact(2).map(|bytes| u16::from_le_bytes([bytes[0], bytes[1]])).collect(),
+    })
+}
+
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrCodeStoreError<E> {
+    Flash(E),
+    SlotOutOfRange,
+    /// read back code does not match written code
+    VerifyFailed,
+}
+
+impl<E> From<E> for IrCodeStoreError<E> {
+    fn from(err: E) -> Self {
+        IrCodeStoreError::Flash(err)
+    }
+}
+
+/// Keeps learned codes in flash region, each slot occupies one sector (codes are saved rarely, by user).
+pub struct IrCodeStore<F> {
+    flash: F,
+    slots: u32,
+}
+
+impl<F: SectorFlash> IrCodeStore<F> {
+    /// `slots` is number of sectors of the region.
+    pub fn new(flash: F, slots: u32) -> Self {
+        Self { flash, slots }
+    }
+
+    pub fn slots(&self) -> u32 {
+        self.slots
+    }
+
+    /// `None` when the slot is empty (or corrupted).
+    pub fn load(&mut self, slot: u32) -> Result<Option<LearnedCode>, IrCodeStoreError<F::Error>> {
+        if slot >= self.slots {
+            return Err(IrCodeStoreError::SlotOutOfRange);
+        }
+
+        let mut bytes = [0; SLOT_LEN];
+        self.flash.read(slot * SECTOR_SIZE, &mut bytes)?;
+
+        Ok(decode_slot(&bytes))
+    }
+
+    /// First slot holding code named `name`.
+    pub fn find(&mut self, name: &Name) -> Result<Option<(u32, LearnedCode)>, IrCodeStoreError<F::Error>> {
+        for slot in 0..self.slots {
+            if let Some(code) = self.load(slot)? && code.name == *name {
+                return Ok(Some((slot, code)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Replaces code in `slot` and reads it back.
+    pub fn save(&mut self, slot: u32, code: &LearnedCode) -> Result<(), IrCodeStoreError<F::Error>> {
+        if slot >= self.slots {
+            return Err(IrCodeStoreError::SlotOutOfRange);
+        }
+
+        self.flash.erase_sector(slot)?;
+        self.flash.write(slot * SECTOR_SIZE, &encode_slot(code))?;
+
+        match self.load(slot)? {
+            Some(read_code) if read_code == *code => Ok(()),
+            _ => Err(IrCodeStoreError::VerifyFailed),
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::flash::RamFlash;
+
+
+    fn code(name: &str, pulses: &[u16]) -> LearnedCode {
+        LearnedCode { name: name_from_str(name).unwrap(), pulses: Vec::from_slice(pulses).unwrap() }
+    }
+
+
+    #[test]
+    fn names() {
+        assert_eq!(name_as_str(&name_from_str("tv").unwrap()), "tv");
+        assert_eq!(name_as_str(&name_from_str("12345678").unwrap()), "12345678");
+        assert_eq!(name_from_str("123456789"), None);
+        assert_eq!(name_from_str(""), None);
+        assert_eq!(name_from_str("a b"), None);
+    }
+
+    #[test]
+    fn longest_code_fits_tx_memory() {
+        assert_eq!(tx_codes(MAX_PULSES), MAX_TX_CODES);
+        assert_eq!(tx_codes(MAX_PULSES + 1), MAX_TX_CODES + 1);
+        assert_eq!(tx_codes(1), 1);
+        assert_eq!(tx_codes(2), 2);
+    }
+
+    #[test]
+    fn normalize_merges_jitter_of_marks_and_spaces_separately() {
+        // nec like: 9000 / 4500 start, 560 marks, 560 and 1690 spaces
+        let mut pulses = [8960, 4480, 532, 588, 560, 1680, 588, 1708, 560];
+        normalize(&mut pulses, 1, 4);
+
+        assert_eq!(pulses, [8960, 4480, 560, 588, 560, 1694, 560, 1694, 560]);
+    }
+
+    #[test]
+    fn normalize_keeps_distant_lengths() {
+        let mut pulses = [600, 600, 1200, 600, 1800];
+        normalize(&mut pulses, 1, 4);
+
+        assert_eq!(pulses, [600, 600, 1200, 600, 1800]);
+    }
+
+    #[test]
+    fn slot_roundtrip_and_corruption() {
+        let code = code("fan", &[2400, 600, 1200, 600, 600]);
+        let mut slot = encode_slot(&code);
+
+        assert_eq!(SLOT_LEN % 4, 0);
+        assert_eq!(decode_slot(&slot), Some(code));
+        assert_eq!(decode_slot(&[0xff; SLOT_LEN]), None);
+
+        // name is covered by crc too
+        slot[4] ^= 0x01;
+        assert_eq!(decode_slot(&slot), None);
+    }
+
+    #[test]
+    fn store_saves_loads_and_finds_by_name() {
+        let mut flash = RamFlash::new(4);
+        let mut store = IrCodeStore::new(&mut flash, 4);
+
+        store.save(1, &code("tv", &[600, 600, 1200])).unwrap();
+        store.save(3, &code("fan", &[2400, 600, 600])).unwrap();
+        store.save(1, &code("light", &[900, 900, 900])).unwrap();
+
+        assert_eq!(store.load(0), Ok(None));
+        assert_eq!(store.load(1), Ok(Some(code("light", &[900, 900, 900]))));
+        assert_eq!(store.find(&name_from_str("fan").unwrap()), Ok(Some((3, code("fan", &[2400, 600, 600])))));
+        assert_eq!(store.find(&name_from_str("tv").unwrap()), Ok(None));
+        assert_eq!(store.save(4, &code("tv", &[600])), Err(IrCodeStoreError::SlotOutOfRange));
+        assert_eq!(flash.erase_count, [0, 2, 0, 1]);
+    }
+}
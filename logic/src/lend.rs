@@ -0,0 +1,106 @@
+/* value owned by the caller lent to code which cannot hold `&mut` (async tasks polled by `executor`) */
+
+use core::{
+    cell::Cell,
+    ptr::NonNull
+};
+
+
+
+/// Value owned by main loop which async tasks can use while it is lent (see `lend`).
+///
+/// Replaces `&mut` arguments of machine `update` methods, future cannot keep `&mut` between polls.
+pub struct Lend<T> {
+    value: Cell<Option<NonNull<T>>>,
+}
+
+impl<T> Lend<T> {
+    pub const fn new() -> Self {
+        Self { value: Cell::new(None) }
+    }
+
+    /// `value` can be used through `with` while `f` runs.
+    pub fn lend<R>(&self, value: &mut T, f: impl FnOnce() -> R) -> R {
+        let previous = self.value.replace(Some(NonNull::from(value)));
+        let result = f();
+        self.value.set(previous);
+
+        result
+    }
+
+    /// `None` when value is not lent or it is already used by outer `with`.
+    pub fn try_with<R>(&self, f: impl FnOnce(&mut T) -> R) -> Option<R> {
+        let mut value = self.value.take()?;
+
+        // SAFETY: pointer was created from `&mut T` which is borrowed by `lend` until the pointer is removed again,
+        // pointer is taken out while it is used, so nested `with` cannot create second reference
+        let result = f(unsafe { value.as_mut() });
+
+        self.value.set(Some(value));
+
+        Some(result)
+    }
+
+    /// Panics when value is not lent or it is already used by outer `with`, tasks are polled only while resources
+    /// are lent (see `AsyncIo::lend`).
+    pub fn with<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        self.try_with(f).expect("lent value used outside of lend")
+    }
+}
+
+impl<T> Default for Lend<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn with_inside_lend() {
+        let lend = Lend::new();
+        let mut value = 1;
+
+        assert_eq!(lend.try_with(|value: &mut i32| *value), None);
+
+        lend.lend(&mut value, || lend.with(|value| *value += 1));
+        assert_eq!(value, 2);
+
+        assert_eq!(lend.try_with(|value| *value), None);
+    }
+
+    #[test]
+    fn nested_with_is_refused() {
+        let lend = Lend::new();
+        let mut value = 1;
+
+        let nested = lend.lend(&mut value, || lend.with(|_| lend.try_with(|value| *value)));
+        assert_eq!(nested, None);
+
+        // value is available again after outer `with`
+        assert_eq!(lend.lend(&mut value, || lend.with(|value| *value)), 1);
+    }
+
+    #[test]
+    fn nested_lend_restores_previous() {
+        let lend = Lend::new();
+        let mut outer = 1;
+        let mut inner = 2;
+
+        let values = lend.lend(&mut outer, || {
+            let inner = lend.lend(&mut inner, || lend.with(|value| *value));
+            (inner, lend.with(|value| *value))
+        });
+        assert_eq!(values, (2, 1));
+    }
+
+    #[test]
+    #[should_panic]
+    fn with_outside_lend_panics() {
+        Lend::<i32>::new().with(|_| ());
+    }
+}
@@ -0,0 +1,45 @@
+//! Hardware independent logic of the firmware (decoders, buffers, encodings, ...).
+//!
+//! Tests run on the host: `cargo test-logic` (alias in `.cargo/config.toml`).
+
+#![cfg_attr(not(test), no_std)]
+
+#![feature(maybe_uninit_write_slice)]
+#![feature(let_chains)]
+#![feature(iter_array_chunks)]
+
+
+
+pub mod alarm_heap;
+pub mod alarm_table;
+pub mod bme280;
+pub mod bus_timing;
+pub mod config_store;
+pub mod event_bus;
+pub mod executor;
+pub mod filter;
+pub mod fixed_point;
+pub mod flash;
+pub mod flash_log;
+pub mod framebuffer;
+pub mod framing;
+pub mod http;
+pub mod humidity;
+pub mod invariants;
+pub mod ir;
+pub mod ir_learning;
+pub mod lend;
+pub mod log;
+pub mod measurment;
+pub mod mqtt;
+pub mod record;
+pub mod ring_buffer;
+pub mod sensirion_crc;
+pub mod sensirion_word;
+pub mod sensor_history;
+pub mod snapshot;
+pub mod soft_i2c;
+pub mod sony_ir;
+pub mod summary;
+pub mod trend;
+pub mod wall_clock;
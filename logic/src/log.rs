@@ -0,0 +1,322 @@
+/* log lines: levels of modules, line format, comment lines and sticky flag of dropped output (macros are used by the
+   firmware, which provides timestamp of lines, see `log!`) */
+
+use core::{fmt::{self, Write}, sync::atomic::{AtomicBool, AtomicU8, Ordering}};
+
+
+
+/// Severity of log line, more verbose levels are greater.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Level {
+    Error = 1,
+    Warn = 2,
+    Info = 3,
+    Debug = 4,
+}
+
+impl Level {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        }
+    }
+
+    /// One letter in the log line.
+    pub fn tag(&self) -> char {
+        match self {
+            Level::Error => 'E',
+            Level::Warn => 'W',
+            Level::Info => 'I',
+            Level::Debug => 'D',
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Level> {
+        [Level::Error, Level::Warn, Level::Info, Level::Debug].into_iter().find(|level| level.name() == name)
+    }
+
+    fn from_u8(value: u8) -> Option<Level> {
+        [Level::Error, Level::Warn, Level::Info, Level::Debug].into_iter().find(|level| *level as u8 == value)
+    }
+}
+
+
+/// Part of the firmware which writes log lines, each module has its own level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Module {
+    Main,
+    Config,
+    Status,
+    Sdc,
+    Ambient,
+    Controller,
+    Alert,
+    Ir,
+    Wifi,
+    Mqtt,
+    Http,
+    Button,
+    Ventilation,
+}
+
+impl Module {
+    pub const COUNT: usize = 13;
+    pub const ALL: [Module; Module::COUNT] = [Module::Main, Module::Config, Module::Status, Module::Sdc, Module::Ambient, Module::Controller, Module::Alert, Module::Ir, Module::Wifi, Module::Mqtt, Module::Http, Module::Button, Module::Ventilation];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Module::Main => "main",
+            Module::Config => "config",
+            Module::Status => "status",
+            Module::Sdc => "scd30",
+            Module::Ambient => "ambient",
+            Module::Controller => "controller",
+            Module::Alert => "alert",
+            Module::Ir => "ir",
+            Module::Wifi => "wifi",
+            Module::Mqtt => "mqtt",
+            Module::Http => "http",
+            Module::Button => "button",
+            Module::Ventilation => "ventilation",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Module> {
+        Module::ALL.into_iter().find(|module| module.name() == name)
+    }
+
+    /// Lines more verbose than this level are not compiled in at all (runtime level cannot enable them), the same for
+    /// all modules: debug lines are only in debug builds.
+    pub const fn static_level(self) -> Level {
+        if cfg!(debug_assertions) { Level::Debug } else { Level::Info }
+    }
+}
+
+
+/// Level of each module set at runtime (see `set_level`), 0 disables the module.
+static LEVELS: [AtomicU8; Module::COUNT] = [const { AtomicU8::new(Level::Info as u8) }; Module::COUNT];
+
+/// Set when output did not fit into the writer (see `take_output_dropped`), stays set until it is reported.
+static OUTPUT_DROPPED: AtomicBool = AtomicBool::new(false);
+
+/// Set while lines are written as comments (see `set_comment_lines`).
+static COMMENT_LINES: AtomicBool = AtomicBool::new(false);
+
+/// Written in place of level tag by `log_line!`, its lines are not subject to log levels.
+pub const OUTPUT_TAG: char = '>';
+
+/// Starts lines of log macros and `log_line!` while they are comments (see `set_comment_lines`), `log_fmt!` output is
+/// not prefixed.
+pub const COMMENT_PREFIX: &str = "# ";
+
+
+
+/// Writes one log line `<ticks> <level> <module> : <message>` when `level` is enabled for `module`.
+///
+/// Level is checked twice: against `Module::static_level` at compile time (line is removed when it is more verbose)
+/// and against runtime level of the module (see `set_level`), all modules start at `Level::Info`.
+///
+/// Timestamp is taken from `crate::log::now()` of the crate using the macro (system timer ticks in the firmware).
+#[macro_export]
+#[allow(clippy::crate_in_macro_def)]
+macro_rules! log {
+    ($writer:expr, $module:expr, $level:expr, $($arg:tt)+) => {{
+        if const { ($level as u8) <= ($module.static_level() as u8) } && $crate::log::enabled($module, $level) {
+            $crate::log::write_line($writer, crate::log::now(), $module, $level, format_args!($($arg)+));
+        }
+    }};
+}
+
+#[macro_export]
+macro_rules! error {
+    ($writer:expr, $module:expr, $($arg:tt)+) => { $crate::log!($writer, $module, $crate::log::Level::Error, $($arg)+) };
+}
+
+// `warn` would be ambiguous with the builtin attribute, firmware imports it under its name
+#[macro_export]
+macro_rules! log_warn {
+    ($writer:expr, $module:expr, $($arg:tt)+) => { $crate::log!($writer, $module, $crate::log::Level::Warn, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($writer:expr, $module:expr, $($arg:tt)+) => { $crate::log!($writer, $module, $crate::log::Level::Info, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($writer:expr, $module:expr, $($arg:tt)+) => { $crate::log!($writer, $module, $crate::log::Level::Debug, $($arg)+) };
+}
+
+/// Writes one line of output `<ticks> > <prefix> : <message>` (replies to console commands, status of machines).
+///
+/// Lines are not subject to log levels. Output which does not fit into the writer sets sticky flag (see
+/// `take_output_dropped`).
+#[macro_export]
+#[allow(clippy::crate_in_macro_def)]
+macro_rules! log_line {
+    ($writer:expr, $prefix:expr, $($arg:tt)+) => {
+        $crate::log::write_output_line($writer, crate::log::now(), $prefix, format_args!($($arg)+))
+    };
+}
+
+/// Same as `write!` for output which is not a line (raw data, csv), sets the same sticky flag as `log_line!` on
+/// overflow.
+#[macro_export]
+macro_rules! log_fmt {
+    ($writer:expr, $($arg:tt)+) => {
+        $crate::log::write_checked($writer, format_args!($($arg)+))
+    };
+}
+
+
+fn check(result: fmt::Result) {
+    if result.is_err() {
+        OUTPUT_DROPPED.store(true, Ordering::Relaxed);
+    }
+}
+
+fn write_tagged_line(writer: &mut impl Write, comment: bool, ticks: u64, tag: char, prefix: &str, args: fmt::Arguments) {
+    let comment = if comment { COMMENT_PREFIX } else { "" };
+    check(writeln!(writer, "{}{} {} {} : {}", comment, ticks, tag, prefix, args));
+}
+
+/// Use log macros instead of calling this directly.
+pub fn write_line(writer: &mut impl Write, ticks: u64, module: Module, level: Level, args: fmt::Arguments) {
+    write_tagged_line(writer, comment_lines(), ticks, level.tag(), module.name(), args);
+}
+
+/// Use `log_line!` instead of calling this directly.
+pub fn write_output_line(writer: &mut impl Write, ticks: u64, prefix: &str, args: fmt::Arguments) {
+    write_tagged_line(writer, comment_lines(), ticks, OUTPUT_TAG, prefix, args);
+}
+
+/// Use `log_fmt!` instead of calling this directly.
+pub fn write_checked(writer: &mut impl Write, args: fmt::Arguments) {
+    check(writer.write_fmt(args));
+}
+
+/// Whether any output was dropped since the last call (writer buffer was full), clears the flag.
+pub fn take_output_dropped() -> bool {
+    OUTPUT_DROPPED.swap(false, Ordering::Relaxed)
+}
+
+/// Lines are started by `COMMENT_PREFIX` while `enabled`, so they can be skipped by hosts which read machine readable
+/// records (csv, json lines) from the same output.
+pub fn set_comment_lines(enabled: bool) {
+    COMMENT_LINES.store(enabled, Ordering::Relaxed);
+}
+
+pub fn comment_lines() -> bool {
+    COMMENT_LINES.load(Ordering::Relaxed)
+}
+
+pub fn enabled(module: Module, level: Level) -> bool {
+    level as u8 <= LEVELS[module as usize].load(Ordering::Relaxed)
+}
+
+/// `None` disables all lines of the module.
+pub fn set_level(module: Module, level: Option<Level>) {
+    LEVELS[module as usize].store(level.map_or(0, |level| level as u8), Ordering::Relaxed);
+}
+
+pub fn level(module: Module) -> Option<Level> {
+    Level::from_u8(LEVELS[module as usize].load(Ordering::Relaxed))
+}
+
+
+
+#[cfg(test)]
+fn now() -> u64 {
+    1234
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use heapless::String;
+
+
+    // levels and the flags are global, each test uses its own module, only `output_dropped_is_sticky` overflows and
+    // comment lines are not enabled (other tests would get them)
+
+
+    #[test]
+    fn log_line() {
+        let mut line = String::<64>::new();
+        crate::log_line!(&mut line, "config", "saved {}", 1);
+
+        assert_eq!(line, "1234 > config : saved 1\n");
+    }
+
+    #[test]
+    fn log_line_at_level() {
+        let mut line = String::<64>::new();
+        crate::info!(&mut line, Module::Wifi, "connected to {}", "ap");
+        crate::error!(&mut line, Module::Wifi, "failed");
+
+        assert_eq!(line, "1234 I wifi : connected to ap\n1234 E wifi : failed\n");
+    }
+
+    #[test]
+    fn level_filters_lines() {
+        let mut line = String::<64>::new();
+
+        // debug lines are compiled in test (debug) builds, runtime level starts at info
+        crate::debug!(&mut line, Module::Mqtt, "skipped");
+        assert_eq!(line, "");
+
+        set_level(Module::Mqtt, Some(Level::Debug));
+        crate::debug!(&mut line, Module::Mqtt, "written");
+        assert_eq!(line, "1234 D mqtt : written\n");
+        assert_eq!(level(Module::Mqtt), Some(Level::Debug));
+
+        line.clear();
+        set_level(Module::Mqtt, None);
+        crate::error!(&mut line, Module::Mqtt, "skipped");
+        assert_eq!(line, "");
+        assert_eq!(level(Module::Mqtt), None);
+    }
+
+    #[test]
+    fn comment_lines() {
+        let mut line = String::<64>::new();
+        write_tagged_line(&mut line, true, 1234, OUTPUT_TAG, "config", format_args!("saved"));
+        write_tagged_line(&mut line, true, 1234, Level::Warn.tag(), Module::Http.name(), format_args!("closed"));
+
+        assert_eq!(line, "# 1234 > config : saved\n# 1234 W http : closed\n");
+        assert!(!super::comment_lines());
+    }
+
+    #[test]
+    fn log_fmt() {
+        let mut line = String::<64>::new();
+        crate::log_fmt!(&mut line, "co2,{}", 400);
+
+        assert_eq!(line, "co2,400");
+    }
+
+    #[test]
+    fn output_dropped_is_sticky() {
+        let mut line = String::<8>::new();
+        crate::log_fmt!(&mut line, "fits");
+        assert!(!take_output_dropped());
+
+        crate::log_line!(&mut line, "config", "does not fit");
+        crate::log_fmt!(&mut line, "a");
+        assert!(take_output_dropped());
+        assert!(!take_output_dropped());
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(Level::from_name("warn"), Some(Level::Warn));
+        assert_eq!(Level::from_name("verbose"), None);
+        assert!(Module::ALL.into_iter().all(|module| Module::from_name(module.name()) == Some(module)));
+    }
+}
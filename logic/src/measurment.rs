@@ -0,0 +1,76 @@
+/* scd30 measurment and sensor it comes from (shared by sensor machines and controller) */
+
+use core::ops::RangeInclusive;
+
+
+
+/// Scd30 sensor (zone) of a measurment, sensors are numbered from 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SensorId(pub u8);
+
+impl SensorId {
+    /// sensor on i2c0, its measurments drive alerts and outputs
+    pub const PRIMARY: SensorId = SensorId(0);
+}
+
+
+/// Scd30 measurment in fixed point values (parsed by `sdc::RawMeasurment::parse` in the firmware).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Measurment {
+    /// in 10^-3 ppm
+    pub co2: i32,
+    /// in m°C
+    pub temperature: i32,
+    /// in m%
+    pub humidity: i32,
+}
+
+impl Measurment {
+    /// measurment ranges from scd30 documentation (in 10^-3 of the unit)
+    const CO2_RANGE: RangeInclusive<i32> = 0..=40_000_000;
+    const TEMPERATURE_RANGE: RangeInclusive<i32> = -40_000..=70_000;
+    const HUMIDITY_RANGE: RangeInclusive<i32> = 0..=100_000;
+
+
+    /// First value outside of range specified by scd30 documentation (co2 0 - 40000 ppm, temperature -40 - 70 °C,
+    /// humidity 0 - 100 %).
+    pub fn check_range(&self) -> Result<(), MeasurmentOutOfRange> {
+        if !Self::CO2_RANGE.contains(&self.co2) {
+            Err(MeasurmentOutOfRange::Co2(self.co2))
+        } else if !Self::TEMPERATURE_RANGE.contains(&self.temperature) {
+            Err(MeasurmentOutOfRange::Temperature(self.temperature))
+        } else if !Self::HUMIDITY_RANGE.contains(&self.humidity) {
+            Err(MeasurmentOutOfRange::Humidity(self.humidity))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+
+/// Measured value outside of range specified by scd30 documentation (value in 10^-3 of the unit), sample is rejected
+/// by sensor machine before it is published (see `Measurment::check_range`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MeasurmentOutOfRange {
+    Co2(i32),
+    Temperature(i32),
+    Humidity(i32),
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn range_check() {
+        let measurment = Measurment { co2: 400_000, temperature: 21_500, humidity: 45_000 };
+
+        assert_eq!(measurment.check_range(), Ok(()));
+        assert_eq!(Measurment { co2: -1, ..measurment }.check_range(), Err(MeasurmentOutOfRange::Co2(-1)));
+        assert_eq!(Measurment { temperature: 70_001, ..measurment }.check_range(), Err(MeasurmentOutOfRange::Temperature(70_001)));
+        assert_eq!(Measurment { humidity: 100_001, ..measurment }.check_range(), Err(MeasurmentOutOfRange::Humidity(100_001)));
+    }
+}
@@ -0,0 +1,309 @@
+/* minimal mqtt 3.1.1 client packets: connect, publish (qos 0), pingreq, disconnect and parsing of broker responses */
+
+
+
+/// Keep alive ping (no variable header, no payload).
+pub const PINGREQ: [u8; 2] = [0xc0, 0x00];
+/// Graceful disconnect (no variable header, no payload).
+pub const DISCONNECT: [u8; 2] = [0xe0, 0x00];
+
+/// Maximum value of remaining length (4 bytes of variable length encoding).
+const MAX_REMAINING_LEN: usize = 268_435_455;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MqttError {
+    /// encoded packet does not fit into output buffer
+    BufferTooSmall,
+    /// string longer than 65535 bytes or packet longer than `MAX_REMAINING_LEN`
+    TooLong,
+    /// remaining length of received packet is encoded in more than 4 bytes
+    MalformedLength,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Connect<'a> {
+    pub client_id: &'a str,
+    /// broker disconnects client which does not send any packet for 1.5 × keep alive
+    pub keep_alive_secs: u16,
+    pub username: Option<&'a str>,
+    /// sent only together with username (mqtt 3.1.1 does not allow password without username)
+    pub password: Option<&'a str>,
+}
+
+
+/// Packet received from broker, client does not subscribe, so only responses are recognized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Packet {
+    /// `return_code` 0 means the connection was accepted
+    ConnAck { session_present: bool, return_code: u8 },
+    PingResp,
+    /// other packet type (upper nibble of the first byte), its content is skipped
+    Other(u8),
+}
+
+
+
+/// Output buffer with tracking of written length, all encoders fail (and write nothing meaningful) when the packet does not fit.
+struct Writer<'o> {
+    out: &'o mut [u8],
+    len: usize,
+}
+
+impl<'o> Writer<'o> {
+    fn bytes(&mut self, bytes: &[u8]) -> Result<(), MqttError> {
+        let end = self.len + bytes.len();
+        self.out.get_mut(self.len..end).ok_or(MqttError::BufferTooSmall)?.copy_from_slice(bytes);
+        self.len = end;
+
+        Ok(())
+    }
+
+    fn u16(&mut self, value: u16) -> Result<(), MqttError> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    /// Length prefixed utf8 string.
+    fn string(&mut self, string: &str) -> Result<(), MqttError> {
+        self.u16(u16::try_from(string.len()).map_err(|_| MqttError::TooLong)?)?;
+        self.bytes(string.as_bytes())
+    }
+
+    /// Variable length encoding, 7 bits per byte, highest bit is continuation.
+    fn remaining_len(&mut self, mut len: usize) -> Result<(), MqttError> {
+        if len > MAX_REMAINING_LEN {
+            return Err(MqttError::TooLong);
+        }
+
+        loop {
+            let byte = (len % 128) as u8;
+            len /= 128;
+
+            if len == 0 {
+                return self.bytes(&[byte]);
+            }
+
+            self.bytes(&[byte | 0x80])?;
+        }
+    }
+}
+
+
+fn string_len(string: &str) -> usize {
+    2 + string.len()
+}
+
+/// Encodes connect packet (clean session) into `out`, returns its length.
+pub fn encode_connect(connect: &Connect, out: &mut [u8]) -> Result<usize, MqttError> {
+    let password = connect.username.and(connect.password);
+
+    let mut flags = 0x02; // clean session
+    let mut remaining_len = string_len("MQTT") + 1 + 1 + 2 + string_len(connect.client_id);
+
+    if let Some(username) = connect.username {
+        flags |= 0x80;
+        remaining_len += string_len(username);
+    }
+    if let Some(password) = password {
+        flags |= 0x40;
+        remaining_len += string_len(password);
+    }
+
+    let mut writer = Writer { out, len: 0 };
+
+    writer.bytes(&[0x10])?;
+    writer.remaining_len(remaining_len)?;
+    writer.string("MQTT")?;
+    writer.bytes(&[4, flags])?; // protocol level 4 (3.1.1)
+    writer.u16(connect.keep_alive_secs)?;
+    writer.string(connect.client_id)?;
+    connect.username.map_or(Ok(()), |username| writer.string(username))?;
+    password.map_or(Ok(()), |password| writer.string(password))?;
+
+    Ok(writer.len)
+}
+
+/// Encodes publish packet with qos 0 (no packet identifier, no acknowledgement) into `out`, returns its length.
+pub fn encode_publish(topic: &str, payload: &[u8], retain: bool, out: &mut [u8]) -> Result<usize, MqttError> {
+    let mut writer = Writer { out, len: 0 };
+
+    writer.bytes(&[0x30 | retain as u8])?;
+    writer.remaining_len(string_len(topic) + payload.len())?;
+    writer.string(topic)?;
+    writer.bytes(payload)?;
+
+    Ok(writer.len)
+}
+
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ParserState {
+    FixedHeader,
+    RemainingLen { shift: u32, len: usize },
+    Body { remaining: usize },
+}
+
+/// Parses packets from tcp stream byte by byte (packets can be split between reads).
+///
+/// Only the first 2 bytes of body are kept (enough for connack), the rest is skipped.
+#[derive(Debug, Clone, Copy)]
+pub struct PacketParser {
+    state: ParserState,
+    first_byte: u8,
+    body: [u8; 2],
+    body_len: usize,
+}
+
+impl PacketParser {
+    pub const fn new() -> Self {
+        Self {
+            state: ParserState::FixedHeader,
+            first_byte: 0,
+            body: [0; 2],
+            body_len: 0,
+        }
+    }
+
+    /// Starts parsing from the beginning of a packet (new connection).
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    fn packet(&self) -> Packet {
+        match self.first_byte >> 4 {
+            2 => Packet::ConnAck { session_present: self.body[0] & 0x01 != 0, return_code: self.body[1] },
+            13 => Packet::PingResp,
+            packet_type => Packet::Other(packet_type),
+        }
+    }
+
+    /// Returns packet when `byte` is its last byte. After an error the stream cannot be parsed any more (connection should be closed).
+    pub fn push(&mut self, byte: u8) -> Result<Option<Packet>, MqttError> {
+        match self.state {
+            ParserState::FixedHeader => {
+                self.first_byte = byte;
+                self.body_len = 0;
+                self.state = ParserState::RemainingLen { shift: 0, len: 0 };
+
+                Ok(None)
+            },
+            ParserState::RemainingLen { shift, len } => {
+                if shift > 21 {
+                    return Err(MqttError::MalformedLength);
+                }
+
+                let len = len | (((byte & 0x7f) as usize) << shift);
+
+                if byte & 0x80 != 0 {
+                    self.state = ParserState::RemainingLen { shift: shift + 7, len };
+                    return Ok(None);
+                }
+
+                if len == 0 {
+                    self.state = ParserState::FixedHeader;
+                    return Ok(Some(self.packet()));
+                }
+
+                self.state = ParserState::Body { remaining: len };
+
+                Ok(None)
+            },
+            ParserState::Body { remaining } => {
+                if let Some(slot) = self.body.get_mut(self.body_len) {
+                    *slot = byte;
+                    self.body_len += 1;
+                }
+
+                if remaining == 1 {
+                    self.state = ParserState::FixedHeader;
+                    return Ok(Some(self.packet()));
+                }
+
+                self.state = ParserState::Body { remaining: remaining - 1 };
+
+                Ok(None)
+            },
+        }
+    }
+}
+
+impl Default for PacketParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+
+    fn parse(bytes: &[u8]) -> Result<Vec<Packet>, MqttError> {
+        let mut parser = PacketParser::new();
+        let mut packets = Vec::new();
+
+        for byte in bytes {
+            packets.extend(parser.push(*byte)?);
+        }
+
+        Ok(packets)
+    }
+
+
+    #[test]
+    fn connect_with_credentials() {
+        let mut out = [0; 64];
+        let connect = Connect { client_id: "scd30", keep_alive_secs: 60, username: Some("u"), password: Some("pw") };
+
+        let len = encode_connect(&connect, &mut out).unwrap();
+
+        assert_eq!(&out[..len], b"\x10\x18\x00\x04MQTT\x04\xc2\x00\x3c\x00\x05scd30\x00\x01u\x00\x02pw");
+    }
+
+    #[test]
+    fn password_without_username_is_not_sent() {
+        let mut out = [0; 64];
+        let connect = Connect { client_id: "a", keep_alive_secs: 10, username: None, password: Some("pw") };
+
+        let len = encode_connect(&connect, &mut out).unwrap();
+
+        assert_eq!(&out[..len], b"\x10\x0d\x00\x04MQTT\x04\x02\x00\x0a\x00\x01a");
+    }
+
+    #[test]
+    fn publish_uses_multi_byte_remaining_length() {
+        let payload = [b'x'; 200];
+        let mut out = [0; 256];
+
+        let len = encode_publish("a/b", &payload, true, &mut out).unwrap();
+
+        // remaining length 205 = 0x4d + 1 * 128
+        assert_eq!(&out[..8], b"\x31\xcd\x01\x00\x03a/b");
+        assert_eq!(len, 3 + 205);
+
+        assert_eq!(encode_publish("a/b", &payload, false, &mut out[..100]), Err(MqttError::BufferTooSmall));
+    }
+
+    #[test]
+    fn parser_recognizes_responses_split_anywhere() {
+        let stream = [0x20, 0x02, 0x00, 0x00, 0x90, 0x03, 0x00, 0x01, 0x00, 0xd0, 0x00, 0x20, 0x02, 0x01, 0x05];
+
+        assert_eq!(parse(&stream), Ok(std::vec![
+            Packet::ConnAck { session_present: false, return_code: 0 },
+            Packet::Other(9),
+            Packet::PingResp,
+            Packet::ConnAck { session_present: true, return_code: 5 },
+        ]));
+    }
+
+    #[test]
+    fn parser_rejects_too_long_remaining_length() {
+        assert_eq!(parse(&[0x30, 0xff, 0xff, 0xff, 0xff, 0x01]), Err(MqttError::MalformedLength));
+    }
+}
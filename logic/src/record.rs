@@ -0,0 +1,347 @@
+/* measurment records passed to sinks and their encodings (text, csv, json lines, binary, compact for flash) */
+
+use core::fmt::Write;
+
+use heapless::{String, Vec};
+
+use crate::{
+    fixed_point::Milli,
+    flash_log::{LogRecord, LogTime},
+    measurment::SensorId,
+};
+
+
+
+/// Maximum length of encoded record (for all encodings).
+pub const MAX_ENCODED_LEN: usize = 128;
+
+
+/// Single measurment passed to all sinks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Record {
+    /// sensor which measured the record, only records of `SensorId::PRIMARY` are passed to sinks with fixed binary layout
+    /// (see `Controller`)
+    pub sensor: SensorId,
+    /// in system timer ticks
+    pub at: u64,
+    /// unix time in ms, `None` when wall clock is not synchronized (see `time`)
+    pub unix_ms: Option<u64>,
+    /// in 10^-3 ppm
+    pub co2: i32,
+    /// in m°C
+    pub temperature: i32,
+    /// in m%
+    pub humidity: i32,
+}
+
+
+/// Encoding of records, independent for each sink.
+pub trait Encoding {
+    /// Appends whole encoded record to `out`, `out` is empty and has capacity `MAX_ENCODED_LEN`.
+    fn encode(&self, record: &Record, out: &mut Vec<u8, MAX_ENCODED_LEN>);
+}
+
+
+/// Human readable text, same as printed by the controller before sinks were introduced.
+pub struct TextEncoding;
+
+impl Encoding for TextEncoding {
+    fn encode(&self, record: &Record, out: &mut Vec<u8, MAX_ENCODED_LEN>) {
+        let mut text = String::<MAX_ENCODED_LEN>::new();
+
+        // all lines fit into `text`
+        if record.sensor != SensorId::PRIMARY {
+            let _ = writeln!(text, "sensor : {}", record.sensor.0);
+        }
+        if let Some(unix_ms) = record.unix_ms {
+            let _ = writeln!(text, "time : {:.3} s (unix)", Milli(unix_ms as i64));
+        }
+        let _ = writeln!(text, "co2 : {:.1} ppm", Milli::from(record.co2));
+        let _ = writeln!(text, "temperature : {:.2} °C", Milli::from(record.temperature));
+        let _ = writeln!(text, "humidity : {:.1} %", Milli::from(record.humidity));
+
+        // cannot fail, same capacity
+        let _ = out.extend_from_slice(text.as_bytes());
+    }
+}
+
+
+/// Format of records written to usb in text mode (see `UsbSink`), selected by console command `format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordFormat {
+    /// `TextEncoding`
+    Text,
+    /// `CsvEncoding`, columns are described by `CSV_HEADER`
+    Csv,
+    /// `JsonLinesEncoding`
+    JsonLines,
+}
+
+impl RecordFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RecordFormat::Text => "text",
+            RecordFormat::Csv => "csv",
+            RecordFormat::JsonLines => "json",
+        }
+    }
+}
+
+
+/// Columns of `CsvEncoding`.
+pub const CSV_HEADER: &str = "at_ms,unix_ms,co2_ppm,temperature_c,humidity_percent,sensor";
+
+
+/// When `CSV_HEADER` is written before a csv record: before the first record and then again every `REPEAT_EVERY`
+/// records, so a host which starts reading later gets the header too.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CsvHeader {
+    /// records written since the last header, `None` before the first header
+    records: Option<u32>,
+}
+
+impl CsvHeader {
+    pub const REPEAT_EVERY: u32 = 60;
+
+    pub const fn new() -> Self {
+        Self { records: None }
+    }
+
+    /// Header is written before the next record (format changed to csv).
+    pub fn reset(&mut self) {
+        self.records = None;
+    }
+
+    /// Whether header has to be written before the record which is written now, counts the record.
+    pub fn before_record(&mut self) -> bool {
+        match self.records {
+            Some(records) if records < Self::REPEAT_EVERY => {
+                self.records = Some(records + 1);
+                false
+            },
+            _ => {
+                self.records = Some(1);
+                true
+            },
+        }
+    }
+}
+
+impl Default for CsvHeader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Single line of comma separated values (see `CSV_HEADER`), `unix_ms` is empty when wall clock is not synchronized.
+pub struct CsvEncoding {
+    /// system timer ticks per second
+    pub ticks_per_second: u64,
+}
+
+impl Encoding for CsvEncoding {
+    fn encode(&self, record: &Record, out: &mut Vec<u8, MAX_ENCODED_LEN>) {
+        let mut text = String::<MAX_ENCODED_LEN>::new();
+
+        // line fits into `text`
+        let _ = write!(text, "{},", record.at / (self.ticks_per_second / 1_000));
+        if let Some(unix_ms) = record.unix_ms {
+            let _ = write!(text, "{}", unix_ms);
+        }
+        let _ = writeln!(
+            text,
+            ",{:.1},{:.2},{:.1},{}",
+            Milli::from(record.co2),
+            Milli::from(record.temperature),
+            Milli::from(record.humidity),
+            record.sensor.0,
+        );
+
+        // cannot fail, same capacity
+        let _ = out.extend_from_slice(text.as_bytes());
+    }
+}
+
+
+/// Single line json object (same keys as `CSV_HEADER`), `unix_ms` is `null` when wall clock is not synchronized.
+pub struct JsonLinesEncoding {
+    /// system timer ticks per second
+    pub ticks_per_second: u64,
+}
+
+impl Encoding for JsonLinesEncoding {
+    fn encode(&self, record: &Record, out: &mut Vec<u8, MAX_ENCODED_LEN>) {
+        let mut text = String::<MAX_ENCODED_LEN>::new();
+
+        // line fits into `text` for values in scd30 ranges (see `longest_records_fit`)
+        let _ = write!(text, "{{\"at_ms\":{},\"unix_ms\":", record.at / (self.ticks_per_second / 1_000));
+        let _ = match record.unix_ms {
+            Some(unix_ms) => write!(text, "{}", unix_ms),
+            None => write!(text, "null"),
+        };
+        let _ = writeln!(
+            text,
+            ",\"co2_ppm\":{:.1},\"temperature_c\":{:.2},\"humidity_percent\":{:.1},\"sensor\":{}}}",
+            Milli::from(record.co2),
+            Milli::from(record.temperature),
+            Milli::from(record.humidity),
+            record.sensor.0,
+        );
+
+        // cannot fail, same capacity
+        let _ = out.extend_from_slice(text.as_bytes());
+    }
+}
+
+
+/// Fixed size little endian binary record (20 bytes) for network sinks: `at` (u64), `co2`, `temperature`, `humidity` (i32).
+/// Layout is described to the host by `metrics::METRICS`.
+pub struct BinaryEncoding;
+
+impl Encoding for BinaryEncoding {
+    fn encode(&self, record: &Record, out: &mut Vec<u8, MAX_ENCODED_LEN>) {
+        // cannot fail, 20 bytes < `MAX_ENCODED_LEN`
+        let _ = out.extend_from_slice(&record.at.to_le_bytes());
+        let _ = out.extend_from_slice(&record.co2.to_le_bytes());
+        let _ = out.extend_from_slice(&record.temperature.to_le_bytes());
+        let _ = out.extend_from_slice(&record.humidity.to_le_bytes());
+    }
+}
+
+
+/// Compact record (10 bytes) for flash, see `LogRecord`.
+/// Time is unix time when the wall clock is synchronized, otherwise time since boot, values are rounded and saturated to the field range.
+pub struct CompactEncoding {
+    /// system timer ticks per second
+    pub ticks_per_second: u64,
+}
+
+impl CompactEncoding {
+    fn scale(value: i32, divisor: i32) -> i32 {
+        // rounding half away from zero
+        let half = if value < 0 { -divisor / 2 } else { divisor / 2 };
+        (value + half) / divisor
+    }
+
+    pub fn log_record(&self, record: &Record) -> LogRecord {
+        let seconds = |value: u64| value.min(u32::MAX as u64) as u32;
+
+        LogRecord {
+            time: match record.unix_ms {
+                Some(unix_ms) => LogTime::Unix(seconds(unix_ms / 1_000)),
+                None => LogTime::SinceBoot(seconds(record.at / self.ticks_per_second)),
+            },
+            co2: Self::scale(record.co2, 1_000).clamp(0, u16::MAX as i32) as u16,
+            temperature: Self::scale(record.temperature, 10).clamp(i16::MIN as i32, i16::MAX as i32) as i16,
+            humidity: Self::scale(record.humidity, 100).clamp(0, u16::MAX as i32) as u16,
+        }
+    }
+}
+
+impl Encoding for CompactEncoding {
+    fn encode(&self, record: &Record, out: &mut Vec<u8, MAX_ENCODED_LEN>) {
+        // cannot fail, `LogRecord::ENCODED_LEN` < `MAX_ENCODED_LEN`
+        let _ = out.extend_from_slice(&self.log_record(record).encode());
+    }
+}
+
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TICKS_PER_SECOND: u64 = 16_000_000;
+
+    fn record(sensor: u8, unix_ms: Option<u64>) -> Record {
+        Record { sensor: SensorId(sensor), at: 90 * TICKS_PER_SECOND, unix_ms, co2: 812_345, temperature: 21_456, humidity: 48_750 }
+    }
+
+    fn encoded(encoding: &impl Encoding, record: &Record) -> Vec<u8, MAX_ENCODED_LEN> {
+        let mut out = Vec::new();
+        encoding.encode(record, &mut out);
+        out
+    }
+
+    #[test]
+    fn csv_record_is_one_line_of_header_columns() {
+        let encoding = CsvEncoding { ticks_per_second: TICKS_PER_SECOND };
+
+        assert_eq!(&encoded(&encoding, &record(0, Some(1_700_000_000_123)))[..], b"90000,1700000000123,812.3,21.46,48.8,0\n");
+        assert_eq!(&encoded(&encoding, &record(1, None))[..], b"90000,,812.3,21.46,48.8,1\n");
+
+        let line = encoded(&encoding, &record(0, None));
+        assert_eq!(line.iter().filter(|byte| **byte == b',').count(), CSV_HEADER.matches(',').count());
+    }
+
+    #[test]
+    fn json_record_is_one_line() {
+        let encoding = JsonLinesEncoding { ticks_per_second: TICKS_PER_SECOND };
+
+        assert_eq!(
+            &encoded(&encoding, &record(0, None))[..],
+            &b"{\"at_ms\":90000,\"unix_ms\":null,\"co2_ppm\":812.3,\"temperature_c\":21.46,\"humidity_percent\":48.8,\"sensor\":0}\n"[..],
+        );
+        assert_eq!(
+            &encoded(&encoding, &record(1, Some(5)))[..],
+            &b"{\"at_ms\":90000,\"unix_ms\":5,\"co2_ppm\":812.3,\"temperature_c\":21.46,\"humidity_percent\":48.8,\"sensor\":1}\n"[..],
+        );
+    }
+
+    #[test]
+    fn longest_records_fit() {
+        // 100 years since boot, wall clock at the end of u32 seconds, extremes of scd30 ranges
+        let at = 100 * 365 * 24 * 3_600 * TICKS_PER_SECOND;
+        let unix_ms = Some(u32::MAX as u64 * 1_000);
+        let record = Record { sensor: SensorId(u8::MAX), at, unix_ms, co2: 40_000_000, temperature: -40_000, humidity: 100_000 };
+
+        let csv = CsvEncoding { ticks_per_second: TICKS_PER_SECOND };
+        let json = JsonLinesEncoding { ticks_per_second: TICKS_PER_SECOND };
+        for line in [encoded(&csv, &record), encoded(&json, &record)] {
+            assert_eq!(line.last(), Some(&b'\n'));
+        }
+        assert!(encoded(&TextEncoding, &record).ends_with(b" %\n"));
+    }
+
+    #[test]
+    fn text_record() {
+        assert_eq!(&encoded(&TextEncoding, &record(0, None))[..], "co2 : 812.3 ppm\ntemperature : 21.46 °C\nhumidity : 48.8 %\n".as_bytes());
+        assert!(encoded(&TextEncoding, &record(1, None)).starts_with(b"sensor : 1\n"));
+    }
+
+    #[test]
+    fn binary_record_layout() {
+        let bytes = encoded(&BinaryEncoding, &record(0, None));
+
+        assert_eq!(bytes.len(), 20);
+        assert_eq!(&bytes[0..8], &(90 * TICKS_PER_SECOND).to_le_bytes());
+        assert_eq!(&bytes[8..12], &812_345i32.to_le_bytes());
+        assert_eq!(&bytes[16..20], &48_750i32.to_le_bytes());
+    }
+
+    #[test]
+    fn compact_record_rounds_values() {
+        let encoding = CompactEncoding { ticks_per_second: TICKS_PER_SECOND };
+
+        let log_record = encoding.log_record(&record(0, Some(1_700_000_000_999)));
+        assert_eq!(log_record.time, LogTime::Unix(1_700_000_000));
+        assert_eq!((log_record.co2, log_record.temperature, log_record.humidity), (812, 2146, 488));
+
+        assert_eq!(encoding.log_record(&record(0, None)).time, LogTime::SinceBoot(90));
+        assert_eq!(&encoded(&encoding, &record(0, None))[..], &encoding.log_record(&record(0, None)).encode()[..]);
+    }
+
+    #[test]
+    fn csv_header_is_repeated() {
+        let mut header = CsvHeader::new();
+
+        assert!(header.before_record());
+        assert!((1..CsvHeader::REPEAT_EVERY).all(|_| !header.before_record()));
+        assert!(header.before_record());
+        assert!(!header.before_record());
+
+        header.reset();
+        assert!(header.before_record());
+    }
+}
@@ -0,0 +1,552 @@
+use core::{cmp, marker::PhantomData, mem::MaybeUninit, ops::{Index, IndexMut}};
+
+
+
+#[derive(Debug, Clone, Copy)]
+pub enum RingBufferError {
+    Overflow,
+}
+
+impl RingBufferError {
+    pub fn result_ignore_overflow(r: Result<(), RingBufferError>) -> Result<(), RingBufferError> {
+        r.or_else(|e| match e {
+            RingBufferError::Overflow => Ok(()),
+            _ => Err(e), // TODO: fix `unreachable_patterns` warning
+        })
+    }
+}
+
+
+// `Sealed` is in private module because without mod `Sealed` would not be `pub` and compiler would emit `private_bounds` warning on `OnOverflow`.
+// This way `Sealed` can be `pub` but it is still hidden from users because it is in non `pub` module.
+mod private {
+    pub trait Sealed {}
+}
+
+pub trait OnOverflow: private::Sealed {}
+
+pub struct Overwrite;
+pub struct Ignore;
+
+impl private::Sealed for Ignore {}
+impl private::Sealed for Overwrite {}
+impl OnOverflow for Ignore {}
+impl OnOverflow for Overwrite {}
+
+pub struct RingBuffer<T, const N: usize, OVERFLOW: OnOverflow> {
+    buf: [MaybeUninit<T>; N],
+    pos: usize,
+    len: usize,
+    phantom: PhantomData<OVERFLOW>,
+}
+
+impl<T, const N: usize, OVERFLOW: OnOverflow> RingBuffer<T, N, OVERFLOW> {
+    const ELEM_UNINIT: MaybeUninit<T> = MaybeUninit::<T>::uninit();
+
+    pub const fn new() -> Self {
+        Self {
+            buf: [Self::ELEM_UNINIT; N],
+            pos: 0,
+            len: 0,
+            phantom: PhantomData,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Removes (and drops) all elements.
+    pub fn clear(&mut self) {
+        self.discard_front(self.len);
+        self.pos = 0;
+    }
+
+    pub fn pop_front(&mut self) -> Option<T> {
+        if self.len == 0 {
+            None
+        } else {
+            // SAFETY
+            // When `len` is non zero the element at `pos` is inside intialized range.
+            // After `pop_front`, the value at `pos` is no longer part of initialized range so no duplication happens.
+            let v = unsafe { self.buf[self.pos].assume_init_read() };
+            self.pos = (self.pos + 1) % N;
+            self.len -= 1;
+
+            Some(v)
+        }
+    }
+
+    pub fn pop_back(&mut self) -> Option<T> {
+        if self.len == 0 {
+            None
+        } else {
+            // SAFETY
+            // When `len` is non zero the element at `pos` is insi intialized range.
+            // After `pop_front`, the value at `pos` is no longer part of initialized range so no duplication happens.
+            let v = unsafe { self.buf[(self.pos + self.len - 1) % N].assume_init_read() };
+            self.len -= 1;
+
+            Some(v)
+        }
+    }
+
+    /// Removes (and drops) up to `n` elements from the front, returns number of removed elements.
+    pub fn discard_front(&mut self, n: usize) -> usize {
+        let n = cmp::min(n, self.len);
+        (0..n).for_each(|_| { self.pop_front(); });
+        n
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        if index < self.len {
+            // SAFETY value is in initialized range
+            Some(unsafe { self.buf[(self.pos + index) % N].assume_init_ref() })
+        } else {
+            None
+        }
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
+        if index < self.len {
+            // SAFETY value is in initialized range
+            Some(unsafe { self.buf[(self.pos + index) % N].assume_init_mut() })
+        } else {
+            None
+        }
+    }
+
+    pub fn front(&self) -> Option<&T> {
+        self.get(0)
+    }
+
+    pub fn front_mut(&mut self) -> Option<&mut T> {
+        self.get_mut(0)
+    }
+
+    pub fn back(&self) -> Option<&T> {
+        if self.len != 0 {
+            // SAFETY value is in initialized range
+            Some(unsafe { self.buf[(self.pos + self.len - 1) % N].assume_init_ref() })
+        } else {
+            None
+        }
+    }
+
+    pub fn back_mut(&mut self) -> Option<&mut T> {
+        if self.len != 0 {
+            // SAFETY value is in initialized range
+            Some(unsafe { self.buf[(self.pos + self.len - 1) % N].assume_init_mut() })
+        } else {
+            None
+        }
+    }
+
+    /// Elements from the front up to the end of the underlying array, elements wrapped around to its start are not included.
+    pub fn front_slice(&self) -> &[T] {
+        let len = cmp::min(self.len, N - self.pos);
+
+        // SAFETY elements `pos..(pos + len)` are in initialized range
+        unsafe { core::slice::from_raw_parts(self.buf.as_ptr().add(self.pos) as *const T, len) }
+    }
+
+    /// Uninitialized range after the last element (`start..end`, or `start..N` and `0..end` when `start >= end`), buffer must not be full.
+    fn extend_prepare_empty_range(&mut self) -> (usize, usize) {
+        if self.len == 0 {
+            self.pos = 0;
+            (0, N)
+        } else {
+            (
+                (self.pos + self.len) % N,
+                if self.pos == 0 { N } else { self.pos }
+            )
+        }
+    }
+}
+
+impl<T: Copy, const N: usize, OVERFLOW: OnOverflow> RingBuffer<T, N, OVERFLOW> {
+    /// Removes up to `n` elements from the front without reading them (`Copy` values have no drop), returns number of removed elements.
+    pub fn consume_front(&mut self, n: usize) -> usize {
+        let n = cmp::min(n, self.len);
+        if n != 0 {
+            self.pos = (self.pos + n) % N;
+            self.len -= n;
+        }
+        n
+    }
+
+    fn extend_from_slice_continous<'a>(&mut self, start: usize, end: usize, s: &'a [T]) -> &'a [T] {
+        let len = end - start;
+
+        if s.len() < len {
+            MaybeUninit::copy_from_slice(&mut self.buf[start..(start + s.len())], s);
+            self.len += s.len();
+            &[]
+        } else {
+            MaybeUninit::copy_from_slice(&mut self.buf[start..end], &s[..len]);
+            self.len += len;
+            &s[len..]
+        }
+    }
+}
+
+impl<T, const N: usize, OVERFLOW: OnOverflow> Drop for RingBuffer<T, N, OVERFLOW> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+impl<T, const N: usize, OVERFLOW: OnOverflow> Index<usize> for RingBuffer<T, N, OVERFLOW> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &Self::Output {
+        self.get(index).expect("RingBuffer::index(...) - out of bounds access")
+    }
+}
+
+impl<T, const N: usize, OVERFLOW: OnOverflow> IndexMut<usize> for RingBuffer<T, N, OVERFLOW> {
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        self.get_mut(index).expect("RingBuffer::index_mut(...) - out of bounds access")
+    }
+}
+
+impl<T, const N: usize> RingBuffer<T, N, Ignore> {
+    pub fn push_back(&mut self, v: T) -> Result<(), RingBufferError> {
+        if self.len == N {
+            return Err(RingBufferError::Overflow);
+        }
+
+        // value is outside if the initialized range (`len != N`), so no leak happens
+        self.buf[(self.pos + self.len) % N] = MaybeUninit::new(v);
+        self.len += 1;
+
+        Ok(())
+    }
+
+    pub fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) -> Result<(), RingBufferError> {
+        let mut iter = iter.into_iter();
+
+        if self.len == N {
+            return match iter.next() {
+                Some(_) => Err(RingBufferError::Overflow),
+                None => Ok(())
+            };
+        }
+
+        let (extend_start, extend_end) = self.extend_prepare_empty_range();
+
+        if extend_start < extend_end {
+            self.len += iter.by_ref().zip(self.buf[extend_start..extend_end].iter_mut()).map(|(v, buf_elem)| { *buf_elem = MaybeUninit::new(v); }).count();
+        } else {
+            self.len += iter.by_ref().zip(self.buf[extend_start..].iter_mut()).map(|(v, buf_elem)| { *buf_elem = MaybeUninit::new(v); }).count();
+            self.len += iter.by_ref().zip(self.buf[..extend_end].iter_mut()).map(|(v, buf_elem)| { *buf_elem = MaybeUninit::new(v); }).count();
+        }
+
+        match iter.next() {
+            Some(_) => Err(RingBufferError::Overflow),
+            None => Ok(())
+        }
+    }
+}
+
+impl<'a, T: Copy + 'a, const N: usize> RingBuffer<T, N, Ignore> {
+    pub fn extend_from_refs<I: IntoIterator<Item = &'a T>>(&mut self, iter: I) -> Result<(), RingBufferError> {
+        self.extend(iter.into_iter().copied())
+    }
+}
+
+impl<T: Copy, const N: usize> RingBuffer<T, N, Ignore> {
+    fn extend_len_into_result(len: usize) -> Result<(), RingBufferError> {
+        if len == 0 {
+            Ok(())
+        } else {
+            Err(RingBufferError::Overflow)
+        }
+    }
+
+    pub fn extend_from_slice(&mut self, s: &[T]) -> Result<(), RingBufferError> {
+        if s.len() == 0 {
+            return Ok(());
+        }
+
+        if self.len == N {
+            return Err(RingBufferError::Overflow);
+        }
+
+        let (extend_start, extend_end) = self.extend_prepare_empty_range();
+
+        if extend_start < extend_end {
+            Self::extend_len_into_result(self.extend_from_slice_continous(extend_start, extend_end, s).len())
+        } else {
+            let s = self.extend_from_slice_continous(extend_start, N, s);
+            if s.len() == 0 {
+                Ok(())
+            } else {
+                Self::extend_len_into_result(self.extend_from_slice_continous(0, extend_end, s).len())
+            }
+        }
+    }
+}
+
+impl<T, const N: usize> RingBuffer<T, N, Overwrite> {
+    pub fn push_back(&mut self, v: T) {
+        if self.len == N {
+            // SAFETY `len` == N, so all values are initialized, which means that also value at `pos` is initialized
+            unsafe { self.buf[self.pos].assume_init_drop() };
+            // value at `pos` is unititialized by line above, so no leak happens
+            self.buf[self.pos] = MaybeUninit::new(v);
+
+            self.pos = (self.pos + 1) % N;
+        } else {
+            // value outside initilized range is acessed, so no leak happens
+            self.buf[(self.pos + self.len) % N] = MaybeUninit::new(v);
+            self.len += 1;
+        }
+    }
+
+    /// Appends all values, the oldest values are dropped when the buffer is full (also values appended by this call).
+    pub fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        iter.into_iter().for_each(|v| self.push_back(v));
+    }
+}
+
+impl<'a, T: Copy + 'a, const N: usize> RingBuffer<T, N, Overwrite> {
+    pub fn extend_from_refs<I: IntoIterator<Item = &'a T>>(&mut self, iter: I) {
+        self.extend(iter.into_iter().copied())
+    }
+}
+
+impl<T: Copy, const N: usize> RingBuffer<T, N, Overwrite> {
+    /// Same as `extend`, but only values which stay in the buffer are copied (at most two continuous copies).
+    pub fn extend_from_slice(&mut self, s: &[T]) {
+        // only the last `N` values can stay
+        let s = &s[s.len().saturating_sub(N)..];
+
+        if s.is_empty() {
+            return;
+        }
+
+        // space for the whole `s`, `Copy` values have no drop
+        self.discard_front((self.len + s.len()).saturating_sub(N));
+
+        let (extend_start, extend_end) = self.extend_prepare_empty_range();
+
+        if extend_start < extend_end {
+            self.extend_from_slice_continous(extend_start, extend_end, s);
+        } else {
+            let s = self.extend_from_slice_continous(extend_start, N, s);
+            self.extend_from_slice_continous(0, extend_end, s);
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, rc::Rc};
+
+    use super::*;
+
+
+    #[test]
+    fn push_pop_in_order() {
+        let mut buffer = RingBuffer::<u8, 4, Ignore>::new();
+
+        buffer.push_back(1).unwrap();
+        buffer.push_back(2).unwrap();
+
+        assert_eq!(buffer.pop_front(), Some(1));
+        assert_eq!(buffer.pop_front(), Some(2));
+        assert_eq!(buffer.pop_front(), None);
+    }
+
+    #[test]
+    fn ignore_rejects_when_full() {
+        let mut buffer = RingBuffer::<u8, 2, Ignore>::new();
+
+        buffer.push_back(1).unwrap();
+        buffer.push_back(2).unwrap();
+
+        assert!(matches!(buffer.push_back(3), Err(RingBufferError::Overflow)));
+        assert_eq!(buffer.len(), 2);
+        assert_eq!(buffer[0], 1);
+    }
+
+    #[test]
+    fn extend_from_slice_wraps_around() {
+        let mut buffer = RingBuffer::<u8, 4, Ignore>::new();
+
+        buffer.extend_from_slice(&[1, 2, 3]).unwrap();
+        buffer.discard_front(2);
+        buffer.extend_from_slice(&[4, 5, 6]).unwrap();
+
+        assert_eq!(buffer.len(), 4);
+        assert_eq!([buffer[0], buffer[1], buffer[2], buffer[3]], [3, 4, 5, 6]);
+
+        assert!(matches!(buffer.extend_from_slice(&[7]), Err(RingBufferError::Overflow)));
+    }
+
+    #[test]
+    fn extend_from_slice_stores_prefix_on_overflow() {
+        let mut buffer = RingBuffer::<u8, 3, Ignore>::new();
+
+        assert!(matches!(buffer.extend_from_slice(&[1, 2, 3, 4]), Err(RingBufferError::Overflow)));
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.back(), Some(&3));
+    }
+
+    #[test]
+    fn front_slice_ends_at_wrap() {
+        let mut buffer = RingBuffer::<u8, 4, Ignore>::new();
+
+        buffer.extend_from_slice(&[1, 2, 3]).unwrap();
+        buffer.consume_front(2);
+        buffer.extend_from_slice(&[4, 5]).unwrap();
+
+        assert_eq!(buffer.front_slice(), &[3, 4]);
+        assert_eq!(buffer.consume_front(2), 2);
+        assert_eq!(buffer.front_slice(), &[5]);
+        assert_eq!(buffer.consume_front(3), 1);
+        assert_eq!(buffer.front_slice(), &[]);
+    }
+
+    #[test]
+    fn overwrite_drops_oldest() {
+        let mut buffer = RingBuffer::<u32, 3, Overwrite>::new();
+
+        // more than two rounds, position has to wrap
+        for i in 0..10 {
+            buffer.push_back(i);
+        }
+
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.front(), Some(&7));
+        assert_eq!(buffer.back(), Some(&9));
+        assert_eq!(buffer.pop_front(), Some(7));
+    }
+
+    #[test]
+    fn overwrite_extend_from_slice_keeps_newest() {
+        let mut buffer = RingBuffer::<u8, 4, Overwrite>::new();
+
+        buffer.extend_from_slice(&[1, 2, 3]);
+        buffer.extend_from_slice(&[4, 5]);
+        assert_eq!([buffer[0], buffer[1], buffer[2], buffer[3]], [2, 3, 4, 5]);
+
+        // wraps around
+        buffer.extend_from_slice(&[6, 7, 8]);
+        assert_eq!([buffer[0], buffer[1], buffer[2], buffer[3]], [5, 6, 7, 8]);
+
+        // longer than capacity
+        buffer.extend_from_slice(&[9, 10, 11, 12, 13, 14]);
+        assert_eq!(buffer.len(), 4);
+        assert_eq!([buffer[0], buffer[1], buffer[2], buffer[3]], [11, 12, 13, 14]);
+    }
+
+    #[test]
+    fn overwrite_extend_same_as_push_back() {
+        let mut extended = RingBuffer::<u32, 5, Overwrite>::new();
+        let mut pushed = RingBuffer::<u32, 5, Overwrite>::new();
+
+        for batch in [0..3, 3..4, 4..11, 11..11, 11..15] {
+            extended.extend_from_refs(batch.clone().collect::<Vec<_>>().iter());
+            batch.for_each(|v| pushed.push_back(v));
+
+            assert_eq!(extended.len(), pushed.len());
+            assert!((0..pushed.len()).all(|i| extended[i] == pushed[i]));
+        }
+    }
+
+    /// Counts its drops.
+    struct Counted(Rc<Cell<usize>>);
+
+    impl Drop for Counted {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn overwrite_extend_drops_overwritten() {
+        let drops = Rc::new(Cell::new(0));
+        let mut buffer = RingBuffer::<Counted, 3, Overwrite>::new();
+
+        buffer.extend((0..5).map(|_| Counted(drops.clone())));
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(drops.get(), 2);
+
+        buffer.discard_front(3);
+        assert_eq!(drops.get(), 5);
+    }
+
+    #[test]
+    fn drop_drops_elements() {
+        let drops = Rc::new(Cell::new(0));
+
+        {
+            let mut buffer = RingBuffer::<Counted, 3, Ignore>::new();
+            buffer.extend((0..3).map(|_| Counted(drops.clone()))).unwrap();
+            // wrapped, initialized range is `2..3` and `0..1`
+            buffer.discard_front(2);
+            buffer.push_back(Counted(drops.clone())).unwrap();
+            assert_eq!(drops.get(), 2);
+        }
+
+        assert_eq!(drops.get(), 4);
+    }
+
+    #[test]
+    fn clear() {
+        let drops = Rc::new(Cell::new(0));
+        let mut buffer = RingBuffer::<Counted, 4, Overwrite>::new();
+
+        buffer.extend((0..6).map(|_| Counted(drops.clone())));
+        assert!(buffer.is_full());
+
+        buffer.clear();
+        assert_eq!(drops.get(), 6);
+        assert!(buffer.is_empty());
+        assert!(buffer.front().is_none());
+
+        buffer.push_back(Counted(drops.clone()));
+        assert_eq!(buffer.len(), 1);
+        assert_eq!(buffer.capacity(), 4);
+    }
+
+    #[test]
+    fn index_math_across_wrap() {
+        let mut buffer = RingBuffer::<u8, 4, Ignore>::new();
+
+        // every start position
+        for start in 0..4u8 {
+            buffer.clear();
+            buffer.extend(0..start).unwrap();
+            buffer.discard_front(start as usize);
+            buffer.extend([10, 11, 12, 13]).unwrap();
+
+            assert!(buffer.is_full());
+            assert_eq!((0..4).map(|i| buffer[i]).collect::<Vec<_>>(), [10, 11, 12, 13]);
+            assert_eq!(buffer.get(4), None);
+            assert_eq!(buffer.back(), Some(&13));
+
+            *buffer.get_mut(3).unwrap() = 23;
+            *buffer.front_mut().unwrap() = 20;
+            assert_eq!(buffer.pop_back(), Some(23));
+            assert_eq!(buffer.pop_front(), Some(20));
+            assert_eq!([buffer[0], buffer[1]], [11, 12]);
+        }
+    }
+}
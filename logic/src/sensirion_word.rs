@@ -0,0 +1,93 @@
+/* word protocol shared by sensirion sensors (scd30, sht3x, ...): 16 bit commands and arguments, data are 16 bit words each followed by crc-8 */
+
+use crate::sensirion_crc::{check_crc, compute_crc};
+
+
+
+/// bytes of one word on the bus (msb, lsb, crc)
+pub const WORD_LEN: u8 = 3;
+
+
+/// Crc of received word does not match its bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CRCCheckFailed;
+
+
+/// Word with its crc, as sent on the bus.
+pub fn encode_word(word: u16) -> [u8; WORD_LEN as usize] {
+    let [msb, lsb] = word.to_be_bytes();
+    [msb, lsb, compute_crc(msb, lsb)]
+}
+
+pub fn decode_word([msb, lsb, crc]: [u8; WORD_LEN as usize]) -> Result<u16, CRCCheckFailed> {
+    if check_crc(msb, lsb, crc) {
+        Ok(u16::from_be_bytes([msb, lsb]))
+    } else {
+        Err(CRCCheckFailed)
+    }
+}
+
+
+/// Bytes of command write: command optionally followed by one argument word (command itself has no crc, argument has).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandBytes {
+    bytes: [u8; 5],
+    len: usize,
+}
+
+impl CommandBytes {
+    pub fn new(command: u16, argument: Option<u16>) -> Self {
+        let [c1, c0] = command.to_be_bytes();
+
+        match argument {
+            Some(argument) => {
+                let [a1, a0, crc] = encode_word(argument);
+                Self { bytes: [c1, c0, a1, a0, crc], len: 5 }
+            },
+            None => Self { bytes: [c1, c0, 0, 0, 0], len: 2 },
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    #[test]
+    fn word_round_trip() {
+        for word in [0x0000, 0x0002, 0x03e8, 0xbeef, 0xffff] {
+            let encoded = encode_word(word);
+            assert_eq!(encoded[..2], word.to_be_bytes());
+            assert_eq!(decode_word(encoded), Ok(word));
+        }
+
+        // datasheet example
+        assert_eq!(encode_word(0xbeef), [0xbe, 0xef, 0x92]);
+    }
+
+    #[test]
+    fn corrupted_word_is_rejected() {
+        let [msb, lsb, crc] = encode_word(0x01f4);
+
+        assert_eq!(decode_word([msb ^ 0x80, lsb, crc]), Err(CRCCheckFailed));
+        assert_eq!(decode_word([msb, lsb ^ 0x01, crc]), Err(CRCCheckFailed));
+        assert_eq!(decode_word([msb, lsb, crc ^ 0x10]), Err(CRCCheckFailed));
+    }
+
+    #[test]
+    fn scd30_commands() {
+        // trigger continuous measurment with ambient pressure 0 (scd30 interface description)
+        assert_eq!(CommandBytes::new(0x0010, Some(0x0000)).as_bytes(), [0x00, 0x10, 0x00, 0x00, 0x81]);
+        // set measurment interval 2 s
+        assert_eq!(CommandBytes::new(0x4600, Some(0x0002)).as_bytes(), [0x46, 0x00, 0x00, 0x02, 0xe3]);
+        // get data ready status
+        assert_eq!(CommandBytes::new(0x0202, None).as_bytes(), [0x02, 0x02]);
+    }
+}
@@ -0,0 +1,287 @@
+/* measurments of each sensor and values derived from them (despiked, moving average, co2 trend) */
+
+use crate::{
+    filter::{self, Median5},
+    measurment::{Measurment, SensorId},
+    ring_buffer::{Overwrite, RingBuffer},
+    trend::{self, Trend},
+};
+
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct TrendConfig {
+    /// co2 slope is computed from measurments of this time (in seconds)
+    pub window_secs: u32,
+    /// slopes with smaller absolute value are `Trend::Stable` (in 10^-3 ppm per minute)
+    pub stable_threshold: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Co2Trend {
+    /// in 10^-3 ppm per minute
+    pub slope: i32,
+    pub trend: Trend,
+}
+
+
+struct TimedMeasurment {
+    measurment: Measurment,
+    /// after median filter (same as `measurment` when despiking is off)
+    despiked: Measurment,
+    /// in ticks
+    at: u64,
+}
+
+
+/// Last `N` measurments of one sensor and values derived from them, times are in ticks of a timer with
+/// `ticks_per_second` (system timer in the firmware).
+pub struct SensorHistory<const N: usize> {
+    measurments: RingBuffer<TimedMeasurment, N, Overwrite>,
+    /// co2, temperature and humidity
+    despike: [Median5; 3],
+    filtered: Option<Measurment>,
+    trend: Option<Co2Trend>,
+    ticks_per_second: u64,
+}
+
+impl<const N: usize> SensorHistory<N> {
+    pub const fn new(ticks_per_second: u64) -> Self {
+        Self {
+            measurments: RingBuffer::new(),
+            despike: [Median5::new(); 3],
+            filtered: None,
+            trend: None,
+            ticks_per_second,
+        }
+    }
+
+    /// Stores `measurment` ready at `at` (in ticks), updates filtered value (median of 5 when `despike` followed by
+    /// average of last `filter_window` measurments) and co2 trend.
+    pub fn push(&mut self, measurment: Measurment, at: u64, despike: bool, filter_window: usize, trend_config: &TrendConfig) {
+        let despiked = if despike {
+            let [co2, temperature, humidity] = &mut self.despike;
+            Measurment { co2: co2.push(measurment.co2), temperature: temperature.push(measurment.temperature), humidity: humidity.push(measurment.humidity) }
+        } else {
+            measurment
+        };
+
+        self.measurments.push_back(TimedMeasurment { measurment, despiked, at });
+        self.filtered = self.moving_average(filter_window);
+        self.trend = self.co2_trend_at(trend_config, at);
+    }
+
+    /// Sensor (re)started measuring, median filter starts again from its next measurment (stored measurments, moving
+    /// average and trend are kept).
+    pub fn restart(&mut self) {
+        self.despike.iter_mut().for_each(Median5::reset);
+    }
+
+    /// Last measurment after filtering, `None` before the first measurment.
+    pub fn filtered(&self) -> Option<Measurment> {
+        self.filtered
+    }
+
+    /// Co2 trend after the last measurment, `None` until there are `trend::MIN_POINTS` measurments in the
+    /// trend window.
+    pub fn trend(&self) -> Option<Co2Trend> {
+        self.trend
+    }
+
+    /// Last (raw) measurment and ticks when it was ready.
+    pub fn last(&self) -> Option<(u64, Measurment)> {
+        self.measurments.back().map(|measurment| (measurment.at, measurment.measurment))
+    }
+
+    /// Stored (raw) measurments with ticks of each, oldest first.
+    pub fn measurments(&self) -> impl DoubleEndedIterator<Item = (u64, Measurment)> + ExactSizeIterator + '_ {
+        (0..self.measurments.len()).map(|i| (self.measurments[i].at, self.measurments[i].measurment))
+    }
+
+    /// Average of last `filter_window` (despiked) measurments.
+    fn moving_average(&self, filter_window: usize) -> Option<Measurment> {
+        let len = self.measurments.len();
+        let window = || ((len - filter_window.min(len))..len).map(|i| self.measurments[i].despiked);
+
+        Some(Measurment {
+            co2: filter::mean(window().map(|measurment| measurment.co2))?,
+            temperature: filter::mean(window().map(|measurment| measurment.temperature))?,
+            humidity: filter::mean(window().map(|measurment| measurment.humidity))?,
+        })
+    }
+
+    /// Least squares slope of (despiked) co2 of measurments in trend window ending at `at`.
+    fn co2_trend_at(&self, trend_config: &TrendConfig, at: u64) -> Option<Co2Trend> {
+        let window = trend_config.window_secs as u64 * self.ticks_per_second;
+        let ticks_per_ms = self.ticks_per_second / 1_000;
+
+        let points = (0..self.measurments.len())
+            .rev()
+            .map(|i| &self.measurments[i])
+            .take_while(|measurment| at.saturating_sub(measurment.at) <= window)
+            .map(|measurment| (measurment.at / ticks_per_ms, measurment.despiked.co2));
+
+        let slope = trend::slope_per_minute(points)?;
+
+        Some(Co2Trend { slope, trend: Trend::classify(slope, trend_config.stable_threshold) })
+    }
+}
+
+
+/// Independent `SensorHistory` of each of `SENSORS` sensors (at least the primary one), indexed by `SensorId`.
+pub struct SensorHistories<const N: usize, const SENSORS: usize> {
+    histories: [SensorHistory<N>; SENSORS],
+}
+
+impl<const N: usize, const SENSORS: usize> SensorHistories<N, SENSORS> {
+    const HAS_PRIMARY: () = assert!(SENSORS > SensorId::PRIMARY.0 as usize, "primary sensor is always tracked");
+
+
+    pub fn new(ticks_per_second: u64) -> Self {
+        let () = Self::HAS_PRIMARY;
+
+        Self { histories: core::array::from_fn(|_| SensorHistory::new(ticks_per_second)) }
+    }
+
+    pub fn primary(&self) -> &SensorHistory<N> {
+        &self.histories[SensorId::PRIMARY.0 as usize]
+    }
+
+    /// `None` for unknown sensor.
+    pub fn get(&self, sensor: SensorId) -> Option<&SensorHistory<N>> {
+        self.histories.get(sensor.0 as usize)
+    }
+
+    /// Restarts history of `sensor` (see `SensorHistory::restart`), `false` for unknown sensor.
+    pub fn restart(&mut self, sensor: SensorId) -> bool {
+        self.histories.get_mut(sensor.0 as usize).map(SensorHistory::restart).is_some()
+    }
+
+    /// Pushes `measurment` to history of `sensor` (see `SensorHistory::push`), `false` for unknown sensor.
+    pub fn push(&mut self, sensor: SensorId, measurment: Measurment, at: u64, despike: bool, filter_window: usize, trend_config: &TrendConfig) -> bool {
+        match self.histories.get_mut(sensor.0 as usize) {
+            Some(history) => {
+                history.push(measurment, at, despike, filter_window, trend_config);
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    const TICKS_PER_SECOND: u64 = 1_000_000;
+    const TREND_CONFIG: TrendConfig = TrendConfig { window_secs: 600, stable_threshold: 5_000 };
+
+    fn co2(co2: i32) -> Measurment {
+        Measurment { co2, temperature: 21_000, humidity: 40_000 }
+    }
+
+    #[test]
+    fn moving_average_of_window() {
+        let mut history = SensorHistory::<8>::new(TICKS_PER_SECOND);
+        assert_eq!(history.filtered(), None);
+
+        for (i, value) in [400_000, 500_000, 600_000, 700_000].into_iter().enumerate() {
+            history.push(co2(value), i as u64 * TICKS_PER_SECOND, false, 2, &TREND_CONFIG);
+        }
+
+        assert_eq!(history.filtered().map(|filtered| filtered.co2), Some(650_000));
+        assert_eq!(history.last(), Some((3 * TICKS_PER_SECOND, co2(700_000))));
+        assert_eq!(history.measurments().len(), 4);
+    }
+
+    #[test]
+    fn despike_removes_spike_but_keeps_raw() {
+        let mut history = SensorHistory::<8>::new(TICKS_PER_SECOND);
+
+        for (i, value) in [400_000, 410_000, 5_000_000, 420_000].into_iter().enumerate() {
+            history.push(co2(value), i as u64 * TICKS_PER_SECOND, true, 1, &TREND_CONFIG);
+        }
+
+        // median of the 4 values is average of 410 and 420 ppm
+        assert_eq!(history.filtered().map(|filtered| filtered.co2), Some(415_000));
+        assert_eq!(history.measurments().map(|(_, measurment)| measurment.co2).nth(2), Some(5_000_000));
+    }
+
+    #[test]
+    fn restart_resets_despike() {
+        let mut history = SensorHistory::<8>::new(TICKS_PER_SECOND);
+
+        for (i, value) in [400_000, 410_000, 420_000].into_iter().enumerate() {
+            history.push(co2(value), i as u64 * TICKS_PER_SECOND, true, 1, &TREND_CONFIG);
+        }
+
+        history.restart();
+        history.push(co2(1_000_000), 3 * TICKS_PER_SECOND, true, 1, &TREND_CONFIG);
+
+        // median of the only value after restart
+        assert_eq!(history.filtered().map(|filtered| filtered.co2), Some(1_000_000));
+        assert_eq!(history.measurments().len(), 4);
+    }
+
+    #[test]
+    fn trend_of_rising_co2() {
+        let mut history = SensorHistory::<16>::new(TICKS_PER_SECOND);
+
+        // +10 ppm per minute
+        for i in 0..2 {
+            history.push(co2(400_000 + i * 10_000), i as u64 * 60 * TICKS_PER_SECOND, false, 1, &TREND_CONFIG);
+            assert_eq!(history.trend(), None);
+        }
+
+        for i in 2..5 {
+            history.push(co2(400_000 + i * 10_000), i as u64 * 60 * TICKS_PER_SECOND, false, 1, &TREND_CONFIG);
+        }
+
+        assert_eq!(history.trend(), Some(Co2Trend { slope: 10_000, trend: Trend::Rising }));
+    }
+
+    #[test]
+    fn trend_ignores_measurments_outside_of_window() {
+        let mut history = SensorHistory::<16>::new(TICKS_PER_SECOND);
+
+        history.push(co2(2_000_000), 0, false, 1, &TREND_CONFIG);
+        for i in 0..3 {
+            let at = (TREND_CONFIG.window_secs as u64 + 60 + i * 60) * TICKS_PER_SECOND;
+            history.push(co2(400_000), at, false, 1, &TREND_CONFIG);
+        }
+
+        assert_eq!(history.trend(), Some(Co2Trend { slope: 0, trend: Trend::Stable }));
+    }
+
+    #[test]
+    fn sensors_are_independent() {
+        let mut histories = SensorHistories::<8, 2>::new(TICKS_PER_SECOND);
+
+        assert!(histories.push(SensorId::PRIMARY, co2(400_000), 0, false, 1, &TREND_CONFIG));
+        assert!(histories.push(SensorId(1), co2(900_000), TICKS_PER_SECOND, false, 1, &TREND_CONFIG));
+        assert!(histories.push(SensorId(1), co2(1_000_000), 2 * TICKS_PER_SECOND, false, 1, &TREND_CONFIG));
+
+        let primary = histories.primary();
+        assert_eq!(primary.last(), Some((0, co2(400_000))));
+        assert_eq!(primary.measurments().len(), 1);
+        assert_eq!(primary.trend(), None);
+
+        let secondary = histories.get(SensorId(1)).unwrap();
+        assert_eq!(secondary.filtered(), Some(co2(1_000_000)));
+        assert_eq!(secondary.measurments().len(), 2);
+        assert_eq!(secondary.trend(), None);
+    }
+
+    #[test]
+    fn unknown_sensor_is_rejected() {
+        let mut histories = SensorHistories::<8, 2>::new(TICKS_PER_SECOND);
+
+        assert!(!histories.push(SensorId(2), co2(400_000), 0, false, 1, &TREND_CONFIG));
+        assert!(!histories.restart(SensorId(2)));
+        assert!(histories.get(SensorId(2)).is_none());
+        assert_eq!(histories.primary().last(), None);
+    }
+}
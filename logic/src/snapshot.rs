@@ -0,0 +1,152 @@
+/* compact binary export of stored measurments (console command `dump`), encoded record by record so it can be streamed
+
+   header - magic "SCDS", record count (u32), system timer ticks per second (u32), epoch offset (u64)
+   record - time in system timer ticks (u64), co2 (i32), temperature (i32), humidity (i32)
+   trailer - crc32 (ieee) of header and records
+
+   all fields little endian, values in the units of `Measurment` (10^-3 ppm, m°C, 10^-3 %) */
+
+
+
+pub const MAGIC: [u8; 4] = *b"SCDS";
+
+pub const HEADER_LEN: usize = 20;
+pub const RECORD_LEN: usize = 20;
+pub const TRAILER_LEN: usize = 4;
+
+
+/// Crc32 (ieee 802.3, reflected, as used by zlib) computed bit by bit, so no table is kept in memory.
+#[derive(Debug, Clone, Copy)]
+pub struct Crc32(u32);
+
+impl Crc32 {
+    const POLYNOMIAL: u32 = 0xedb8_8320;
+
+
+    pub const fn new() -> Self {
+        Self(0xffff_ffff)
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u32;
+            for _ in 0..8 {
+                self.0 = if self.0 & 1 != 0 { (self.0 >> 1) ^ Self::POLYNOMIAL } else { self.0 >> 1 };
+            }
+        }
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotHeader {
+    pub count: u32,
+    pub ticks_per_second: u32,
+    /// unix time in ms of system timer tick 0 (record time in unix ms is `epoch_offset + at * 1000 / ticks_per_second`),
+    /// zero when the wall clock is not synchronized
+    pub epoch_offset: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SnapshotRecord {
+    pub at: u64,
+    pub co2: i32,
+    pub temperature: i32,
+    pub humidity: i32,
+}
+
+
+/// Encodes header, records and trailer in this order, keeping crc of everything encoded so far.
+pub struct SnapshotEncoder {
+    crc: Crc32,
+}
+
+impl SnapshotEncoder {
+    pub const fn new() -> Self {
+        Self { crc: Crc32::new() }
+    }
+
+    /// Total length of snapshot with `count` records.
+    pub const fn encoded_len(count: u32) -> usize {
+        HEADER_LEN + count as usize * RECORD_LEN + TRAILER_LEN
+    }
+
+    pub fn header(&mut self, header: &SnapshotHeader) -> [u8; HEADER_LEN] {
+        let mut out = [0; HEADER_LEN];
+        out[0..4].copy_from_slice(&MAGIC);
+        out[4..8].copy_from_slice(&header.count.to_le_bytes());
+        out[8..12].copy_from_slice(&header.ticks_per_second.to_le_bytes());
+        out[12..20].copy_from_slice(&header.epoch_offset.to_le_bytes());
+
+        self.crc.update(&out);
+        out
+    }
+
+    pub fn record(&mut self, record: &SnapshotRecord) -> [u8; RECORD_LEN] {
+        let mut out = [0; RECORD_LEN];
+        out[0..8].copy_from_slice(&record.at.to_le_bytes());
+        out[8..12].copy_from_slice(&record.co2.to_le_bytes());
+        out[12..16].copy_from_slice(&record.temperature.to_le_bytes());
+        out[16..20].copy_from_slice(&record.humidity.to_le_bytes());
+
+        self.crc.update(&out);
+        out
+    }
+
+    pub fn trailer(&self) -> [u8; TRAILER_LEN] {
+        self.crc.finish().to_le_bytes()
+    }
+}
+
+impl Default for SnapshotEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    #[test]
+    fn crc32_check_value() {
+        let mut crc = Crc32::new();
+        crc.update(b"12345");
+        crc.update(b"6789");
+
+        assert_eq!(crc.finish(), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn snapshot_layout() {
+        let mut encoder = SnapshotEncoder::new();
+        let mut snapshot = Vec::new();
+
+        snapshot.extend(encoder.header(&SnapshotHeader { count: 1, ticks_per_second: 16_000_000, epoch_offset: 1_700_000_000_000 }));
+        snapshot.extend(encoder.record(&SnapshotRecord { at: 32_000_000, co2: 415_500, temperature: -1_250, humidity: 45_000 }));
+        snapshot.extend(encoder.trailer());
+
+        assert_eq!(snapshot.len(), SnapshotEncoder::encoded_len(1));
+        assert_eq!(&snapshot[0..4], b"SCDS");
+        assert_eq!(u32::from_le_bytes(snapshot[8..12].try_into().unwrap()), 16_000_000);
+        assert_eq!(u64::from_le_bytes(snapshot[20..28].try_into().unwrap()), 32_000_000);
+        assert_eq!(i32::from_le_bytes(snapshot[32..36].try_into().unwrap()), -1_250);
+
+        let mut crc = Crc32::new();
+        crc.update(&snapshot[..(HEADER_LEN + RECORD_LEN)]);
+        assert_eq!(&snapshot[(HEADER_LEN + RECORD_LEN)..], &crc.finish().to_le_bytes());
+    }
+}
@@ -0,0 +1,429 @@
+/* non-blocking bit-banged i2c transfer (one write or one read), lines are driven by the firmware (open drain gpio pins) */
+
+
+
+/// Open drain scl and sda lines, `true` releases the line (pulled up), `false` drives it low.
+pub trait Lines {
+    fn set_scl(&mut self, high: bool);
+    fn set_sda(&mut self, high: bool);
+    fn scl(&self) -> bool;
+    fn sda(&self) -> bool;
+    /// Half of scl period (5 us at 100 kHz).
+    fn wait_half_period(&mut self);
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SoftI2CError {
+    /// address or written byte was not acknowledged
+    Nack,
+    /// device stretched the clock for longer than the timeout
+    Timeout,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Step {
+    Start,
+    /// bits 0 - 7 are data (msb first), bit 8 is ack
+    Bit { byte: usize, bit: u8 },
+    Stop,
+    Done,
+}
+
+
+/// Write of bytes to or read of bytes from device, started by `write` / `read` and progressed by `poll`.
+///
+/// Clock pulses are done without waiting for the device, pulse stretched by the device (scl held low) is resumed by the
+/// next `poll`, so the caller is blocked only for a few half periods of each pulse. Stop condition is sent even after
+/// error (except timeout of stop itself), so the device releases the bus.
+#[derive(Debug, Clone)]
+pub struct Transfer {
+    /// address byte followed by written or read bytes
+    bytes: [u8; Transfer::MAX_LEN],
+    len: usize,
+    read: bool,
+    step: Step,
+    /// time when scl of the current pulse was released, `None` when no pulse is waiting for scl
+    scl_released_at: Option<u64>,
+    error: Option<SoftI2CError>,
+}
+
+impl Transfer {
+    /// address byte and bytes of transfer (same as fifo of i2c peripheral)
+    pub const MAX_LEN: usize = 32;
+
+
+    /// Panics when there are more than `MAX_LEN - 1` bytes.
+    pub fn write(address: u8, bytes: &[u8]) -> Self {
+        assert!(bytes.len() < Self::MAX_LEN);
+
+        let mut transfer = Self::new(address << 1, 1 + bytes.len(), false);
+        transfer.bytes[1..transfer.len].copy_from_slice(bytes);
+
+        transfer
+    }
+
+    /// Panics when `len` is not in `1..MAX_LEN`.
+    pub fn read(address: u8, len: usize) -> Self {
+        assert!(len != 0 && len < Self::MAX_LEN);
+
+        Self::new((address << 1) | 1, 1 + len, true)
+    }
+
+    fn new(address_byte: u8, len: usize, read: bool) -> Self {
+        let mut bytes = [0; Self::MAX_LEN];
+        bytes[0] = address_byte;
+
+        Self { bytes, len, read, step: Step::Start, scl_released_at: None, error: None }
+    }
+
+    /// Does pulses of the transfer until it is done or the device stretches the clock, `now` and `stretch_timeout` are
+    /// in ticks of the same timer. Returns result when the transfer is done (also on every later call).
+    pub fn poll(&mut self, lines: &mut impl Lines, now: u64, stretch_timeout: u64) -> Option<Result<(), SoftI2CError>> {
+        loop {
+            match self.step {
+                Step::Start => {
+                    if self.scl_released_at.is_none() {
+                        lines.set_sda(true);
+                    }
+                    if !self.release_scl(lines, now, stretch_timeout)? {
+                        self.fail(SoftI2CError::Timeout);
+                        continue;
+                    }
+
+                    lines.wait_half_period();
+                    lines.set_sda(false);
+                    lines.wait_half_period();
+                    lines.set_scl(false);
+
+                    self.step = Step::Bit { byte: 0, bit: 0 };
+                },
+                Step::Bit { byte, bit } => {
+                    let controller_writes = match bit {
+                        8 => self.read && byte != 0,
+                        _ => !self.read || byte == 0,
+                    };
+
+                    if self.scl_released_at.is_none() {
+                        let level = match bit {
+                            // last read byte is not acknowledged
+                            8 => byte + 1 == self.len,
+                            _ => self.bytes[byte] & (0x80 >> bit) != 0,
+                        };
+                        lines.set_sda(!controller_writes || level);
+                        lines.wait_half_period();
+                    }
+                    if !self.release_scl(lines, now, stretch_timeout)? {
+                        self.fail(SoftI2CError::Timeout);
+                        continue;
+                    }
+
+                    let sda = lines.sda();
+                    lines.wait_half_period();
+                    lines.set_scl(false);
+
+                    if !controller_writes {
+                        match bit {
+                            8 if sda => {
+                                self.fail(SoftI2CError::Nack);
+                                continue;
+                            },
+                            8 => {},
+                            _ => self.bytes[byte] = (self.bytes[byte] << 1) | sda as u8,
+                        }
+                    }
+
+                    self.step = match (bit, byte + 1) {
+                        (8, next) if next == self.len => Step::Stop,
+                        (8, next) => Step::Bit { byte: next, bit: 0 },
+                        (bit, _) => Step::Bit { byte, bit: bit + 1 },
+                    };
+                },
+                Step::Stop => {
+                    if self.scl_released_at.is_none() {
+                        lines.set_sda(false);
+                        lines.wait_half_period();
+                    }
+                    if !self.release_scl(lines, now, stretch_timeout)? {
+                        self.error.get_or_insert(SoftI2CError::Timeout);
+                        self.step = Step::Done;
+                        continue;
+                    }
+
+                    lines.wait_half_period();
+                    lines.set_sda(true);
+                    lines.wait_half_period();
+
+                    self.step = Step::Done;
+                },
+                Step::Done => return Some(self.error.map_or(Ok(()), Err)),
+            }
+        }
+    }
+
+    /// Read bytes, `None` until the read is done without error.
+    pub fn response(&self) -> Option<&[u8]> {
+        (self.read && self.step == Step::Done && self.error.is_none()).then(|| &self.bytes[1..self.len])
+    }
+
+    /// Releases scl (once per pulse), `Some(true)` when it is high, `Some(false)` when the device stretched it for longer
+    /// than `stretch_timeout` and `None` while it is stretched.
+    fn release_scl(&mut self, lines: &mut impl Lines, now: u64, stretch_timeout: u64) -> Option<bool> {
+        let released_at = *self.scl_released_at.get_or_insert_with(|| {
+            lines.set_scl(true);
+            now
+        });
+
+        if lines.scl() {
+            self.scl_released_at = None;
+            Some(true)
+        } else if now.saturating_sub(released_at) > stretch_timeout {
+            self.scl_released_at = None;
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    /// First error is kept, transfer continues by stop condition.
+    fn fail(&mut self, error: SoftI2CError) {
+        self.error.get_or_insert(error);
+        self.step = Step::Stop;
+    }
+}
+
+
+/// Clocks scl until the device releases sda and sends stop condition, `true` when both lines are high afterwards.
+///
+/// Up to 9 pulses are sent (same as recovery of i2c peripheral), clock stretching is not waited for.
+pub fn recover(lines: &mut impl Lines) -> bool {
+    const RECOVERY_PULSES: usize = 9;
+
+    lines.set_sda(true);
+    lines.set_scl(true);
+    lines.wait_half_period();
+
+    for _ in 0..RECOVERY_PULSES {
+        if lines.sda() {
+            break;
+        }
+
+        lines.set_scl(false);
+        lines.wait_half_period();
+        lines.set_scl(true);
+        lines.wait_half_period();
+    }
+
+    lines.set_scl(false);
+    lines.wait_half_period();
+    lines.set_sda(false);
+    lines.wait_half_period();
+    lines.set_scl(true);
+    lines.wait_half_period();
+    lines.set_sda(true);
+    lines.wait_half_period();
+
+    lines.scl() && lines.sda()
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, vec::Vec};
+
+    use super::*;
+
+
+    /// Device on the bus, records bits clocked by the controller and answers with queued bits.
+    #[derive(Default)]
+    struct Device {
+        scl: bool,
+        sda: bool,
+        /// sda driven low by the device
+        device_sda_low: bool,
+        /// bits sampled on rising edges of scl since the last start condition
+        bits: Vec<bool>,
+        starts: usize,
+        stops: usize,
+        /// sda levels driven by the device on the next pulses (`false` is low, taken on falling edges of scl), released
+        /// when empty
+        responses: VecDeque<bool>,
+        /// scl is held low for this many reads of scl after it is released
+        stretch: usize,
+        /// time is advanced by each half period
+        half_periods: usize,
+    }
+
+    impl Device {
+        fn new() -> Self {
+            Self { scl: true, sda: true, ..Default::default() }
+        }
+
+        fn respond_ack(&mut self) {
+            self.responses.push_back(false);
+        }
+
+        fn respond_byte(&mut self, byte: u8) {
+            self.responses.extend((0..8).map(|i| byte & (0x80 >> i) != 0));
+        }
+
+        /// Bytes of clocked bits, 9 bits per byte (ack and bit clocked by stop condition are dropped).
+        fn bytes(&self) -> Vec<u8> {
+            self.bits.chunks_exact(9).map(|bits| bits[..8].iter().fold(0, |byte, bit| (byte << 1) | *bit as u8)).collect()
+        }
+    }
+
+    impl Lines for Device {
+        fn set_scl(&mut self, high: bool) {
+            let rising = high && !self.scl;
+            let falling = !high && self.scl;
+            self.scl = high;
+
+            if rising {
+                self.bits.push(self.sda());
+            } else if falling {
+                // device drives sda for the next pulse (after ack of the controller)
+                self.device_sda_low = self.responses.pop_front().is_some_and(|level| !level);
+            }
+        }
+
+        fn set_sda(&mut self, high: bool) {
+            if self.scl && self.sda && !high {
+                self.starts += 1;
+                self.bits.clear();
+            } else if self.scl && !self.sda && high {
+                self.stops += 1;
+            }
+            self.sda = high;
+        }
+
+        fn scl(&self) -> bool {
+            self.scl && self.stretch == 0
+        }
+
+        fn sda(&self) -> bool {
+            self.sda && !self.device_sda_low
+        }
+
+        fn wait_half_period(&mut self) {
+            self.half_periods += 1;
+        }
+    }
+
+
+    /// Polls with time advanced by 1 tick on each call until the transfer is done.
+    fn poll_until_done(transfer: &mut Transfer, device: &mut Device, stretch_timeout: u64) -> (Result<(), SoftI2CError>, u64) {
+        for now in 0..1_000 {
+            if let Some(result) = transfer.poll(device, now, stretch_timeout) {
+                return (result, now);
+            }
+            device.stretch = device.stretch.saturating_sub(1);
+        }
+
+        panic!("transfer is not done");
+    }
+
+    #[test]
+    fn write_is_acknowledged() {
+        let mut device = Device::new();
+        // address and two bytes, device answers after 8 data bits of each byte
+        for _ in 0..3 {
+            device.responses.extend([true; 8]);
+            device.respond_ack();
+        }
+
+        let mut transfer = Transfer::write(0x61, &[0x00, 0x10]);
+        assert_eq!(transfer.poll(&mut device, 0, 10), Some(Ok(())));
+
+        assert_eq!(device.bytes(), [0x61 << 1, 0x00, 0x10]);
+        assert_eq!((device.starts, device.stops), (1, 1));
+        assert!(device.scl() && device.sda());
+        assert_eq!(transfer.response(), None);
+    }
+
+    #[test]
+    fn nack_of_address_sends_stop() {
+        let mut device = Device::new();
+
+        let mut transfer = Transfer::write(0x61, &[0x00, 0x10]);
+        assert_eq!(transfer.poll(&mut device, 0, 10), Some(Err(SoftI2CError::Nack)));
+
+        // only address byte was sent
+        assert_eq!(device.bytes(), [(0x61 << 1)]);
+        assert_eq!((device.starts, device.stops), (1, 1));
+        assert_eq!(transfer.poll(&mut device, 1, 10), Some(Err(SoftI2CError::Nack)));
+    }
+
+    #[test]
+    fn read_acknowledges_all_but_last_byte() {
+        let mut device = Device::new();
+        device.responses.extend([true; 8]);
+        device.respond_ack();
+        device.respond_byte(0xbe);
+        device.responses.push_back(true);
+        device.respond_byte(0xef);
+
+        let mut transfer = Transfer::read(0x61, 2);
+        assert_eq!(transfer.poll(&mut device, 0, 10), Some(Ok(())));
+
+        assert_eq!(transfer.response(), Some(&[0xbe, 0xef][..]));
+        // ack bits driven by the controller
+        assert_eq!([device.bits[17], device.bits[26]], [false, true]);
+        assert_eq!((device.starts, device.stops), (1, 1));
+    }
+
+    #[test]
+    fn clock_stretching_does_not_block() {
+        let mut device = Device::new();
+        device.responses.extend([true; 8]);
+        device.respond_ack();
+        device.respond_byte(0x42);
+
+        let mut transfer = Transfer::read(0x61, 1);
+        device.stretch = 5;
+
+        // start condition waits for scl
+        assert_eq!(transfer.poll(&mut device, 0, 10), None);
+        let half_periods = device.half_periods;
+        assert_eq!(transfer.poll(&mut device, 1, 10), None);
+        assert_eq!(device.half_periods, half_periods);
+
+        let (result, now) = poll_until_done(&mut transfer, &mut device, 10);
+        assert_eq!(result, Ok(()));
+        assert!(now >= 5);
+        assert_eq!(transfer.response(), Some(&[0x42][..]));
+        assert_eq!((device.starts, device.stops), (1, 1));
+    }
+
+    #[test]
+    fn stretch_timeout() {
+        let mut device = Device::new();
+        device.stretch = usize::MAX;
+
+        let mut transfer = Transfer::write(0x61, &[0x00]);
+        assert_eq!(transfer.poll(&mut device, 0, 10), None);
+        assert_eq!(transfer.poll(&mut device, 10, 10), None);
+        // stop condition waits for scl again
+        assert_eq!(transfer.poll(&mut device, 11, 10), None);
+        assert_eq!(transfer.poll(&mut device, 22, 10), Some(Err(SoftI2CError::Timeout)));
+        assert_eq!(transfer.response(), None);
+    }
+
+    #[test]
+    fn recover_clocks_until_sda_is_released() {
+        let mut device = Device::new();
+        // device releases sda on the third pulse
+        device.device_sda_low = true;
+        device.responses.extend([false, false]);
+
+        assert!(recover(&mut device));
+        assert_eq!(device.stops, 1);
+
+        let mut device = Device::new();
+        device.device_sda_low = true;
+        device.responses.extend([false; 20]);
+        assert!(!recover(&mut device));
+    }
+}
@@ -46,7 +46,7 @@ impl SonyIRRawCommand {
             },
             SonyIRCommand::V15 { address, command } => SonyIRRawCommand {
                 data: (((address & 0b1111_1111) as u32) << 7) | ((command & 0b0111_1111) as u32),
-                bits: 12
+                bits: 15
             },
             SonyIRCommand::Raw(raw) => raw,
         }
@@ -206,4 +206,63 @@ impl SonyIRDecoder {
             None => { Ok(None) },
         }
     }
-}
\ No newline at end of file
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use crate::sony_ir::SonyIRCommand;
+
+    use super::*;
+
+
+    /// Feeds deltas between edges of `command` (as transmitted by the remote) into the decoder.
+    fn feed(decoder: &mut SonyIRDeltaDecoder, command: SonyIRRawCommand) -> Result<(), SonyIRError> {
+        // first edge, start mark, start space
+        decoder.pulse(0)?;
+        decoder.pulse(SonyIRDeltaDecoder::PULSE_LONG)?;
+        decoder.pulse(SonyIRDeltaDecoder::PULSE_SHORT)?;
+
+        for bit in 0..command.bits {
+            let mark = if (command.data >> bit) & 1 == 1 { SonyIRDeltaDecoder::PULSE_MID } else { SonyIRDeltaDecoder::PULSE_SHORT };
+            decoder.pulse(mark)?;
+
+            // space after last mark ends with timeout
+            if bit + 1 != command.bits {
+                decoder.pulse(SonyIRDeltaDecoder::PULSE_SHORT)?;
+            }
+        }
+
+        Ok(())
+    }
+
+
+    #[test]
+    fn decodes_12_bit_command() {
+        let mut decoder = SonyIRDeltaDecoder::new();
+        let command = SonyIRRawCommand::from_command(SonyIRCommand::V12 { address: 1, command: 0x15 });
+
+        feed(&mut decoder, command).unwrap();
+        let raw = decoder.timeout().unwrap();
+
+        assert_eq!(raw, command);
+        assert_eq!(SonyIRCommand::from_raw(&raw), SonyIRCommand::V12 { address: 1, command: 0x15 });
+    }
+
+    #[test]
+    fn command_roundtrip_keeps_bit_count() {
+        for command in [SonyIRCommand::V12 { address: 0x1f, command: 0x7f }, SonyIRCommand::V15 { address: 0xa4, command: 0x2a }] {
+            assert_eq!(SonyIRCommand::from_raw(&SonyIRRawCommand::from_command(command)), command);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_bit_count() {
+        let mut decoder = SonyIRDeltaDecoder::new();
+
+        feed(&mut decoder, SonyIRRawCommand { data: 0, bits: 13 }).unwrap();
+
+        assert!(decoder.timeout().is_err());
+    }
+}
@@ -0,0 +1,61 @@
+use core::iter;
+
+use super::SonyIRRawCommand;
+
+
+
+/// Start mark length (in units, 1 unit = 600 us).
+const START_MARK: u16 = 4;
+
+
+/// Pulses of one frame as `(mark, space)` pairs in units (1 unit = 600 us), bits are sent lsb first.
+///
+/// Space after the last mark is `0` (rmt end marker), pause between frames is handled by the transmitter.
+/// Frame has `command.bits + 1` pulses, `command.bits` must not be zero.
+pub fn pulses(command: SonyIRRawCommand) -> impl Iterator<Item = (u16, u16)> {
+    let last_bit = command.bits.saturating_sub(1);
+
+    iter::once((START_MARK, 1))
+        .chain((0..command.bits).map(move |bit| {
+            let mark = if (command.data >> bit) & 0b1 == 1 { 2 } else { 1 };
+            let space = if bit == last_bit { 0 } else { 1 };
+
+            (mark, space)
+        }))
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use crate::sony_ir::{rx::SonyIRDeltaDecoder, SonyIRCommand};
+
+    use super::*;
+
+
+    #[test]
+    fn encoded_frame_is_decoded() {
+        const UNIT: u64 = 600 * 16;
+
+        let command = SonyIRRawCommand::from_command(SonyIRCommand::V15 { address: 0xa4, command: 0x2a });
+        let pulses = pulses(command).collect::<Vec<_>>();
+
+        assert_eq!(pulses.len(), command.bits as usize + 1);
+        assert_eq!(pulses.last().unwrap().1, 0);
+
+        let mut decoder = SonyIRDeltaDecoder::new();
+        decoder.pulse(0).unwrap();
+
+        for (mark, space) in pulses {
+            decoder.pulse(mark as u64 * UNIT).unwrap();
+
+            if space != 0 {
+                decoder.pulse(space as u64 * UNIT).unwrap();
+            }
+        }
+
+        assert_eq!(decoder.timeout().unwrap(), command);
+    }
+}
@@ -0,0 +1,114 @@
+/* rolling minimum, maximum and average of a value over a time window (last hour, last day, ...) in constant memory */
+
+
+
+/// Minimum, maximum and average of values pushed in the window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Summary {
+    pub min: i32,
+    pub max: i32,
+    /// rounded towards zero
+    pub mean: i32,
+    pub count: u32,
+    /// time of the oldest value in the summary (start of its bucket, in ms given by the caller)
+    pub since_ms: u64,
+}
+
+
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    /// multiple of bucket length
+    start_ms: u64,
+    min: i32,
+    max: i32,
+    sum: i64,
+    count: u32,
+}
+
+
+/// Window of `window_ms` split into `B` buckets, each bucket keeps minimum, maximum and sum of its values.
+///
+/// Window moves by whole buckets, so summary covers between `window_ms - window_ms / B` and `window_ms` of values.
+/// Times have to be monotonic (e.g. system timer, not wall clock which can jump when synchronized).
+pub struct RollingSummary<const B: usize> {
+    bucket_ms: u64,
+    /// indexed by `(start_ms / bucket_ms) % B`
+    buckets: [Option<Bucket>; B],
+}
+
+impl<const B: usize> RollingSummary<B> {
+    pub const fn new(window_ms: u64) -> Self {
+        Self {
+            bucket_ms: window_ms / B as u64,
+            buckets: [None; B],
+        }
+    }
+
+    pub fn push(&mut self, at_ms: u64, value: i32) {
+        let number = at_ms / self.bucket_ms;
+        let start_ms = number * self.bucket_ms;
+
+        // bucket from previous turn of the window is replaced
+        match &mut self.buckets[(number % B as u64) as usize] {
+            Some(bucket) if bucket.start_ms == start_ms => {
+                bucket.min = bucket.min.min(value);
+                bucket.max = bucket.max.max(value);
+                bucket.sum += value as i64;
+                bucket.count += 1;
+            },
+            slot => *slot = Some(Bucket { start_ms, min: value, max: value, sum: value as i64, count: 1 }),
+        }
+    }
+
+    /// Summary of values in the window ending at `now_ms`, `None` when there are none.
+    pub fn summary(&self, now_ms: u64) -> Option<Summary> {
+        let oldest_start_ms = (now_ms / self.bucket_ms).saturating_sub(B as u64 - 1) * self.bucket_ms;
+
+        let (min, max, sum, count, since_ms) = self.buckets.iter()
+            .flatten()
+            .filter(|bucket| bucket.start_ms >= oldest_start_ms && bucket.start_ms <= now_ms)
+            .fold((i32::MAX, i32::MIN, 0i64, 0u32, u64::MAX), |(min, max, sum, count, since_ms), bucket| {
+                (min.min(bucket.min), max.max(bucket.max), sum + bucket.sum, count + bucket.count, since_ms.min(bucket.start_ms))
+            });
+
+        (count > 0).then(|| Summary { min, max, mean: (sum / count as i64) as i32, count, since_ms })
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR_MS: u64 = 3_600_000;
+
+
+    #[test]
+    fn summary_of_values_in_window() {
+        let mut summary = RollingSummary::<12>::new(HOUR_MS);
+        assert_eq!(summary.summary(0), None);
+
+        [(0, 400), (60_000, 600), (400_000, 500), (3_000_000, -100)].into_iter().for_each(|(at, value)| summary.push(at, value));
+
+        assert_eq!(summary.summary(3_000_000), Some(Summary { min: -100, max: 600, mean: 350, count: 4, since_ms: 0 }));
+    }
+
+    #[test]
+    fn old_buckets_leave_window() {
+        let mut summary = RollingSummary::<24>::new(24 * HOUR_MS);
+
+        // one value every 30 minutes for two days, value is the hour
+        (0..96).for_each(|i| summary.push(i * HOUR_MS / 2, (i / 2) as i32));
+
+        let now = 95 * HOUR_MS / 2;
+        assert_eq!(summary.summary(now), Some(Summary { min: 24, max: 47, mean: 35, count: 48, since_ms: 24 * HOUR_MS }));
+
+        // nothing pushed for a day
+        assert_eq!(summary.summary(now + 24 * HOUR_MS), None);
+
+        // bucket of hour 0 is reused for hour 48
+        summary.push(48 * HOUR_MS + 1, 1_000);
+        assert_eq!(summary.summary(48 * HOUR_MS + 2), Some(Summary { min: 25, max: 1_000, mean: 56, count: 47, since_ms: 25 * HOUR_MS }));
+    }
+}
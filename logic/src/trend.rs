@@ -0,0 +1,112 @@
+/* rate of change (least squares slope) of fixed point values and its classification */
+
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trend {
+    Rising,
+    Falling,
+    Stable,
+}
+
+impl Trend {
+    /// `Stable` when absolute value of `slope` is smaller than `threshold` (same units).
+    pub fn classify(slope: i32, threshold: i32) -> Self {
+        if slope >= threshold.max(1) {
+            Trend::Rising
+        } else if slope <= -threshold.max(1) {
+            Trend::Falling
+        } else {
+            Trend::Stable
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Trend::Rising => "rising",
+            Trend::Falling => "falling",
+            Trend::Stable => "stable",
+        }
+    }
+}
+
+
+/// Fewest points `slope_per_minute` fits, a line through two points follows noise of a single measurment.
+pub const MIN_POINTS: usize = 3;
+
+
+/// Least squares slope of `points` (time in ms, value) in value units per minute (rounded towards zero).
+///
+/// `None` when there are less than `MIN_POINTS` points or all points have the same time. Times do not have to be
+/// sorted.
+pub fn slope_per_minute(points: impl Iterator<Item = (u64, i32)> + Clone) -> Option<i32> {
+    // times relative to the first point keep sums small
+    let t0 = points.clone().next()?.0 as i128;
+
+    let (n, sum_t, sum_v, sum_tt, sum_tv) = points.fold((0i128, 0i128, 0i128, 0i128, 0i128), |(n, sum_t, sum_v, sum_tt, sum_tv), (t, v)| {
+        let t = t as i128 - t0;
+        let v = v as i128;
+        (n + 1, sum_t + t, sum_v + v, sum_tt + t * t, sum_tv + t * v)
+    });
+
+    let denominator = n * sum_tt - sum_t * sum_t;
+    if n < MIN_POINTS as i128 || denominator == 0 {
+        return None;
+    }
+
+    let slope = (n * sum_tv - sum_t * sum_v) * 60_000 / denominator;
+
+    Some(slope.clamp(i32::MIN as i128, i32::MAX as i128) as i32)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slope_of_line() {
+        // +10 ppm every 30 s
+        let points = (0..10).map(|i| (1_000_000 + i * 30_000, 400_000 + i as i32 * 10_000));
+        assert_eq!(slope_per_minute(points), Some(20_000));
+
+        let points = (0..10).map(|i| (i * 60_000, 21_000 - i as i32 * 500));
+        assert_eq!(slope_per_minute(points), Some(-500));
+    }
+
+    #[test]
+    fn slope_of_noisy_values() {
+        let noise = [300, -200, 0, 100, -300, 200, -100, 0];
+        let points = noise.iter().enumerate().map(|(i, noise)| (i as u64 * 10_000, 800_000 + i as i32 * 5_000 + noise));
+
+        // 30 ppm per minute without noise
+        let slope = slope_per_minute(points).unwrap();
+        assert!((29_000..=31_000).contains(&slope), "{}", slope);
+    }
+
+    #[test]
+    fn slope_needs_three_points_and_two_times() {
+        assert_eq!(slope_per_minute([].into_iter()), None);
+        assert_eq!(slope_per_minute([(5_000, 400_000)].into_iter()), None);
+        assert_eq!(slope_per_minute([(5_000, 400_000), (65_000, 400_000)].into_iter()), None);
+        assert_eq!(slope_per_minute([(5_000, 400_000), (5_000, 500_000), (5_000, 600_000)].into_iter()), None);
+        assert_eq!(slope_per_minute([(5_000, 400_000), (5_000, 400_000), (65_000, 400_000)].into_iter()), Some(0));
+    }
+
+    #[test]
+    fn large_values_do_not_overflow() {
+        let points = (0..1024).map(|i| (u64::MAX / 2 + i * 3_600_000, if i % 2 == 0 { 40_000_000 } else { 0 }));
+        assert!(slope_per_minute(points).is_some());
+    }
+
+    #[test]
+    fn classify() {
+        assert_eq!(Trend::classify(20_000, 5_000), Trend::Rising);
+        assert_eq!(Trend::classify(5_000, 5_000), Trend::Rising);
+        assert_eq!(Trend::classify(4_999, 5_000), Trend::Stable);
+        assert_eq!(Trend::classify(-4_999, 5_000), Trend::Stable);
+        assert_eq!(Trend::classify(-5_000, 5_000), Trend::Falling);
+        assert_eq!(Trend::classify(0, 0), Trend::Stable);
+    }
+}
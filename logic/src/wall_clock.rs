@@ -0,0 +1,103 @@
+/* wall clock (unix time) derived from system timer ticks, offset to unix time is set by synchronization (console, network, ...) */
+
+
+
+/// Width of system timer counter, counter starts from zero again after `2^52` ticks (~ 8.9 years at 16 MHz).
+pub const SYSTIMER_BITS: u32 = 52;
+
+
+/// Extends raw system timer values to 64 bits (by counting rollovers) and converts them to unix time.
+///
+/// Rollover is detected when raw value is lower than the previous one, so some conversion has to happen at least once per rollover period.
+#[derive(Debug, Clone, Copy)]
+pub struct WallClock {
+    ticks_per_second: u64,
+    last_raw: u64,
+    rollovers: u64,
+    /// unix time (in ms) minus extended ticks (in ms), `None` until synchronized
+    offset_ms: Option<i64>,
+}
+
+impl WallClock {
+    pub const fn new(ticks_per_second: u64) -> Self {
+        Self {
+            ticks_per_second,
+            last_raw: 0,
+            rollovers: 0,
+            offset_ms: None,
+        }
+    }
+
+    /// Extended ticks of raw system timer value, values have to be passed in non decreasing order (except for rollover).
+    pub fn ticks(&mut self, raw: u64) -> u64 {
+        let raw = raw & ((1 << SYSTIMER_BITS) - 1);
+
+        if raw < self.last_raw {
+            self.rollovers += 1;
+        }
+        self.last_raw = raw;
+
+        (self.rollovers << SYSTIMER_BITS) | raw
+    }
+
+    fn ticks_ms(&self, ticks: u64) -> i64 {
+        (ticks / (self.ticks_per_second / 1_000)) as i64
+    }
+
+    /// Synchronizes clock, `raw` is system timer value at `unix_ms`.
+    pub fn set_unix_ms(&mut self, raw: u64, unix_ms: u64) {
+        let ticks = self.ticks(raw);
+        self.offset_ms = Some(unix_ms as i64 - self.ticks_ms(ticks));
+    }
+
+    pub fn is_synchronized(&self) -> bool {
+        self.offset_ms.is_some()
+    }
+
+    /// Unix time (in ms) of extended `ticks` (see `ticks`), `None` until synchronized or for ticks before unix epoch.
+    pub fn unix_ms_at(&self, ticks: u64) -> Option<u64> {
+        let unix_ms = self.ticks_ms(ticks) + self.offset_ms?;
+        u64::try_from(unix_ms).ok()
+    }
+
+    /// Unix time (in ms) of raw system timer value.
+    pub fn unix_ms(&mut self, raw: u64) -> Option<u64> {
+        let ticks = self.ticks(raw);
+        self.unix_ms_at(ticks)
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+    const TICKS_PER_SECOND: u64 = 16_000_000;
+
+
+    #[test]
+    fn clock_is_unsynchronized_until_set() {
+        let mut clock = WallClock::new(TICKS_PER_SECOND);
+
+        assert_eq!(clock.unix_ms(TICKS_PER_SECOND), None);
+
+        clock.set_unix_ms(2 * TICKS_PER_SECOND, 1_700_000_000_000);
+
+        assert!(clock.is_synchronized());
+        assert_eq!(clock.unix_ms(5 * TICKS_PER_SECOND), Some(1_700_000_003_000));
+    }
+
+    #[test]
+    fn rollover_keeps_time_monotonic() {
+        let mut clock = WallClock::new(TICKS_PER_SECOND);
+        let max_raw = (1 << SYSTIMER_BITS) - 1;
+
+        clock.set_unix_ms(max_raw, 1_700_000_000_000);
+        let before = clock.unix_ms(max_raw).unwrap();
+
+        assert_eq!(clock.ticks(TICKS_PER_SECOND), (1 << SYSTIMER_BITS) + TICKS_PER_SECOND);
+        assert_eq!(clock.unix_ms(TICKS_PER_SECOND).unwrap() - before, 1_000);
+    }
+}
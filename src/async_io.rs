@@ -0,0 +1,286 @@
+/* resources of the main loop lent to async tasks and futures waiting for qq alarms, interrupts and the i2c bus */
+
+use core::{
+    cell::RefCell,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll, Waker}
+};
+
+pub use crate::lend::Lend;
+
+use crate::{
+    error_registry::{self, Subsystem},
+    event_bus::EventBus,
+    i2c_bus::{I2CBus, I2CBusUser},
+    i2c_trace,
+    interrupts::{self, I2CInterruptStatus, InterruptSource},
+    pac_utils::i2c::I2CTransmissionError,
+    qq_alarm_queue::{QQAlarmQueue, QQOwner, TaggedQQAlarmQueue}
+};
+
+
+
+/// Delays (of all tasks of one executor) waiting at the same time.
+pub const MAX_ALARMS: usize = 4;
+
+
+#[derive(Debug)]
+struct AlarmSlot {
+    qq_alarm_id: usize,
+    fired: bool,
+    waker: Option<Waker>,
+    /// future was dropped while the alarm was waiting and it could not be removed, slot is freed by `on_alarm`
+    orphaned: bool,
+}
+
+
+/// Resources lent to async tasks of one executor for the duration of its poll (see `lend`) and qq alarms of their delays.
+///
+/// Alarms are added on behalf of `owner`, main loop dispatches them to `on_alarm` (see `AsyncTasks`).
+pub struct AsyncIo<'d, W, Q> {
+    pub i2c_bus: Lend<I2CBus<'d>>,
+    pub usb_writer: Lend<W>,
+    pub qq: Lend<Q>,
+    pub events: Lend<EventBus>,
+    owner: QQOwner,
+    alarms: RefCell<[Option<AlarmSlot>; MAX_ALARMS]>,
+}
+
+impl<'d, W, Q> AsyncIo<'d, W, Q> {
+    pub fn new(owner: QQOwner) -> Self {
+        Self {
+            i2c_bus: Lend::new(),
+            usb_writer: Lend::new(),
+            qq: Lend::new(),
+            events: Lend::new(),
+            owner,
+            alarms: RefCell::new([const { None }; MAX_ALARMS]),
+        }
+    }
+
+    pub fn owner(&self) -> QQOwner {
+        self.owner
+    }
+
+    /// Lends all resources while `f` runs (it should poll the executor).
+    pub fn lend<R>(&self, i2c_bus: &mut I2CBus<'d>, usb_writer: &mut W, qq: &mut Q, events: &mut EventBus, f: impl FnOnce() -> R) -> R {
+        self.i2c_bus.lend(i2c_bus, || self.usb_writer.lend(usb_writer, || self.qq.lend(qq, || self.events.lend(events, f))))
+    }
+
+    /// Wakes task waiting for the alarm, returns `true` when alarm was added by one of the delays.
+    pub fn on_alarm(&self, qq_alarm_id: usize) -> bool {
+        let mut alarms = self.alarms.borrow_mut();
+        let Some(slot) = alarms.iter_mut().find(|slot| slot.as_ref().is_some_and(|alarm| alarm.qq_alarm_id == qq_alarm_id)) else {
+            return false;
+        };
+
+        // checked by find
+        let Some(alarm) = slot.as_mut() else {
+            return false;
+        };
+
+        if alarm.orphaned {
+            *slot = None;
+        } else {
+            alarm.fired = true;
+            if let Some(waker) = alarm.waker.take() {
+                waker.wake();
+            }
+        }
+
+        true
+    }
+}
+
+
+/// Future of `sleep_until`.
+pub struct Sleep<'a, 'd, W, Q> where Q: TaggedQQAlarmQueue {
+    io: &'a AsyncIo<'d, W, Q>,
+    wake_at: u64,
+    /// index into `AsyncIo::alarms`
+    slot: Option<usize>,
+    /// qq error is recorded only once per sleep, not on every retry
+    error_recorded: bool,
+}
+
+/// Waits until `wake_at` (in system timer ticks) using qq alarm, same as `machines::Delay`.
+///
+/// When qq alarm queue is full (or all alarm slots are used), adding the alarm is tried again in the next main loop iteration
+/// (the error is recorded only for the first try).
+pub fn sleep_until<'a, 'd, W, Q: TaggedQQAlarmQueue>(io: &'a AsyncIo<'d, W, Q>, wake_at: u64) -> Sleep<'a, 'd, W, Q> {
+    Sleep { io, wake_at, slot: None, error_recorded: false }
+}
+
+impl<W, Q> Future for Sleep<'_, '_, W, Q> where Q: TaggedQQAlarmQueue {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let io = self.io;
+        let mut alarms = io.alarms.borrow_mut();
+
+        if let Some(index) = self.slot {
+            let Some(alarm) = alarms[index].as_mut() else {
+                return Poll::Ready(());
+            };
+
+            if alarm.fired {
+                alarms[index] = None;
+                self.slot = None;
+                return Poll::Ready(());
+            }
+
+            alarm.waker = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+
+        let added = alarms.iter().position(Option::is_none).and_then(|index| {
+            match io.qq.with(|qq| qq.owned(io.owner).add(self.wake_at)) {
+                Ok(qq_alarm_id) => Some((index, qq_alarm_id)),
+                Err(err) => {
+                    if !self.error_recorded {
+                        error_registry::record_error(Subsystem::Qq, &err);
+                        self.error_recorded = true;
+                    }
+                    None
+                },
+            }
+        });
+
+        match added {
+            Some((index, qq_alarm_id)) => {
+                alarms[index] = Some(AlarmSlot { qq_alarm_id, fired: false, waker: Some(cx.waker().clone()), orphaned: false });
+                self.slot = Some(index);
+            },
+            None => cx.waker().wake_by_ref(),
+        }
+
+        Poll::Pending
+    }
+}
+
+impl<W, Q> Drop for Sleep<'_, '_, W, Q> where Q: TaggedQQAlarmQueue {
+    fn drop(&mut self) {
+        let Some(index) = self.slot else {
+            return;
+        };
+
+        let mut alarms = self.io.alarms.borrow_mut();
+        let Some(alarm) = alarms[index].as_mut() else {
+            return;
+        };
+
+        // alarm is removed only when qq is lent (future is dropped by task being polled), otherwise its slot claims it later
+        let removed = alarm.fired || self.io.qq.try_with(|qq| qq.remove(alarm.qq_alarm_id).is_ok()).unwrap_or(false);
+        if removed {
+            alarms[index] = None;
+        } else {
+            alarm.orphaned = true;
+            alarm.waker = None;
+        }
+    }
+}
+
+
+/// Future of `wait_interrupt`.
+pub struct WaitInterrupt<F> {
+    source: InterruptSource,
+    check: F,
+}
+
+/// Waits until `check` returns `Some` (it should consume pending interrupt flags), checked again after each interrupt of `source`.
+pub fn wait_interrupt<T, F: FnMut() -> Option<T> + Unpin>(source: InterruptSource, check: F) -> WaitInterrupt<F> {
+    WaitInterrupt { source, check }
+}
+
+impl<T, F: FnMut() -> Option<T> + Unpin> Future for WaitInterrupt<F> {
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some(value) = (self.check)() {
+            return Poll::Ready(value);
+        }
+
+        interrupts::register_waker(self.source, cx.waker());
+
+        // interrupt could come between the check and registration
+        match (self.check)() {
+            Some(value) => Poll::Ready(value),
+            None => Poll::Pending,
+        }
+    }
+}
+
+
+/// Waits for the end of i2c transaction (any i2c interrupt), same as `sdc::machines::Set`.
+pub async fn i2c_transaction() -> Result<(), I2CTransmissionError> {
+    let pending_interrupts = wait_interrupt(InterruptSource::I2c, || {
+        let pending_interrupts = interrupts::i2c_interrupt_get_and_clear(I2CInterruptStatus::all());
+        (!pending_interrupts.is_empty()).then_some(pending_interrupts)
+    }).await;
+    i2c_trace::status(pending_interrupts);
+
+    match I2CTransmissionError::from_interrupt_flags(pending_interrupts) {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+
+/// Future of `acquire_bus`.
+pub struct AcquireBus<'a, 'd, W, Q> {
+    io: &'a AsyncIo<'d, W, Q>,
+    user: I2CBusUser,
+}
+
+/// Waits until `user` owns the i2c bus (see `I2CBus::try_acquire`), woken when the bus is released.
+pub fn acquire_bus<'a, 'd, W, Q>(io: &'a AsyncIo<'d, W, Q>, user: I2CBusUser) -> AcquireBus<'a, 'd, W, Q> {
+    AcquireBus { io, user }
+}
+
+impl<W, Q> Future for AcquireBus<'_, '_, W, Q> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let acquired = self.io.i2c_bus.with(|bus| {
+            let acquired = bus.try_acquire(self.user);
+            if !acquired {
+                bus.register_waker(cx.waker());
+            }
+            acquired
+        });
+
+        if acquired { Poll::Ready(()) } else { Poll::Pending }
+    }
+}
+
+
+/// Output of `first`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Either<A, B> {
+    First(A),
+    Second(B),
+}
+
+/// Future of `first`.
+pub struct First<A, B> {
+    a: A,
+    b: B,
+}
+
+/// Waits for whichever of `a` and `b` is ready first (`a` is polled first), the other one is dropped.
+pub fn first<A: Future + Unpin, B: Future + Unpin>(a: A, b: B) -> First<A, B> {
+    First { a, b }
+}
+
+impl<A: Future + Unpin, B: Future + Unpin> Future for First<A, B> {
+    type Output = Either<A::Output, B::Output>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Poll::Ready(value) = Pin::new(&mut self.a).poll(cx) {
+            return Poll::Ready(Either::First(value));
+        }
+
+        Pin::new(&mut self.b).poll(cx).map(Either::Second)
+    }
+}
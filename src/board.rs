@@ -0,0 +1,73 @@
+use esp_hal::gpio::{GpioPin, Pins};
+
+
+
+/// Pin map of the board, change pin numbers here to build the firmware for different board layout.
+///
+/// Drivers derive pin numbers from pin types (see `pac_utils::gpio::PinNumber`), so nothing else has to be edited.
+pub struct BoardPins {
+    pub i2c_scl: GpioPin<4>,
+    pub i2c_sda: GpioPin<5>,
+    /// scd30 data ready pin
+    pub sdc_ready: GpioPin<6>,
+    /// bit-banged bus of second scd30 (feature `second-sdc`), external pull ups are needed
+    #[cfg(feature = "second-sdc")]
+    pub sdc_secondary_scl: GpioPin<21>,
+    #[cfg(feature = "second-sdc")]
+    pub sdc_secondary_sda: GpioPin<22>,
+    #[cfg(feature = "second-sdc")]
+    pub sdc_secondary_ready: GpioPin<23>,
+    /// single color status led, red channel of rgb status led (feature `rgb-led`)
+    pub status_led: GpioPin<7>,
+    #[cfg(feature = "rgb-led")]
+    pub status_led_green: GpioPin<18>,
+    #[cfg(feature = "rgb-led")]
+    pub status_led_blue: GpioPin<19>,
+    /// data of addressable led strip (feature `ws2812`, spi2 mosi), on board rgb led of devkit is on the same pin
+    #[cfg(feature = "ws2812")]
+    pub led_strip: GpioPin<8>,
+    /// co2 alert buzzer (active high), passive piezo buzzer driven by ledc (feature `piezo-buzzer`)
+    pub buzzer: GpioPin<3>,
+    /// ventilation relay (active high), pwm input of fan driven by ledc (feature `ventilation-pwm`)
+    pub ventilation: GpioPin<20>,
+    pub ir_rx: GpioPin<10>,
+    pub ir_tx: GpioPin<11>,
+    /// second ir led, driven by nec transmitter (`ir_tx` is driven by sony transmitter)
+    pub ir_nec_tx: GpioPin<2>,
+    /// push button to ground (boot button of devkit)
+    pub button: GpioPin<9>,
+    /// host output when usb writer is replaced by uart writer (feature `uart-output`)
+    #[cfg(feature = "uart-output")]
+    pub uart_tx: GpioPin<16>,
+}
+
+impl BoardPins {
+    pub fn new(pins: Pins) -> Self {
+        Self {
+            i2c_scl: pins.gpio4,
+            i2c_sda: pins.gpio5,
+            sdc_ready: pins.gpio6,
+            #[cfg(feature = "second-sdc")]
+            sdc_secondary_scl: pins.gpio21,
+            #[cfg(feature = "second-sdc")]
+            sdc_secondary_sda: pins.gpio22,
+            #[cfg(feature = "second-sdc")]
+            sdc_secondary_ready: pins.gpio23,
+            status_led: pins.gpio7,
+            #[cfg(feature = "rgb-led")]
+            status_led_green: pins.gpio18,
+            #[cfg(feature = "rgb-led")]
+            status_led_blue: pins.gpio19,
+            #[cfg(feature = "ws2812")]
+            led_strip: pins.gpio8,
+            buzzer: pins.gpio3,
+            ventilation: pins.gpio20,
+            ir_rx: pins.gpio10,
+            ir_tx: pins.gpio11,
+            ir_nec_tx: pins.gpio2,
+            button: pins.gpio9,
+            #[cfg(feature = "uart-output")]
+            uart_tx: pins.gpio16,
+        }
+    }
+}
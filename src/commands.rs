@@ -0,0 +1,187 @@
+/* commands of console, ir remote and button which are not handled by a single machine (output, logging, config, time),
+   machine commands are passed to machines by `Scheduler` (see `Machine::on_command`) */
+
+use core::fmt::Write;
+
+use esp_hal::timer::systimer::SystemTimer;
+
+#[cfg(not(feature = "async-sdc"))]
+use fugit::SecsDurationU32;
+
+use crate::{
+    config_store::{Config, MAX_IR_BINDINGS},
+    error_registry::{self, Subsystem},
+    fixed_point::Milli,
+    flight_recorder,
+    i2c_trace,
+    log::{self, info, log_line, Module},
+    machines::console::{self, ConsoleCommand},
+    metrics,
+    qq_alarm_queue::{QQOwner, TaggedQQAlarmQueue},
+    reboot,
+    scheduler::Context,
+    time,
+    usb_writer::UsbWriter,
+};
+#[cfg(not(feature = "async-sdc"))]
+use crate::{measurment_interval::{self, IntervalError}, sdc::SDCSetCommand};
+
+
+
+/// Handles `command`, returns `false` when it was rejected (e.g. interval out of range), rejected command is not passed
+/// to machines.
+pub fn handle<W, Q, const N: usize>(command: ConsoleCommand, context: &mut Context<'_, '_, W, Q, N>) -> bool
+where
+    W: Write + UsbWriter,
+    Q: TaggedQQAlarmQueue,
+{
+    let usb_writer = &mut *context.usb_writer;
+
+    match command {
+        ConsoleCommand::Errors => error_registry::write_and_clear_last_errors(usb_writer),
+        ConsoleCommand::LogLevels => log::write_levels(usb_writer),
+        ConsoleCommand::LogLevel { module, level } => {
+            match module {
+                Some(module) => log::set_level(module, level),
+                None => Module::ALL.into_iter().for_each(|module| log::set_level(module, level)),
+            }
+            log::write_levels(usb_writer);
+        },
+        // benchmark and dump are stopped by their machines, they write raw data which would break framing
+        ConsoleCommand::OutputMode(output_mode) => usb_writer.set_output_mode(output_mode),
+        ConsoleCommand::OverflowPolicy(overflow_policy) => {
+            info!(usb_writer, Module::Main, "overflow policy : {:?}", overflow_policy);
+            usb_writer.set_overflow_policy(overflow_policy);
+        },
+        ConsoleCommand::RecordFormat(record_format) => {
+            // reply is already a comment when the new format is csv or json
+            context.controller.set_record_format(record_format);
+            info!(usb_writer, Module::Controller, "record format : {}", record_format.name());
+        },
+        ConsoleCommand::Metadata => metrics::write_metadata(usb_writer),
+        ConsoleCommand::Summary => context.controller.write_summary(usb_writer),
+        ConsoleCommand::I2CTrace(enabled) => i2c_trace::set_enabled(enabled),
+        ConsoleCommand::Trace { entries } => flight_recorder::write_last(usb_writer, entries as usize),
+        ConsoleCommand::Reboot(mode) => {
+            log_line!(usb_writer, "reboot", "{:?}", mode);
+            // reply reaches the host unless it is not reading (100 ms)
+            let _ = usb_writer.flush_blocking(SystemTimer::TICKS_PER_SECOND / 10);
+
+            reboot::reboot(mode);
+        },
+        ConsoleCommand::Flush => {
+            let _ = usb_writer.flush(&mut context.qq.owned(QQOwner::UsbWriter));
+        },
+        ConsoleCommand::Time => {
+            match time::now_unix_ms() {
+                Some(unix_ms) => log_line!(usb_writer, "time", "{:.3} s (unix)", Milli(unix_ms as i64)),
+                None => log_line!(usb_writer, "time", "not synchronized"),
+            }
+        },
+        ConsoleCommand::SetTime { unix_ms } => time::set_unix_ms(unix_ms),
+        ConsoleCommand::ConfigShow => {
+            let config = &context.config;
+            log_line!(
+                usb_writer,
+                "config",
+                "interval {} s, co2 alert {:.1} / {:.1} ppm (hysteresis {:.1} ppm), ventilation {:.1} / {:.1} ppm, report host {}.{}.{}.{}, {} ir bindings",
+                config.interval_secs,
+                Milli::from(config.alert_warning),
+                Milli::from(config.alert_critical),
+                Milli::from(config.alert_hysteresis),
+                Milli::from(config.ventilation_on),
+                Milli::from(config.ventilation_off),
+                config.report_host[0],
+                config.report_host[1],
+                config.report_host[2],
+                config.report_host[3],
+                config.ir_bindings.iter().count(),
+            );
+        },
+        // machines using thresholds take them from the command (see `Alert::on_command`, `Ventilation::on_command`)
+        ConsoleCommand::AlertThresholds { warning, critical, hysteresis } => {
+            let hysteresis = hysteresis.unwrap_or(context.config.alert_hysteresis);
+
+            if !Config::alert_thresholds_valid(warning, critical, hysteresis) {
+                log_line!(usb_writer, "alert", "thresholds must be 0 <= hysteresis < warning < critical");
+                return false;
+            }
+
+            context.config.alert_warning = warning;
+            context.config.alert_critical = critical;
+            context.config.alert_hysteresis = hysteresis;
+        },
+        ConsoleCommand::VentilationThresholds { on, off } => {
+            if !Config::ventilation_thresholds_valid(on, off) {
+                log_line!(usb_writer, "ventilation", "thresholds must be 0 <= off < on");
+                return false;
+            }
+
+            context.config.ventilation_on = on;
+            context.config.ventilation_off = off;
+        },
+        // ir rx dispatch takes bindings from the config (see `IrRxDispatch::update`)
+        ConsoleCommand::IrBind(binding) => {
+            if !context.config.ir_bindings.bind(binding) {
+                log_line!(usb_writer, "ir bind", "all {} bindings are used", MAX_IR_BINDINGS);
+                return false;
+            }
+        },
+        ConsoleCommand::IrUnbind { protocol, address, ir_command } => {
+            if !context.config.ir_bindings.unbind(protocol, address, ir_command) {
+                log_line!(usb_writer, "ir unbind", "key is not bound");
+                return false;
+            }
+        },
+        ConsoleCommand::IrBindings => {
+            for binding in context.config.ir_bindings.iter() {
+                let repeat = if binding.repeat { " repeat" } else { "" };
+                log_line!(usb_writer, "ir binding", "{} {} {}{} : {}", console::ir_protocol_word(binding.protocol), binding.address, binding.ir_command, repeat, binding.command());
+            }
+        },
+        // wifi reporter takes the host from the command (see `Network::on_command`)
+        ConsoleCommand::ReportHost { host } => context.config.report_host = host,
+        // config is not saved automatically on every change, flash sectors have limited number of erase cycles
+        ConsoleCommand::ConfigSave => {
+            match context.config_store.save(context.config) {
+                Ok(()) => log_line!(usb_writer, "config", "saved"),
+                Err(err) => {
+                    error_registry::record_error(Subsystem::Config, &err);
+                    log_line!(usb_writer, "config", "save failed ({:?})", err)
+                },
+            }
+        },
+        // machines with timing derived from the interval get the command (see `Machine::on_command`), controller is
+        // not scheduled as a machine
+        #[cfg(not(feature = "async-sdc"))]
+        ConsoleCommand::Interval { seconds } => {
+            match measurment_interval::change_interval(SecsDurationU32::secs(seconds), &mut [&mut *context.controller]) {
+                Ok(()) => context.config.interval_secs = seconds,
+                Err(IntervalError::OutOfRange) => {
+                    log_line!(usb_writer, "interval", "must be {} - {} s", measurment_interval::MIN_INTERVAL_SECS, measurment_interval::MAX_INTERVAL_SECS);
+                    return false;
+                },
+            }
+        },
+        // checked here, so rejected reference is not passed to any sensor machine (there can be two, see `SecondarySDC`)
+        #[cfg(not(feature = "async-sdc"))]
+        ConsoleCommand::ForcedRecalibration { ppm } => {
+            if SDCSetCommand::forced_recalibration(ppm).is_err() {
+                log_line!(usb_writer, "frc", "must be {} - {} ppm", SDCSetCommand::FORCED_RECALIBRATION_MIN_PPM, SDCSetCommand::FORCED_RECALIBRATION_MAX_PPM);
+                return false;
+            }
+        },
+        #[cfg(feature = "async-sdc")]
+        ConsoleCommand::Interval { .. } |
+        ConsoleCommand::SdcStop |
+        ConsoleCommand::SdcToggle |
+        ConsoleCommand::SdcStart |
+        ConsoleCommand::ForcedRecalibration { .. } => {
+            log_line!(usb_writer, "scd30", "not supported by async task");
+            return false;
+        },
+        _ => {},
+    }
+
+    true
+}
@@ -0,0 +1,219 @@
+use core::{cell::Cell, fmt::Write};
+
+use critical_section::Mutex;
+use esp_hal::timer::systimer::SystemTimer;
+
+use crate::{alarm_table::QQAlarmError, config_store::ConfigStoreError, ir_learning::IrCodeStoreError, fixed_point::Milli, flight_recorder::{self, TraceEvent}, framing::FrameType, ir::IrDecodeError, log::log_line, usb_writer::{UsbOutputMode, UsbWriter}};
+
+
+
+/// Part of the firmware which can report errors, each subsystem has one last error slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subsystem {
+    Usb,
+    Sdc,
+    AmbientSensor,
+    IrRx,
+    IrTx,
+    Qq,
+    Config,
+    FlashLog,
+    Wifi,
+    Mqtt,
+    Http,
+    Watchdog,
+    LedStrip,
+    Display,
+    PressureSensor,
+}
+
+impl Subsystem {
+    pub const COUNT: usize = 15;
+    pub const ALL: [Subsystem; Subsystem::COUNT] = [Subsystem::Usb, Subsystem::Sdc, Subsystem::AmbientSensor, Subsystem::IrRx, Subsystem::IrTx, Subsystem::Qq, Subsystem::Config, Subsystem::FlashLog, Subsystem::Wifi, Subsystem::Mqtt, Subsystem::Http, Subsystem::Watchdog, Subsystem::LedStrip, Subsystem::Display, Subsystem::PressureSensor];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Subsystem::Usb => "usb",
+            Subsystem::Sdc => "sdc",
+            Subsystem::AmbientSensor => "ambient sensor",
+            Subsystem::IrRx => "ir rx",
+            Subsystem::IrTx => "ir tx",
+            Subsystem::Qq => "qq",
+            Subsystem::Config => "config",
+            Subsystem::FlashLog => "flash log",
+            Subsystem::Wifi => "wifi",
+            Subsystem::Mqtt => "mqtt",
+            Subsystem::Http => "http",
+            Subsystem::Watchdog => "watchdog",
+            Subsystem::LedStrip => "led strip",
+            Subsystem::Display => "display",
+            Subsystem::PressureSensor => "pressure sensor",
+        }
+    }
+}
+
+
+/// Numeric code of an error, stored in the registry instead of the error itself.
+///
+/// Codes are unique within one subsystem:
+/// - `0x01 - 0x0f` - device responses (crc, format) and value parsing
+/// - `0x10 - 0x1f` - i2c write, low nibble are i2c error flags (see `I2CTransmissionError`)
+/// - `0x20 - 0x2f` - i2c read, same low nibble
+/// - `0x30 - 0x3f` - rmt
+/// - `0x40 - 0x4f` - ir decoding
+/// - `0x50 - 0x5f` - qq alarm queue
+/// - `0x60 - 0x6f` - flash (config store, measurment log)
+/// - `0x70 - 0x7f` - wifi
+/// - `0x80 - 0x8f` - mqtt
+/// - `0x90 - 0x9f` - http
+/// - `0xa0 - 0xaf` - watchdog (missed heartbeat)
+pub trait ErrorCode {
+    fn error_code(&self) -> u16;
+}
+
+// logic crate does not know about the registry, codes of its errors are defined here
+impl ErrorCode for IrDecodeError {
+    fn error_code(&self) -> u16 {
+        0x40
+    }
+}
+
+impl ErrorCode for QQAlarmError {
+    fn error_code(&self) -> u16 {
+        match self {
+            QQAlarmError::QueueFull => 0x50,
+            QQAlarmError::IdNotFound => 0x51,
+        }
+    }
+}
+
+impl<E: ErrorCode> ErrorCode for ConfigStoreError<E> {
+    fn error_code(&self) -> u16 {
+        match self {
+            ConfigStoreError::Flash(err) => err.error_code(),
+            ConfigStoreError::VerifyFailed => 0x6f,
+        }
+    }
+}
+
+impl<E: ErrorCode> ErrorCode for IrCodeStoreError<E> {
+    fn error_code(&self) -> u16 {
+        match self {
+            IrCodeStoreError::Flash(err) => err.error_code(),
+            IrCodeStoreError::SlotOutOfRange => 0x6d,
+            IrCodeStoreError::VerifyFailed => 0x6e,
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorRecord {
+    pub code: u16,
+    /// system timer ticks of the last occurrence
+    pub at: u64,
+    /// number of occurrences since the slot was cleared
+    pub count: u32,
+}
+
+
+static LAST_ERRORS: [Mutex<Cell<Option<ErrorRecord>>>; Subsystem::COUNT] = [const { Mutex::new(Cell::new(None)) }; Subsystem::COUNT];
+/// Bit per subsystem, set when an error is recorded and cleared when it is sent as error frame (see `write_error_frames`).
+static UNREPORTED: Mutex<Cell<u32>> = Mutex::new(Cell::new(0));
+
+
+
+/// Stores `code` as the last error of `subsystem`, errors are kept until cleared (even if the subsystem recovers).
+pub fn record(subsystem: Subsystem, code: u16) {
+    let at = SystemTimer::now();
+    flight_recorder::record(TraceEvent::Error, (subsystem as u32) << 16 | code as u32);
+
+    critical_section::with(|cs| {
+        let slot = LAST_ERRORS[subsystem as usize].borrow(cs);
+        let count = slot.get().map_or(0, |record| record.count).saturating_add(1);
+        slot.set(Some(ErrorRecord { code, at, count }));
+
+        let unreported = UNREPORTED.borrow(cs);
+        unreported.set(unreported.get() | (1 << subsystem as u32));
+    });
+}
+
+pub fn record_error(subsystem: Subsystem, error: &impl ErrorCode) {
+    record(subsystem, error.error_code());
+}
+
+pub fn last_error(subsystem: Subsystem) -> Option<ErrorRecord> {
+    critical_section::with(|cs| LAST_ERRORS[subsystem as usize].borrow(cs).get())
+}
+
+/// Returns last error and clears the slot.
+pub fn take_last_error(subsystem: Subsystem) -> Option<ErrorRecord> {
+    critical_section::with(|cs| LAST_ERRORS[subsystem as usize].borrow(cs).take())
+}
+
+
+
+fn write_record(writer: &mut impl Write, subsystem: Subsystem, record: &ErrorRecord) {
+    let at_ms = (record.at / (SystemTimer::TICKS_PER_SECOND / 1_000)) as i64;
+
+    log_line!(writer, "last error", "{} code 0x{:02x} at {:.1} s ({}x)", subsystem.name(), record.code, Milli(at_ms), record.count);
+}
+
+/// Writes all non empty slots, slots are not cleared (used by periodic status outputs).
+pub fn write_last_errors(writer: &mut impl Write) {
+    for subsystem in Subsystem::ALL {
+        if let Some(record) = last_error(subsystem) {
+            write_record(writer, subsystem, &record);
+        }
+    }
+}
+
+/// Writes all non empty slots and clears them (clear-on-read, used by console).
+pub fn write_and_clear_last_errors(writer: &mut impl Write) {
+    let mut any = false;
+
+    for subsystem in Subsystem::ALL {
+        if let Some(record) = take_last_error(subsystem) {
+            write_record(writer, subsystem, &record);
+            any = true;
+        }
+    }
+
+    if !any {
+        log_line!(writer, "last error", "none");
+    }
+}
+
+/// Sends each error recorded since the last call as `FrameType::Error` frame (framed mode only, in text mode errors are only forgotten).
+///
+/// Payload (little endian): subsystem index in `Subsystem::ALL` (u8), code (u16), at (u64), count (u32).
+/// Only the last error of each subsystem is sent, errors recorded in between are merged (see count).
+pub fn write_error_frames(usb_writer: &mut impl UsbWriter) -> bool {
+    let unreported = critical_section::with(|cs| UNREPORTED.borrow(cs).take());
+
+    if unreported == 0 || usb_writer.output_mode() != UsbOutputMode::Framed {
+        return false;
+    }
+
+    for subsystem in Subsystem::ALL {
+        if unreported & (1 << subsystem as u32) == 0 {
+            continue;
+        }
+
+        let Some(record) = last_error(subsystem) else {
+            // cleared by console in between
+            continue;
+        };
+
+        let mut payload = [0u8; 15];
+        payload[0] = subsystem as u8;
+        payload[1..3].copy_from_slice(&record.code.to_le_bytes());
+        payload[3..11].copy_from_slice(&record.at.to_le_bytes());
+        payload[11..15].copy_from_slice(&record.count.to_le_bytes());
+
+        // dropped frame is not repeated, the error is still in its slot (console `errors`)
+        let _ = usb_writer.write_frame(FrameType::Error, &payload);
+    }
+
+    true
+}
@@ -0,0 +1,78 @@
+/* events of the firmware and subscriptions of machines, machines publish events and poll events of their subscription
+   in `update` (bus itself is in the logic crate, see `rust_esp_logic::event_bus`) */
+
+use rust_esp_logic::event_bus::{self, Subscription};
+
+use crate::{
+    machines::{alert::AlertLevel, ambient_sensor::AmbientReading, console::ConsoleCommand},
+    sdc::{Measurment, SensorId}
+};
+
+
+
+/// Number of events queued for each subscriber, events published when the queue is full are dropped (for that subscriber only).
+pub const QUEUE_LEN: usize = 8;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    /// scd30 measurment of `sensor`, `at` is system timer ticks when it was ready (data ready edge), not when it was
+    /// read, `None` when the edge was not captured (ready found by poll)
+    Measurment { sensor: SensorId, measurment: Measurment, at: Option<u64> },
+    /// scd30 `sensor` started measuring (after initialization), measurments before it are not despiked together with
+    /// the following ones
+    SensorStarted(SensorId),
+    /// failed scd30 transaction of the sensor (including errors recovered by retry)
+    SensorError(SensorId),
+    /// scd30 measurment of `sensor` outside of documented ranges, it is not published (see `Measurment::check_range`)
+    MeasurmentRejected { sensor: SensorId },
+    Ambient(AmbientReading),
+    /// in Pa, from sensor which does not measure temperature and humidity used by the controller (e.g. `Bme280`)
+    // published only by pressure sensor (feature `bme280`)
+    #[cfg_attr(not(feature = "bme280"), allow(dead_code))]
+    Pressure(u32),
+    /// command of console, received ir code (see `IrRxDispatch`) or button, handled by `Scheduler`
+    Command(ConsoleCommand),
+    /// applied (debounced) co2 alert level
+    AlertLevel(AlertLevel),
+}
+
+
+/// Machine (or `Scheduler`) receiving events, each subscriber has its own queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subscriber {
+    Controller,
+    StalenessMonitor,
+    /// commands handled by `Scheduler` (see `commands::handle`, `Machine::on_command`)
+    Commands,
+    Display,
+}
+
+impl Subscriber {
+    /// number of queues of `EventBus`
+    pub const COUNT: usize = 4;
+}
+
+const _: () = assert!(<Subscriber as Subscription<Event>>::ALL.len() == Subscriber::COUNT, "queue for each subscriber");
+
+impl Subscription<Event> for Subscriber {
+    const ALL: &'static [Subscriber] = &[Subscriber::Controller, Subscriber::StalenessMonitor, Subscriber::Commands, Subscriber::Display];
+
+    fn index(&self) -> usize {
+        *self as usize
+    }
+
+    /// Subscription of each subscriber.
+    fn wants(&self, event: &Event) -> bool {
+        match self {
+            Subscriber::Controller => matches!(event, Event::Measurment { .. } | Event::SensorStarted(_) | Event::SensorError(_) | Event::MeasurmentRejected { .. } | Event::Ambient(_) | Event::Pressure(_)),
+            Subscriber::StalenessMonitor => matches!(event, Event::Measurment { .. } | Event::MeasurmentRejected { .. }),
+            Subscriber::Commands => matches!(event, Event::Command(_)),
+            Subscriber::Display => cfg!(feature = "oled-display") && matches!(event, Event::AlertLevel(_)),
+        }
+    }
+}
+
+
+/// Event bus of the firmware (see `rust_esp_logic::event_bus::EventBus`).
+pub type EventBus = event_bus::EventBus<Event, Subscriber, { Subscriber::COUNT }, QUEUE_LEN>;
@@ -0,0 +1,95 @@
+/* flight recorder: ring of the last interesting transitions of machines, dumped by `trace` command and by the panic handler */
+
+use core::{cell::RefCell, fmt::Write};
+
+use critical_section::Mutex;
+use esp_hal::timer::systimer::SystemTimer;
+
+use crate::{
+    fixed_point::Milli,
+    log::log_line,
+    ring_buffer::{Overwrite, RingBuffer}
+};
+
+
+
+/// Number of kept entries, older entries are overwritten.
+pub const RECORDER_LEN: usize = 64;
+
+
+/// What happened, `arg` of the entry is described for each event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceEvent {
+    /// arg - 0
+    Boot,
+    /// arg - subsystem index in high half, error code in low half (see `error_registry::record`)
+    Error,
+    /// arg - co2 in ppm
+    SdcMeasurment,
+    /// arg - number of consecutive errors
+    SdcRetry,
+    /// arg - 0, scd30 gave up after retries (error state)
+    SdcFailed,
+    /// arg - 1 when the bus is free afterwards
+    I2CRecovery,
+    /// arg - new alert level index
+    AlertLevel,
+}
+
+impl TraceEvent {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TraceEvent::Boot => "boot",
+            TraceEvent::Error => "error",
+            TraceEvent::SdcMeasurment => "scd30 measurment",
+            TraceEvent::SdcRetry => "scd30 retry",
+            TraceEvent::SdcFailed => "scd30 failed",
+            TraceEvent::I2CRecovery => "i2c recovery",
+            TraceEvent::AlertLevel => "alert level",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TraceRecord {
+    /// system timer ticks
+    at: u64,
+    event: TraceEvent,
+    arg: u32,
+}
+
+
+static RECORDER: Mutex<RefCell<RingBuffer<TraceRecord, RECORDER_LEN, Overwrite>>> = Mutex::new(RefCell::new(RingBuffer::new()));
+
+
+
+/// Cheap enough to be called from any machine (one short critical section).
+pub fn record(event: TraceEvent, arg: u32) {
+    let at = SystemTimer::now();
+
+    critical_section::with(|cs| RECORDER.borrow_ref_mut(cs).push_back(TraceRecord { at, event, arg }));
+}
+
+/// Writes last `count` entries (oldest first), times are relative to now.
+///
+/// Entries are copied out one by one, so the recorder is not borrowed while writing. Used also by the panic handler,
+/// nothing is written when the recorder was borrowed by the panicking code.
+pub fn write_last(w: &mut impl Write, count: usize) {
+    let now = SystemTimer::now();
+
+    let (len, skip) = match critical_section::with(|cs| RECORDER.borrow(cs).try_borrow().map(|recorder| recorder.len())) {
+        Ok(len) => (len, len.saturating_sub(count)),
+        Err(_) => return,
+    };
+
+    log_line!(w, "trace", "last {} of {} entries", len - skip, len);
+
+    for i in skip..len {
+        let Some(record) = critical_section::with(|cs| RECORDER.borrow(cs).try_borrow().ok().and_then(|recorder| recorder.get(i).copied())) else {
+            return;
+        };
+
+        let ago = (now.saturating_sub(record.at) * 1_000 / SystemTimer::TICKS_PER_SECOND) as i64;
+        log_line!(w, "trace", "-{:.3} s {:<16} 0x{:08x}", Milli(ago), record.event.name(), record.arg);
+    }
+}
@@ -0,0 +1,74 @@
+/* heartbeats of machines supervised by the watchdog (see `machines::watchdog::Watchdog`) */
+
+use core::cell::Cell;
+
+use critical_section::Mutex;
+use esp_hal::timer::systimer::SystemTimer;
+
+
+
+/// Machine which reports that it is not stuck, each one has one slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Heartbeat {
+    /// bus is free or was released recently (transaction did not hang)
+    I2cBus,
+    /// scd30 machine got back to an idle state (waiting for data ready, stopped)
+    Sdc,
+    /// same as `Sdc`, second scd30 (see `SecondarySDC`)
+    SdcSecondary,
+}
+
+impl Heartbeat {
+    pub const COUNT: usize = 3;
+    pub const ALL: [Heartbeat; Heartbeat::COUNT] = [Heartbeat::I2cBus, Heartbeat::Sdc, Heartbeat::SdcSecondary];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Heartbeat::I2cBus => "i2c bus",
+            Heartbeat::Sdc => "scd30",
+            Heartbeat::SdcSecondary => "scd30 #1",
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct HeartbeatSlot {
+    /// in system timer ticks
+    timeout: u64,
+    last_beat_at: u64,
+}
+
+
+static HEARTBEATS: [Mutex<Cell<Option<HeartbeatSlot>>>; Heartbeat::COUNT] = [const { Mutex::new(Cell::new(None)) }; Heartbeat::COUNT];
+
+
+
+/// Machine is supervised from now on, it has to `beat` at least once per `timeout` (in system timer ticks).
+pub fn register(heartbeat: Heartbeat, timeout: u64) {
+    let last_beat_at = SystemTimer::now();
+
+    critical_section::with(|cs| HEARTBEATS[heartbeat as usize].borrow(cs).set(Some(HeartbeatSlot { timeout, last_beat_at })));
+}
+
+/// Does nothing when `heartbeat` is not registered.
+pub fn beat(heartbeat: Heartbeat) {
+    let now = SystemTimer::now();
+
+    critical_section::with(|cs| {
+        let slot = HEARTBEATS[heartbeat as usize].borrow(cs);
+        if let Some(record) = slot.get() {
+            slot.set(Some(HeartbeatSlot { last_beat_at: now, ..record }));
+        }
+    });
+}
+
+/// First registered heartbeat which did not beat within its timeout and ticks since its last beat.
+pub fn overdue(now: u64) -> Option<(Heartbeat, u64)> {
+    Heartbeat::ALL.into_iter().find_map(|heartbeat| {
+        let slot = critical_section::with(|cs| HEARTBEATS[heartbeat as usize].borrow(cs).get())?;
+        let since = now.saturating_sub(slot.last_beat_at);
+
+        (since > slot.timeout).then_some((heartbeat, since))
+    })
+}
@@ -0,0 +1,324 @@
+use core::task::Waker;
+
+use esp_hal::{
+    clock::Clocks,
+    gpio::{any_pin::AnyPin, CreateErasedPin, InputPin, OutputOpenDrain, OutputPin},
+    peripheral::{Peripheral, PeripheralRef},
+    peripherals::I2C0,
+    timer::systimer::SystemTimer
+};
+
+use fugit::HertzU32;
+
+use crate::{
+    bus_timing,
+    flight_recorder::{self, TraceEvent},
+    heartbeat::{self, Heartbeat},
+    i2c_engine::{I2CEngine, I2CEngineError, I2COperation, MAX_RESPONSE_LEN, MAX_WRITE_LEN},
+    i2c_trace,
+    interrupts::{self, I2CInterruptStatus},
+    invariants::invariant,
+    machines::State,
+    pac_utils::{gpio::PinNumber, i2c::{self as i2c_utils, I2CTransmissionError, ShortRead}},
+    qq_alarm_queue::QQAlarmQueue
+};
+
+
+
+/// Identifies machine (device driver) using the shared bus, at most `I2CBus::MAX_USERS` users (`0..MAX_USERS`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct I2CBusUser(pub u8);
+
+impl I2CBusUser {
+    fn bit(&self) -> u32 {
+        1 << self.0
+    }
+}
+
+
+/// Bus of simple transactions (one write or one read) used by scd30 driver (see `sdc`), implemented by `I2CBus` (i2c0,
+/// end of transaction is signaled by interrupt) and `SoftI2CBus` (bit-banged, transaction progresses when its result is polled).
+///
+/// Same rules as for `I2CBus`: bus has to be acquired before starting a transaction and only one transaction can be
+/// in progress at the time.
+pub trait TransactionBus {
+    fn try_acquire(&mut self, user: I2CBusUser) -> bool;
+    fn release(&mut self, user: I2CBusUser);
+    /// Frees stuck bus after failed transaction, returns `true` when the bus is free afterwards.
+    fn recover(&mut self) -> bool;
+
+    /// Starts write of `bytes` to device at `address`, panics when there are more than 31 bytes.
+    fn start_write(&mut self, address: u8, bytes: &[u8]);
+    /// Starts read of `len` bytes from device at `address` (taken by `read_response_into`), panics when `len` is not
+    /// in `1..=31`.
+    fn start_read(&mut self, address: u8, len: u8);
+    /// Result of the started transaction, `None` while it is in progress. Result is returned only once.
+    fn transaction_result(&mut self) -> Option<Result<(), I2CTransmissionError>>;
+    /// `true` while the started transaction progresses only when its result is polled (bit-banged bus), so the machine
+    /// has to keep main loop running instead of waiting for an interrupt.
+    // only bus of the second sensor is bit-banged (feature `second-sdc`)
+    #[cfg_attr(not(feature = "second-sdc"), allow(dead_code))]
+    fn needs_poll(&self) -> bool {
+        false
+    }
+    /// Takes next bytes of finished read (response can be taken in parts), nothing is taken when there are fewer than
+    /// `buffer.len()` bytes left.
+    fn read_response_into(&mut self, buffer: &mut [u8]) -> Result<(), ShortRead>;
+
+    fn read_response<const N: usize>(&mut self) -> Result<[u8; N], ShortRead> where Self: Sized {
+        let mut buffer = [0u8; N];
+        self.read_response_into(&mut buffer)?;
+
+        Ok(buffer)
+    }
+}
+
+
+/// I2C0 bus shared by multiple machines (drivers of devices connected to the bus).
+///
+/// Interrupt flags of the I2C peripheral are shared, so only one transaction can be in progress at the time.
+/// Machine has to `try_acquire` the bus before starting a transaction and `release` it after the result of the transaction was consumed.
+/// Machines borrow the bus in their `update` methods (same as qq alarm queue).
+///
+/// Free bus is handed out round-robin (by user number, starting after the last owner) among users waiting for it,
+/// so a machine acquiring the bus often (e.g. after each short transaction) cannot starve others.
+/// User is waiting when its `try_acquire` failed in the current or previous main loop iteration (see `update`),
+/// so machine which stopped asking for the bus does not block others.
+///
+/// Bus owned for longer than `HEARTBEAT_TIMEOUT` (e.g. transaction waiting forever for its interrupt) is reported by watchdog.
+pub struct I2CBus<'a> {
+    i2c: PeripheralRef<'a, I2C0>,
+    scl_pin: OutputOpenDrain<'a, AnyPin<'a>>,
+    sda_pin: OutputOpenDrain<'a, AnyPin<'a>>,
+    /// gpio numbers of erased pins (for bus recovery)
+    scl_num: u8,
+    sda_num: u8,
+    owner: Option<I2CBusUser>,
+    last_owner: Option<I2CBusUser>,
+    /// bit for each user (see `I2CBusUser::bit`)
+    waiting: u32,
+    /// users which failed to acquire the bus since the last `update`
+    waiting_refreshed: u32,
+    /// async user waiting for the bus, woken by `release` (see `register_waker`)
+    waker: Option<Waker>,
+}
+
+impl<'a> I2CBus<'a> {
+    pub const MAX_USERS: u8 = 32;
+    /// longest time the bus can be owned by one user (commands with delayed read take few ms, see `bus_timing`)
+    pub const HEARTBEAT_TIMEOUT: u64 = SystemTimer::TICKS_PER_SECOND / 1_000 * bus_timing::I2C_HEARTBEAT_TIMEOUT_MS;
+
+
+    pub fn new<SCL, SDA>(
+        i2c: impl Peripheral<P = I2C0> + 'a,
+        scl_pin: impl Peripheral<P = SCL> + 'a,
+        sda_pin: impl Peripheral<P = SDA> + 'a,
+        freq: HertzU32,
+        clocks: &Clocks,
+    ) -> Self
+    where
+        SCL: OutputPin + InputPin + CreateErasedPin + PinNumber,
+        SDA: OutputPin + InputPin + CreateErasedPin + PinNumber,
+    {
+        let mut i2c = i2c.into_ref();
+
+        i2c_utils::setup(i2c.reborrow(), freq, clocks);
+
+        let (scl_pin, sda_pin) = i2c_utils::setup_pins(AnyPin::new(scl_pin), SCL::NUMBER, AnyPin::new(sda_pin), SDA::NUMBER);
+
+        heartbeat::register(Heartbeat::I2cBus, Self::HEARTBEAT_TIMEOUT);
+
+        Self {
+            i2c,
+            scl_pin,
+            sda_pin,
+            scl_num: SCL::NUMBER,
+            sda_num: SDA::NUMBER,
+            owner: None,
+            last_owner: None,
+            waiting: 0,
+            waiting_refreshed: 0,
+            waker: None,
+        }
+    }
+
+    /// Forgets users which did not ask for the bus since the previous call, should be called once in each main loop iteration.
+    pub fn update(&mut self) {
+        self.waiting = self.waiting_refreshed;
+        self.waiting_refreshed = 0;
+
+        if self.owner.is_none() {
+            heartbeat::beat(Heartbeat::I2cBus);
+        }
+    }
+
+    /// User which gets the free bus next, `user` is also counted as waiting.
+    fn next_user(&self, user: I2CBusUser) -> I2CBusUser {
+        let waiting = self.waiting | user.bit();
+        let start = self.last_owner.map_or(0, |last_owner| last_owner.0 + 1);
+
+        (0..Self::MAX_USERS)
+            .map(|i| (start + i) % Self::MAX_USERS)
+            .find(|i| waiting & (1 << i) != 0)
+            .map_or(user, I2CBusUser)
+    }
+
+    /// Returns `true` when bus was free and it is `user`'s turn or bus was already owned by `user`, `user` owns the bus afterwards.
+    ///
+    /// Panics when `user` is not smaller than `MAX_USERS`.
+    pub fn try_acquire(&mut self, user: I2CBusUser) -> bool {
+        assert!(user.0 < Self::MAX_USERS);
+
+        match self.owner {
+            None if self.next_user(user) == user => {
+                self.owner = Some(user);
+                self.waiting &= !user.bit();
+                self.waiting_refreshed &= !user.bit();
+                true
+            },
+            Some(owner) if owner == user => true,
+            _ => {
+                self.waiting |= user.bit();
+                self.waiting_refreshed |= user.bit();
+                false
+            },
+        }
+    }
+
+    pub fn release(&mut self, user: I2CBusUser) {
+        if invariant!(self.owner == Some(user), "i2c bus released by user which does not own it") {
+            self.owner = None;
+            self.last_owner = Some(user);
+            heartbeat::beat(Heartbeat::I2cBus);
+
+            if let Some(waker) = self.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+
+    /// `waker` is woken when the bus is released, for async users after failed `try_acquire` (see `async_io::acquire_bus`).
+    ///
+    /// Only one waker is kept, different waker replaces (and wakes) the previous one.
+    #[cfg(feature = "async-sdc")]
+    pub fn register_waker(&mut self, waker: &Waker) {
+        match &self.waker {
+            Some(registered) if registered.will_wake(waker) => {},
+            _ => {
+                if let Some(previous) = self.waker.replace(waker.clone()) {
+                    previous.wake();
+                }
+            },
+        }
+    }
+
+    /// Frees stuck bus and resets the peripheral (see `i2c_utils::recover_bus`), should be used only by the current owner
+    /// after failed transaction. Returns `true` when the bus is free afterwards.
+    pub fn recover(&mut self) -> bool {
+        let free = i2c_utils::recover_bus(self.i2c.reborrow(), &mut self.scl_pin, self.scl_num, &mut self.sda_pin, self.sda_num);
+        flight_recorder::record(TraceEvent::I2CRecovery, free as u32);
+
+        free
+    }
+
+    /// I2C peripheral, should be used only by the current owner of the bus.
+    pub fn i2c(&mut self) -> PeripheralRef<'_, I2C0> {
+        self.i2c.reborrow()
+    }
+}
+
+
+
+impl TransactionBus for I2CBus<'_> {
+    fn try_acquire(&mut self, user: I2CBusUser) -> bool {
+        I2CBus::try_acquire(self, user)
+    }
+
+    fn release(&mut self, user: I2CBusUser) {
+        I2CBus::release(self, user)
+    }
+
+    fn recover(&mut self) -> bool {
+        I2CBus::recover(self)
+    }
+
+    fn start_write(&mut self, address: u8, bytes: &[u8]) {
+        assert!(bytes.len() < i2c_utils::FIFO_LEN);
+
+        // SAFETY: length checked above
+        unsafe { i2c_utils::do_write(self.i2c(), address, bytes) };
+    }
+
+    fn start_read(&mut self, address: u8, len: u8) {
+        assert!(len != 0 && (len as usize) < i2c_utils::FIFO_LEN);
+
+        // SAFETY: length checked above
+        unsafe { i2c_utils::do_read(self.i2c(), address, len) };
+    }
+
+    fn transaction_result(&mut self) -> Option<Result<(), I2CTransmissionError>> {
+        let pending_interrupts = interrupts::i2c_interrupt_get_and_clear(I2CInterruptStatus::all());
+
+        if pending_interrupts.is_empty() {
+            return None;
+        }
+        i2c_trace::status(pending_interrupts);
+
+        match I2CTransmissionError::from_interrupt_flags(pending_interrupts) {
+            Some(err) => Some(Err(err)),
+            None => Some(Ok(())),
+        }
+    }
+
+    fn read_response_into(&mut self, buffer: &mut [u8]) -> Result<(), ShortRead> {
+        i2c_utils::read_response_into(self.i2c(), buffer)
+    }
+}
+
+
+
+/// Generic (device independent) transaction: write command, wait `delta`, read `read_len` bytes (see `I2CEngine`).
+/// After it is done, response can be read with `response`.
+#[derive(Debug)]
+pub struct DelayedWriteRead {
+    engine: I2CEngine<3>,
+}
+
+impl DelayedWriteRead {
+    /// Maximum length of write and read part.
+    pub const MAX_LEN: usize = if MAX_WRITE_LEN < MAX_RESPONSE_LEN { MAX_WRITE_LEN } else { MAX_RESPONSE_LEN };
+    /// not acknowledged write or read (e.g. device is still measuring) is repeated
+    const MAX_RETRIES: u8 = 2;
+
+
+    /// Panics when `bytes` or `read_len` is longer than `MAX_LEN`.
+    pub fn start(address: u8, bytes: &[u8], read_len: u8, delta: u64) -> DelayedWriteRead {
+        assert!(bytes.len() <= Self::MAX_LEN && read_len as usize <= Self::MAX_LEN && read_len != 0);
+
+        let mut engine = I2CEngine::new(Self::MAX_RETRIES);
+
+        // cannot fail, lengths checked above and queue has space for 3 operations
+        let _ = I2COperation::write(address, bytes).map(|write| engine.push(write));
+        let _ = engine.push(I2COperation::Delay(delta));
+        let _ = engine.push(I2COperation::Read { address, len: read_len as usize });
+
+        engine.start();
+
+        DelayedWriteRead {
+            engine,
+        }
+    }
+
+    pub fn update(&mut self, qq: &mut impl QQAlarmQueue, i2c: PeripheralRef<I2C0>) -> State<Result<(), I2CEngineError>> {
+        self.engine.update(qq, i2c)
+    }
+
+    /// Read data, valid after the transaction is done.
+    pub fn response(&self) -> &[u8] {
+        self.engine.response()
+    }
+
+    pub fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        self.engine.on_alarm(qq_alarm_id)
+    }
+}
@@ -0,0 +1,290 @@
+/* interrupt driven i2c transactions longer than fifo, split into chunks (bus is held between chunks with end command) */
+
+use esp_hal::{peripheral::PeripheralRef, peripherals::I2C0, timer::systimer::SystemTimer};
+
+use heapless::{Deque, Vec};
+
+use crate::{
+    error_registry::ErrorCode,
+    i2c_trace,
+    interrupts::{self, I2CInterruptStatus},
+    machines::{Delay, State},
+    pac_utils::i2c::{self as i2c_utils, I2CCommand, I2CTransmissionError, ShortRead},
+    qq_alarm_queue::QQAlarmQueue
+};
+
+
+
+/// Maximum length of data written by one operation.
+pub const MAX_WRITE_LEN: usize = 64;
+/// Maximum length of data read by all operations of one run (see `I2CEngine::response`).
+pub const MAX_RESPONSE_LEN: usize = 64;
+
+
+/// One operation of the engine queue, each write and read is separate i2c transaction (start ... stop).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum I2COperation {
+    Write {
+        address: u8,
+        bytes: Vec<u8, MAX_WRITE_LEN>,
+    },
+    Read {
+        address: u8,
+        len: usize,
+    },
+    /// wait (in system timer ticks) before next operation, bus is not used but it is still owned
+    Delay(u64),
+}
+
+impl I2COperation {
+    /// Returns `None` when `bytes` is longer than `MAX_WRITE_LEN`.
+    pub fn write(address: u8, bytes: &[u8]) -> Option<I2COperation> {
+        Some(I2COperation::Write {
+            address,
+            bytes: Vec::from_slice(bytes).ok()?,
+        })
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum I2CEngineError {
+    Write(I2CTransmissionError),
+    Read(I2CTransmissionError),
+    /// read transaction finished with fewer bytes in rx fifo than its chunk
+    ShortRead(ShortRead),
+}
+
+impl ErrorCode for I2CEngineError {
+    fn error_code(&self) -> u16 {
+        match self {
+            I2CEngineError::Write(err) => 0x10 | err.flags_nibble(),
+            I2CEngineError::Read(err) => 0x20 | err.flags_nibble(),
+            I2CEngineError::ShortRead(err) => err.error_code(),
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy)]
+enum I2CEngineState {
+    Idle,
+    /// operation at the front of the queue should be started
+    Next,
+    /// chunk of operation at the front of the queue is being transferred, `done` bytes were transferred by previous chunks
+    Transfer {
+        done: usize,
+        chunk_len: usize,
+        last: bool,
+    },
+    Delay(Delay),
+    Done,
+}
+
+/// Executes queue of operations (writes, reads and delays) one after another, user has to own the bus (see `I2CBus`).
+///
+/// Transfers longer than fifo are split into chunks, fifo is refilled (or drained) after each chunk.
+/// Write or read which was not acknowledged is repeated (from the beginning) at most `max_retries` times,
+/// other errors end the run and remaining operations are dropped.
+///
+/// Data read by all operations of the run are concatenated in `response`.
+#[derive(Debug)]
+pub struct I2CEngine<const QUEUE_SIZE: usize> {
+    queue: Deque<I2COperation, QUEUE_SIZE>,
+    response: Vec<u8, MAX_RESPONSE_LEN>,
+    /// length of data read by queued operations
+    queued_read_len: usize,
+    max_retries: u8,
+    retries: u8,
+    state: I2CEngineState,
+}
+
+impl<const QUEUE_SIZE: usize> I2CEngine<QUEUE_SIZE> {
+    pub fn new(max_retries: u8) -> Self {
+        Self {
+            queue: Deque::new(),
+            response: Vec::new(),
+            queued_read_len: 0,
+            max_retries,
+            retries: 0,
+            state: I2CEngineState::Idle,
+        }
+    }
+
+    /// Adds operation to the queue, operation is returned back when queue is full or response would not fit into `MAX_RESPONSE_LEN`.
+    ///
+    /// Operations can be added also while engine is running.
+    pub fn push(&mut self, operation: I2COperation) -> Result<(), I2COperation> {
+        let read_len = match operation {
+            I2COperation::Read { len, .. } => len,
+            _ => 0,
+        };
+
+        if self.queued_read_len + read_len > MAX_RESPONSE_LEN {
+            return Err(operation);
+        }
+
+        self.queue.push_back(operation)?;
+        self.queued_read_len += read_len;
+
+        Ok(())
+    }
+
+    /// Starts executing queued operations, `response` of the previous run is cleared.
+    pub fn start(&mut self) {
+        self.response.clear();
+        self.state = I2CEngineState::Next;
+    }
+
+    /// Data read by the current (or last) run.
+    pub fn response(&self) -> &[u8] {
+        &self.response
+    }
+
+    fn start_chunk(&mut self, mut i2c: PeripheralRef<I2C0>, done: usize) {
+        let mut commands: Vec<I2CCommand, { i2c_utils::MAX_COMMANDS }> = Vec::new();
+        let mut bytes: Vec<u8, { i2c_utils::FIFO_LEN }> = Vec::new();
+
+        let (chunk_len, last) = match self.queue.front() {
+            Some(I2COperation::Write { address, bytes: data }) => {
+                let first = done == 0;
+                let chunk_len = (data.len() - done).min(i2c_utils::FIFO_LEN - first as usize);
+                let last = done + chunk_len == data.len();
+
+                // cannot fail, at most 3 commands and `FIFO_LEN` bytes
+                if first {
+                    let _ = commands.push(I2CCommand::Start);
+                    let _ = bytes.push(address << 1);
+                }
+                let _ = commands.push(I2CCommand::Write { ack_ckeck: true, ack_exp: false, len: (chunk_len + first as usize) as u8 });
+                let _ = commands.push(if last { I2CCommand::Stop } else { I2CCommand::End });
+                let _ = bytes.extend_from_slice(&data[done..(done + chunk_len)]);
+
+                (chunk_len, last)
+            },
+            Some(I2COperation::Read { address, len }) => {
+                let chunk_len = (len - done).min(i2c_utils::FIFO_LEN);
+                let last = done + chunk_len == *len;
+
+                // cannot fail, at most 5 commands and 1 byte
+                if done == 0 {
+                    let _ = commands.push(I2CCommand::Start);
+                    let _ = commands.push(I2CCommand::Write { ack_ckeck: true, ack_exp: false, len: 1 });
+                    let _ = bytes.push((address << 1) | 1);
+                }
+                if last {
+                    // last byte is not acknowledged
+                    if chunk_len > 1 {
+                        let _ = commands.push(I2CCommand::Read { ack: false, len: (chunk_len - 1) as u8 });
+                    }
+                    let _ = commands.push(I2CCommand::Read { ack: true, len: 1 });
+                    let _ = commands.push(I2CCommand::Stop);
+                } else {
+                    let _ = commands.push(I2CCommand::Read { ack: false, len: chunk_len as u8 });
+                    let _ = commands.push(I2CCommand::End);
+                }
+
+                (chunk_len, last)
+            },
+            _ => unreachable!("chunk is started only for write or read"),
+        };
+
+        // SAFETY: at most 5 commands and `FIFO_LEN` bytes (checked by `Vec` capacity)
+        unsafe { i2c_utils::prepare_chunk_unchecked(i2c.reborrow(), &commands, &bytes) };
+        i2c_utils::start(i2c);
+
+        self.state = I2CEngineState::Transfer { done, chunk_len, last };
+    }
+
+    /// Starts operation at the front of the queue.
+    fn start_operation(&mut self, qq: &mut impl QQAlarmQueue, mut i2c: PeripheralRef<I2C0>) -> State<Result<(), I2CEngineError>> {
+        match self.queue.front() {
+            None => {
+                self.state = I2CEngineState::Done;
+                return State::Done(Ok(()));
+            },
+            Some(I2COperation::Delay(delta)) => {
+                self.state = I2CEngineState::Delay(Delay::start(qq, SystemTimer::now() + delta));
+            },
+            Some(I2COperation::Write { .. } | I2COperation::Read { .. }) => {
+                i2c_utils::reset_fifo(i2c.reborrow());
+                self.start_chunk(i2c, 0);
+            },
+        }
+
+        State::Active(true)
+    }
+
+    fn finish_operation(&mut self, qq: &mut impl QQAlarmQueue, i2c: PeripheralRef<I2C0>) -> State<Result<(), I2CEngineError>> {
+        if let Some(I2COperation::Read { len, .. }) = self.queue.pop_front() {
+            self.queued_read_len -= len;
+        }
+        self.retries = 0;
+
+        self.start_operation(qq, i2c)
+    }
+
+    fn fail(&mut self, error: I2CEngineError) -> State<Result<(), I2CEngineError>> {
+        self.queue.clear();
+        self.queued_read_len = 0;
+        self.retries = 0;
+        self.state = I2CEngineState::Done;
+
+        State::Done(Err(error))
+    }
+
+    pub fn update(&mut self, qq: &mut impl QQAlarmQueue, mut i2c: PeripheralRef<I2C0>) -> State<Result<(), I2CEngineError>> {
+        match self.state {
+            I2CEngineState::Idle => State::Active(false),
+            I2CEngineState::Next => self.start_operation(qq, i2c),
+            I2CEngineState::Transfer { done, chunk_len, last } => {
+                let pending_interrupts = interrupts::i2c_interrupt_get_and_clear(I2CInterruptStatus::all());
+
+                if pending_interrupts.is_empty() {
+                    return State::Active(false);
+                }
+                i2c_trace::status(pending_interrupts);
+
+                let is_read = matches!(self.queue.front(), Some(I2COperation::Read { .. }));
+
+                if let Some(err) = I2CTransmissionError::from_interrupt_flags(pending_interrupts) {
+                    if matches!(err, I2CTransmissionError::Nack(_)) && self.retries < self.max_retries {
+                        self.retries += 1;
+                        // data read by previous chunks of this operation are read again
+                        self.response.truncate(self.response.len() - if is_read { done } else { 0 });
+
+                        return self.start_operation(qq, i2c);
+                    }
+
+                    return self.fail(if is_read { I2CEngineError::Read(err) } else { I2CEngineError::Write(err) });
+                }
+
+                if is_read {
+                    let mut buffer = [0u8; i2c_utils::FIFO_LEN];
+                    if let Err(err) = i2c_utils::read_response_into(i2c.reborrow(), &mut buffer[..chunk_len]) {
+                        return self.fail(I2CEngineError::ShortRead(err));
+                    }
+                    // cannot fail, total length of reads is checked in `push`
+                    let _ = self.response.extend_from_slice(&buffer[..chunk_len]);
+                }
+
+                if last {
+                    self.finish_operation(qq, i2c)
+                } else {
+                    self.start_chunk(i2c, done + chunk_len);
+                    State::Active(true)
+                }
+            },
+            I2CEngineState::Delay(Delay::Done) => self.finish_operation(qq, i2c),
+            I2CEngineState::Delay(ref mut delay) => State::Active(delay.retry(qq)),
+            I2CEngineState::Done => State::Done(Ok(())),
+        }
+    }
+
+    pub fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        match &mut self.state {
+            I2CEngineState::Delay(delay) => delay.on_alarm(qq_alarm_id),
+            _ => false,
+        }
+    }
+}
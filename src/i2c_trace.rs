@@ -0,0 +1,211 @@
+/* mirror of i2c0 traffic prepared through `pac_utils::i2c` to the usb writer (`i2c trace on`), for debugging sensor protocols */
+
+use core::{cell::RefCell, fmt::Write, sync::atomic::{AtomicBool, Ordering}};
+
+use critical_section::Mutex;
+
+use crate::{
+    interrupts::I2CInterruptStatus,
+    log::{log_fmt, log_line},
+    pac_utils::i2c::FIFO_LEN,
+    ring_buffer::{Overwrite, RingBuffer},
+    usb_writer::ByteSink
+};
+
+
+
+/// Number of entries kept until they are written, oldest entries are dropped when usb writer does not keep up.
+pub const TRACE_LEN: usize = 16;
+/// Longest line written by `write_pending` (`"i2c c 61 : "`, 3 chars per byte, status and newline).
+const MAX_LINE_LEN: usize = 16 + 3 * FIFO_LEN + 8;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Direction {
+    /// simple write, `data` are written bytes (without address)
+    Write,
+    /// simple read, `data` are empty (response is traced separately)
+    Read,
+    /// chunk of longer transaction (see `I2CEngine`), `data` are bytes of tx fifo (including address byte)
+    Chunk,
+    /// bytes taken from rx fifo
+    Response,
+}
+
+impl Direction {
+    fn symbol(&self) -> char {
+        match self {
+            Direction::Write => 'w',
+            Direction::Read => 'r',
+            Direction::Chunk => 'c',
+            Direction::Response => '<',
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct TraceEntry {
+    direction: Direction,
+    /// `None` for chunks and responses
+    address: Option<u8>,
+    /// requested length for `Read`, otherwise `data.len()`
+    len: u8,
+    data: [u8; FIFO_LEN],
+    /// pending interrupt flags after the transaction, `None` until they are known (never known for responses)
+    status: Option<I2CInterruptStatus>,
+}
+
+impl TraceEntry {
+    fn new(direction: Direction, address: Option<u8>, bytes: &[u8]) -> Self {
+        let len = bytes.len().min(FIFO_LEN);
+        let mut data = [0; FIFO_LEN];
+        data[..len].copy_from_slice(&bytes[..len]);
+
+        Self { direction, address, len: len as u8, data, status: None }
+    }
+
+    fn data(&self) -> &[u8] {
+        match self.direction {
+            Direction::Read => &[],
+            _ => &self.data[..self.len as usize],
+        }
+    }
+
+    /// Transaction without status is not written yet (unless newer entry follows, e.g. transaction was abandoned).
+    fn is_complete(&self) -> bool {
+        self.direction == Direction::Response || self.status.is_some()
+    }
+}
+
+
+struct Trace {
+    entries: RingBuffer<TraceEntry, TRACE_LEN, Overwrite>,
+    /// entries overwritten before they were written, since the last `write_pending`
+    dropped: u32,
+}
+
+impl Trace {
+    fn push(&mut self, entry: TraceEntry) {
+        // tracing could be disabled after the caller checked `enabled`
+        if !enabled() {
+            return;
+        }
+
+        if self.entries.is_full() {
+            self.dropped += 1;
+        }
+        self.entries.push_back(entry);
+    }
+}
+
+
+/// checked before entry is built, so disabled tracing costs one load per transaction
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static TRACE: Mutex<RefCell<Trace>> = Mutex::new(RefCell::new(Trace { entries: RingBuffer::new(), dropped: 0 }));
+
+
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Entries which were not written yet are discarded when tracing is disabled.
+pub fn set_enabled(enabled: bool) {
+    critical_section::with(|cs| {
+        ENABLED.store(enabled, Ordering::Relaxed);
+        if !enabled {
+            let mut trace = TRACE.borrow_ref_mut(cs);
+            trace.entries.clear();
+            trace.dropped = 0;
+        }
+    });
+}
+
+fn push(entry: impl FnOnce() -> TraceEntry) {
+    if enabled() {
+        let entry = entry();
+        critical_section::with(|cs| TRACE.borrow_ref_mut(cs).push(entry));
+    }
+}
+
+pub fn write(address: u8, bytes: &[u8]) {
+    push(|| TraceEntry::new(Direction::Write, Some(address), bytes));
+}
+
+pub fn read(address: u8, len: u8) {
+    push(|| TraceEntry { len, ..TraceEntry::new(Direction::Read, Some(address), &[]) });
+}
+
+pub fn chunk(bytes: &[u8]) {
+    push(|| TraceEntry::new(Direction::Chunk, None, bytes));
+}
+
+pub fn response(bytes: &[u8]) {
+    push(|| TraceEntry::new(Direction::Response, None, bytes));
+}
+
+/// Status of the last transaction (or chunk), called with interrupt flags which ended it.
+pub fn status(flags: I2CInterruptStatus) {
+    if !enabled() {
+        return;
+    }
+
+    critical_section::with(|cs| {
+        let mut trace = TRACE.borrow_ref_mut(cs);
+        let last = (0..trace.entries.len()).rev().find(|i| trace.entries[*i].direction != Direction::Response);
+
+        if let Some(entry) = last.map(|i| &mut trace.entries[i]) && entry.status.is_none() {
+            entry.status = Some(flags);
+        }
+    });
+}
+
+/// Writes completed entries as one line each (`i2c w 61 : 00 10 00 00 81 : 0088`, status is hex of interrupt flags),
+/// only while usb writer has space for the longest line, so tracing never overflows it.
+pub fn write_pending(w: &mut (impl Write + ByteSink)) -> bool {
+    // entries are discarded when tracing is disabled
+    if !enabled() {
+        return false;
+    }
+
+    let mut did_something = false;
+
+    loop {
+        if w.free_space() < MAX_LINE_LEN {
+            return did_something;
+        }
+
+        let (entry, dropped) = critical_section::with(|cs| {
+            let mut trace = TRACE.borrow_ref_mut(cs);
+            let dropped = core::mem::take(&mut trace.dropped);
+            let complete = trace.entries.front().is_some_and(|entry| entry.is_complete() || trace.entries.len() > 1);
+
+            (if complete { trace.entries.pop_front() } else { None }, dropped)
+        });
+
+        if dropped > 0 {
+            log_line!(w, "i2c trace", "{} entries dropped", dropped);
+            did_something = true;
+        }
+
+        let Some(entry) = entry else {
+            return did_something;
+        };
+
+        log_fmt!(w, "i2c {}", entry.direction.symbol());
+        if let Some(address) = entry.address {
+            log_fmt!(w, " {:02x}", address);
+        }
+        log_fmt!(w, " :");
+        entry.data().iter().for_each(|byte| { log_fmt!(w, " {:02x}", byte); });
+        if entry.direction == Direction::Read {
+            log_fmt!(w, " {} bytes", entry.len);
+        }
+        match entry.status {
+            Some(status) => log_fmt!(w, " : {:04x}\n", status.bits()),
+            None => log_fmt!(w, "\n"),
+        }
+
+        did_something = true;
+    }
+}
@@ -1,14 +1,37 @@
-use core::sync::atomic::{AtomicU32, Ordering};
+use core::{cell::RefCell, sync::atomic::{AtomicBool, AtomicU32, Ordering}};
+#[cfg(feature = "async-sdc")]
+use core::task::Waker;
 
 use bitflags::bitflags;
-use esp_hal::{interrupt::{self, Priority}, macros::handler, peripherals::{Interrupt, GPIO, I2C0, RMT, SYSTIMER, USB_DEVICE}};
+use critical_section::Mutex;
+use esp_hal::{interrupt::{self, Priority}, macros::handler, peripherals::{Interrupt, GPIO, I2C0, RMT, SYSTIMER, USB_DEVICE}, timer::systimer::SystemTimer};
+use esp_hal::peripheral::Peripheral;
+#[cfg(feature = "uart-output")]
+use esp_hal::peripherals::UART0;
+
+use crate::ring_buffer::{Overwrite, RingBuffer};
+#[cfg(feature = "usb-irq-refill")]
+use crate::{output_buffer::OutputBuffer, pac_utils::usb_serial};
+use crate::pac_utils::rmt::RxStream;
 
 
 
 bitflags! {
     #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub struct USBInterruptStatus: u32 {
+        const SERIAL_OUT_RECV_PKT = 1 << 2;
         const SERIAL_IN_EMPTY = 1 << 3;
+        /// host polled serial IN endpoint (enabled only while waiting for the host, see `is_host_connected`)
+        const IN_TOKEN_REC_IN_EP1 = 1 << 8;
+        const USB_BUS_RESET = 1 << 9;
+    }
+}
+
+#[cfg(feature = "uart-output")]
+bitflags! {
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct UARTInterruptStatus: u32 {
+        const TXFIFO_EMPTY = 1 << 1;
     }
 }
 
@@ -22,6 +45,7 @@ bitflags! {
 bitflags! {
     #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub struct I2CInterruptStatus: u32 {
+        const END_DETECT = 1 << 3;
         const ARBITRATION_LOST = 1 << 5;
         const TRANSACTION_COMPLETE = 1 << 7;
         const TIME_OUT = 1 << 8;
@@ -46,30 +70,298 @@ impl I2CInterruptStatus {
 bitflags! {
     #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub struct GPIOInterruptStatus: u32 {
-        const GPIO6 = 1 << 6;
+        // one bit for each gpio, see `GPIOInterruptStatus::pin`
+        const _ = !0;
+    }
+}
+
+impl GPIOInterruptStatus {
+    pub fn pin(pin_num: u8) -> GPIOInterruptStatus {
+        GPIOInterruptStatus::from_bits_retain(1 << pin_num)
+    }
+}
+
+/// Edge timestamped by gpio interrupt handler (see `gpio_capture_enable`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GPIOEdge {
+    /// system timer ticks when the interrupt was handled
+    pub at: u64,
+    /// pin level read by the handler (level after the edge, unless the pin changed again before)
+    pub level: bool,
+}
+
+struct GPIOCapture {
+    pin: u8,
+    edges: RingBuffer<GPIOEdge, GPIO_CAPTURE_LEN, Overwrite>,
+}
+
+impl GPIOCapture {
+    fn push(&mut self, status: u32, levels: u32, at: u64) {
+        if status & (1 << self.pin) != 0 {
+            self.edges.push_back(GPIOEdge { at, level: levels & (1 << self.pin) != 0 });
+        }
     }
 }
 
 bitflags! {
     #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub struct RMTInterruptStatus: u32 {
+        const CH0_TX_END = 1 << 0;
+        const CH1_TX_END = 1 << 1;
         const CH2_END = 1 << 2;
+        const CH3_END = 1 << 3;
+        const CH0_TX_ERROR = 1 << 4;
+        const CH1_TX_ERROR = 1 << 5;
         const CH2_ERROR = 1 << 6;
+        const CH3_ERROR = 1 << 7;
+        const CH2_THRESHOLD = 1 << 10;
+        const CH3_THRESHOLD = 1 << 11;
     }
 }
 
 impl RMTInterruptStatus {
     pub fn is_error(&self) -> bool {
-        self.intersects(RMTInterruptStatus::CH2_ERROR)
+        self.intersects(RMTInterruptStatus::CH0_TX_ERROR | RMTInterruptStatus::CH1_TX_ERROR | RMTInterruptStatus::CH2_ERROR | RMTInterruptStatus::CH3_ERROR)
+    }
+}
+
+
+/// Interrupt handled by this module, its handler wakes async tasks (see `register_waker`) and is counted by statistics
+/// (see `stats_snapshot`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InterruptSource {
+    Usb,
+    Systimer,
+    I2c,
+    Gpio,
+    Rmt,
+    #[cfg(feature = "uart-output")]
+    Uart0,
+}
+
+impl InterruptSource {
+    pub const COUNT: usize = if cfg!(feature = "uart-output") { 6 } else { 5 };
+    pub const ALL: [InterruptSource; InterruptSource::COUNT] = [
+        InterruptSource::Usb,
+        InterruptSource::Systimer,
+        InterruptSource::I2c,
+        InterruptSource::Gpio,
+        InterruptSource::Rmt,
+        #[cfg(feature = "uart-output")]
+        InterruptSource::Uart0,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            InterruptSource::Usb => "usb",
+            InterruptSource::Systimer => "systimer",
+            InterruptSource::I2c => "i2c",
+            InterruptSource::Gpio => "gpio",
+            InterruptSource::Rmt => "rmt",
+            #[cfg(feature = "uart-output")]
+            InterruptSource::Uart0 => "uart0",
+        }
     }
 }
 
 
+/// Priority of each interrupt source, all interrupts are enabled by `init` from one table.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PriorityTable {
+    /// `None` leaves interrupt disabled (its handler is not bound)
+    priorities: [Option<Priority>; InterruptSource::COUNT],
+}
+
+impl PriorityTable {
+    /// qq alarm queue timer above usb output, bus and pin interrupts (i2c, gpio data ready, rmt ir receiver) below.
+    pub const DEFAULT: PriorityTable = PriorityTable {
+        priorities: [
+            Some(Priority::Priority9),
+            Some(Priority::Priority10),
+            Some(Priority::Priority5),
+            Some(Priority::Priority5),
+            Some(Priority::Priority5),
+            #[cfg(feature = "uart-output")]
+            Some(Priority::Priority9),
+        ],
+    };
+
+
+    pub fn get(&self, source: InterruptSource) -> Option<Priority> {
+        self.priorities[source as usize]
+    }
+
+    /// Replaces priorities of sources in `overrides`, fails when one source is assigned different priorities or when
+    /// `Priority::None` is used (it masks the interrupt, `None` disables it).
+    pub fn with_overrides(mut self, overrides: &[(InterruptSource, Option<Priority>)]) -> Result<PriorityTable, PriorityError> {
+        for (index, (source, priority)) in overrides.iter().enumerate() {
+            if *priority == Some(Priority::None) {
+                return Err(PriorityError::Masked(*source));
+            }
+
+            if overrides[..index].iter().any(|(other_source, other_priority)| other_source == source && other_priority != priority) {
+                return Err(PriorityError::Conflict(*source));
+            }
 
-pub fn usb_interrupt_enable(priority: Option<Priority>) {
+            self.priorities[*source as usize] = *priority;
+        }
+
+        Ok(self)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PriorityError {
+    /// source is assigned different priorities
+    Conflict(InterruptSource),
+    /// source is assigned `Priority::None`
+    Masked(InterruptSource),
+}
+
+
+/// Handler calls of one interrupt source since the last `stats_reset`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptStats {
+    pub count: u32,
+    /// handler calls without any flag known to this module (nothing for consumers to handle)
+    pub spurious: u32,
+    /// handler calls which raised flag still pending from the previous call, no machine consumed it in between
+    /// (e.g. gpio edge of a pin nobody waits for)
+    pub unclaimed: u32,
+    /// system timer ms of the last handler call (wraps after ~49 days), kept by `stats_reset`
+    pub last_at_ms: Option<u32>,
+}
+
+
+/// Counters of one source, updated by its handler without critical section (snapshot of all counters is not atomic).
+struct AtomicStats {
+    count: AtomicU32,
+    spurious: AtomicU32,
+    unclaimed: AtomicU32,
+    /// `NEVER` before the first handler call
+    last_at_ms: AtomicU32,
+}
+
+impl AtomicStats {
+    const NEVER: u32 = u32::MAX;
+
+
+    const fn new() -> Self {
+        Self { count: AtomicU32::new(0), spurious: AtomicU32::new(0), unclaimed: AtomicU32::new(0), last_at_ms: AtomicU32::new(Self::NEVER) }
+    }
+
+    fn increment(counter: &AtomicU32) {
+        // saturated counter is not changed
+        let _ = counter.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |value| value.checked_add(1));
+    }
+
+    fn snapshot(&self) -> InterruptStats {
+        InterruptStats {
+            count: self.count.load(Ordering::Relaxed),
+            spurious: self.spurious.load(Ordering::Relaxed),
+            unclaimed: self.unclaimed.load(Ordering::Relaxed),
+            last_at_ms: Some(self.last_at_ms.load(Ordering::Relaxed)).filter(|at| *at != Self::NEVER),
+        }
+    }
+
+    fn reset(&self) {
+        self.count.store(0, Ordering::Relaxed);
+        self.spurious.store(0, Ordering::Relaxed);
+        self.unclaimed.store(0, Ordering::Relaxed);
+    }
+}
+
+
+/// One waker for each source, woken (and removed) by the next interrupt of its source.
+#[cfg(feature = "async-sdc")]
+static WAKERS: [Mutex<RefCell<Option<Waker>>>; InterruptSource::COUNT] = [const { Mutex::new(RefCell::new(None)) }; InterruptSource::COUNT];
+
+static STATS: [AtomicStats; InterruptSource::COUNT] = [const { AtomicStats::new() }; InterruptSource::COUNT];
+
+/// Binds handlers and enables interrupts of all sources with priority in `table`, called once at startup (peripherals
+/// enable their own interrupt flags, e.g. `Console::enable_interrupt`).
+pub fn init(table: &PriorityTable) {
+    for source in InterruptSource::ALL {
+        let Some(priority) = table.get(source) else {
+            continue;
+        };
+
+        match source {
+            InterruptSource::Usb => usb_interrupt_enable(priority),
+            InterruptSource::Systimer => systimer_target0_interrupt_enable(priority),
+            InterruptSource::I2c => i2c_interrupt_enable(priority),
+            InterruptSource::Gpio => gpio_interrupt_enable(priority),
+            InterruptSource::Rmt => rmt_interrupt_enable(priority),
+            #[cfg(feature = "uart-output")]
+            InterruptSource::Uart0 => uart0_interrupt_enable(priority),
+        }
+    }
+}
+
+/// `waker` is woken by the next interrupt of `source` (after the pending flags are set, so woken task sees them).
+///
+/// There is only one waker for each source, registering different waker wakes the previous one (its task polls
+/// again and registers itself again, so tasks waiting on the same source take turns instead of losing wakeups).
+#[cfg(feature = "async-sdc")]
+pub fn register_waker(source: InterruptSource, waker: &Waker) {
+    let previous = critical_section::with(|cs| {
+        let mut slot = WAKERS[source as usize].borrow_ref_mut(cs);
+        match slot.as_ref() {
+            Some(registered) if registered.will_wake(waker) => None,
+            _ => slot.replace(waker.clone()),
+        }
+    });
+
+    if let Some(previous) = previous {
+        previous.wake();
+    }
+}
+
+#[cfg(feature = "async-sdc")]
+fn wake(source: InterruptSource) {
+    if let Some(waker) = critical_section::with(|cs| WAKERS[source as usize].borrow_ref_mut(cs).take()) {
+        waker.wake();
+    }
+}
+
+/// There are no async tasks without `async-sdc`, handlers do not take critical section for wakers.
+#[cfg(not(feature = "async-sdc"))]
+fn wake(_source: InterruptSource) {}
+
+/// Counts handler call of `source`, `status` are flags read by the handler and `previous` are pending flags before
+/// they were added, only `known` flags (which consumers clear) are checked.
+fn record(source: InterruptSource, status: u32, previous: u32, known: u32) {
+    let stats = &STATS[source as usize];
+
+    AtomicStats::increment(&stats.count);
+    // `NEVER` is skipped when ms wrap
+    let at_ms = (SystemTimer::now() / (SystemTimer::TICKS_PER_SECOND / 1_000)) as u32;
+    stats.last_at_ms.store(at_ms.min(AtomicStats::NEVER - 1), Ordering::Relaxed);
+    if status & known == 0 {
+        AtomicStats::increment(&stats.spurious);
+    }
+    if status & previous & known != 0 {
+        AtomicStats::increment(&stats.unclaimed);
+    }
+}
+
+/// Statistics of each source (indexed same as `InterruptSource::ALL`), counters of one source can be from different
+/// handler calls when it is interrupted by the handler.
+pub fn stats_snapshot() -> [InterruptStats; InterruptSource::COUNT] {
+    core::array::from_fn(|i| STATS[i].snapshot())
+}
+
+/// Resets counters of all sources, timestamps of the last call are kept.
+pub fn stats_reset() {
+    STATS.iter().for_each(AtomicStats::reset);
+}
+
+
+
+fn usb_interrupt_enable(priority: Priority) {
     // [todo] safety
     unsafe { interrupt::bind_interrupt(Interrupt::USB_DEVICE, usb_handler.handler()) };
-    interrupt::enable(Interrupt::USB_DEVICE, priority.unwrap_or(usb_handler.priority())).unwrap();
+    interrupt::enable(Interrupt::USB_DEVICE, priority).unwrap();
 }
 
 pub fn usb_interrupt_get() -> USBInterruptStatus {
@@ -87,24 +379,165 @@ pub fn usb_interrupt_get_and_clear(interrupts: USBInterruptStatus) -> USBInterru
 
 static USB_PENDING_INTERRUPTS: AtomicU32 = AtomicU32::new(USBInterruptStatus::empty().bits());
 
+/// Host reads usb serial output, see `is_host_connected`.
+static USB_HOST_CONNECTED: AtomicBool = AtomicBool::new(true);
+
+/// Output buffer of usb writer, written by the writer in critical sections and moved into the serial fifo by usb handler
+/// (`usb-irq-refill`), `None` until the writer is created.
+#[cfg(feature = "usb-irq-refill")]
+pub static USB_OUTPUT: Mutex<RefCell<Option<OutputBuffer<USB_OUTPUT_LEN>>>> = Mutex::new(RefCell::new(None));
+
+#[cfg(feature = "usb-irq-refill")]
+pub const USB_OUTPUT_LEN: usize = 4096;
+
+/// Packets moved into the serial fifo by usb handler (progress of the host while the writer is not notified).
+#[cfg(feature = "usb-irq-refill")]
+static USB_PACKETS: AtomicU32 = AtomicU32::new(0);
+
+/// Handler calls which notified the usb writer (woke the main loop), see `usb_wakeup_count`.
+static USB_WAKEUPS: AtomicU32 = AtomicU32::new(0);
+
+
+/// Whether a host reads usb serial output (a terminal has the port open).
+///
+/// Host is assumed connected at boot. It is disconnected when usb writer buffer overflows while the writer is timed out
+/// (see `usb_host_lost`) or on usb bus reset (cable plugged in, host enumerates the device without opening the port),
+/// and connected again by the first IN token on the serial endpoint, host polls the endpoint only while the port is open.
+/// Usb writer enables IN token interrupt only while the host is disconnected (open port is polled every frame).
+pub fn is_host_connected() -> bool {
+    USB_HOST_CONNECTED.load(Ordering::Relaxed)
+}
+
+/// Host does not read output, it is disconnected until it polls the serial endpoint again.
+pub fn usb_host_lost() {
+    USB_HOST_CONNECTED.store(false, Ordering::Relaxed);
+}
+
+/// Number of usb handler calls which woke the main loop (all calls, or only when the output buffer ran empty with
+/// `usb-irq-refill`), for comparison of both modes (see `UsbBench`).
+pub fn usb_wakeup_count() -> u32 {
+    USB_WAKEUPS.load(Ordering::Relaxed)
+}
+
+/// Number of packets moved into the serial fifo by usb handler, changes while the host reads output.
+#[cfg(feature = "usb-irq-refill")]
+pub fn usb_packet_count() -> u32 {
+    USB_PACKETS.load(Ordering::Relaxed)
+}
+
+/// Moves next packet of usb writer output into the fifo, disables `SERIAL_IN_EMPTY` interrupt when the output is empty
+/// (writer enables it again after write). Returns `true` when the output is empty and the writer should be notified.
+#[cfg(feature = "usb-irq-refill")]
+fn usb_output_refill(usb: &USB_DEVICE) -> bool {
+    critical_section::with(|cs| {
+        let mut output = USB_OUTPUT.borrow_ref_mut(cs);
+        let Some(output) = output.as_mut() else {
+            return true;
+        };
+
+        if usb_serial::write_packet(usb, output.buffer_mut()) != 0 {
+            USB_PACKETS.fetch_add(1, Ordering::Relaxed);
+        }
+
+        if output.is_empty() {
+            usb.int_ena().modify(|_, w| w.serial_in_empty().clear_bit()); // disable interupt
+            true
+        } else {
+            false
+        }
+    })
+}
+
 
 #[handler]
 fn usb_handler() {
     // [todo] safety
     let usb = unsafe { USB_DEVICE::steal() };
-
-    USB_PENDING_INTERRUPTS.fetch_or(usb.int_st().read().bits(), Ordering::Relaxed);
+    let status = usb.int_st().read().bits();
+
+    // fifo is refilled here, usb writer is notified only when its output runs empty
+    #[cfg(feature = "usb-irq-refill")]
+    let notify = if status & USBInterruptStatus::SERIAL_IN_EMPTY.bits() != 0 && !usb_output_refill(&usb) {
+        status & !USBInterruptStatus::SERIAL_IN_EMPTY.bits()
+    } else {
+        status
+    };
+    #[cfg(not(feature = "usb-irq-refill"))]
+    let notify = status;
+    // host detection is handled here, usb writer reads `is_host_connected` in each update (bits would stay pending)
+    let notify = notify & !(USBInterruptStatus::IN_TOKEN_REC_IN_EP1 | USBInterruptStatus::USB_BUS_RESET).bits();
+
+    let previous = USB_PENDING_INTERRUPTS.fetch_or(notify, Ordering::Relaxed);
+
+    // both pending - host opened the port right after the reset
+    let status_flags = USBInterruptStatus::from_bits_truncate(status);
+    if status_flags.contains(USBInterruptStatus::USB_BUS_RESET) {
+        USB_HOST_CONNECTED.store(false, Ordering::Relaxed);
+    }
+    if status_flags.contains(USBInterruptStatus::IN_TOKEN_REC_IN_EP1) {
+        USB_HOST_CONNECTED.store(true, Ordering::Relaxed);
+    }
 
     // SAFETY: clear all interrupts, bits are valid according to specification
     usb.int_clr().write(|w| unsafe { w.bits(0xffff) });
+
+    record(InterruptSource::Usb, status, previous, USBInterruptStatus::all().bits());
+    if notify != 0 {
+        USB_WAKEUPS.fetch_add(1, Ordering::Relaxed);
+        wake(InterruptSource::Usb);
+    }
+}
+
+
+
+#[cfg(feature = "uart-output")]
+fn uart0_interrupt_enable(priority: Priority) {
+    // [todo] safety
+    unsafe { interrupt::bind_interrupt(Interrupt::UART0, uart0_handler.handler()) };
+    interrupt::enable(Interrupt::UART0, priority).unwrap();
+}
+
+#[cfg(feature = "uart-output")]
+pub fn uart0_interrupt_get() -> UARTInterruptStatus {
+    UARTInterruptStatus::from_bits_truncate(UART0_PENDING_INTERRUPTS.load(Ordering::Relaxed))
+}
+
+#[cfg(feature = "uart-output")]
+pub fn uart0_interrupt_get_and_clear(interrupts: UARTInterruptStatus) -> UARTInterruptStatus {
+    UARTInterruptStatus::from_bits_truncate(UART0_PENDING_INTERRUPTS.fetch_and((!interrupts).bits(), Ordering::Relaxed)).intersection(interrupts)
 }
 
 
+#[cfg(feature = "uart-output")]
+static UART0_PENDING_INTERRUPTS: AtomicU32 = AtomicU32::new(UARTInterruptStatus::empty().bits());
+
 
-pub fn systimer_target0_interrupt_enable(priority: Option<Priority>) {
+#[cfg(feature = "uart-output")]
+#[handler]
+fn uart0_handler() {
+    // [todo] safety
+    let uart = unsafe { UART0::steal() };
+    let status = uart.int_st().read().bits();
+
+    let previous = UART0_PENDING_INTERRUPTS.fetch_or(status, Ordering::Relaxed);
+
+    // txfifo empty is raised again right after clearing while the fifo is below threshold, so it is disabled
+    // until uart writer refills the fifo (see `UartWriter::update`)
+    uart.int_ena().modify(|_, w| w.txfifo_empty().clear_bit());
+
+    // SAFETY: clear all interrupts, bits are valid according to specification
+    uart.int_clr().write(|w| unsafe { w.bits(0x000f_ffff) });
+
+    record(InterruptSource::Uart0, status, previous, UARTInterruptStatus::all().bits());
+    wake(InterruptSource::Uart0);
+}
+
+
+
+fn systimer_target0_interrupt_enable(priority: Priority) {
     // [todo] safety
     unsafe { interrupt::bind_interrupt(Interrupt::SYSTIMER_TARGET0, systimer_target0_handler.handler()) };
-    interrupt::enable(Interrupt::SYSTIMER_TARGET0, priority.unwrap_or(systimer_target0_handler.priority())).unwrap();
+    interrupt::enable(Interrupt::SYSTIMER_TARGET0, priority).unwrap();
 }
 
 pub fn systimer_target0_interrupt_get() -> SystimerTartet0InterruptStatus {
@@ -123,23 +556,28 @@ pub fn systimer_target0_interrupt_get_and_clear(interrupts: SystimerTartet0Inter
 static SYSTIMER_TARGET0_PENDING_INTERRUPTS: AtomicU32 = AtomicU32::new(SystimerTartet0InterruptStatus::empty().bits());
 
 
-#[handler(priority = esp_hal::interrupt::Priority::Priority10)]
+#[handler]
 fn systimer_target0_handler() {
     // [todo]
     let systimer = unsafe { SYSTIMER::steal() };
 
-    SYSTIMER_TARGET0_PENDING_INTERRUPTS.fetch_or(systimer.int_st().read().bits() & 0b1, Ordering::Relaxed);
+    let status = systimer.int_st().read().bits() & 0b1;
+
+    let previous = SYSTIMER_TARGET0_PENDING_INTERRUPTS.fetch_or(status, Ordering::Relaxed);
 
     // SAFETY: clear all interrupts, bits are valid according to specification
     systimer.int_clr().write(|w| unsafe { w.bits(0b1) });
+
+    record(InterruptSource::Systimer, status, previous, SystimerTartet0InterruptStatus::all().bits());
+    wake(InterruptSource::Systimer);
 }
 
 
 
-pub fn i2c_interrupt_enable(priority: Option<Priority>) {
+fn i2c_interrupt_enable(priority: Priority) {
     // [todo] safety
     unsafe { interrupt::bind_interrupt(Interrupt::I2C_EXT0, i2c_handler.handler()) };
-    interrupt::enable(Interrupt::I2C_EXT0, priority.unwrap_or(i2c_handler.priority())).unwrap();
+    interrupt::enable(Interrupt::I2C_EXT0, priority).unwrap();
 }
 
 pub fn i2c_interrupt_get() -> I2CInterruptStatus {
@@ -155,6 +593,15 @@ pub fn i2c_interrupt_get_and_clear(interrupts: I2CInterruptStatus) -> I2CInterru
 }
 
 
+/// Sets pending interrupt flags without the peripheral, used by mock peripherals (see `mock`).
+#[cfg(feature = "mock-hw")]
+pub fn i2c_interrupt_raise(interrupts: I2CInterruptStatus) {
+    let previous = I2C_PENDING_INTERRUPTS.fetch_or(interrupts.bits(), Ordering::Relaxed);
+    record(InterruptSource::I2c, interrupts.bits(), previous, I2CInterruptStatus::all().bits());
+    wake(InterruptSource::I2c);
+}
+
+
 static I2C_PENDING_INTERRUPTS: AtomicU32 = AtomicU32::new(I2CInterruptStatus::empty().bits());
 
 
@@ -162,19 +609,23 @@ static I2C_PENDING_INTERRUPTS: AtomicU32 = AtomicU32::new(I2CInterruptStatus::em
 fn i2c_handler() {
     // [todo]
     let i2c = unsafe { I2C0::steal() };
+    let status = i2c.int_st().read().bits();
 
-    I2C_PENDING_INTERRUPTS.fetch_or(i2c.int_st().read().bits(), Ordering::Relaxed);
+    let previous = I2C_PENDING_INTERRUPTS.fetch_or(status, Ordering::Relaxed);
 
     // SAFETY: clear all interrupts, bits are valid according to specification
     i2c.int_clr().write(|w| unsafe { w.bits(0b0111_1111_1111_1111_1111) });
+
+    record(InterruptSource::I2c, status, previous, I2CInterruptStatus::all().bits());
+    wake(InterruptSource::I2c);
 }
 
 
 
-pub fn gpio_interrupt_enable(priority: Option<Priority>) {
+fn gpio_interrupt_enable(priority: Priority) {
     // [todo] safety
     unsafe { interrupt::bind_interrupt(Interrupt::GPIO, gpio_handler.handler()) };
-    interrupt::enable(Interrupt::GPIO, priority.unwrap_or(gpio_handler.priority())).unwrap();
+    interrupt::enable(Interrupt::GPIO, priority).unwrap();
 }
 
 pub fn gpio_interrupt_get() -> GPIOInterruptStatus {
@@ -190,26 +641,105 @@ pub fn gpio_interrupt_get_and_clear(interrupts: GPIOInterruptStatus) -> GPIOInte
 }
 
 
+/// See `i2c_interrupt_raise`, raised edges are captured as rising.
+#[cfg(feature = "mock-hw")]
+pub fn gpio_interrupt_raise(interrupts: GPIOInterruptStatus) {
+    gpio_capture(interrupts.bits(), interrupts.bits(), SystemTimer::now());
+    let previous = GPIO_PENDING_INTERRUPTS.fetch_or(interrupts.bits(), Ordering::Relaxed);
+    record(InterruptSource::Gpio, interrupts.bits(), previous, GPIOInterruptStatus::all().bits());
+    wake(InterruptSource::Gpio);
+}
+
+/// Edges of `pin` are timestamped by the interrupt handler, so consumers get time of the edge instead of time they polled.
+/// Last `GPIO_CAPTURE_LEN` edges are kept (see `gpio_capture_pop`), returns `false` when all capture slots are used.
+pub fn gpio_capture_enable(pin: u8) -> bool {
+    critical_section::with(|cs| {
+        let mut captures = GPIO_CAPTURES.borrow_ref_mut(cs);
+
+        if captures.iter().flatten().any(|capture| capture.pin == pin) {
+            return true;
+        }
+
+        match captures.iter_mut().find(|capture| capture.is_none()) {
+            Some(slot) => {
+                *slot = Some(GPIOCapture { pin, edges: RingBuffer::new() });
+                true
+            },
+            None => false,
+        }
+    })
+}
+
+/// Oldest captured edge of `pin`, `None` when there is none or capture is not enabled.
+pub fn gpio_capture_pop(pin: u8) -> Option<GPIOEdge> {
+    critical_section::with(|cs| {
+        GPIO_CAPTURES.borrow_ref_mut(cs).iter_mut().flatten().find(|capture| capture.pin == pin)?.edges.pop_front()
+    })
+}
+
+/// Time of the last captured rising edge of `pin`, all captured edges are dropped. `None` when there is no rising
+/// edge (interrupt was missed or capture is not enabled).
+pub fn gpio_capture_last_rising(pin: u8) -> Option<u64> {
+    let mut at = None;
+
+    while let Some(edge) = gpio_capture_pop(pin) {
+        if edge.level {
+            at = Some(edge.at);
+        }
+    }
+
+    at
+}
+
+/// Drops captured edges of `pin` (e.g. stale edges before restart).
+pub fn gpio_capture_clear(pin: u8) {
+    while gpio_capture_pop(pin).is_some() {}
+}
+
+fn gpio_capture(status: u32, levels: u32, at: u64) {
+    critical_section::with(|cs| {
+        for capture in GPIO_CAPTURES.borrow_ref_mut(cs).iter_mut().flatten() {
+            capture.push(status, levels, at);
+        }
+    });
+}
+
+
 static GPIO_PENDING_INTERRUPTS: AtomicU32 = AtomicU32::new(GPIOInterruptStatus::empty().bits());
 
+/// number of pins which can be captured at once
+const GPIO_CAPTURE_PINS: usize = 2;
+/// edges kept for each captured pin, oldest are overwritten
+const GPIO_CAPTURE_LEN: usize = 8;
+
+static GPIO_CAPTURES: Mutex<RefCell<[Option<GPIOCapture>; GPIO_CAPTURE_PINS]>> = Mutex::new(RefCell::new([const { None }; GPIO_CAPTURE_PINS]));
+
 
 #[handler]
 fn gpio_handler() {
     // TODO
     let gpio = unsafe { GPIO::steal() };
 
-    GPIO_PENDING_INTERRUPTS.fetch_or(gpio.status().read().bits(), Ordering::Relaxed);
+    // timestamp is taken first, it should be as close to the edge as possible
+    let at = SystemTimer::now();
+    let status = gpio.status().read().bits();
+
+    gpio_capture(status, gpio.in_().read().bits(), at);
+    let previous = GPIO_PENDING_INTERRUPTS.fetch_or(status, Ordering::Relaxed);
 
     // SAFETY: clear all interrupts, bits are valid according to specification
     gpio.status_w1tc().write(|w| unsafe { w.bits(0b0111_1111_1111_1111_1111) });
+
+    record(InterruptSource::Gpio, status, previous, GPIOInterruptStatus::all().bits());
+    wake(InterruptSource::Gpio);
 }
 
 
 
-pub fn rmt_interrupt_enable(priority: Option<Priority>) {
+fn rmt_interrupt_enable(priority: Priority) {
     // [todo] safety
     unsafe { interrupt::bind_interrupt(Interrupt::RMT, rmt_handler.handler()) };
-    interrupt::enable(Interrupt::RMT, priority.unwrap_or(rmt_handler.priority())).unwrap();
+    interrupt::enable(Interrupt::RMT, priority).unwrap();
 }
 
 pub fn rmt_interrupt_get() -> RMTInterruptStatus {
@@ -225,16 +755,54 @@ pub fn rmt_interrupt_get_and_clear(interrupts: RMTInterruptStatus) -> RMTInterru
 }
 
 
+/// See `i2c_interrupt_raise`.
+#[cfg(feature = "mock-hw")]
+pub fn rmt_interrupt_raise(interrupts: RMTInterruptStatus) {
+    let previous = RMT_PENDING_INTERRUPTS.fetch_or(interrupts.bits(), Ordering::Relaxed);
+    record(InterruptSource::Rmt, interrupts.bits(), previous, RMTInterruptStatus::all().bits());
+    wake(InterruptSource::Rmt);
+}
+
+
+/// Starts collecting pulses of `stream` channel, parts of long frames are read by the interrupt handler on threshold
+/// interrupt (replaces previous stream). End of the frame is reported by channel end interrupt, it is read by
+/// `RxStream::finish` (see `rmt_rx_stream_with`).
+pub fn rmt_rx_stream_start(stream: RxStream) {
+    critical_section::with(|cs| RMT_RX_STREAM.borrow_ref_mut(cs).replace(stream));
+}
+
+/// Runs `f` with the rx stream in critical section (interrupt handler reads into it), `None` when no stream was started.
+pub fn rmt_rx_stream_with<R>(f: impl FnOnce(&mut RxStream) -> R) -> Option<R> {
+    critical_section::with(|cs| RMT_RX_STREAM.borrow_ref_mut(cs).as_mut().map(f))
+}
+
+
 static RMT_PENDING_INTERRUPTS: AtomicU32 = AtomicU32::new(RMTInterruptStatus::empty().bits());
 
+static RMT_RX_STREAM: Mutex<RefCell<Option<RxStream>>> = Mutex::new(RefCell::new(None));
+
 
 #[handler]
 fn rmt_handler() {
     // TODO
     let rmt = unsafe { RMT::steal() };
+    let status = rmt.int_st().read().bits();
 
-    RMT_PENDING_INTERRUPTS.fetch_or(rmt.int_st().read().bits(), Ordering::Relaxed);
+    // rx channel memory has to be read before it is overwritten
+    critical_section::with(|cs| {
+        if let Some(stream) = RMT_RX_STREAM.borrow_ref_mut(cs).as_mut()
+            && RMTInterruptStatus::from_bits_truncate(status).contains(stream.channel().threshold_interrupt())
+        {
+            // SAFETY: only memory of the stream channel is read
+            stream.read_chunk(unsafe { RMT::steal() }.into_ref());
+        }
+    });
+
+    let previous = RMT_PENDING_INTERRUPTS.fetch_or(status, Ordering::Relaxed);
 
     // SAFETY: clear all interrupts, bits are valid according to specification
     rmt.int_clr().write(|w| unsafe { w.bits(0b0011_1111_1111_1111) });
+
+    record(InterruptSource::Rmt, status, previous, RMTInterruptStatus::all().bits());
+    wake(InterruptSource::Rmt);
 }
\ No newline at end of file
@@ -0,0 +1,26 @@
+/* log lines of the firmware, levels, line format and macros are in the logic crate (see `rust_esp_logic::log`) */
+
+use core::fmt::Write;
+
+use esp_hal::timer::systimer::SystemTimer;
+
+pub use rust_esp_logic::log::*;
+pub(crate) use rust_esp_logic::{error, log_warn as warn, info, debug, log_line, log_fmt};
+
+
+
+/// error registry code of output dropped by log macros (recorded as `Subsystem::Usb`, see `take_output_dropped`)
+pub const ERROR_CODE_OUTPUT_DROPPED: u16 = 0x02;
+
+
+/// Timestamp of log lines (system timer ticks), used by the log macros.
+pub fn now() -> u64 {
+    SystemTimer::now()
+}
+
+/// Writes runtime level of all modules (used by console).
+pub fn write_levels(writer: &mut impl Write) {
+    for module in Module::ALL {
+        log_line!(writer, "log level", "{} {}", module.name(), level(module).map_or("off", |level| level.name()));
+    }
+}
@@ -1,21 +1,83 @@
+pub mod alert;
+pub mod ambient_sensor;
+#[cfg(feature = "async-sdc")]
+pub mod async_tasks;
+#[cfg(feature = "bme280")]
+pub mod bme280;
+#[cfg(feature = "piezo-buzzer")]
+pub mod buzzer;
+pub mod button;
+pub mod console;
 pub mod controller;
 pub mod debug_print;
+#[cfg(feature = "oled-display")]
+pub mod display;
+pub mod flash_logger;
+pub mod http_server;
+pub mod measurment_dump;
+pub mod mqtt_client;
+pub mod network;
+#[cfg(feature = "async-sdc")]
+pub mod sdc_async;
 pub mod sdc_simple_measurment;
-pub mod status_led;
-pub mod ir_nec_rx;
+pub mod staleness_monitor;
+pub mod status_led;
+pub mod usb_bench;
+pub mod ventilation;
+pub mod watchdog;
+pub mod wifi_reporter;
+pub mod ir_rx_dispatch;
+pub mod ir_nec_tx;
+pub mod ir_sony_tx;
+#[cfg(feature = "ws2812")]
+pub mod ws2812;
 
 
+use crate::{error_registry::{self, Subsystem}, qq_alarm_queue::QQAlarmQueue};
+
+
+
+/// Result of updating helper (finite) state machine.
+/// `Active(did_something)` - machine is still running, `Done(result)` - machine finished.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State<T> {
+    Active(bool),
+    Done(T)
+}
+
 
 /// Helper state machine representing waiting for qq alarm
+///
+/// When qq is full, alarm is added later by `retry` (machine calls it from its `update`), so the delay is only prolonged.
+/// Full qq has some waiting alarms, so main loop is woken up and slot is freed by one of them.
+/// This is the overflow policy of qq, alarms are never evicted, failed add is recorded to error registry (`Subsystem::Qq`)
+/// once by `start`, retries do not record it again.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Delay {
     Waiting { qq_alarm_id: usize },
+    Retry { wake_at: u64 },
     Done,
 }
 
 impl Delay {
-    pub fn new(qq_alarm_id: usize) -> Delay {
-        Delay::Waiting { qq_alarm_id }
+    pub fn start(qq: &mut impl QQAlarmQueue, wake_at: u64) -> Delay {
+        match qq.add(wake_at) {
+            Ok(qq_alarm_id) => Delay::Waiting { qq_alarm_id },
+            Err(err) => {
+                error_registry::record_error(Subsystem::Qq, &err);
+                Delay::Retry { wake_at }
+            },
+        }
+    }
+
+    /// Returns `true` when the alarm was added (previous attempt failed because qq was full).
+    pub fn retry(&mut self, qq: &mut impl QQAlarmQueue) -> bool {
+        if let Delay::Retry { wake_at } = *self && let Ok(qq_alarm_id) = qq.add(wake_at) {
+            *self = Delay::Waiting { qq_alarm_id };
+            true
+        } else {
+            false
+        }
     }
 
     pub fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
@@ -26,4 +88,53 @@ impl Delay {
             false
         }
     }
+}
+
+/// Helper state machine representing periodic qq alarm (see `QQAlarmQueue::add_periodic`)
+///
+/// Alarm stays in qq between ticks, so there is no drift. When qq is full, it is added later by `retry` (same as `Delay`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ticker {
+    Running { qq_alarm_id: usize, ticked: bool },
+    Retry { period: u64, phase: u64 },
+}
+
+impl Ticker {
+    pub fn start(qq: &mut impl QQAlarmQueue, period: u64, phase: u64) -> Ticker {
+        match qq.add_periodic(period, phase) {
+            Ok(qq_alarm_id) => Ticker::Running { qq_alarm_id, ticked: false },
+            Err(err) => {
+                error_registry::record_error(Subsystem::Qq, &err);
+                Ticker::Retry { period, phase }
+            },
+        }
+    }
+
+    /// Returns `true` when the alarm was added (previous attempt failed because qq was full).
+    pub fn retry(&mut self, qq: &mut impl QQAlarmQueue) -> bool {
+        if let Ticker::Retry { period, phase } = *self && let Ok(qq_alarm_id) = qq.add_periodic(period, phase) {
+            *self = Ticker::Running { qq_alarm_id, ticked: false };
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Returns `true` once for each fired alarm (periods missed in between are merged).
+    pub fn take_tick(&mut self) -> bool {
+        if let Ticker::Running { ticked, .. } = self {
+            core::mem::take(ticked)
+        } else {
+            false
+        }
+    }
+
+    pub fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        if let Ticker::Running { qq_alarm_id: id, ticked } = self && *id == qq_alarm_id {
+            *ticked = true;
+            true
+        } else {
+            false
+        }
+    }
 }
\ No newline at end of file
@@ -0,0 +1,270 @@
+use core::fmt::Write;
+
+use embedded_hal::digital::OutputPin;
+
+use esp_hal::timer::systimer::SystemTimer;
+
+use crate::{
+    event_bus::{Event, EventBus},
+    fixed_point::Milli,
+    flight_recorder::{self, TraceEvent},
+    log::{debug, info, warn, Module},
+    qq_alarm_queue::{QQAlarmQueue, QQOwner, TaggedQQAlarmQueue},
+    scheduler::{Context, Machine},
+    usb_writer::UsbWriter
+};
+
+use super::{console::ConsoleCommand, controller::Controller, status_led::{LedPattern, LedPatternRequest}, Delay};
+
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AlertLevel {
+    Normal,
+    Warning,
+    Critical,
+}
+
+impl AlertLevel {
+    fn name(&self) -> &'static str {
+        match self {
+            AlertLevel::Normal => "normal",
+            AlertLevel::Warning => "warning",
+            AlertLevel::Critical => "critical",
+        }
+    }
+}
+
+
+/// Buzzer driven by alert, either active buzzer (any output pin, on while level is critical) or piezo buzzer playing
+/// tone pattern of each level (see `PiezoBuzzer`).
+pub trait AlertBuzzer {
+    fn alarm(&mut self, qq: &mut impl QQAlarmQueue, level: AlertLevel);
+
+    fn silence(&mut self, qq: &mut impl QQAlarmQueue);
+
+    /// Buzzers with timing are updated (and get alarms) through `Alert`.
+    fn update(&mut self, _qq: &mut impl QQAlarmQueue) -> bool {
+        false
+    }
+
+    fn on_alarm(&mut self, _qq_alarm_id: usize) -> bool {
+        false
+    }
+}
+
+impl<T> AlertBuzzer for T where T: OutputPin {
+    fn alarm(&mut self, _qq: &mut impl QQAlarmQueue, level: AlertLevel) {
+        self.set_state((level == AlertLevel::Critical).into()).unwrap();
+    }
+
+    fn silence(&mut self, _qq: &mut impl QQAlarmQueue) {
+        self.set_low().unwrap();
+    }
+}
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct AlertConfig {
+    /// co2 thresholds (in 10^-3 ppm), level is raised when co2 is at or above threshold
+    pub warning: i32,
+    pub critical: i32,
+    /// level is cleared when co2 drops below `threshold - hysteresis` (in 10^-3 ppm)
+    pub hysteresis: i32,
+    /// new level has to be kept for this long (in system timer ticks) before it is applied
+    pub debounce: u64,
+    /// warning is raised also while co2 rises at least this fast (in 10^-3 ppm per minute, see `Controller::co2_trend`),
+    /// even when co2 is below the warning threshold
+    pub rate_warning: Option<i32>,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum AlertState {
+    Steady,
+    /// co2 crossed threshold, `level` is applied when delay is done (unless co2 returns back before that)
+    Debounce {
+        level: AlertLevel,
+        delay: Delay,
+    },
+}
+
+/// Watches co2 of measurments processed by `Controller` and drives outputs by alert level.
+///
+/// Warning is raised also by fast rising co2 (`AlertConfig::rate_warning`), before the warning threshold is reached.
+/// Warning shows `LedPattern::Co2Alarm`, critical shows `LedPattern::Co2Critical`, buzzer is alarmed on each level
+/// (see `AlertBuzzer`).
+/// Each applied change of level (raised or cleared) is logged to usb and published as `Event::AlertLevel`.
+pub struct Alert<T> {
+    buzzer: T,
+    config: AlertConfig,
+    level: AlertLevel,
+    last_measurment_at: Option<u64>,
+    warning_led: LedPatternRequest,
+    critical_led: LedPatternRequest,
+    state: AlertState,
+}
+
+impl<T> Alert<T> where T: AlertBuzzer {
+    pub fn new(buzzer: T, config: AlertConfig) -> Self {
+        Self {
+            buzzer,
+            config,
+            level: AlertLevel::Normal,
+            last_measurment_at: None,
+            warning_led: LedPatternRequest::new(LedPattern::Co2Alarm),
+            critical_led: LedPatternRequest::new(LedPattern::Co2Critical),
+            state: AlertState::Steady,
+        }
+    }
+
+    /// Level given by `co2` (in 10^-3 ppm) and its `slope` (in 10^-3 ppm per minute), hysteresis is applied to thresholds
+    /// at or below current level.
+    fn target_level(&self, co2: i32, slope: Option<i32>) -> AlertLevel {
+        let threshold = |level: AlertLevel, threshold: i32| if level <= self.level { threshold - self.config.hysteresis } else { threshold };
+        let rising_fast = matches!((slope, self.config.rate_warning), (Some(slope), Some(rate_warning)) if slope >= rate_warning);
+
+        if co2 >= threshold(AlertLevel::Critical, self.config.critical) {
+            AlertLevel::Critical
+        } else if co2 >= threshold(AlertLevel::Warning, self.config.warning) || rising_fast {
+            AlertLevel::Warning
+        } else {
+            AlertLevel::Normal
+        }
+    }
+
+    fn apply(&mut self, usb_writer: &mut impl Write, qq: &mut impl QQAlarmQueue, events: &mut EventBus, level: AlertLevel) {
+        if level > self.level {
+            warn!(usb_writer, Module::Alert, "co2 {} raised", level.name());
+        } else {
+            info!(usb_writer, Module::Alert, "co2 {} cleared", self.level.name());
+        }
+
+        flight_recorder::record(TraceEvent::AlertLevel, level as u32);
+
+        self.level = level;
+        self.warning_led.set(level == AlertLevel::Warning);
+        self.critical_led.set(level == AlertLevel::Critical);
+        self.buzzer.alarm(qq, level);
+        events.publish(Event::AlertLevel(level));
+    }
+
+    /// Turns buzzer off until the level changes again, led patterns stay.
+    pub fn silence(&mut self, usb_writer: &mut impl Write, qq: &mut impl QQAlarmQueue) {
+        if self.level != AlertLevel::Normal {
+            info!(usb_writer, Module::Alert, "buzzer silenced");
+        }
+
+        self.buzzer.silence(qq);
+    }
+
+    fn cancel_debounce(&mut self, qq: &mut impl QQAlarmQueue) {
+        if let AlertState::Debounce { delay: Delay::Waiting { qq_alarm_id }, .. } = self.state {
+            let _ = qq.remove(qq_alarm_id);
+        }
+
+        self.state = AlertState::Steady;
+    }
+
+    pub fn update<const N: usize>(&mut self, usb_writer: &mut impl Write, qq: &mut impl QQAlarmQueue, events: &mut EventBus, controller: &Controller<N>) -> bool {
+        self.buzzer.update(qq) | self.update_level(usb_writer, qq, events, controller)
+    }
+
+    fn update_level<const N: usize>(&mut self, usb_writer: &mut impl Write, qq: &mut impl QQAlarmQueue, events: &mut EventBus, controller: &Controller<N>) -> bool {
+        let last_measurment_at = controller.last_measurment_at();
+
+        if last_measurment_at != self.last_measurment_at {
+            self.last_measurment_at = last_measurment_at;
+
+            let Some(co2) = controller.last_co2() else {
+                return true;
+            };
+
+            let slope = controller.co2_trend().map(|trend| trend.slope);
+            let level = self.target_level(co2, slope);
+
+            match self.state {
+                // already debouncing the same change
+                AlertState::Debounce { level: pending, .. } if pending == level => {},
+                _ => {
+                    self.cancel_debounce(qq);
+
+                    if level != self.level {
+                        debug!(usb_writer, Module::Alert, "co2 {:.1} ppm ({:+.1} ppm/min), {} pending", Milli::from(co2), Milli::from(slope.unwrap_or(0)), level.name());
+                        self.state = AlertState::Debounce {
+                            level,
+                            delay: Delay::start(qq, SystemTimer::now() + self.config.debounce),
+                        };
+                    }
+                },
+            }
+
+            return true;
+        }
+
+        match &mut self.state {
+            AlertState::Debounce { level, delay: Delay::Done } => {
+                let level = *level;
+                self.state = AlertState::Steady;
+                self.apply(usb_writer, qq, events, level);
+
+                true
+            },
+            AlertState::Debounce { delay, .. } => delay.retry(qq),
+            AlertState::Steady => false,
+        }
+    }
+
+    pub fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        if self.buzzer.on_alarm(qq_alarm_id) {
+            return true;
+        }
+
+        match &mut self.state {
+            AlertState::Debounce { delay, .. } => delay.on_alarm(qq_alarm_id),
+            _ => false,
+        }
+    }
+
+    /// Silence command (`alert silence`, ir remote or button).
+    /// Thresholds are validated by `commands::handle`, new thresholds are used from the next measurment.
+    pub fn on_command(&mut self, command: ConsoleCommand, usb_writer: &mut impl Write, qq: &mut impl QQAlarmQueue) -> bool {
+        match command {
+            ConsoleCommand::AlertSilence => self.silence(usb_writer, qq),
+            ConsoleCommand::AlertThresholds { warning, critical, hysteresis } => {
+                self.config.warning = warning;
+                self.config.critical = critical;
+                self.config.hysteresis = hysteresis.unwrap_or(self.config.hysteresis);
+            },
+            _ => return false,
+        }
+
+        true
+    }
+}
+
+impl<'c, 'i, T, W, Q, const N: usize> Machine<Context<'c, 'i, W, Q, N>> for Alert<T>
+where
+    T: AlertBuzzer,
+    W: Write + UsbWriter,
+    Q: TaggedQQAlarmQueue,
+{
+    fn name(&self) -> &'static str {
+        "alert"
+    }
+
+    fn owns(&self, owner: QQOwner) -> bool {
+        owner == QQOwner::Alert
+    }
+
+    fn update(&mut self, context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        Self::update(self, context.usb_writer, &mut context.qq.owned(QQOwner::Alert), context.events, context.controller)
+    }
+
+    fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        Self::on_alarm(self, qq_alarm_id)
+    }
+
+    fn on_command(&mut self, command: ConsoleCommand, context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        Self::on_command(self, command, context.usb_writer, &mut context.qq.owned(QQOwner::Alert))
+    }
+}
@@ -0,0 +1,247 @@
+use core::fmt::Write;
+
+use esp_hal::timer::systimer::SystemTimer;
+
+use crate::{
+    error_registry::{self, ErrorCode, Subsystem},
+    event_bus::{Event, EventBus},
+    i2c_bus::{DelayedWriteRead, I2CBus, I2CBusUser},
+    i2c_engine::I2CEngineError,
+    invariants::invariant,
+    log::{error, Module},
+    qq_alarm_queue::{QQAlarmQueue, QQOwner, TaggedQQAlarmQueue},
+    scheduler::{Context, Machine},
+    sensirion_word,
+    usb_writer::UsbWriter
+};
+
+use super::{status_led::{LedPattern, LedPatternRequest}, Delay, State};
+
+
+
+/// Reading from ambient (temperature / humidity) sensor, pressure is measured by `Bme280` (feature `bme280`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AmbientReading {
+    /// in m°C
+    pub temperature: i32,
+    /// in m%
+    pub humidity: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AmbientSensorError {
+    Transaction(I2CEngineError),
+    CRCCheckFailed,
+}
+
+impl ErrorCode for AmbientSensorError {
+    fn error_code(&self) -> u16 {
+        match self {
+            AmbientSensorError::Transaction(err) => err.error_code(),
+            AmbientSensorError::CRCCheckFailed => 0x01,
+        }
+    }
+}
+
+
+/// Device specific part of `AmbientSensor`, single measurement is done as: write `measure_command`, wait `measure_delay`, read `RESULT_LEN` bytes.
+pub trait AmbientSensorDriver {
+    const ADDRESS: u8;
+    /// at most `DelayedWriteRead::MAX_LEN`
+    const RESULT_LEN: u8;
+
+    fn name(&self) -> &'static str;
+    fn measure_command(&self) -> &[u8];
+    /// in system timer ticks
+    fn measure_delay(&self) -> u64;
+    /// `result` has length `RESULT_LEN`
+    fn parse_result(&self, result: &[u8]) -> Result<AmbientReading, AmbientSensorError>;
+}
+
+
+/// Sensirion SHT3x temperature and humidity sensor.
+/// Uses single shot measurement with high repeatability and without clock stretching.
+pub struct Sht3x;
+
+impl Sht3x {
+    pub const DEFAULT_ADDRESS: u8 = 0x44;
+    /// single shot measurement, high repeatability, without clock stretching
+    pub const MEASURE_COMMAND: u16 = 0x2400;
+    /// from sht3x documentation: maximum measurement duration for high repeatability is 15.5ms
+    pub const MEASURE_DELAY: u64 = SystemTimer::TICKS_PER_SECOND / 1000 * 16;
+
+
+    /// Reading from raw temperature and humidity words.
+    pub fn reading(temperature_raw: u16, humidity_raw: u16) -> AmbientReading {
+        let temperature_raw = temperature_raw as i64;
+        let humidity_raw = humidity_raw as i64;
+
+        // T = -45 + 175 * raw / (2^16 - 1), RH = 100 * raw / (2^16 - 1)
+        AmbientReading {
+            temperature: (-45_000 + 175_000 * temperature_raw / 65_535) as i32,
+            humidity: (100_000 * humidity_raw / 65_535) as i32,
+        }
+    }
+}
+
+impl AmbientSensorDriver for Sht3x {
+    const ADDRESS: u8 = Self::DEFAULT_ADDRESS;
+    const RESULT_LEN: u8 = 6;
+
+    fn name(&self) -> &'static str {
+        "sht3x"
+    }
+
+    fn measure_command(&self) -> &[u8] {
+        const COMMAND: [u8; 2] = Sht3x::MEASURE_COMMAND.to_be_bytes();
+        &COMMAND
+    }
+
+    fn measure_delay(&self) -> u64 {
+        Self::MEASURE_DELAY
+    }
+
+    fn parse_result(&self, result: &[u8]) -> Result<AmbientReading, AmbientSensorError> {
+        // sht3x uses same word protocol as scd30
+        let word = |i: usize| sensirion_word::decode_word([result[i], result[i + 1], result[i + 2]]).map_err(|_| AmbientSensorError::CRCCheckFailed);
+
+        Ok(Self::reading(word(0)?, word(3)?))
+    }
+}
+
+
+
+pub struct AmbientSensorConfig {
+    /// delay between measurements, in system timer ticks
+    pub period: u64,
+    pub bus_user: I2CBusUser,
+}
+
+#[derive(Debug)]
+enum AmbientSensorState {
+    None,
+    Waiting(Delay),
+    /// transaction is in `AmbientSensor::measuring`
+    Measuring,
+}
+
+/// Periodically measures ambient temperature and humidity using second sensor on the shared i2c bus and publishes readings (`Event::Ambient`).
+///
+/// Errors are reported and measurement is retried after `period`.
+pub struct AmbientSensor<D> {
+    driver: D,
+    period: u64,
+    bus_user: I2CBusUser,
+    i2c_error: LedPatternRequest,
+    state: AmbientSensorState,
+    /// kept out of `state` (there is no allocator to box it), `Some` in `AmbientSensorState::Measuring`
+    measuring: Option<DelayedWriteRead>,
+}
+
+impl<D> AmbientSensor<D> where D: AmbientSensorDriver {
+    pub fn new(driver: D, config: AmbientSensorConfig) -> Self {
+        Self {
+            driver,
+            period: config.period,
+            bus_user: config.bus_user,
+            i2c_error: LedPatternRequest::new(LedPattern::I2CError),
+            state: AmbientSensorState::None,
+            measuring: None,
+        }
+    }
+
+    fn start_delay(&mut self, qq: &mut impl QQAlarmQueue) {
+        self.state = AmbientSensorState::Waiting(Delay::start(qq, SystemTimer::now() + self.period));
+    }
+
+    pub fn start(&mut self, qq: &mut impl QQAlarmQueue) {
+        if let AmbientSensorState::None = self.state {
+            self.start_delay(qq);
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        bus: &mut I2CBus,
+        usb_writer: &mut impl Write,
+        qq: &mut impl QQAlarmQueue,
+        events: &mut EventBus,
+    ) -> bool {
+        match &mut self.state {
+            AmbientSensorState::Waiting(Delay::Done) => {
+                if !bus.try_acquire(self.bus_user) {
+                    return false;
+                }
+
+                self.measuring = Some(DelayedWriteRead::start(D::ADDRESS, self.driver.measure_command(), D::RESULT_LEN, self.driver.measure_delay()));
+                self.state = AmbientSensorState::Measuring;
+
+                true
+            },
+            AmbientSensorState::Measuring => {
+                let Some(measuring) = &mut self.measuring else {
+                    invariant!(false, "ambient sensor measuring without transaction");
+                    bus.release(self.bus_user);
+                    self.start_delay(qq);
+                    return true;
+                };
+
+                let result = match measuring.update(qq, bus.i2c()) {
+                    State::Active(did_something) => return did_something,
+                    State::Done(Ok(())) => self.driver.parse_result(measuring.response()),
+                    State::Done(Err(err)) => Err(AmbientSensorError::Transaction(err)),
+                };
+
+                self.measuring = None;
+                bus.release(self.bus_user);
+
+                self.i2c_error.set(result.is_err());
+
+                match result {
+                    Ok(reading) => events.publish(Event::Ambient(reading)),
+                    Err(err) => {
+                        error!(usb_writer, Module::Ambient, "{} error : {:?}", self.driver.name(), err);
+                        error_registry::record_error(Subsystem::AmbientSensor, &err);
+                    },
+                }
+
+                self.start_delay(qq);
+
+                true
+            },
+            AmbientSensorState::Waiting(delay) => delay.retry(qq),
+            AmbientSensorState::None => false,
+        }
+    }
+
+    pub fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        match &mut self.state {
+            AmbientSensorState::Waiting(delay) => delay.on_alarm(qq_alarm_id),
+            AmbientSensorState::Measuring => self.measuring.as_mut().is_some_and(|measuring| measuring.on_alarm(qq_alarm_id)),
+            _ => false,
+        }
+    }
+}
+
+impl<'c, 'i, D, W, Q, const N: usize> Machine<Context<'c, 'i, W, Q, N>> for AmbientSensor<D>
+where
+    D: AmbientSensorDriver,
+    W: Write + UsbWriter,
+    Q: TaggedQQAlarmQueue,
+{
+    fn name(&self) -> &'static str {
+        "ambient sensor"
+    }
+
+    fn owns(&self, owner: QQOwner) -> bool {
+        owner == QQOwner::AmbientSensor
+    }
+
+    fn update(&mut self, context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        Self::update(self, context.i2c_bus, context.usb_writer, &mut context.qq.owned(QQOwner::AmbientSensor), context.events)
+    }
+
+    fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        Self::on_alarm(self, qq_alarm_id)
+    }
+}
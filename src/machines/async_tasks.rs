@@ -0,0 +1,52 @@
+use core::fmt::Write;
+
+use crate::{
+    async_io::AsyncIo,
+    executor::{Executor, Task},
+    qq_alarm_queue::{QQOwner, TaggedQQAlarmQueue},
+    scheduler::{Context, Machine},
+    usb_writer::UsbWriter,
+};
+
+
+
+/// Async tasks (see `executor`) scheduled as one machine, they use resources of `Context` through `io`.
+///
+/// Tasks are polled only when they were woken, qq alarms of their delays are added on behalf of `io.owner()`.
+pub struct AsyncTasks<'a, 'd, W, Q> {
+    executor: Executor<'a>,
+    io: &'a AsyncIo<'d, W, Q>,
+}
+
+impl<'a, 'd, W, Q> AsyncTasks<'a, 'd, W, Q> {
+    pub fn new(io: &'a AsyncIo<'d, W, Q>) -> Self {
+        Self { executor: Executor::new(), io }
+    }
+
+    pub fn spawn(&mut self, task: Task<'a>) -> Result<(), Task<'a>> {
+        self.executor.spawn(task)
+    }
+}
+
+impl<'a, 'c, 'd, W, Q, const N: usize> Machine<Context<'c, 'd, W, Q, N>> for AsyncTasks<'a, 'd, W, Q>
+where
+    W: Write + UsbWriter,
+    Q: TaggedQQAlarmQueue,
+{
+    fn name(&self) -> &'static str {
+        "async tasks"
+    }
+
+    fn owns(&self, owner: QQOwner) -> bool {
+        owner == self.io.owner()
+    }
+
+    fn update(&mut self, context: &mut Context<'c, 'd, W, Q, N>) -> bool {
+        let executor = &mut self.executor;
+        self.io.lend(context.i2c_bus, context.usb_writer, context.qq, context.events, || executor.poll())
+    }
+
+    fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        self.io.on_alarm(qq_alarm_id)
+    }
+}
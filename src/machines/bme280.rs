@@ -0,0 +1,262 @@
+use core::fmt::Write;
+
+use esp_hal::timer::systimer::SystemTimer;
+
+use crate::{
+    bme280::{self, Calibration, RawReading},
+    error_registry::{self, ErrorCode, Subsystem},
+    event_bus::{Event, EventBus},
+    i2c_bus::{I2CBus, I2CBusUser},
+    i2c_engine::{I2CEngine, I2CEngineError, I2COperation},
+    log::{error, info, Module},
+    qq_alarm_queue::{QQAlarmQueue, QQOwner, TaggedQQAlarmQueue},
+    scheduler::{Context, Machine},
+    usb_writer::UsbWriter
+};
+
+use super::{status_led::{LedPattern, LedPatternRequest}, Delay, State};
+
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bme280Error {
+    Transaction(I2CEngineError),
+    /// device on the address is neither BME280 nor BMP280
+    UnknownChipId(u8),
+    InvalidCalibration,
+}
+
+impl ErrorCode for Bme280Error {
+    fn error_code(&self) -> u16 {
+        match self {
+            Bme280Error::Transaction(err) => err.error_code(),
+            Bme280Error::UnknownChipId(_) => 0x05,
+            Bme280Error::InvalidCalibration => 0x06,
+        }
+    }
+}
+
+
+pub struct Bme280Config {
+    /// `Bme280::DEFAULT_ADDRESS` or `0x77` (SDO pin high)
+    pub address: u8,
+    /// delay between measurements, in system timer ticks
+    pub period: u64,
+    pub bus_user: I2CBusUser,
+}
+
+#[derive(Debug)]
+enum Bme280State {
+    None,
+    ReadingCalibration(I2CEngine<6>),
+    Waiting(Delay),
+    Measuring(I2CEngine<5>),
+    Error,
+}
+
+/// Bosch BME280 (or BMP280 without humidity) on the shared i2c bus, periodically measures ambient pressure.
+///
+/// Only pressure is published as `Event::Pressure` (temperature and humidity are measured by `AmbientSensor`), it is used for scd30 pressure compensation (see `Controller::pressure_compensation`).
+/// Calibration is read once after start, measurements use forced mode with oversampling x1.
+/// When calibration cannot be read (no sensor on the address) machine stops, measurement errors are reported and measurement is retried after `period`.
+/// Built only with feature `bme280`.
+pub struct Bme280 {
+    address: u8,
+    period: u64,
+    bus_user: I2CBusUser,
+    calibration: Option<Calibration>,
+    i2c_error: LedPatternRequest,
+    state: Bme280State,
+}
+
+impl Bme280 {
+    pub const DEFAULT_ADDRESS: u8 = 0x76;
+
+    const CHIP_ID_BME280: u8 = 0x60;
+    /// BMP280 (mass production and samples)
+    const CHIP_IDS_BMP280: [u8; 3] = [0x56, 0x57, 0x58];
+
+    /// from bme280 documentation: maximum measurement time with oversampling x1 for all values is 9.3 ms
+    const MEASURE_DELAY: u64 = SystemTimer::TICKS_PER_SECOND / 1000 * 10;
+
+
+    pub fn new(config: Bme280Config) -> Self {
+        Self {
+            address: config.address,
+            period: config.period,
+            bus_user: config.bus_user,
+            calibration: None,
+            i2c_error: LedPatternRequest::new(LedPattern::I2CError),
+            state: Bme280State::None,
+        }
+    }
+
+    pub fn start(&mut self) {
+        if let Bme280State::None = self.state {
+            let mut engine = I2CEngine::new(1);
+
+            // cannot fail, 6 operations and 34 bytes of response
+            let _ = I2COperation::write(self.address, &[0xd0]).map(|write| engine.push(write));
+            let _ = engine.push(I2COperation::Read { address: self.address, len: 1 });
+            let _ = I2COperation::write(self.address, &[0x88]).map(|write| engine.push(write));
+            let _ = engine.push(I2COperation::Read { address: self.address, len: Calibration::TP_LEN });
+            // read also from bmp280, values are ignored
+            let _ = I2COperation::write(self.address, &[0xe1]).map(|write| engine.push(write));
+            let _ = engine.push(I2COperation::Read { address: self.address, len: Calibration::H_LEN });
+
+            engine.start();
+            self.state = Bme280State::ReadingCalibration(engine);
+        }
+    }
+
+    fn start_measurement(&mut self) {
+        let mut engine = I2CEngine::new(1);
+        let has_humidity = self.calibration.is_some_and(|calibration| calibration.has_humidity());
+
+        // cannot fail, 5 operations and 8 bytes of response
+        if has_humidity {
+            // ctrl_hum (humidity oversampling x1), has to be written before ctrl_meas
+            let _ = I2COperation::write(self.address, &[0xf2, 0x01]).map(|write| engine.push(write));
+        }
+        // ctrl_meas (temperature and pressure oversampling x1, forced mode)
+        let _ = I2COperation::write(self.address, &[0xf4, 0x25]).map(|write| engine.push(write));
+        let _ = engine.push(I2COperation::Delay(Self::MEASURE_DELAY));
+        let _ = I2COperation::write(self.address, &[0xf7]).map(|write| engine.push(write));
+        let _ = engine.push(I2COperation::Read { address: self.address, len: RawReading::LEN });
+
+        engine.start();
+        self.state = Bme280State::Measuring(engine);
+    }
+
+    fn parse_calibration(response: &[u8]) -> Result<Calibration, Bme280Error> {
+        let (chip_id, tp, h) = (response[0], &response[1..(1 + Calibration::TP_LEN)], &response[(1 + Calibration::TP_LEN)..]);
+
+        // cannot fail, lengths are given by read operations
+        let tp = tp.try_into().map_err(|_| Bme280Error::InvalidCalibration)?;
+        let h = h.try_into().map_err(|_| Bme280Error::InvalidCalibration)?;
+
+        match chip_id {
+            Self::CHIP_ID_BME280 => Ok(Calibration::from_registers(tp, Some(h))),
+            chip_id if Self::CHIP_IDS_BMP280.contains(&chip_id) => Ok(Calibration::from_registers(tp, None)),
+            chip_id => Err(Bme280Error::UnknownChipId(chip_id)),
+        }
+    }
+
+    fn start_delay(&mut self, qq: &mut impl QQAlarmQueue) {
+        self.state = Bme280State::Waiting(Delay::start(qq, SystemTimer::now() + self.period));
+    }
+
+    pub fn update(
+        &mut self,
+        bus: &mut I2CBus,
+        usb_writer: &mut impl Write,
+        qq: &mut impl QQAlarmQueue,
+        events: &mut EventBus,
+    ) -> bool {
+        match &mut self.state {
+            Bme280State::ReadingCalibration(engine) => {
+                if !bus.try_acquire(self.bus_user) {
+                    return false;
+                }
+
+                let result = match engine.update(qq, bus.i2c()) {
+                    State::Active(did_something) => return did_something,
+                    State::Done(Ok(())) => Self::parse_calibration(engine.response()),
+                    State::Done(Err(err)) => Err(Bme280Error::Transaction(err)),
+                };
+
+                match result {
+                    Ok(calibration) => {
+                        info!(usb_writer, Module::Ambient, "bme280 variant : {}", if calibration.has_humidity() { "bme280" } else { "bmp280 (no humidity)" });
+                        self.calibration = Some(calibration);
+                        // bus is still owned
+                        self.start_measurement();
+                    },
+                    Err(err) => {
+                        error!(usb_writer, Module::Ambient, "bme280 calibration error : {:?}", err);
+                        error_registry::record_error(Subsystem::PressureSensor, &err);
+                        bus.release(self.bus_user);
+                        self.i2c_error.set(true);
+                        self.state = Bme280State::Error;
+                    },
+                }
+
+                true
+            },
+            Bme280State::Waiting(Delay::Done) => {
+                if !bus.try_acquire(self.bus_user) {
+                    return false;
+                }
+
+                self.start_measurement();
+                true
+            },
+            Bme280State::Measuring(engine) => {
+                let result = match engine.update(qq, bus.i2c()) {
+                    State::Active(did_something) => return did_something,
+                    State::Done(Ok(())) => {
+                        // cannot fail, length is given by read operation
+                        let registers = engine.response().try_into().map_err(|_| Bme280Error::InvalidCalibration);
+
+                        registers.and_then(|registers| {
+                            self.calibration
+                                .and_then(|calibration| bme280::compensate(&calibration, RawReading::from_registers(registers)))
+                                .ok_or(Bme280Error::InvalidCalibration)
+                        })
+                    },
+                    State::Done(Err(err)) => Err(Bme280Error::Transaction(err)),
+                };
+
+                bus.release(self.bus_user);
+
+                self.i2c_error.set(result.is_err());
+
+                match result {
+                    Ok(reading) => events.publish(Event::Pressure(reading.pressure)),
+                    Err(err) => {
+                        error!(usb_writer, Module::Ambient, "bme280 error : {:?}", err);
+                        error_registry::record_error(Subsystem::PressureSensor, &err);
+                    },
+                }
+
+                self.start_delay(qq);
+
+                true
+            },
+            Bme280State::Waiting(delay) => delay.retry(qq),
+            Bme280State::None |
+            Bme280State::Error => false,
+        }
+    }
+
+    pub fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        match &mut self.state {
+            Bme280State::Waiting(delay) => delay.on_alarm(qq_alarm_id),
+            Bme280State::ReadingCalibration(engine) => engine.on_alarm(qq_alarm_id),
+            Bme280State::Measuring(engine) => engine.on_alarm(qq_alarm_id),
+            _ => false,
+        }
+    }
+}
+
+impl<'c, 'i, W, Q, const N: usize> Machine<Context<'c, 'i, W, Q, N>> for Bme280
+where
+    W: Write + UsbWriter,
+    Q: TaggedQQAlarmQueue,
+{
+    fn name(&self) -> &'static str {
+        "bme280"
+    }
+
+    fn owns(&self, owner: QQOwner) -> bool {
+        owner == QQOwner::Bme280
+    }
+
+    fn update(&mut self, context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        Self::update(self, context.i2c_bus, context.usb_writer, &mut context.qq.owned(QQOwner::Bme280), context.events)
+    }
+
+    fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        Self::on_alarm(self, qq_alarm_id)
+    }
+}
@@ -0,0 +1,246 @@
+use core::fmt::Write;
+
+use esp_hal::{
+    gpio::{Event, Input, InputPin, Pull},
+    peripheral::Peripheral,
+    timer::systimer::SystemTimer
+};
+
+use crate::{
+    event_bus,
+    interrupts::{self, GPIOInterruptStatus},
+    invariants::invariant,
+    log::{debug, info, Module},
+    pac_utils::gpio::PinNumber,
+    qq_alarm_queue::{QQAlarmQueue, QQOwner, TaggedQQAlarmQueue},
+    scheduler::{Context, Machine},
+    usb_writer::UsbWriter
+};
+
+use super::{console::ConsoleCommand, Delay};
+
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ButtonEvent {
+    ShortPress,
+    LongPress,
+    DoubleClick,
+}
+
+impl ButtonEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            ButtonEvent::ShortPress => "short press",
+            ButtonEvent::LongPress => "long press",
+            ButtonEvent::DoubleClick => "double click",
+        }
+    }
+}
+
+/// Button event bound to a command, handled same as the command entered into console (see `Event::Command`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ButtonBinding {
+    pub event: ButtonEvent,
+    pub command: ConsoleCommand,
+}
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct ButtonConfig {
+    /// level has to be stable for this long after the last edge (in system timer ticks)
+    pub debounce: u64,
+    /// press held for this long is long press (in system timer ticks)
+    pub long_press: u64,
+    /// second press has to come within this time after release of the first one (in system timer ticks)
+    pub double_click: u64,
+    pub bindings: &'static [ButtonBinding],
+}
+
+
+#[derive(Debug, Clone, Copy)]
+enum ButtonState {
+    Released,
+    /// `delay` is long press timeout, `second` is second press of double click
+    Pressed { delay: Delay, second: bool },
+    /// long press was reported, waiting for release
+    Held,
+    /// short press was released, waiting for second press, `delay` is double click timeout
+    Clicked(Delay),
+}
+
+/// Button connected between pin and ground (internal pull up is used), e.g. boot button of devkit.
+///
+/// Each edge (re)starts debounce delay, pin level is read when it is done. Debounced presses are turned into events:
+/// - long press - reported as soon as button is held for `long_press`
+/// - double click - reported on release of second press which started within `double_click` after the first one (unless it is held for long press)
+/// - short press - reported after `double_click` without second press (so short press is delayed by it)
+///
+/// Events of `bindings` are turned into commands, command is kept until `take_command` is called.
+pub struct Button<'d, PIN> {
+    pin: Input<'d, PIN>,
+    config: ButtonConfig,
+    /// debounced level
+    pressed: bool,
+    debounce: Option<Delay>,
+    command: Option<ConsoleCommand>,
+    state: ButtonState,
+}
+
+impl<'d, PIN> Button<'d, PIN>
+where
+    PIN: InputPin + PinNumber,
+{
+    pub fn new(pin: impl Peripheral<P = PIN> + 'd, config: ButtonConfig) -> Self {
+        let mut pin = Input::new(pin, Pull::Up);
+        pin.listen(Event::AnyEdge);
+
+        Self {
+            pin,
+            config,
+            pressed: false,
+            debounce: None,
+            command: None,
+            state: ButtonState::Released,
+        }
+    }
+
+    /// Command bound to the last event (see `ButtonConfig::bindings`).
+    pub fn take_command(&mut self) -> Option<ConsoleCommand> {
+        self.command.take()
+    }
+
+    fn emit(&mut self, usb_writer: &mut impl Write, event: ButtonEvent) {
+        info!(usb_writer, Module::Button, "{}", event.name());
+
+        if let Some(binding) = self.config.bindings.iter().find(|binding| binding.event == event) {
+            self.command = Some(binding.command);
+        }
+    }
+
+    fn on_press(&mut self, qq: &mut impl QQAlarmQueue) {
+        let second = match self.state {
+            ButtonState::Clicked(delay) => {
+                if let Delay::Waiting { qq_alarm_id } = delay {
+                    invariant!(qq.remove(qq_alarm_id).is_ok(), "button alarm not found in qq");
+                }
+                true
+            },
+            _ => false,
+        };
+
+        self.state = ButtonState::Pressed {
+            delay: Delay::start(qq, SystemTimer::now() + self.config.long_press),
+            second,
+        };
+    }
+
+    fn on_release(&mut self, usb_writer: &mut impl Write, qq: &mut impl QQAlarmQueue) {
+        match self.state {
+            ButtonState::Pressed { delay, second } => {
+                if let Delay::Waiting { qq_alarm_id } = delay {
+                    invariant!(qq.remove(qq_alarm_id).is_ok(), "button alarm not found in qq");
+                }
+
+                if second {
+                    self.state = ButtonState::Released;
+                    self.emit(usb_writer, ButtonEvent::DoubleClick);
+                } else {
+                    self.state = ButtonState::Clicked(Delay::start(qq, SystemTimer::now() + self.config.double_click));
+                }
+            },
+            // release without press (level at start)
+            _ => self.state = ButtonState::Released,
+        }
+    }
+
+    pub fn update(&mut self, usb_writer: &mut impl Write, qq: &mut impl QQAlarmQueue) -> bool {
+        let mut did_something = false;
+
+        if !interrupts::gpio_interrupt_get_and_clear(GPIOInterruptStatus::pin(PIN::NUMBER)).is_empty() {
+            if let Some(Delay::Waiting { qq_alarm_id }) = self.debounce {
+                invariant!(qq.remove(qq_alarm_id).is_ok(), "button debounce alarm not found in qq");
+            }
+
+            self.debounce = Some(Delay::start(qq, SystemTimer::now() + self.config.debounce));
+            did_something = true;
+        }
+
+        match &mut self.debounce {
+            Some(Delay::Done) => {
+                self.debounce = None;
+
+                let pressed = self.pin.is_low();
+                if pressed != self.pressed {
+                    debug!(usb_writer, Module::Button, "{}", if pressed { "pressed" } else { "released" });
+                    self.pressed = pressed;
+
+                    if pressed {
+                        self.on_press(qq);
+                    } else {
+                        self.on_release(usb_writer, qq);
+                    }
+                }
+
+                did_something = true;
+            },
+            Some(debounce) => did_something |= debounce.retry(qq),
+            None => {},
+        }
+
+        match &mut self.state {
+            ButtonState::Pressed { delay: Delay::Done, .. } => {
+                self.state = ButtonState::Held;
+                self.emit(usb_writer, ButtonEvent::LongPress);
+                true
+            },
+            ButtonState::Clicked(Delay::Done) => {
+                self.state = ButtonState::Released;
+                self.emit(usb_writer, ButtonEvent::ShortPress);
+                true
+            },
+            ButtonState::Pressed { delay, .. } | ButtonState::Clicked(delay) => did_something | delay.retry(qq),
+            ButtonState::Released | ButtonState::Held => did_something,
+        }
+    }
+
+    pub fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        if let Some(debounce) = &mut self.debounce && debounce.on_alarm(qq_alarm_id) {
+            return true;
+        }
+
+        match &mut self.state {
+            ButtonState::Pressed { delay, .. } | ButtonState::Clicked(delay) => delay.on_alarm(qq_alarm_id),
+            ButtonState::Released | ButtonState::Held => false,
+        }
+    }
+}
+
+impl<'c, 'i, 'd, PIN, W, Q, const N: usize> Machine<Context<'c, 'i, W, Q, N>> for Button<'d, PIN>
+where
+    PIN: InputPin + PinNumber,
+    W: Write + UsbWriter,
+    Q: TaggedQQAlarmQueue,
+{
+    fn name(&self) -> &'static str {
+        "button"
+    }
+
+    fn owns(&self, owner: QQOwner) -> bool {
+        owner == QQOwner::Button
+    }
+
+    fn update(&mut self, context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        let active = Self::update(self, context.usb_writer, &mut context.qq.owned(QQOwner::Button));
+
+        if let Some(command) = self.take_command() {
+            context.events.publish(event_bus::Event::Command(command));
+        }
+
+        active
+    }
+
+    fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        Self::on_alarm(self, qq_alarm_id)
+    }
+}
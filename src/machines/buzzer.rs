@@ -0,0 +1,192 @@
+use esp_hal::{
+    gpio::{Output, OutputPin},
+    peripheral::{Peripheral, PeripheralRef},
+    peripherals::{LEDC, SYSTEM},
+    timer::systimer::SystemTimer
+};
+
+use crate::{
+    pac_utils::{gpio::PinNumber, ledc::{self as ledc_utils, LedcChannel, LedcTimerConfig}},
+    qq_alarm_queue::QQAlarmQueue
+};
+
+use super::{alert::{AlertBuzzer, AlertLevel}, Delay};
+
+
+
+/// One step of tone pattern, pattern is a sequence of tones (e.g. `&[Tone::new(2000, 100), Tone::rest(100)]`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tone {
+    /// in Hz, zero is silence
+    pub freq: u16,
+    /// in ms
+    pub duration: u16,
+}
+
+impl Tone {
+    pub const fn new(freq: u16, duration: u16) -> Self {
+        Self { freq, duration }
+    }
+
+    pub const fn rest(duration: u16) -> Self {
+        Self { freq: 0, duration }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct PiezoBuzzerConfig {
+    /// played once when warning level is raised, empty pattern is silent
+    pub warning: &'static [Tone],
+    /// repeated while level is critical (until silenced)
+    pub critical: &'static [Tone],
+}
+
+#[derive(Debug, Clone, Copy)]
+enum PiezoBuzzerState {
+    Idle,
+    /// tone `index` of `tones` is played until delay is done
+    Playing {
+        tones: &'static [Tone],
+        index: usize,
+        repeat: bool,
+        delay: Delay,
+    },
+}
+
+/// Passive piezo buzzer driven by ledc (timer 1, channel 3, square wave with 50 % duty), plays tone patterns of alert
+/// levels.
+///
+/// Frequency of each tone is set by ledc timer divider (lowest frequency is 77 Hz), end of each tone is scheduled by qq alarm.
+pub struct PiezoBuzzer<'d, PIN> {
+    ledc: PeripheralRef<'d, LEDC>,
+    // pin is kept only to stay owned, it is driven by ledc through gpio matrix
+    _pin: Output<'d, PIN>,
+    config: PiezoBuzzerConfig,
+    state: PiezoBuzzerState,
+}
+
+impl<'d, PIN> PiezoBuzzer<'d, PIN>
+where
+    PIN: OutputPin + PinNumber
+{
+    const TIMER: u8 = 1;
+    const CHANNEL: LedcChannel = LedcChannel::Ch3;
+    const DUTY_RES: u8 = 10;
+
+    /// `80 MHz / 2^DUTY_RES`, with 8 fractional bits of timer divider
+    const DIV_PER_HZ: u32 = (80_000_000 >> Self::DUTY_RES) << 8;
+    /// divider has 10 integer bits
+    const MAX_DIV: u32 = (1 << 18) - 1;
+
+
+    pub fn new<'s>(
+        ledc: impl Peripheral<P = LEDC> + 'd,
+        system: impl Peripheral<P = SYSTEM> + 's,
+        pin: impl Peripheral<P = PIN> + 'd,
+        config: PiezoBuzzerConfig,
+    ) -> Self {
+        let mut ledc = ledc.into_ref();
+
+        ledc_utils::config_clock(system.into_ref());
+        ledc_utils::config(ledc.reborrow());
+        ledc_utils::timer_config(ledc.reborrow(), Self::TIMER, LedcTimerConfig {
+            clk_div: Self::DIV_PER_HZ / 2000,
+            duty_res: Self::DUTY_RES,
+        });
+        ledc_utils::channel_config(ledc.reborrow(), Self::CHANNEL, Self::TIMER);
+
+        Self {
+            ledc,
+            _pin: ledc_utils::setup_output_pin(pin, Self::CHANNEL),
+            config,
+            state: PiezoBuzzerState::Idle,
+        }
+    }
+
+    fn set_freq(&mut self, freq: u16) {
+        if freq == 0 {
+            ledc_utils::set_duty(self.ledc.reborrow(), Self::CHANNEL, 0);
+            return;
+        }
+
+        ledc_utils::timer_config(self.ledc.reborrow(), Self::TIMER, LedcTimerConfig {
+            clk_div: (Self::DIV_PER_HZ / freq as u32).clamp(1 << 8, Self::MAX_DIV),
+            duty_res: Self::DUTY_RES,
+        });
+        ledc_utils::set_duty(self.ledc.reborrow(), Self::CHANNEL, 1 << (Self::DUTY_RES - 1));
+    }
+
+    fn cancel(&mut self, qq: &mut impl QQAlarmQueue) {
+        if let PiezoBuzzerState::Playing { delay: Delay::Waiting { qq_alarm_id }, .. } = self.state {
+            let _ = qq.remove(qq_alarm_id);
+        }
+
+        self.set_freq(0);
+        self.state = PiezoBuzzerState::Idle;
+    }
+
+    fn start_tone(&mut self, qq: &mut impl QQAlarmQueue, tones: &'static [Tone], index: usize, repeat: bool) {
+        let Some(tone) = tones.get(index) else {
+            self.set_freq(0);
+            self.state = PiezoBuzzerState::Idle;
+            return;
+        };
+
+        self.set_freq(tone.freq);
+        self.state = PiezoBuzzerState::Playing {
+            tones,
+            index,
+            repeat,
+            delay: Delay::start(qq, SystemTimer::now() + tone.duration as u64 * SystemTimer::TICKS_PER_SECOND / 1000),
+        };
+    }
+
+    /// Starts playing `tones` (current pattern is stopped), `repeat` plays it until `stop`.
+    pub fn play(&mut self, qq: &mut impl QQAlarmQueue, tones: &'static [Tone], repeat: bool) {
+        self.cancel(qq);
+        self.start_tone(qq, tones, 0, repeat);
+    }
+
+    pub fn stop(&mut self, qq: &mut impl QQAlarmQueue) {
+        self.cancel(qq);
+    }
+}
+
+impl<'d, PIN> AlertBuzzer for PiezoBuzzer<'d, PIN>
+where
+    PIN: OutputPin + PinNumber
+{
+    fn alarm(&mut self, qq: &mut impl QQAlarmQueue, level: AlertLevel) {
+        match level {
+            AlertLevel::Normal => self.stop(qq),
+            AlertLevel::Warning => self.play(qq, self.config.warning, false),
+            AlertLevel::Critical => self.play(qq, self.config.critical, true),
+        }
+    }
+
+    fn silence(&mut self, qq: &mut impl QQAlarmQueue) {
+        self.stop(qq);
+    }
+
+    fn update(&mut self, qq: &mut impl QQAlarmQueue) -> bool {
+        match &mut self.state {
+            PiezoBuzzerState::Playing { tones, index, repeat, delay: Delay::Done } => {
+                let (tones, repeat) = (*tones, *repeat);
+                let next = if repeat { (*index + 1) % tones.len() } else { *index + 1 };
+
+                self.start_tone(qq, tones, next, repeat);
+                true
+            },
+            PiezoBuzzerState::Playing { delay, .. } => delay.retry(qq),
+            PiezoBuzzerState::Idle => false,
+        }
+    }
+
+    fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        match &mut self.state {
+            PiezoBuzzerState::Playing { delay, .. } => delay.on_alarm(qq_alarm_id),
+            PiezoBuzzerState::Idle => false,
+        }
+    }
+}
@@ -0,0 +1,380 @@
+use core::fmt::Write;
+
+use esp_hal::{peripheral::{Peripheral, PeripheralRef}, peripherals::USB_DEVICE};
+
+use heapless::{String, Vec};
+
+use crate::{config_store::{IrKeyBinding, IR_COMMAND_LEN}, event_bus::Event, interrupts::{self, USBInterruptStatus}, ir::IrProtocol, ir_learning::{self, Name}, log::{log_line, Level, Module}, qq_alarm_queue::TaggedQQAlarmQueue, reboot::RebootMode, scheduler::{Context, Machine}, record::RecordFormat, sony_ir::SonyIRCommand, usb_writer::{OverflowPolicy, UsbOutputMode, UsbWriter}};
+
+use super::ventilation::VentilationMode;
+
+
+
+/// Command entered by user into the usb serial console (one command per line).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConsoleCommand {
+    /// print last error of each subsystem and clear them
+    Errors,
+    /// `bench <bytes>` - write `bytes` of pseudo-random data through usb writer and report throughput
+    Bench { bytes: u32 },
+    /// `bench stop`
+    BenchStop,
+    /// `dump <n>` - write last `n` measurments as binary snapshot (see `snapshot`)
+    Dump { count: u32 },
+    /// `dump stop`
+    DumpStop,
+    /// `mode text` or `mode framed`
+    OutputMode(UsbOutputMode),
+    /// `overflow oldest` or `overflow newest` - which data are dropped when the output buffer is full (see `OverflowPolicy`)
+    OverflowPolicy(OverflowPolicy),
+    /// `format text`, `format csv` or `format json` - format of measurment records in text mode
+    RecordFormat(RecordFormat),
+    /// `interval <seconds>` - change scd30 measurment interval
+    Interval { seconds: u32 },
+    /// `meta` - describe all metrics sent to the host (see `metrics`)
+    Metadata,
+    /// `sony <address> <command> [repeats]` - send sony ir command (12 bit when address fits into 5 bits, otherwise 15 bit), 3 frames by default
+    SonySend { command: SonyIRCommand, repeats: u8 },
+    /// `nec <address> <message> [repeats]` - send nec message followed by repeat frames, no repeat frames by default
+    NecSend { address: u8, message: u8, repeats: u8 },
+    /// `ir learn <slot> <name>` - capture next frame of any remote into flash slot (see `ir_learning`)
+    IrLearn { slot: u8, name: Name },
+    /// `ir learn stop`
+    IrLearnStop,
+    /// `ir play <name> [repeats]` - send learned code, once by default
+    IrPlay { name: Name, repeats: u8 },
+    /// `ir codes` - list learned codes
+    IrCodes,
+    /// `ir bind <protocol> <address> <command> [repeat] <console command>` - bind key of a remote to console command
+    /// (protocol `nec`, `sirc`, `rc5`, `rc6` or `raw`), `repeat` gives the command again while the key is held, bindings
+    /// are part of the config (see `config save`)
+    IrBind(IrKeyBinding),
+    /// `ir unbind <protocol> <address> <command>`
+    IrUnbind { protocol: IrProtocol, address: u8, ir_command: u16 },
+    /// `ir bindings` - list bound keys
+    IrBindings,
+    /// `frc <ppm>` - recalibrate scd30 to reference co2 concentration
+    ForcedRecalibration { ppm: u16 },
+    /// `scd30 stop` - stop scd30 continuous measurment
+    SdcStop,
+    /// `scd30 start` - start scd30 measurment again (after stop or error)
+    SdcStart,
+    /// `scd30 toggle` - stop running scd30 measurment or start stopped one
+    SdcToggle,
+    /// `flush` - send incomplete line of text held back by usb writer (see `UsbWriter::flush`)
+    Flush,
+    /// `time` - print wall clock time
+    Time,
+    /// `time <unix ms>` - synchronize wall clock (see `time`)
+    SetTime { unix_ms: u64 },
+    /// `config` - print configuration kept in flash (current values, not the stored copy)
+    ConfigShow,
+    /// `config save` - store current configuration to flash, it is loaded at boot
+    ConfigSave,
+    /// `config host <a.b.c.d>` - change receiver of measurment udp packets (see `WifiReporter`)
+    ReportHost { host: [u8; 4] },
+    /// `log` - dump measurment log stored in flash
+    LogDump,
+    /// `log stop`
+    LogDumpStop,
+    /// `log level` - print runtime log level of each module
+    LogLevels,
+    /// `log level <module | all> <error | warn | info | debug | off>` - change runtime log level (`None` module means all)
+    LogLevel { module: Option<Module>, level: Option<Level> },
+    /// `alert silence` - turn co2 alert buzzer off until alert level changes
+    AlertSilence,
+    /// `alert <warning> <critical> [hysteresis]` - change co2 alert thresholds (in ppm, parsed into 10^-3 ppm),
+    /// hysteresis is kept when not given
+    AlertThresholds { warning: i32, critical: i32, hysteresis: Option<i32> },
+    /// `vent` - print ventilation mode and speed
+    VentilationStatus,
+    /// `vent on`, `vent off` or `vent auto` - manual override of ventilation (`auto` is driven by co2)
+    Ventilation(VentilationMode),
+    /// `vent next` - switch to the next manual override (auto -> on -> off -> auto), single button control
+    VentilationNext,
+    /// `vent threshold <on> <off>` - change co2 thresholds of automatic ventilation (in ppm, parsed into 10^-3 ppm)
+    VentilationThresholds { on: i32, off: i32 },
+    /// `i2c trace on` or `i2c trace off` - mirror i2c0 transactions to usb output (see `i2c_trace`)
+    I2CTrace(bool),
+    /// `trace [entries]` - print last entries of flight recorder (see `flight_recorder`), 16 by default
+    Trace { entries: u8 },
+    /// `reboot` or `reboot download` - reset the chip, `download` enters rom download mode (see `reboot`)
+    Reboot(RebootMode),
+    /// `stats` - print execution time of each machine and of main loop iterations since the last `stats` (see `Scheduler`)
+    Stats,
+    /// `summary` - print minimum, average and maximum of co2 and temperature in the last hour and the last day
+    Summary,
+}
+
+/// Whole ppm into 10^-3 ppm (thresholds of configuration).
+fn parse_ppm(word: &str) -> Option<i32> {
+    word.parse::<u16>().ok().map(|ppm| ppm as i32 * 1_000)
+}
+
+/// Protocol of ir key binding as written in console commands.
+pub const IR_PROTOCOL_WORDS: [(IrProtocol, &str); 5] = [
+    (IrProtocol::Nec, "nec"),
+    (IrProtocol::Sirc, "sirc"),
+    (IrProtocol::Rc5, "rc5"),
+    (IrProtocol::Rc6, "rc6"),
+    (IrProtocol::Raw, "raw"),
+];
+
+pub fn ir_protocol_word(protocol: IrProtocol) -> &'static str {
+    IR_PROTOCOL_WORDS.iter().find(|(p, _)| *p == protocol).map_or("", |(_, word)| word)
+}
+
+fn parse_ir_protocol(word: &str) -> Option<IrProtocol> {
+    IR_PROTOCOL_WORDS.iter().find(|(_, w)| *w == word).map(|(protocol, _)| *protocol)
+}
+
+/// Dotted decimal ipv4 address (`192.168.1.4`).
+fn parse_ipv4(word: &str) -> Option<[u8; 4]> {
+    let mut octets = word.split('.');
+    let mut address = [0; 4];
+
+    for octet in &mut address {
+        *octet = octets.next()?.parse().ok()?;
+    }
+
+    octets.next().is_none().then_some(address)
+}
+
+
+impl ConsoleCommand {
+    /// Command name and arguments are separated by whitespace (also used for commands of ir key bindings).
+    pub fn parse(line: &[u8]) -> Option<ConsoleCommand> {
+        let line = core::str::from_utf8(line).ok()?;
+        let mut words = line.split_ascii_whitespace();
+
+        let command = match (words.next()?, words.next()) {
+            ("errors", None) => ConsoleCommand::Errors,
+            ("meta", None) => ConsoleCommand::Metadata,
+            ("stats", None) => ConsoleCommand::Stats,
+            ("summary", None) => ConsoleCommand::Summary,
+            ("reboot", None) => ConsoleCommand::Reboot(RebootMode::Normal),
+            ("reboot", Some("download")) => ConsoleCommand::Reboot(RebootMode::Download),
+            ("trace", None) => ConsoleCommand::Trace { entries: 16 },
+            ("trace", Some(entries)) => ConsoleCommand::Trace { entries: entries.parse().ok()? },
+            ("bench", Some("stop")) => ConsoleCommand::BenchStop,
+            ("bench", Some(bytes)) => ConsoleCommand::Bench { bytes: bytes.parse().ok()? },
+            ("dump", Some("stop")) => ConsoleCommand::DumpStop,
+            ("dump", Some(count)) => ConsoleCommand::Dump { count: count.parse().ok()? },
+            ("mode", Some("text")) => ConsoleCommand::OutputMode(UsbOutputMode::Text),
+            ("mode", Some("framed")) => ConsoleCommand::OutputMode(UsbOutputMode::Framed),
+            ("overflow", Some("oldest")) => ConsoleCommand::OverflowPolicy(OverflowPolicy::DropOldest),
+            ("overflow", Some("newest")) => ConsoleCommand::OverflowPolicy(OverflowPolicy::RejectNewest),
+            ("format", Some("text")) => ConsoleCommand::RecordFormat(RecordFormat::Text),
+            ("format", Some("csv")) => ConsoleCommand::RecordFormat(RecordFormat::Csv),
+            ("format", Some("json")) => ConsoleCommand::RecordFormat(RecordFormat::JsonLines),
+            ("interval", Some(seconds)) => ConsoleCommand::Interval { seconds: seconds.parse().ok()? },
+            ("scd30", Some("stop")) => ConsoleCommand::SdcStop,
+            ("scd30", Some("start")) => ConsoleCommand::SdcStart,
+            ("scd30", Some("toggle")) => ConsoleCommand::SdcToggle,
+            ("flush", None) => ConsoleCommand::Flush,
+            ("time", None) => ConsoleCommand::Time,
+            ("time", Some(unix_ms)) => ConsoleCommand::SetTime { unix_ms: unix_ms.parse().ok()? },
+            ("config", None) => ConsoleCommand::ConfigShow,
+            ("config", Some("save")) => ConsoleCommand::ConfigSave,
+            ("config", Some("host")) => ConsoleCommand::ReportHost { host: parse_ipv4(words.next()?)? },
+            ("log", None) => ConsoleCommand::LogDump,
+            ("log", Some("stop")) => ConsoleCommand::LogDumpStop,
+            ("log", Some("level")) => match (words.next(), words.next()) {
+                (None, _) => ConsoleCommand::LogLevels,
+                (Some(module), Some(level)) => ConsoleCommand::LogLevel {
+                    module: if module == "all" { None } else { Some(Module::from_name(module)?) },
+                    level: if level == "off" { None } else { Some(Level::from_name(level)?) },
+                },
+                (Some(_), None) => return None,
+            },
+            ("alert", Some("silence")) => ConsoleCommand::AlertSilence,
+            ("alert", Some(warning)) => {
+                let warning = parse_ppm(warning)?;
+                let critical = parse_ppm(words.next()?)?;
+                let hysteresis = match words.next() {
+                    Some(hysteresis) => Some(parse_ppm(hysteresis)?),
+                    None => None,
+                };
+
+                ConsoleCommand::AlertThresholds { warning, critical, hysteresis }
+            },
+            ("i2c", Some("trace")) => match words.next()? {
+                "on" => ConsoleCommand::I2CTrace(true),
+                "off" => ConsoleCommand::I2CTrace(false),
+                _ => return None,
+            },
+            ("vent", None) => ConsoleCommand::VentilationStatus,
+            ("vent", Some("on")) => ConsoleCommand::Ventilation(VentilationMode::On),
+            ("vent", Some("off")) => ConsoleCommand::Ventilation(VentilationMode::Off),
+            ("vent", Some("auto")) => ConsoleCommand::Ventilation(VentilationMode::Auto),
+            ("vent", Some("next")) => ConsoleCommand::VentilationNext,
+            ("vent", Some("threshold")) => ConsoleCommand::VentilationThresholds { on: parse_ppm(words.next()?)?, off: parse_ppm(words.next()?)? },
+            ("ir", Some("codes")) => ConsoleCommand::IrCodes,
+            ("ir", Some("bindings")) => ConsoleCommand::IrBindings,
+            ("ir", Some("unbind")) => ConsoleCommand::IrUnbind {
+                protocol: parse_ir_protocol(words.next()?)?,
+                address: words.next()?.parse().ok()?,
+                ir_command: words.next()?.parse().ok()?,
+            },
+            ("ir", Some("bind")) => {
+                let protocol = parse_ir_protocol(words.next()?)?;
+                let address = words.next()?.parse().ok()?;
+                let ir_command = words.next()?.parse().ok()?;
+
+                let mut words = words.by_ref().peekable();
+                let repeat = words.next_if_eq(&"repeat").is_some();
+
+                // rest of the line is the bound command, it has to be valid now
+                let mut command = String::<IR_COMMAND_LEN>::new();
+                for word in words {
+                    if !command.is_empty() {
+                        command.push(' ').ok()?;
+                    }
+                    command.push_str(word).ok()?;
+                }
+                ConsoleCommand::parse(command.as_bytes())?;
+
+                ConsoleCommand::IrBind(IrKeyBinding::new(protocol, address, ir_command, &command, repeat)?)
+            },
+            ("ir", Some("learn")) => match (words.next()?, words.next()) {
+                ("stop", None) => ConsoleCommand::IrLearnStop,
+                (slot, Some(name)) => ConsoleCommand::IrLearn { slot: slot.parse().ok()?, name: ir_learning::name_from_str(name)? },
+                _ => return None,
+            },
+            ("ir", Some("play")) => {
+                let name = ir_learning::name_from_str(words.next()?)?;
+                let repeats = words.next().map_or(Some(1), |repeats| repeats.parse().ok())?;
+
+                ConsoleCommand::IrPlay { name, repeats }
+            },
+            ("frc", Some(ppm)) => ConsoleCommand::ForcedRecalibration { ppm: ppm.parse().ok()? },
+            ("nec", Some(address)) => {
+                let address = address.parse().ok()?;
+                let message = words.next()?.parse().ok()?;
+                let repeats = words.next().map_or(Some(0), |repeats| repeats.parse().ok())?;
+
+                ConsoleCommand::NecSend { address, message, repeats }
+            },
+            ("sony", Some(address)) => {
+                let address: u8 = address.parse().ok()?;
+                let command: u8 = words.next()?.parse().ok()?;
+                let repeats = words.next().map_or(Some(3), |repeats| repeats.parse().ok())?;
+
+                let command = if address < 32 {
+                    SonyIRCommand::V12 { address, command }
+                } else {
+                    SonyIRCommand::V15 { address, command }
+                };
+
+                ConsoleCommand::SonySend { command, repeats }
+            },
+            _ => return None,
+        };
+
+        words.next().is_none().then_some(command)
+    }
+}
+
+
+
+/// Reads lines from usb serial (OUT endpoint) and parses them into `ConsoleCommand`s.
+///
+/// Writing (IN endpoint) is done by usb writer, console uses only OUT endpoint registers, so both can hold `USB_DEVICE`.
+/// Parsed command is kept until `take_command` is called, it is not executed by console itself (scheduled console
+/// publishes it as `Event::Command`).
+pub struct Console<'a, const LINE_SIZE: usize> {
+    usb: PeripheralRef<'a, USB_DEVICE>,
+    line: Vec<u8, LINE_SIZE>,
+    line_overflow: bool,
+    fifo_pending: bool, // bytes were left in the fifo by previous update
+    command: Option<ConsoleCommand>,
+}
+
+impl<'a, const LINE_SIZE: usize> Console<'a, LINE_SIZE> {
+    pub fn new(usb: impl Peripheral<P = USB_DEVICE> + 'a) -> Self {
+        Self {
+            usb: usb.into_ref(),
+            line: Vec::new(),
+            line_overflow: false,
+            fifo_pending: false,
+            command: None,
+        }
+    }
+
+    /// This does not enable USB interrupt itself, it is enabled by usb writer.
+    pub fn enable_interrupt(&mut self) {
+        self.usb.int_ena().modify(|_, w| w.serial_out_recv_pkt().set_bit());
+    }
+
+    pub fn take_command(&mut self) -> Option<ConsoleCommand> {
+        self.command.take()
+    }
+
+    fn on_line(&mut self, usb_writer: &mut impl Write) {
+        let line = self.line.as_slice().trim_ascii();
+
+        if self.line_overflow {
+            log_line!(usb_writer, "console", "line too long");
+        } else if !line.is_empty() {
+            match ConsoleCommand::parse(line) {
+                Some(command) => self.command = Some(command),
+                None => {
+                    log_line!(usb_writer, "console", "unknown command");
+                },
+            }
+        }
+
+        self.line.clear();
+        self.line_overflow = false;
+    }
+
+    pub fn update(&mut self, usb_writer: &mut impl Write) -> bool {
+        // previous command has to be taken first, remaining bytes stay in the usb fifo
+        if self.command.is_some() {
+            return false;
+        }
+
+        let pending_interrupts = interrupts::usb_interrupt_get_and_clear(USBInterruptStatus::SERIAL_OUT_RECV_PKT);
+
+        if pending_interrupts.is_empty() && !self.fifo_pending {
+            return false;
+        }
+
+        while self.command.is_none() && self.usb.ep1_conf().read().serial_out_ep_data_avail().bit_is_set() {
+            let byte = self.usb.ep1().read().rdwr_byte().bits();
+
+            match byte {
+                b'\r' | b'\n' => self.on_line(usb_writer),
+                _ => {
+                    if self.line.push(byte).is_err() {
+                        self.line_overflow = true;
+                    }
+                },
+            }
+        }
+
+        self.fifo_pending = self.usb.ep1_conf().read().serial_out_ep_data_avail().bit_is_set();
+
+        true
+    }
+}
+
+impl<'c, 'i, 'a, const LINE_SIZE: usize, W, Q, const N: usize> Machine<Context<'c, 'i, W, Q, N>> for Console<'a, LINE_SIZE>
+where
+    W: Write + UsbWriter,
+    Q: TaggedQQAlarmQueue,
+{
+    fn name(&self) -> &'static str {
+        "console"
+    }
+
+    fn update(&mut self, context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        let active = Self::update(self, context.usb_writer);
+
+        if let Some(command) = self.take_command() {
+            context.events.publish(Event::Command(command));
+        }
+
+        active
+    }
+}
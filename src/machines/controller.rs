@@ -1,97 +1,412 @@
-use core::{cmp::Ordering, fmt::Write};
+use core::{fmt::Write, num::NonZeroU16};
 
 use esp_hal::timer::systimer::SystemTimer;
 
-use crate::{ring_buffer::{Overwrite, RingBuffer}, sdc::RawMeasurment};
+use fugit::SecsDurationU32;
 
+use crate::{
+    event_bus::{Event, EventBus, Subscriber},
+    fixed_point::Milli,
+    humidity,
+    invariants::invariant,
+    log::{self, log_fmt, log_line},
+    measurment_interval::IntervalObserver,
+    sdc::{Measurment, SensorId},
+    sensor_history::SensorHistories,
+    usb_writer::{UsbOutputMode, UsbWriter},
+    record::{self, CsvHeader, Record, RecordFormat},
+    sinks::{Sink, UsbSink},
+    summary::RollingSummary,
+    time,
+};
 
+pub use crate::sensor_history::{Co2Trend, TrendConfig};
 
-#[derive(Debug, Clone, Copy)]
-enum ParseFloatE3Error {
-    Negative,
-    TooSmall,
-    TooBig,
+use super::ambient_sensor::AmbientReading;
+
+
+
+/// Number of sensors tracked by the controller (primary and `SecondarySDC`).
+pub const MAX_SENSORS: usize = 2;
+
+
+/// Rolling statistics of co2 and temperature of primary sensor, in system timer ms (see `Controller::write_summary`).
+struct Summaries {
+    /// 5 minute buckets
+    co2_hour: RollingSummary<12>,
+    co2_day: RollingSummary<24>,
+    /// 5 minute buckets
+    temperature_hour: RollingSummary<12>,
+    temperature_day: RollingSummary<24>,
 }
 
-fn parse_float_e3(f: u32) -> Result<u32, ParseFloatE3Error> {
-    let sign = (f >> 31) as u8;
-    let exp = (f >> 23) as u8;
-    let frac = f & 0x3FFFFF;
+impl Summaries {
+    const HOUR_MS: u64 = 3_600_000;
+    const DAY_MS: u64 = 24 * Self::HOUR_MS;
 
-    if sign == 1 {
-        return Err(ParseFloatE3Error::Negative);
+    const fn new() -> Self {
+        Self {
+            co2_hour: RollingSummary::new(Self::HOUR_MS),
+            co2_day: RollingSummary::new(Self::DAY_MS),
+            temperature_hour: RollingSummary::new(Self::HOUR_MS),
+            temperature_day: RollingSummary::new(Self::DAY_MS),
+        }
     }
 
-    let exp = exp.checked_sub(124).ok_or(ParseFloatE3Error::TooSmall)?;
-    if exp > 31 {
-        return Err(ParseFloatE3Error::TooBig);
+    fn push(&mut self, at_ms: u64, measurment: &Measurment) {
+        self.co2_hour.push(at_ms, measurment.co2);
+        self.co2_day.push(at_ms, measurment.co2);
+        self.temperature_hour.push(at_ms, measurment.temperature);
+        self.temperature_day.push(at_ms, measurment.temperature);
     }
+}
 
-    let mut dec = match exp.cmp(&23) {
-        Ordering::Less => frac >> (23 - exp),
-        Ordering::Greater => frac << (exp - 23),
-        Ordering::Equal => frac,
-    };
-    dec |= 1u32 << exp;
 
-    dec.checked_mul(125).ok_or(ParseFloatE3Error::TooBig)
+#[derive(Debug, Clone, Copy)]
+pub struct FilterConfig {
+    /// number of measurments in moving average, `None` is derived from the measurment interval (5 minutes)
+    pub window: Option<usize>,
+    /// median of 5 is applied before moving average
+    pub despike: bool,
 }
 
 
-
-struct TimedMeasurment {
-    measurment: RawMeasurment,
-    at: u64,
+/// Values derived from temperature and relative humidity of one measurment (see `humidity`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HumidityMetrics {
+    /// in m°C, `None` for zero humidity
+    pub dew_point: Option<i32>,
+    /// in mg/m³
+    pub absolute_humidity: i32,
 }
 
+impl HumidityMetrics {
+    pub fn of(measurment: &Measurment) -> Self {
+        Self {
+            dew_point: humidity::dew_point(measurment.temperature, measurment.humidity),
+            absolute_humidity: humidity::absolute_humidity(measurment.temperature, measurment.humidity),
+        }
+    }
+}
 
+/// Measurments are filtered by optional median of 5 (removes spikes) followed by moving average, raw values are passed
+/// to `sinks` and both raw and filtered values are available to other machines (`last_record`, `last_filtered`).
+///
+/// History is kept for each sensor (up to `MAX_SENSORS`, see `SensorId`), records written to usb are tagged with sensor id.
+/// Primary sensor drives other machines (accessors without sensor id) and sinks, other sensors are only written to usb
+/// and available through `*_of` accessors.
+///
+/// Window of moving average and publishing cadence of `sinks` are derived from the measurment interval (see `IntervalObserver`),
+/// so they cover the same time when the interval changes.
 pub struct Controller<const N: usize> {
-    measurments: RingBuffer<TimedMeasurment, N, Overwrite>,
-    pending_measurment: Option<RawMeasurment>,
+    histories: SensorHistories<N, MAX_SENSORS>,
+    /// indexed by sensor id
+    last_records: [Option<Record>; MAX_SENSORS],
+    filter_config: FilterConfig,
+    /// number of measurments in moving average
+    filter_window: usize,
+    trend_config: TrendConfig,
+    /// every n-th measurment is passed to `sinks`
+    publish_every: u32,
+    publish_counter: u32,
+    /// measurment and time it was ready
+    pending_measurment: Option<(SensorId, Measurment, Option<u64>)>,
+    ambient: Option<AmbientReading>,
+    pending_ambient: bool,
+    /// in Pa, from pressure sensor (`Bme280`, feature `bme280`)
+    pressure: Option<u32>,
+    pending_pressure: bool,
+    /// scd30 errors since boot, indexed by sensor id
+    sensor_errors: [u32; MAX_SENSORS],
+    /// measurments outside of scd30 ranges since boot, rejected by sensor machine (see `Measurment::check_range`)
+    rejected_measurments: u32,
+    record_format: RecordFormat,
+    /// dew point and absolute humidity are written after each measurment (text format only)
+    humidity_output: bool,
+    /// when csv header is written before a record
+    csv_header: CsvHeader,
+    summaries: Summaries,
 }
 
 impl<const N: usize> Controller<N> {
-    pub fn new() -> Self {
+    /// pressure compensation range accepted by scd30 (in mbar)
+    const PRESSURE_COMPENSATION_MIN: u32 = 700;
+    const PRESSURE_COMPENSATION_MAX: u32 = 1400;
+
+    /// time covered by moving average when window is not configured (in seconds)
+    const FILTER_DURATION_SECS: u32 = 300;
+    /// minimal time between records passed to `sinks` (in seconds)
+    const PUBLISH_PERIOD_SECS: u32 = 60;
+
+
+
+    pub fn new(filter_config: FilterConfig, trend_config: TrendConfig) -> Self {
         Self {
-            measurments: RingBuffer::new(),
+            histories: SensorHistories::new(SystemTimer::TICKS_PER_SECOND),
+            last_records: [None; MAX_SENSORS],
+            filter_config,
+            filter_window: filter_config.window.unwrap_or(1).clamp(1, N),
+            trend_config,
+            publish_every: 1,
+            publish_counter: 0,
             pending_measurment: None,
+            ambient: None,
+            pending_ambient: false,
+            pressure: None,
+            pending_pressure: false,
+            sensor_errors: [0; MAX_SENSORS],
+            rejected_measurments: 0,
+            record_format: RecordFormat::Text,
+            humidity_output: false,
+            csv_header: CsvHeader::new(),
+            summaries: Summaries::new(),
         }
     }
 
-    pub fn update(&mut self, usb_writer: &mut impl Write) -> bool {
-        if let Some(measurment) = self.pending_measurment.take() {
-            let co2 = parse_float_e3(u32::from_be_bytes(measurment.co2));
-            let temperature = parse_float_e3(u32::from_be_bytes(measurment.temperature));
-            let humidity = parse_float_e3(u32::from_be_bytes(measurment.humidity));
+    /// Measurments, readings and errors are taken from `events`, measurments are passed to `usb_writer` (see `UsbSink`)
+    /// and every `publish_every`-th to each of `sinks` (in sink's own encoding).
+    ///
+    /// Human readable lines (ambient readings, filtered values) are written only with `RecordFormat::Text`, so csv and json
+    /// records are not mixed with other output of the controller. Lines of other machines are written as comments then
+    /// (see `set_record_format`).
+    pub fn update(&mut self, usb_writer: &mut (impl Write + UsbWriter), events: &mut EventBus, sinks: &mut [&mut dyn Sink]) -> bool {
+        let mut did_something = false;
+
+        // at most one measurment is processed by each update
+        while self.pending_measurment.is_none() && let Some(event) = events.poll(Subscriber::Controller) {
+            self.on_event(event);
+            did_something = true;
+        }
+
+        let text = self.record_format == RecordFormat::Text;
 
-            if let Err(e) = co2 {
-                let _ = writeln!(usb_writer, "cannot parse co2 : {:?}", e);
+        if self.pending_ambient && let Some(ambient) = self.ambient {
+            self.pending_ambient = false;
+
+            if text {
+                log_line!(usb_writer, "ambient temperature", "{:.2} °C", Milli::from(ambient.temperature));
+                log_line!(usb_writer, "ambient humidity", "{:.1} %", Milli::from(ambient.humidity));
             }
-            if let Err(e) = temperature {
-                let _ = writeln!(usb_writer, "cannot parse temperature : {:?}", e);
+
+            did_something = true;
+        }
+
+        if self.pending_pressure && let Some(pressure) = self.pressure {
+            self.pending_pressure = false;
+
+            if text {
+                // value in Pa formatted as fixed point (value / 1000) is in kPa
+                log_line!(usb_writer, "ambient pressure", "{:.2} kPa", Milli::from(pressure));
             }
-            if let Err(e) = humidity {
-                let _ = writeln!(usb_writer, "cannot parse humidity : {:?}", e);
+
+            did_something = true;
+        }
+
+        if let Some((sensor, measurment, at)) = self.pending_measurment.take() {
+            // measurment without captured ready edge is recorded when it was read
+            let at = at.unwrap_or_else(SystemTimer::now);
+            let record = Record { sensor, at, unix_ms: time::unix_ms_at(at), co2: measurment.co2, temperature: measurment.temperature, humidity: measurment.humidity };
+
+            // framed output has binary records without header
+            if self.record_format == RecordFormat::Csv && usb_writer.output_mode() == UsbOutputMode::Text && self.csv_header.before_record() {
+                log_fmt!(usb_writer, "{}\n", record::CSV_HEADER);
             }
 
-            if let Ok(co2) = co2 && let Ok(temperature) = temperature && let Ok(humidity) = humidity {
-                let _ = writeln!(usb_writer, "co2 : {}.{} ppm", co2 / 1000, co2 % 1000);
-                let _ = writeln!(usb_writer, "temperature : {}.{} °C", temperature / 1000, temperature % 1000);
-                let _ = writeln!(usb_writer, "humidity : {}.{} %", humidity / 1000, humidity % 1000);
+            // sinks are independent, dropped record in one sink does not affect others
+            UsbSink::new(usb_writer, self.record_format).push(&record);
+
+            if sensor == SensorId::PRIMARY {
+                self.summaries.push(at / (SystemTimer::TICKS_PER_SECOND / 1_000), &measurment);
+
+                if self.publish_counter == 0 {
+                    sinks.iter_mut().for_each(|sink| { sink.push(&record); });
+                }
+                self.publish_counter = (self.publish_counter + 1) % self.publish_every;
             }
 
-            let now = SystemTimer::now();
-            self.measurments.push_back(TimedMeasurment { measurment, at: now });
+            // sensor id was checked when the event was received (see `on_event`)
+            self.histories.push(sensor, measurment, at, self.filter_config.despike, self.filter_window, &self.trend_config);
+            self.last_records[sensor.0 as usize] = Some(record);
+
+            if text && (self.filter_window > 1 || self.filter_config.despike) && let Some(filtered) = self.last_filtered_of(sensor) {
+                log_line!(usb_writer, "filtered co2", "{:.1} ppm ({} measurments)", Milli::from(filtered.co2), self.filter_window);
+                log_line!(usb_writer, "filtered temperature", "{:.2} °C", Milli::from(filtered.temperature));
+                log_line!(usb_writer, "filtered humidity", "{:.1} %", Milli::from(filtered.humidity));
+            }
+
+            if text && self.humidity_output {
+                let metrics = HumidityMetrics::of(&measurment);
+                if let Some(dew_point) = metrics.dew_point {
+                    log_line!(usb_writer, "dew point", "{:.1} °C", Milli::from(dew_point));
+                }
+                log_line!(usb_writer, "absolute humidity", "{:.2} g/m³", Milli::from(metrics.absolute_humidity));
+            }
 
             // TODO: process measurment
 
-            true
+            did_something = true;
+        }
+
+        did_something
+    }
+
+    /// Co2 trend after the last measurment of primary sensor, `None` until there are
+    /// `trend::MIN_POINTS` measurments in the trend window.
+    pub fn co2_trend(&self) -> Option<Co2Trend> {
+        self.co2_trend_of(SensorId::PRIMARY)
+    }
+
+    /// Same as `co2_trend` for any sensor, `None` for unknown sensor.
+    pub fn co2_trend_of(&self, sensor: SensorId) -> Option<Co2Trend> {
+        self.histories.get(sensor)?.trend()
+    }
+
+    /// System timer ticks of the last measurment of primary sensor (when sensor signaled it is ready, see `on_measurment`).
+    pub fn last_measurment_at(&self) -> Option<u64> {
+        self.histories.primary().last().map(|(at, _)| at)
+    }
+
+    /// Stored (raw) measurments of primary sensor with system timer ticks of each, oldest first.
+    pub fn measurments(&self) -> impl DoubleEndedIterator<Item = (u64, Measurment)> + ExactSizeIterator + '_ {
+        self.histories.primary().measurments()
+    }
+
+    /// Co2 (in 10^-3 ppm) of the last measurment of primary sensor.
+    pub fn last_co2(&self) -> Option<i32> {
+        self.histories.primary().last().map(|(_, measurment)| measurment.co2)
+    }
+
+    /// Record of the last measurment of primary sensor (same as passed to sinks), for outputs which send only the latest value.
+    pub fn last_record(&self) -> Option<Record> {
+        self.last_record_of(SensorId::PRIMARY)
+    }
+
+    /// Same as `last_record` for any sensor, `None` for unknown sensor.
+    pub fn last_record_of(&self, sensor: SensorId) -> Option<Record> {
+        *self.last_records.get(sensor.0 as usize)?
+    }
+
+    /// Last measurment of primary sensor after filtering (see `FilterConfig`), raw values are in `last_record`.
+    pub fn last_filtered(&self) -> Option<Measurment> {
+        self.last_filtered_of(SensorId::PRIMARY)
+    }
+
+    /// Same as `last_filtered` for any sensor, `None` for unknown sensor.
+    pub fn last_filtered_of(&self, sensor: SensorId) -> Option<Measurment> {
+        self.histories.get(sensor)?.filtered()
+    }
+
+    /// Minimum, average and maximum of co2 and temperature of primary sensor in the last hour and the last day.
+    pub fn write_summary(&self, w: &mut impl Write) {
+        let ticks_per_ms = SystemTimer::TICKS_PER_SECOND / 1_000;
+        let now_ms = SystemTimer::now() / ticks_per_ms;
+
+        let windows = [
+            ("1 h", self.summaries.co2_hour.summary(now_ms), self.summaries.temperature_hour.summary(now_ms)),
+            ("24 h", self.summaries.co2_day.summary(now_ms), self.summaries.temperature_day.summary(now_ms)),
+        ];
+
+        for (name, co2, temperature) in windows {
+            let (Some(co2), Some(temperature)) = (co2, temperature) else {
+                log_line!(w, "summary", "{} no measurments", name);
+                continue;
+            };
+
+            // start of the oldest bucket, window moves by whole buckets
+            match time::unix_ms_at(co2.since_ms * ticks_per_ms) {
+                Some(unix_ms) => log_line!(w, "summary", "{} {} measurments since {:.3} s (unix)", name, co2.count, Milli(unix_ms as i64)),
+                None => log_line!(w, "summary", "{} {} measurments since {} s ago", name, co2.count, (now_ms - co2.since_ms) / 1_000),
+            }
+            log_line!(w, "summary", "{} co2 min {:.1} / avg {:.1} / max {:.1} ppm", name, Milli::from(co2.min), Milli::from(co2.mean), Milli::from(co2.max));
+            log_line!(w, "summary", "{} temperature min {:.2} / avg {:.2} / max {:.2} °C", name, Milli::from(temperature.min), Milli::from(temperature.mean), Milli::from(temperature.max));
+        }
+    }
+
+    fn on_event(&mut self, event: Event) {
+        match event {
+            Event::Measurment { sensor, measurment, at } => {
+                if invariant!((sensor.0 as usize) < MAX_SENSORS, "measurment of unknown sensor") {
+                    self.pending_measurment = Some((sensor, measurment, at));
+                }
+            },
+            Event::SensorStarted(sensor) => {
+                invariant!(self.histories.restart(sensor), "start of unknown sensor");
+            },
+            Event::SensorError(sensor) => {
+                if invariant!((sensor.0 as usize) < MAX_SENSORS, "error of unknown sensor") {
+                    self.sensor_errors[sensor.0 as usize] = self.sensor_errors[sensor.0 as usize].saturating_add(1);
+                }
+            },
+            Event::MeasurmentRejected { .. } => self.rejected_measurments = self.rejected_measurments.saturating_add(1),
+            Event::Ambient(reading) => {
+                self.ambient = Some(reading);
+                self.pending_ambient = true;
+            },
+            Event::Pressure(pressure) => {
+                self.pressure = Some(pressure);
+                self.pending_pressure = true;
+            },
+            Event::Command(_) | Event::AlertLevel(_) => {},
+        }
+    }
+
+    /// Format of records written to usb in text mode, csv header is written before the first csv record and repeated
+    /// (see `CsvHeader`). With csv and json lines all log lines are comments (see `log::set_comment_lines`), so hosts
+    /// can skip them.
+    pub fn set_record_format(&mut self, record_format: RecordFormat) {
+        if record_format == RecordFormat::Csv && self.record_format != RecordFormat::Csv {
+            self.csv_header.reset();
+        }
+        log::set_comment_lines(record_format != RecordFormat::Text);
+        self.record_format = record_format;
+    }
+
+    /// Dew point and absolute humidity are written to usb after each measurment (in text format).
+    pub fn set_humidity_output(&mut self, enabled: bool) {
+        self.humidity_output = enabled;
+    }
+
+    /// Dew point and absolute humidity of the last measurment of primary sensor.
+    pub fn humidity_metrics(&self) -> Option<HumidityMetrics> {
+        self.histories.primary().last().map(|(_, measurment)| HumidityMetrics::of(&measurment))
+    }
+
+    /// Number of scd30 errors of all sensors since boot, including errors recovered by retry.
+    pub fn sensor_error_count(&self) -> u32 {
+        self.sensor_errors.iter().fold(0, |sum, errors| sum.saturating_add(*errors))
+    }
+
+    /// Same as `sensor_error_count` for one sensor, `None` for unknown sensor.
+    pub fn sensor_error_count_of(&self, sensor: SensorId) -> Option<u32> {
+        self.sensor_errors.get(sensor.0 as usize).copied()
+    }
+
+    /// Number of measurments rejected since boot (values outside of ranges specified by scd30 documentation).
+    pub fn rejected_measurment_count(&self) -> u32 {
+        self.rejected_measurments
+    }
+
+    /// Ambient pressure (in mbar) which should be used by scd30 for pressure compensation.
+    /// `None` when there is no pressure reading or it is outside of range accepted by scd30, pressure is measured only by
+    /// `Bme280` (feature `bme280`), without it scd30 is not compensated.
+    pub fn pressure_compensation(&self) -> Option<NonZeroU16> {
+        let pressure = self.pressure? / 100; // Pa -> mbar
+
+        if (Self::PRESSURE_COMPENSATION_MIN..=Self::PRESSURE_COMPENSATION_MAX).contains(&pressure) {
+            NonZeroU16::new(pressure as u16)
         } else {
-            false
+            None
         }
     }
+}
+
+
+impl<const N: usize> IntervalObserver for Controller<N> {
+    fn on_interval_changed(&mut self, interval: SecsDurationU32) {
+        let interval = interval.to_secs().max(1);
 
-    pub fn on_measurment(&mut self, measurment: RawMeasurment) {
-        self.pending_measurment = Some(measurment);
+        self.filter_window = self.filter_config.window.unwrap_or((Self::FILTER_DURATION_SECS / interval) as usize).clamp(1, N);
+        self.publish_every = Self::PUBLISH_PERIOD_SECS.div_ceil(interval).max(1);
+        self.publish_counter = 0;
     }
 }
\ No newline at end of file
@@ -1,23 +1,36 @@
-use core::fmt::Write;
+use core::fmt::{self, Display, Write};
 
 use esp_hal::timer::systimer::SystemTimer;
 
-use crate::qq_alarm_queue::QQAlarmQueue;
-use super::Delay;
+use crate::{error_registry, event_bus::EventBus, fixed_point::Milli, framing::FrameType, interrupts::{self, InterruptSource, InterruptStats}, invariants, log::log_line, sdc::SensorId, qq_alarm_queue::{QQAlarmQueue, QQOwner, TaggedQQAlarmQueue}, scheduler::{self, Context, Machine, StatsSummary}, usb_writer::{UsbOutputMode, UsbWriter}};
+use super::{controller::{self, Controller}, Ticker};
 
 
 
+/// Interrupt count of each source, `<source> <count>` separated by commas.
+struct InterruptCounts<'a>(&'a [InterruptStats; InterruptSource::COUNT]);
+
+impl Display for InterruptCounts<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, (source, stats)) in InterruptSource::ALL.iter().zip(self.0).enumerate() {
+            write!(f, "{}{} {}", if index == 0 { "" } else { ", " }, source.name(), stats.count)?;
+        }
+
+        Ok(())
+    }
+}
+
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 enum DebugPrintState {
     None,
-    Waiting(Delay),
+    Running(Ticker),
 }
 
 pub struct DebugPrint {
     state: DebugPrintState,
     delta: u64,
     tick_counter: usize,
-    wakeup_counter: usize,
 }
 
 impl DebugPrint {
@@ -26,46 +39,152 @@ impl DebugPrint {
             state: DebugPrintState::None,
             delta,
             tick_counter: 0,
-            wakeup_counter: 0,
         }
     }
 
-    /// assumes that currently we are not waiting for alarm
-    fn start_delay_unchecked(&mut self, qq: &mut impl QQAlarmQueue) {
-        let wake_at = SystemTimer::now() + self.delta;
-        let qq_alarm_id = qq.add(wake_at).unwrap();
-        self.state = DebugPrintState::Waiting(Delay::new(qq_alarm_id));
-    }
-
     pub fn start(&mut self, qq: &mut impl QQAlarmQueue) {
         if self.state == DebugPrintState::None {
-            self.start_delay_unchecked(qq);
+            self.state = DebugPrintState::Running(Ticker::start(qq, self.delta, SystemTimer::now() + self.delta));
         }
     }
 
-    pub fn wakeup(&mut self) {
-        self.wakeup_counter += 1;
+    /// Health frame payload (little endian): tick counter (u32), wakeup count (u32), usb dropped bytes (u64), invariant violations (u32), usb timeouts (u32),
+    /// qq overflows (u32), scd30 errors (u32). Layout is described to the host by `metrics::METRICS`.
+    fn write_health_frame<const N: usize>(&self, qq: &impl QQAlarmQueue, usb_writer: &mut impl UsbWriter, controller: &Controller<N>, stats: &StatsSummary) {
+        let mut payload = [0u8; 32];
+
+        payload[0..4].copy_from_slice(&(self.tick_counter as u32).to_le_bytes());
+        payload[4..8].copy_from_slice(&stats.wakeups.to_le_bytes());
+        payload[8..16].copy_from_slice(&usb_writer.dropped_bytes().to_le_bytes());
+        payload[16..20].copy_from_slice(&invariants::violation_count().to_le_bytes());
+        payload[20..24].copy_from_slice(&usb_writer.timeout_count().to_le_bytes());
+        payload[24..28].copy_from_slice(&qq.overflow_count().to_le_bytes());
+        payload[28..32].copy_from_slice(&controller.sensor_error_count().to_le_bytes());
+
+        let _ = usb_writer.write_frame(FrameType::Health, &payload);
+    }
+
+    /// Interrupt counts since the previous print (statistics are reset), details only for sources with spurious or
+    /// unclaimed interrupts.
+    fn write_interrupt_stats(&self, usb_writer: &mut impl Write) {
+        let all_stats = interrupts::stats_snapshot();
+        interrupts::stats_reset();
+
+        log_line!(usb_writer, "interrupts", "{}", InterruptCounts(&all_stats));
+
+        for (source, stats) in InterruptSource::ALL.iter().zip(&all_stats).filter(|(_, stats)| stats.spurious != 0 || stats.unclaimed != 0) {
+            let last_at_ms = stats.last_at_ms.unwrap_or(0);
+            log_line!(
+                usb_writer,
+                "interrupts",
+                "{} spurious = {}, unclaimed = {}, last at {:.3} s",
+                source.name(),
+                stats.spurious,
+                stats.unclaimed,
+                Milli(last_at_ms as i64),
+            );
+        }
     }
 
-    pub fn update(&mut self, qq: &mut impl QQAlarmQueue, usb_writer: &mut impl Write) -> bool {
-        match self.state {
-            DebugPrintState::Waiting(Delay::Done) => {
-                let _ = writeln!(usb_writer, "DEBUG PRINT {}, wakeup count = {}", self.tick_counter, self.wakeup_counter);
+    pub fn update<const N: usize>(&mut self, qq: &mut impl QQAlarmQueue, usb_writer: &mut (impl Write + UsbWriter), controller: &Controller<N>, events: &EventBus, stats: &StatsSummary) -> bool {
+        let DebugPrintState::Running(ticker) = &mut self.state else {
+            return false;
+        };
+
+        if !ticker.take_tick() {
+            return ticker.retry(qq);
+        }
+
+        if usb_writer.output_mode() == UsbOutputMode::Framed {
+            self.write_health_frame(qq, usb_writer, controller, stats);
+
+            self.tick_counter += 1;
+
+            return true;
+        }
+
+        // dropped output is reported as usb error (see `Scheduler`)
+        let dropped_bytes = usb_writer.dropped_bytes();
+        let uptime_ms = (SystemTimer::now() / (SystemTimer::TICKS_PER_SECOND / 1_000)) as i64;
+        log_line!(usb_writer, "debug print", "{}, uptime = {:.1} s, wakeup count = {}, usb dropped bytes = {}", self.tick_counter, Milli(uptime_ms), stats.wakeups, dropped_bytes);
 
-                self.tick_counter += 1;
+        self.write_interrupt_stats(usb_writer);
 
-                self.start_delay_unchecked(qq);
+        let qq_overflow_count = qq.overflow_count();
+        if qq_overflow_count != 0 || qq.is_full() {
+            log_line!(usb_writer, "debug print", "qq overflows = {}, qq alarms = {} / {}", qq_overflow_count, qq.len(), qq.capacity());
+        }
 
-                true                
+        let violation_count = invariants::violation_count();
+        if violation_count != 0 && let Some(violation) = invariants::last_violation() {
+            log_line!(usb_writer, "debug print", "invariant violations = {}, last {} ({}:{})", violation_count, violation.message, violation.file, violation.line);
+        }
+
+        if let Some(trend) = controller.co2_trend() {
+            log_line!(usb_writer, "debug print", "co2 trend = {} ({:+.1} ppm/min)", trend.trend.name(), Milli::from(trend.slope));
+        }
+
+        for sensor in (0..controller::MAX_SENSORS as u8).map(SensorId) {
+            if let Some(sensor_errors) = controller.sensor_error_count_of(sensor) && sensor_errors != 0 {
+                log_line!(usb_writer, "debug print", "scd30 {} errors = {}", sensor.0, sensor_errors);
             }
-            _ => false,
         }
+
+        let rejected_measurments = controller.rejected_measurment_count();
+        if rejected_measurments != 0 {
+            log_line!(usb_writer, "debug print", "rejected measurments = {}", rejected_measurments);
+        }
+
+        let dropped_events = events.dropped_count();
+        if dropped_events != 0 {
+            log_line!(usb_writer, "debug print", "dropped events = {}", dropped_events);
+        }
+
+        if let Some((slowest, slowest_ticks)) = stats.slowest {
+            log_line!(
+                usb_writer,
+                "debug print",
+                "loop = avg {} us, max {} us, slowest update = {} ({} us)",
+                scheduler::ticks_to_us(stats.iterations.average()),
+                scheduler::ticks_to_us(stats.iterations.max),
+                slowest,
+                scheduler::ticks_to_us(slowest_ticks),
+            );
+        }
+
+        error_registry::write_last_errors(usb_writer);
+
+        self.tick_counter += 1;
+
+        true
     }
 
     pub fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
         match &mut self.state {
-            DebugPrintState::Waiting(delay) => delay.on_alarm(qq_alarm_id),
+            DebugPrintState::Running(ticker) => ticker.on_alarm(qq_alarm_id),
             _ => false,
         }
     }
+}
+
+impl<'c, 'i, W, Q, const N: usize> Machine<Context<'c, 'i, W, Q, N>> for DebugPrint
+where
+    W: Write + UsbWriter,
+    Q: TaggedQQAlarmQueue,
+{
+    fn name(&self) -> &'static str {
+        "debug print"
+    }
+
+    fn owns(&self, owner: QQOwner) -> bool {
+        owner == QQOwner::DebugPrint
+    }
+
+    fn update(&mut self, context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        Self::update(self, &mut context.qq.owned(QQOwner::DebugPrint), context.usb_writer, context.controller, context.events, &context.stats)
+    }
+
+    fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        Self::on_alarm(self, qq_alarm_id)
+    }
 }
\ No newline at end of file
@@ -0,0 +1,372 @@
+use core::fmt::Write;
+
+use esp_hal::timer::systimer::SystemTimer;
+
+use heapless::String;
+
+use crate::{
+    error_registry::{self, Subsystem},
+    event_bus::{Event, EventBus, Subscriber},
+    fixed_point::Milli,
+    framebuffer::{Framebuffer, WIDTH},
+    i2c_bus::{I2CBus, I2CBusUser},
+    i2c_engine::{I2CEngine, I2COperation},
+    log::{debug, error, info, Module},
+    qq_alarm_queue::{QQAlarmQueue, QQOwner, TaggedQQAlarmQueue},
+    scheduler::{Context, Machine},
+    usb_writer::UsbWriter
+};
+
+use super::{alert::AlertLevel, controller::Controller, status_led::{self, LedPattern, LedPatternRequest}, State, Ticker};
+
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DisplayController {
+    Ssd1306,
+    /// 132 columns, visible 128 columns start at column 2
+    #[allow(dead_code)] // selected in `main` by board
+    Sh1106,
+}
+
+impl DisplayController {
+    fn column_offset(&self) -> u8 {
+        match self {
+            DisplayController::Ssd1306 => 0,
+            DisplayController::Sh1106 => 2,
+        }
+    }
+
+    /// Control byte followed by initialization commands (128x64, page addressing, rotated by 180°).
+    fn init_commands(&self) -> &'static [u8] {
+        match self {
+            DisplayController::Ssd1306 => &[
+                0x00,
+                0xae, // display off
+                0xd5, 0x80, // clock divider
+                0xa8, 0x3f, // multiplex ratio 64
+                0xd3, 0x00, // display offset
+                0x40, // start line 0
+                0x8d, 0x14, // internal charge pump on
+                0x20, 0x02, // page addressing mode
+                0xa1, 0xc8, // segment remap, com scan from com63
+                0xda, 0x12, // alternative com pins
+                0x81, 0x7f, // contrast
+                0xd9, 0xf1, // precharge period
+                0xdb, 0x40, // vcomh level
+                0xa4, 0xa6, // display from ram, not inverted
+                0xaf, // display on
+            ],
+            DisplayController::Sh1106 => &[
+                0x00,
+                0xae,
+                0xd5, 0x80,
+                0xa8, 0x3f,
+                0xd3, 0x00,
+                0x40,
+                0xad, 0x8b, // dc-dc converter on (sh1106 has only page addressing)
+                0xa1, 0xc8,
+                0xda, 0x12,
+                0x81, 0x7f,
+                0xd9, 0x22,
+                0xdb, 0x35,
+                0xa4, 0xa6,
+                0xaf,
+            ],
+        }
+    }
+}
+
+
+pub struct DisplayConfig {
+    pub controller: DisplayController,
+    /// `Display::DEFAULT_ADDRESS` or `0x3d`
+    pub address: u8,
+    /// delay between refreshes, in system timer ticks
+    pub period: u64,
+    pub bus_user: I2CBusUser,
+}
+
+#[derive(Debug)]
+enum DisplayState {
+    None,
+    Init(I2CEngine<1>),
+    /// waiting for the next refresh
+    Idle,
+    /// dirty pages are waiting for the bus
+    Sending,
+    /// page `page` is being written
+    Writing { page: usize, engine: I2CEngine<{ Display::PAGE_OPERATIONS }> },
+    Error,
+}
+
+/// 128x64 oled (ssd1306 or sh1106) on the shared i2c bus, shows the last co2, temperature and humidity with icons
+/// of co2 alert level (`Event::AlertLevel`) and requested status led patterns.
+///
+/// Screen is rendered into framebuffer every `period` (qq periodic alarm), only pages (8 rows) which changed are sent.
+/// Each page is written while holding the bus, bus is released between pages so sensors are not delayed by whole frame.
+/// When initialization fails machine stops, write errors are reported and remaining pages are sent after the next refresh.
+pub struct Display {
+    controller: DisplayController,
+    address: u8,
+    period: u64,
+    bus_user: I2CBusUser,
+    framebuffer: Framebuffer,
+    /// pages (bit `n` is page `n`) which differ from display memory
+    dirty: u8,
+    ticker: Option<Ticker>,
+    i2c_error: LedPatternRequest,
+    alert_level: AlertLevel,
+    state: DisplayState,
+}
+
+impl Display {
+    pub const DEFAULT_ADDRESS: u8 = 0x3c;
+
+    /// columns of page are sent in writes of this length (control byte is not included)
+    const SEGMENT_LEN: usize = 32;
+    /// page address command and data writes of one page
+    const PAGE_OPERATIONS: usize = 1 + WIDTH / Display::SEGMENT_LEN;
+
+    /// 8x8 icons, columns with least significant bit at the top
+    const ICON_WARNING: [u8; 8] = [0xc0, 0xb0, 0x8c, 0xdb, 0xdb, 0x8c, 0xb0, 0xc0];
+    const ICON_CRITICAL: [u8; 8] = [0xc0, 0xf0, 0xfc, 0xa7, 0xa7, 0xfc, 0xf0, 0xc0];
+    const ICON_I2C_ERROR: [u8; 8] = [0xff, 0xc3, 0xa5, 0x99, 0x99, 0xa5, 0xc3, 0xff];
+    const ICON_USB: [u8; 8] = [0x00, 0x3f, 0x40, 0x40, 0x40, 0x40, 0x3f, 0x00];
+
+
+    pub fn new(config: DisplayConfig) -> Self {
+        Self {
+            controller: config.controller,
+            address: config.address,
+            period: config.period,
+            bus_user: config.bus_user,
+            framebuffer: Framebuffer::new(),
+            // display memory is not cleared by reset
+            dirty: u8::MAX,
+            ticker: None,
+            i2c_error: LedPatternRequest::new(LedPattern::I2CError),
+            alert_level: AlertLevel::Normal,
+            state: DisplayState::None,
+        }
+    }
+
+    pub fn start(&mut self, qq: &mut impl QQAlarmQueue) {
+        if let DisplayState::None = self.state {
+            let mut engine = I2CEngine::new(1);
+
+            // cannot fail, init commands are shorter than `MAX_WRITE_LEN`
+            let _ = I2COperation::write(self.address, self.controller.init_commands()).map(|write| engine.push(write));
+
+            engine.start();
+            self.state = DisplayState::Init(engine);
+            self.ticker = Some(Ticker::start(qq, self.period, SystemTimer::now()));
+        }
+    }
+
+    fn render<const N: usize>(controller: &Controller<N>, alert_level: AlertLevel) -> Framebuffer {
+        let mut framebuffer = Framebuffer::new();
+        let mut text = String::<16>::new();
+
+        framebuffer.draw_text(0, 0, "CO2", 1);
+
+        let co2_icon = match alert_level {
+            AlertLevel::Normal => None,
+            AlertLevel::Warning => Some(Self::ICON_WARNING),
+            AlertLevel::Critical => Some(Self::ICON_CRITICAL),
+        };
+        let usb_icon = (status_led::is_requested(LedPattern::UsbTimeout) || status_led::is_requested(LedPattern::UsbOverflow)).then_some(Self::ICON_USB);
+        let i2c_icon = status_led::is_requested(LedPattern::I2CError).then_some(Self::ICON_I2C_ERROR);
+
+        // from the right edge
+        let mut icon_x = WIDTH;
+        for icon in [co2_icon, usb_icon, i2c_icon].into_iter().flatten() {
+            icon_x -= icon.len() + 2;
+            framebuffer.draw_columns(icon_x, 0, &icon, 1);
+        }
+
+        let record = controller.last_record();
+
+        // cannot fail, values are shorter than the string capacity
+        match record {
+            Some(record) => { let _ = write!(text, "{:.0}", Milli::from(record.co2)); },
+            None => { let _ = text.push_str("----"); },
+        }
+        let x = framebuffer.draw_text(0, 12, &text, 3);
+        framebuffer.draw_text(x + 2, 26, "PPM", 1);
+
+        framebuffer.fill_rect(0, 38, WIDTH, 1, true);
+
+        text.clear();
+        match record {
+            Some(record) => { let _ = write!(text, "{:.1}°C", Milli::from(record.temperature)); },
+            None => { let _ = text.push_str("--.-°C"); },
+        }
+        framebuffer.draw_text(0, 46, &text, 2);
+
+        text.clear();
+        match record {
+            Some(record) => { let _ = write!(text, "{:.0}%", Milli::from(record.humidity)); },
+            None => { let _ = text.push_str("--%"); },
+        }
+        framebuffer.draw_text(WIDTH - Framebuffer::text_width(&text, 2), 46, &text, 2);
+
+        framebuffer
+    }
+
+    fn start_page(&mut self, page: usize) {
+        let mut engine = I2CEngine::new(1);
+        let column = self.controller.column_offset();
+
+        // cannot fail, `PAGE_OPERATIONS` operations, writes are shorter than `MAX_WRITE_LEN`
+        let _ = I2COperation::write(self.address, &[0x00, 0xb0 | page as u8, column & 0x0f, 0x10 | (column >> 4)]).map(|write| engine.push(write));
+        for segment in self.framebuffer.page(page).chunks(Self::SEGMENT_LEN) {
+            let mut bytes = [0x40; 1 + Self::SEGMENT_LEN];
+            bytes[1..].copy_from_slice(segment);
+            let _ = I2COperation::write(self.address, &bytes).map(|write| engine.push(write));
+        }
+
+        engine.start();
+        self.state = DisplayState::Writing { page, engine };
+    }
+
+    pub fn update<const N: usize>(
+        &mut self,
+        bus: &mut I2CBus,
+        usb_writer: &mut impl Write,
+        qq: &mut impl QQAlarmQueue,
+        events: &mut EventBus,
+        controller: &Controller<N>,
+    ) -> bool {
+        let mut did_something = false;
+
+        // icon is changed by the next refresh
+        while let Some(event) = events.poll(Subscriber::Display) {
+            if let Event::AlertLevel(level) = event {
+                self.alert_level = level;
+            }
+        }
+
+        if let Some(ticker) = &mut self.ticker {
+            if ticker.take_tick() {
+                let framebuffer = Self::render(controller, self.alert_level);
+                let changed = framebuffer.changed_pages(&self.framebuffer);
+
+                if changed != 0 {
+                    debug!(usb_writer, Module::Status, "display refresh, pages {:08b}", changed);
+                    self.framebuffer = framebuffer;
+                    self.dirty |= changed;
+                }
+
+                // pages are sent only after refresh, so failed writes are not repeated immediately
+                if let DisplayState::Idle = self.state && self.dirty != 0 {
+                    self.state = DisplayState::Sending;
+                }
+
+                did_something = true;
+            } else {
+                did_something |= ticker.retry(qq);
+            }
+        }
+
+        match &mut self.state {
+            DisplayState::Init(engine) => {
+                if !bus.try_acquire(self.bus_user) {
+                    return did_something;
+                }
+
+                let result = match engine.update(qq, bus.i2c()) {
+                    State::Active(active) => return did_something | active,
+                    State::Done(result) => result,
+                };
+
+                bus.release(self.bus_user);
+
+                match result {
+                    Ok(()) => {
+                        info!(usb_writer, Module::Status, "display initialized");
+                        self.state = DisplayState::Idle;
+                    },
+                    Err(err) => {
+                        error!(usb_writer, Module::Status, "display init error : {:?}", err);
+                        error_registry::record_error(Subsystem::Display, &err);
+                        self.i2c_error.set(true);
+                        self.state = DisplayState::Error;
+                    },
+                }
+
+                true
+            },
+            DisplayState::Sending => {
+                if !bus.try_acquire(self.bus_user) {
+                    return did_something;
+                }
+
+                self.start_page(self.dirty.trailing_zeros() as usize);
+                true
+            },
+            DisplayState::Writing { page, engine } => {
+                let result = match engine.update(qq, bus.i2c()) {
+                    State::Active(active) => return did_something | active,
+                    State::Done(result) => result,
+                };
+                let page = *page;
+
+                bus.release(self.bus_user);
+
+                self.i2c_error.set(result.is_err());
+
+                match result {
+                    Ok(()) => {
+                        self.dirty &= !(1 << page);
+                        self.state = if self.dirty != 0 { DisplayState::Sending } else { DisplayState::Idle };
+                    },
+                    Err(err) => {
+                        error!(usb_writer, Module::Status, "display page {} error : {:?}", page, err);
+                        error_registry::record_error(Subsystem::Display, &err);
+                        self.state = DisplayState::Idle;
+                    },
+                }
+
+                true
+            },
+            DisplayState::None |
+            DisplayState::Idle |
+            DisplayState::Error => did_something,
+        }
+    }
+
+    pub fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        if let Some(ticker) = &mut self.ticker && ticker.on_alarm(qq_alarm_id) {
+            return true;
+        }
+
+        match &mut self.state {
+            DisplayState::Init(engine) => engine.on_alarm(qq_alarm_id),
+            DisplayState::Writing { engine, .. } => engine.on_alarm(qq_alarm_id),
+            _ => false,
+        }
+    }
+}
+
+impl<'c, 'i, W, Q, const N: usize> Machine<Context<'c, 'i, W, Q, N>> for Display
+where
+    W: Write + UsbWriter,
+    Q: TaggedQQAlarmQueue,
+{
+    fn name(&self) -> &'static str {
+        "display"
+    }
+
+    fn owns(&self, owner: QQOwner) -> bool {
+        owner == QQOwner::Display
+    }
+
+    fn update(&mut self, context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        Self::update(self, context.i2c_bus, context.usb_writer, &mut context.qq.owned(QQOwner::Display), context.events, context.controller)
+    }
+
+    fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        Self::on_alarm(self, qq_alarm_id)
+    }
+}
@@ -0,0 +1,189 @@
+use core::fmt::Write;
+
+use esp_hal::timer::systimer::SystemTimer;
+
+use crate::{
+    error_registry::{self, Subsystem},
+    fixed_point::Milli,
+    flash::SectorFlash,
+    flash_log::{FlashLog, LogCursor, LogRecord, LogTime},
+    log::log_line,
+    qq_alarm_queue::TaggedQQAlarmQueue,
+    record::CompactEncoding,
+    scheduler::{Context, Machine},
+    sinks::{QueueSink, Sink},
+    usb_writer::UsbWriter,
+};
+
+use super::console::ConsoleCommand;
+
+
+
+#[derive(Debug, Clone, Copy)]
+struct LogDump {
+    cursor: LogCursor,
+    records: u32,
+}
+
+/// Appends records published by the controller (through its own `QueueSink`) to the flash log and dumps the log to usb.
+///
+/// One record is appended per update. Erase of the next sector keeps interrupts disabled for tens of ms (see `rom_flash`),
+/// so it is done ahead, as soon as the head sector is full and usb output is sent (and no dump is running), not when the
+/// next record comes.
+/// Dump writes one line per record, only when the line fits into usb writer buffer (nothing is dropped).
+pub struct FlashLogger<F, const N: usize> {
+    /// `None` when the log could not be opened (error is recorded)
+    log: Option<FlashLog<F>>,
+    sink: QueueSink<CompactEncoding, N>,
+    dump: Option<LogDump>,
+}
+
+impl<F, const N: usize> FlashLogger<F, N> where F: SectorFlash, F::Error: error_registry::ErrorCode {
+    /// free space of usb writer needed for one dump line (including framing in framed mode)
+    const DUMP_LINE_SPACE: usize = 128;
+
+
+    /// Opens log in `sectors` sectors of `flash`.
+    pub fn new(flash: F, sectors: u32) -> Self {
+        let log = match FlashLog::open(flash, sectors) {
+            Ok(log) => Some(log),
+            Err(err) => {
+                error_registry::record_error(Subsystem::FlashLog, &err);
+                None
+            },
+        };
+
+        Self {
+            log,
+            sink: QueueSink::new(CompactEncoding { ticks_per_second: SystemTimer::TICKS_PER_SECOND }),
+            dump: None,
+        }
+    }
+
+    /// Sink to be passed to `Controller::update`.
+    pub fn sink(&mut self) -> &mut QueueSink<CompactEncoding, N> {
+        &mut self.sink
+    }
+
+    pub fn start_dump(&mut self, usb_writer: &mut impl Write) {
+        let Some(log) = &self.log else {
+            log_line!(usb_writer, "log", "not available");
+            return;
+        };
+
+        if self.dump.is_some() {
+            log_line!(usb_writer, "log", "dump already running");
+            return;
+        }
+
+        log_line!(usb_writer, "log", "dump (capacity {} records)", log.capacity());
+
+        self.dump = Some(LogDump { cursor: log.cursor(), records: 0 });
+    }
+
+    pub fn stop_dump(&mut self, usb_writer: &mut impl Write) {
+        if let Some(dump) = self.dump.take() {
+            log_line!(usb_writer, "log", "dump stopped after {} records", dump.records);
+        }
+    }
+
+    fn write_record(usb_writer: &mut impl Write, record: &LogRecord) {
+        let (seconds, kind) = match record.time {
+            LogTime::Unix(seconds) => (seconds, "unix"),
+            LogTime::SinceBoot(seconds) => (seconds, "since boot"),
+        };
+
+        log_line!(
+            usb_writer,
+            "log",
+            "{} s ({}), co2 {} ppm, temperature {:.1} °C, humidity {:.1} %",
+            seconds,
+            kind,
+            record.co2,
+            Milli::from(record.temperature as i32 * 10),
+            Milli::from(record.humidity as i32 * 100),
+        );
+    }
+
+    pub fn update(&mut self, usb_writer: &mut (impl Write + UsbWriter)) -> bool {
+        let Some(log) = &mut self.log else {
+            return false;
+        };
+
+        let mut did_something = false;
+
+        // records are queued whole, so the queue always holds whole records
+        let mut encoded = [0; LogRecord::ENCODED_LEN];
+        if self.sink.drain_into(&mut encoded) == LogRecord::ENCODED_LEN {
+            if let Err(err) = log.append(&LogRecord::decode(&encoded)) {
+                error_registry::record_error(Subsystem::FlashLog, &err);
+            }
+
+            did_something = true;
+        }
+
+        if log.needs_erase() && self.dump.is_none() && usb_writer.buffered_len() == 0 {
+            if let Err(err) = log.prepare_head() {
+                error_registry::record_error(Subsystem::FlashLog, &err);
+            }
+
+            did_something = true;
+        }
+
+        if let Some(dump) = &mut self.dump {
+            while usb_writer.free_space() >= Self::DUMP_LINE_SPACE {
+                did_something = true;
+
+                match log.read_next(&mut dump.cursor) {
+                    Ok(Some(record)) => {
+                        Self::write_record(usb_writer, &record);
+                        dump.records += 1;
+                    },
+                    Ok(None) => {
+                        log_line!(usb_writer, "log", "end, {} records ({} dropped before flash)", dump.records, self.sink.dropped_records());
+                        self.dump = None;
+                        break;
+                    },
+                    Err(err) => {
+                        error_registry::record_error(Subsystem::FlashLog, &err);
+                        log_line!(usb_writer, "log", "dump failed after {} records", dump.records);
+                        self.dump = None;
+                        break;
+                    },
+                }
+            }
+        }
+
+        did_something
+    }
+}
+
+impl<'c, 'i, F, const Q_LEN: usize, W, Q, const N: usize> Machine<Context<'c, 'i, W, Q, N>> for FlashLogger<F, Q_LEN>
+where
+    F: SectorFlash,
+    F::Error: error_registry::ErrorCode,
+    W: Write + UsbWriter,
+    Q: TaggedQQAlarmQueue,
+{
+    fn name(&self) -> &'static str {
+        "flash logger"
+    }
+
+    fn update(&mut self, context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        Self::update(self, context.usb_writer)
+    }
+
+    fn on_command(&mut self, command: ConsoleCommand, context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        match command {
+            ConsoleCommand::LogDump => self.start_dump(context.usb_writer),
+            ConsoleCommand::LogDumpStop => self.stop_dump(context.usb_writer),
+            _ => return false,
+        }
+
+        true
+    }
+
+    fn sink(&mut self) -> Option<&mut dyn Sink> {
+        Some(Self::sink(self))
+    }
+}
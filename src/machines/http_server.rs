@@ -0,0 +1,355 @@
+use core::fmt::Write;
+
+use esp_hal::timer::systimer::SystemTimer;
+
+use heapless::{String, Vec};
+use smoltcp::{iface::SocketHandle, socket::tcp};
+
+use crate::{
+    config_store::Config,
+    error_registry::{self, ErrorCode, Subsystem},
+    fixed_point::Milli,
+    http::{self, Method, Status},
+    invariants::invariant,
+    log::{info, Module},
+    net::NetStack,
+    qq_alarm_queue::QQAlarmQueue,
+};
+
+use super::{controller::Controller, Delay};
+
+
+
+impl ErrorCode for tcp::ListenError {
+    fn error_code(&self) -> u16 {
+        0x90
+    }
+}
+
+
+/// longer request heads are responded by 400 (browsers send ~500 bytes of headers, only request line is needed)
+const REQUEST_LEN: usize = 1024;
+const RESPONSE_LEN: usize = 1536;
+const BODY_LEN: usize = 1280;
+
+
+#[derive(Debug, Clone, Copy)]
+enum HttpServerState {
+    /// `start` was not called or listening failed
+    Stopped,
+    /// waiting for a client, socket accepts connection by itself
+    Listening,
+    /// reading request head, delay is the connection timeout (same for the whole connection)
+    Receiving(Delay),
+    /// writing response as send buffer frees up
+    Sending { timeout: Delay, sent: usize },
+    /// response was sent, waiting until the client closes its side
+    Closing(Delay),
+}
+
+/// Serves json snapshot of the device (latest measurment, uptime, last errors, config) on `GET /` over http/1.0.
+///
+/// Only one connection is handled at a time (single tcp socket), other clients are refused until the response is sent.
+/// Connection which does not finish within `timeout` is aborted, so a stalled client cannot block the server.
+pub struct HttpServer {
+    tcp: SocketHandle,
+    port: u16,
+    /// connection timeout (in system timer ticks)
+    timeout: u64,
+    request: Vec<u8, REQUEST_LEN>,
+    response: String<RESPONSE_LEN>,
+    state: HttpServerState,
+}
+
+impl HttpServer {
+    pub const ERROR_CODE_TIMEOUT: u16 = 0x91;
+    /// json snapshot did not fit into response buffer
+    pub const ERROR_CODE_RESPONSE_TOO_LONG: u16 = 0x92;
+
+
+    /// `tcp` is handle of tcp socket in the `NetStack` passed to `update`.
+    pub fn new(tcp: SocketHandle, port: u16, timeout: u64) -> Self {
+        Self {
+            tcp,
+            port,
+            timeout,
+            request: Vec::new(),
+            response: String::new(),
+            state: HttpServerState::Stopped,
+        }
+    }
+
+    pub fn start(&mut self, usb_writer: &mut impl Write, net: &mut NetStack) {
+        self.listen(net);
+
+        if matches!(self.state, HttpServerState::Listening) {
+            info!(usb_writer, Module::Http, "listening on port {}", self.port);
+        }
+    }
+
+    fn listen(&mut self, net: &mut NetStack) {
+        self.request.clear();
+
+        self.state = match net.tcp(self.tcp).listen(self.port) {
+            Ok(()) => HttpServerState::Listening,
+            Err(err) => {
+                error_registry::record_error(Subsystem::Http, &err);
+                HttpServerState::Stopped
+            },
+        };
+    }
+
+    fn cancel_alarm(&mut self, qq: &mut impl QQAlarmQueue) {
+        match self.state {
+            HttpServerState::Receiving(Delay::Waiting { qq_alarm_id })
+            | HttpServerState::Sending { timeout: Delay::Waiting { qq_alarm_id }, .. }
+            | HttpServerState::Closing(Delay::Waiting { qq_alarm_id }) => {
+                invariant!(qq.remove(qq_alarm_id).is_ok(), "http server alarm not found in qq");
+            },
+            _ => {},
+        }
+    }
+
+    /// Drops the connection (client gets reset) and listens for the next one.
+    fn abort(&mut self, qq: &mut impl QQAlarmQueue, net: &mut NetStack) {
+        self.cancel_alarm(qq);
+        net.tcp(self.tcp).abort();
+        self.listen(net);
+    }
+
+    fn write_status_json<const N: usize>(body: &mut impl Write, controller: &Controller<N>, config: &Config) -> core::fmt::Result {
+        let ticks_per_ms = SystemTimer::TICKS_PER_SECOND / 1_000;
+
+        write!(body, "{{\"uptime_ms\":{},\"measurment\":", SystemTimer::now() / ticks_per_ms)?;
+
+        match controller.last_record() {
+            Some(record) => {
+                write!(body, "{{\"at_ms\":{},\"unix_ms\":", record.at / ticks_per_ms)?;
+                match record.unix_ms {
+                    Some(unix_ms) => write!(body, "{}", unix_ms)?,
+                    None => write!(body, "null")?,
+                }
+                write!(
+                    body,
+                    ",\"co2_ppm\":{:.1},\"temperature_c\":{:.2},\"humidity_percent\":{:.1}}}",
+                    Milli::from(record.co2),
+                    Milli::from(record.temperature),
+                    Milli::from(record.humidity),
+                )?;
+            },
+            None => write!(body, "null")?,
+        }
+
+        write!(body, ",\"filtered\":")?;
+
+        match controller.last_filtered() {
+            Some(filtered) => write!(
+                body,
+                "{{\"co2_ppm\":{:.1},\"temperature_c\":{:.2},\"humidity_percent\":{:.1}}}",
+                Milli::from(filtered.co2),
+                Milli::from(filtered.temperature),
+                Milli::from(filtered.humidity),
+            )?,
+            None => write!(body, "null")?,
+        }
+
+        write!(body, ",\"humidity\":")?;
+
+        match controller.humidity_metrics() {
+            Some(metrics) => {
+                write!(body, "{{\"dew_point_c\":")?;
+                match metrics.dew_point {
+                    Some(dew_point) => write!(body, "{:.1}", Milli::from(dew_point))?,
+                    None => write!(body, "null")?,
+                }
+                write!(body, ",\"absolute_g_m3\":{:.2}}}", Milli::from(metrics.absolute_humidity))?;
+            },
+            None => write!(body, "null")?,
+        }
+
+        write!(body, ",\"co2_trend\":")?;
+
+        match controller.co2_trend() {
+            Some(trend) => write!(body, "{{\"trend\":\"{}\",\"ppm_per_min\":{:.1}}}", trend.trend.name(), Milli::from(trend.slope))?,
+            None => write!(body, "null")?,
+        }
+
+        write!(body, ",\"errors\":{{")?;
+
+        let mut first = true;
+        for subsystem in Subsystem::ALL {
+            if let Some(record) = error_registry::last_error(subsystem) {
+                let separator = if first { "" } else { "," };
+                write!(body, "{}\"{}\":{{\"code\":{},\"at_ms\":{},\"count\":{}}}", separator, subsystem.name(), record.code, record.at / ticks_per_ms, record.count)?;
+                first = false;
+            }
+        }
+
+        write!(
+            body,
+            "}},\"config\":{{\"interval_secs\":{},\"alert_warning_ppm\":{:.1},\"alert_critical_ppm\":{:.1},\"alert_hysteresis_ppm\":{:.1},\"ventilation_on_ppm\":{:.1},\"ventilation_off_ppm\":{:.1}}}}}",
+            config.interval_secs,
+            Milli::from(config.alert_warning),
+            Milli::from(config.alert_critical),
+            Milli::from(config.alert_hysteresis),
+            Milli::from(config.ventilation_on),
+            Milli::from(config.ventilation_off),
+        )
+    }
+
+    /// Prepares whole response (head and body) into `response`.
+    fn respond<const N: usize>(&mut self, controller: &Controller<N>, config: &Config) {
+        let request = http::head_len(&self.request).map(|len| http::parse_request_line(&self.request[..len]));
+
+        let mut body = String::<BODY_LEN>::new();
+
+        let (status, method) = match request {
+            Some(Ok(request)) if request.method == Method::Other => (Status::MethodNotAllowed, request.method),
+            Some(Ok(request)) if request.path != "/" => (Status::NotFound, request.method),
+            Some(Ok(request)) => {
+                if Self::write_status_json(&mut body, controller, config).is_ok() {
+                    (Status::Ok, request.method)
+                } else {
+                    error_registry::record(Subsystem::Http, Self::ERROR_CODE_RESPONSE_TOO_LONG);
+                    (Status::InternalServerError, request.method)
+                }
+            },
+            // head did not fit into request buffer or request line is malformed
+            None | Some(Err(_)) => (Status::BadRequest, Method::Get),
+        };
+
+        if status != Status::Ok {
+            body.clear();
+            let _ = write!(body, "{{\"error\":\"{}\"}}", status.reason());
+        }
+
+        self.response.clear();
+        // cannot fail, head is shorter than `RESPONSE_LEN - BODY_LEN`
+        let _ = http::write_response_head(&mut self.response, status, "application/json", body.len());
+        if method != Method::Head {
+            let _ = self.response.push_str(&body);
+        }
+    }
+
+    /// `net` has to be updated before.
+    pub fn update<const N: usize>(&mut self, qq: &mut impl QQAlarmQueue, net: &mut NetStack, controller: &Controller<N>, config: &Config) -> bool {
+        match self.state {
+            HttpServerState::Stopped => false,
+            HttpServerState::Listening => {
+                // connection is established (handshake is done by the socket)
+                if !net.tcp(self.tcp).may_recv() {
+                    return false;
+                }
+
+                self.state = HttpServerState::Receiving(Delay::start(qq, SystemTimer::now() + self.timeout));
+                true
+            },
+            HttpServerState::Receiving(mut timeout) => {
+                if timeout == Delay::Done {
+                    error_registry::record(Subsystem::Http, Self::ERROR_CODE_TIMEOUT);
+                    self.abort(qq, net);
+                    return true;
+                }
+
+                let socket = net.tcp(self.tcp);
+
+                if !socket.may_recv() {
+                    // client closed the connection without complete request
+                    self.abort(qq, net);
+                    return true;
+                }
+
+                let mut did_something = timeout.retry(qq);
+
+                let mut bytes = [0; 128];
+                while socket.can_recv() && !self.request.is_full() {
+                    let free = (REQUEST_LEN - self.request.len()).min(bytes.len());
+
+                    match socket.recv_slice(&mut bytes[..free]) {
+                        // cannot fail, length is limited by free space
+                        Ok(len) => { let _ = self.request.extend_from_slice(&bytes[..len]); },
+                        Err(err) => {
+                            error_registry::record_error(Subsystem::Http, &err);
+                            self.abort(qq, net);
+                            return true;
+                        },
+                    }
+
+                    did_something = true;
+                }
+
+                if http::head_len(&self.request).is_some() || self.request.is_full() {
+                    self.respond(controller, config);
+                    self.state = HttpServerState::Sending { timeout, sent: 0 };
+                    return true;
+                }
+
+                self.state = HttpServerState::Receiving(timeout);
+                did_something
+            },
+            HttpServerState::Sending { mut timeout, mut sent } => {
+                if timeout == Delay::Done {
+                    error_registry::record(Subsystem::Http, Self::ERROR_CODE_TIMEOUT);
+                    self.abort(qq, net);
+                    return true;
+                }
+
+                let socket = net.tcp(self.tcp);
+
+                if !socket.may_send() {
+                    self.abort(qq, net);
+                    return true;
+                }
+
+                let mut did_something = timeout.retry(qq);
+
+                match socket.send_slice(&self.response.as_bytes()[sent..]) {
+                    Ok(len) => {
+                        sent += len;
+                        did_something |= len != 0;
+                    },
+                    Err(err) => {
+                        error_registry::record_error(Subsystem::Http, &err);
+                        self.abort(qq, net);
+                        return true;
+                    },
+                }
+
+                if sent == self.response.len() {
+                    // fin is sent after the queued response
+                    socket.close();
+                    self.state = HttpServerState::Closing(timeout);
+                    return true;
+                }
+
+                self.state = HttpServerState::Sending { timeout, sent };
+                did_something
+            },
+            HttpServerState::Closing(mut timeout) => {
+                if timeout == Delay::Done {
+                    error_registry::record(Subsystem::Http, Self::ERROR_CODE_TIMEOUT);
+                    self.abort(qq, net);
+                    return true;
+                }
+
+                // time wait is not active, the socket can listen again right away
+                if !net.tcp(self.tcp).is_active() {
+                    self.cancel_alarm(qq);
+                    self.listen(net);
+                    return true;
+                }
+
+                let did_something = timeout.retry(qq);
+                self.state = HttpServerState::Closing(timeout);
+                did_something
+            },
+        }
+    }
+
+    pub fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        match &mut self.state {
+            HttpServerState::Receiving(timeout) | HttpServerState::Sending { timeout, .. } | HttpServerState::Closing(timeout) => timeout.on_alarm(qq_alarm_id),
+            HttpServerState::Listening | HttpServerState::Stopped => false,
+        }
+    }
+}
@@ -0,0 +1,232 @@
+use core::fmt::Write;
+
+use esp_hal::{gpio::{Output, OutputPin}, peripheral::{Peripheral, PeripheralRef}, peripherals::RMT, rmt::PulseCode, timer::systimer::SystemTimer};
+
+use heapless::Deque;
+
+use crate::{
+    error_registry::{self, Subsystem},
+    interrupts,
+    ir::nec,
+    log::{error, log_line, Module},
+    pac_utils::{gpio::PinNumber, rmt::{self as rmt_utils, RMTError, RmtCarrier, RmtTxChConfig, TxChannel}},
+    qq_alarm_queue::{QQAlarmQueue, QQOwner, TaggedQQAlarmQueue},
+    scheduler::{Context, Machine},
+    usb_writer::UsbWriter
+};
+
+use super::{console::ConsoleCommand, Delay};
+
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrNecTxError {
+    QueueFull,
+}
+
+
+#[derive(Debug, Clone, Copy)]
+struct QueuedCommand {
+    address: u8,
+    message: u8,
+    repeats: u8,
+}
+
+enum IrNecTxState {
+    Idle,
+    /// frame is being sent, next frame can start when it was sent and `delay` is done (frame period is counted from the start)
+    Sending {
+        /// remaining repeat frames
+        remaining: u8,
+        /// repeat frame is in channel memory (message frame was already sent)
+        repeat_loaded: bool,
+        sent: bool,
+        delay: Delay,
+    },
+    Error,
+}
+
+/// channel ticks per nec unit
+const UNIT_TICKS: u16 = 7;
+
+fn to_pulse_codes(pulses: impl Iterator<Item = (u16, u16)>) -> impl Iterator<Item = PulseCode> {
+    pulses.map(|(mark, space)| PulseCode {
+        level1: true,
+        length1: mark * UNIT_TICKS,
+        level2: false,
+        length2: space * UNIT_TICKS,
+    })
+}
+
+/// Sends NEC messages using rmt channel 1 (channel 0 is used by `IrSonyTx`), other machines queue messages with `send`.
+///
+/// Message frame is followed by `repeats` repeat frames (as if the key was held), frames start every 108 ms.
+/// Rmt clock is configured by ir reciever (`IrRxDispatch`), so it has to be created first.
+pub struct IrNecTx<'a, 'b, PIN, const QUEUE_SIZE: usize> {
+    rmt: PeripheralRef<'a, RMT>,
+    _pin: Output<'b, PIN>,
+    queue: Deque<QueuedCommand, QUEUE_SIZE>,
+    state: IrNecTxState,
+}
+
+impl<'a, 'b, PIN, const QUEUE_SIZE: usize> IrNecTx<'a, 'b, PIN, QUEUE_SIZE>
+where
+    PIN: OutputPin + PinNumber
+{
+    const CHANNEL: TxChannel = TxChannel::Ch1;
+
+    const FRAME_PERIOD: u64 = 108 * SystemTimer::TICKS_PER_SECOND / 1000;
+
+    /// 66 ticks, 37.9 KHz (~ 38 KHz)
+    const CARRIER: RmtCarrier = RmtCarrier::from_khz(38, 33);
+
+
+    pub fn new(rmt: impl Peripheral<P = RMT> + 'a, pin: impl Peripheral<P = PIN> + 'b) -> Self {
+        let mut rmt = rmt.into_ref();
+
+        rmt_utils::tx_config(rmt.reborrow(), Self::CHANNEL, RmtTxChConfig {
+            clock_div: 201, // 80.4 us, 7 ticks = 562.8 us (~ 562.5 us, nec unit)
+            carrier: Some(Self::CARRIER),
+            idle_level: false,
+        });
+
+        rmt_utils::tx_enable_interrupts(rmt.reborrow(), Self::CHANNEL);
+
+        let pin = rmt_utils::setup_output_pin(pin, Self::CHANNEL);
+
+        Self {
+            rmt,
+            _pin: pin,
+            queue: Deque::new(),
+            state: IrNecTxState::Idle,
+        }
+    }
+
+    /// Queues message frame followed by `repeats` repeat frames.
+    pub fn send(&mut self, address: u8, message: u8, repeats: u8) -> Result<(), IrNecTxError> {
+        self.queue.push_back(QueuedCommand { address, message, repeats }).map_err(|_| IrNecTxError::QueueFull)
+    }
+
+    fn start_frame(&mut self, qq: &mut impl QQAlarmQueue, remaining: u8, repeat_loaded: bool) {
+        rmt_utils::tx_start(self.rmt.reborrow(), Self::CHANNEL);
+
+        self.state = IrNecTxState::Sending {
+            remaining,
+            repeat_loaded,
+            sent: false,
+            delay: Delay::start(qq, SystemTimer::now() + Self::FRAME_PERIOD),
+        };
+    }
+
+    pub fn update(&mut self, qq: &mut impl QQAlarmQueue, usb_writer: &mut impl Write) -> bool {
+        match &mut self.state {
+            IrNecTxState::Idle => {
+                let Some(queued) = self.queue.pop_front() else {
+                    return false;
+                };
+
+                // message frame (34 pulse codes) always fits into channel memory
+                match rmt_utils::tx_fifo_fill(self.rmt.reborrow(), Self::CHANNEL, to_pulse_codes(nec::pulses(queued.address, queued.message))) {
+                    Ok(()) => self.start_frame(qq, queued.repeats, false),
+                    Err(err) => {
+                        error!(usb_writer, Module::Ir, "rmt nec tx error : {:?}", err);
+                        error_registry::record_error(Subsystem::IrTx, &err);
+                    },
+                }
+
+                true
+            },
+            IrNecTxState::Sending { remaining, repeat_loaded, sent, delay } => {
+                let mut did_something = delay.retry(qq);
+
+                let pending_interrupts = interrupts::rmt_interrupt_get_and_clear(Self::CHANNEL.interrupts());
+
+                if let Some(err) = RMTError::from_interrupt_flags(pending_interrupts) {
+                    error!(usb_writer, Module::Ir, "rmt nec tx error : {:?}", err);
+                    error_registry::record_error(Subsystem::IrTx, &err);
+
+                    if let Delay::Waiting { qq_alarm_id } = *delay {
+                        let _ = qq.remove(qq_alarm_id);
+                    }
+
+                    self.state = IrNecTxState::Error;
+                    return true;
+                }
+
+                if !pending_interrupts.is_empty() {
+                    *sent = true;
+                    did_something = true;
+                }
+
+                if *sent && *delay == Delay::Done {
+                    match (*remaining, *repeat_loaded) {
+                        (0, _) => self.state = IrNecTxState::Idle,
+                        // repeat frame is still in channel memory
+                        (remaining, true) => self.start_frame(qq, remaining - 1, true),
+                        (remaining, false) => match rmt_utils::tx_fifo_fill(self.rmt.reborrow(), Self::CHANNEL, to_pulse_codes(nec::repeat_pulses())) {
+                            Ok(()) => self.start_frame(qq, remaining - 1, true),
+                            Err(err) => {
+                                error!(usb_writer, Module::Ir, "rmt nec tx error : {:?}", err);
+                                error_registry::record_error(Subsystem::IrTx, &err);
+
+                                self.state = IrNecTxState::Idle;
+                            },
+                        },
+                    }
+
+                    did_something = true;
+                }
+
+                did_something
+            },
+            IrNecTxState::Error => false,
+        }
+    }
+
+    pub fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        match &mut self.state {
+            IrNecTxState::Sending { delay, .. } => delay.on_alarm(qq_alarm_id),
+            _ => false,
+        }
+    }
+
+    /// Send command (`nec`).
+    pub fn on_command(&mut self, command: ConsoleCommand, usb_writer: &mut impl Write) -> bool {
+        let ConsoleCommand::NecSend { address, message, repeats } = command else {
+            return false;
+        };
+
+        if let Err(err) = self.send(address, message, repeats) {
+            log_line!(usb_writer, "ir nec tx", "{:?}", err);
+        }
+
+        true
+    }
+}
+
+impl<'c, 'i, 'a, 'b, PIN, const QUEUE_SIZE: usize, W, Q, const N: usize> Machine<Context<'c, 'i, W, Q, N>> for IrNecTx<'a, 'b, PIN, QUEUE_SIZE>
+where
+    PIN: OutputPin + PinNumber,
+    W: Write + UsbWriter,
+    Q: TaggedQQAlarmQueue,
+{
+    fn name(&self) -> &'static str {
+        "ir nec tx"
+    }
+
+    fn owns(&self, owner: QQOwner) -> bool {
+        owner == QQOwner::IrNecTx
+    }
+
+    fn update(&mut self, context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        Self::update(self, &mut context.qq.owned(QQOwner::IrNecTx), context.usb_writer)
+    }
+
+    fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        Self::on_alarm(self, qq_alarm_id)
+    }
+
+    fn on_command(&mut self, command: ConsoleCommand, context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        Self::on_command(self, command, context.usb_writer)
+    }
+}
@@ -0,0 +1,428 @@
+use core::fmt::Write;
+
+use esp_hal::{gpio::InputPin, peripheral::{Peripheral, PeripheralRef}, peripherals::{RMT, SYSTEM}, timer::systimer::SystemTimer};
+
+use heapless::Vec;
+
+use crate::{
+    config_store::IrBindings,
+    error_registry::{self, Subsystem},
+    event_bus::{Event, EventBus},
+    framing::FrameType,
+    interrupts,
+    ir::{
+        event::{IrEvent, IrEventTracker, IrRepeatConfig},
+        nec::{NecIrTimingConfig, NecMessage},
+        rc5::Rc5IrTimingConfig,
+        rc6::Rc6IrTimingConfig,
+        sirc::SircIrTimingConfig,
+        IrDispatchDecoder,
+        IrMessage,
+        IrTimingConfig
+    },
+    ir_learning::{self, IrCodeStore, LearnedCode, Name, MAX_PULSES},
+    log::{debug, error, info, log_line, warn, Module},
+    pac_utils::{gpio::PinNumber, rmt::{self as rmt_utils, RMTError, RmtClockConfig, RmtRxChConfig, RxChannel, RxStream, RX_MEM_CODES, RX_STREAM_PULSES}},
+    qq_alarm_queue::{QQAlarmQueue, QQOwner, TaggedQQAlarmQueue},
+    rom_flash::RomFlash,
+    scheduler::{Context, Machine},
+    usb_writer::{UsbOutputMode, UsbWriter}
+};
+
+use super::{console::ConsoleCommand, status_led::{self, LedPattern}, Delay};
+
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct IrRxDispatchConfig {
+    /// `RxChannel::Ch2` is expected by mock hardware (see `mock::ir`)
+    pub channel: RxChannel,
+    /// held nec key is released when no repeat code comes in this time (in system timer ticks), repeat period is ~108 ms
+    pub nec_repeat_timeout: u64,
+}
+
+
+enum IrRxDispatchState {
+    Active,
+    Error,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrLearnError {
+    /// frame has more than `ir_learning::MAX_PULSES` pulses, it could not be replayed
+    TooLong { pulses: usize },
+}
+
+#[derive(Debug)]
+enum Learning {
+    Off,
+    /// next recieved frame is captured instead of being decoded
+    Waiting,
+    Done(Result<Vec<u16, MAX_PULSES>, IrLearnError>),
+}
+
+/// Captures raw rmt pulse sequences and tries all supported protocol decoders (NEC, Sony SIRC, RC5, RC6) on them,
+/// so one reciever pin can be used with remotes of different brands.
+///
+/// Channel runs in wrap mode, pulses are collected by the rmt interrupt handler on each threshold interrupt (see
+/// `RxStream`), so frames longer than channel memory (e.g. air conditioner remotes) can be recieved.
+///
+/// Decoded messages and frames none of the decoders understood are turned into `IrEvent`s (see `IrEventTracker`), which
+/// decides about repeats per protocol: nec repeat codes belong to the held key, sony remotes send whole frame again
+/// every 45 ms (repeat unless there was a gap of `SIRC_REPEAT_GAP`), philips remotes repeat frames every 114 ms with
+/// the same toggle bit (repeat unless there was a gap of `RC_REPEAT_GAP`). Events matching ir key bindings of the config
+/// are turned into commands, commands are published as `Event::Command`. Bindings are matched against `IrEvent`, so raw
+/// codes of unknown remotes can be bound too.
+///
+/// In learning mode (see `start_learning`) the next frame of any remote is captured as normalized pulse lengths (in us)
+/// instead of being decoded, it is taken by `take_learned`. Frames learned by `ir learn` command are saved to the code
+/// store by `save_learned`.
+pub struct IrRxDispatch<'a> {
+    rmt: PeripheralRef<'a, RMT>,
+    channel: RxChannel,
+    decoder: IrDispatchDecoder,
+    /// pulses of the last recieved frame
+    pulses: Vec<u16, RX_STREAM_PULSES>,
+    /// time is in system timer ticks
+    tracker: IrEventTracker,
+    /// release of held nec key
+    nec_release: Option<Delay>,
+    learning: Learning,
+    /// slot and name of the code being learned by `ir learn` command
+    learn_to: Option<(u8, Name)>,
+    state: IrRxDispatchState,
+}
+
+impl<'a> IrRxDispatch<'a> {
+    /// half of channel memory, the other half is being written while pulses are read
+    const WRAP_THRESH: u16 = RX_MEM_CODES / 2;
+    /// more than two sirc frame periods (45 ms)
+    const SIRC_REPEAT_GAP: u64 = 100 * SystemTimer::TICKS_PER_SECOND / 1000;
+    /// more than two rc5 / rc6 frame periods (114 ms)
+    const RC_REPEAT_GAP: u64 = 250 * SystemTimer::TICKS_PER_SECOND / 1000;
+    /// length of channel tick (in us)
+    const TICK_US: u16 = 28;
+
+
+    pub fn new<'b, 'c, PIN>(
+        rmt: impl Peripheral<P = RMT> + 'a,
+        pin: impl Peripheral<P = PIN> + 'b,
+        system: impl Peripheral<P = SYSTEM> + 'c,
+        config: IrRxDispatchConfig,
+    ) -> Self
+    where
+        PIN: InputPin + PinNumber
+    {
+        let mut rmt = rmt.into_ref();
+
+        rmt_utils::config_clock(system.into_ref(), RmtClockConfig {
+            selection: 1, // using PPL_F80M_CLK (80 MHz)
+            div_num: 32 - 1, // rmt_sclk F = 2.5 MHz (T = 0.4 us, ir channels divide it further)
+            div_a: 0,
+            div_b: 0,
+        });
+
+        rmt_utils::config(rmt.reborrow(), true);
+
+        // TODO: maybe test idle_tresh
+        rmt_utils::rx_config(rmt.reborrow(), config.channel, RmtRxChConfig {
+            clock_div: 70, // clk_div T = 28 us (=> small pulse = 20 ticks)
+            idle_thresh: 714, // 19.992 ms (~ 20 ms)
+            wrap_thresh: Some(Self::WRAP_THRESH),
+        });
+
+        rmt_utils::rx_enable_interrupts(rmt.reborrow(), config.channel, true);
+        interrupts::rmt_rx_stream_start(RxStream::new(config.channel, Self::WRAP_THRESH));
+
+        // pin is not read by the machine, gpio matrix keeps it connected to the channel after `Input` is dropped
+        let _ = rmt_utils::setup_input_pin(pin, config.channel);
+
+        // TODO: lower tolerance maybe, when ir sensor electric connection is better
+        let decoder = IrDispatchDecoder::new(IrTimingConfig {
+            nec: NecIrTimingConfig {
+                short: 20, // 560 us
+                tol_div: 2, // 50% tolerance
+                tol_num: 1,
+            },
+            // sirc and rc5 pulses are close to each other (rc5 full bit vs sirc start), so smaller tolerance is used
+            sirc: SircIrTimingConfig {
+                unit: 21, // 600 us
+                tol_div: 4, // 25% tolerance
+                tol_num: 1,
+            },
+            rc5: Rc5IrTimingConfig {
+                half: 32, // 889 us
+                tol_div: 4, // 25% tolerance
+                tol_num: 1,
+            },
+            rc6: Rc6IrTimingConfig {
+                unit: 16, // 444 us
+                tol_div: 4, // 25% tolerance
+                tol_num: 1,
+            },
+        });
+
+        Self {
+            rmt,
+            channel: config.channel,
+            decoder,
+            pulses: Vec::new(),
+            tracker: IrEventTracker::new(IrRepeatConfig {
+                nec_timeout: config.nec_repeat_timeout,
+                sirc_gap: Self::SIRC_REPEAT_GAP,
+                rc_gap: Self::RC_REPEAT_GAP,
+            }),
+            nec_release: None,
+            learning: Learning::Off,
+            learn_to: None,
+            state: IrRxDispatchState::Active,
+        }
+    }
+
+    pub fn start(&mut self) {
+        rmt_utils::rx_start(self.rmt.reborrow(), self.channel);
+    }
+
+    /// Next recieved frame is captured (previously captured frame which was not taken is dropped).
+    pub fn start_learning(&mut self) {
+        self.learning = Learning::Waiting;
+    }
+
+    pub fn stop_learning(&mut self) {
+        self.learning = Learning::Off;
+    }
+
+    /// Captured frame, pulse lengths are normalized (reciever jitter is removed, see `ir_learning::normalize`).
+    pub fn take_learned(&mut self) -> Option<Result<Vec<u16, MAX_PULSES>, IrLearnError>> {
+        match core::mem::replace(&mut self.learning, Learning::Off) {
+            Learning::Done(result) => Some(result),
+            learning => {
+                self.learning = learning;
+                None
+            },
+        }
+    }
+
+    fn capture(&self) -> Result<Vec<u16, MAX_PULSES>, IrLearnError> {
+        if self.pulses.len() > MAX_PULSES {
+            return Err(IrLearnError::TooLong { pulses: self.pulses.len() });
+        }
+
+        let mut pulses: Vec<u16, MAX_PULSES> = self.pulses.iter().map(|pulse| pulse.saturating_mul(Self::TICK_US)).collect();
+
+        ir_learning::normalize(&mut pulses, 1, 4);
+
+        Ok(pulses)
+    }
+
+    fn on_event(&mut self, usb_writer: &mut impl Write, events: &mut EventBus, bindings: &IrBindings, event: IrEvent) {
+        debug!(usb_writer, Module::Ir, "event {:?}", event);
+
+        let binding = bindings.iter().find(|binding| binding.is_key(event.protocol, event.address, event.command) && (binding.repeat || !event.repeat));
+        let Some(binding) = binding else {
+            return;
+        };
+
+        // command was valid when it was bound, stored config of older firmware could have different commands
+        match ConsoleCommand::parse(binding.command().as_bytes()) {
+            Some(command) => {
+                info!(usb_writer, Module::Ir, "command {:?}", command);
+                events.publish(Event::Command(command));
+            },
+            None => warn!(usb_writer, Module::Ir, "bound command is not valid : {}", binding.command()),
+        }
+    }
+
+    /// Restarts release delay after change of held nec key.
+    fn restart_nec_release(&mut self, qq: &mut impl QQAlarmQueue) {
+        if let Some(Delay::Waiting { qq_alarm_id }) = self.nec_release {
+            let _ = qq.remove(qq_alarm_id);
+        }
+
+        self.nec_release = self.tracker.deadline().map(|deadline| Delay::start(qq, deadline));
+    }
+
+    /// Decoded messages are written as `FrameType::IrCode` frames in framed mode, received keys are looked up in
+    /// `bindings`.
+    pub fn update(&mut self, usb_writer: &mut (impl Write + UsbWriter), qq: &mut impl QQAlarmQueue, events: &mut EventBus, bindings: &IrBindings) -> bool {
+        if let Some(Delay::Done) = self.nec_release {
+            if let Some(event) = self.tracker.on_timeout(SystemTimer::now()) {
+                debug!(usb_writer, Module::Ir, "key {:?}", event);
+            }
+            // deadline could be moved by repeat received after the alarm was set
+            self.restart_nec_release(qq);
+
+            return true;
+        }
+
+        if self.nec_release.as_mut().is_some_and(|delay| delay.retry(qq)) {
+            return true;
+        }
+
+        match self.state {
+            IrRxDispatchState::Active => {
+                let pending_interrupts = interrupts::rmt_interrupt_get_and_clear(self.channel.interrupts());
+
+                if pending_interrupts.is_empty() {
+                    return false;
+                }
+
+                if let Some(err) = RMTError::from_interrupt_flags(pending_interrupts) {
+                    error!(usb_writer, Module::Ir, "rmt rx error : {:?}", err);
+                    error_registry::record_error(Subsystem::IrRx, &err);
+
+                    self.state = IrRxDispatchState::Error;
+                } else {
+                    // we assume that level's are alternating and that pulse code sequance starts with level 1
+
+                    // parts of long frame were read by interrupt handler on threshold interrupts
+                    if !pending_interrupts.contains(self.channel.end_interrupt()) {
+                        return false;
+                    }
+
+                    let rmt = self.rmt.reborrow();
+                    let too_long = !interrupts::rmt_rx_stream_with(|stream| stream.finish(rmt, &mut self.pulses)).unwrap_or(false);
+
+                    if let Learning::Waiting = self.learning {
+                        let captured = if too_long { Err(IrLearnError::TooLong { pulses: self.pulses.len() }) } else { self.capture() };
+
+                        status_led::flash(LedPattern::IrReceived);
+                        self.learning = Learning::Done(captured);
+                        return true;
+                    }
+
+                    let now = SystemTimer::now();
+                    let pulses = self.pulses.iter().copied();
+                    let decoded = (!too_long).then(|| self.decoder.decode(pulses.clone()).map_err(|err| (err, self.tracker.on_raw(pulses, now))));
+
+                    let Some(decoded) = decoded else {
+                        warn!(usb_writer, Module::Ir, "rmt recieved too many pulses");
+                        return true;
+                    };
+
+                    match decoded {
+                        Ok(message) => {
+                            status_led::flash(LedPattern::IrReceived);
+
+                            if usb_writer.output_mode() == UsbOutputMode::Framed {
+                                let _ = usb_writer.write_frame(FrameType::IrCode, &message.encode());
+                            }
+
+                            let protocol = message.protocol().name();
+
+                            match message {
+                                IrMessage::Nec(NecMessage::Repeat) => debug!(usb_writer, Module::Ir, "rmt recieved ({}) : REPEAT", protocol),
+                                IrMessage::Nec(NecMessage::Message { address, message }) => debug!(usb_writer, Module::Ir, "rmt recieved ({}) : ADDRESS {} MESSAGE {}", protocol, address, message),
+                                IrMessage::Sirc(message) => debug!(usb_writer, Module::Ir, "rmt recieved ({}) : ADDRESS {} COMMAND {} EXTENDED {} ({} bits)", protocol, message.address, message.command, message.extended, message.bits),
+                                IrMessage::Rc5(message) => debug!(usb_writer, Module::Ir, "rmt recieved ({}) : ADDRESS {} COMMAND {} TOGGLE {}", protocol, message.address, message.command, message.toggle),
+                                IrMessage::Rc6(message) => debug!(usb_writer, Module::Ir, "rmt recieved ({}) : ADDRESS {} COMMAND {} TOGGLE {}", protocol, message.address, message.command, message.toggle),
+                            }
+
+                            if let Some(event) = self.tracker.on_message(message, now) {
+                                self.on_event(usb_writer, events, bindings, event);
+                            }
+
+                            if let IrMessage::Nec(_) = message {
+                                self.restart_nec_release(qq);
+                            }
+                        },
+                        Err((err, raw_event)) => {
+                            warn!(usb_writer, Module::Ir, "rmt decoding error : {:?}", err);
+                            error_registry::record_error(Subsystem::IrRx, &err);
+
+                            self.on_event(usb_writer, events, bindings, raw_event);
+                        },
+                    }
+                }
+
+                true
+            },
+            IrRxDispatchState::Error => false,
+        }
+    }
+
+    pub fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        self.nec_release.as_mut().is_some_and(|delay| delay.on_alarm(qq_alarm_id))
+    }
+
+    /// Learning commands (`ir learn`, `ir codes`), codes are kept in `ir_codes`.
+    pub fn on_command(&mut self, command: ConsoleCommand, usb_writer: &mut impl Write, ir_codes: &mut IrCodeStore<RomFlash>) -> bool {
+        match command {
+            ConsoleCommand::IrLearn { slot, name } => {
+                if (slot as u32) < ir_codes.slots() {
+                    self.learn_to = Some((slot, name));
+                    self.start_learning();
+                    log_line!(usb_writer, "ir learn", "press key of the remote")
+                } else {
+                    log_line!(usb_writer, "ir learn", "slot must be 0 - {}", ir_codes.slots() - 1)
+                }
+            },
+            ConsoleCommand::IrLearnStop => {
+                self.learn_to = None;
+                self.stop_learning();
+            },
+            ConsoleCommand::IrCodes => {
+                for slot in 0..ir_codes.slots() {
+                    match ir_codes.load(slot) {
+                        Ok(Some(code)) => log_line!(usb_writer, "ir code", "{} {} ({} pulses, {} us)", slot, ir_learning::name_as_str(&code.name), code.pulses.len(), code.duration()),
+                        Ok(None) => log_line!(usb_writer, "ir code", "{} empty", slot),
+                        Err(err) => log_line!(usb_writer, "ir code", "{} load failed ({:?})", slot, err),
+                    }
+                }
+            },
+            _ => return false,
+        }
+
+        true
+    }
+
+    /// Saves frame captured for `ir learn` command to its slot of `ir_codes`.
+    pub fn save_learned(&mut self, usb_writer: &mut impl Write, ir_codes: &mut IrCodeStore<RomFlash>) -> bool {
+        let Some(learned) = self.take_learned() else {
+            return false;
+        };
+        let Some((slot, name)) = self.learn_to.take() else {
+            return false;
+        };
+
+        match learned.map(|pulses| LearnedCode { name, pulses }) {
+            Ok(code) => match ir_codes.save(slot as u32, &code) {
+                Ok(()) => log_line!(usb_writer, "ir learn", "{} saved to slot {} ({} pulses, {} us)", ir_learning::name_as_str(&name), slot, code.pulses.len(), code.duration()),
+                Err(err) => {
+                    error_registry::record_error(Subsystem::IrRx, &err);
+                    log_line!(usb_writer, "ir learn", "save failed ({:?})", err)
+                },
+            },
+            Err(err) => log_line!(usb_writer, "ir learn", "{:?}", err),
+        }
+
+        true
+    }
+}
+
+impl<'c, 'i, 'a, W, Q, const N: usize> Machine<Context<'c, 'i, W, Q, N>> for IrRxDispatch<'a>
+where
+    W: Write + UsbWriter,
+    Q: TaggedQQAlarmQueue,
+{
+    fn name(&self) -> &'static str {
+        "ir rx"
+    }
+
+    fn owns(&self, owner: QQOwner) -> bool {
+        owner == QQOwner::IrRx
+    }
+
+    fn update(&mut self, context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        Self::update(self, context.usb_writer, &mut context.qq.owned(QQOwner::IrRx), context.events, &context.config.ir_bindings)
+            | self.save_learned(context.usb_writer, context.ir_codes)
+    }
+
+    fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        Self::on_alarm(self, qq_alarm_id)
+    }
+
+    fn on_command(&mut self, command: ConsoleCommand, context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        Self::on_command(self, command, context.usb_writer, context.ir_codes)
+    }
+}
\ No newline at end of file
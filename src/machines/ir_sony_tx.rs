@@ -0,0 +1,341 @@
+use core::fmt::Write;
+
+use esp_hal::{gpio::{Output, OutputPin}, peripheral::{Peripheral, PeripheralRef}, peripherals::RMT, rmt::PulseCode, timer::systimer::SystemTimer};
+
+use heapless::{Deque, Vec};
+
+use crate::{
+    error_registry::{self, Subsystem},
+    interrupts,
+    ir_learning::{self, IrCodeStore, MAX_PULSES},
+    log::{error, log_line, Module},
+    pac_utils::{gpio::PinNumber, rmt::{self as rmt_utils, RMTError, RmtCarrier, RmtTxChConfig, TxChannel}},
+    qq_alarm_queue::{QQAlarmQueue, QQOwner, TaggedQQAlarmQueue},
+    rom_flash::RomFlash,
+    scheduler::{Context, Machine},
+    sony_ir::{self, SonyIRCommand, SonyIRRawCommand},
+    usb_writer::UsbWriter
+};
+
+use super::{console::ConsoleCommand, Delay};
+
+
+
+// learned codes are checked against `ir_learning::MAX_TX_CODES` (see `IrSonyTx::send_learned`)
+const _: () = assert!(ir_learning::MAX_TX_CODES == rmt_utils::TX_MEM_CODES as usize, "learned code limit differs from rmt channel memory");
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IrSonyTxError {
+    /// command does not fit into rmt channel memory (47 bits at most), can happen only with `SonyIRCommand::Raw`, or
+    /// learned code is empty or does not fit into rmt channel memory (see `ir_learning::tx_codes`)
+    UnsendableCommand,
+    QueueFull,
+}
+
+
+#[derive(Debug, Clone)]
+enum Frame {
+    Sony(SonyIRRawCommand),
+    /// learned code, pulse lengths in us (see `ir_learning`)
+    Learned(Vec<u16, MAX_PULSES>),
+}
+
+#[derive(Debug, Clone)]
+struct QueuedCommand {
+    frame: Frame,
+    repeats: u8,
+}
+
+enum IrSonyTxState {
+    Idle,
+    /// frame is being sent, next frame can start when it was sent and `delay` is done (frame period is counted from the start)
+    Sending {
+        remaining: u8,
+        period: u64,
+        sent: bool,
+        delay: Delay,
+    },
+    /// rmt reported error, rest of the command is dropped and channel is reset when `delay` is done
+    Error {
+        delay: Delay,
+    },
+}
+
+/// Sends Sony SIRC commands and learned codes of other remotes (see `ir_learning`) using rmt channel 0, other machines
+/// queue commands with `send` and `send_learned`.
+///
+/// Each command is sent `repeats` times (remotes send at least 3 frames), frames start every 45 ms. Learned frames are
+/// repeated after `LEARNED_GAP` of silence.
+/// Rmt clock is configured by ir reciever (`IrRxDispatch`), so it has to be created first.
+pub struct IrSonyTx<'a, 'b, PIN, const QUEUE_SIZE: usize> {
+    rmt: PeripheralRef<'a, RMT>,
+    _pin: Output<'b, PIN>,
+    queue: Deque<QueuedCommand, QUEUE_SIZE>,
+    state: IrSonyTxState,
+}
+
+impl<'a, 'b, PIN, const QUEUE_SIZE: usize> IrSonyTx<'a, 'b, PIN, QUEUE_SIZE>
+where
+    PIN: OutputPin + PinNumber
+{
+    const CHANNEL: TxChannel = TxChannel::Ch0;
+
+    const FRAME_PERIOD: u64 = 45 * SystemTimer::TICKS_PER_SECOND / 1000;
+
+    /// rmt channel memory has space for 48 pulse codes (start pulse + one per bit)
+    const MAX_BITS: u8 = 47;
+
+    /// channel ticks per sony unit
+    const UNIT_TICKS: u16 = 7;
+
+    /// 63 ticks, 39.7 KHz (~ 40 KHz)
+    const CARRIER: RmtCarrier = RmtCarrier::from_khz(40, 22);
+
+    /// 85.6 us, 7 ticks = 599.2 us (~ 600 us, sony unit)
+    const SONY_CLOCK_DIV: u8 = 214;
+    /// 28 us (same as ir reciever), for learned codes
+    const LEARNED_CLOCK_DIV: u8 = 70;
+    const LEARNED_TICK_US: u16 = 28;
+    /// silence between repeated learned frames
+    const LEARNED_GAP: u64 = 40 * SystemTimer::TICKS_PER_SECOND / 1000;
+    /// pause after rmt error before next command (receiver has to see silence before valid frame)
+    const ERROR_PAUSE: u64 = 100 * SystemTimer::TICKS_PER_SECOND / 1000;
+
+
+    pub fn new(rmt: impl Peripheral<P = RMT> + 'a, pin: impl Peripheral<P = PIN> + 'b) -> Self {
+        let mut rmt = rmt.into_ref();
+
+        Self::config_channel(rmt.reborrow(), Self::SONY_CLOCK_DIV);
+
+        rmt_utils::tx_enable_interrupts(rmt.reborrow(), Self::CHANNEL);
+
+        let pin = rmt_utils::setup_output_pin(pin, Self::CHANNEL);
+
+        Self {
+            rmt,
+            _pin: pin,
+            queue: Deque::new(),
+            state: IrSonyTxState::Idle,
+        }
+    }
+
+    /// Queues `command` to be sent `repeats` times (nothing is sent when `repeats` is zero).
+    pub fn send(&mut self, command: SonyIRCommand, repeats: u8) -> Result<(), IrSonyTxError> {
+        let command = SonyIRRawCommand::from_command(command);
+
+        if command.bits == 0 || command.bits > Self::MAX_BITS {
+            return Err(IrSonyTxError::UnsendableCommand);
+        }
+
+        if repeats == 0 {
+            return Ok(());
+        }
+
+        self.queue.push_back(QueuedCommand { frame: Frame::Sony(command), repeats }).map_err(|_| IrSonyTxError::QueueFull)
+    }
+
+    /// Queues learned code (pulse lengths in us, starting and ending with mark) to be sent `repeats` times.
+    pub fn send_learned(&mut self, pulses: &[u16], repeats: u8) -> Result<(), IrSonyTxError> {
+        let pulses = Vec::from_slice(pulses).map_err(|_| IrSonyTxError::UnsendableCommand)?;
+
+        if pulses.is_empty() || ir_learning::tx_codes(pulses.len()) > ir_learning::MAX_TX_CODES {
+            return Err(IrSonyTxError::UnsendableCommand);
+        }
+
+        if repeats == 0 {
+            return Ok(());
+        }
+
+        self.queue.push_back(QueuedCommand { frame: Frame::Learned(pulses), repeats }).map_err(|_| IrSonyTxError::QueueFull)
+    }
+
+    /// Carrier is same for all frames, only channel clock differs.
+    fn config_channel(rmt: PeripheralRef<RMT>, clock_div: u8) {
+        rmt_utils::tx_config(rmt, Self::CHANNEL, RmtTxChConfig {
+            clock_div,
+            carrier: Some(Self::CARRIER),
+            idle_level: false,
+        });
+    }
+
+    /// Writes frame into channel memory, returns frame period.
+    fn load_frame(&mut self, frame: &Frame) -> Result<u64, RMTError> {
+        match frame {
+            Frame::Sony(command) => {
+                Self::config_channel(self.rmt.reborrow(), Self::SONY_CLOCK_DIV);
+
+                let pulse_codes = sony_ir::tx::pulses(*command).map(|(mark, space)| PulseCode {
+                    level1: true,
+                    length1: mark * Self::UNIT_TICKS,
+                    level2: false,
+                    length2: space * Self::UNIT_TICKS,
+                });
+                rmt_utils::tx_fifo_fill(self.rmt.reborrow(), Self::CHANNEL, pulse_codes)?;
+
+                Ok(Self::FRAME_PERIOD)
+            },
+            Frame::Learned(pulses) => {
+                Self::config_channel(self.rmt.reborrow(), Self::LEARNED_CLOCK_DIV);
+
+                let ticks = |us: u16| (us / Self::LEARNED_TICK_US).clamp(1, 0x7fff);
+                // zero length space after the last mark is the end marker, code ending with space needs extra end
+                // marker (see `ir_learning::tx_codes`)
+                let pulse_codes = pulses.chunks(2).map(|pair| PulseCode {
+                    level1: true,
+                    length1: ticks(pair[0]),
+                    level2: false,
+                    length2: pair.get(1).map_or(0, |space| ticks(*space)),
+                }).chain((pulses.len() % 2 == 0).then(PulseCode::default));
+                rmt_utils::tx_fifo_fill(self.rmt.reborrow(), Self::CHANNEL, pulse_codes)?;
+
+                let duration_us = pulses.iter().map(|pulse| *pulse as u64).sum::<u64>();
+                Ok(duration_us * SystemTimer::TICKS_PER_SECOND / 1_000_000 + Self::LEARNED_GAP)
+            },
+        }
+    }
+
+    fn start_frame(&mut self, qq: &mut impl QQAlarmQueue, remaining: u8, period: u64) {
+        rmt_utils::tx_start(self.rmt.reborrow(), Self::CHANNEL);
+
+        self.state = IrSonyTxState::Sending {
+            remaining,
+            period,
+            sent: false,
+            delay: Delay::start(qq, SystemTimer::now() + period),
+        };
+    }
+
+    pub fn update(&mut self, qq: &mut impl QQAlarmQueue, usb_writer: &mut impl Write) -> bool {
+        match &mut self.state {
+            IrSonyTxState::Idle => {
+                let Some(queued) = self.queue.pop_front() else {
+                    return false;
+                };
+
+                // frames are checked by `send` and `send_learned`, so they always fit
+                match self.load_frame(&queued.frame) {
+                    Ok(period) => self.start_frame(qq, queued.repeats - 1, period),
+                    Err(err) => {
+                        error!(usb_writer, Module::Ir, "rmt tx error : {:?}", err);
+                        error_registry::record_error(Subsystem::IrTx, &err);
+                    },
+                }
+
+                true
+            },
+            IrSonyTxState::Sending { remaining, period, sent, delay } => {
+                let period = *period;
+
+                let mut did_something = delay.retry(qq);
+
+                let pending_interrupts = interrupts::rmt_interrupt_get_and_clear(Self::CHANNEL.interrupts());
+
+                if let Some(err) = RMTError::from_interrupt_flags(pending_interrupts) {
+                    error!(usb_writer, Module::Ir, "rmt tx error : {:?}", err);
+                    error_registry::record_error(Subsystem::IrTx, &err);
+
+                    if let Delay::Waiting { qq_alarm_id } = *delay {
+                        let _ = qq.remove(qq_alarm_id);
+                    }
+
+                    self.state = IrSonyTxState::Error { delay: Delay::start(qq, SystemTimer::now() + Self::ERROR_PAUSE) };
+                    return true;
+                }
+
+                if !pending_interrupts.is_empty() {
+                    *sent = true;
+                    did_something = true;
+                }
+
+                if *sent && *delay == Delay::Done {
+                    match *remaining {
+                        0 => self.state = IrSonyTxState::Idle,
+                        // command is still in channel memory
+                        remaining => self.start_frame(qq, remaining - 1, period),
+                    }
+
+                    did_something = true;
+                }
+
+                did_something
+            },
+            IrSonyTxState::Error { delay } => {
+                let did_something = delay.retry(qq);
+
+                if *delay != Delay::Done {
+                    return did_something;
+                }
+
+                // channel is configured again by the next frame, interrupts which came after the error are stale
+                Self::config_channel(self.rmt.reborrow(), Self::SONY_CLOCK_DIV);
+                let _ = interrupts::rmt_interrupt_get_and_clear(Self::CHANNEL.interrupts());
+
+                self.state = IrSonyTxState::Idle;
+                true
+            },
+        }
+    }
+
+    pub fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        match &mut self.state {
+            IrSonyTxState::Sending { delay, .. } | IrSonyTxState::Error { delay } => delay.on_alarm(qq_alarm_id),
+            IrSonyTxState::Idle => false,
+        }
+    }
+
+    /// Send commands (`sony`, `ir play`), played codes are loaded from `ir_codes`.
+    pub fn on_command(&mut self, command: ConsoleCommand, usb_writer: &mut impl Write, ir_codes: &mut IrCodeStore<RomFlash>) -> bool {
+        match command {
+            ConsoleCommand::SonySend { command, repeats } => {
+                if let Err(err) = self.send(command, repeats) {
+                    log_line!(usb_writer, "ir tx", "{:?}", err);
+                }
+            },
+            ConsoleCommand::IrPlay { name, repeats } => {
+                match ir_codes.find(&name) {
+                    Ok(Some((_, code))) => {
+                        if let Err(err) = self.send_learned(&code.pulses, repeats) {
+                            log_line!(usb_writer, "ir tx", "{:?}", err);
+                        }
+                    },
+                    Ok(None) => log_line!(usb_writer, "ir play", "{} not learned", ir_learning::name_as_str(&name)),
+                    Err(err) => {
+                        error_registry::record_error(Subsystem::IrTx, &err);
+                        log_line!(usb_writer, "ir play", "load failed ({:?})", err);
+                    },
+                }
+            },
+            _ => return false,
+        }
+
+        true
+    }
+}
+
+impl<'c, 'i, 'a, 'b, PIN, const QUEUE_SIZE: usize, W, Q, const N: usize> Machine<Context<'c, 'i, W, Q, N>> for IrSonyTx<'a, 'b, PIN, QUEUE_SIZE>
+where
+    PIN: OutputPin + PinNumber,
+    W: Write + UsbWriter,
+    Q: TaggedQQAlarmQueue,
+{
+    fn name(&self) -> &'static str {
+        "ir sony tx"
+    }
+
+    fn owns(&self, owner: QQOwner) -> bool {
+        owner == QQOwner::IrSonyTx
+    }
+
+    fn update(&mut self, context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        Self::update(self, &mut context.qq.owned(QQOwner::IrSonyTx), context.usb_writer)
+    }
+
+    fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        Self::on_alarm(self, qq_alarm_id)
+    }
+
+    fn on_command(&mut self, command: ConsoleCommand, context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        Self::on_command(self, command, context.usb_writer, context.ir_codes)
+    }
+}
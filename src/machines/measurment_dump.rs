@@ -0,0 +1,196 @@
+use core::fmt::Write;
+
+use esp_hal::timer::systimer::SystemTimer;
+
+use crate::{
+    log::{log_fmt, log_line},
+    qq_alarm_queue::TaggedQQAlarmQueue,
+    scheduler::{Context, Machine},
+    snapshot::{SnapshotEncoder, SnapshotHeader, SnapshotRecord, HEADER_LEN, RECORD_LEN, TRAILER_LEN},
+    time,
+    usb_writer::{UsbOutputMode, UsbWriter},
+};
+
+use super::{console::ConsoleCommand, controller::Controller};
+
+
+
+struct DumpRun {
+    encoder: SnapshotEncoder,
+    header: Option<SnapshotHeader>,
+    /// records not written yet
+    remaining: u32,
+    /// system timer ticks of the first record
+    first: u64,
+    /// system timer ticks of the last written record, following records are newer
+    last: Option<u64>,
+}
+
+/// Streams last measurments of the controller to usb as binary snapshot (see `snapshot`), started from console (`dump <n>`).
+///
+/// Text line with number of records and length of the snapshot is written first, snapshot follows right after it.
+/// Snapshot is written in chunks which fit into usb writer buffer (nothing is dropped), text output of other machines
+/// is suppressed in the meantime (see `UsbWriter::set_text_suppressed`), so it does not end up inside the snapshot.
+/// Works only in text output mode (raw data would break framing).
+pub struct MeasurmentDump {
+    run: Option<DumpRun>,
+}
+
+impl MeasurmentDump {
+    /// records written to usb writer in one write
+    const CHUNK_RECORDS: usize = 8;
+
+
+    pub fn new() -> Self {
+        Self {
+            run: None,
+        }
+    }
+
+    /// Ends suppression of text output and the line of raw data of interrupted dump, so following text starts on a new line.
+    fn abort(usb_writer: &mut (impl Write + UsbWriter)) {
+        usb_writer.set_text_suppressed(false);
+        log_fmt!(usb_writer, "\n");
+    }
+
+    pub fn start<const N: usize>(&mut self, count: u32, usb_writer: &mut (impl Write + UsbWriter), controller: &Controller<N>) {
+        if self.run.is_some() {
+            log_line!(usb_writer, "dump", "already running");
+            return;
+        }
+
+        if usb_writer.output_mode() != UsbOutputMode::Text {
+            log_line!(usb_writer, "dump", "only in text output mode");
+            return;
+        }
+
+        let measurments = controller.measurments();
+        let count = count.min(measurments.len() as u32);
+
+        log_line!(usb_writer, "dump", "{} records, {} bytes", count, SnapshotEncoder::encoded_len(count));
+
+        // first record is the oldest of last `count` ones
+        let first = count.checked_sub(1).and_then(|i| measurments.rev().nth(i as usize)).map_or(0, |(at, _)| at);
+
+        self.run = Some(DumpRun {
+            encoder: SnapshotEncoder::new(),
+            header: Some(SnapshotHeader {
+                count,
+                ticks_per_second: SystemTimer::TICKS_PER_SECOND as u32,
+                // unix time of system timer tick 0
+                epoch_offset: time::unix_ms_at(0).unwrap_or(0),
+            }),
+            remaining: count,
+            first,
+            last: None,
+        });
+
+        usb_writer.set_text_suppressed(true);
+    }
+
+    pub fn stop(&mut self, usb_writer: &mut (impl Write + UsbWriter)) {
+        if let Some(run) = self.run.take() {
+            Self::abort(usb_writer);
+            log_line!(usb_writer, "dump", "stopped, {} records not written", run.remaining);
+        }
+    }
+
+    pub fn update<const N: usize>(&mut self, usb_writer: &mut (impl Write + UsbWriter), controller: &Controller<N>) -> bool {
+        let Some(run) = &mut self.run else {
+            return false;
+        };
+
+        let mut did_something = false;
+
+        if let Some(header) = run.header {
+            if usb_writer.free_space() < HEADER_LEN {
+                return false;
+            }
+
+            // cannot overflow, free space checked above
+            let _ = usb_writer.write(&run.encoder.header(&header));
+            run.header = None;
+            did_something = true;
+        }
+
+        while run.remaining != 0 {
+            let len = (run.remaining as usize).min(Self::CHUNK_RECORDS);
+
+            if usb_writer.free_space() < len * RECORD_LEN {
+                return did_something;
+            }
+
+            let overwritten = controller.measurments().next().is_some_and(|(at, _)| at > run.last.unwrap_or(run.first));
+
+            // measurments are stored in time order, new ones are appended while dump is running
+            let (first, last) = (run.first, run.last);
+            let measurments = controller.measurments()
+                .skip_while(|(at, _)| last.map_or(*at < first, |last| *at <= last))
+                .take(len);
+
+            let mut chunk = [0; Self::CHUNK_RECORDS * RECORD_LEN];
+            let mut chunk_len = 0;
+
+            for (at, measurment) in measurments {
+                let record = SnapshotRecord { at, co2: measurment.co2, temperature: measurment.temperature, humidity: measurment.humidity };
+                chunk[chunk_len..(chunk_len + RECORD_LEN)].copy_from_slice(&run.encoder.record(&record));
+                chunk_len += RECORD_LEN;
+                run.last = Some(at);
+            }
+
+            // oldest measurments were overwritten by new ones before they were written (dump is stalled by the host)
+            if overwritten || chunk_len == 0 {
+                Self::abort(usb_writer);
+                log_line!(usb_writer, "dump", "failed, measurments overwritten, {} records not written", run.remaining);
+                self.run = None;
+                return true;
+            }
+
+            // cannot overflow, free space checked above
+            let _ = usb_writer.write(&chunk[..chunk_len]);
+
+            run.remaining -= (chunk_len / RECORD_LEN) as u32;
+            did_something = true;
+        }
+
+        if usb_writer.free_space() >= TRAILER_LEN {
+            let _ = usb_writer.write(&run.encoder.trailer());
+            usb_writer.set_text_suppressed(false);
+            self.run = None;
+            did_something = true;
+        }
+
+        did_something
+    }
+}
+
+impl Default for MeasurmentDump {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'c, 'i, W, Q, const N: usize> Machine<Context<'c, 'i, W, Q, N>> for MeasurmentDump
+where
+    W: Write + UsbWriter,
+    Q: TaggedQQAlarmQueue,
+{
+    fn name(&self) -> &'static str {
+        "measurment dump"
+    }
+
+    fn update(&mut self, context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        Self::update(self, context.usb_writer, context.controller)
+    }
+
+    fn on_command(&mut self, command: ConsoleCommand, context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        match command {
+            ConsoleCommand::Dump { count } => self.start(count, context.usb_writer, context.controller),
+            // dump writes raw data, which would break framing
+            ConsoleCommand::DumpStop | ConsoleCommand::OutputMode(_) => self.stop(context.usb_writer),
+            _ => return false,
+        }
+
+        true
+    }
+}
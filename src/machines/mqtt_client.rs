@@ -0,0 +1,386 @@
+use core::fmt::Write;
+
+use esp_hal::timer::systimer::SystemTimer;
+
+use heapless::String;
+use smoltcp::{iface::SocketHandle, socket::tcp, wire::IpAddress};
+
+use crate::{
+    error_registry::{self, ErrorCode, Subsystem},
+    fixed_point::Milli,
+    invariants::invariant,
+    log::{error, info, warn, Module},
+    mqtt::{self, Connect, MqttError, Packet, PacketParser},
+    net::NetStack,
+    qq_alarm_queue::QQAlarmQueue,
+    record::Record,
+};
+
+use super::{controller::Controller, Delay, Ticker};
+
+
+
+/// Topics of published values, payload is decimal text (ppm, °C, %), as expected by home assistant mqtt sensors.
+#[derive(Debug, Clone, Copy)]
+pub struct MqttTopics {
+    pub co2: &'static str,
+    pub temperature: &'static str,
+    pub humidity: &'static str,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MqttClientConfig {
+    pub broker: [u8; 4],
+    pub port: u16,
+    pub client_id: &'static str,
+    pub username: Option<&'static str>,
+    pub password: Option<&'static str>,
+    pub topics: MqttTopics,
+    /// broker keeps the last value of retained topic for new subscribers
+    pub retain: bool,
+    pub keep_alive_secs: u16,
+    /// tcp handshake and connack have to finish within this time (in system timer ticks)
+    pub connect_timeout: u64,
+    /// delay before reconnecting after the first failure, doubled by each next failure up to `backoff_max` (in system timer ticks)
+    pub backoff_min: u64,
+    pub backoff_max: u64,
+}
+
+
+impl ErrorCode for MqttError {
+    fn error_code(&self) -> u16 {
+        match self {
+            MqttError::BufferTooSmall => 0x80,
+            MqttError::TooLong => 0x81,
+            MqttError::MalformedLength => 0x82,
+        }
+    }
+}
+
+impl ErrorCode for tcp::ConnectError {
+    fn error_code(&self) -> u16 {
+        0x83
+    }
+}
+
+impl ErrorCode for tcp::SendError {
+    fn error_code(&self) -> u16 {
+        0x84
+    }
+}
+
+impl ErrorCode for tcp::RecvError {
+    fn error_code(&self) -> u16 {
+        0x85
+    }
+}
+
+
+#[derive(Debug, Clone, Copy)]
+enum MqttClientState {
+    /// waiting until network stack is up (see `WifiReporter`)
+    Offline,
+    /// waiting for tcp handshake and connack, delay is the connect timeout
+    Connecting {
+        timeout: Delay,
+        connect_sent: bool,
+    },
+    Connected {
+        /// ticks twice per keep alive, ping is sent on each tick
+        keep_alive: Ticker,
+        /// pingresp was not received yet, broker is considered lost when it is not received until the next tick
+        ping_outstanding: bool,
+        /// `at` of the last published record
+        last_published_at: Option<u64>,
+    },
+    /// connection failed or was lost, connecting again when delay is done
+    Backoff(Delay),
+}
+
+/// Publishes co2, temperature and humidity of each new measurment to mqtt broker (qos 0, over tcp of `NetStack`).
+///
+/// Connects when the network stack is up, keep alive pings are timed by qq. Failed connection (refused, timeout,
+/// no pingresp) is retried after exponential backoff. Measurment which does not fit into tcp send buffer is not published.
+pub struct MqttClient {
+    tcp: SocketHandle,
+    config: MqttClientConfig,
+    parser: PacketParser,
+    backoff: u64,
+    state: MqttClientState,
+}
+
+impl MqttClient {
+    pub const ERROR_CODE_CONNECT_TIMEOUT: u16 = 0x86;
+    pub const ERROR_CODE_PING_TIMEOUT: u16 = 0x87;
+    pub const ERROR_CODE_CLOSED: u16 = 0x88;
+    /// connack with non zero return code, the code is printed to usb
+    pub const ERROR_CODE_REFUSED: u16 = 0x89;
+
+    /// enough for connect packet with credentials and for one publish packet
+    const PACKET_LEN: usize = 192;
+
+
+    /// `tcp` is handle of tcp socket in the `NetStack` passed to `update`.
+    pub fn new(tcp: SocketHandle, config: MqttClientConfig) -> Self {
+        Self {
+            tcp,
+            config,
+            parser: PacketParser::new(),
+            backoff: config.backoff_min,
+            state: MqttClientState::Offline,
+        }
+    }
+
+    fn cancel_alarm(&mut self, qq: &mut impl QQAlarmQueue) {
+        match self.state {
+            MqttClientState::Connecting { timeout: Delay::Waiting { qq_alarm_id }, .. }
+            | MqttClientState::Connected { keep_alive: Ticker::Running { qq_alarm_id, .. }, .. }
+            | MqttClientState::Backoff(Delay::Waiting { qq_alarm_id }) => {
+                invariant!(qq.remove(qq_alarm_id).is_ok(), "mqtt alarm not found in qq");
+            },
+            _ => {},
+        }
+    }
+
+    fn fail(&mut self, usb_writer: &mut impl Write, qq: &mut impl QQAlarmQueue, net: &mut NetStack, code: u16) {
+        error_registry::record(Subsystem::Mqtt, code);
+
+        self.cancel_alarm(qq);
+        net.tcp(self.tcp).abort();
+
+        warn!(usb_writer, Module::Mqtt, "disconnected (code 0x{:02x}), reconnecting in {} s", code, self.backoff / SystemTimer::TICKS_PER_SECOND);
+
+        self.state = MqttClientState::Backoff(Delay::start(qq, SystemTimer::now() + self.backoff));
+        self.backoff = (self.backoff * 2).min(self.config.backoff_max);
+    }
+
+    fn connect(&mut self, usb_writer: &mut impl Write, qq: &mut impl QQAlarmQueue, net: &mut NetStack) {
+        let [a, b, c, d] = self.config.broker;
+
+        self.parser.reset();
+
+        if let Err(err) = net.tcp_connect(self.tcp, IpAddress::v4(a, b, c, d), self.config.port) {
+            self.fail(usb_writer, qq, net, err.error_code());
+            return;
+        }
+
+        self.state = MqttClientState::Connecting {
+            timeout: Delay::start(qq, SystemTimer::now() + self.config.connect_timeout),
+            connect_sent: false,
+        };
+    }
+
+    /// Sends whole packet or nothing (`false` when it does not fit into send buffer).
+    fn send(socket: &mut tcp::Socket, packet: &[u8]) -> Result<bool, tcp::SendError> {
+        if socket.send_capacity() - socket.send_queue() < packet.len() {
+            return Ok(false);
+        }
+
+        socket.send_slice(packet).map(|_| true)
+    }
+
+    fn send_connect(&mut self, net: &mut NetStack) -> Result<(), u16> {
+        let connect = Connect {
+            client_id: self.config.client_id,
+            keep_alive_secs: self.config.keep_alive_secs,
+            username: self.config.username,
+            password: self.config.password,
+        };
+
+        let mut packet = [0; Self::PACKET_LEN];
+        let len = mqtt::encode_connect(&connect, &mut packet).map_err(|err| err.error_code())?;
+
+        // send buffer of new connection is empty
+        Self::send(net.tcp(self.tcp), &packet[..len]).map(|_| ()).map_err(|err| err.error_code())
+    }
+
+    /// Publishes all values of `record`, values are skipped (not queued) when they do not fit into send buffer.
+    fn publish(&mut self, net: &mut NetStack, record: &Record) -> Result<(), u16> {
+        let values = [
+            (self.config.topics.co2, record.co2, 1),
+            (self.config.topics.temperature, record.temperature, 2),
+            (self.config.topics.humidity, record.humidity, 1),
+        ];
+
+        for (topic, value, precision) in values {
+            let mut payload = String::<16>::new();
+            let _ = write!(payload, "{:.*}", precision, Milli::from(value));
+
+            let mut packet = [0; Self::PACKET_LEN];
+            let len = mqtt::encode_publish(topic, payload.as_bytes(), self.config.retain, &mut packet).map_err(|err| err.error_code())?;
+
+            Self::send(net.tcp(self.tcp), &packet[..len]).map_err(|err| err.error_code())?;
+        }
+
+        Ok(())
+    }
+
+    /// Parses all received bytes, returns packets which matter to the client.
+    fn receive(&mut self, net: &mut NetStack) -> Result<(Option<u8>, bool), u16> {
+        let socket = net.tcp(self.tcp);
+
+        let mut connack = None;
+        let mut pingresp = false;
+
+        while socket.can_recv() {
+            let mut bytes = [0; 64];
+            let len = socket.recv_slice(&mut bytes).map_err(|err| err.error_code())?;
+
+            for byte in &bytes[..len] {
+                match self.parser.push(*byte).map_err(|err| err.error_code())? {
+                    Some(Packet::ConnAck { return_code, .. }) => connack = Some(return_code),
+                    Some(Packet::PingResp) => pingresp = true,
+                    _ => {},
+                }
+            }
+        }
+
+        Ok((connack, pingresp))
+    }
+
+    /// `net` has to be updated before.
+    pub fn update<const N: usize>(&mut self, usb_writer: &mut impl Write, qq: &mut impl QQAlarmQueue, net: &mut NetStack, controller: &Controller<N>) -> bool {
+        if !net.is_up() {
+            if matches!(self.state, MqttClientState::Offline) {
+                return false;
+            }
+
+            // wifi reconnects by itself, backoff is not needed
+            self.cancel_alarm(qq);
+            net.tcp(self.tcp).abort();
+            self.state = MqttClientState::Offline;
+
+            return true;
+        }
+
+        match self.state {
+            MqttClientState::Offline => {
+                self.connect(usb_writer, qq, net);
+                true
+            },
+            MqttClientState::Connecting { mut timeout, mut connect_sent } => {
+                if timeout == Delay::Done {
+                    self.fail(usb_writer, qq, net, Self::ERROR_CODE_CONNECT_TIMEOUT);
+                    return true;
+                }
+
+                let socket = net.tcp(self.tcp);
+
+                if !socket.is_open() {
+                    // connection refused (reset) by the broker
+                    self.fail(usb_writer, qq, net, Self::ERROR_CODE_CLOSED);
+                    return true;
+                }
+
+                let mut did_something = timeout.retry(qq);
+
+                if !connect_sent && socket.may_send() {
+                    if let Err(code) = self.send_connect(net) {
+                        self.fail(usb_writer, qq, net, code);
+                        return true;
+                    }
+
+                    connect_sent = true;
+                    did_something = true;
+                }
+
+                match self.receive(net) {
+                    Ok((Some(0), _)) => {
+                        self.cancel_alarm(qq);
+
+                        info!(usb_writer, Module::Mqtt, "connected");
+
+                        let keep_alive = SystemTimer::TICKS_PER_SECOND * self.config.keep_alive_secs as u64;
+                        self.backoff = self.config.backoff_min;
+                        self.state = MqttClientState::Connected {
+                            keep_alive: Ticker::start(qq, keep_alive / 2, SystemTimer::now() + keep_alive / 2),
+                            ping_outstanding: false,
+                            last_published_at: None,
+                        };
+
+                        true
+                    },
+                    Ok((Some(return_code), _)) => {
+                        error!(usb_writer, Module::Mqtt, "connection refused (return code {})", return_code);
+                        self.fail(usb_writer, qq, net, Self::ERROR_CODE_REFUSED);
+                        true
+                    },
+                    Ok((None, _)) => {
+                        self.state = MqttClientState::Connecting { timeout, connect_sent };
+                        did_something
+                    },
+                    Err(code) => {
+                        self.fail(usb_writer, qq, net, code);
+                        true
+                    },
+                }
+            },
+            MqttClientState::Connected { mut keep_alive, mut ping_outstanding, mut last_published_at } => {
+                // broker closed or reset the connection
+                if net.tcp(self.tcp).state() != tcp::State::Established {
+                    self.fail(usb_writer, qq, net, Self::ERROR_CODE_CLOSED);
+                    return true;
+                }
+
+                let mut did_something = keep_alive.retry(qq);
+
+                match self.receive(net) {
+                    Ok((_, pingresp)) => ping_outstanding &= !pingresp,
+                    Err(code) => {
+                        self.fail(usb_writer, qq, net, code);
+                        return true;
+                    },
+                }
+
+                if keep_alive.take_tick() {
+                    if ping_outstanding {
+                        self.fail(usb_writer, qq, net, Self::ERROR_CODE_PING_TIMEOUT);
+                        return true;
+                    }
+
+                    match Self::send(net.tcp(self.tcp), &mqtt::PINGREQ) {
+                        Ok(sent) => ping_outstanding = sent,
+                        Err(err) => {
+                            self.fail(usb_writer, qq, net, err.error_code());
+                            return true;
+                        },
+                    }
+
+                    did_something = true;
+                }
+
+                if let Some(record) = controller.last_record() && last_published_at != Some(record.at) {
+                    last_published_at = Some(record.at);
+
+                    if let Err(code) = self.publish(net, &record) {
+                        self.fail(usb_writer, qq, net, code);
+                        return true;
+                    }
+
+                    did_something = true;
+                }
+
+                self.state = MqttClientState::Connected { keep_alive, ping_outstanding, last_published_at };
+                did_something
+            },
+            MqttClientState::Backoff(mut delay) => {
+                if delay == Delay::Done {
+                    self.connect(usb_writer, qq, net);
+                    return true;
+                }
+
+                let did_something = delay.retry(qq);
+                self.state = MqttClientState::Backoff(delay);
+                did_something
+            },
+        }
+    }
+
+    pub fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        match &mut self.state {
+            MqttClientState::Connecting { timeout: delay, .. } | MqttClientState::Backoff(delay) => delay.on_alarm(qq_alarm_id),
+            MqttClientState::Connected { keep_alive, .. } => keep_alive.on_alarm(qq_alarm_id),
+            MqttClientState::Offline => false,
+        }
+    }
+}
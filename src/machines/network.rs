@@ -0,0 +1,66 @@
+use core::fmt::Write;
+
+use crate::{
+    net::NetStack,
+    qq_alarm_queue::{QQOwner, TaggedQQAlarmQueue},
+    scheduler::{Context, Machine},
+    usb_writer::UsbWriter,
+};
+
+use super::{console::ConsoleCommand, http_server::HttpServer, mqtt_client::MqttClient, wifi_reporter::WifiReporter};
+
+
+
+/// Network stack and machines using it, scheduled as one machine (all of them borrow the stack in their updates).
+///
+/// Exists only when wifi is configured (see `WIFI_SSID` in `main`). Network outputs send only the latest record (see
+/// `WifiReporter`, `MqttClient`), they are not sinks of the controller.
+pub struct Network<'a> {
+    net: NetStack<'a>,
+    wifi_reporter: WifiReporter,
+    mqtt_client: MqttClient,
+    http_server: HttpServer,
+}
+
+impl<'a> Network<'a> {
+    pub fn new(net: NetStack<'a>, wifi_reporter: WifiReporter, mqtt_client: MqttClient, http_server: HttpServer) -> Self {
+        Self { net, wifi_reporter, mqtt_client, http_server }
+    }
+
+    pub fn start(&mut self, usb_writer: &mut impl Write, qq: &mut impl TaggedQQAlarmQueue) {
+        self.wifi_reporter.start(usb_writer, &mut qq.owned(QQOwner::WifiReporter), &mut self.net);
+        self.http_server.start(usb_writer, &mut self.net);
+    }
+}
+
+impl<'c, 'i, 'a, W, Q, const N: usize> Machine<Context<'c, 'i, W, Q, N>> for Network<'a>
+where
+    W: Write + UsbWriter,
+    Q: TaggedQQAlarmQueue,
+{
+    fn name(&self) -> &'static str {
+        "network"
+    }
+
+    fn owns(&self, owner: QQOwner) -> bool {
+        matches!(owner, QQOwner::WifiReporter | QQOwner::MqttClient | QQOwner::HttpServer)
+    }
+
+    fn update(&mut self, context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        let mut did_something = self.net.update();
+
+        did_something |= self.wifi_reporter.update(context.usb_writer, &mut context.qq.owned(QQOwner::WifiReporter), &mut self.net, context.controller);
+        did_something |= self.mqtt_client.update(context.usb_writer, &mut context.qq.owned(QQOwner::MqttClient), &mut self.net, context.controller);
+        did_something |= self.http_server.update(&mut context.qq.owned(QQOwner::HttpServer), &mut self.net, context.controller, context.config);
+
+        did_something
+    }
+
+    fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        self.wifi_reporter.on_alarm(qq_alarm_id) || self.mqtt_client.on_alarm(qq_alarm_id) || self.http_server.on_alarm(qq_alarm_id)
+    }
+
+    fn on_command(&mut self, command: ConsoleCommand, _context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        self.wifi_reporter.on_command(command)
+    }
+}
@@ -0,0 +1,271 @@
+use core::fmt::Write;
+
+use esp_hal::{
+    gpio::{Event, Input, InputPin, Pull},
+    peripheral::Peripheral,
+    timer::systimer::SystemTimer
+};
+
+use fugit::SecsDurationU32;
+
+use crate::{
+    async_io::{acquire_bus, first, i2c_transaction, sleep_until, wait_interrupt, AsyncIo, Either},
+    error_registry::{self, ErrorCode, Subsystem},
+    event_bus,
+    flight_recorder::{self, TraceEvent},
+    i2c_bus::{I2CBus, I2CBusUser},
+    interrupts::{self, GPIOInterruptStatus, InterruptSource},
+    invariants::invariant,
+    log::{error, info, warn, Module},
+    pac_utils::{gpio::PinNumber, i2c::I2CTransmissionError},
+    qq_alarm_queue::TaggedQQAlarmQueue,
+    sdc::{self, machines::DelayedGetError, Measurment, MeasurmentOutOfRange, SDCGetCommand, SDCReadResponseError, SDCSetCommand, SensorId},
+    sensirion_common,
+};
+
+use super::sdc_simple_measurment::{Recovery, ResumeAt, SDCSimpleMeasurment};
+
+
+
+pub struct SDCTaskConfig {
+    pub delta: SecsDurationU32,
+    pub delayed_get_delta: Option<u64>,
+    pub bus_user: I2CBusUser,
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SDCTaskError {
+    Set(I2CTransmissionError),
+    Get(DelayedGetError),
+    Response(SDCReadResponseError),
+    OutOfRange(MeasurmentOutOfRange),
+}
+
+impl From<SDCTaskError> for Recovery {
+    fn from(err: SDCTaskError) -> Recovery {
+        match err {
+            SDCTaskError::Set(err) => err.into(),
+            SDCTaskError::Get(err) => err.transmission_error().into(),
+            SDCTaskError::Response(err) => err.into(),
+            SDCTaskError::OutOfRange(_) => Recovery::Reject,
+        }
+    }
+}
+
+impl ErrorCode for SDCTaskError {
+    fn error_code(&self) -> u16 {
+        match self {
+            SDCTaskError::Set(err) => err.error_code(),
+            SDCTaskError::Get(err) => err.error_code(),
+            SDCTaskError::Response(err) => err.error_code(),
+            SDCTaskError::OutOfRange(err) => err.error_code(),
+        }
+    }
+}
+
+/// Failed step (name used in log) and its error.
+type StepError = (&'static str, SDCTaskError);
+
+
+/// constants and backoff shared with the machine
+type Sdc<'d, RDY> = SDCSimpleMeasurment<'d, RDY>;
+
+
+async fn set<W, Q: TaggedQQAlarmQueue>(io: &AsyncIo<'_, W, Q>, command: SDCSetCommand) -> Result<(), SDCTaskError> {
+    let (code, argument) = command.code_and_argument();
+    io.i2c_bus.with(|bus| sensirion_common::command_write(bus, sdc::DEFAULT_ADDRESS, code, argument));
+    i2c_transaction().await.map_err(SDCTaskError::Set)
+}
+
+/// Same as `sdc::machines::DelayedGet`, response is read by `response`.
+async fn delayed_get<W, Q: TaggedQQAlarmQueue>(io: &AsyncIo<'_, W, Q>, command: SDCGetCommand, delta: u64) -> Result<(), SDCTaskError> {
+    io.i2c_bus.with(|bus| sensirion_common::command_write(bus, sdc::DEFAULT_ADDRESS, command.code(), None));
+    i2c_transaction().await.map_err(|err| SDCTaskError::Get(DelayedGetError::Write(err)))?;
+
+    sleep_until(io, SystemTimer::now() + delta).await;
+
+    io.i2c_bus.with(|bus| sensirion_common::start_read_words(bus, sdc::DEFAULT_ADDRESS, command.response_words()));
+    i2c_transaction().await.map_err(|err| SDCTaskError::Get(DelayedGetError::Read(err)))
+}
+
+fn response<W, Q, T>(io: &AsyncIo<'_, W, Q>, read: impl FnOnce(&mut I2CBus) -> Result<T, SDCReadResponseError>) -> Result<T, SDCTaskError> {
+    io.i2c_bus.with(read).map_err(SDCTaskError::Response)
+}
+
+
+/// Soft reset, firmware version (sensor presence check), set delta and start. Bus has to be owned by `user`, it is
+/// released during boot delay (which is longer than bus heartbeat timeout) and owned again on return.
+async fn init<W: Write, Q: TaggedQQAlarmQueue>(io: &AsyncIo<'_, W, Q>, user: I2CBusUser, delta: SecsDurationU32, delayed_get_delta: u64) -> Result<(), StepError> {
+    set(io, SDCSetCommand::SoftReset).await.map_err(|err| ("soft reset", err))?;
+    io.i2c_bus.with(|bus| bus.release(user));
+    sleep_until(io, SystemTimer::now() + sdc::machines::Reset::BOOT_DELAY).await;
+    acquire_bus(io, user).await;
+
+    delayed_get(io, SDCGetCommand::FirmwareVersion, delayed_get_delta).await.map_err(|err| ("firmware version", err))?;
+    let version = response(io, |bus| sdc::read_response_firmware_version(bus)).map_err(|err| ("firmware version response", err))?;
+    io.usb_writer.with(|usb_writer| info!(usb_writer, Module::Sdc, "firmware version {}.{}", version.major, version.minor));
+
+    set(io, SDCSetCommand::SetDelta { delta }).await.map_err(|err| ("set delta", err))?;
+    set(io, SDCSetCommand::Start { pressure: None }).await.map_err(|err| ("start", err))
+}
+
+/// Reads measurment, bus has to be owned.
+async fn measurment<W, Q: TaggedQQAlarmQueue>(io: &AsyncIo<'_, W, Q>, delayed_get_delta: u64) -> Result<Measurment, StepError> {
+    delayed_get(io, SDCGetCommand::Measurment, delayed_get_delta).await.map_err(|err| ("measurment", err))?;
+    let measurment = response(io, |bus| sdc::read_response_measurment(bus)).map_err(|err| ("measurment response", err))?;
+    measurment.check_range().map_err(|err| ("measurment range check", SDCTaskError::OutOfRange(err)))?;
+
+    Ok(measurment)
+}
+
+/// Waits for data ready edge, returns system timer ticks of the edge (`None` when the edge was not captured). When
+/// there is no edge within interval and grace period, level of ready pin is checked (edge was missed).
+///
+/// After failed read data ready stays high (there is no new rising edge), so with `retry` high level is enough.
+async fn wait_ready<W, Q, RDY>(io: &AsyncIo<'_, W, Q>, ready_pin: &Input<'_, RDY>, timeout: u64, retry: bool) -> Option<u64>
+where
+    W: Write,
+    Q: TaggedQQAlarmQueue,
+    RDY: InputPin + PinNumber,
+{
+    if !(retry && ready_pin.is_high()) {
+        loop {
+            let edge = wait_interrupt(InterruptSource::Gpio, || {
+                (!interrupts::gpio_interrupt_get_and_clear(GPIOInterruptStatus::pin(RDY::NUMBER)).is_empty()).then_some(())
+            });
+
+            match first(edge, sleep_until(io, SystemTimer::now() + timeout)).await {
+                Either::First(()) => break,
+                Either::Second(()) if ready_pin.is_high() => {
+                    io.usb_writer.with(|usb_writer| warn!(usb_writer, Module::Sdc, "data ready interrupt missed"));
+                    error_registry::record(Subsystem::Sdc, Sdc::<RDY>::ERROR_CODE_MISSED_READY);
+                    break;
+                },
+                // sensor is late (or ready line is disconnected), keep waiting
+                Either::Second(()) => {},
+            }
+        }
+    }
+
+    interrupts::gpio_interrupt_get_and_clear(GPIOInterruptStatus::pin(RDY::NUMBER));
+
+    // last rising edge belongs to this measurment, older are from measurments which were not read
+    interrupts::gpio_capture_last_rising(RDY::NUMBER)
+}
+
+
+/// Scd30 measurment written as async task (proof of concept of `executor` and `async_io`), replaces `SDCSimpleMeasurment`
+/// with `async-sdc` feature.
+///
+/// Same steps as `SDCSimpleMeasurment` in straight-line code: boot delay, soft reset and firmware version, set delta, start,
+/// then wait for data ready and read measurment in a loop. Errors are recovered the same way (bus recovery, exponential
+/// backoff, continue where the error happened, full re-init after `SDCSimpleMeasurment::MAX_RETRIES` errors, then the task
+/// stops).
+///
+/// Not ported yet: init settings, pressure compensation, interval change, stop / start, forced recalibration,
+/// `ReadyMode::Poll` (ready pin is required) and watchdog heartbeat.
+pub async fn run<W, Q, RDY>(io: &AsyncIo<'_, W, Q>, ready_pin: impl Peripheral<P = RDY>, config: SDCTaskConfig)
+where
+    W: Write,
+    Q: TaggedQQAlarmQueue,
+    RDY: InputPin + PinNumber,
+{
+    let mut ready_pin = Input::new(ready_pin, Pull::None);
+    ready_pin.listen(Event::RisingEdge);
+    invariant!(interrupts::gpio_capture_enable(RDY::NUMBER), "no free gpio capture slot for sdc ready pin");
+    interrupts::gpio_capture_clear(RDY::NUMBER);
+
+    let delayed_get_delta = config.delayed_get_delta.unwrap_or(Sdc::<RDY>::DEFAULT_DELAYED_GET_DELTA);
+    let ready_timeout = config.delta.to_secs() as u64 * SystemTimer::TICKS_PER_SECOND + Sdc::<RDY>::READY_POLL_GRACE;
+
+    sleep_until(io, SystemTimer::now() + SystemTimer::TICKS_PER_SECOND * 5 / 2).await;
+
+    let mut consecutive_errors = 0;
+    let mut resume = ResumeAt::Init;
+
+    loop {
+        let result = match resume {
+            ResumeAt::Init => {
+                acquire_bus(io, config.bus_user).await;
+                init(io, config.bus_user, config.delta, delayed_get_delta).await.map(|()| None)
+            },
+            ResumeAt::Measuring => {
+                let at = wait_ready(io, &ready_pin, ready_timeout, consecutive_errors != 0).await;
+                acquire_bus(io, config.bus_user).await;
+                measurment(io, delayed_get_delta).await.map(|measurment| Some((measurment, at)))
+            },
+        };
+
+        let (step, err) = match result {
+            Ok(measurment) => {
+                io.i2c_bus.with(|bus| bus.release(config.bus_user));
+
+                match measurment {
+                    Some((measurment, at)) => {
+                        flight_recorder::record(TraceEvent::SdcMeasurment, (measurment.co2 / 1_000) as u32);
+                        io.events.with(|events| events.publish(event_bus::Event::Measurment { sensor: SensorId::PRIMARY, measurment, at }));
+                        consecutive_errors = 0;
+                    },
+                    None => io.events.with(|events| events.publish(event_bus::Event::SensorStarted(SensorId::PRIMARY))),
+                }
+
+                resume = ResumeAt::Measuring;
+                continue;
+            },
+            Err((_, SDCTaskError::Response(err))) if err.is_value_error() => {
+                // measurment is dropped, sensor keeps measuring
+                io.i2c_bus.with(|bus| bus.release(config.bus_user));
+                io.usb_writer.with(|usb_writer| warn!(usb_writer, Module::Sdc, "invalid measurment ({:?})", err));
+                error_registry::record_error(Subsystem::Sdc, &err);
+                continue;
+            },
+            Err((step, err @ SDCTaskError::OutOfRange(_))) => {
+                // not published (does not reset staleness), repeated rejections end in initialization or stop same as
+                // i2c errors
+                io.events.with(|events| events.publish(event_bus::Event::MeasurmentRejected { sensor: SensorId::PRIMARY }));
+                (step, err)
+            },
+            Err(step_error) => step_error,
+        };
+
+        io.usb_writer.with(|usb_writer| error!(usb_writer, Module::Sdc, "error after {}: {:?}", step, err));
+        error_registry::record_error(Subsystem::Sdc, &err);
+        io.events.with(|events| events.publish(event_bus::Event::SensorError(SensorId::PRIMARY)));
+
+        if consecutive_errors > Sdc::<RDY>::MAX_RETRIES {
+            io.i2c_bus.with(|bus| bus.release(config.bus_user));
+            io.usb_writer.with(|usb_writer| error!(usb_writer, Module::Sdc, "task stopped after {} errors", consecutive_errors + 1));
+            flight_recorder::record(TraceEvent::SdcFailed, 0);
+            return;
+        }
+
+        consecutive_errors += 1;
+        flight_recorder::record(TraceEvent::SdcRetry, consecutive_errors as u32);
+
+        // retries did not help, last attempt initializes the sensor again
+        if consecutive_errors > Sdc::<RDY>::MAX_RETRIES {
+            resume = ResumeAt::Init;
+        }
+        let backoff = Sdc::<RDY>::retry_backoff(consecutive_errors);
+
+        let bus_state = io.i2c_bus.with(|bus| {
+            let bus_state = Recovery::from(err).apply(bus);
+            bus.release(config.bus_user);
+            bus_state
+        });
+
+        io.usb_writer.with(|usb_writer| warn!(
+            usb_writer,
+            Module::Sdc,
+            "retry {} / {} in {} ms from {} ({})",
+            consecutive_errors,
+            Sdc::<RDY>::MAX_RETRIES + 1,
+            backoff / (SystemTimer::TICKS_PER_SECOND / 1_000),
+            resume.name(),
+            bus_state,
+        ));
+
+        sleep_until(io, SystemTimer::now() + backoff).await;
+    }
+}
@@ -1,198 +1,953 @@
-use core::fmt::Write;
+use core::{fmt::{Debug, Write}, num::NonZeroU16};
 
 use esp_hal::{
-    clock::Clocks,
-    gpio::{Event, Input, InputPin, OutputOpenDrain, OutputPin, Pull},
-    interrupt::Priority,
-    peripheral::{Peripheral, PeripheralRef},
-    peripherals::I2C0,
+    gpio::{Event, Input, InputPin, Pull},
+    peripheral::Peripheral,
     timer::systimer::SystemTimer
 };
 
-use fugit::{RateExtU32, SecsDurationU32};
+use fugit::SecsDurationU32;
 
 use crate::{
+    error_registry::{self, ErrorCode, Subsystem},
+    event_bus::{self, EventBus},
+    fixed_point::Milli,
+    flight_recorder::{self, TraceEvent},
+    heartbeat::{self, Heartbeat},
+    i2c_bus::{I2CBusUser, TransactionBus},
     interrupts::{self, GPIOInterruptStatus},
-    qq_alarm_queue::QQAlarmQueue,
+    invariants::invariant,
+    log::{error, info, log_line, warn, Module},
+    measurment_interval::{self, IntervalError, IntervalObserver},
+    pac_utils::{gpio::PinNumber, i2c::I2CTransmissionError},
+    qq_alarm_queue::{QQAlarmQueue, QQOwner, TaggedQQAlarmQueue},
+    scheduler::{Context, Machine},
     sdc::{
         self,
-        machines::{DelayedGet as SDCDelayedGet, DelayedGetError, Set as SDCSet, State as SDCState},
+        machines::{DelayedGet as SDCDelayedGet, DelayedGetError, Reset as SDCReset, Set as SDCSet},
+        SDCCommandError,
         SDCGetCommand,
-        SDCSetCommand
+        SDCReadResponseError,
+        SDCSetCommand,
+        SensorId
     },
-    pac_utils::i2c::{self as i2c_utils, I2CTransmissionError}
+    usb_writer::UsbWriter,
 };
 
-use super::{controller::Controller, Delay};
+use super::{console::ConsoleCommand, status_led::{LedPattern, LedPatternRequest}, Delay, State as SDCState};
 
 
 
+/// How machine finds out that measurment is ready.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReadyMode {
+    /// data ready pin interrupt, is ready command is sent only when interrupt is late
+    Pin,
+    /// is ready command every `period` (in system timer ticks), for wiring without data ready line (ready pin is not used)
+    Poll { period: u64 },
+}
+
 pub struct SDCSimpleMeasurmentConfig {
     pub delta: SecsDurationU32, // TODO: unit, constraints
     pub delayed_get_delta: Option<u64>, // TODO: unit
+    pub bus_user: I2CBusUser,
+    /// i2c address of the sensor, `sdc::DEFAULT_ADDRESS` unless there is an address translator on the bus
+    pub address: u8,
+    /// published with measurments (see `event_bus::Event::Measurment`)
+    pub sensor: SensorId,
+    pub ready_mode: ReadyMode,
+    /// following settings are set during init, `None` keeps setting stored in the sensor
+    pub automatic_self_calibration: Option<bool>,
+    /// in 10^-2 °C (subtracted from measured temperature)
+    pub temperature_offset: Option<u16>,
+    /// in m above sea level (ignored by sensor when pressure compensation is used)
+    pub altitude: Option<u16>,
+}
+
+
+/// Settings set (and read back) during init, in this order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(in crate::machines) enum InitSetting {
+    AutomaticSelfCalibration,
+    TemperatureOffset,
+    AltitudeCompensation,
+}
+
+impl InitSetting {
+    const ALL: [InitSetting; 3] = [InitSetting::AutomaticSelfCalibration, InitSetting::TemperatureOffset, InitSetting::AltitudeCompensation];
+
+
+    fn name(&self) -> &'static str {
+        match self {
+            InitSetting::AutomaticSelfCalibration => "automatic self calibration",
+            InitSetting::TemperatureOffset => "temperature offset",
+            InitSetting::AltitudeCompensation => "altitude compensation",
+        }
+    }
+
+    fn get_command(&self) -> SDCGetCommand {
+        match self {
+            InitSetting::AutomaticSelfCalibration => SDCGetCommand::GetAutomaticSelfCalibration,
+            InitSetting::TemperatureOffset => SDCGetCommand::GetTemperatureOffset,
+            InitSetting::AltitudeCompensation => SDCGetCommand::GetAltitudeCompensation,
+        }
+    }
+}
+
+/// Where machine continues after error recovery backoff.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(in crate::machines) enum ResumeAt {
+    /// sensor keeps measuring, only waiting for data ready again
+    Measuring,
+    /// from soft reset (step 2.)
+    Init,
+}
+
+impl ResumeAt {
+    pub(in crate::machines) fn name(&self) -> &'static str {
+        match self {
+            ResumeAt::Measuring => "measuring",
+            ResumeAt::Init => "init",
+        }
+    }
+}
+
+/// How the bus is handled after error, chosen by error type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(in crate::machines) enum Recovery {
+    /// sensor did not acknowledge (e.g. it is busy), bus is idle and command is only sent again after backoff
+    Retry,
+    /// bus is recovered (see `I2CBus::recover`) after arbitration lost, timeouts and corrupted responses
+    RecoverBus,
+    /// response was valid but measurment is out of range (see `Measurment::check_range`), bus is idle and the next
+    /// measurment is read after backoff
+    Reject,
+}
+
+impl Recovery {
+    /// Bus has to be owned, returns state of the bus for log.
+    pub(in crate::machines) fn apply(&self, bus: &mut impl TransactionBus) -> &'static str {
+        match self {
+            Recovery::Retry => "nack, bus not recovered",
+            Recovery::RecoverBus if bus.recover() => "bus free",
+            Recovery::RecoverBus => "bus still held",
+            Recovery::Reject => "measurment rejected, bus not recovered",
+        }
+    }
+}
+
+impl From<I2CTransmissionError> for Recovery {
+    fn from(err: I2CTransmissionError) -> Recovery {
+        if err.needs_bus_recovery() { Recovery::RecoverBus } else { Recovery::Retry }
+    }
+}
+
+impl From<SDCReadResponseError> for Recovery {
+    fn from(_: SDCReadResponseError) -> Recovery {
+        Recovery::RecoverBus
+    }
 }
 
 #[derive(Debug)]
 pub(in crate::machines) enum SDCSimpleMeasurmentState {
     None,
     BootDelay(Delay),
+    Reset(SDCReset),
+    InitSet {
+        setting: InitSetting,
+        sdc_write: SDCSet,
+    },
+    InitGet {
+        setting: InitSetting,
+        sdc_delayed_get: SDCDelayedGet,
+    },
     SetDelta(SDCSet),
+    /// read back of interval after set delta
+    GetDelta(SDCDelayedGet),
     Start(SDCSet),
     WaitReady,
+    /// is ready command, data ready interrupt did not come in time (see `READY_POLL_GRACE`)
+    ReadyPoll(SDCDelayedGet),
+    ForcedRecalibration(SDCSet),
     Measurment(SDCDelayedGet),
+    StopMeasurment(SDCSet),
+    /// waiting after error before `resume`
+    Recovery {
+        resume: ResumeAt,
+        backoff: Delay,
+    },
+    /// measurment was stopped by `stop`, can be started again with `start`
+    Stopped,
     Error,
 }
 
 
 /// 1. boot delay
-/// 2. set delta
-/// 3. start
-/// 4. wait
-/// 5. is ready - if not go to 4.
-/// 6. measurment - then go to 4.
-/// 
-/// Generic over sda, scl and ready pin types, so user can use either `GpioPin` or `AnyPin` or references to them.
-pub struct SDCSimpleMeasurment<'a, 'b, 'c, 'd, SDA, SCL, RDY> {
-    i2c: PeripheralRef<'a, I2C0>,
-    scl_pin: OutputOpenDrain<'b, SCL>, // TODO: no need to hold this pins, remove and use phantom data for 'b, 'c, 'd lifetimes so this struct still acts like it holds this pins ??
-    sda_pin: OutputOpenDrain<'c, SDA>,
+/// 2. soft reset and read firmware version (sensor presence check)
+/// 3. set and read back each configured init setting (automatic self calibration, temperature offset, altitude)
+/// 4. set delta and read it back
+/// 5. start
+/// 6. wait for data ready interrupt
+/// 7. is ready - if not go to 6.
+/// 8. measurment - then go to 6.
+///
+/// Edge of data ready can be missed (pin is already high when listening starts, flaky interrupt), so when there is no
+/// measurment within interval and `READY_POLL_GRACE`, level of ready pin is checked and, when it is low, is ready command is
+/// sent (in step 6.). Without data ready line (`ReadyMode::Poll`) only is ready command is sent, periodically.
+///
+/// When pressure compensation from `Controller` changes, start command is sent again (in step 6.) with the new pressure.
+/// When measurment interval changes (see `IntervalObserver`), set delta and start commands are sent again (in step 6.).
+/// Forced recalibration (see `force_recalibration`) is also sent in step 6.
+/// Measurment can be stopped (see `stop`) and started again (from step 1.) without reboot.
+///
+/// After error the bus is recovered (see `Recovery`, nack only retries) and machine waits with exponential backoff, then it continues
+/// where the error happened: failed measurment is read again (step 6.), any other error starts again from step 2.
+/// After `MAX_RETRIES` errors in a row sensor is fully re-initialized (step 2.) once more, when that fails too machine stays in error.
+/// Counter is reset by successful measurment, every error is counted by `Controller::on_sensor_error`.
+///
+/// I2C bus is shared with other machines, it is acquired only for the duration of each command. Machine is generic over the
+/// bus (see `TransactionBus`), primary sensor uses shared `I2CBus` (see `Machine` impl), second sensor has its own bus
+/// (see `SecondarySDC`).
+///
+/// Machine has to get back to an idle state (waiting for data ready, stopped, error) within `HEARTBEAT_TIMEOUT`
+/// (boot delay and all commands), otherwise it is reported by watchdog.
+///
+/// Generic over ready pin type, so user can use either `GpioPin` or `AnyPin` or references to them (any free pin in `ReadyMode::Poll`).
+pub struct SDCSimpleMeasurment<'d, RDY> {
     ready_pin: Input<'d, RDY>,
     delta: SecsDurationU32,
+    delta_changed: bool,
     delayed_get_delta: u64,
+    bus_user: I2CBusUser,
+    address: u8,
+    sensor: SensorId,
+    /// `Heartbeat::Sdc` of primary sensor, `Heartbeat::SdcSecondary` of others
+    heartbeat: Heartbeat,
+    ready_mode: ReadyMode,
+    pressure: Option<NonZeroU16>,
+    automatic_self_calibration: Option<bool>,
+    temperature_offset: Option<u16>,
+    altitude: Option<u16>,
+    /// command waiting to be sent (see `force_recalibration`)
+    pending_recalibration: Option<SDCSetCommand>,
+    /// stop measurment command is sent when transaction in progress is done (see `stop`)
+    stop_requested: bool,
+    /// held in `Error` state (machine is not restarted)
+    i2c_error: LedPatternRequest,
+    /// errors since last successful measurment
+    consecutive_errors: u8,
+    /// system timer ticks of data ready edge of measurment being read, `None` when there is no captured edge (ready was
+    /// found by poll)
+    ready_at: Option<u64>,
+    /// fallback for missed data ready interrupt, `None` when sensor is not measuring
+    ready_poll: Option<Delay>,
     state: SDCSimpleMeasurmentState,
 }
 
-impl<'a, 'b, 'c, 'd, SDA, SCL, RDY> SDCSimpleMeasurment<'a, 'b, 'c, 'd, SDA, SCL, RDY>
+impl<'d, RDY> SDCSimpleMeasurment<'d, RDY>
 where
-    SDA: OutputPin + InputPin,
-    SCL: OutputPin + InputPin,
-    RDY: InputPin,
+    RDY: InputPin + PinNumber,
 {
     /// from sdc documentation: delay between i2c write and read should be at least 3ms
     /// default delay here is 5ms
     pub const DEFAULT_DELAYED_GET_DELTA: u64 = SystemTimer::TICKS_PER_SECOND / 200; // TODO: try lowering this
+    /// boot delay is 2.5 s, init commands take few ms each
+    pub const HEARTBEAT_TIMEOUT: u64 = SystemTimer::TICKS_PER_SECOND * 10;
+    /// retries continuing where the error happened, next error is followed by full re-init
+    pub const MAX_RETRIES: u8 = 3;
+    /// backoff after first error, doubled after each next one
+    pub(in crate::machines) const RETRY_BACKOFF_MIN: u64 = SystemTimer::TICKS_PER_SECOND / 5;
+    /// has to be well below `HEARTBEAT_TIMEOUT` (backoff is followed by init commands)
+    const RETRY_BACKOFF_MAX: u64 = SystemTimer::TICKS_PER_SECOND * 5;
+    /// sensor interval is not exact, ready is polled only when measurment is late by this
+    pub(in crate::machines) const READY_POLL_GRACE: u64 = SystemTimer::TICKS_PER_SECOND * 2;
+
+    /// data ready was found by poll, not by interrupt
+    pub const ERROR_CODE_MISSED_READY: u16 = 0x07;
+    /// interval read back after set delta differs from the one which was set
+    pub const ERROR_CODE_DELTA_MISMATCH: u16 = 0x09;
 
 
     pub fn new(
-        i2c: impl Peripheral<P = I2C0> + 'a,
-        scl_pin: impl Peripheral<P = SCL> + 'b,
-        sda_pin: impl Peripheral<P = SDA> + 'c,
         ready_pin: impl Peripheral<P = RDY> + 'd,
         config: SDCSimpleMeasurmentConfig,
-        clocks: &Clocks,
     ) -> Self {
-        let mut i2c = i2c.into_ref();
-
-        i2c_utils::setup(i2c.reborrow(), 50u32.kHz(), clocks);
-
-        let (scl_pin, sda_pin) = i2c_utils::setup_pins(scl_pin, sda_pin);
-
-        // TODO: if ready is already high interrupt is not fired
+        // when ready is already high interrupt is not fired, measurment is then found by ready poll
         let mut ready_pin = Input::new(ready_pin, Pull::None);
-        ready_pin.listen(Event::RisingEdge);
+        if config.ready_mode == ReadyMode::Pin {
+            ready_pin.listen(Event::RisingEdge);
+            invariant!(interrupts::gpio_capture_enable(RDY::NUMBER), "no free gpio capture slot for sdc ready pin");
+        }
+
+        let heartbeat = if config.sensor == SensorId::PRIMARY { Heartbeat::Sdc } else { Heartbeat::SdcSecondary };
+        heartbeat::register(heartbeat, Self::HEARTBEAT_TIMEOUT);
 
         Self {
-            i2c,
-            scl_pin,
-            sda_pin,
             ready_pin,
             delta: config.delta,
+            delta_changed: false,
             delayed_get_delta: config.delayed_get_delta.unwrap_or(Self::DEFAULT_DELAYED_GET_DELTA),
+            bus_user: config.bus_user,
+            address: config.address,
+            sensor: config.sensor,
+            heartbeat,
+            ready_mode: config.ready_mode,
+            pressure: None,
+            automatic_self_calibration: config.automatic_self_calibration,
+            temperature_offset: config.temperature_offset,
+            altitude: config.altitude,
+            pending_recalibration: None,
+            stop_requested: false,
+            i2c_error: LedPatternRequest::new(LedPattern::I2CError),
+            consecutive_errors: 0,
+            ready_at: None,
+            ready_poll: None,
             state: SDCSimpleMeasurmentState::None,
         }
     }
 
-    /// This does not enable GPIO interrupt needed for ready pin, users should enable this interrupt themselves.
-    pub fn enable_interrupt(&mut self) {
-        interrupts::i2c_interrupt_enable(Some(Priority::Priority5));
+    /// Should be called only when machine is not running (see `is_stopped`).
+    pub fn start(&mut self, qq: &mut impl QQAlarmQueue) {
+        self.cancel_ready_poll(qq);
+        interrupts::gpio_capture_clear(RDY::NUMBER);
+        self.stop_requested = false;
+        self.i2c_error.set(false);
+        self.consecutive_errors = 0;
+        self.state = SDCSimpleMeasurmentState::BootDelay(Delay::start(qq, SystemTimer::now() + SystemTimer::TICKS_PER_SECOND * 5 / 2));
     }
 
-    pub fn start(&mut self, qq: &mut impl QQAlarmQueue) {
-        let qq_alarm_id = qq.add(SystemTimer::now() + SystemTimer::TICKS_PER_SECOND * 5 / 2).unwrap();
+    /// `true` when `start` can be called (machine was not started yet, was stopped or failed).
+    pub fn is_stopped(&self) -> bool {
+        matches!(self.state, SDCSimpleMeasurmentState::None | SDCSimpleMeasurmentState::Stopped | SDCSimpleMeasurmentState::Error)
+    }
+
+    /// Stops continuous measurment, stop command is sent to the sensor when it is not in the middle of another transaction.
+    ///
+    /// When sensor was not started yet (or is in error) machine is stopped immediately.
+    pub fn stop(&mut self, qq: &mut impl QQAlarmQueue) {
+        match self.state {
+            SDCSimpleMeasurmentState::BootDelay(delay) |
+            SDCSimpleMeasurmentState::Recovery { backoff: delay, .. } => {
+                if let Delay::Waiting { qq_alarm_id } = delay {
+                    let _ = qq.remove(qq_alarm_id);
+                }
+
+                self.cancel_ready_poll(qq);
+                self.state = SDCSimpleMeasurmentState::Stopped;
+            },
+            SDCSimpleMeasurmentState::None |
+            SDCSimpleMeasurmentState::Error => self.state = SDCSimpleMeasurmentState::Stopped,
+            SDCSimpleMeasurmentState::StopMeasurment(_) |
+            SDCSimpleMeasurmentState::Stopped => {},
+            // transaction in progress is finished first (machine gets to `WaitReady`)
+            _ => self.stop_requested = true,
+        }
+    }
+
+    /// Changes measurment interval, set delta (and start) is sent when no measurment is being read and the interval is
+    /// read back. Interval has to be within `MIN_INTERVAL_SECS` and `MAX_INTERVAL_SECS`.
+    ///
+    /// Other machines with timing derived from the interval are not updated, `measurment_interval::change_interval`
+    /// should be used instead (it calls this through `IntervalObserver`).
+    pub fn set_interval(&mut self, seconds: u32) -> Result<(), IntervalError> {
+        let interval = SecsDurationU32::secs(seconds);
+
+        if !measurment_interval::is_valid_interval(interval) {
+            return Err(IntervalError::OutOfRange);
+        }
+
+        self.delta = interval;
+        self.delta_changed = true;
+
+        Ok(())
+    }
+
+    /// Recalibrates sensor to reference co2 concentration `ppm`, command is sent when no measurment is being read.
+    ///
+    /// Sensor should be running for at least 2 minutes in stable environment with known co2 concentration (e.g. fresh air ~ 420 ppm).
+    pub fn force_recalibration(&mut self, ppm: u16) -> Result<(), SDCCommandError> {
+        self.pending_recalibration = Some(SDCSetCommand::forced_recalibration(ppm)?);
+        Ok(())
+    }
+
+    fn init_command(&self, setting: InitSetting) -> Option<SDCSetCommand> {
+        match setting {
+            InitSetting::AutomaticSelfCalibration => self.automatic_self_calibration.map(SDCSetCommand::SetAutomaticSelfCalibration),
+            InitSetting::TemperatureOffset => self.temperature_offset.map(|offset| SDCSetCommand::SetTemperatureOffset { offset }),
+            InitSetting::AltitudeCompensation => self.altitude.map(|altitude| SDCSetCommand::SetAltitudeCompensation { altitude }),
+        }
+    }
+
+    /// Sends first configured init setting starting from `InitSetting::ALL[from]`, set delta is sent when there is none left.
+    ///
+    /// Bus has to be owned.
+    fn start_init(&mut self, bus: &mut impl TransactionBus, from: usize) {
+        let next = InitSetting::ALL.iter().skip(from).find_map(|setting| self.init_command(*setting).map(|command| (*setting, command)));
+
+        self.state = match next {
+            Some((setting, command)) => SDCSimpleMeasurmentState::InitSet {
+                setting,
+                sdc_write: SDCSet::start(bus, self.address, command),
+            },
+            None => SDCSimpleMeasurmentState::SetDelta(SDCSet::start(bus, self.address, SDCSetCommand::SetDelta { delta: self.delta })),
+        };
+    }
+
+    /// Next measurment is expected within interval from now (sensor was started or measurment was read).
+    fn arm_ready_poll(&mut self, qq: &mut impl QQAlarmQueue) {
+        let delay = match self.ready_mode {
+            ReadyMode::Pin => self.delta.to_secs() as u64 * SystemTimer::TICKS_PER_SECOND + Self::READY_POLL_GRACE,
+            ReadyMode::Poll { period } => period,
+        };
+
+        self.cancel_ready_poll(qq);
+        self.ready_poll = Some(Delay::start(qq, SystemTimer::now() + delay));
+    }
+
+    fn cancel_ready_poll(&mut self, qq: &mut impl QQAlarmQueue) {
+        if let Some(Delay::Waiting { qq_alarm_id }) = self.ready_poll {
+            invariant!(qq.remove(qq_alarm_id).is_ok(), "sdc ready poll alarm not found in qq");
+        }
+
+        self.ready_poll = None;
+    }
+
+    /// Measurment is read (bus has to be owned), interrupt flag is cleared even when measurment was found by poll.
+    fn start_measurment(&mut self, bus: &mut impl TransactionBus, qq: &mut impl QQAlarmQueue) {
+        interrupts::gpio_interrupt_get_and_clear(GPIOInterruptStatus::pin(RDY::NUMBER));
+
+        // last rising edge belongs to this measurment, older are from measurments which were not read
+        self.ready_at = interrupts::gpio_capture_last_rising(RDY::NUMBER);
 
-        self.state = SDCSimpleMeasurmentState::BootDelay(Delay::new(qq_alarm_id));
+        self.arm_ready_poll(qq);
+        self.state = SDCSimpleMeasurmentState::Measurment(SDCDelayedGet::start(bus, self.address, SDCGetCommand::Measurment, self.delayed_get_delta));
     }
 
-    fn after_error(&mut self, usb_writer: &mut impl Write, name_for_error: &str, error: I2CTransmissionError) -> bool {
-        let _ = writeln!(usb_writer, "i2c error after {}: {:?}", name_for_error, error);
-        self.state = SDCSimpleMeasurmentState::Error;
+    fn on_missed_ready(&mut self, usb_writer: &mut impl Write) {
+        warn!(usb_writer, Module::Sdc, "data ready interrupt missed");
+        error_registry::record(Subsystem::Sdc, Self::ERROR_CODE_MISSED_READY);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn after_error(
+        &mut self,
+        bus: &mut impl TransactionBus,
+        usb_writer: &mut impl Write,
+        qq: &mut impl QQAlarmQueue,
+        events: &mut EventBus,
+        name_for_error: &str,
+        error: impl Debug + Into<Recovery>,
+        error_code: u16,
+        resume: ResumeAt,
+    ) -> bool {
+        error!(usb_writer, Module::Sdc, "i2c error after {}: {:?}", name_for_error, error);
+        error_registry::record(Subsystem::Sdc, error_code);
+        self.recover_or_fail(bus, usb_writer, qq, events, error.into(), resume);
 
         true
     }
 
-    pub fn update<const N: usize>(
+    /// `attempt` starts at 1
+    pub(in crate::machines) fn retry_backoff(attempt: u8) -> u64 {
+        Self::RETRY_BACKOFF_MIN.checked_shl(attempt as u32 - 1).unwrap_or(u64::MAX).min(Self::RETRY_BACKOFF_MAX)
+    }
+
+    /// Recovers the bus (still owned after failed transaction) when `recovery` needs it and waits before `resume`, or stays
+    /// in error when there were too many errors.
+    fn recover_or_fail(&mut self, bus: &mut impl TransactionBus, usb_writer: &mut impl Write, qq: &mut impl QQAlarmQueue, events: &mut EventBus, recovery: Recovery, resume: ResumeAt) {
+        events.publish(event_bus::Event::SensorError(self.sensor));
+
+        if self.stop_requested || self.consecutive_errors > Self::MAX_RETRIES {
+            self.cancel_ready_poll(qq);
+            bus.release(self.bus_user);
+            self.i2c_error.set(true);
+            flight_recorder::record(TraceEvent::SdcFailed, 0);
+            self.state = SDCSimpleMeasurmentState::Error;
+            return;
+        }
+
+        self.consecutive_errors += 1;
+        flight_recorder::record(TraceEvent::SdcRetry, self.consecutive_errors as u32);
+
+        // retries did not help, last attempt initializes the sensor again
+        let resume = if self.consecutive_errors > Self::MAX_RETRIES { ResumeAt::Init } else { resume };
+        let backoff = Self::retry_backoff(self.consecutive_errors);
+
+        let bus_state = recovery.apply(bus);
+        bus.release(self.bus_user);
+
+        warn!(
+            usb_writer,
+            Module::Sdc,
+            "retry {} / {} in {} ms from {} ({})",
+            self.consecutive_errors,
+            Self::MAX_RETRIES + 1,
+            backoff / (SystemTimer::TICKS_PER_SECOND / 1_000),
+            resume.name(),
+            bus_state,
+        );
+
+        self.state = SDCSimpleMeasurmentState::Recovery {
+            resume,
+            backoff: Delay::start(qq, SystemTimer::now() + backoff),
+        };
+    }
+
+    /// `pressure` is ambient pressure for pressure compensation (see `Controller::pressure_compensation`), measurments and
+    /// errors are published to `events`.
+    pub fn update(
         &mut self,
+        bus: &mut impl TransactionBus,
         usb_writer: &mut impl Write,
         qq: &mut impl QQAlarmQueue,
-        controller: &mut Controller<N>
+        events: &mut EventBus,
+        pressure: Option<NonZeroU16>,
     ) -> bool {
+        if matches!(
+            self.state,
+            SDCSimpleMeasurmentState::None | SDCSimpleMeasurmentState::WaitReady | SDCSimpleMeasurmentState::Stopped | SDCSimpleMeasurmentState::Error
+        ) {
+            heartbeat::beat(self.heartbeat);
+        }
+
         match &mut self.state {
             SDCSimpleMeasurmentState::BootDelay(Delay::Done) => {
-                self.state = SDCSimpleMeasurmentState::SetDelta(SDCSet::start(self.i2c.reborrow(), SDCSetCommand::SetDelta { delta: self.delta }));
+                if !bus.try_acquire(self.bus_user) {
+                    return false;
+                }
+
+                self.delta_changed = false;
+                self.state = SDCSimpleMeasurmentState::Reset(SDCReset::start(bus, self.address, self.bus_user, self.delayed_get_delta));
                 true
             },
+            SDCSimpleMeasurmentState::Reset(sdc_reset) => {
+                match sdc_reset.update(qq, bus) {
+                    SDCState::Done(Ok(version)) => {
+                        info!(usb_writer, Module::Sdc, "firmware version {}.{}", version.major, version.minor);
+
+                        // bus is still owned
+                        self.start_init(bus, 0);
+                        true
+                    },
+                    SDCState::Done(Err(err)) => {
+                        error!(usb_writer, Module::Sdc, "reset error (sensor not present ?) : {:?}", err);
+                        error_registry::record_error(Subsystem::Sdc, &err);
+                        let recovery = err.transmission_error().map_or(Recovery::RecoverBus, Recovery::from);
+                        self.recover_or_fail(bus, usb_writer, qq, events, recovery, ResumeAt::Init);
+                        true
+                    },
+                    SDCState::Active(did_something) => did_something,
+                }
+            },
+            SDCSimpleMeasurmentState::InitSet { setting, sdc_write } => {
+                let setting = *setting;
+
+                match sdc_write.update(bus) {
+                    SDCState::Done(Ok(())) => {
+                        // bus is still owned
+                        self.state = SDCSimpleMeasurmentState::InitGet {
+                            setting,
+                            sdc_delayed_get: SDCDelayedGet::start(bus, self.address, setting.get_command(), self.delayed_get_delta),
+                        };
+                        true
+                    },
+                    SDCState::Done(Err(err)) => self.after_error(bus, usb_writer, qq, events, setting.name(), err, err.error_code(), ResumeAt::Init),
+                    SDCState::Active(did_something) => did_something,
+                }
+            },
+            SDCSimpleMeasurmentState::InitGet { setting, sdc_delayed_get } => {
+                let setting = *setting;
+
+                match sdc_delayed_get.update(qq, bus) {
+                    SDCState::Done(Ok(())) => {
+                        // setting is only reported, measurment works either way
+                        let response = match setting {
+                            InitSetting::AutomaticSelfCalibration => sdc::read_response_automatic_self_calibration(bus)
+                                .map(|enabled| info!(usb_writer, Module::Sdc, "automatic self calibration {}", enabled)),
+                            InitSetting::TemperatureOffset => sdc::read_response_temperature_offset(bus)
+                                .map(|offset| info!(usb_writer, Module::Sdc, "temperature offset {:.2} °C", Milli::from(offset as u32 * 10))),
+                            InitSetting::AltitudeCompensation => sdc::read_response_altitude_compensation(bus)
+                                .map(|altitude| info!(usb_writer, Module::Sdc, "altitude compensation {} m", altitude)),
+                        };
+
+                        if let Err(err) = response {
+                            error!(usb_writer, Module::Sdc, "i2c error: {} reading response ({:?})", setting.name(), err);
+                            error_registry::record_error(Subsystem::Sdc, &err);
+                        }
+
+                        // bus is still owned
+                        self.start_init(bus, setting as usize + 1);
+                        true
+                    },
+                    SDCState::Done(Err(error @ (DelayedGetError::Write(err) | DelayedGetError::Read(err)))) => self.after_error(bus, usb_writer, qq, events, setting.name(), err, error.error_code(), ResumeAt::Init),
+                    SDCState::Active(active) => active,
+                }
+            },
             SDCSimpleMeasurmentState::SetDelta(sdc_write) => {
-                match sdc_write.update() {
+                match sdc_write.update(bus) {
                     SDCState::Done(Ok(())) => {
-                        self.state = SDCSimpleMeasurmentState::Start(SDCSet::start(self.i2c.reborrow(), SDCSetCommand::Start { pressure: None }));
+                        // bus is still owned
+                        self.state = SDCSimpleMeasurmentState::GetDelta(SDCDelayedGet::start(bus, self.address, SDCGetCommand::GetDelta, self.delayed_get_delta));
                         true
                     },
-                    SDCState::Done(Err(err)) => self.after_error(usb_writer, "set delta", err),
+                    SDCState::Done(Err(err)) => self.after_error(bus, usb_writer, qq, events, "set delta", err, err.error_code(), ResumeAt::Init),
                     SDCState::Active(did_something) => did_something,
                 }
             },
+            SDCSimpleMeasurmentState::GetDelta(sdc_delayed_get) => {
+                match sdc_delayed_get.update(qq, bus) {
+                    SDCState::Done(Ok(())) => {
+                        // mismatch is only reported, sensor measures with whatever interval it has
+                        match sdc::read_response_delta(bus) {
+                            Ok(seconds) if seconds as u32 == self.delta.to_secs() => info!(usb_writer, Module::Sdc, "measurment interval {} s confirmed", seconds),
+                            Ok(seconds) => {
+                                error!(usb_writer, Module::Sdc, "measurment interval {} s read back, {} s was set", seconds, self.delta.to_secs());
+                                error_registry::record(Subsystem::Sdc, Self::ERROR_CODE_DELTA_MISMATCH);
+                            },
+                            Err(err) => {
+                                error!(usb_writer, Module::Sdc, "i2c error: measurment interval reading response ({:?})", err);
+                                error_registry::record_error(Subsystem::Sdc, &err);
+                            },
+                        }
+
+                        // bus is still owned
+                        self.pressure = pressure;
+                        self.state = SDCSimpleMeasurmentState::Start(SDCSet::start(bus, self.address, SDCSetCommand::Start { pressure: self.pressure }));
+                        true
+                    },
+                    SDCState::Done(Err(error @ (DelayedGetError::Write(err) | DelayedGetError::Read(err)))) => self.after_error(bus, usb_writer, qq, events, "get delta", err, error.error_code(), ResumeAt::Init),
+                    SDCState::Active(active) => active,
+                }
+            },
             SDCSimpleMeasurmentState::Start(sdc_write) => {
-                match sdc_write.update() {
+                match sdc_write.update(bus) {
                     SDCState::Done(Ok(())) => {
+                        bus.release(self.bus_user);
+                        events.publish(event_bus::Event::SensorStarted(self.sensor));
+                        self.arm_ready_poll(qq);
                         self.state = SDCSimpleMeasurmentState::WaitReady;
                         true
                     },
-                    SDCState::Done(Err(err)) => self.after_error(usb_writer, "start", err),
+                    SDCState::Done(Err(err)) => self.after_error(bus, usb_writer, qq, events, "start", err, err.error_code(), ResumeAt::Init),
                     SDCState::Active(did_something) => did_something,
                 }
             },
             SDCSimpleMeasurmentState::WaitReady => {
-                let pending_interrupts = interrupts::gpio_interrupt_get_and_clear(GPIOInterruptStatus::GPIO6);
+                let poll_due = self.ready_poll == Some(Delay::Done);
+                let (interrupted, ready) = match self.ready_mode {
+                    ReadyMode::Pin => {
+                        let interrupted = interrupts::gpio_interrupt_get().contains(GPIOInterruptStatus::pin(RDY::NUMBER));
+                        // after failed read data ready stays high (there is no new rising edge), measurment is read again
+                        (interrupted, interrupted || ((poll_due || self.consecutive_errors != 0) && self.ready_pin.is_high()))
+                    },
+                    ReadyMode::Poll { .. } => (false, false),
+                };
+
+                let did_something = self.ready_poll.as_mut().is_some_and(|poll| poll.retry(qq));
+
+                // measurment has priority (except stop), interval, pressure compensation and recalibration are updated when there is nothing to read
+                if !(ready || poll_due || self.stop_requested || self.delta_changed || pressure != self.pressure || self.pending_recalibration.is_some()) || !bus.try_acquire(self.bus_user) {
+                    return did_something;
+                }
+
+                if self.stop_requested {
+                    self.stop_requested = false;
+                    // stale ready flag would start reading measurment after restart
+                    interrupts::gpio_interrupt_get_and_clear(GPIOInterruptStatus::pin(RDY::NUMBER));
+                    self.state = SDCSimpleMeasurmentState::StopMeasurment(SDCSet::start(bus, self.address, SDCSetCommand::StopMeasurment));
+                } else if ready {
+                    if !interrupted && poll_due {
+                        self.on_missed_ready(usb_writer);
+                    }
 
-                if !pending_interrupts.is_empty() {
-                    self.state = SDCSimpleMeasurmentState::Measurment(SDCDelayedGet::start(self.i2c.reborrow(), SDCGetCommand::Measurment, self.delayed_get_delta));
-                    true
+                    self.start_measurment(bus, qq);
+                } else if let Some(command) = self.pending_recalibration.take() {
+                    self.state = SDCSimpleMeasurmentState::ForcedRecalibration(SDCSet::start(bus, self.address, command));
+                } else if self.delta_changed {
+                    // start (with current pressure compensation) is sent after set delta
+                    info!(usb_writer, Module::Sdc, "measurment interval {} s", self.delta.to_secs());
+                    self.delta_changed = false;
+                    self.state = SDCSimpleMeasurmentState::SetDelta(SDCSet::start(bus, self.address, SDCSetCommand::SetDelta { delta: self.delta }));
+                } else if poll_due {
+                    // ready pin is low (it may be disconnected) or is not used
+                    self.state = SDCSimpleMeasurmentState::ReadyPoll(SDCDelayedGet::start(bus, self.address, SDCGetCommand::IsReady, self.delayed_get_delta));
                 } else {
-                    false
+                    info!(usb_writer, Module::Sdc, "pressure compensation {:?} mbar", pressure.map(NonZeroU16::get));
+                    self.pressure = pressure;
+                    self.state = SDCSimpleMeasurmentState::Start(SDCSet::start(bus, self.address, SDCSetCommand::Start { pressure }));
                 }
+
+                true
             }
-            SDCSimpleMeasurmentState::Measurment(sdc_delayed_get) => {
-                match sdc_delayed_get.update(qq, self.i2c.reborrow()) {
+            SDCSimpleMeasurmentState::StopMeasurment(sdc_write) => {
+                match sdc_write.update(bus) {
                     SDCState::Done(Ok(())) => {
-                        match sdc::read_response_measurment(self.i2c.reborrow()) {
-                            Ok(measurment) => {
-                                controller.on_measurment(measurment);
+                        info!(usb_writer, Module::Sdc, "measurment stopped");
+                        bus.release(self.bus_user);
+                        self.cancel_ready_poll(qq);
+                        self.state = SDCSimpleMeasurmentState::Stopped;
+                        true
+                    },
+                    SDCState::Done(Err(err)) => self.after_error(bus, usb_writer, qq, events, "stop measurment", err, err.error_code(), ResumeAt::Init),
+                    SDCState::Active(did_something) => did_something,
+                }
+            },
+            SDCSimpleMeasurmentState::ReadyPoll(sdc_delayed_get) => {
+                match sdc_delayed_get.update(qq, bus) {
+                    SDCState::Done(Ok(())) => {
+                        match sdc::read_response_is_ready(bus) {
+                            Ok(true) => {
+                                // bus is still owned
+                                if self.ready_mode == ReadyMode::Pin {
+                                    self.on_missed_ready(usb_writer);
+                                }
+
+                                self.start_measurment(bus, qq);
+                                true
+                            },
+                            Ok(false) => {
+                                bus.release(self.bus_user);
+                                self.arm_ready_poll(qq);
                                 self.state = SDCSimpleMeasurmentState::WaitReady;
+                                true
                             },
-                            Err(err) => {
-                                let _ = writeln!(usb_writer, "i2c error: measurment reading response ({:?})", err);
-                                self.state = SDCSimpleMeasurmentState::Error;
-                            }
+                            Err(err) => self.after_error(bus, usb_writer, qq, events, "is ready response", err, err.error_code(), ResumeAt::Measuring),
                         }
-
+                    },
+                    SDCState::Done(Err(error @ (DelayedGetError::Write(err) | DelayedGetError::Read(err)))) => {
+                        self.after_error(bus, usb_writer, qq, events, "is ready", err, error.error_code(), ResumeAt::Measuring)
+                    },
+                    SDCState::Active(active) => active,
+                }
+            },
+            SDCSimpleMeasurmentState::ForcedRecalibration(sdc_write) => {
+                match sdc_write.update(bus) {
+                    SDCState::Done(Ok(())) => {
+                        info!(usb_writer, Module::Sdc, "forced recalibration done");
+                        bus.release(self.bus_user);
+                        self.state = SDCSimpleMeasurmentState::WaitReady;
                         true
                     },
-                    SDCState::Done(Err(DelayedGetError::Write(err))) => self.after_error(usb_writer, "measurment write", err),
-                    SDCState::Done(Err(DelayedGetError::Read(err))) => self.after_error(usb_writer, "measurment read", err),
+                    SDCState::Done(Err(err)) => self.after_error(bus, usb_writer, qq, events, "forced recalibration", err, err.error_code(), ResumeAt::Init),
+                    SDCState::Active(did_something) => did_something,
+                }
+            },
+            SDCSimpleMeasurmentState::Measurment(sdc_delayed_get) => {
+                match sdc_delayed_get.update(qq, bus) {
+                    SDCState::Done(Ok(())) => {
+                        let response = sdc::read_response_measurment(bus);
+
+                        match response {
+                            Ok(measurment) => match measurment.check_range() {
+                                Ok(()) => {
+                                    bus.release(self.bus_user);
+                                    flight_recorder::record(TraceEvent::SdcMeasurment, (measurment.co2 / 1_000) as u32);
+                                    events.publish(event_bus::Event::Measurment { sensor: self.sensor, measurment, at: self.ready_at });
+                                    self.consecutive_errors = 0;
+                                    self.state = SDCSimpleMeasurmentState::WaitReady;
+                                    true
+                                },
+                                Err(err) => {
+                                    // not published (does not reset staleness), repeated rejections end in initialization
+                                    // or error same as i2c errors
+                                    warn!(usb_writer, Module::Sdc, "measurment rejected ({:?})", err);
+                                    error_registry::record_error(Subsystem::Sdc, &err);
+                                    events.publish(event_bus::Event::MeasurmentRejected { sensor: self.sensor });
+                                    self.recover_or_fail(bus, usb_writer, qq, events, Recovery::Reject, ResumeAt::Measuring);
+                                    true
+                                },
+                            },
+                            Err(err) if err.is_value_error() => {
+                                // measurment is dropped, sensor keeps measuring
+                                bus.release(self.bus_user);
+                                warn!(usb_writer, Module::Sdc, "invalid measurment ({:?})", err);
+                                error_registry::record_error(Subsystem::Sdc, &err);
+                                self.state = SDCSimpleMeasurmentState::WaitReady;
+                                true
+                            },
+                            Err(err) => self.after_error(bus, usb_writer, qq, events, "measurment response", err, err.error_code(), ResumeAt::Measuring),
+                        }
+                    },
+                    SDCState::Done(Err(error @ DelayedGetError::Write(err))) => self.after_error(bus, usb_writer, qq, events, "measurment write", err, error.error_code(), ResumeAt::Measuring),
+                    SDCState::Done(Err(error @ DelayedGetError::Read(err))) => self.after_error(bus, usb_writer, qq, events, "measurment read", err, error.error_code(), ResumeAt::Measuring),
                     SDCState::Active(active) => active,
                 }
             }
+            SDCSimpleMeasurmentState::BootDelay(delay) => delay.retry(qq),
+            SDCSimpleMeasurmentState::Recovery { resume, backoff } => {
+                if *backoff != Delay::Done {
+                    return backoff.retry(qq);
+                }
+
+                self.state = match resume {
+                    ResumeAt::Measuring => SDCSimpleMeasurmentState::WaitReady,
+                    // soft reset is sent as after boot delay
+                    ResumeAt::Init => SDCSimpleMeasurmentState::BootDelay(Delay::Done),
+                };
+                true
+            },
             SDCSimpleMeasurmentState::None |
-            SDCSimpleMeasurmentState::Error |
-            SDCSimpleMeasurmentState::BootDelay(Delay::Waiting { .. }) => false,
+            SDCSimpleMeasurmentState::Stopped |
+            SDCSimpleMeasurmentState::Error => false,
         }
     }
 
     pub fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        if let Some(ready_poll) = &mut self.ready_poll && ready_poll.on_alarm(qq_alarm_id) {
+            return true;
+        }
+
         match &mut self.state {
-            SDCSimpleMeasurmentState::BootDelay(delay) => delay.on_alarm(qq_alarm_id),
+            SDCSimpleMeasurmentState::BootDelay(delay) |
+            SDCSimpleMeasurmentState::Recovery { backoff: delay, .. } => delay.on_alarm(qq_alarm_id),
+            SDCSimpleMeasurmentState::Reset(sdc_reset) => sdc_reset.on_alarm(qq_alarm_id),
+            SDCSimpleMeasurmentState::InitGet { sdc_delayed_get, .. } |
+            SDCSimpleMeasurmentState::GetDelta(sdc_delayed_get) |
+            SDCSimpleMeasurmentState::ReadyPoll(sdc_delayed_get) |
             SDCSimpleMeasurmentState::Measurment(sdc_delayed_get) => sdc_delayed_get.on_alarm(qq_alarm_id),
             _ => false
         }
     }
+
+    /// Sensor commands (`interval`, `scd30`, `frc`), interval was already checked by `commands::handle`.
+    pub fn on_command(&mut self, command: ConsoleCommand, usb_writer: &mut impl Write, qq: &mut impl QQAlarmQueue) -> bool {
+        match command {
+            ConsoleCommand::Interval { seconds } => self.on_interval_changed(SecsDurationU32::secs(seconds)),
+            ConsoleCommand::SdcStop => self.stop(qq),
+            ConsoleCommand::SdcToggle => {
+                if self.is_stopped() {
+                    self.start(qq);
+                } else {
+                    self.stop(qq);
+                }
+            },
+            ConsoleCommand::SdcStart => {
+                if self.is_stopped() {
+                    self.start(qq);
+                } else {
+                    log_line!(usb_writer, "scd30", "already running");
+                }
+            },
+            ConsoleCommand::ForcedRecalibration { ppm } => {
+                if let Err(SDCCommandError::ParamOutOfRange) = self.force_recalibration(ppm) {
+                    log_line!(usb_writer, "frc", "must be {} - {} ppm", SDCSetCommand::FORCED_RECALIBRATION_MIN_PPM, SDCSetCommand::FORCED_RECALIBRATION_MAX_PPM);
+                }
+            },
+            _ => return false,
+        }
+
+        true
+    }
+}
+
+
+impl<'d, RDY> IntervalObserver for SDCSimpleMeasurment<'d, RDY>
+where
+    RDY: InputPin + PinNumber,
+{
+    fn on_interval_changed(&mut self, interval: SecsDurationU32) {
+        // range was checked by `change_interval`
+        let _ = self.set_interval(interval.to_secs());
+    }
+}
+
+impl<'c, 'i, 'd, RDY, W, Q, const N: usize> Machine<Context<'c, 'i, W, Q, N>> for SDCSimpleMeasurment<'d, RDY>
+where
+    RDY: InputPin + PinNumber,
+    W: Write + UsbWriter,
+    Q: TaggedQQAlarmQueue,
+{
+    fn name(&self) -> &'static str {
+        "scd30"
+    }
+
+    fn owns(&self, owner: QQOwner) -> bool {
+        owner == QQOwner::Sdc
+    }
+
+    fn update(&mut self, context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        Self::update(self, context.i2c_bus, context.usb_writer, &mut context.qq.owned(QQOwner::Sdc), context.events, context.controller.pressure_compensation())
+    }
+
+    fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        Self::on_alarm(self, qq_alarm_id)
+    }
+
+    fn on_command(&mut self, command: ConsoleCommand, context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        Self::on_command(self, command, context.usb_writer, &mut context.qq.owned(QQOwner::Sdc))
+    }
+}
+
+
+/// Second scd30 (another zone) with its own bus, address of scd30 is fixed, so it cannot share `I2CBus` with the primary sensor.
+///
+/// Bus is owned by the machine (usually `SoftI2CBus`), otherwise it works the same as the primary sensor, measurments are
+/// published with sensor id from config (see `SDCSimpleMeasurmentConfig::sensor`).
+#[cfg(feature = "second-sdc")]
+pub struct SecondarySDC<'d, RDY, B> {
+    sdc: SDCSimpleMeasurment<'d, RDY>,
+    bus: B,
+}
+
+#[cfg(feature = "second-sdc")]
+impl<'d, RDY, B> SecondarySDC<'d, RDY, B>
+where
+    RDY: InputPin + PinNumber,
+    B: TransactionBus,
+{
+    pub fn new(sdc: SDCSimpleMeasurment<'d, RDY>, bus: B) -> Self {
+        Self { sdc, bus }
+    }
+
+    pub fn start(&mut self, qq: &mut impl QQAlarmQueue) {
+        self.sdc.start(qq);
+    }
+}
+
+#[cfg(feature = "second-sdc")]
+impl<'d, RDY, B> IntervalObserver for SecondarySDC<'d, RDY, B>
+where
+    RDY: InputPin + PinNumber,
+{
+    fn on_interval_changed(&mut self, interval: SecsDurationU32) {
+        self.sdc.on_interval_changed(interval);
+    }
+}
+
+#[cfg(feature = "second-sdc")]
+impl<'c, 'i, 'd, RDY, B, W, Q, const N: usize> Machine<Context<'c, 'i, W, Q, N>> for SecondarySDC<'d, RDY, B>
+where
+    RDY: InputPin + PinNumber,
+    B: TransactionBus,
+    W: Write + UsbWriter,
+    Q: TaggedQQAlarmQueue,
+{
+    fn name(&self) -> &'static str {
+        "scd30 #1"
+    }
+
+    fn owns(&self, owner: QQOwner) -> bool {
+        owner == QQOwner::SdcSecondary
+    }
+
+    fn update(&mut self, context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        let did_something = self.sdc.update(&mut self.bus, context.usb_writer, &mut context.qq.owned(QQOwner::SdcSecondary), context.events, context.controller.pressure_compensation());
+
+        did_something || self.bus.needs_poll()
+    }
+
+    fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        self.sdc.on_alarm(qq_alarm_id)
+    }
+
+    // other sensor commands control the primary sensor
+    fn on_command(&mut self, command: ConsoleCommand, _context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        let ConsoleCommand::Interval { seconds } = command else {
+            return false;
+        };
+
+        self.on_interval_changed(SecsDurationU32::secs(seconds));
+
+        true
+    }
 }
\ No newline at end of file
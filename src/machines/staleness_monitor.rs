@@ -0,0 +1,174 @@
+use core::fmt::Write;
+
+use esp_hal::timer::systimer::SystemTimer;
+
+use fugit::SecsDurationU32;
+
+use crate::{
+    error_registry::{self, Subsystem},
+    event_bus::{Event, EventBus, Subscriber},
+    invariants::invariant,
+    log::{info, warn, Module},
+    measurment_interval::IntervalObserver,
+    qq_alarm_queue::{QQAlarmQueue, QQOwner, TaggedQQAlarmQueue},
+    scheduler::{Context, Machine},
+    sdc::SensorId,
+    usb_writer::UsbWriter
+};
+
+use super::{console::ConsoleCommand, status_led::{LedPattern, LedPatternRequest}, Delay};
+
+
+
+#[derive(Debug, Clone, Copy)]
+enum StalenessMonitorState {
+    /// interval is not known yet
+    None,
+    Waiting(Delay),
+    Stale,
+}
+
+/// Reports (and records in error registry) when there was no measurment for `STALE_INTERVALS` measurment intervals,
+/// `LedPattern::StaleMeasurment` is shown until the next measurment. Rejected measurments (out of range) do not count
+/// as measurments, their number is reported with staleness.
+///
+/// Deadline is counted from the last measurment. When the interval changes, deadline is counted again from the change,
+/// so sensor has whole new timeout to apply the new interval (no false alarm after the interval is increased).
+pub struct StalenessMonitor {
+    stale_after: Option<u64>,
+    interval_changed: bool,
+    /// ready edge of the last measurment, `None` also when it was not captured (timeout starts when the measurment is
+    /// received)
+    last_measurment_at: Option<u64>,
+    /// since the last measurment
+    rejected: u32,
+    stale_led: LedPatternRequest,
+    state: StalenessMonitorState,
+}
+
+impl StalenessMonitor {
+    /// sensor does not measure exactly with the interval (boot delay, ready pin, bus contention), so some slack is needed
+    const STALE_INTERVALS: u64 = 3;
+
+    pub const ERROR_CODE_STALE: u16 = 0x05;
+
+
+    pub fn new() -> Self {
+        Self {
+            stale_after: None,
+            interval_changed: false,
+            last_measurment_at: None,
+            rejected: 0,
+            stale_led: LedPatternRequest::new(LedPattern::StaleMeasurment),
+            state: StalenessMonitorState::None,
+        }
+    }
+
+    pub fn update(&mut self, usb_writer: &mut impl Write, qq: &mut impl QQAlarmQueue, events: &mut EventBus) -> bool {
+        let mut new_measurment = false;
+
+        while let Some(event) = events.poll(Subscriber::StalenessMonitor) {
+            // only primary sensor is supervised (its measurments drive alerts and outputs)
+            match event {
+                Event::Measurment { sensor: SensorId::PRIMARY, at, .. } => {
+                    self.last_measurment_at = at;
+                    self.rejected = 0;
+                    new_measurment = true;
+                },
+                Event::MeasurmentRejected { sensor: SensorId::PRIMARY } => self.rejected = self.rejected.saturating_add(1),
+                _ => {},
+            }
+        }
+
+        if new_measurment {
+            self.stale_led.set(false);
+        }
+
+        if new_measurment || self.interval_changed {
+            let Some(stale_after) = self.stale_after else {
+                return new_measurment;
+            };
+
+            match self.state {
+                StalenessMonitorState::Waiting(Delay::Waiting { qq_alarm_id }) => {
+                    invariant!(qq.remove(qq_alarm_id).is_ok(), "staleness alarm not found in qq");
+                },
+                StalenessMonitorState::Stale if new_measurment => {
+                    info!(usb_writer, Module::Sdc, "measurments resumed");
+                },
+                _ => {},
+            }
+
+            let from = match self.last_measurment_at {
+                Some(at) if !self.interval_changed => at,
+                _ => SystemTimer::now(),
+            };
+
+            self.interval_changed = false;
+            self.state = StalenessMonitorState::Waiting(Delay::start(qq, from + stale_after));
+
+            return true;
+        }
+
+        match &mut self.state {
+            StalenessMonitorState::Waiting(Delay::Done) => {
+                let stale_after = self.stale_after.unwrap_or(0);
+                warn!(usb_writer, Module::Sdc, "no measurment for {} s ({} rejected)", stale_after / SystemTimer::TICKS_PER_SECOND, self.rejected);
+                error_registry::record(Subsystem::Sdc, Self::ERROR_CODE_STALE);
+                self.stale_led.set(true);
+                self.state = StalenessMonitorState::Stale;
+
+                true
+            },
+            StalenessMonitorState::Waiting(delay) => delay.retry(qq),
+            StalenessMonitorState::None |
+            StalenessMonitorState::Stale => false,
+        }
+    }
+
+    pub fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        match &mut self.state {
+            StalenessMonitorState::Waiting(delay) => delay.on_alarm(qq_alarm_id),
+            _ => false,
+        }
+    }
+}
+
+impl IntervalObserver for StalenessMonitor {
+    fn on_interval_changed(&mut self, interval: SecsDurationU32) {
+        self.stale_after = Some(interval.to_secs() as u64 * Self::STALE_INTERVALS * SystemTimer::TICKS_PER_SECOND);
+        self.interval_changed = true;
+    }
+}
+
+impl<'c, 'i, W, Q, const N: usize> Machine<Context<'c, 'i, W, Q, N>> for StalenessMonitor
+where
+    W: Write + UsbWriter,
+    Q: TaggedQQAlarmQueue,
+{
+    fn name(&self) -> &'static str {
+        "staleness monitor"
+    }
+
+    fn owns(&self, owner: QQOwner) -> bool {
+        owner == QQOwner::StalenessMonitor
+    }
+
+    fn update(&mut self, context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        Self::update(self, context.usb_writer, &mut context.qq.owned(QQOwner::StalenessMonitor), context.events)
+    }
+
+    fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        Self::on_alarm(self, qq_alarm_id)
+    }
+
+    fn on_command(&mut self, command: ConsoleCommand, _context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        let ConsoleCommand::Interval { seconds } = command else {
+            return false;
+        };
+
+        self.on_interval_changed(SecsDurationU32::secs(seconds));
+
+        true
+    }
+}
@@ -1,18 +1,333 @@
+use core::{fmt::Write, sync::atomic::{AtomicU32, Ordering}};
+
 use embedded_hal::digital::OutputPin;
 
 use esp_hal::timer::systimer::SystemTimer;
+#[cfg(feature = "rgb-led")]
+use esp_hal::{
+    gpio::{Output, OutputPin as HalOutputPin},
+    peripheral::{Peripheral, PeripheralRef},
+    peripherals::{LEDC, SYSTEM},
+};
 
-use crate::{qq_alarm_queue::QQAlarmQueue, usb_writer::UsbWriter};
+use crate::{invariants::invariant, qq_alarm_queue::{QQAlarmQueue, QQOwner, TaggedQQAlarmQueue}, scheduler::{Context, Machine}, usb_writer::UsbWriter};
+#[cfg(feature = "rgb-led")]
+use crate::pac_utils::{gpio::PinNumber, ledc::{self as ledc_utils, LedcChannel, LedcTimerConfig}};
 
 use super::Delay;
 
 
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Color {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Color {
+    pub const OFF: Color = Color::new(0, 0, 0);
+    pub const WHITE: Color = Color::new(255, 255, 255);
+    pub const GREEN: Color = Color::new(0, 255, 0);
+    pub const YELLOW: Color = Color::new(255, 160, 0);
+    pub const RED: Color = Color::new(255, 0, 0);
+    pub const BLUE: Color = Color::new(0, 0, 255);
+    pub const CYAN: Color = Color::new(0, 255, 255);
+    pub const MAGENTA: Color = Color::new(255, 0, 255);
+    pub const VIOLET: Color = Color::new(128, 0, 255);
+
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+
+    /// Color at `step` of `steps` of linear transition from `self` to `to`.
+    fn mix(&self, to: Color, step: usize, steps: usize) -> Color {
+        let channel = |from: u8, to: u8| (from as i32 + (to as i32 - from as i32) * step as i32 / steps as i32) as u8;
+
+        Color::new(channel(self.r, to.r), channel(self.g, to.g), channel(self.b, to.b))
+    }
+}
+
+
+/// Led driven by status led machine, either single color led (any output pin, on for any color except `Color::OFF`)
+/// or rgb led (see `RgbLed`).
+pub trait LedOutput {
+    /// rgb led shows co2 level by color instead of blinking (see `StatusLed`)
+    const RGB: bool;
+
+    fn set_color(&mut self, color: Color);
+}
+
+impl<T> LedOutput for T where T: OutputPin {
+    const RGB: bool = false;
+
+    fn set_color(&mut self, color: Color) {
+        self.set_state((color != Color::OFF).into()).unwrap();
+    }
+}
+
+
+/// Common cathode rgb led, each color is driven by its own ledc channel (~4 kHz pwm with 10 bit resolution, ledc timer 0).
+#[cfg(feature = "rgb-led")]
+pub struct RgbLed<'d, R, G, B> {
+    ledc: PeripheralRef<'d, LEDC>,
+    // pins are kept only to stay owned, they are driven by ledc through gpio matrix
+    _red: Output<'d, R>,
+    _green: Output<'d, G>,
+    _blue: Output<'d, B>,
+}
+
+#[cfg(feature = "rgb-led")]
+impl<'d, R, G, B> RgbLed<'d, R, G, B>
+where
+    R: HalOutputPin + PinNumber,
+    G: HalOutputPin + PinNumber,
+    B: HalOutputPin + PinNumber,
+{
+    const TIMER: u8 = 0;
+    const DUTY_RES: u8 = 10;
+    const CHANNELS: [LedcChannel; 3] = [LedcChannel::Ch0, LedcChannel::Ch1, LedcChannel::Ch2];
+
+    pub fn new<'s>(
+        ledc: impl Peripheral<P = LEDC> + 'd,
+        system: impl Peripheral<P = SYSTEM> + 's,
+        red: impl Peripheral<P = R> + 'd,
+        green: impl Peripheral<P = G> + 'd,
+        blue: impl Peripheral<P = B> + 'd,
+    ) -> Self {
+        let mut ledc = ledc.into_ref();
+
+        ledc_utils::config_clock(system.into_ref());
+        ledc_utils::config(ledc.reborrow());
+        ledc_utils::timer_config(ledc.reborrow(), Self::TIMER, LedcTimerConfig {
+            clk_div: 20 << 8, // 80 MHz / 20 / 2^10 ~ 3.9 kHz
+            duty_res: Self::DUTY_RES,
+        });
+
+        for ch in Self::CHANNELS {
+            ledc_utils::channel_config(ledc.reborrow(), ch, Self::TIMER);
+        }
+
+        Self {
+            ledc,
+            _red: ledc_utils::setup_output_pin(red, Self::CHANNELS[0]),
+            _green: ledc_utils::setup_output_pin(green, Self::CHANNELS[1]),
+            _blue: ledc_utils::setup_output_pin(blue, Self::CHANNELS[2]),
+        }
+    }
+}
+
+#[cfg(feature = "rgb-led")]
+impl<'d, R, G, B> LedOutput for RgbLed<'d, R, G, B>
+where
+    R: HalOutputPin + PinNumber,
+    G: HalOutputPin + PinNumber,
+    B: HalOutputPin + PinNumber,
+{
+    const RGB: bool = true;
+
+    fn set_color(&mut self, color: Color) {
+        let max_duty = (1 << Self::DUTY_RES) - 1;
+
+        for (ch, value) in Self::CHANNELS.into_iter().zip([color.r, color.g, color.b]) {
+            // squared for roughly linear perceived brightness
+            let duty = (value as u32 * value as u32) * max_duty / (255 * 255);
+            ledc_utils::set_duty(self.ledc.reborrow(), ch, duty);
+        }
+    }
+}
+
+
+
+/// Timing of led pattern, on and off steps alternate (starting with on), durations are in ms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PatternShape {
+    Solid,
+    /// on and off for half of `period` each
+    Blink { period: u16 },
+    /// `count` pulses separated by `off`, last pulse is followed by `pause`
+    Pulses { count: u8, on: u16, off: u16, pause: u16 },
+    /// two short pulses and long pause
+    Heartbeat,
+}
+
+impl PatternShape {
+    const HEARTBEAT: [u16; 4] = [80, 120, 80, 720];
+
+    /// Number of steps of one period, zero for solid shape.
+    fn len(&self) -> usize {
+        match self {
+            PatternShape::Solid => 0,
+            PatternShape::Blink { .. } => 2,
+            PatternShape::Pulses { count, .. } => 2 * *count as usize,
+            PatternShape::Heartbeat => Self::HEARTBEAT.len(),
+        }
+    }
+
+    /// Duration of `step` (`< len`).
+    fn duration(&self, step: usize) -> u16 {
+        match *self {
+            PatternShape::Solid => 0,
+            PatternShape::Blink { period } => period / 2,
+            PatternShape::Pulses { count, on, off, pause } => {
+                if step % 2 == 0 {
+                    on
+                } else if step + 1 == 2 * count as usize {
+                    pause
+                } else {
+                    off
+                }
+            },
+            PatternShape::Heartbeat => Self::HEARTBEAT[step],
+        }
+    }
+}
+
+
+/// State of the system shown by status led, only pattern with the highest priority (last variant) is shown.
+///
+/// Background patterns are shown while they are requested (see `LedPatternRequest`), transient patterns are played
+/// once (see `flash`) over any background pattern, then the background is shown again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedPattern {
+    /// usb writer dropped data recently (output is produced faster than host reads it)
+    UsbOverflow,
+    /// host does not read usb data
+    UsbTimeout,
+    /// no measurment for several intervals (see `StalenessMonitor`)
+    StaleMeasurment,
+    /// i2c transaction or sensor response failed
+    I2CError,
+    /// co2 concentration is too high (warning level, see `Alert`)
+    Co2Alarm,
+    /// co2 concentration is critical
+    Co2Critical,
+    /// ir code was received (transient)
+    IrReceived,
+}
+
+impl LedPattern {
+    pub const COUNT: usize = 7;
+    /// ordered by priority (lowest first)
+    pub const ALL: [LedPattern; LedPattern::COUNT] = [LedPattern::UsbOverflow, LedPattern::UsbTimeout, LedPattern::StaleMeasurment, LedPattern::I2CError, LedPattern::Co2Alarm, LedPattern::Co2Critical, LedPattern::IrReceived];
+
+    fn shape(&self) -> PatternShape {
+        match self {
+            LedPattern::UsbOverflow => PatternShape::Pulses { count: 1, on: 50, off: 0, pause: 950 },
+            LedPattern::UsbTimeout => PatternShape::Solid,
+            LedPattern::StaleMeasurment => PatternShape::Heartbeat,
+            LedPattern::I2CError => PatternShape::Pulses { count: 2, on: 100, off: 200, pause: 1000 },
+            LedPattern::Co2Alarm => PatternShape::Blink { period: 200 },
+            LedPattern::Co2Critical => PatternShape::Blink { period: 100 },
+            LedPattern::IrReceived => PatternShape::Pulses { count: 1, on: 30, off: 0, pause: 70 },
+        }
+    }
+
+    /// Color of on steps on rgb led.
+    fn color(&self) -> Color {
+        match self {
+            LedPattern::UsbOverflow => Color::CYAN,
+            LedPattern::UsbTimeout => Color::MAGENTA,
+            LedPattern::StaleMeasurment => Color::VIOLET,
+            LedPattern::I2CError => Color::BLUE,
+            LedPattern::Co2Alarm => Color::YELLOW,
+            LedPattern::Co2Critical => Color::RED,
+            LedPattern::IrReceived => Color::WHITE,
+        }
+    }
+
+    /// Co2 level patterns are shown as base color on rgb led (not blinked).
+    fn is_co2_level(&self) -> bool {
+        matches!(self, LedPattern::Co2Alarm | LedPattern::Co2Critical)
+    }
+
+    /// Transient patterns are played once by `flash`, they cannot be requested.
+    fn is_transient(&self) -> bool {
+        matches!(self, LedPattern::IrReceived)
+    }
+}
+
+
+/// number of holders of each pattern (see `LedPatternRequest`)
+static PATTERN_REQUESTS: [AtomicU32; LedPattern::COUNT] = [const { AtomicU32::new(0) }; LedPattern::COUNT];
+
+/// Pattern is requested by at least one machine (shown also by other outputs, e.g. icons of `Display`).
+pub fn is_requested(pattern: LedPattern) -> bool {
+    PATTERN_REQUESTS[pattern as usize].load(Ordering::Relaxed) != 0
+}
+
+/// `rgb` skips co2 level patterns.
+fn requested_pattern(rgb: bool) -> Option<LedPattern> {
+    LedPattern::ALL.into_iter().rev().filter(|pattern| !(rgb && pattern.is_co2_level())).find(|pattern| is_requested(*pattern))
+}
+
+/// transient patterns waiting to be played (bit of each pattern)
+static PENDING_FLASHES: AtomicU32 = AtomicU32::new(0);
+
+/// Plays transient `pattern` once over the current pattern, flashes requested while another one is played are merged.
+pub fn flash(pattern: LedPattern) {
+    invariant!(pattern.is_transient(), "flash of background led pattern");
+
+    PENDING_FLASHES.fetch_or(1 << pattern as u32, Ordering::Relaxed);
+}
+
+/// Pending transient pattern with the highest priority, the other pending patterns are dropped.
+fn take_flash() -> Option<LedPattern> {
+    let pending = PENDING_FLASHES.swap(0, Ordering::Relaxed);
+
+    LedPattern::ALL.into_iter().rev().find(|pattern| pending & (1 << *pattern as u32) != 0)
+}
+
+
+/// Request of led pattern held by a machine, pattern is shown while at least one machine requests it.
+///
+/// Each holder counts only once, so `set` can be called repeatedly (e.g. after each measurment).
+pub struct LedPatternRequest {
+    pattern: LedPattern,
+    requested: bool,
+}
+
+impl LedPatternRequest {
+    pub const fn new(pattern: LedPattern) -> Self {
+        Self {
+            pattern,
+            requested: false,
+        }
+    }
+
+    pub fn set(&mut self, requested: bool) {
+        if requested == self.requested {
+            return;
+        }
+
+        let counter = &PATTERN_REQUESTS[self.pattern as usize];
+
+        if requested {
+            counter.fetch_add(1, Ordering::Relaxed);
+        } else {
+            counter.fetch_sub(1, Ordering::Relaxed);
+        }
+
+        self.requested = requested;
+    }
+}
+
+
+
 #[derive(Debug, Clone, Copy)]
 pub struct StatusLedConfig {
     /// in system timer ticks
     pub boot_blink_duration: u64,
     pub boot_blink_count: usize,
+    /// overflow pattern is shown for this time after the last dropped byte (in system timer ticks)
+    pub usb_overflow_hold: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Fade {
+    from: Color,
+    step: usize,
+    delay: Delay,
 }
 
 enum StatusLedState {
@@ -21,24 +336,57 @@ enum StatusLedState {
         count: usize,
         delay: Delay,
     },
-    UsbTimeoutMonitor(bool),
+    /// no pattern requested, led shows `base` color (off for single color led), `fade` is transition into it
+    Idle {
+        base: Color,
+        fade: Option<Fade>,
+    },
+    Pattern {
+        pattern: LedPattern,
+        step: usize,
+        /// `None` for solid pattern
+        delay: Option<Delay>,
+    },
 }
 
+/// Blinks at boot, then shows requested pattern with the highest priority (see `LedPatternRequest`), transient patterns
+/// (see `flash`) are played over it.
+///
+/// Rgb led shows co2 level by color when no other pattern is requested: green, yellow for `LedPattern::Co2Alarm` and red
+/// for `LedPattern::Co2Critical`, changes of the color are faded. Other patterns blink in their own color over it.
 pub struct StatusLed<T> {
     led: T,
+    /// currently shown color
+    color: Color,
     boot_blink_duration: u64,
     boot_blink_count: usize,
+    usb_timeout: LedPatternRequest,
+    usb_overflow: LedPatternRequest,
+    usb_overflow_hold: u64,
+    /// `UsbWriter::dropped_bytes` seen by the last update
+    usb_dropped_bytes: u64,
+    usb_dropped_at: Option<u64>,
     state: StatusLedState,
 }
 
 // TODO: maybe use peripherals for blinking instead of manual timing
-impl<T> StatusLed<T> where T: OutputPin {
+impl<T> StatusLed<T> where T: LedOutput {
+    const FADE_STEPS: usize = 25;
+    /// 0.5 s fade
+    const FADE_STEP: u64 = SystemTimer::TICKS_PER_SECOND / 50;
+
     // TODO: config defaults
     pub fn new(led: T, config: StatusLedConfig) -> Self {
         Self {
             led,
+            color: Color::OFF,
             boot_blink_duration: config.boot_blink_duration,
             boot_blink_count: 2 * config.boot_blink_count,
+            usb_timeout: LedPatternRequest::new(LedPattern::UsbTimeout),
+            usb_overflow: LedPatternRequest::new(LedPattern::UsbOverflow),
+            usb_overflow_hold: config.usb_overflow_hold,
+            usb_dropped_bytes: 0,
+            usb_dropped_at: None,
             state: StatusLedState::None,
         }
     }
@@ -53,26 +401,105 @@ impl<T> StatusLed<T> where T: OutputPin {
         };
     }
 
-    
+
+    fn set_color(&mut self, color: Color) {
+        self.led.set_color(color);
+        self.color = color;
+    }
+
+    /// Co2 level color of rgb led, single color led is off.
+    fn base_color() -> Color {
+        if !T::RGB {
+            Color::OFF
+        } else if is_requested(LedPattern::Co2Critical) {
+            Color::RED
+        } else if is_requested(LedPattern::Co2Alarm) {
+            Color::YELLOW
+        } else {
+            Color::GREEN
+        }
+    }
+
     fn boot_set_led(&mut self, qq: &mut impl QQAlarmQueue, led_state: bool) -> Delay {
-        self.led.set_state(led_state.into()).unwrap();
+        self.set_color(if led_state { Color::WHITE } else { Color::OFF });
+
+        let now = SystemTimer::now();
+
+        Delay::start(qq, now + self.boot_blink_duration)
+    }
+
+    /// Sets led for `step` of `pattern` and returns delay until the next step (`None` for solid pattern).
+    fn pattern_set_led(&mut self, qq: &mut impl QQAlarmQueue, pattern: LedPattern, step: usize) -> Option<Delay> {
+        let shape = pattern.shape();
+
+        if shape.len() == 0 {
+            self.set_color(pattern.color());
+            return None;
+        }
+
+        self.set_color(if step % 2 == 0 { pattern.color() } else { Color::OFF });
+
+        let duration = shape.duration(step) as u64 * SystemTimer::TICKS_PER_SECOND / 1000;
+
+        Some(Delay::start(qq, SystemTimer::now() + duration))
+    }
 
+    /// Starts fade from the current color into `base`, single color led is set immediately.
+    fn fade_to(&mut self, qq: &mut impl QQAlarmQueue, base: Color) -> StatusLedState {
+        let fade = if T::RGB && base != self.color {
+            Some(Fade {
+                from: self.color,
+                step: 0,
+                delay: Delay::start(qq, SystemTimer::now() + Self::FADE_STEP),
+            })
+        } else {
+            self.set_color(base);
+            None
+        };
+
+        StatusLedState::Idle { base, fade }
+    }
+
+    fn show(&mut self, qq: &mut impl QQAlarmQueue, pattern: Option<LedPattern>) {
+        match self.state {
+            StatusLedState::Pattern { delay: Some(Delay::Waiting { qq_alarm_id }), .. } |
+            StatusLedState::Idle { fade: Some(Fade { delay: Delay::Waiting { qq_alarm_id }, .. }), .. } => {
+                let _ = qq.remove(qq_alarm_id);
+            },
+            _ => {},
+        }
+
+        self.state = match pattern {
+            Some(pattern) => StatusLedState::Pattern {
+                pattern,
+                step: 0,
+                delay: self.pattern_set_led(qq, pattern, 0),
+            },
+            None => self.fade_to(qq, Self::base_color()),
+        };
+    }
+
+    /// Overflow is not cleared by its own alarm, it is noticed by the next update after the hold (loop wakes up at least once per debug print period).
+    fn update_usb_overflow(&mut self, usb_writer: &impl UsbWriter) {
         let now = SystemTimer::now();
-        let qq_alarm_id = qq.add(now + self.boot_blink_duration).unwrap();
+        let dropped_bytes = usb_writer.dropped_bytes();
+
+        if dropped_bytes != self.usb_dropped_bytes {
+            self.usb_dropped_bytes = dropped_bytes;
+            self.usb_dropped_at = Some(now);
+        }
 
-        Delay::new(qq_alarm_id)
+        self.usb_overflow.set(self.usb_dropped_at.is_some_and(|at| now < at + self.usb_overflow_hold));
     }
 
     pub fn update(&mut self, usb_writer: &impl UsbWriter, qq: &mut impl QQAlarmQueue) -> bool {
         match self.state {
             StatusLedState::Booting { count, delay: Delay::Done } => {
                 if count == self.boot_blink_count {
-                    self.led.set_low().unwrap();
-
-                    self.state = StatusLedState::UsbTimeoutMonitor(false);
+                    self.show(qq, None);
                 } else {
                     let delay = self.boot_set_led(qq, count % 2 == 0);
-    
+
                     self.state = StatusLedState::Booting {
                         count: count + 1,
                         delay,
@@ -81,27 +508,98 @@ impl<T> StatusLed<T> where T: OutputPin {
 
                 true
             },
-            StatusLedState::UsbTimeoutMonitor(ref mut led_state) => {
-                let timeout = usb_writer.is_timeouted();
+            StatusLedState::Booting { ref mut delay, .. } => delay.retry(qq),
+            StatusLedState::None => false,
+            StatusLedState::Idle { .. } |
+            StatusLedState::Pattern { .. } => {
+                self.usb_timeout.set(usb_writer.is_timeouted());
+                self.update_usb_overflow(usb_writer);
+
+                let requested = requested_pattern(T::RGB);
+                let base = Self::base_color();
 
-                if timeout == *led_state {
-                    return false;
+                // transient pattern is not interrupted, flashes requested meanwhile are played after it
+                let transient = matches!(self.state, StatusLedState::Pattern { pattern, .. } if pattern.is_transient());
+                if !transient && let Some(pattern) = take_flash() {
+                    self.show(qq, Some(pattern));
+                    return true;
                 }
 
-                *led_state = timeout;
-                self.led.set_state(timeout.into()).unwrap();
+                match &mut self.state {
+                    StatusLedState::Pattern { pattern, .. } if !pattern.is_transient() && Some(*pattern) != requested => {
+                        self.show(qq, requested);
+                        true
+                    },
+                    StatusLedState::Idle { base: shown, .. } if requested.is_some() || *shown != base => {
+                        self.show(qq, requested);
+                        true
+                    },
+                    StatusLedState::Idle { base, fade: Some(Fade { from, step, delay: Delay::Done }) } => {
+                        let (base, from, step) = (*base, *from, *step + 1);
+                        self.set_color(from.mix(base, step, Self::FADE_STEPS));
 
-                true
+                        let fade = (step < Self::FADE_STEPS).then(|| Fade {
+                            from,
+                            step,
+                            delay: Delay::start(qq, SystemTimer::now() + Self::FADE_STEP),
+                        });
+
+                        self.state = StatusLedState::Idle { base, fade };
+                        true
+                    },
+                    StatusLedState::Idle { fade: Some(Fade { delay, .. }), .. } => delay.retry(qq),
+                    StatusLedState::Pattern { pattern, step, delay: Some(Delay::Done) } => {
+                        let (pattern, step) = (*pattern, *step + 1);
+                        let len = pattern.shape().len();
+
+                        if pattern.is_transient() && step == len {
+                            // back to the background pattern
+                            self.show(qq, requested);
+                        } else {
+                            let step = step % len;
+                            let delay = self.pattern_set_led(qq, pattern, step);
+
+                            self.state = StatusLedState::Pattern { pattern, step, delay };
+                        }
+
+                        true
+                    },
+                    StatusLedState::Pattern { delay: Some(delay), .. } => delay.retry(qq),
+                    _ => false,
+                }
             },
-            StatusLedState::None |
-            StatusLedState::Booting { delay: Delay::Waiting { .. }, .. } => false,
         }
     }
 
     pub fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
         match &mut self.state {
-            StatusLedState::Booting { delay, .. } => delay.on_alarm(qq_alarm_id),
+            StatusLedState::Booting { delay, .. } |
+            StatusLedState::Pattern { delay: Some(delay), .. } |
+            StatusLedState::Idle { fade: Some(Fade { delay, .. }), .. } => delay.on_alarm(qq_alarm_id),
             _ => false,
         }
     }
-}
\ No newline at end of file
+}
+
+impl<'c, 'i, T, W, Q, const N: usize> Machine<Context<'c, 'i, W, Q, N>> for StatusLed<T>
+where
+    T: LedOutput,
+    W: Write + UsbWriter,
+    Q: TaggedQQAlarmQueue,
+{
+    fn name(&self) -> &'static str {
+        "status led"
+    }
+
+    fn owns(&self, owner: QQOwner) -> bool {
+        owner == QQOwner::StatusLed
+    }
+
+    fn update(&mut self, context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        Self::update(self, context.usb_writer, &mut context.qq.owned(QQOwner::StatusLed))
+    }
+
+    fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        Self::on_alarm(self, qq_alarm_id)
+    }
+}
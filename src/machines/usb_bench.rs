@@ -0,0 +1,215 @@
+use core::fmt::Write;
+
+use esp_hal::timer::systimer::SystemTimer;
+
+use crate::{fixed_point::Milli, interrupts::{self, InterruptSource}, log::{log_fmt, log_line}, qq_alarm_queue::TaggedQQAlarmQueue, scheduler::{Context, Machine}, usb_writer::{UsbOutputMode, UsbWriter}};
+
+use super::console::ConsoleCommand;
+
+
+
+#[derive(Debug, Clone, Copy)]
+struct UsbBenchRun {
+    bytes: u32,
+    remaining: u32,
+    started_at: u64,
+    timeout_count_at_start: u32,
+    /// usb handler calls and main loop wakeups by them (`interrupts::usb_wakeup_count`) at start
+    usb_interrupts_at_start: u32,
+    usb_wakeups_at_start: u32,
+    rng: u32,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum UsbBenchState {
+    None,
+    Writing(UsbBenchRun),
+    /// all data written into usb writer, waiting until usb writer buffer is empty (host read all data)
+    Draining(UsbBenchRun),
+}
+
+/// Throughput benchmark of usb writer (started from console).
+///
+/// Writes lines of pseudo-random hex characters, only when they fit into usb writer buffer (nothing is dropped),
+/// and reports throughput, buffer high-water mark, number of timeouts, usb interrupts and main loop wakeups by them
+/// (to compare usb writer modes, see `RingBufferUsbWriter`), after the host has read all the data.
+/// Works only in text output mode (raw data would break framing).
+pub struct UsbBench {
+    state: UsbBenchState,
+}
+
+impl UsbBench {
+    const LINE_LEN: usize = 64;
+
+
+    pub fn new() -> Self {
+        Self {
+            state: UsbBenchState::None,
+        }
+    }
+
+    pub fn start(&mut self, bytes: u32, usb_writer: &mut (impl Write + UsbWriter)) {
+        if !matches!(self.state, UsbBenchState::None) {
+            log_line!(usb_writer, "bench", "already running");
+            return;
+        }
+
+        if usb_writer.output_mode() != UsbOutputMode::Text {
+            log_line!(usb_writer, "bench", "only in text output mode");
+            return;
+        }
+
+        log_line!(usb_writer, "bench", "writing {} bytes", bytes);
+
+        usb_writer.reset_high_water_mark();
+
+        let now = SystemTimer::now();
+
+        self.state = UsbBenchState::Writing(UsbBenchRun {
+            bytes,
+            remaining: bytes,
+            started_at: now,
+            timeout_count_at_start: usb_writer.timeout_count(),
+            usb_interrupts_at_start: Self::usb_interrupt_count(),
+            usb_wakeups_at_start: interrupts::usb_wakeup_count(),
+            rng: (now as u32) | 1, // xorshift state must not be zero
+        });
+    }
+
+    pub fn stop(&mut self, usb_writer: &mut impl Write) {
+        if let UsbBenchState::Writing(run) | UsbBenchState::Draining(run) = self.state {
+            // ends the line of raw data
+            log_fmt!(usb_writer, "\n");
+            log_line!(usb_writer, "bench", "stopped after {} bytes", run.bytes - run.remaining);
+            self.state = UsbBenchState::None;
+        }
+    }
+
+    fn usb_interrupt_count() -> u32 {
+        interrupts::stats_snapshot()[InterruptSource::Usb as usize].count
+    }
+
+    fn next_random(rng: &mut u32) -> u32 {
+        // xorshift32
+        *rng ^= *rng << 13;
+        *rng ^= *rng >> 17;
+        *rng ^= *rng << 5;
+        *rng
+    }
+
+    /// Fills `line` with pseudo-random hex characters, last one is `'\n'`.
+    fn fill_line(rng: &mut u32, line: &mut [u8]) {
+        const HEX: &[u8; 16] = b"0123456789abcdef";
+
+        let (last, chars) = line.split_last_mut().unwrap();
+
+        for chunk in chars.chunks_mut(8) {
+            let mut random = Self::next_random(rng);
+
+            for c in chunk {
+                *c = HEX[(random & 0xf) as usize];
+                random >>= 4;
+            }
+        }
+
+        *last = b'\n';
+    }
+
+    fn report(run: &UsbBenchRun, usb_writer: &mut (impl Write + UsbWriter)) {
+        let elapsed = (SystemTimer::now() - run.started_at).max(1);
+        let elapsed_ms = elapsed * 1_000 / SystemTimer::TICKS_PER_SECOND;
+        let throughput = run.bytes as u64 * SystemTimer::TICKS_PER_SECOND / elapsed;
+
+        let capacity = usb_writer.free_space() + usb_writer.buffered_len();
+        let high_water_mark = usb_writer.high_water_mark();
+        let timeouts = usb_writer.timeout_count() - run.timeout_count_at_start;
+        // stats can be reset in the meantime
+        let usb_interrupts = Self::usb_interrupt_count().wrapping_sub(run.usb_interrupts_at_start);
+        let usb_wakeups = interrupts::usb_wakeup_count().wrapping_sub(run.usb_wakeups_at_start);
+
+        log_line!(
+            usb_writer,
+            "bench",
+            "{} bytes in {:.3} s ({} B/s), high water mark {} / {} bytes, timeouts {}, usb interrupts {}, wakeups {}",
+            run.bytes,
+            Milli(elapsed_ms as i64),
+            throughput,
+            high_water_mark,
+            capacity,
+            timeouts,
+            usb_interrupts,
+            usb_wakeups,
+        );
+    }
+
+    pub fn update(&mut self, usb_writer: &mut (impl Write + UsbWriter)) -> bool {
+        match &mut self.state {
+            UsbBenchState::Writing(run) => {
+                let mut did_something = false;
+
+                while run.remaining != 0 {
+                    let len = (run.remaining as usize).min(Self::LINE_LEN);
+
+                    if usb_writer.free_space() < len {
+                        break;
+                    }
+
+                    let mut line = [0u8; Self::LINE_LEN];
+                    let line = &mut line[..len];
+                    Self::fill_line(&mut run.rng, line);
+
+                    // cannot overflow, free space checked above
+                    let _ = usb_writer.write(line);
+
+                    run.remaining -= len as u32;
+                    did_something = true;
+                }
+
+                if run.remaining == 0 {
+                    self.state = UsbBenchState::Draining(*run);
+                    did_something = true;
+                }
+
+                did_something
+            },
+            UsbBenchState::Draining(run) => {
+                if usb_writer.buffered_len() != 0 {
+                    return false;
+                }
+
+                let run = *run;
+                self.state = UsbBenchState::None;
+
+                Self::report(&run, usb_writer);
+
+                true
+            },
+            UsbBenchState::None => false,
+        }
+    }
+}
+
+impl<'c, 'i, W, Q, const N: usize> Machine<Context<'c, 'i, W, Q, N>> for UsbBench
+where
+    W: Write + UsbWriter,
+    Q: TaggedQQAlarmQueue,
+{
+    fn name(&self) -> &'static str {
+        "usb bench"
+    }
+
+    fn update(&mut self, context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        Self::update(self, context.usb_writer)
+    }
+
+    fn on_command(&mut self, command: ConsoleCommand, context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        match command {
+            ConsoleCommand::Bench { bytes } => self.start(bytes, context.usb_writer),
+            // benchmark writes raw data, which would break framing
+            ConsoleCommand::BenchStop | ConsoleCommand::OutputMode(_) => self.stop(context.usb_writer),
+            _ => return false,
+        }
+
+        true
+    }
+}
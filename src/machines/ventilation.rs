@@ -0,0 +1,319 @@
+use core::fmt::Write;
+
+use embedded_hal::digital::OutputPin;
+
+use esp_hal::timer::systimer::SystemTimer;
+
+#[cfg(feature = "ventilation-pwm")]
+use esp_hal::{
+    gpio::{Output, OutputPin as HalOutputPin},
+    peripheral::{Peripheral, PeripheralRef},
+    peripherals::{LEDC, SYSTEM}
+};
+
+#[cfg(feature = "ventilation-pwm")]
+use crate::pac_utils::{gpio::PinNumber, ledc::{self as ledc_utils, LedcChannel, LedcTimerConfig}};
+use crate::{
+    fixed_point::Milli,
+    log::{info, log_line, Module},
+    qq_alarm_queue::{QQAlarmQueue, QQOwner, TaggedQQAlarmQueue},
+    scheduler::{Context, Machine},
+    usb_writer::UsbWriter
+};
+
+use super::{console::ConsoleCommand, controller::Controller, Delay};
+
+
+
+/// Manual override of ventilation (console command `vent`, ir remote), `Auto` follows co2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VentilationMode {
+    Auto,
+    On,
+    Off,
+}
+
+impl VentilationMode {
+    pub fn name(&self) -> &'static str {
+        match self {
+            VentilationMode::Auto => "auto",
+            VentilationMode::On => "on",
+            VentilationMode::Off => "off",
+        }
+    }
+
+    /// Mode after this one when modes are cycled with a single button (`ConsoleCommand::VentilationNext`).
+    pub fn next(&self) -> VentilationMode {
+        match self {
+            VentilationMode::Auto => VentilationMode::On,
+            VentilationMode::On => VentilationMode::Off,
+            VentilationMode::Off => VentilationMode::Auto,
+        }
+    }
+}
+
+
+/// Output driven by ventilation, either relay (any output pin, on at any speed) or fan with pwm speed input
+/// (see `PwmFan`).
+pub trait VentilationOutput {
+    /// `speed` in %, zero is off
+    fn set_speed(&mut self, speed: u8);
+}
+
+impl<T> VentilationOutput for T where T: OutputPin {
+    fn set_speed(&mut self, speed: u8) {
+        self.set_state((speed != 0).into()).unwrap();
+    }
+}
+
+
+/// Fan with pwm speed input (4 pin pc fan through open drain buffer, or mosfet of 2 pin fan), driven by ledc
+/// (timer 2, channel 4, 25 kHz).
+#[cfg(feature = "ventilation-pwm")]
+pub struct PwmFan<'d, PIN> {
+    ledc: PeripheralRef<'d, LEDC>,
+    // pin is kept only to stay owned, it is driven by ledc through gpio matrix
+    _pin: Output<'d, PIN>,
+}
+
+#[cfg(feature = "ventilation-pwm")]
+impl<'d, PIN> PwmFan<'d, PIN>
+where
+    PIN: HalOutputPin + PinNumber
+{
+    const TIMER: u8 = 2;
+    const CHANNEL: LedcChannel = LedcChannel::Ch4;
+    const DUTY_RES: u8 = 8;
+
+    pub fn new<'s>(
+        ledc: impl Peripheral<P = LEDC> + 'd,
+        system: impl Peripheral<P = SYSTEM> + 's,
+        pin: impl Peripheral<P = PIN> + 'd,
+    ) -> Self {
+        let mut ledc = ledc.into_ref();
+
+        ledc_utils::config_clock(system.into_ref());
+        ledc_utils::config(ledc.reborrow());
+        ledc_utils::timer_config(ledc.reborrow(), Self::TIMER, LedcTimerConfig {
+            clk_div: 25 << 7, // 80 MHz / 12.5 / 2^8 = 25 kHz
+            duty_res: Self::DUTY_RES,
+        });
+        ledc_utils::channel_config(ledc.reborrow(), Self::CHANNEL, Self::TIMER);
+
+        Self {
+            ledc,
+            _pin: ledc_utils::setup_output_pin(pin, Self::CHANNEL),
+        }
+    }
+}
+
+#[cfg(feature = "ventilation-pwm")]
+impl<'d, PIN> VentilationOutput for PwmFan<'d, PIN>
+where
+    PIN: HalOutputPin + PinNumber
+{
+    fn set_speed(&mut self, speed: u8) {
+        let max_duty = 1 << Self::DUTY_RES;
+        ledc_utils::set_duty(self.ledc.reborrow(), Self::CHANNEL, speed.min(100) as u32 * max_duty / 100);
+    }
+}
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct VentilationConfig {
+    /// ventilation is turned on when co2 is at or above `on` and turned off when co2 drops below `off` (in 10^-3 ppm)
+    pub on: i32,
+    pub off: i32,
+    /// co2 of full speed (in 10^-3 ppm), speed rises linearly from `min_speed` at `on` (only pwm fan uses speed)
+    pub full: i32,
+    /// in %
+    pub min_speed: u8,
+    /// ventilation turned on by co2 runs at least this long (in system timer ticks), so relay and fan are not switched
+    /// on and off by each measurment around thresholds
+    pub min_run: u64,
+}
+
+
+/// Closed loop co2 control, drives ventilation output by co2 of measurments processed by `Controller`.
+///
+/// In `VentilationMode::Auto` ventilation runs between `on` and `off` thresholds (hysteresis) for at least `min_run`,
+/// speed follows co2 (see `VentilationConfig`). Manual `On` (full speed) and `Off` override co2 until mode is set back
+/// to `Auto`. Each change of output is logged to usb.
+pub struct Ventilation<T> {
+    output: T,
+    config: VentilationConfig,
+    mode: VentilationMode,
+    /// speed of running ventilation in %, zero when stopped
+    speed: u8,
+    last_measurment_at: Option<u64>,
+    /// co2 of the last measurment (in 10^-3 ppm)
+    co2: Option<i32>,
+    /// minimal run time after ventilation was turned on by co2, it is not turned off by co2 until done
+    min_run: Delay,
+}
+
+impl<T> Ventilation<T> where T: VentilationOutput {
+    pub fn new(mut output: T, config: VentilationConfig) -> Self {
+        output.set_speed(0);
+
+        Self {
+            output,
+            config,
+            mode: VentilationMode::Auto,
+            speed: 0,
+            last_measurment_at: None,
+            co2: None,
+            min_run: Delay::Done,
+        }
+    }
+
+    /// Speed in auto mode given by `co2` (in 10^-3 ppm), `running` is the current state (hysteresis).
+    fn auto_speed(&self, co2: i32, running: bool) -> u8 {
+        if co2 < self.config.off || (!running && co2 < self.config.on) {
+            return 0;
+        }
+
+        let min_speed = self.config.min_speed.clamp(1, 100) as i32;
+        let range = (self.config.full - self.config.on).max(1);
+        let speed = min_speed + (co2 - self.config.on).clamp(0, range) * (100 - min_speed) / range;
+
+        speed as u8
+    }
+
+    fn target_speed(&self) -> u8 {
+        match self.mode {
+            VentilationMode::On => 100,
+            VentilationMode::Off => 0,
+            VentilationMode::Auto => {
+                let speed = self.co2.map_or(0, |co2| self.auto_speed(co2, self.speed != 0));
+
+                // stopping waits for minimal run time
+                if speed == 0 && self.speed != 0 && self.min_run != Delay::Done {
+                    self.config.min_speed.clamp(1, 100)
+                } else {
+                    speed
+                }
+            },
+        }
+    }
+
+    fn apply(&mut self, usb_writer: &mut impl Write, qq: &mut impl QQAlarmQueue) {
+        let speed = self.target_speed();
+        if speed == self.speed {
+            return;
+        }
+
+        let co2 = Milli::from(self.co2.unwrap_or(0));
+        match (self.speed, speed) {
+            (0, _) => info!(usb_writer, Module::Ventilation, "on at {} % (co2 {:.1} ppm, {})", speed, co2, self.mode.name()),
+            (_, 0) => info!(usb_writer, Module::Ventilation, "off (co2 {:.1} ppm, {})", co2, self.mode.name()),
+            _ => info!(usb_writer, Module::Ventilation, "speed {} % (co2 {:.1} ppm)", speed, co2),
+        }
+
+        if self.speed == 0 && self.mode == VentilationMode::Auto {
+            self.cancel_min_run(qq);
+            self.min_run = Delay::start(qq, SystemTimer::now() + self.config.min_run);
+        }
+
+        self.speed = speed;
+        self.output.set_speed(speed);
+    }
+
+    fn cancel_min_run(&mut self, qq: &mut impl QQAlarmQueue) {
+        if let Delay::Waiting { qq_alarm_id } = self.min_run {
+            let _ = qq.remove(qq_alarm_id);
+        }
+
+        self.min_run = Delay::Done;
+    }
+
+    /// Manual override, `VentilationMode::Auto` returns control to co2 (minimal run time does not apply to manual modes).
+    pub fn set_mode(&mut self, usb_writer: &mut impl Write, qq: &mut impl QQAlarmQueue, mode: VentilationMode) {
+        info!(usb_writer, Module::Ventilation, "mode {}", mode.name());
+
+        self.cancel_min_run(qq);
+        self.mode = mode;
+        self.apply(usb_writer, qq);
+    }
+
+    pub fn mode(&self) -> VentilationMode {
+        self.mode
+    }
+
+    /// Current speed in %, zero when ventilation is off.
+    pub fn speed(&self) -> u8 {
+        self.speed
+    }
+
+    pub fn update<const N: usize>(&mut self, usb_writer: &mut impl Write, qq: &mut impl QQAlarmQueue, controller: &Controller<N>) -> bool {
+        let last_measurment_at = controller.last_measurment_at();
+
+        if last_measurment_at != self.last_measurment_at {
+            self.last_measurment_at = last_measurment_at;
+            self.co2 = controller.last_co2();
+            self.apply(usb_writer, qq);
+
+            return true;
+        }
+
+        match self.min_run {
+            // minimal run time elapsed, co2 could drop below `off` meanwhile
+            Delay::Done if self.speed != self.target_speed() => {
+                self.apply(usb_writer, qq);
+                true
+            },
+            Delay::Retry { .. } => self.min_run.retry(qq),
+            _ => false,
+        }
+    }
+
+    pub fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        self.min_run.on_alarm(qq_alarm_id)
+    }
+
+    /// Mode commands (`vent`, `vent on|off|auto|next`).
+    pub fn on_command(&mut self, command: ConsoleCommand, usb_writer: &mut impl Write, qq: &mut impl QQAlarmQueue) -> bool {
+        match command {
+            ConsoleCommand::VentilationStatus => {
+                log_line!(usb_writer, "ventilation", "mode {}, speed {} %", self.mode().name(), self.speed());
+            },
+            ConsoleCommand::Ventilation(mode) => self.set_mode(usb_writer, qq, mode),
+            ConsoleCommand::VentilationNext => self.set_mode(usb_writer, qq, self.mode.next()),
+            // validated by `commands::handle`, used from the next measurment
+            ConsoleCommand::VentilationThresholds { on, off } => {
+                self.config.on = on;
+                self.config.off = off;
+            },
+            _ => return false,
+        }
+
+        true
+    }
+}
+
+impl<'c, 'i, T, W, Q, const N: usize> Machine<Context<'c, 'i, W, Q, N>> for Ventilation<T>
+where
+    T: VentilationOutput,
+    W: Write + UsbWriter,
+    Q: TaggedQQAlarmQueue,
+{
+    fn name(&self) -> &'static str {
+        "ventilation"
+    }
+
+    fn owns(&self, owner: QQOwner) -> bool {
+        owner == QQOwner::Ventilation
+    }
+
+    fn update(&mut self, context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        Self::update(self, context.usb_writer, &mut context.qq.owned(QQOwner::Ventilation), context.controller)
+    }
+
+    fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        Self::on_alarm(self, qq_alarm_id)
+    }
+
+    fn on_command(&mut self, command: ConsoleCommand, context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        Self::on_command(self, command, context.usb_writer, &mut context.qq.owned(QQOwner::Ventilation))
+    }
+}
@@ -0,0 +1,137 @@
+use core::fmt::Write;
+
+use esp_hal::{peripherals::TIMG1, timer::{systimer::SystemTimer, timg::Wdt}, Blocking};
+
+use fugit::MicrosDurationU64;
+
+use crate::{
+    error_registry::{self, Subsystem},
+    heartbeat,
+    invariants::invariant,
+    log::{error, Module},
+    qq_alarm_queue::{QQAlarmQueue, QQOwner, TaggedQQAlarmQueue},
+    scheduler::{Context, Machine},
+    usb_writer::UsbWriter,
+};
+
+use super::Ticker;
+
+
+
+#[derive(Clone, Copy, Debug)]
+pub struct WatchdogConfig {
+    /// chip is reset when the watchdog is not fed for this long (in system timer ticks)
+    pub timeout: u64,
+    /// has to be shorter than `timeout` (in system timer ticks)
+    pub feed_period: u64,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum WatchdogState {
+    None,
+    Feeding(Ticker),
+    /// heartbeat was missed, watchdog is not fed any more and resets the chip after its timeout
+    Starving,
+}
+
+/// Feeds timer group 1 watchdog (MWDT) from the main loop, chip is reset when the loop stalls.
+///
+/// Machines registered in `heartbeat` are checked on every update, when one of them misses its heartbeat it is reported
+/// (log line and error registry) and the watchdog is not fed any more, so the report reaches the host before the reset.
+/// Ticker wakes the main loop up, so the watchdog is fed even when there is nothing else to do.
+pub struct Watchdog {
+    wdt: Wdt<TIMG1, Blocking>,
+    config: WatchdogConfig,
+    state: WatchdogState,
+}
+
+impl Watchdog {
+    /// error code is this + `Heartbeat` index
+    pub const ERROR_CODE_HEARTBEAT: u16 = 0xa0;
+
+
+    pub fn new(wdt: Wdt<TIMG1, Blocking>, config: WatchdogConfig) -> Self {
+        Self {
+            wdt,
+            config,
+            state: WatchdogState::None,
+        }
+    }
+
+    /// Enables the watchdog.
+    pub fn start(&mut self, qq: &mut impl QQAlarmQueue) {
+        if self.state != WatchdogState::None {
+            return;
+        }
+
+        self.wdt.set_timeout(MicrosDurationU64::micros(self.config.timeout * 1_000_000 / SystemTimer::TICKS_PER_SECOND));
+        self.wdt.enable();
+
+        self.state = WatchdogState::Feeding(Ticker::start(qq, self.config.feed_period, SystemTimer::now() + self.config.feed_period));
+    }
+
+    pub fn update(&mut self, usb_writer: &mut impl Write, qq: &mut impl QQAlarmQueue) -> bool {
+        let WatchdogState::Feeding(ticker) = &mut self.state else {
+            return false;
+        };
+
+        let now = SystemTimer::now();
+
+        if let Some((heartbeat, since)) = heartbeat::overdue(now) {
+            if let Ticker::Running { qq_alarm_id, .. } = *ticker {
+                invariant!(qq.remove(qq_alarm_id).is_ok(), "watchdog alarm not found in qq");
+            }
+
+            error!(
+                usb_writer,
+                Module::Main,
+                "watchdog : {} missed heartbeat ({} ms since last), reset in {} ms",
+                heartbeat.name(),
+                since * 1_000 / SystemTimer::TICKS_PER_SECOND,
+                self.config.timeout * 1_000 / SystemTimer::TICKS_PER_SECOND,
+            );
+            error_registry::record(Subsystem::Watchdog, Self::ERROR_CODE_HEARTBEAT + heartbeat as u16);
+
+            self.state = WatchdogState::Starving;
+            return true;
+        }
+
+        let mut did_something = ticker.retry(qq);
+
+        if ticker.take_tick() {
+            self.wdt.feed();
+            did_something = true;
+        }
+
+        did_something
+    }
+
+    pub fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        match &mut self.state {
+            WatchdogState::Feeding(ticker) => ticker.on_alarm(qq_alarm_id),
+            WatchdogState::None | WatchdogState::Starving => false,
+        }
+    }
+}
+
+impl<'c, 'i, W, Q, const N: usize> Machine<Context<'c, 'i, W, Q, N>> for Watchdog
+where
+    W: Write + UsbWriter,
+    Q: TaggedQQAlarmQueue,
+{
+    fn name(&self) -> &'static str {
+        "watchdog"
+    }
+
+    fn owns(&self, owner: QQOwner) -> bool {
+        owner == QQOwner::Watchdog
+    }
+
+    fn update(&mut self, context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        Self::update(self, context.usb_writer, &mut context.qq.owned(QQOwner::Watchdog))
+    }
+
+    fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        Self::on_alarm(self, qq_alarm_id)
+    }
+}
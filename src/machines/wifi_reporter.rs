@@ -0,0 +1,298 @@
+use core::fmt::Write;
+
+use esp_hal::timer::systimer::SystemTimer;
+
+use esp_wifi::wifi::{AuthMethod, ClientConfiguration, Configuration, WifiController, WifiError};
+use heapless::Vec;
+use smoltcp::{iface::SocketHandle, socket::udp, wire::{IpAddress, IpEndpoint}};
+
+use crate::{
+    error_registry::{self, ErrorCode, Subsystem},
+    invariants::invariant,
+    log::{error, info, warn, Module},
+    net::NetStack,
+    qq_alarm_queue::QQAlarmQueue,
+    record::{BinaryEncoding, Encoding},
+};
+
+use super::{console::ConsoleCommand, controller::Controller, Delay, Ticker};
+
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct WifiReporterConfig {
+    pub ssid: &'static str,
+    /// empty for open network
+    pub password: &'static str,
+    /// receiver of measurment packets
+    pub host: [u8; 4],
+    pub port: u16,
+    /// period of sending the latest measurment (in system timer ticks)
+    pub period: u64,
+    /// association and dhcp have to finish within this time (in system timer ticks)
+    pub connect_timeout: u64,
+    /// delay before connecting again after connection failed or was lost (in system timer ticks)
+    pub reconnect_delay: u64,
+}
+
+
+// esp-wifi and smoltcp do not know about the registry, codes of their errors are defined here
+
+impl ErrorCode for WifiError {
+    fn error_code(&self) -> u16 {
+        match self {
+            WifiError::NotInitialized => 0x70,
+            WifiError::InternalError(_) => 0x71,
+            WifiError::Disconnected => 0x72,
+            WifiError::UnknownWifiMode => 0x73,
+        }
+    }
+}
+
+impl ErrorCode for udp::BindError {
+    fn error_code(&self) -> u16 {
+        0x78
+    }
+}
+
+impl ErrorCode for udp::SendError {
+    fn error_code(&self) -> u16 {
+        0x79
+    }
+}
+
+
+#[derive(Debug, Clone, Copy)]
+enum WifiReporterState {
+    /// `start` was not called
+    Stopped,
+    /// waiting for association with access point, delay is the connect timeout
+    Associating(Delay),
+    /// associated, waiting for ip address from dhcp, delay is the connect timeout
+    WaitingForIp(Delay),
+    Connected {
+        ticker: Ticker,
+        /// `at` of the last sent record, same record is not sent twice
+        last_sent_at: Option<u64>,
+    },
+    /// connection failed or was lost, connecting again when delay is done
+    Backoff(Delay),
+}
+
+/// Connects to wifi access point (sta mode) and periodically sends the latest measurment (`BinaryEncoding`) as udp packet.
+///
+/// Connection (association and dhcp of `NetStack`) is kept by polling the wifi controller in `update`,
+/// lost connection is retried after `reconnect_delay`. Other network machines wait until the stack is up.
+/// Sending does not wait for acknowledgement, packets sent while connection is being lost are silently lost.
+pub struct WifiReporter {
+    controller: WifiController<'static>,
+    udp: SocketHandle,
+    config: WifiReporterConfig,
+    state: WifiReporterState,
+}
+
+impl WifiReporter {
+    pub const ERROR_CODE_ASSOCIATION_TIMEOUT: u16 = 0x7a;
+    pub const ERROR_CODE_DHCP_TIMEOUT: u16 = 0x7b;
+
+
+    /// `udp` is handle of udp socket in the `NetStack` passed to `update`.
+    pub fn new(controller: WifiController<'static>, udp: SocketHandle, config: WifiReporterConfig) -> Self {
+        Self {
+            controller,
+            udp,
+            config,
+            state: WifiReporterState::Stopped,
+        }
+    }
+
+    /// Configures and starts wifi controller and starts connecting.
+    pub fn start(&mut self, usb_writer: &mut impl Write, qq: &mut impl QQAlarmQueue, net: &mut NetStack) {
+        let client_config = Configuration::Client(ClientConfiguration {
+            ssid: self.config.ssid.try_into().unwrap_or_default(),
+            password: self.config.password.try_into().unwrap_or_default(),
+            auth_method: if self.config.password.is_empty() { AuthMethod::None } else { AuthMethod::WPAWPA2Personal },
+            ..Default::default()
+        });
+
+        if let Err(err) = self.controller.set_configuration(&client_config).and_then(|()| self.controller.start()) {
+            error_registry::record_error(Subsystem::Wifi, &err);
+            error!(usb_writer, Module::Wifi, "start failed ({:?})", err);
+            return;
+        }
+
+        // local port is the same as the port of the host
+        if let Err(err) = net.udp(self.udp).bind(self.config.port) {
+            error_registry::record_error(Subsystem::Wifi, &err);
+            error!(usb_writer, Module::Wifi, "udp bind failed ({:?})", err);
+            return;
+        }
+
+        self.connect(usb_writer, qq, net);
+    }
+
+    fn connect(&mut self, usb_writer: &mut impl Write, qq: &mut impl QQAlarmQueue, net: &mut NetStack) {
+        info!(usb_writer, Module::Wifi, "connecting to {}", self.config.ssid);
+
+        if let Err(err) = self.controller.connect() {
+            self.fail(usb_writer, qq, net, &err);
+            return;
+        }
+
+        self.state = WifiReporterState::Associating(Delay::start(qq, SystemTimer::now() + self.config.connect_timeout));
+    }
+
+    /// Removes alarm of current state (delay or ticker).
+    fn cancel_alarm(&mut self, qq: &mut impl QQAlarmQueue) {
+        match self.state {
+            WifiReporterState::Associating(Delay::Waiting { qq_alarm_id })
+            | WifiReporterState::WaitingForIp(Delay::Waiting { qq_alarm_id })
+            | WifiReporterState::Backoff(Delay::Waiting { qq_alarm_id })
+            | WifiReporterState::Connected { ticker: Ticker::Running { qq_alarm_id, .. }, .. } => {
+                invariant!(qq.remove(qq_alarm_id).is_ok(), "wifi reporter alarm not found in qq");
+            },
+            _ => {},
+        }
+    }
+
+    fn fail(&mut self, usb_writer: &mut impl Write, qq: &mut impl QQAlarmQueue, net: &mut NetStack, error: &impl ErrorCode) {
+        error_registry::record_error(Subsystem::Wifi, error);
+        self.backoff(usb_writer, qq, net);
+    }
+
+    fn backoff(&mut self, usb_writer: &mut impl Write, qq: &mut impl QQAlarmQueue, net: &mut NetStack) {
+        self.cancel_alarm(qq);
+
+        // controller may still try to associate, next `connect` starts from scratch
+        let _ = self.controller.disconnect();
+        net.reset();
+
+        warn!(usb_writer, Module::Wifi, "disconnected, reconnecting in {} s", self.config.reconnect_delay / SystemTimer::TICKS_PER_SECOND);
+        self.state = WifiReporterState::Backoff(Delay::start(qq, SystemTimer::now() + self.config.reconnect_delay));
+    }
+
+    fn send_latest<const N: usize>(&mut self, net: &mut NetStack, controller: &Controller<N>, last_sent_at: &mut Option<u64>) {
+        let Some(record) = controller.last_record() else {
+            return;
+        };
+
+        if *last_sent_at == Some(record.at) {
+            return;
+        }
+
+        let mut packet = Vec::new();
+        BinaryEncoding.encode(&record, &mut packet);
+
+        let [a, b, c, d] = self.config.host;
+
+        match net.udp(self.udp).send_slice(&packet, IpEndpoint::new(IpAddress::v4(a, b, c, d), self.config.port)) {
+            Ok(()) => *last_sent_at = Some(record.at),
+            Err(err) => error_registry::record_error(Subsystem::Wifi, &err),
+        }
+    }
+
+    /// `net` has to be updated before (dhcp).
+    pub fn update<const N: usize>(&mut self, usb_writer: &mut impl Write, qq: &mut impl QQAlarmQueue, net: &mut NetStack, controller: &Controller<N>) -> bool {
+        if matches!(self.state, WifiReporterState::Stopped) {
+            return false;
+        }
+
+        let connected = self.controller.is_connected();
+
+        match self.state {
+            WifiReporterState::Stopped => false,
+            WifiReporterState::Associating(mut delay) => match connected {
+                Ok(true) => {
+                    self.cancel_alarm(qq);
+                    info!(usb_writer, Module::Wifi, "associated, waiting for ip address");
+                    self.state = WifiReporterState::WaitingForIp(Delay::start(qq, SystemTimer::now() + self.config.connect_timeout));
+                    true
+                },
+                Err(err) => {
+                    self.fail(usb_writer, qq, net, &err);
+                    true
+                },
+                Ok(false) if delay == Delay::Done => {
+                    error_registry::record(Subsystem::Wifi, Self::ERROR_CODE_ASSOCIATION_TIMEOUT);
+                    self.backoff(usb_writer, qq, net);
+                    true
+                },
+                Ok(false) => {
+                    let did_something = delay.retry(qq);
+                    self.state = WifiReporterState::Associating(delay);
+                    did_something
+                },
+            },
+            WifiReporterState::WaitingForIp(mut delay) => {
+                if let Err(err) = connected {
+                    self.fail(usb_writer, qq, net, &err);
+                    return true;
+                }
+
+                if let Some(address) = net.address() {
+                    self.cancel_alarm(qq);
+
+                    info!(usb_writer, Module::Wifi, "connected, ip {}", address);
+
+                    self.state = WifiReporterState::Connected { ticker: Ticker::start(qq, self.config.period, 0), last_sent_at: None };
+                    return true;
+                }
+
+                if delay == Delay::Done {
+                    error_registry::record(Subsystem::Wifi, Self::ERROR_CODE_DHCP_TIMEOUT);
+                    self.backoff(usb_writer, qq, net);
+                    return true;
+                }
+
+                let did_something = delay.retry(qq);
+                self.state = WifiReporterState::WaitingForIp(delay);
+                did_something
+            },
+            WifiReporterState::Connected { mut ticker, mut last_sent_at } => {
+                if !matches!(connected, Ok(true)) {
+                    warn!(usb_writer, Module::Wifi, "connection lost");
+                    self.backoff(usb_writer, qq, net);
+                    return true;
+                }
+
+                let mut did_something = ticker.retry(qq);
+
+                if ticker.take_tick() {
+                    self.send_latest(net, controller, &mut last_sent_at);
+                    did_something = true;
+                }
+
+                self.state = WifiReporterState::Connected { ticker, last_sent_at };
+                did_something
+            },
+            WifiReporterState::Backoff(mut delay) => {
+                if delay == Delay::Done {
+                    self.connect(usb_writer, qq, net);
+                    return true;
+                }
+
+                let did_something = delay.retry(qq);
+                self.state = WifiReporterState::Backoff(delay);
+                did_something
+            },
+        }
+    }
+
+    /// New host is used from the next packet.
+    pub fn on_command(&mut self, command: ConsoleCommand) -> bool {
+        match command {
+            ConsoleCommand::ReportHost { host } => self.config.host = host,
+            _ => return false,
+        }
+
+        true
+    }
+
+    pub fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        match &mut self.state {
+            WifiReporterState::Associating(delay) | WifiReporterState::WaitingForIp(delay) | WifiReporterState::Backoff(delay) => delay.on_alarm(qq_alarm_id),
+            WifiReporterState::Connected { ticker, .. } => ticker.on_alarm(qq_alarm_id),
+            WifiReporterState::Stopped => false,
+        }
+    }
+}
@@ -0,0 +1,261 @@
+use core::fmt::Write;
+
+use esp_hal::{dma::{Channel0, DmaTransferTxOwned}, peripherals::SPI2, spi::{master::dma::SpiDma, FullDuplexMode}, timer::systimer::SystemTimer, Blocking};
+
+use crate::{
+    error_registry::{self, Subsystem},
+    invariants::invariant,
+    log::{debug, error, Module},
+    qq_alarm_queue::{QQAlarmQueue, QQOwner, TaggedQQAlarmQueue},
+    scheduler::{Context, Machine},
+    usb_writer::UsbWriter
+};
+
+use super::{console::ConsoleCommand, controller::Controller, status_led::Color, Delay};
+
+
+
+/// Spi (2.5 MHz, 0.4 us per bit) with dma channel sending the frame, only mosi pin is connected.
+pub type LedStripSpi<'d> = SpiDma<'d, SPI2, Channel0, FullDuplexMode, Blocking>;
+
+/// Spi bits of one data bit, 0.4 us high + 0.8 us low or 0.8 us high + 0.4 us low.
+const SPI_BITS_PER_BIT: usize = 3;
+const BYTES_PER_PIXEL: usize = 3 * SPI_BITS_PER_BIT;
+
+/// Length of frame buffer of strip with `pixels` (in bytes), see `Ws2812::new`.
+pub const fn frame_len(pixels: usize) -> usize {
+    pixels * BYTES_PER_PIXEL
+}
+
+
+#[derive(Debug, Clone, Copy)]
+pub struct Ws2812Config {
+    /// scale of all colors (255 is full brightness)
+    pub brightness: u8,
+    /// co2 of one lit pixel and of full bar (in 10^-3 ppm)
+    pub co2_min: i32,
+    pub co2_max: i32,
+    /// pixels of bar at or above these levels are yellow and red (in 10^-3 ppm, same as `AlertConfig`)
+    pub warning: i32,
+    pub critical: i32,
+}
+
+enum Ws2812State {
+    Idle,
+    Sending,
+    /// data line is held low after the frame, pixels latch their colors
+    Latch(Delay),
+    Error,
+}
+
+/// Addressable led strip (ws2812, grb order) showing the last co2 as bar graph, pixels are colored by co2 level of
+/// their position.
+///
+/// Pixels are set in back buffer, frame is encoded (with brightness scaling) into frame buffer and sent by spi2 mosi
+/// with dma (rmt tx channels are used by ir transmitters), changes made while sending are sent by the next frame.
+pub struct Ws2812<'d, const PIXELS: usize> {
+    /// spi and frame buffer while no frame is sent, `None` while they are owned by `transfer`
+    idle: Option<(LedStripSpi<'d>, &'static mut [u8])>,
+    transfer: Option<DmaTransferTxOwned<LedStripSpi<'d>, &'static mut [u8]>>,
+    config: Ws2812Config,
+    pixels: [Color; PIXELS],
+    /// pixels were changed since the last frame
+    dirty: bool,
+    last_measurment_at: Option<u64>,
+    state: Ws2812State,
+}
+
+impl<'d, const PIXELS: usize> Ws2812<'d, PIXELS> {
+    /// dma transfer did not start (frame is longer than dma maximum)
+    pub const ERROR_CODE_START: u16 = 0xb0;
+    /// dma descriptor error while sending
+    pub const ERROR_CODE_DMA: u16 = 0xb1;
+
+    /// spi bits of data bit 0 and 1 (msb first)
+    const ZERO: u8 = 0b100;
+    const ONE: u8 = 0b110;
+
+    /// reset time is at least 280 us
+    const LATCH: u64 = SystemTimer::TICKS_PER_SECOND / 1000;
+
+
+    /// `frame` has to be `frame_len(PIXELS)` bytes long (e.g. tx buffer of `dma_buffers!`).
+    pub fn new(spi: LedStripSpi<'d>, frame: &'static mut [u8], config: Ws2812Config) -> Self {
+        invariant!(frame.len() == frame_len(PIXELS), "led strip frame buffer length does not match pixels");
+
+        Self {
+            idle: Some((spi, frame)),
+            transfer: None,
+            config,
+            pixels: [Color::OFF; PIXELS],
+            // strip keeps colors from before reset
+            dirty: true,
+            last_measurment_at: None,
+            state: Ws2812State::Idle,
+        }
+    }
+
+    fn level_color(&self, co2: i32) -> Color {
+        if co2 >= self.config.critical {
+            Color::RED
+        } else if co2 >= self.config.warning {
+            Color::YELLOW
+        } else {
+            Color::GREEN
+        }
+    }
+
+    /// Bar of `co2` into back buffer, at least one pixel is lit.
+    fn render_bar(&mut self, co2: i32) {
+        let range = (self.config.co2_max - self.config.co2_min).max(1) as i64;
+        let lit = ((co2 - self.config.co2_min) as i64 * PIXELS as i64 / range + 1).clamp(1, PIXELS as i64) as usize;
+
+        for i in 0..PIXELS {
+            let pixel_co2 = self.config.co2_min + (range * i as i64 / PIXELS as i64) as i32;
+
+            self.pixels[i] = if i < lit { self.level_color(pixel_co2) } else { Color::OFF };
+        }
+
+        self.dirty = true;
+    }
+
+    /// Encodes back buffer (grb, scaled by brightness) into frame buffer and starts sending it.
+    fn send_frame(&mut self, usb_writer: &mut impl Write) {
+        let Some((spi, frame)) = self.idle.take() else {
+            invariant!(false, "led strip frame started while sending");
+            self.state = Ws2812State::Error;
+            return;
+        };
+
+        let scale = |value: u8| (value as u16 * self.config.brightness as u16 / 255) as u8;
+
+        let bytes = self.pixels.iter().flat_map(|color| [scale(color.g), scale(color.r), scale(color.b)]);
+        for (byte, chunk) in bytes.zip(frame.chunks_exact_mut(SPI_BITS_PER_BIT)) {
+            // 8 data bits (msb first) into 24 spi bits
+            let mut bits = 0u32;
+            for i in (0..8).rev() {
+                let code = if (byte >> i) & 1 != 0 { Self::ONE } else { Self::ZERO };
+                bits = (bits << SPI_BITS_PER_BIT) | code as u32;
+            }
+
+            chunk.copy_from_slice(&bits.to_be_bytes()[1..]);
+        }
+
+        self.dirty = false;
+
+        match spi.dma_write_owned(frame) {
+            Ok(transfer) => {
+                self.transfer = Some(transfer);
+                self.state = Ws2812State::Sending;
+            },
+            Err(err) => {
+                error!(usb_writer, Module::Status, "led strip dma start error : {:?}", err);
+                error_registry::record(Subsystem::LedStrip, Self::ERROR_CODE_START);
+
+                // spi and frame buffer were consumed by `dma_write_owned`, machine stays in error
+                self.state = Ws2812State::Error;
+            },
+        }
+    }
+
+    pub fn update<const N: usize>(&mut self, usb_writer: &mut impl Write, qq: &mut impl QQAlarmQueue, controller: &Controller<N>) -> bool {
+        let mut did_something = false;
+
+        let last_measurment_at = controller.last_measurment_at();
+        if last_measurment_at != self.last_measurment_at {
+            self.last_measurment_at = last_measurment_at;
+
+            if let Some(co2) = controller.last_co2() {
+                self.render_bar(co2);
+            }
+
+            did_something = true;
+        }
+
+        match &mut self.state {
+            Ws2812State::Idle if self.dirty => {
+                debug!(usb_writer, Module::Status, "led strip frame");
+                self.send_frame(usb_writer);
+                true
+            },
+            Ws2812State::Sending => {
+                if self.transfer.as_mut().is_some_and(|transfer| !transfer.is_done()) {
+                    return did_something;
+                }
+
+                // transfer is done, `wait` returns right away
+                match self.transfer.take().map(|transfer| transfer.wait()) {
+                    Some(Ok(idle)) => {
+                        self.idle = Some(idle);
+                        self.state = Ws2812State::Latch(Delay::start(qq, SystemTimer::now() + Self::LATCH));
+                    },
+                    Some(Err((err, spi, frame))) => {
+                        error!(usb_writer, Module::Status, "led strip dma error : {:?}", err);
+                        error_registry::record(Subsystem::LedStrip, Self::ERROR_CODE_DMA);
+
+                        self.idle = Some((spi, frame));
+                        self.state = Ws2812State::Error;
+                    },
+                    None => {
+                        invariant!(false, "led strip sending without transfer");
+                        self.state = Ws2812State::Error;
+                    },
+                }
+
+                true
+            },
+            Ws2812State::Latch(Delay::Done) => {
+                self.state = Ws2812State::Idle;
+                true
+            },
+            Ws2812State::Latch(delay) => did_something | delay.retry(qq),
+            Ws2812State::Idle | Ws2812State::Error => did_something,
+        }
+    }
+
+    pub fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        match &mut self.state {
+            Ws2812State::Latch(delay) => delay.on_alarm(qq_alarm_id),
+            _ => false,
+        }
+    }
+
+    pub fn on_command(&mut self, command: ConsoleCommand) -> bool {
+        match command {
+            // colors follow alert thresholds (validated by `commands::handle`)
+            ConsoleCommand::AlertThresholds { warning, critical, .. } => {
+                self.config.warning = warning;
+                self.config.critical = critical;
+            },
+            _ => return false,
+        }
+
+        true
+    }
+}
+
+impl<'c, 'i, 'd, const PIXELS: usize, W, Q, const N: usize> Machine<Context<'c, 'i, W, Q, N>> for Ws2812<'d, PIXELS>
+where
+    W: Write + UsbWriter,
+    Q: TaggedQQAlarmQueue,
+{
+    fn name(&self) -> &'static str {
+        "led strip"
+    }
+
+    fn owns(&self, owner: QQOwner) -> bool {
+        owner == QQOwner::LedStrip
+    }
+
+    fn update(&mut self, context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        Self::update(self, context.usb_writer, &mut context.qq.owned(QQOwner::LedStrip), context.controller)
+    }
+
+    fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        Self::on_alarm(self, qq_alarm_id)
+    }
+
+    fn on_command(&mut self, command: ConsoleCommand, _context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        Self::on_command(self, command)
+    }
+}
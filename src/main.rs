@@ -5,36 +5,278 @@
 #![feature(let_chains)]
 #![feature(iter_array_chunks)]
 
+// hardware drivers replaced by mocks (or usb writer replaced by uart writer) are not used
+#![cfg_attr(any(feature = "mock-hw", feature = "uart-output"), allow(dead_code))]
 
 
 
-use core::fmt::Write;
 
-
-use esp_hal::{clock::ClockControl, gpio::{Io, Level, Output}, interrupt::Priority, peripherals::{Peripherals, SYSTEM}, prelude::*, system::SystemControl, timer::systimer::SystemTimer};
+use esp_hal::{clock::ClockControl, gpio::Io, interrupt::Priority, peripherals::{Peripherals, RMT, SYSTEM, USB_DEVICE}, prelude::*, rng::Rng, system::SystemControl, timer::{systimer::SystemTimer, timg::TimerGroup, PeriodicTimer}};
 use esp_backtrace as _;
+use esp_wifi::{wifi::WifiStaDevice, EspWifiInitFor};
+use smoltcp::{iface::SocketStorage, socket::{tcp, udp}};
 
 use fugit::ExtU32;
 
-
-use qq_alarm_queue::DumbQQAlarmQueue;
-use usb_writer::RingBufferUsbWriter;
-
-use machines::{controller::Controller, debug_print::DebugPrint, ir_nec_rx::IrNecRx, sdc_simple_measurment::{SDCSimpleMeasurment, SDCSimpleMeasurmentConfig}, status_led::{StatusLed, StatusLedConfig}};
-
-
-
-mod ring_buffer;
+use rust_esp_logic::{alarm_heap, alarm_table, bus_timing, config_store, fixed_point, flash, flash_log, framing, http, humidity, invariants, ir, ir_learning, measurment, mqtt, record, ring_buffer, sensirion_word, sensor_history, snapshot, sony_ir, summary, wall_clock};
+#[cfg(feature = "bme280")]
+use rust_esp_logic::bme280;
+#[cfg(feature = "oled-display")]
+use rust_esp_logic::framebuffer;
+#[cfg(feature = "async-sdc")]
+use rust_esp_logic::{executor, lend};
+
+
+use board::BoardPins;
+use rom_flash::RomFlash;
+use config_store::{Config, ConfigStore, IrBindings, IrKeyBinding};
+use ir::IrProtocol;
+use ir_learning::IrCodeStore;
+use error_registry::Subsystem;
+use i2c_bus::{I2CBus, I2CBusUser};
+use interrupts::{InterruptSource, PriorityTable};
+#[cfg(feature = "async-sdc")]
+use invariants::invariant;
+use log::{error, info, Module};
+#[cfg(any(not(feature = "async-sdc"), feature = "second-sdc"))]
+use sdc::SensorId;
+#[cfg(not(feature = "mock-hw"))]
+use qq_alarm_queue::HeapQQAlarmQueue;
+use qq_alarm_queue::{QQOwner, TaggedQQAlarmQueue};
+#[cfg(not(feature = "mock-hw"))]
+use usb_writer::OverflowPolicy;
+#[cfg(not(any(feature = "mock-hw", feature = "uart-output")))]
+use usb_writer::{RingBufferUsbWriter, RingBufferUsbWriterConfig};
+#[cfg(all(feature = "uart-output", not(feature = "mock-hw")))]
+use uart_writer::{UartWriter, UartWriterConfig};
+use usb_writer::UsbOutputMode;
+#[cfg(not(feature = "mock-hw"))]
+use panic::PanicOutput;
+
+use net::NetStack;
+use machines::{alert::{Alert, AlertConfig}, button::{Button, ButtonBinding, ButtonConfig, ButtonEvent}, console::{Console, ConsoleCommand}, controller::{Controller, FilterConfig, TrendConfig}, debug_print::DebugPrint, flash_logger::FlashLogger, http_server::HttpServer, ir_rx_dispatch::{IrRxDispatch, IrRxDispatchConfig}, ir_sony_tx::IrSonyTx, measurment_dump::MeasurmentDump, mqtt_client::{MqttClient, MqttClientConfig, MqttTopics}, network::Network, staleness_monitor::StalenessMonitor, status_led::{StatusLed, StatusLedConfig}, usb_bench::UsbBench, ventilation::{Ventilation, VentilationConfig}, watchdog::{Watchdog, WatchdogConfig}, wifi_reporter::{WifiReporter, WifiReporterConfig}};
+use machines::ambient_sensor::{AmbientSensor, AmbientSensorConfig, Sht3x};
+#[cfg(any(not(feature = "async-sdc"), feature = "second-sdc"))]
+use machines::sdc_simple_measurment::{ReadyMode, SDCSimpleMeasurment, SDCSimpleMeasurmentConfig};
+#[cfg(feature = "second-sdc")]
+use machines::sdc_simple_measurment::SecondarySDC;
+#[cfg(feature = "second-sdc")]
+use soft_i2c::SoftI2CBus;
+#[cfg(feature = "async-sdc")]
+use machines::{async_tasks::AsyncTasks, sdc_async::{self, SDCTaskConfig}};
+#[cfg(feature = "async-sdc")]
+use async_io::AsyncIo;
+#[cfg(feature = "bme280")]
+use machines::bme280::{Bme280, Bme280Config};
+#[cfg(feature = "rgb-led")]
+use machines::status_led::RgbLed;
+use machines::ir_nec_tx::IrNecTx;
+#[cfg(feature = "ws2812")]
+use machines::ws2812::{self, Ws2812, Ws2812Config};
+#[cfg(feature = "ws2812")]
+use esp_hal::{dma::{Dma, DmaPriority}, dma_buffers, spi::{master::{prelude::*, Spi}, SpiMode}};
+#[cfg(feature = "oled-display")]
+use machines::display::{Display, DisplayConfig, DisplayController};
+#[cfg(feature = "piezo-buzzer")]
+use machines::buzzer::{PiezoBuzzer, PiezoBuzzerConfig, Tone};
+#[cfg(feature = "ventilation-pwm")]
+use machines::ventilation::PwmFan;
+#[cfg(any(feature = "piezo-buzzer", feature = "ventilation-pwm"))]
+use esp_hal::peripherals::LEDC;
+// simple outputs of status led, buzzer and ventilation relay
+#[cfg(not(all(feature = "rgb-led", feature = "piezo-buzzer", feature = "ventilation-pwm")))]
+use esp_hal::gpio::{Level, Output};
+use pac_utils::rmt::RxChannel;
+use power::IdleMode;
+use record::RecordFormat;
+use event_bus::EventBus;
+use flight_recorder::TraceEvent;
+use scheduler::{Resources, Scheduler};
+
+
+
+#[cfg(feature = "async-sdc")]
+mod async_io;
+mod commands;
+mod error_registry;
+mod event_bus;
+mod flight_recorder;
+mod board;
+mod heartbeat;
+mod i2c_bus;
+mod i2c_engine;
+mod i2c_trace;
 mod interrupts;
+mod log;
+mod measurment_interval;
+mod metrics;
+mod net;
+mod output_buffer;
+mod panic;
 mod qq_alarm_queue;
+mod reboot;
+mod rom_flash;
+mod scheduler;
 mod usb_writer;
+#[cfg(feature = "uart-output")]
+mod uart_writer;
 mod sdc;
+mod sensirion_common;
+mod sinks;
+#[cfg(feature = "second-sdc")]
+mod soft_i2c;
 mod machines;
 mod pac_utils;
+mod power;
+mod time;
+
+#[cfg(feature = "mock-hw")]
+mod mock;
 
-// mod sony_ir;
 
 
+/// Binding of key of a remote to console command, repeats of held key are ignored.
+const fn ir_key(protocol: IrProtocol, address: u8, ir_command: u16, command: &str) -> IrKeyBinding {
+    match IrKeyBinding::new(protocol, address, ir_command, command, false) {
+        Some(binding) => binding,
+        None => panic!("ir binding command is too long"),
+    }
+}
+
+/// Keys of remotes, used until bindings are changed by `ir bind` and saved (see `Config::ir_bindings`).
+const IR_BINDINGS: &[IrKeyBinding] = &[
+    /* common 21 key nec remote (address 0) */
+    // CH-, CH+
+    ir_key(IrProtocol::Nec, 0x00, 0x45, "scd30 toggle"),
+    ir_key(IrProtocol::Nec, 0x00, 0x47, "flush"),
+    // 1, 2, 3
+    ir_key(IrProtocol::Nec, 0x00, 0x0c, "interval 2"),
+    ir_key(IrProtocol::Nec, 0x00, 0x18, "interval 10"),
+    ir_key(IrProtocol::Nec, 0x00, 0x5e, "interval 60"),
+    // EQ
+    ir_key(IrProtocol::Nec, 0x00, 0x09, "config save"),
+    // PLAY/PAUSE
+    ir_key(IrProtocol::Nec, 0x00, 0x43, "alert silence"),
+    // 4, 5, 6
+    ir_key(IrProtocol::Nec, 0x00, 0x08, "vent on"),
+    ir_key(IrProtocol::Nec, 0x00, 0x1c, "vent off"),
+    ir_key(IrProtocol::Nec, 0x00, 0x5a, "vent auto"),
+
+    /* sony tv remote (address 1) */
+    // power
+    ir_key(IrProtocol::Sirc, 0x01, 0x15, "scd30 toggle"),
+    // 1, 2, 3
+    ir_key(IrProtocol::Sirc, 0x01, 0x00, "interval 2"),
+    ir_key(IrProtocol::Sirc, 0x01, 0x01, "interval 10"),
+    ir_key(IrProtocol::Sirc, 0x01, 0x02, "interval 60"),
+    // mute
+    ir_key(IrProtocol::Sirc, 0x01, 0x14, "alert silence"),
+    // 4, 5, 6
+    ir_key(IrProtocol::Sirc, 0x01, 0x03, "vent on"),
+    ir_key(IrProtocol::Sirc, 0x01, 0x04, "vent off"),
+    ir_key(IrProtocol::Sirc, 0x01, 0x05, "vent auto"),
+
+    /* philips tv remotes (address 0), rc5 and rc6 remotes use same command codes for these keys */
+    // standby
+    ir_key(IrProtocol::Rc5, 0x00, 0x0c, "scd30 toggle"),
+    ir_key(IrProtocol::Rc6, 0x00, 0x0c, "scd30 toggle"),
+    // mute
+    ir_key(IrProtocol::Rc5, 0x00, 0x0d, "alert silence"),
+    ir_key(IrProtocol::Rc6, 0x00, 0x0d, "alert silence"),
+];
+
+/// Two short beeps when co2 warning is raised.
+#[cfg(feature = "piezo-buzzer")]
+const BUZZER_WARNING: &[Tone] = &[Tone::new(2000, 80), Tone::rest(80), Tone::new(2000, 80)];
+/// Two tone siren repeated while co2 is critical.
+#[cfg(feature = "piezo-buzzer")]
+const BUZZER_CRITICAL: &[Tone] = &[Tone::new(2800, 250), Tone::new(2000, 250), Tone::new(2800, 250), Tone::new(2000, 250), Tone::rest(1000)];
+
+
+/// Boot button of devkit, double click cycles ventilation override, long press recalibrates scd30 to fresh air
+/// (scd30 is toggled by ir remote or console).
+const BUTTON_BINDINGS: &[ButtonBinding] = &[
+    ButtonBinding { event: ButtonEvent::ShortPress, command: ConsoleCommand::AlertSilence },
+    ButtonBinding { event: ButtonEvent::DoubleClick, command: ConsoleCommand::VentilationNext },
+    ButtonBinding { event: ButtonEvent::LongPress, command: ConsoleCommand::ForcedRecalibration { ppm: 420 } },
+];
+
+
+/// Wifi credentials are given at build time (`WIFI_SSID`, `WIFI_PASSWORD` environment variables), wifi is not used without ssid.
+const WIFI_SSID: Option<&str> = option_env!("WIFI_SSID");
+const WIFI_PASSWORD: &str = match option_env!("WIFI_PASSWORD") {
+    Some(password) => password,
+    None => "",
+};
+
+/// Scd30 without data ready line is polled by is ready command (`SDC_READY_POLL` environment variable set at build time).
+#[cfg(any(not(feature = "async-sdc"), feature = "second-sdc"))]
+const SDC_READY_MODE: ReadyMode = match option_env!("SDC_READY_POLL") {
+    Some(_) => ReadyMode::Poll { period: SystemTimer::TICKS_PER_SECOND / 2 },
+    None => ReadyMode::Pin,
+};
+
+/// Interrupt priorities different from `PriorityTable::DEFAULT`, `None` leaves interrupt disabled.
+const INTERRUPT_PRIORITIES: &[(InterruptSource, Option<Priority>)] = &[
+    // mock usb writer and qq alarm queue do not use interrupts
+    #[cfg(feature = "mock-hw")]
+    (InterruptSource::Usb, None),
+    #[cfg(feature = "mock-hw")]
+    (InterruptSource::Systimer, None),
+    #[cfg(all(feature = "mock-hw", feature = "uart-output"))]
+    (InterruptSource::Uart0, None),
+];
+
+/// Format of measurment records on usb (`RECORD_FORMAT` environment variable `csv` or `json` set at build time), changed
+/// at runtime by console command `format`.
+const RECORD_FORMAT: RecordFormat = match option_env!("RECORD_FORMAT") {
+    // `str` cannot be matched in constants
+    Some(format) => match format.as_bytes() {
+        b"csv" => RecordFormat::Csv,
+        b"json" => RecordFormat::JsonLines,
+        _ => RecordFormat::Text,
+    },
+    None => RecordFormat::Text,
+};
+
+/// Dew point and absolute humidity are written after each measurment in text format (`HUMIDITY_METRICS` environment
+/// variable set at build time).
+const HUMIDITY_METRICS: bool = option_env!("HUMIDITY_METRICS").is_some();
+
+/// Port of measurment udp packets receiver (see `WifiReporter`), its address is in config (`Config::report_host`).
+const REPORT_PORT: u16 = 9125;
+
+/// Mqtt broker for measurment telemetry (see `MqttClient`), credentials are optional (`MQTT_USERNAME`, `MQTT_PASSWORD` at build time).
+const MQTT_BROKER: [u8; 4] = [192, 168, 1, 4];
+const MQTT_PORT: u16 = 1883;
+const MQTT_USERNAME: Option<&str> = option_env!("MQTT_USERNAME");
+const MQTT_PASSWORD: Option<&str> = option_env!("MQTT_PASSWORD");
+/// Port of json status endpoint (see `HttpServer`).
+const HTTP_PORT: u16 = 80;
+
+const MQTT_TOPICS: MqttTopics = MqttTopics {
+    co2: "esp-scd30/co2",
+    temperature: "esp-scd30/temperature",
+    humidity: "esp-scd30/humidity",
+};
+
+
+/// Pixels of addressable led strip (feature `ws2812`), its frame buffer is sized by them.
+#[cfg(feature = "ws2812")]
+const LED_STRIP_PIXELS: usize = 8;
+
+/// Used when no valid config is stored in flash (first boot, incompatible firmware).
+const DEFAULT_CONFIG: Config = Config {
+    interval_secs: 10,
+    alert_warning: 1_200_000,
+    alert_critical: 2_000_000,
+    alert_hysteresis: 100_000,
+    ventilation_on: 1_000_000,
+    ventilation_off: 800_000,
+    report_host: [192, 168, 1, 4],
+    ir_bindings: IrBindings::new(IR_BINDINGS),
+};
+
 
 #[entry]
 fn main() -> ! {
@@ -45,102 +287,351 @@ fn main() -> ! {
     let clocks = ClockControl::max(system.clock_control).freeze();
 
     let io = Io::new(peripherals.GPIO, peripherals.IO_MUX);
+    #[cfg(not(feature = "mock-hw"))]
     let systimer = SystemTimer::new(peripherals.SYSTIMER);
 
     // # before loop
-    let status_led = Output::new(io.pins.gpio7, Level::Low);
-
-    let mut qq = DumbQQAlarmQueue::<8>::new(systimer.alarm0);
-    let mut usb_writer = RingBufferUsbWriter::<4096>::new(peripherals.USB_DEVICE, None);
+    let pins = BoardPins::new(io.pins);
+
+    panic::register_status_led(&pins.status_led);
+    flight_recorder::record(TraceEvent::Boot, 0);
+    #[cfg(not(feature = "rgb-led"))]
+    let status_led = Output::new(pins.status_led, Level::Low);
+    // SAFETY: system is used only temporarily inside `RgbLed::new` function to configure ledc clock (see `IrRxDispatch::new`)
+    #[cfg(feature = "rgb-led")]
+    let status_led = RgbLed::new(peripherals.LEDC, unsafe { SYSTEM::steal() }, pins.status_led, pins.status_led_green, pins.status_led_blue);
+    #[cfg(not(feature = "piezo-buzzer"))]
+    let buzzer = Output::new(pins.buzzer, Level::Low);
+    // SAFETY: buzzer uses only ledc timer 1 and channel 3 (rgb status led uses timer 0 and channels 0 - 2), system is used only temporarily (same as `RgbLed::new`)
+    #[cfg(feature = "piezo-buzzer")]
+    let buzzer = PiezoBuzzer::new(unsafe { LEDC::steal() }, unsafe { SYSTEM::steal() }, pins.buzzer, PiezoBuzzerConfig {
+        warning: BUZZER_WARNING,
+        critical: BUZZER_CRITICAL,
+    });
+    #[cfg(not(feature = "ventilation-pwm"))]
+    let ventilation_output = Output::new(pins.ventilation, Level::Low);
+    // SAFETY: fan uses only ledc timer 2 and channel 4 (see `PiezoBuzzer::new` above), system is used only temporarily
+    #[cfg(feature = "ventilation-pwm")]
+    let ventilation_output = PwmFan::new(unsafe { LEDC::steal() }, unsafe { SYSTEM::steal() }, pins.ventilation);
+
+    // `SystimerQQAlarmQueue<AlarmTable<_>>` can be selected instead (same interface, linear scans of alarms)
+    #[cfg(not(feature = "mock-hw"))]
+    let mut qq = HeapQQAlarmQueue::<22>::new(systimer.alarm0);
+    #[cfg(not(any(feature = "mock-hw", feature = "uart-output")))]
+    let mut usb_writer = RingBufferUsbWriter::<4096>::new(peripherals.USB_DEVICE, RingBufferUsbWriterConfig {
+        timeout_delay: None,
+        overflow_policy: OverflowPolicy::RejectNewest,
+        drop_marker_period: None,
+        output_mode: UsbOutputMode::Text,
+    });
+    // same interface as usb writer, all output goes to uart0 tx pin (console input stays on usb)
+    #[cfg(all(feature = "uart-output", not(feature = "mock-hw")))]
+    let mut usb_writer = UartWriter::<4096>::new(peripherals.UART0, pins.uart_tx, &clocks, UartWriterConfig {
+        baudrate: 115_200,
+        overflow_policy: OverflowPolicy::RejectNewest,
+        drop_marker_period: None,
+        output_mode: UsbOutputMode::Text,
+    }).unwrap();
+    #[cfg(not(any(feature = "mock-hw", feature = "uart-output")))]
+    panic::register_output(PanicOutput::UsbSerial);
+    #[cfg(all(feature = "uart-output", not(feature = "mock-hw")))]
+    panic::register_output(PanicOutput::Uart0);
+
+    #[cfg(feature = "mock-hw")]
+    let mut qq = mock::MockQQAlarmQueue::<22>::new();
+    #[cfg(feature = "mock-hw")]
+    let mut usb_writer = mock::MockUsbWriter::new(UsbOutputMode::Text);
+    #[cfg(feature = "mock-hw")]
+    let mock_hw = mock::MockHardware::new(&pins.sdc_ready);
 
     let mut status_led = StatusLed::new(status_led, StatusLedConfig {
         boot_blink_duration: SystemTimer::TICKS_PER_SECOND / 10,
         boot_blink_count: 10,
+        usb_overflow_hold: SystemTimer::TICKS_PER_SECOND * 10,
     });
     let mut debug_print = DebugPrint::new(SystemTimer::TICKS_PER_SECOND);
+
+    // timer group 0 is used by wifi
+    let mut watchdog = Watchdog::new(TimerGroup::new(peripherals.TIMG1, &clocks, None).wdt, WatchdogConfig {
+        timeout: SystemTimer::TICKS_PER_SECOND * 5,
+        feed_period: SystemTimer::TICKS_PER_SECOND,
+    });
+
+    let mut config_store = ConfigStore::new(RomFlash::new(rom_flash::CONFIG_FLASH_OFFSET, rom_flash::CONFIG_FLASH_SECTORS));
+    let stored_config = config_store.load();
+    let config = match stored_config {
+        Ok(Some(config)) if measurment_interval::is_valid_interval(config.interval_secs.secs()) && config.thresholds_valid() => config,
+        Ok(_) => DEFAULT_CONFIG,
+        Err(err) => {
+            error_registry::record_error(Subsystem::Config, &err);
+            DEFAULT_CONFIG
+        },
+    };
+
+    let initial_interval = config.interval_secs.secs();
+    let i2c_bus = I2CBus::new(peripherals.I2C0, pins.i2c_scl, pins.i2c_sda, 50u32.kHz(), &clocks);
+    #[cfg(not(feature = "async-sdc"))]
     let mut sdc = SDCSimpleMeasurment::new(
-        peripherals.I2C0,
-        io.pins.gpio4,
-        io.pins.gpio5,
-        io.pins.gpio6,
+        pins.sdc_ready,
         SDCSimpleMeasurmentConfig {
-            delta: 10u32.secs(),
+            delta: initial_interval,
             delayed_get_delta: None,
+            bus_user: I2CBusUser(0),
+            address: sdc::DEFAULT_ADDRESS,
+            sensor: SensorId::PRIMARY,
+            ready_mode: SDC_READY_MODE,
+            automatic_self_calibration: None,
+            temperature_offset: None,
+            altitude: None,
         },
-        &clocks,
     );
-    // SAFETY: system is used only temporarily inside `IrNecRx::new` function, it is not stored in `ir_nec_rx` (cannot use `peripherals.SYSTEM` because it's already moved)
-    let mut ir_nec_rx = IrNecRx::new(peripherals.RMT, io.pins.gpio10, unsafe { SYSTEM::steal() });
-    let mut controller = Controller::<1024>::new();
+    // second zone, same settings as the primary sensor on its own bus
+    #[cfg(feature = "second-sdc")]
+    let mut sdc_secondary = SecondarySDC::new(
+        SDCSimpleMeasurment::new(
+            pins.sdc_secondary_ready,
+            SDCSimpleMeasurmentConfig {
+                delta: initial_interval,
+                delayed_get_delta: None,
+                bus_user: I2CBusUser(0),
+                address: sdc::DEFAULT_ADDRESS,
+                sensor: SensorId(1),
+                ready_mode: SDC_READY_MODE,
+                automatic_self_calibration: None,
+                temperature_offset: None,
+                altitude: None,
+            },
+        ),
+        SoftI2CBus::new(pins.sdc_secondary_scl, pins.sdc_secondary_sda),
+    );
+    // async task is polled by `async_tasks` machine, task and its io live until the end of `main`
+    #[cfg(feature = "async-sdc")]
+    let sdc_io = AsyncIo::new(QQOwner::Sdc);
+    #[cfg(feature = "async-sdc")]
+    let mut sdc_task = core::pin::pin!(sdc_async::run(&sdc_io, pins.sdc_ready, SDCTaskConfig {
+        delta: initial_interval,
+        delayed_get_delta: None,
+        bus_user: I2CBusUser(0),
+    }));
+    #[cfg(feature = "async-sdc")]
+    let mut async_tasks = AsyncTasks::new(&sdc_io);
+    #[cfg(feature = "async-sdc")]
+    invariant!(async_tasks.spawn(sdc_task.as_mut()).is_ok(), "no free task slot for sdc task");
+    let mut ambient_sensor = AmbientSensor::new(Sht3x, AmbientSensorConfig {
+        period: SystemTimer::TICKS_PER_SECOND * 10,
+        bus_user: I2CBusUser(1),
+    });
+    #[cfg(feature = "bme280")]
+    let mut bme280 = Bme280::new(Bme280Config {
+        address: Bme280::DEFAULT_ADDRESS,
+        period: SystemTimer::TICKS_PER_SECOND * 10,
+        bus_user: I2CBusUser(2),
+    });
+    #[cfg(feature = "oled-display")]
+    let mut display = Display::new(DisplayConfig {
+        controller: DisplayController::Ssd1306,
+        address: Display::DEFAULT_ADDRESS,
+        period: SystemTimer::TICKS_PER_SECOND,
+        bus_user: I2CBusUser(3),
+    });
+    // SAFETY: system is used only temporarily inside `IrRxDispatch::new` function, it is not stored in `ir_rx` (cannot use `peripherals.SYSTEM` because it's already moved)
+    let mut ir_rx = IrRxDispatch::new(peripherals.RMT, pins.ir_rx, unsafe { SYSTEM::steal() }, IrRxDispatchConfig {
+        channel: RxChannel::Ch2,
+        nec_repeat_timeout: SystemTimer::TICKS_PER_SECOND / 1000 * 150,
+    });
+    let ir_codes = IrCodeStore::new(RomFlash::new(rom_flash::IR_FLASH_OFFSET, rom_flash::IR_FLASH_SECTORS), rom_flash::IR_FLASH_SECTORS);
+    // SAFETY: ir tx uses only channel 0 registers (and its interrupt enable bits), ir rx uses channel 2 and configures shared rmt clock
+    let ir_tx = IrSonyTx::<_, 4>::new(unsafe { RMT::steal() }, pins.ir_tx);
+    // SAFETY: nec tx uses only channel 1 registers (and its interrupt enable bits)
+    let ir_nec_tx = IrNecTx::<_, 4>::new(unsafe { RMT::steal() }, pins.ir_nec_tx);
+    #[cfg(feature = "ws2812")]
+    let led_strip = {
+        let (frame, tx_descriptors, _, rx_descriptors) = dma_buffers!(ws2812::frame_len(LED_STRIP_PIXELS), 0);
+        let dma = Dma::new(peripherals.DMA);
+        let spi = Spi::new(peripherals.SPI2, 2500.kHz(), SpiMode::Mode0, &clocks)
+            .with_mosi(pins.led_strip)
+            .with_dma(dma.channel0.configure(false, DmaPriority::Priority0), tx_descriptors, rx_descriptors);
+
+        Ws2812::<LED_STRIP_PIXELS>::new(spi, frame, Ws2812Config {
+            brightness: 32,
+            co2_min: 400_000,
+            co2_max: 2_000_000,
+            warning: config.alert_warning,
+            critical: config.alert_critical,
+        })
+    };
+    let button = Button::new(pins.button, ButtonConfig {
+        debounce: SystemTimer::TICKS_PER_SECOND / 1000 * 30,
+        long_press: SystemTimer::TICKS_PER_SECOND * 2,
+        double_click: SystemTimer::TICKS_PER_SECOND / 1000 * 300,
+        bindings: BUTTON_BINDINGS,
+    });
+    let events = EventBus::new();
+    let mut controller = Controller::<1024>::new(FilterConfig {
+        window: None,
+        despike: true,
+    }, TrendConfig {
+        window_secs: 600,
+        stable_threshold: 5_000,
+    });
+    controller.set_record_format(RECORD_FORMAT);
+    controller.set_humidity_output(HUMIDITY_METRICS);
+    let mut staleness_monitor = StalenessMonitor::new();
+    let flash_logger = FlashLogger::<_, 256>::new(RomFlash::new(rom_flash::LOG_FLASH_OFFSET, rom_flash::LOG_FLASH_SECTORS), rom_flash::LOG_FLASH_SECTORS);
+    let alert = Alert::new(buzzer, AlertConfig {
+        warning: config.alert_warning,
+        critical: config.alert_critical,
+        hysteresis: config.alert_hysteresis,
+        debounce: SystemTimer::TICKS_PER_SECOND * 30,
+        rate_warning: Some(50_000),
+    });
+    let ventilation = Ventilation::new(ventilation_output, VentilationConfig {
+        on: config.ventilation_on,
+        off: config.ventilation_off,
+        full: 1_500_000,
+        min_speed: 30,
+        min_run: SystemTimer::TICKS_PER_SECOND * 300,
+    });
+
+    // network stack and its sockets borrow these buffers (dhcp, udp of wifi reporter, tcp of mqtt client and http server)
+    let mut socket_storage = <[SocketStorage; 4]>::default();
+    let mut udp_rx_meta = [udp::PacketMetadata::EMPTY; 4];
+    let mut udp_rx_buffer = [0u8; 256];
+    let mut udp_tx_meta = [udp::PacketMetadata::EMPTY; 4];
+    let mut udp_tx_buffer = [0u8; 256];
+    let mut tcp_rx_buffer = [0u8; 256];
+    let mut tcp_tx_buffer = [0u8; 512];
+    let mut http_rx_buffer = [0u8; 512];
+    let mut http_tx_buffer = [0u8; 1024];
+    let mut network = None;
+
+    if let Some(ssid) = WIFI_SSID {
+        // wifi scheduler uses its own timer (systimer alarms are used by qq)
+        let timg0 = TimerGroup::new(peripherals.TIMG0, &clocks, None);
+        let wifi_init = esp_wifi::initialize(EspWifiInitFor::Wifi, PeriodicTimer::new(timg0.timer0.into()), Rng::new(peripherals.RNG), peripherals.RADIO_CLK, &clocks).unwrap();
+        let (device, wifi_controller) = esp_wifi::wifi::new_with_mode(&wifi_init, peripherals.WIFI, WifiStaDevice).unwrap();
+
+        let mut net_stack = NetStack::new(device, &mut socket_storage);
+        let udp_socket = net_stack.add_udp_socket(udp::Socket::new(
+            udp::PacketBuffer::new(&mut udp_rx_meta[..], &mut udp_rx_buffer[..]),
+            udp::PacketBuffer::new(&mut udp_tx_meta[..], &mut udp_tx_buffer[..]),
+        ));
+        let tcp_socket = net_stack.add_tcp_socket(tcp::Socket::new(tcp::SocketBuffer::new(&mut tcp_rx_buffer[..]), tcp::SocketBuffer::new(&mut tcp_tx_buffer[..])));
+        let http_socket = net_stack.add_tcp_socket(tcp::Socket::new(tcp::SocketBuffer::new(&mut http_rx_buffer[..]), tcp::SocketBuffer::new(&mut http_tx_buffer[..])));
+
+        let wifi_reporter = WifiReporter::new(wifi_controller, udp_socket, WifiReporterConfig {
+            ssid,
+            password: WIFI_PASSWORD,
+            host: config.report_host,
+            port: REPORT_PORT,
+            period: SystemTimer::TICKS_PER_SECOND * 60,
+            connect_timeout: SystemTimer::TICKS_PER_SECOND * 20,
+            reconnect_delay: SystemTimer::TICKS_PER_SECOND * 30,
+        });
+        let mqtt_client = MqttClient::new(tcp_socket, MqttClientConfig {
+            broker: MQTT_BROKER,
+            port: MQTT_PORT,
+            client_id: "esp-scd30",
+            username: MQTT_USERNAME,
+            password: MQTT_PASSWORD,
+            topics: MQTT_TOPICS,
+            retain: true,
+            keep_alive_secs: 60,
+            connect_timeout: SystemTimer::TICKS_PER_SECOND * 10,
+            backoff_min: SystemTimer::TICKS_PER_SECOND * 5,
+            backoff_max: SystemTimer::TICKS_PER_SECOND * 300,
+        });
+        let http_server = HttpServer::new(http_socket, HTTP_PORT, SystemTimer::TICKS_PER_SECOND * 10);
+        network = Some(Network::new(net_stack, wifi_reporter, mqtt_client, http_server));
+    }
 
-    qq.enable_interrupt();
-    usb_writer.enable_interrupt();
-    sdc.enable_interrupt();
-    interrupts::gpio_interrupt_enable(Some(Priority::Priority5));
-    ir_nec_rx.enable_interrupt();
+    // SAFETY: console uses only OUT endpoint registers (and its interrupt enable bit), usb writer uses only IN endpoint
+    let mut console = Console::<64>::new(unsafe { USB_DEVICE::steal() });
+    let usb_bench = UsbBench::new();
+    let measurment_dump = MeasurmentDump::new();
 
-    // # start
-    let _ = writeln!(usb_writer, "starting ...");
+    // derived timing is initialized in the same way as when the interval is changed (scd30 already has it in config)
+    measurment_interval::change_interval(initial_interval, &mut [&mut controller, &mut staleness_monitor]).unwrap();
 
-    status_led.start(&mut qq);
-    debug_print.start(&mut qq);
-    sdc.start(&mut qq);
-    ir_nec_rx.start();
+    let interrupt_priorities = PriorityTable::DEFAULT.with_overrides(INTERRUPT_PRIORITIES).unwrap_or_else(|err| {
+        error!(&mut usb_writer, Module::Main, "interrupt priorities rejected, using defaults : {:?}", err);
+        PriorityTable::DEFAULT
+    });
+    console.enable_interrupt();
+    interrupts::init(&interrupt_priorities);
 
-    let mut sleeping = false;
+    // # start
+    info!(&mut usb_writer, Module::Main, "starting ...");
+    match stored_config {
+        Ok(Some(stored)) if stored == config => info!(&mut usb_writer, Module::Config, "loaded from flash"),
+        _ => info!(&mut usb_writer, Module::Config, "defaults"),
+    }
 
-    // # loop
-    loop {
-        let mut did_something = false;
-
-        did_something |= qq.update();
-
-        if let Some(qq_pending_alarms) = qq.consume_pending() {
-            qq_pending_alarms.for_each(|qq_alarm_id| {
-                // if !usb_writer.on_alarm(qq_alarm_id) && !debug_print.on_alarm(qq_alarm_id) {
-                if !status_led.on_alarm(qq_alarm_id) && !usb_writer.on_alarm(qq_alarm_id) && !sdc.on_alarm(qq_alarm_id) && !debug_print.on_alarm(qq_alarm_id) {
-                    let _ = writeln!(usb_writer, "ajejeje ...");
-                }
-            });
-        }
-
-        did_something |= usb_writer.update(&mut qq);
-
-        did_something |= status_led.update(&usb_writer, &mut qq);
-
-        did_something |= debug_print.update(&mut qq, &mut usb_writer);
-
-        did_something |= sdc.update(&mut usb_writer, &mut qq, &mut controller);
-
-        did_something |= ir_nec_rx.update(&mut usb_writer);
-
-        did_something |= controller.update(&mut usb_writer);
-
-        // critcal section disables interrupts
-        // TODO: critical section works ??? go to sleep and enable interrupts in one cycle
-        // TODO: interrupts
-        // `systimer_target0` - always awaited
-        // `usb` - managed (on/off) by usb task, when on always awaited
-        // `i2c` - managed by sdc i2c task
-        //         always on and only selected relevant subinterrupts enabled
-        //         (not always awaited, but) when interrupt can happen sdc task is always waiting on it
-        // `gpio` - not working, awaited when not needed (maybe ???)
-        critical_section::with(|_cs| {
-            let no_interrupts = interrupts::systimer_target0_interrupt_get().is_empty()
-                && interrupts::usb_interrupt_get().is_empty()
-                && interrupts::i2c_interrupt_get().is_empty()
-                && interrupts::gpio_interrupt_get().is_empty()
-                && interrupts::rmt_interrupt_get().is_empty();
-
-            if no_interrupts && !did_something {
-                sleeping = true;
-            } else {
-                if sleeping {
-                    debug_print.wakeup();
-                }
-
-                sleeping = false;
-            }
-        })
+    status_led.start(&mut qq.owned(QQOwner::StatusLed));
+    debug_print.start(&mut qq.owned(QQOwner::DebugPrint));
+    #[cfg(not(feature = "async-sdc"))]
+    sdc.start(&mut qq.owned(QQOwner::Sdc));
+    #[cfg(feature = "second-sdc")]
+    sdc_secondary.start(&mut qq.owned(QQOwner::SdcSecondary));
+    ambient_sensor.start(&mut qq.owned(QQOwner::AmbientSensor));
+    #[cfg(feature = "bme280")]
+    bme280.start();
+    #[cfg(feature = "oled-display")]
+    display.start(&mut qq.owned(QQOwner::Display));
+    match &mut network {
+        Some(network) => network.start(&mut usb_writer, &mut qq),
+        None => info!(&mut usb_writer, Module::Wifi, "disabled (WIFI_SSID not set at build time)"),
     }
+    ir_rx.start();
+    // started last, initialization above (wifi) can take longer than watchdog timeout
+    watchdog.start(&mut qq.owned(QQOwner::Watchdog));
+
+    // mock qq alarm queue polls system timer, so the loop cannot wait for interrupt
+    let idle_mode = if cfg!(feature = "mock-hw") { IdleMode::Busy } else { IdleMode::WaitForInterrupt };
+
+    let resources = Resources {
+        usb_writer,
+        qq,
+        i2c_bus,
+        events,
+        controller,
+        config,
+        config_store,
+        ir_codes,
+    };
+
+    // # loop
+    Scheduler::new(resources, (
+        #[cfg(feature = "mock-hw")]
+        mock_hw,
+        status_led,
+        debug_print,
+        watchdog,
+        #[cfg(not(feature = "async-sdc"))]
+        sdc,
+        #[cfg(feature = "async-sdc")]
+        async_tasks,
+        #[cfg(feature = "second-sdc")]
+        sdc_secondary,
+        ambient_sensor,
+        #[cfg(feature = "bme280")]
+        bme280,
+        #[cfg(feature = "oled-display")]
+        display,
+        ir_rx,
+        ir_tx,
+        ir_nec_tx,
+        #[cfg(feature = "ws2812")]
+        led_strip,
+        button,
+    ), (
+        flash_logger,
+        staleness_monitor,
+        alert,
+        ventilation,
+        network,
+        console,
+        usb_bench,
+        measurment_dump,
+    )).run(idle_mode)
 }
\ No newline at end of file
@@ -0,0 +1,45 @@
+/* scd30 measurment interval and propagation of its changes to machines with timing derived from it */
+
+use fugit::SecsDurationU32;
+
+
+
+/// Interval range accepted by scd30 (in seconds).
+pub const MIN_INTERVAL_SECS: u32 = 2;
+pub const MAX_INTERVAL_SECS: u32 = 1800;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IntervalError {
+    OutOfRange,
+}
+
+
+/// Machine with timing (timeouts, windows, periods) derived from the measurment interval.
+///
+/// Called without access to the qq alarm queue or i2c bus, changes which need them (alarms, sensor commands)
+/// are applied in the observer's next `update`. Scheduled observers are called from `Machine::on_command` with
+/// `ConsoleCommand::Interval`, after the interval was checked by `commands::handle`.
+pub trait IntervalObserver {
+    fn on_interval_changed(&mut self, interval: SecsDurationU32);
+}
+
+
+
+pub fn is_valid_interval(interval: SecsDurationU32) -> bool {
+    (MIN_INTERVAL_SECS..=MAX_INTERVAL_SECS).contains(&interval.to_secs())
+}
+
+/// Passes new interval to all `observers` (scd30 machine should be one of them), nothing is changed when interval is out of range.
+///
+/// All machines depending on the interval must be passed here, otherwise their derived timing would not match the sensor
+/// (e.g. staleness detection would report false alarms after the interval was increased).
+pub fn change_interval(interval: SecsDurationU32, observers: &mut [&mut dyn IntervalObserver]) -> Result<(), IntervalError> {
+    if !is_valid_interval(interval) {
+        return Err(IntervalError::OutOfRange);
+    }
+
+    observers.iter_mut().for_each(|observer| observer.on_interval_changed(interval));
+
+    Ok(())
+}
@@ -0,0 +1,117 @@
+/* registry of values (metrics) and events sent to the host, host tools can query it (console command `meta`) instead of hard-coding frame layouts */
+
+use core::fmt::Write;
+
+use heapless::Vec;
+
+use crate::{framing::{FrameType, MAX_PAYLOAD_LEN}, log::log_line, usb_writer::{UsbOutputMode, UsbWriter}};
+
+
+
+/// Encoding of the value in frame payload (all numbers are little endian).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ValueType {
+    U32 = 1,
+    I32 = 2,
+    U64 = 3,
+    /// utf8 text till the end of payload
+    Text = 4,
+    U8 = 5,
+    U16 = 6,
+}
+
+impl ValueType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ValueType::U32 => "u32",
+            ValueType::I32 => "i32",
+            ValueType::U64 => "u64",
+            ValueType::Text => "text",
+            ValueType::U8 => "u8",
+            ValueType::U16 => "u16",
+        }
+    }
+}
+
+
+/// Description of one value (or event) sent to the host, id of the metric is its index in `METRICS`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metric {
+    pub name: &'static str,
+    pub unit: &'static str,
+    /// value in `unit` is raw value * 10^scale
+    pub scale: i8,
+    pub value_type: ValueType,
+    /// frame in which the value is sent (in framed output mode)
+    pub frame_type: FrameType,
+    /// byte offset in frame payload
+    pub offset: u8,
+    pub description: &'static str,
+}
+
+
+/// Layouts have to match `record::BinaryEncoding` (measurment frame), `DebugPrint` (health frame), `IrMessage::encode` (ir code frame)
+/// and `error_registry::write_error_frames` (error frame).
+pub static METRICS: [Metric; 21] = [
+    Metric { name: "measurment_at", unit: "tick", scale: 0, value_type: ValueType::U64, frame_type: FrameType::Measurment, offset: 0, description: "system timer ticks since boot (16 MHz)" },
+    Metric { name: "co2", unit: "ppm", scale: -3, value_type: ValueType::I32, frame_type: FrameType::Measurment, offset: 8, description: "scd30 co2 concentration" },
+    Metric { name: "temperature", unit: "°C", scale: -3, value_type: ValueType::I32, frame_type: FrameType::Measurment, offset: 12, description: "scd30 temperature" },
+    Metric { name: "humidity", unit: "%", scale: -3, value_type: ValueType::I32, frame_type: FrameType::Measurment, offset: 16, description: "scd30 relative humidity" },
+    Metric { name: "log", unit: "", scale: 0, value_type: ValueType::Text, frame_type: FrameType::Log, offset: 0, description: "one line of text output (event)" },
+    Metric { name: "tick_counter", unit: "", scale: 0, value_type: ValueType::U32, frame_type: FrameType::Health, offset: 0, description: "number of health reports" },
+    Metric { name: "wakeup_count", unit: "", scale: 0, value_type: ValueType::U32, frame_type: FrameType::Health, offset: 4, description: "main loop wakeups" },
+    Metric { name: "usb_dropped", unit: "B", scale: 0, value_type: ValueType::U64, frame_type: FrameType::Health, offset: 8, description: "bytes dropped by usb writer" },
+    Metric { name: "invariant_violations", unit: "", scale: 0, value_type: ValueType::U32, frame_type: FrameType::Health, offset: 16, description: "violated invariants" },
+    Metric { name: "usb_timeouts", unit: "", scale: 0, value_type: ValueType::U32, frame_type: FrameType::Health, offset: 20, description: "host did not read usb data in time" },
+    Metric { name: "qq_overflows", unit: "", scale: 0, value_type: ValueType::U32, frame_type: FrameType::Health, offset: 24, description: "qq alarms delayed by full queue" },
+    Metric { name: "sdc_errors", unit: "", scale: 0, value_type: ValueType::U32, frame_type: FrameType::Health, offset: 28, description: "scd30 errors including recovered ones" },
+    Metric { name: "ir_protocol", unit: "", scale: 0, value_type: ValueType::U8, frame_type: FrameType::IrCode, offset: 0, description: "1 nec, 2 sony sirc, 3 rc5, 4 rc6" },
+    Metric { name: "ir_flags", unit: "", scale: 0, value_type: ValueType::U8, frame_type: FrameType::IrCode, offset: 1, description: "nec repeat, sirc bits, rc5 / rc6 toggle" },
+    Metric { name: "ir_address", unit: "", scale: 0, value_type: ValueType::U8, frame_type: FrameType::IrCode, offset: 2, description: "ir device address" },
+    Metric { name: "ir_command", unit: "", scale: 0, value_type: ValueType::U8, frame_type: FrameType::IrCode, offset: 3, description: "ir command (nec message)" },
+    Metric { name: "ir_extended", unit: "", scale: 0, value_type: ValueType::U8, frame_type: FrameType::IrCode, offset: 4, description: "sirc extended bits" },
+    Metric { name: "error_subsystem", unit: "", scale: 0, value_type: ValueType::U8, frame_type: FrameType::Error, offset: 0, description: "index of subsystem (see `errors`)" },
+    Metric { name: "error_code", unit: "", scale: 0, value_type: ValueType::U16, frame_type: FrameType::Error, offset: 1, description: "error code within subsystem" },
+    Metric { name: "error_at", unit: "tick", scale: 0, value_type: ValueType::U64, frame_type: FrameType::Error, offset: 3, description: "system timer ticks of the error" },
+    Metric { name: "error_count", unit: "", scale: 0, value_type: ValueType::U32, frame_type: FrameType::Error, offset: 11, description: "occurrences since last clear" },
+];
+
+
+
+/// Metadata frame payload: id (u8), metric count (u8), frame type (u8), offset (u8), value type (u8), scale (i8),
+/// then name, unit and description (utf8), each terminated by `0x00`.
+fn encode_metadata(id: usize, metric: &Metric, payload: &mut Vec<u8, MAX_PAYLOAD_LEN>) -> Result<(), ()> {
+    payload.extend_from_slice(&[id as u8, METRICS.len() as u8, metric.frame_type as u8, metric.offset, metric.value_type as u8, metric.scale as u8]).map_err(|_| ())?;
+
+    for text in [metric.name, metric.unit, metric.description] {
+        payload.extend_from_slice(text.as_bytes()).map_err(|_| ())?;
+        payload.push(0).map_err(|_| ())?;
+    }
+
+    Ok(())
+}
+
+/// Writes all metrics, in framed mode as `FrameType::Metadata` frames (one per metric), in text mode one line per metric.
+pub fn write_metadata(usb_writer: &mut (impl Write + UsbWriter)) {
+    for (id, metric) in METRICS.iter().enumerate() {
+        match usb_writer.output_mode() {
+            UsbOutputMode::Text => {
+                log_line!(
+                    usb_writer,
+                    "meta",
+                    "{} {} [{} 10^{}] {} {:?}@{} - {}",
+                    id, metric.name, metric.unit, metric.scale, metric.value_type.name(), metric.frame_type, metric.offset, metric.description
+                );
+            },
+            UsbOutputMode::Framed => {
+                let mut payload = Vec::new();
+
+                // all descriptions are short enough
+                if encode_metadata(id, metric, &mut payload).is_ok() {
+                    let _ = usb_writer.write_frame(FrameType::Metadata, &payload);
+                }
+            },
+        }
+    }
+}
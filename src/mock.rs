@@ -0,0 +1,290 @@
+/* stub peripherals for development without sensors (feature `mock-hw`), machines are not changed, mocks replace the lowest layer (pac utils, usb writer, qq),
+   still runs on esp32-c6 (system timer and `esp_println` are used) */
+
+use core::fmt::Write;
+
+use esp_hal::timer::systimer::SystemTimer;
+
+use esp_println::Printer;
+
+use crate::{
+    alarm_table::{AlarmTable, TargetChange},
+    framing::{self, FrameType, MAX_FRAME_LEN},
+    interrupts::{self, GPIOInterruptStatus, RMTInterruptStatus},
+    pac_utils::gpio::PinNumber,
+    qq_alarm_queue::{QQAlarmError, QQAlarmQueue, QQOwner, TaggedQQAlarmQueue},
+    ring_buffer::RingBufferError,
+    scheduler::{Context, Machine},
+    usb_writer::{ByteSink, OverflowPolicy, UsbOutputMode, UsbWriter}
+};
+
+
+
+pub mod i2c;
+pub mod ir;
+
+
+
+/// Usb writer which prints everything immediately using `esp_println` (nothing is buffered, dropped or timeouted).
+///
+/// Frames are printed as hex dump lines (`frame : ...`), text is printed as plain text in both output modes (unless it
+/// is suppressed).
+pub struct MockUsbWriter {
+    output_mode: UsbOutputMode,
+    frame_sequence: u8,
+    text_suppressed: bool,
+}
+
+impl MockUsbWriter {
+    pub fn new(output_mode: UsbOutputMode) -> Self {
+        Self {
+            output_mode,
+            frame_sequence: 0,
+            text_suppressed: false,
+        }
+    }
+
+    fn write_hex(prefix: &str, bytes: &[u8]) {
+        let _ = write!(Printer, "{}", prefix);
+        bytes.iter().for_each(|byte| { let _ = write!(Printer, " {:02x}", byte); });
+        let _ = writeln!(Printer);
+    }
+}
+
+impl ByteSink for MockUsbWriter {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), RingBufferError> {
+        match core::str::from_utf8(bytes) {
+            Ok(text) => { let _ = Printer.write_str(text); },
+            Err(_) => Self::write_hex("raw :", bytes),
+        }
+
+        Ok(())
+    }
+
+    fn dropped_bytes(&self) -> u64 {
+        0
+    }
+
+    fn free_space(&self) -> usize {
+        usize::MAX
+    }
+
+    fn buffered_len(&self) -> usize {
+        0
+    }
+
+    fn high_water_mark(&self) -> usize {
+        0
+    }
+
+    fn reset_high_water_mark(&mut self) {}
+}
+
+impl UsbWriter for MockUsbWriter {
+    fn is_timeouted(&self) -> bool {
+        false
+    }
+
+    fn timeout_count(&self) -> u32 {
+        0
+    }
+
+    fn output_mode(&self) -> UsbOutputMode {
+        self.output_mode
+    }
+
+    fn set_output_mode(&mut self, output_mode: UsbOutputMode) {
+        self.output_mode = output_mode;
+    }
+
+    // output is printed right away, nothing is dropped
+    fn set_overflow_policy(&mut self, _overflow_policy: OverflowPolicy) {}
+
+    fn set_text_suppressed(&mut self, suppressed: bool) {
+        self.text_suppressed = suppressed;
+    }
+
+    fn write_frame(&mut self, frame_type: FrameType, payload: &[u8]) -> Result<(), RingBufferError> {
+        let mut frame = [0u8; MAX_FRAME_LEN];
+        let len = framing::encode_frame(frame_type, self.frame_sequence, payload, &mut frame).map_err(|_| RingBufferError::Overflow)?;
+
+        self.frame_sequence = self.frame_sequence.wrapping_add(1);
+
+        Self::write_hex("frame :", &frame[..len]);
+
+        Ok(())
+    }
+
+    fn flush(&mut self, _qq: &mut impl QQAlarmQueue) -> Result<(), RingBufferError> {
+        Ok(())
+    }
+
+    fn update(&mut self, _qq: &mut impl QQAlarmQueue) -> bool {
+        false
+    }
+
+    fn on_alarm(&mut self, _qq_alarm_id: usize) -> bool {
+        false
+    }
+
+    // output is printed right away
+    fn flush_blocking(&mut self, _timeout: u64) -> bool {
+        true
+    }
+}
+
+impl Write for MockUsbWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        if self.text_suppressed {
+            return Ok(());
+        }
+
+        Printer.write_str(s)
+    }
+}
+
+
+
+/// Qq alarm queue which polls system timer in `update` instead of using the timer alarm (and its interrupt).
+pub struct MockQQAlarmQueue<const N: usize> {
+    table: AlarmTable<N>,
+    target: Option<u64>,
+}
+
+impl<const N: usize> MockQQAlarmQueue<N> {
+    pub fn new() -> Self {
+        Self {
+            table: AlarmTable::new(),
+            target: None,
+        }
+    }
+
+    fn apply(&mut self, target_change: TargetChange) {
+        match target_change {
+            TargetChange::Keep => {},
+            TargetChange::Enable(target) | TargetChange::Set(target) => self.target = Some(target),
+            TargetChange::Disable => self.target = None,
+        }
+    }
+
+    pub fn update(&mut self) -> bool {
+        let now = SystemTimer::now();
+
+        if !self.target.is_some_and(|target| target <= now) {
+            return false;
+        }
+
+        let target_change = self.table.on_timer(now);
+        self.apply(target_change);
+
+        true
+    }
+
+    /// see `SystimerQQAlarmQueue::consume_pending`
+    pub fn consume_pending(&mut self) -> Option<impl Iterator<Item = (Option<QQOwner>, usize)> + '_> {
+        self.table.consume_pending().map(|pending| pending.map(|(tag, id)| (QQOwner::from_tag(tag), id)))
+    }
+}
+
+impl<const N: usize> TaggedQQAlarmQueue for MockQQAlarmQueue<N> {
+    fn add_owned(&mut self, owner: QQOwner, wake_at: u64) -> Result<usize, QQAlarmError> {
+        let (id, target_change) = self.table.add(owner.tag(), wake_at)?;
+        self.apply(target_change);
+
+        Ok(id)
+    }
+
+    fn add_periodic_owned(&mut self, owner: QQOwner, period: u64, phase: u64) -> Result<usize, QQAlarmError> {
+        let (id, target_change) = self.table.add_periodic(owner.tag(), SystemTimer::now(), period, phase)?;
+        self.apply(target_change);
+
+        Ok(id)
+    }
+
+    fn remove(&mut self, id: usize) -> Result<(), QQAlarmError> {
+        let target_change = self.table.remove(id)?;
+        self.apply(target_change);
+
+        Ok(())
+    }
+
+    fn len(&self) -> usize {
+        self.table.len()
+    }
+
+    fn capacity(&self) -> usize {
+        self.table.capacity()
+    }
+
+    fn overflow_count(&self) -> u32 {
+        self.table.overflow_count()
+    }
+
+    fn update(&mut self) -> bool {
+        Self::update(self)
+    }
+
+    fn consume_pending(&mut self) -> Option<impl Iterator<Item = (Option<QQOwner>, usize)> + '_> {
+        Self::consume_pending(self)
+    }
+}
+
+
+
+/// Drives mock devices from the main loop, raises interrupt flags instead of real peripherals:
+/// - scd30 data ready pin, when synthetic measurment is ready (see `i2c::MockScd30`)
+/// - rmt ch2 end, every `IR_FRAME_PERIOD` with synthetic ir frame (see `ir`)
+pub struct MockHardware {
+    sdc_ready_pin: u8,
+    next_ir_frame_at: u64,
+    ir_frame_count: u32,
+}
+
+impl MockHardware {
+    const IR_FRAME_PERIOD: u64 = SystemTimer::TICKS_PER_SECOND * 5;
+
+
+    /// `sdc_ready` is only used to get pin number, pin is still used by scd30 machine.
+    pub fn new<RDY: PinNumber>(_sdc_ready: &RDY) -> Self {
+        Self {
+            sdc_ready_pin: RDY::NUMBER,
+            next_ir_frame_at: SystemTimer::now() + Self::IR_FRAME_PERIOD,
+            ir_frame_count: 0,
+        }
+    }
+
+    pub fn update(&mut self) -> bool {
+        let now = SystemTimer::now();
+        let mut did_something = false;
+
+        if i2c::update(now) {
+            interrupts::gpio_interrupt_raise(GPIOInterruptStatus::pin(self.sdc_ready_pin));
+            did_something = true;
+        }
+
+        if now >= self.next_ir_frame_at {
+            ir::next_frame(self.ir_frame_count);
+            interrupts::rmt_interrupt_raise(RMTInterruptStatus::CH2_END);
+
+            self.ir_frame_count = self.ir_frame_count.wrapping_add(1);
+            self.next_ir_frame_at += Self::IR_FRAME_PERIOD;
+            did_something = true;
+        }
+
+        did_something
+    }
+}
+
+impl<'c, 'i, W, Q, const N: usize> Machine<Context<'c, 'i, W, Q, N>> for MockHardware
+where
+    W: Write + UsbWriter,
+    Q: TaggedQQAlarmQueue,
+{
+    fn name(&self) -> &'static str {
+        "mock hw"
+    }
+
+    fn update(&mut self, _context: &mut Context<'c, 'i, W, Q, N>) -> bool {
+        Self::update(self)
+    }
+}
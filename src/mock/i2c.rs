@@ -0,0 +1,366 @@
+/* mock i2c bus with synthetic scd30, sht3x, bmp280 (feature `bme280`) and oled display (feature `oled-display`), transactions are completed instantly (interrupt flags are raised directly) */
+
+use core::cell::RefCell;
+
+use critical_section::Mutex;
+use esp_hal::timer::systimer::SystemTimer;
+
+use heapless::Vec;
+
+use crate::{
+    interrupts::{self, I2CInterruptStatus},
+    machines::ambient_sensor::Sht3x,
+    pac_utils::i2c::{I2CCommand, ShortRead, MAX_COMMANDS},
+    sdc,
+    sensirion_word
+};
+#[cfg(feature = "bme280")]
+use crate::machines::bme280::Bme280;
+#[cfg(feature = "oled-display")]
+use crate::machines::display::Display;
+
+
+
+/// Response prepared by the last write, returned by the next read (same as i2c fifo).
+type Response = Vec<u8, 32>;
+
+
+fn push_param(response: &mut Response, param: u16) {
+    // cannot fail, longest response (scd30 measurment) has 18 bytes
+    let _ = response.extend_from_slice(&sensirion_word::encode_word(param));
+}
+
+/// Triangle wave `0 - 16 - 0` with period of 32 samples, so synthetic values slowly change.
+fn triangle(sample: u32) -> f32 {
+    let phase = (sample % 32) as f32;
+    if phase < 16.0 { phase } else { 32.0 - phase }
+}
+
+
+/// Scd30 with continuous measurment, new measurment is ready every interval after start.
+struct MockScd30 {
+    interval_secs: u16,
+    automatic_self_calibration: bool,
+    temperature_offset: u16,
+    altitude: u16,
+    next_measurment_at: Option<u64>,
+    ready: bool,
+    sample: u32,
+}
+
+impl MockScd30 {
+    const fn new() -> Self {
+        Self {
+            interval_secs: 2,
+            automatic_self_calibration: false,
+            temperature_offset: 0,
+            altitude: 0,
+            next_measurment_at: None,
+            ready: false,
+            sample: 0,
+        }
+    }
+
+    fn write(&mut self, bytes: &[u8], response: &mut Response) {
+        let param = match bytes {
+            [_, _, b2, b1, _crc] => u16::from_be_bytes([*b2, *b1]),
+            _ => 0,
+        };
+
+        match bytes {
+            // set / get measurment interval
+            [0x46, 0x00, _, _, _] => self.interval_secs = param.max(2),
+            [0x46, 0x00] => push_param(response, self.interval_secs),
+            // start continuous measurment
+            [0x00, 0x10, ..] => self.next_measurment_at = Some(SystemTimer::now() + self.interval_secs as u64 * SystemTimer::TICKS_PER_SECOND),
+            // stop continuous measurment
+            [0x01, 0x04] => {
+                self.next_measurment_at = None;
+                self.ready = false;
+            },
+            // set automatic self calibration
+            [0x53, 0x06, _, _, _] => self.automatic_self_calibration = param != 0,
+            // get automatic self calibration
+            [0x53, 0x06] => push_param(response, self.automatic_self_calibration as u16),
+            // set / get temperature offset
+            [0x54, 0x03, _, _, _] => self.temperature_offset = param,
+            [0x54, 0x03] => push_param(response, self.temperature_offset),
+            // set / get altitude compensation
+            [0x51, 0x02, _, _, _] => self.altitude = param,
+            [0x51, 0x02] => push_param(response, self.altitude),
+            // get firmware version (3.66)
+            [0xd1, 0x00] => push_param(response, 0x0342),
+            // get data ready status
+            [0x02, 0x02] => push_param(response, self.ready as u16),
+            // read measurment
+            [0x03, 0x00] => {
+                let t = triangle(self.sample);
+
+                for value in [500.0 + 40.0 * t, 22.0 + 0.1 * t, 45.0 - 0.5 * t] {
+                    let bits = f32::to_bits(value);
+                    push_param(response, (bits >> 16) as u16);
+                    push_param(response, bits as u16);
+                }
+
+                self.ready = false;
+            },
+            // other commands are acknowledged and ignored
+            _ => {},
+        }
+    }
+
+    /// Returns `true` when new measurment is ready (data ready pin rises).
+    fn update(&mut self, now: u64) -> bool {
+        let Some(next_measurment_at) = self.next_measurment_at else {
+            return false;
+        };
+
+        if now < next_measurment_at {
+            return false;
+        }
+
+        self.next_measurment_at = Some(next_measurment_at + self.interval_secs as u64 * SystemTimer::TICKS_PER_SECOND);
+        self.ready = true;
+        self.sample = self.sample.wrapping_add(1);
+
+        true
+    }
+}
+
+
+/// Sht3x single shot measurment (21.5 °C, 40 %).
+struct MockSht3x;
+
+impl MockSht3x {
+    fn write(&mut self, bytes: &[u8], response: &mut Response) {
+        if bytes == [0x24, 0x00] {
+            // raw = (T + 45) * (2^16 - 1) / 175, raw = RH * (2^16 - 1) / 100
+            push_param(response, ((21_500 + 45_000) * 65_535u64 / 175_000) as u16);
+            push_param(response, (40_000 * 65_535u64 / 100_000) as u16);
+        }
+    }
+}
+
+
+/// Bmp280 with calibration and measurment from the datasheet example (25.08 °C, 1006.53 hPa).
+#[cfg(feature = "bme280")]
+struct MockBmp280;
+
+#[cfg(feature = "bme280")]
+impl MockBmp280 {
+    const CALIBRATION: [u16; 12] = [
+        27504, 26435, (-1000i16) as u16,
+        36477, (-10685i16) as u16, 3024, 2855, 140, (-7i16) as u16, 15500, (-14600i16) as u16, 6000,
+    ];
+
+    /// Register address is written first, following read starts at the address.
+    fn write(&mut self, bytes: &[u8], response: &mut Response) {
+        // cannot fail, longest read (calibration) has 26 bytes
+        match bytes {
+            // chip id
+            [0xd0] => { let _ = response.push(0x58); },
+            // calibration (last 2 bytes are reserved)
+            [0x88] => {
+                Self::CALIBRATION.iter().for_each(|word| { let _ = response.extend_from_slice(&word.to_le_bytes()); });
+                let _ = response.extend_from_slice(&[0, 0]);
+            },
+            // measurment (pressure, temperature, no humidity)
+            [0xf7] => { let _ = response.extend_from_slice(&[0x65, 0x5a, 0xc0, 0x7e, 0xed, 0x00, 0x80, 0x00]); },
+            // control registers are acknowledged and ignored, other registers are read as zero
+            _ => {},
+        }
+    }
+}
+
+
+/// Write split into chunks (see `prepare_chunk`), address and data written so far.
+type PendingWrite = (u8, Vec<u8, 64>);
+
+struct MockI2CBus {
+    scd30: MockScd30,
+    sht3x: MockSht3x,
+    #[cfg(feature = "bme280")]
+    bmp280: MockBmp280,
+    /// address and response of the last write
+    response: Option<(u8, Response)>,
+    /// bytes of the last read (i2c fifo)
+    fifo: Response,
+    /// commands and tx fifo of the chunk prepared by `prepare_chunk`
+    chunk: (Vec<I2CCommand, MAX_COMMANDS>, Response),
+    pending_write: Option<PendingWrite>,
+}
+
+impl MockI2CBus {
+    /// Write to a device without response is nacked.
+    fn write(&mut self, address: u8, bytes: &[u8]) -> bool {
+        let mut response = Response::new();
+
+        match address {
+            sdc::DEFAULT_ADDRESS => self.scd30.write(bytes, &mut response),
+            Sht3x::DEFAULT_ADDRESS => self.sht3x.write(bytes, &mut response),
+            #[cfg(feature = "bme280")]
+            Bme280::DEFAULT_ADDRESS => self.bmp280.write(bytes, &mut response),
+            // commands and data are acknowledged and ignored
+            #[cfg(feature = "oled-display")]
+            Display::DEFAULT_ADDRESS => {},
+            _ => return false,
+        }
+
+        self.response = Some((address, response));
+        true
+    }
+
+    /// Read is nacked when the last write was to different device, missing bytes are read as `0xff` (see `pad_fifo`).
+    fn read(&mut self, address: u8) -> bool {
+        let Some((response_address, response)) = self.response.take() else {
+            return false;
+        };
+
+        if response_address != address {
+            return false;
+        }
+
+        self.fifo = response;
+        true
+    }
+
+    /// Device which sent its whole response leaves sda high (released bus), it is read as `0xff`.
+    fn pad_fifo(&mut self, len: usize) {
+        while self.fifo.len() < len && self.fifo.push(0xff).is_ok() {}
+    }
+
+    /// Executes prepared chunk, returns interrupt raised by the peripheral.
+    fn run_chunk(&mut self) -> I2CInterruptStatus {
+        let (commands, tx_fifo) = core::mem::take(&mut self.chunk);
+        let mut tx_fifo = tx_fifo.into_iter();
+        // bytes read by this chunk, bytes of previous chunks were already taken from fifo
+        let mut read_len = 0;
+
+        for command in commands {
+            match command {
+                I2CCommand::Write { len, .. } => {
+                    let mut bytes = tx_fifo.by_ref().take(len as usize);
+
+                    if self.pending_write.is_none() {
+                        // first byte after start is address
+                        let Some(address_byte) = bytes.next() else {
+                            return I2CInterruptStatus::NACK;
+                        };
+                        let address = address_byte >> 1;
+
+                        if address_byte & 1 == 1 {
+                            if !self.read(address) {
+                                return I2CInterruptStatus::NACK;
+                            }
+                            continue;
+                        }
+
+                        self.pending_write = Some((address, Vec::new()));
+                    }
+
+                    if let Some((_, data)) = &mut self.pending_write {
+                        bytes.for_each(|byte| { let _ = data.push(byte); });
+                    }
+                },
+                I2CCommand::Stop => {
+                    if let Some((address, data)) = self.pending_write.take() && !self.write(address, &data) {
+                        return I2CInterruptStatus::NACK;
+                    }
+
+                    return I2CInterruptStatus::TRANSACTION_COMPLETE;
+                },
+                I2CCommand::End => return I2CInterruptStatus::END_DETECT,
+                // read bytes are already in fifo (`read`)
+                I2CCommand::Read { len, .. } => {
+                    read_len += len as usize;
+                    self.pad_fifo(read_len);
+                },
+                I2CCommand::Start => {},
+            }
+        }
+
+        I2CInterruptStatus::TRANSACTION_COMPLETE
+    }
+}
+
+static BUS: Mutex<RefCell<MockI2CBus>> = Mutex::new(RefCell::new(MockI2CBus {
+    scd30: MockScd30::new(),
+    sht3x: MockSht3x,
+    #[cfg(feature = "bme280")]
+    bmp280: MockBmp280,
+    response: None,
+    fifo: Vec::new(),
+    chunk: (Vec::new(), Vec::new()),
+    pending_write: None,
+}));
+
+
+
+/// Write of whole transaction at once.
+pub fn write(address: u8, bytes: &[u8]) {
+    let acked = critical_section::with(|cs| BUS.borrow_ref_mut(cs).write(address, bytes));
+
+    interrupts::i2c_interrupt_raise(if acked { I2CInterruptStatus::TRANSACTION_COMPLETE } else { I2CInterruptStatus::NACK });
+}
+
+/// Read of whole transaction at once.
+pub fn read(address: u8, len: u8) {
+    let acked = critical_section::with(|cs| {
+        let mut bus = BUS.borrow_ref_mut(cs);
+        let acked = bus.read(address);
+        if acked {
+            bus.pad_fifo(len as usize);
+        }
+        acked
+    });
+
+    interrupts::i2c_interrupt_raise(if acked { I2CInterruptStatus::TRANSACTION_COMPLETE } else { I2CInterruptStatus::NACK });
+}
+
+/// Chunk of transaction (see `i2c_utils::prepare_chunk_unchecked`), it is executed by `start_chunk`.
+pub fn prepare_chunk(commands: &[I2CCommand], bytes: &[u8]) {
+    critical_section::with(|cs| {
+        let mut bus = BUS.borrow_ref_mut(cs);
+        // cannot fail, lengths are checked by caller
+        bus.chunk = (Vec::from_slice(commands).unwrap_or_default(), Vec::from_slice(bytes).unwrap_or_default());
+    });
+}
+
+pub fn start_chunk() {
+    let interrupt = critical_section::with(|cs| {
+        let mut bus = BUS.borrow_ref_mut(cs);
+        let interrupt = bus.run_chunk();
+
+        if interrupt.is_error() {
+            bus.pending_write = None;
+        }
+
+        interrupt
+    });
+
+    interrupts::i2c_interrupt_raise(interrupt);
+}
+
+/// Same as hardware, nothing is read when fifo is shorter than `buffer`.
+pub fn read_response_into(buffer: &mut [u8]) -> Result<(), ShortRead> {
+    critical_section::with(|cs| {
+        let mut bus = BUS.borrow_ref_mut(cs);
+        let len = buffer.len();
+
+        if bus.fifo.len() < len {
+            return Err(ShortRead { expected: len, available: bus.fifo.len() });
+        }
+
+        buffer.copy_from_slice(&bus.fifo[..len]);
+        let remaining = bus.fifo.len() - len;
+        bus.fifo.rotate_left(len);
+        bus.fifo.truncate(remaining);
+
+        Ok(())
+    })
+}
+
+/// Advances synthetic devices, returns `true` when scd30 has new measurment ready.
+pub fn update(now: u64) -> bool {
+    critical_section::with(|cs| BUS.borrow_ref_mut(cs).scd30.update(now))
+}
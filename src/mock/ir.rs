@@ -0,0 +1,69 @@
+/* synthetic ir frames for ir reciever (rmt ch2), alternating NEC and Sony SIRC remotes */
+
+use core::cell::RefCell;
+
+use critical_section::Mutex;
+
+use heapless::Vec;
+
+use crate::sony_ir::{self, SonyIRCommand, SonyIRRawCommand};
+
+
+
+/// Pulse lengths in rmt ch2 ticks (28 us), first pulse is mark.
+type Frame = Vec<u16, 128>;
+
+static FRAME: Mutex<RefCell<Frame>> = Mutex::new(RefCell::new(Vec::new()));
+
+
+/// NEC short pulse (560 us)
+const NEC_SHORT: u16 = 20;
+/// SIRC unit (600 us)
+const SIRC_UNIT: u16 = 21;
+
+
+fn nec_frame(address: u8, command: u8, frame: &mut Frame) {
+    // cannot fail, frame has 68 pulses
+    let _ = frame.extend_from_slice(&[16 * NEC_SHORT, 8 * NEC_SHORT]);
+
+    for byte in [address, !address, command, !command] {
+        for bit in 0..8 {
+            let space = if (byte >> bit) & 1 == 1 { 3 * NEC_SHORT } else { NEC_SHORT };
+            let _ = frame.extend_from_slice(&[NEC_SHORT, space]);
+        }
+    }
+
+    let _ = frame.push(NEC_SHORT);
+}
+
+fn sirc_frame(command: SonyIRCommand, frame: &mut Frame) {
+    for (mark, space) in sony_ir::tx::pulses(SonyIRRawCommand::from_command(command)) {
+        let _ = frame.push(mark * SIRC_UNIT);
+
+        // last space is end marker
+        if space != 0 {
+            let _ = frame.push(space * SIRC_UNIT);
+        }
+    }
+}
+
+
+/// Prepares frame number `count`, it is read by `frame_pulses` (after rmt ch2 end interrupt).
+pub fn next_frame(count: u32) {
+    let command = (count / 2 % 10) as u8;
+
+    let mut frame = Frame::new();
+
+    if count % 2 == 0 {
+        nec_frame(0x04, command, &mut frame);
+    } else {
+        sirc_frame(SonyIRCommand::V12 { address: 1, command }, &mut frame);
+    }
+
+    critical_section::with(|cs| *FRAME.borrow_ref_mut(cs) = frame);
+}
+
+/// Pulses of the last frame, frame is consumed.
+pub fn frame_pulses() -> impl Iterator<Item = u16> {
+    critical_section::with(|cs| FRAME.take(cs)).into_iter()
+}
@@ -0,0 +1,130 @@
+/* non blocking network stack (smoltcp over esp-wifi sta device) polled from the main loop, shared by network machines */
+
+use esp_wifi::{current_millis, wifi::{WifiDevice, WifiStaDevice}};
+use smoltcp::{
+    iface::{Config, Interface, SocketHandle, SocketSet, SocketStorage},
+    socket::{dhcpv4, tcp, udp},
+    time::Instant,
+    wire::{EthernetAddress, HardwareAddress, IpAddress, IpCidr, IpEndpoint, Ipv4Address},
+};
+
+
+
+fn timestamp() -> Instant {
+    Instant::from_millis(current_millis() as i64)
+}
+
+
+/// Interface with dhcp client and sockets of network machines.
+///
+/// Esp-wifi's own `WifiStack` sockets block (connect, read), so machines use smoltcp sockets directly through this stack.
+/// Machines keep only socket handles, the stack is passed to their `update` (same as i2c bus).
+pub struct NetStack<'a> {
+    iface: Interface,
+    device: WifiDevice<'static, WifiStaDevice>,
+    sockets: SocketSet<'a>,
+    dhcp: SocketHandle,
+    /// address assigned by dhcp
+    address: Option<Ipv4Address>,
+    /// last used local port of tcp connections
+    local_port: u16,
+}
+
+impl<'a> NetStack<'a> {
+    /// ephemeral port range (rfc 6335)
+    const LOCAL_PORT_MIN: u16 = 49152;
+    const LOCAL_PORT_MAX: u16 = 65535;
+
+
+    /// `storage` has to have one slot for dhcp client and one for each socket of machines.
+    pub fn new(mut device: WifiDevice<'static, WifiStaDevice>, storage: &'a mut [SocketStorage<'a>]) -> Self {
+        let config = Config::new(HardwareAddress::Ethernet(EthernetAddress(device.mac_address())));
+        let iface = Interface::new(config, &mut device, timestamp());
+
+        let mut sockets = SocketSet::new(storage);
+        let dhcp = sockets.add(dhcpv4::Socket::new());
+
+        Self {
+            iface,
+            device,
+            sockets,
+            dhcp,
+            address: None,
+            local_port: Self::LOCAL_PORT_MIN,
+        }
+    }
+
+    /// Processes incoming and outgoing packets and dhcp events, returns `true` when something changed.
+    pub fn update(&mut self) -> bool {
+        let mut did_something = self.iface.poll(timestamp(), &mut self.device, &mut self.sockets);
+
+        match self.sockets.get_mut::<dhcpv4::Socket>(self.dhcp).poll() {
+            Some(dhcpv4::Event::Configured(config)) => {
+                self.iface.update_ip_addrs(|addrs| {
+                    addrs.clear();
+                    // cannot fail, list was cleared
+                    let _ = addrs.push(IpCidr::Ipv4(config.address));
+                });
+
+                match config.router {
+                    Some(router) => { let _ = self.iface.routes_mut().add_default_ipv4_route(router); },
+                    None => { self.iface.routes_mut().remove_default_ipv4_route(); },
+                }
+
+                self.address = Some(config.address.address());
+                did_something = true;
+            },
+            Some(dhcpv4::Event::Deconfigured) => {
+                self.deconfigure();
+                did_something = true;
+            },
+            None => {},
+        }
+
+        did_something
+    }
+
+    fn deconfigure(&mut self) {
+        self.iface.update_ip_addrs(|addrs| addrs.clear());
+        self.iface.routes_mut().remove_default_ipv4_route();
+        self.address = None;
+    }
+
+    /// Forgets the address and starts dhcp again, called when wifi connection is lost (new network may have different addresses).
+    pub fn reset(&mut self) {
+        self.sockets.get_mut::<dhcpv4::Socket>(self.dhcp).reset();
+        self.deconfigure();
+    }
+
+    pub fn address(&self) -> Option<Ipv4Address> {
+        self.address
+    }
+
+    pub fn is_up(&self) -> bool {
+        self.address.is_some()
+    }
+
+    pub fn add_udp_socket(&mut self, socket: udp::Socket<'a>) -> SocketHandle {
+        self.sockets.add(socket)
+    }
+
+    pub fn add_tcp_socket(&mut self, socket: tcp::Socket<'a>) -> SocketHandle {
+        self.sockets.add(socket)
+    }
+
+    pub fn udp(&mut self, handle: SocketHandle) -> &mut udp::Socket<'a> {
+        self.sockets.get_mut(handle)
+    }
+
+    pub fn tcp(&mut self, handle: SocketHandle) -> &mut tcp::Socket<'a> {
+        self.sockets.get_mut(handle)
+    }
+
+    /// Starts connecting tcp socket (does not wait for the connection, see `tcp::Socket::may_send`).
+    pub fn tcp_connect(&mut self, handle: SocketHandle, address: IpAddress, port: u16) -> Result<(), tcp::ConnectError> {
+        self.local_port = if self.local_port == Self::LOCAL_PORT_MAX { Self::LOCAL_PORT_MIN } else { self.local_port + 1 };
+
+        let socket = self.sockets.get_mut::<tcp::Socket>(handle);
+        socket.connect(self.iface.context(), IpEndpoint::new(address, port), self.local_port)
+    }
+}
@@ -0,0 +1,5 @@
+/* sony ir types and decoder are in logic crate, only transmitter (rmt) is here */
+
+pub use rust_esp_logic::sony_ir::*;
+
+pub mod tx;
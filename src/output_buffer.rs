@@ -0,0 +1,280 @@
+use core::fmt::Write;
+
+
+use esp_hal::timer::systimer::SystemTimer;
+
+use heapless::{String, Vec};
+
+
+use crate::{
+    error_registry::{self, Subsystem},
+    framing::{self, FrameType, MAX_FRAME_LEN, MAX_PAYLOAD_LEN},
+    ring_buffer::{Ignore, RingBuffer, RingBufferError},
+    usb_writer::{OverflowPolicy, UsbOutputMode},
+};
+
+
+
+/// Buffered host output shared by writer backends (usb serial jtag, uart): overflow policy, drop markers and framing
+/// of text in framed mode. Backend only moves bytes from the buffer into its hardware fifo.
+pub struct OutputBuffer<const BUFFER_SIZE: usize> {
+    buffer: RingBuffer<u8, BUFFER_SIZE, Ignore>,
+    overflow_policy: OverflowPolicy,
+    dropped_bytes_pending: usize, // dropped since last marker
+    dropped_bytes_total: u64,
+    drop_marker_period: u64,
+    last_drop_marker_at: Option<u64>,
+    high_water_mark: usize,
+    output_mode: UsbOutputMode,
+    log_line: Vec<u8, MAX_PAYLOAD_LEN>, // incomplete line of text in framed mode
+    frame_sequence: u8,
+    text_suppressed: bool,
+}
+
+impl<const BUFFER_SIZE: usize> OutputBuffer<BUFFER_SIZE> {
+    const DEFAULT_DROP_MARKER_PERIOD: u64 = SystemTimer::TICKS_PER_SECOND; // 1s
+
+    /// error registry code of buffer overflow (recorded as `Subsystem::Usb` for all backends)
+    pub const ERROR_CODE_OVERFLOW: u16 = 0x01;
+
+
+    /// `drop_marker_period` is minimal delay between two "[n bytes dropped]" markers, in system timer ticks.
+    pub fn new(overflow_policy: OverflowPolicy, drop_marker_period: Option<u64>, output_mode: UsbOutputMode) -> Self {
+        Self {
+            buffer: RingBuffer::new(),
+            overflow_policy,
+            dropped_bytes_pending: 0,
+            dropped_bytes_total: 0,
+            drop_marker_period: drop_marker_period.unwrap_or(Self::DEFAULT_DROP_MARKER_PERIOD),
+            last_drop_marker_at: None,
+            high_water_mark: 0,
+            output_mode,
+            log_line: Vec::new(),
+            frame_sequence: 0,
+            text_suppressed: false,
+        }
+    }
+
+    fn on_dropped(&mut self, count: usize) {
+        self.dropped_bytes_pending += count;
+        self.dropped_bytes_total += count as u64;
+
+        if count != 0 {
+            error_registry::record(Subsystem::Usb, Self::ERROR_CODE_OVERFLOW);
+        }
+    }
+
+    /// Counts `count` bytes as dropped without writing anything (no error is recorded), see `emit_drop_marker`.
+    pub fn discard(&mut self, count: usize) {
+        self.dropped_bytes_pending += count;
+        self.dropped_bytes_total += count as u64;
+    }
+
+    /// Same as `discard` with length of the encoded frame, sequence number is used up, so the host sees the gap.
+    pub fn discard_frame(&mut self, frame_type: FrameType, payload: &[u8]) {
+        let mut frame = [0u8; MAX_FRAME_LEN];
+        if let Ok(len) = framing::encode_frame(frame_type, self.frame_sequence, payload, &mut frame) {
+            self.frame_sequence = self.frame_sequence.wrapping_add(1);
+            self.discard(len);
+        }
+    }
+
+    /// Drops all buffered bytes (and incomplete line of framed mode), they are counted same as by `discard`.
+    pub fn discard_buffered(&mut self) {
+        let count = self.buffer.len() + self.log_line.len();
+
+        self.buffer.clear();
+        self.log_line.clear();
+        self.discard(count);
+    }
+
+    /// Writes "[n bytes dropped]" marker into the buffer, when some bytes were dropped since last marker.
+    /// Marker is written only when it fits into the free space (it never causes another drop) and at most once per `drop_marker_period`.
+    ///
+    /// Called by backends before each buffered write and after fifo refill, not by `write` (marker would be written
+    /// in the middle of framed text flush).
+    pub fn emit_drop_marker(&mut self) {
+        // marker is text too, it is written after suppression ends
+        if self.dropped_bytes_pending == 0 || self.text_suppressed {
+            return;
+        }
+
+        let now = SystemTimer::now();
+        if let Some(last_drop_marker_at) = self.last_drop_marker_at && now < last_drop_marker_at + self.drop_marker_period {
+            return;
+        }
+
+        let mut marker = String::<32>::new();
+        if writeln!(marker, "[{} bytes dropped]", self.dropped_bytes_pending).is_err() {
+            return;
+        }
+
+        let mut frame = [0u8; MAX_FRAME_LEN];
+        let marker = match self.output_mode {
+            UsbOutputMode::Text => marker.as_bytes(),
+            UsbOutputMode::Framed => match framing::encode_frame(FrameType::Log, self.frame_sequence, marker.as_bytes(), &mut frame) {
+                Ok(len) => &frame[..len],
+                Err(_) => return,
+            },
+        };
+
+        if self.buffer.capacity() - self.buffer.len() < marker.len() {
+            return;
+        }
+
+        // cannot fail, there is enough free space
+        let _ = self.buffer.extend_from_slice(marker);
+
+        if self.output_mode == UsbOutputMode::Framed {
+            self.frame_sequence = self.frame_sequence.wrapping_add(1);
+        }
+
+        self.dropped_bytes_pending = 0;
+        self.last_drop_marker_at = Some(now);
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) -> Result<(), RingBufferError> {
+        let free = self.buffer.capacity() - self.buffer.len();
+
+        let bytes = if bytes.len() > free {
+            match self.overflow_policy {
+                OverflowPolicy::RejectNewest => {
+                    self.on_dropped(bytes.len());
+                    return Err(RingBufferError::Overflow);
+                },
+                OverflowPolicy::DropOldest => {
+                    // only last `capacity` bytes can fit into the buffer
+                    let skip = bytes.len().saturating_sub(self.buffer.capacity());
+                    let bytes = &bytes[skip..];
+
+                    let discarded = self.buffer.discard_front(bytes.len() - free);
+                    self.on_dropped(skip + discarded);
+
+                    bytes
+                },
+            }
+        } else {
+            bytes
+        };
+
+        self.buffer.extend_from_slice(bytes)?;
+        self.high_water_mark = self.high_water_mark.max(self.buffer.len());
+
+        Ok(())
+    }
+
+    /// Writes whole frame (see `framing`), frame is written even in text mode.
+    pub fn write_frame(&mut self, frame_type: FrameType, payload: &[u8]) -> Result<(), RingBufferError> {
+        let mut frame = [0u8; MAX_FRAME_LEN];
+        // too long payload is dropped same as data which do not fit into the buffer
+        let len = framing::encode_frame(frame_type, self.frame_sequence, payload, &mut frame).map_err(|_| RingBufferError::Overflow)?;
+
+        self.frame_sequence = self.frame_sequence.wrapping_add(1);
+
+        self.write(&frame[..len])
+    }
+
+    /// Sends incomplete line of text as log frame (framed mode).
+    fn flush_log_line(&mut self) -> Result<(), RingBufferError> {
+        if self.log_line.is_empty() {
+            return Ok(());
+        }
+
+        let line = self.log_line.clone();
+        self.log_line.clear();
+
+        self.write_frame(FrameType::Log, &line)
+    }
+
+    /// Sends incomplete line of text which is held back in framed mode, does nothing in text mode.
+    pub fn flush(&mut self) -> Result<(), RingBufferError> {
+        match self.output_mode {
+            UsbOutputMode::Text => Ok(()),
+            UsbOutputMode::Framed => self.flush_log_line(),
+        }
+    }
+
+    /// Text output (`core::fmt::Write` of writers), in framed mode each line is sent as log frame.
+    pub fn write_text(&mut self, s: &str) -> Result<(), RingBufferError> {
+        if self.text_suppressed {
+            self.discard(s.len());
+            return Ok(());
+        }
+
+        match self.output_mode {
+            UsbOutputMode::Text => self.write(s.as_bytes()),
+            UsbOutputMode::Framed => {
+                let mut result = Ok(());
+
+                // line is sent without '\n', too long line is split into more frames
+                for byte in s.bytes() {
+                    if byte == b'\n' {
+                        result = result.and(self.flush_log_line());
+                    } else if let Err(byte) = self.log_line.push(byte) {
+                        result = result.and(self.flush_log_line());
+                        // cannot fail, line is empty after flush
+                        let _ = self.log_line.push(byte);
+                    }
+                }
+
+                result
+            },
+        }
+    }
+
+    // used by uart writer (feature `uart-output`)
+    #[cfg_attr(not(feature = "uart-output"), allow(dead_code))]
+    pub fn pop_front(&mut self) -> Option<u8> {
+        self.buffer.pop_front()
+    }
+
+    /// Buffered bytes, backend removes sent bytes from the front (batched fifo writes, see `pac_utils::usb_serial`).
+    pub fn buffer_mut(&mut self) -> &mut RingBuffer<u8, BUFFER_SIZE, Ignore> {
+        &mut self.buffer
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.len() == 0
+    }
+
+    pub fn output_mode(&self) -> UsbOutputMode {
+        self.output_mode
+    }
+
+    /// Suppressed text is counted as dropped (see `discard`), so the host gets drop marker after suppression ends.
+    pub fn set_text_suppressed(&mut self, suppressed: bool) {
+        self.text_suppressed = suppressed;
+    }
+
+    pub fn set_overflow_policy(&mut self, overflow_policy: OverflowPolicy) {
+        self.overflow_policy = overflow_policy;
+    }
+
+    pub fn set_output_mode(&mut self, output_mode: UsbOutputMode) {
+        if self.output_mode == UsbOutputMode::Framed {
+            let _ = self.flush_log_line();
+        }
+
+        self.output_mode = output_mode;
+    }
+
+    pub fn dropped_bytes(&self) -> u64 {
+        self.dropped_bytes_total
+    }
+
+    pub fn free_space(&self) -> usize {
+        self.buffer.capacity() - self.buffer.len()
+    }
+
+    pub fn buffered_len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn high_water_mark(&self) -> usize {
+        self.high_water_mark
+    }
+
+    pub fn reset_high_water_mark(&mut self) {
+        self.high_water_mark = self.buffer.len();
+    }
+}
@@ -1,2 +1,6 @@
+pub mod gpio;
 pub mod i2c;
-pub mod rmt;
\ No newline at end of file
+#[cfg(any(feature = "rgb-led", feature = "piezo-buzzer", feature = "ventilation-pwm"))]
+pub mod ledc;
+pub mod rmt;
+pub mod usb_serial;
\ No newline at end of file
@@ -0,0 +1,14 @@
+use esp_hal::gpio::GpioPin;
+
+
+
+/// GPIO number of a pin type, esp-hal keeps pin number private, so it is derived from `GpioPin<N>` type instead.
+///
+/// Erased pins (`AnyPin`) do not implement this, number has to be taken before erasing the pin.
+pub trait PinNumber {
+    const NUMBER: u8;
+}
+
+impl<const N: u8> PinNumber for GpioPin<N> {
+    const NUMBER: u8 = N;
+}
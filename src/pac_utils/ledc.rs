@@ -0,0 +1,127 @@
+use esp_hal::{gpio::{Level, Output, OutputPin}, peripheral::{Peripheral, PeripheralRef}, peripherals::{self, LEDC, SYSTEM}};
+
+use crate::pac_utils::gpio::PinNumber;
+
+
+
+/// Ledc channels (all channels of esp32c6 are low speed).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedcChannel {
+    // used by rgb status led (feature `rgb-led`)
+    #[cfg_attr(not(feature = "rgb-led"), allow(dead_code))]
+    Ch0,
+    #[cfg_attr(not(feature = "rgb-led"), allow(dead_code))]
+    Ch1,
+    #[cfg_attr(not(feature = "rgb-led"), allow(dead_code))]
+    Ch2,
+    // used by piezo buzzer (feature `piezo-buzzer`)
+    #[cfg_attr(not(feature = "piezo-buzzer"), allow(dead_code))]
+    Ch3,
+    // used by pwm fan (feature `ventilation-pwm`)
+    #[cfg_attr(not(feature = "ventilation-pwm"), allow(dead_code))]
+    Ch4,
+    #[allow(dead_code)] // not used by any machine yet
+    Ch5,
+}
+
+impl LedcChannel {
+    fn index(&self) -> usize {
+        *self as usize
+    }
+
+    /// Gpio matrix output signal of the channel.
+    fn out_signal(&self) -> u8 {
+        *self as u8 // LEDC_LS_SIGn
+    }
+}
+
+
+pub struct LedcTimerConfig {
+    /// source clock divider, fixed point with 8 fractional bits
+    pub clk_div: u32,
+    /// duty resolution in bits, pwm frequency is `source clock / clk_div / 2^duty_res`
+    pub duty_res: u8,
+}
+
+/// Enables ledc and selects PLL_F80M_CLK (80 MHz) as its source clock.
+pub fn config_clock(system: PeripheralRef<SYSTEM>) {
+    system.ledc_conf().modify(|_, w| w.ledc_clk_en().set_bit().ledc_rst_en().clear_bit());
+    // SAFETY: 1 selects PLL_F80M_CLK
+    system.ledc_sclk_conf().modify(|_, w| unsafe { w.ledc_sclk_sel().bits(1) }.ledc_sclk_en().set_bit());
+}
+
+pub fn config(ledc: PeripheralRef<LEDC>) {
+    ledc.conf().modify(|_, w| w.clk_en().set_bit()); // register clock always on
+}
+
+pub fn timer_config(ledc: PeripheralRef<LEDC>, timer: u8, config: LedcTimerConfig) {
+    let conf = ledc.timer(timer as usize).conf();
+
+    // TODO: safety
+    conf.modify(|_, w| unsafe {
+        w
+            .tick_sel().clear_bit() // source clock (not ref tick)
+            .rst().clear_bit()
+            .pause().clear_bit()
+            .clk_div().bits(config.clk_div)
+            .duty_res().bits(config.duty_res)
+    });
+    conf.modify(|_, w| w.para_up().set_bit());
+}
+
+/// Channel `ch` is driven by `timer`, output starts at zero duty.
+pub fn channel_config(mut ledc: PeripheralRef<LEDC>, ch: LedcChannel, timer: u8) {
+    let regs = ledc.ch(ch.index());
+
+    regs.hpoint().write(|w| unsafe { w.hpoint().bits(0) });
+    // SAFETY: there are 4 timers
+    regs.conf0().modify(|_, w| unsafe { w.timer_sel().bits(timer) }.sig_out_en().set_bit());
+
+    set_duty(ledc.reborrow(), ch, 0);
+}
+
+/// Duty is number of timer ticks of high level (up to `2^duty_res`), it is applied at the start of next pwm period.
+pub fn set_duty(ledc: PeripheralRef<LEDC>, ch: LedcChannel, duty: u32) {
+    let regs = ledc.ch(ch.index());
+
+    // duty register has 4 fractional bits
+    regs.duty().write(|w| unsafe { w.duty().bits(duty << 4) });
+    regs.conf1().write(|w| w.duty_start().set_bit());
+    // one step without change (fading is not used)
+    ledc.ch_gamma_wr(ch.index()).write(|w| unsafe {
+        w
+            .ch_gamma_duty_inc().set_bit()
+            .ch_gamma_duty_num().bits(1)
+            .ch_gamma_duty_cycle().bits(1)
+            .ch_gamma_scale().bits(0)
+    });
+    regs.conf0().modify(|_, w| w.para_up().set_bit());
+}
+
+
+/// Pin is connected to output of channel `ch`, gpio number is taken from pin type.
+pub fn setup_output_pin<'a, PIN>(
+    pin: impl Peripheral<P = PIN> + 'a,
+    ch: LedcChannel,
+) -> Output<'a, PIN>
+where
+    PIN: OutputPin + PinNumber
+{
+    let pin_num = PIN::NUMBER;
+    let pin = Output::new(pin, Level::Low);
+
+    // SAFETY: only registers of pin owned by this function are accessed
+    let pac_gpio = unsafe { peripherals::GPIO::steal() };
+    let pac_io_mux = unsafe { peripherals::IO_MUX::steal() };
+
+    pac_io_mux.gpio(pin_num as usize).modify(|_, w| unsafe {
+        w.mcu_sel().bits(1) // set alternate function to 1 - use gpio matrix
+    });
+    pac_gpio.func_out_sel_cfg(pin_num as usize).modify(|_, w| unsafe {
+        w
+            .out_sel().bits(ch.out_signal()) // LEDC_LS_SIGn
+            .oen_sel().set_bit() // output enable from gpio enable register (set by `Output`)
+    });
+
+    pin
+}
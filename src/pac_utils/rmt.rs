@@ -1,14 +1,21 @@
+#[cfg(not(feature = "mock-hw"))]
 use core::iter;
 
-use esp_hal::{gpio::{Input, InputPin, Pull}, peripheral::{Peripheral, PeripheralRef}, peripherals::{self, RMT, SYSTEM}, rmt::PulseCode};
+use esp_hal::{gpio::{Input, InputPin, Level, Output, OutputPin, Pull}, peripheral::{Peripheral, PeripheralRef}, peripherals::{self, RMT, SYSTEM}, rmt::PulseCode};
 
-use crate::interrupts::RMTInterruptStatus;
+use heapless::Vec;
+
+use crate::{error_registry::ErrorCode, interrupts::RMTInterruptStatus, pac_utils::gpio::PinNumber};
+#[cfg(feature = "mock-hw")]
+use crate::interrupts;
 
 
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RMTError {
     Unknown(RMTInterruptStatus),
+    /// pulse codes do not fit into channel memory (see `TxMemWriter`, `tx_fifo_fill` writes nothing then)
+    TxMemOverflow,
 }
 
 impl RMTError {
@@ -17,6 +24,18 @@ impl RMTError {
     }
 }
 
+impl ErrorCode for RMTError {
+    fn error_code(&self) -> u16 {
+        match self {
+            RMTError::Unknown(_) => 0x30,
+            RMTError::TxMemOverflow => 0x31,
+        }
+    }
+}
+
+
+/// Frequency of rmt_sclk configured by `IrRxDispatch` (PLL_F80M_CLK divided by 32), carrier is counted in its ticks.
+pub const SCLK_HZ: u32 = 2_500_000;
 
 pub struct RmtClockConfig {
     pub selection: u8,
@@ -40,63 +59,331 @@ pub fn config(rmt: PeripheralRef<RMT>, use_fifo: bool) {
     rmt.sys_conf().modify(|_, w| w.apb_fifo_mask().bit(!use_fifo)); // fifo on/off
 }
 
+/// Rmt channels which can recieve.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RxChannel {
+    Ch2,
+    #[allow(dead_code)] // not used by any machine yet
+    Ch3,
+}
+
+impl RxChannel {
+    /// Index into rx registers (and rx interrupt fields), rx channels are numbered from 0 there.
+    fn rx_index(&self) -> usize {
+        match self {
+            RxChannel::Ch2 => 0,
+            RxChannel::Ch3 => 1,
+        }
+    }
+
+    /// Index into channel memory (shared by all channels).
+    fn mem_index(&self) -> usize {
+        self.rx_index() + 2
+    }
+
+    /// Interrupt flags of the channel (recieving end, error and threshold).
+    pub fn interrupts(&self) -> RMTInterruptStatus {
+        match self {
+            RxChannel::Ch2 => RMTInterruptStatus::CH2_END | RMTInterruptStatus::CH2_ERROR | RMTInterruptStatus::CH2_THRESHOLD,
+            RxChannel::Ch3 => RMTInterruptStatus::CH3_END | RMTInterruptStatus::CH3_ERROR | RMTInterruptStatus::CH3_THRESHOLD,
+        }
+    }
+
+    pub fn end_interrupt(&self) -> RMTInterruptStatus {
+        match self {
+            RxChannel::Ch2 => RMTInterruptStatus::CH2_END,
+            RxChannel::Ch3 => RMTInterruptStatus::CH3_END,
+        }
+    }
+
+    pub fn threshold_interrupt(&self) -> RMTInterruptStatus {
+        match self {
+            RxChannel::Ch2 => RMTInterruptStatus::CH2_THRESHOLD,
+            RxChannel::Ch3 => RMTInterruptStatus::CH3_THRESHOLD,
+        }
+    }
+
+    /// Input signal of the channel in gpio matrix (RMT_SIG_IN0, RMT_SIG_IN1).
+    fn in_signal(&self) -> usize {
+        match self {
+            RxChannel::Ch2 => 71,
+            RxChannel::Ch3 => 72,
+        }
+    }
+}
+
+
+/// Channel memory block size (in pulse codes).
+pub const RX_MEM_CODES: u16 = 48;
+
 pub struct RmtRxChConfig {
     pub clock_div: u8,
     pub idle_thresh: u16,
+    /// Enables wrap mode, channel memory is written again from start when it is full (instead of error),
+    /// threshold interrupt is raised each time this many pulse codes are written (has to be less than `RX_MEM_CODES`),
+    /// codes have to be read by `rx_fifo_chunk_iter` before they are overwritten.
+    pub wrap_thresh: Option<u16>,
 }
 
-pub fn ch2_config(rmt: PeripheralRef<RMT>, config: RmtRxChConfig) {
-    rmt.ch2_rx_conf0().modify(|_, w| unsafe {
+pub fn rx_config(rmt: PeripheralRef<RMT>, ch: RxChannel, config: RmtRxChConfig) {
+    rmt.ch_rx_conf0(ch.rx_index()).modify(|_, w| unsafe {
         w
             .div_cnt().bits(config.clock_div)
             .idle_thres().bits(config.idle_thresh)
             .carrier_en().bit(false) // disable demodulation
     });
 
-    rmt.ch2_rx_conf1().modify(|_, w| w.conf_update().set_bit()); // sync
+    if let Some(wrap_thresh) = config.wrap_thresh {
+        rmt.ch_rx_lim(ch.rx_index()).modify(|_, w| unsafe { w.rmt_rx_lim().bits(wrap_thresh) });
+    }
+
+    rmt.ch_rx_conf1(ch.rx_index()).modify(|_, w| w.mem_rx_wrap_en().bit(config.wrap_thresh.is_some()));
+    rmt.ch_rx_conf1(ch.rx_index()).modify(|_, w| w.conf_update().set_bit()); // sync
 }
 
-pub fn ch2_enable_interrupts(rmt: PeripheralRef<RMT>) {
+/// `threshold` enables threshold interrupt (used in wrap mode).
+pub fn rx_enable_interrupts(rmt: PeripheralRef<RMT>, ch: RxChannel, threshold: bool) {
     rmt.int_ena().modify(|_, w| {
         w
-            .ch2_rx_end().bit(true)
-            .ch2_rx_err().bit(true)
+            .ch_rx_end(ch.rx_index() as u8).bit(true)
+            .ch_rx_err(ch.rx_index() as u8).bit(true)
+            .ch_rx_thr_event(ch.rx_index() as u8).bit(threshold)
     });
 }
 
-fn ch2_rx_enable(rmt: PeripheralRef<RMT>, enable: bool) {
-    rmt.ch2_rx_conf1().modify(|_, w| w.rx_en().bit(enable)); // enable recieving
-    rmt.ch2_rx_conf1().modify(|_, w| w.conf_update().set_bit()); // sync
+fn rx_enable(rmt: PeripheralRef<RMT>, ch: RxChannel, enable: bool) {
+    rmt.ch_rx_conf1(ch.rx_index()).modify(|_, w| w.rx_en().bit(enable)); // enable recieving
+    rmt.ch_rx_conf1(ch.rx_index()).modify(|_, w| w.conf_update().set_bit()); // sync
 }
 
-pub fn ch2_start(rmt: PeripheralRef<RMT>) {
-    ch2_rx_enable(rmt, true);
+pub fn rx_start(rmt: PeripheralRef<RMT>, ch: RxChannel) {
+    rx_enable(rmt, ch, true);
 }
 
 
-pub fn setup_pins<'a, PIN>(
+/// Rmt channels which can transmit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxChannel {
+    Ch0,
+    Ch1,
+}
+
+impl TxChannel {
+    fn index(&self) -> usize {
+        match self {
+            TxChannel::Ch0 => 0,
+            TxChannel::Ch1 => 1,
+        }
+    }
+
+    /// Interrupt flags of the channel (transmission end and error).
+    pub fn interrupts(&self) -> RMTInterruptStatus {
+        match self {
+            TxChannel::Ch0 => RMTInterruptStatus::CH0_TX_END | RMTInterruptStatus::CH0_TX_ERROR,
+            TxChannel::Ch1 => RMTInterruptStatus::CH1_TX_END | RMTInterruptStatus::CH1_TX_ERROR,
+        }
+    }
+
+    /// Output signal of the channel in gpio matrix (RMT_SIG_OUT0, RMT_SIG_OUT1).
+    fn out_signal(&self) -> u8 {
+        match self {
+            TxChannel::Ch0 => 71,
+            TxChannel::Ch1 => 72,
+        }
+    }
+}
+
+
+/// Channel memory block size (in pulse codes).
+pub const TX_MEM_CODES: u16 = 48;
+
+/// Carrier modulating high level of tx channel output, lengths are in rmt_sclk ticks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RmtCarrier {
+    pub high: u16,
+    pub low: u16,
+}
+
+impl RmtCarrier {
+    /// Carrier closest to `khz` with high level taking `duty_percent` of the period (rounded to whole ticks of
+    /// `SCLK_HZ`), e.g. 38 kHz with 33% duty is 22 + 44 ticks (37.9 kHz).
+    pub const fn from_khz(khz: u32, duty_percent: u32) -> Self {
+        assert!(khz > 0 && duty_percent > 0 && duty_percent < 100);
+
+        let period = (SCLK_HZ + 500 * khz) / (1000 * khz);
+        let high = (period * duty_percent + 50) / 100;
+
+        assert!(high > 0 && high < period && period <= u16::MAX as u32);
+
+        Self { high: high as u16, low: (period - high) as u16 }
+    }
+}
+
+pub struct RmtTxChConfig {
+    pub clock_div: u8,
+    /// `None` disables modulation
+    pub carrier: Option<RmtCarrier>,
+    /// output level while channel is not sending (carrier is never output on idle)
+    pub idle_level: bool,
+}
+
+pub fn tx_config(rmt: PeripheralRef<RMT>, ch: TxChannel, config: RmtTxChConfig) {
+    let carrier = config.carrier.unwrap_or(RmtCarrier { high: 0, low: 0 });
+
+    rmt.chcarrier_duty(ch.index()).write(|w| unsafe {
+        w
+            .carrier_high().bits(carrier.high)
+            .carrier_low().bits(carrier.low)
+    });
+
+    rmt.ch_tx_conf0(ch.index()).modify(|_, w| unsafe {
+        w
+            .div_cnt().bits(config.clock_div)
+            .carrier_en().bit(config.carrier.is_some()) // enable modulation
+            .carrier_out_lv().bit(true) // modulate high level
+            .carrier_eff_en().bit(true) // no carrier on idle
+            .idle_out_en().bit(true)
+            .idle_out_lv().bit(config.idle_level)
+    });
+
+    rmt.ch_tx_conf0(ch.index()).modify(|_, w| w.conf_update().set_bit()); // sync
+}
+
+pub fn tx_enable_interrupts(rmt: PeripheralRef<RMT>, ch: TxChannel) {
+    rmt.int_ena().modify(|_, w| {
+        w
+            .ch_tx_end(ch.index() as u8).bit(true)
+            .ch_tx_err(ch.index() as u8).bit(true)
+    });
+}
+
+/// Writes `pulse_codes` into channel memory through fifo (rmt is configured with `use_fifo`),
+/// sequence should end with end marker (zero length).
+///
+/// Channel memory has space for `TX_MEM_CODES` pulse codes, longer sequence is rejected before anything is written
+/// (memory keeps the previous sequence).
+pub fn tx_fifo_fill(mut rmt: PeripheralRef<RMT>, ch: TxChannel, pulse_codes: impl Iterator<Item = PulseCode>) -> Result<(), RMTError> {
+    let mut codes = [PulseCode::default(); TX_MEM_CODES as usize];
+    let mut len = 0;
+
+    for pulse_code in pulse_codes {
+        *codes.get_mut(len).ok_or(RMTError::TxMemOverflow)? = pulse_code;
+        len += 1;
+    }
+
+    let mut writer = TxMemWriter::new(rmt.reborrow(), ch);
+    codes[..len].iter().try_for_each(|pulse_code| writer.write(*pulse_code))
+}
+
+/// Writes pulse codes into channel memory through fifo (rmt is configured with `use_fifo`) from its start.
+///
+/// Written codes are counted, so at most `TX_MEM_CODES` codes are written, fifo write address would wrap and the
+/// first codes would be overwritten (channel memory address is never computed by hand).
+pub struct TxMemWriter<'a> {
+    rmt: PeripheralRef<'a, RMT>,
+    ch: TxChannel,
+    len: u16,
+}
+
+impl<'a> TxMemWriter<'a> {
+    pub fn new(rmt: PeripheralRef<'a, RMT>, ch: TxChannel) -> Self {
+        rmt.ch_tx_conf0(ch.index()).modify(|_, w| w.apb_mem_rst().set_bit()); // reset fifo write address
+
+        Self { rmt, ch, len: 0 }
+    }
+
+    /// Number of codes which can still be written.
+    pub fn remaining(&self) -> u16 {
+        TX_MEM_CODES - self.len
+    }
+
+    pub fn write(&mut self, pulse_code: PulseCode) -> Result<(), RMTError> {
+        if self.remaining() == 0 {
+            return Err(RMTError::TxMemOverflow);
+        }
+
+        tx_fifo_push(self.rmt.reborrow(), self.ch, core::iter::once(pulse_code));
+        self.len += 1;
+
+        Ok(())
+    }
+}
+
+/// Writes `pulse_codes` into channel memory after the last written code.
+fn tx_fifo_push(rmt: PeripheralRef<RMT>, ch: TxChannel, pulse_codes: impl Iterator<Item = PulseCode>) {
+    for pulse_code in pulse_codes {
+        rmt.chdata(ch.index()).write(|w| unsafe { w.bits(pulse_code.into()) });
+    }
+}
+
+
+/// Sends pulse codes from channel memory (memory is kept, so the same sequence can be sent again).
+#[cfg(not(feature = "mock-hw"))]
+pub fn tx_start(rmt: PeripheralRef<RMT>, ch: TxChannel) {
+    rmt.ch_tx_conf0(ch.index()).modify(|_, w| w.mem_rd_rst().set_bit()); // reset TX channel's RAM read address
+    rmt.ch_tx_conf0(ch.index()).modify(|_, w| w.conf_update().set_bit()); // sync
+    rmt.ch_tx_conf0(ch.index()).modify(|_, w| w.tx_start().set_bit());
+}
+
+/// Mock transmission is done instantly.
+#[cfg(feature = "mock-hw")]
+pub fn tx_start(_rmt: PeripheralRef<RMT>, ch: TxChannel) {
+    interrupts::rmt_interrupt_raise(ch.interrupts().difference(RMTInterruptStatus::CH0_TX_ERROR | RMTInterruptStatus::CH1_TX_ERROR));
+}
+
+
+/// Pin is connected to input of channel `ch`, gpio number is taken from pin type.
+pub fn setup_input_pin<'a, PIN>(
     pin: impl Peripheral<P = PIN> + 'a,
+    ch: RxChannel,
 ) -> Input<'a, PIN>
 where
-    PIN: InputPin
+    PIN: InputPin + PinNumber
 {
+    let pin_num = PIN::NUMBER;
     let pin = Input::new(pin, Pull::None);
 
-    let pin_num = 10;
-
     // TODO
     // SAFETY: only pin owned by this function is accessed ???
     let pac_gpio = unsafe { peripherals::GPIO::steal() };
     let pac_io_mux = unsafe { peripherals::IO_MUX::steal() };
 
     // TODO: safety
-    pac_io_mux.gpio(pin_num).modify(|_, w| unsafe {
+    pac_io_mux.gpio(pin_num as usize).modify(|_, w| unsafe {
         w.mcu_sel().bits(1) // set alternate function to 1 - use gpio matrix
     });
-    pac_gpio.func_in_sel_cfg(71).modify(|_, w| unsafe {
+    pac_gpio.func_in_sel_cfg(ch.in_signal()).modify(|_, w| unsafe {
         w
             .sel().set_bit() // use gpio matrix for input
-            .in_sel().bits(pin_num as u8) // connect input to gpio via gpio matrix
+            .in_sel().bits(pin_num) // connect input to gpio via gpio matrix
+    });
+
+    pin
+}
+
+
+/// Pin is connected to output of channel `ch`, gpio number is taken from pin type.
+pub fn setup_output_pin<'a, PIN>(
+    pin: impl Peripheral<P = PIN> + 'a,
+    ch: TxChannel,
+) -> Output<'a, PIN>
+where
+    PIN: OutputPin + PinNumber
+{
+    let pin_num = PIN::NUMBER;
+    let pin = Output::new(pin, Level::Low);
+
+    // SAFETY: only registers of pin owned by this function are accessed
+    let pac_gpio = unsafe { peripherals::GPIO::steal() };
+    let pac_io_mux = unsafe { peripherals::IO_MUX::steal() };
+
+    pac_io_mux.gpio(pin_num as usize).modify(|_, w| unsafe {
+        w.mcu_sel().bits(1) // set alternate function to 1 - use gpio matrix
+    });
+    pac_gpio.func_out_sel_cfg(pin_num as usize).modify(|_, w| unsafe {
+        w
+            .out_sel().bits(ch.out_signal()) // RMT_SIG_OUTn
+            .oen_sel().set_bit() // output enable from gpio enable register (set by `Output`)
     });
 
     pin
@@ -119,19 +406,20 @@ impl HalfPulseCode {
 }
 
 
-pub fn ch2_fifo_iter<'a>(mut rmt: PeripheralRef<'a, RMT>, pause_rx: bool) -> impl Iterator<Item = HalfPulseCode> + 'a {
-    if pause_rx {
-        ch2_rx_enable(rmt.reborrow(), false);
-    }
-
+/// Reads pulse codes until end marker (or until `max_codes` codes were read).
+#[cfg(not(feature = "mock-hw"))]
+fn fifo_iter(rmt: PeripheralRef<'_, RMT>, ch: RxChannel, max_codes: Option<u16>) -> impl Iterator<Item = HalfPulseCode> + '_ {
     let mut end_marker = false;
+    let mut codes = 0;
 
     iter::repeat_with(move || {
-        if end_marker {
+        if end_marker || max_codes.is_some_and(|max_codes| codes == max_codes) {
             return [None, None];
         }
-            
-        let (pulse1, pulse2) = HalfPulseCode::from_pulse_code(PulseCode::from(rmt.ch2data().read().bits()));
+
+        codes += 1;
+
+        let (pulse1, pulse2) = HalfPulseCode::from_pulse_code(PulseCode::from(rmt.chdata(ch.mem_index()).read().bits()));
 
         let pulse1_zero = pulse1.length == 0;
         let pulse2_zero = pulse2.length == 0;
@@ -147,8 +435,93 @@ pub fn ch2_fifo_iter<'a>(mut rmt: PeripheralRef<'a, RMT>, pause_rx: bool) -> imp
         .filter_map(|code| code)
 }
 
-pub fn ch2_reset_after_recieving<'a>(rmt: PeripheralRef<'a, RMT>, rx_paused: bool) {
-    rmt.ch2_rx_conf1().modify(|_, w| {
+/// Maximal number of pulses of one frame recieved by `RxStream`.
+pub const RX_STREAM_PULSES: usize = 512;
+
+/// Pulses of frame recieved by rx channel in wrap mode.
+///
+/// Channel memory holds only 48 codes, so each part of `wrap_thresh` codes is read by the rmt interrupt handler as soon
+/// as it was written (see `interrupts::rmt_rx_stream_start`), the rest of the frame is read after channel end by
+/// `finish`. Channel has to be configured with the same `wrap_thresh` and threshold interrupt.
+pub struct RxStream {
+    ch: RxChannel,
+    wrap_thresh: u16,
+    pulses: Vec<u16, RX_STREAM_PULSES>,
+    /// some pulses of the frame did not fit into `pulses`
+    overflow: bool,
+}
+
+impl RxStream {
+    pub fn new(ch: RxChannel, wrap_thresh: u16) -> Self {
+        Self {
+            ch,
+            wrap_thresh,
+            pulses: Vec::new(),
+            overflow: false,
+        }
+    }
+
+    pub fn channel(&self) -> RxChannel {
+        self.ch
+    }
+
+    fn push(&mut self, pulses: impl Iterator<Item = HalfPulseCode>) {
+        for pulse in pulses {
+            self.overflow |= self.pulses.push(pulse.length).is_err();
+        }
+    }
+
+    /// Reads next part of the frame, to be called on threshold interrupt.
+    pub fn read_chunk(&mut self, rmt: PeripheralRef<RMT>) {
+        let chunk = rx_fifo_chunk_iter(rmt, self.ch, self.wrap_thresh);
+        self.push(chunk);
+    }
+
+    /// Reads the rest of the frame after channel end and resets channel memory for the next frame, pulse lengths of
+    /// the whole frame are moved into `pulses`. Returns `false` when some pulses did not fit (`pulses` are incomplete).
+    pub fn finish(&mut self, mut rmt: PeripheralRef<RMT>, pulses: &mut Vec<u16, RX_STREAM_PULSES>) -> bool {
+        let rest = rx_fifo_iter(rmt.reborrow(), self.ch, false);
+        self.push(rest);
+        rx_reset_after_recieving(rmt, self.ch, false);
+
+        *pulses = core::mem::take(&mut self.pulses);
+
+        !core::mem::take(&mut self.overflow)
+    }
+}
+
+/// Pulses recieved after the last read, to be called after recieving end.
+#[cfg(not(feature = "mock-hw"))]
+pub fn rx_fifo_iter(mut rmt: PeripheralRef<'_, RMT>, ch: RxChannel, pause_rx: bool) -> impl Iterator<Item = HalfPulseCode> + '_ {
+    if pause_rx {
+        rx_enable(rmt.reborrow(), ch, false);
+    }
+
+    fifo_iter(rmt, ch, None)
+}
+
+/// Pulses of `wrap_thresh` pulse codes written in wrap mode, to be called after threshold interrupt.
+#[cfg(not(feature = "mock-hw"))]
+pub fn rx_fifo_chunk_iter(rmt: PeripheralRef<'_, RMT>, ch: RxChannel, wrap_thresh: u16) -> impl Iterator<Item = HalfPulseCode> + '_ {
+    fifo_iter(rmt, ch, Some(wrap_thresh))
+}
+
+/// Pulses of the last synthetic frame (see `mock::ir`) instead of channel memory.
+#[cfg(feature = "mock-hw")]
+pub fn rx_fifo_iter(_rmt: PeripheralRef<'_, RMT>, _ch: RxChannel, _pause_rx: bool) -> impl Iterator<Item = HalfPulseCode> + '_ {
+    crate::mock::ir::frame_pulses()
+        .enumerate()
+        .map(|(i, length)| HalfPulseCode { level: i % 2 == 0, length })
+}
+
+/// Synthetic frames are passed whole by `rx_fifo_iter` (threshold interrupt is never raised).
+#[cfg(feature = "mock-hw")]
+pub fn rx_fifo_chunk_iter(_rmt: PeripheralRef<'_, RMT>, _ch: RxChannel, _wrap_thresh: u16) -> impl Iterator<Item = HalfPulseCode> + '_ {
+    core::iter::empty()
+}
+
+pub fn rx_reset_after_recieving(rmt: PeripheralRef<'_, RMT>, ch: RxChannel, rx_paused: bool) {
+    rmt.ch_rx_conf1(ch.rx_index()).modify(|_, w| {
         w
             .mem_wr_rst().bit(true) // reset RX channel's RAM write address
             .apb_mem_rst().bit(true) // reset fifo
@@ -156,6 +529,6 @@ pub fn ch2_reset_after_recieving<'a>(rmt: PeripheralRef<'a, RMT>, rx_paused: boo
     });
 
     if rx_paused {
-        ch2_rx_enable(rmt, true);
+        rx_enable(rmt, ch, true);
     }
 }
\ No newline at end of file
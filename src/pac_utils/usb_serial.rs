@@ -0,0 +1,77 @@
+use esp_hal::{peripherals::USB_DEVICE, timer::systimer::SystemTimer};
+
+use crate::ring_buffer::{OnOverflow, RingBuffer};
+
+
+
+/// Size of serial IN endpoint (one packet), free fifo takes this many bytes.
+pub const EP1_LEN: usize = 64;
+
+
+/// Moves up to one packet from the front of `ring` into the serial IN fifo and flushes it, returns number of moved bytes.
+///
+/// Free flag is checked only once, bytes are then written from contiguous slices of `ring` without checking it. Users
+/// always flush after writing, so free fifo is empty (host took the previous packet) and whole packet fits.
+pub fn write_packet<const N: usize, OVERFLOW: OnOverflow>(usb: &USB_DEVICE, ring: &mut RingBuffer<u8, N, OVERFLOW>) -> usize {
+    if !is_free(usb) {
+        return 0;
+    }
+
+    let mut count = 0;
+
+    // second slice when the data wrap around the end of the ring
+    while count < EP1_LEN && !ring.is_empty() {
+        let bytes = ring.front_slice();
+        let len = bytes.len().min(EP1_LEN - count);
+
+        push(usb, &bytes[..len]);
+
+        ring.consume_front(len);
+        count += len;
+    }
+
+    if count != 0 {
+        flush(usb);
+    }
+
+    count
+}
+
+/// Writes `bytes` into the serial IN fifo packet by packet by spinning (panic path, no buffer and no interrupts).
+///
+/// Gives up when the host does not take a packet for `timeout` (in system timer ticks), returns `true` when all bytes
+/// were written.
+pub fn write_blocking(usb: &USB_DEVICE, mut bytes: &[u8], timeout: u64) -> bool {
+    let mut progress_at = SystemTimer::now();
+
+    while !bytes.is_empty() {
+        if is_free(usb) {
+            let (packet, rest) = bytes.split_at(bytes.len().min(EP1_LEN));
+
+            push(usb, packet);
+            flush(usb);
+
+            bytes = rest;
+            progress_at = SystemTimer::now();
+        } else if SystemTimer::now() >= progress_at + timeout {
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Host took the previous packet.
+fn is_free(usb: &USB_DEVICE) -> bool {
+    usb.ep1_conf().read().serial_in_ep_data_free().bit_is_set()
+}
+
+fn push(usb: &USB_DEVICE, bytes: &[u8]) {
+    for &byte in bytes {
+        usb.ep1().write(|w| unsafe { w.rdwr_byte().bits(byte) }); // TODO: safety
+    }
+}
+
+fn flush(usb: &USB_DEVICE) {
+    usb.ep1_conf().write(|w| w.wr_done().set_bit());
+}
@@ -0,0 +1,192 @@
+/* panic handler: sends buffered output shared with interrupt handler, writes panic message with backtrace and last flight recorder entries unbuffered, then blinks sos on status led */
+
+use core::{fmt::{self, Write}, panic::PanicInfo, sync::atomic::{AtomicBool, AtomicU8, Ordering}};
+
+use esp_hal::{peripherals::{GPIO, USB_DEVICE}, timer::systimer::SystemTimer};
+
+#[cfg(feature = "uart-output")]
+use crate::uart_writer;
+use crate::{flight_recorder, interrupts, pac_utils::{gpio::PinNumber, usb_serial}};
+
+
+
+/// Peripheral written by the panic handler, it is stolen by the handler, writer which owns it is never used again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum PanicOutput {
+    /// esp-println (default)
+    Println,
+    UsbSerial,
+    #[cfg(feature = "uart-output")]
+    Uart0,
+}
+
+
+/// Unbuffered text writer using `write` (returns `false` on timeout), stops writing after the first timeout (host is gone).
+struct RawWriter<F: FnMut(&[u8]) -> bool> {
+    write: F,
+    failed: bool,
+}
+
+impl<F: FnMut(&[u8]) -> bool> Write for RawWriter<F> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.failed = self.failed || !(self.write)(s.as_bytes());
+
+        if self.failed { Err(fmt::Error) } else { Ok(()) }
+    }
+}
+
+
+/// `PanicOutput` as `u8`
+static OUTPUT: AtomicU8 = AtomicU8::new(PanicOutput::Println as u8);
+static PANICKING: AtomicBool = AtomicBool::new(false);
+/// `NO_LED` when status led is not registered
+static STATUS_LED_PIN: AtomicU8 = AtomicU8::new(NO_LED);
+
+/// host not reading for this long is considered gone
+const FLUSH_TIMEOUT: u64 = SystemTimer::TICKS_PER_SECOND / 10;
+/// flight recorder entries written after the panic message
+const PANIC_TRACE_ENTRIES: usize = 32;
+/// return address points after the call instruction
+const RA_OFFSET: usize = 4;
+const NO_LED: u8 = u8::MAX;
+
+/// gpio matrix output signal driven by the gpio output register (simple gpio output)
+const GPIO_OUT_SIGNAL: u8 = 128;
+/// length of sos dot, dash is 3 dots (in ms)
+const SOS_DOT_MS: u64 = 200;
+/// (on, off) durations in dots, last gap is between repetitions
+const SOS: [(u64, u64); 9] = [(1, 1), (1, 1), (1, 3), (3, 1), (3, 1), (3, 3), (1, 1), (1, 1), (1, 7)];
+
+
+
+/// Panic message is written unbuffered directly into the fifo of `output`, without registered output it is printed by
+/// esp-println.
+///
+/// Buffered output of the writer is sent before the panic message only when it is shared with the interrupt handler
+/// (feature `usb-irq-refill`), buffer owned by the main loop cannot be reached from the panic handler.
+pub fn register_output(output: PanicOutput) {
+    OUTPUT.store(output as u8, Ordering::Relaxed);
+}
+
+fn registered_output() -> PanicOutput {
+    match OUTPUT.load(Ordering::Relaxed) {
+        output if output == PanicOutput::UsbSerial as u8 => PanicOutput::UsbSerial,
+        #[cfg(feature = "uart-output")]
+        output if output == PanicOutput::Uart0 as u8 => PanicOutput::Uart0,
+        _ => PanicOutput::Println,
+    }
+}
+
+/// Sends output buffered for the usb interrupt handler, gives up after `timeout` without progress. Nothing is sent
+/// when the panic happened while the buffer was borrowed.
+#[cfg(feature = "usb-irq-refill")]
+fn flush_usb_output(usb: &USB_DEVICE, timeout: u64) {
+    critical_section::with(|cs| {
+        let Ok(mut output) = interrupts::USB_OUTPUT.borrow(cs).try_borrow_mut() else {
+            return;
+        };
+        let Some(output) = output.as_mut() else {
+            return;
+        };
+
+        let _ = output.flush();
+        let mut progress_at = SystemTimer::now();
+
+        while !output.is_empty() {
+            if usb_serial::write_packet(usb, output.buffer_mut()) != 0 {
+                progress_at = SystemTimer::now();
+            } else if SystemTimer::now() >= progress_at + timeout {
+                return;
+            }
+        }
+    });
+}
+
+fn write_panic_and_trace(writer: &mut impl Write, info: &PanicInfo) {
+    let _ = write_panic(writer, info);
+    flight_recorder::write_last(writer, PANIC_TRACE_ENTRIES);
+}
+
+/// Status led blinks sos after panic, pin has to be configured as output (its driver is not used by the panic handler).
+pub fn register_status_led<P: PinNumber>(_pin: &P) {
+    STATUS_LED_PIN.store(P::NUMBER, Ordering::Relaxed);
+}
+
+/// Panic is a function call (not an exception with saved trap frame), registers of the panicking code are gone by now,
+/// backtrace walks frames of its callers instead.
+fn write_panic(writer: &mut impl Write, info: &PanicInfo) -> fmt::Result {
+    writeln!(writer)?;
+    writeln!(writer, "!! {}", info)?;
+    write!(writer, "backtrace :")?;
+
+    for address in esp_backtrace::arch::backtrace().into_iter().flatten() {
+        write!(writer, " 0x{:x}", address.wrapping_sub(RA_OFFSET))?;
+    }
+
+    writeln!(writer)
+}
+
+fn wait_ms(ms: u64) {
+    let until = SystemTimer::now() + ms * SystemTimer::TICKS_PER_SECOND / 1_000;
+
+    while SystemTimer::now() < until {
+        core::hint::spin_loop();
+    }
+}
+
+fn blink_sos(pin: u8) -> ! {
+    // SAFETY: only output level of the led pin is written, nothing else runs after the panic
+    let gpio = unsafe { GPIO::steal() };
+
+    // led may be driven by a peripheral (e.g. ledc of rgb status led), it is switched back to simple gpio output
+    // SAFETY: 128 is valid output signal
+    gpio.func_out_sel_cfg(pin as usize).modify(|_, w| unsafe { w.out_sel().bits(GPIO_OUT_SIGNAL) });
+
+    loop {
+        for (on, off) in SOS {
+            // SAFETY: only bit of the led pin is set
+            gpio.out_w1ts().write(|w| unsafe { w.bits(1 << pin) });
+            wait_ms(on * SOS_DOT_MS);
+            gpio.out_w1tc().write(|w| unsafe { w.bits(1 << pin) });
+            wait_ms(off * SOS_DOT_MS);
+        }
+    }
+}
+
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    // interrupts must not touch the writer being flushed, they stay disabled until reset
+    // SAFETY: critical section is never released
+    unsafe { critical_section::acquire() };
+
+    // panic inside the panic handler (e.g. while flushing) falls back to esp-println
+    let output = if PANICKING.swap(true, Ordering::Relaxed) { PanicOutput::Println } else { registered_output() };
+
+    match output {
+        PanicOutput::UsbSerial => {
+            // SAFETY: usb writer owning the peripheral is never used again, interrupts stay disabled and main loop
+            // does not continue
+            let usb = unsafe { USB_DEVICE::steal() };
+            // host which is not reading is not waited for
+            let timeout = if interrupts::is_host_connected() { FLUSH_TIMEOUT } else { 0 };
+
+            #[cfg(feature = "usb-irq-refill")]
+            flush_usb_output(&usb, timeout);
+
+            write_panic_and_trace(&mut RawWriter { write: |bytes: &[u8]| usb_serial::write_blocking(&usb, bytes, timeout), failed: false }, info);
+        },
+        #[cfg(feature = "uart-output")]
+        PanicOutput::Uart0 => {
+            write_panic_and_trace(&mut RawWriter { write: |bytes: &[u8]| uart_writer::write_blocking(bytes, FLUSH_TIMEOUT), failed: false }, info);
+        },
+        PanicOutput::Println => write_panic_and_trace(&mut esp_println::Printer, info),
+    }
+
+    match STATUS_LED_PIN.load(Ordering::Relaxed) {
+        NO_LED => loop {
+            core::hint::spin_loop();
+        },
+        pin => blink_sos(pin),
+    }
+}
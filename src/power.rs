@@ -0,0 +1,29 @@
+/* idle path of the main loop, core waits for interrupt when there is nothing to do */
+
+use critical_section::CriticalSection;
+
+
+
+/// What main loop does when there is nothing to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdleMode {
+    /// loop keeps spinning, needed when something is polled instead of waiting on interrupt (e.g. mock qq alarm queue)
+    Busy,
+    /// core is stopped by `wfi` until some interrupt is pending
+    /// (qq alarm queue keeps systimer alarm programmed to its next wakeup, so alarms wake the core up)
+    WaitForInterrupt,
+}
+
+
+/// Has to be called inside critical section after checking that no interrupt flags are pending.
+///
+/// `wfi` returns when interrupt is pending even while interrupts are disabled, its handler runs after the critical section ends,
+/// so interrupt which comes between the check and `wfi` is not lost (core does not sleep, or it is woken up right away).
+/// Light sleep is not used, it would stop usb serial (console and logs).
+pub fn idle(_cs: CriticalSection, mode: IdleMode) {
+    match mode {
+        IdleMode::Busy => {},
+        // SAFETY: `wfi` only stops the core until interrupt, it has no other effects
+        IdleMode::WaitForInterrupt => unsafe { core::arch::asm!("wfi") },
+    }
+}
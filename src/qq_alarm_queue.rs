@@ -1,62 +1,186 @@
-use core::{cmp, iter};
+use core::cmp;
 
 
 use esp_hal::{
-    interrupt::Priority, prelude::*, timer::systimer::{Alarm, SystemTimer, Target}, Blocking
+    prelude::*, timer::systimer::{Alarm, SystemTimer, Target}, Blocking
 };
 
-use crate::interrupts::{self, SystimerTartet0InterruptStatus};
+use crate::{
+    alarm_heap::AlarmHeap,
+    alarm_table::{AlarmBook, OwnerTag, TargetChange},
+    interrupts::{self, SystimerTartet0InterruptStatus}
+};
+
+pub use crate::alarm_table::QQAlarmError;
 
 
 
+/// Machine which added qq alarm, pending alarms are dispatched by owner in main (alarms of scheduled machines through
+/// `Scheduler::on_alarm`).
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum QQAlarmError {
-    QueueFull,
-    IdNotFound,
+#[repr(u8)]
+pub enum QQOwner {
+    UsbWriter,
+    StatusLed,
+    DebugPrint,
+    Sdc,
+    AmbientSensor,
+    Bme280,
+    StalenessMonitor,
+    Alert,
+    IrRx,
+    IrSonyTx,
+    IrNecTx,
+    WifiReporter,
+    MqttClient,
+    HttpServer,
+    Watchdog,
+    Button,
+    LedStrip,
+    Display,
+    Ventilation,
+    SdcSecondary,
+}
+
+impl QQOwner {
+    /// ordered by tag
+    const ALL: [QQOwner; 20] = [
+        QQOwner::UsbWriter,
+        QQOwner::StatusLed,
+        QQOwner::DebugPrint,
+        QQOwner::Sdc,
+        QQOwner::AmbientSensor,
+        QQOwner::Bme280,
+        QQOwner::StalenessMonitor,
+        QQOwner::Alert,
+        QQOwner::IrRx,
+        QQOwner::IrSonyTx,
+        QQOwner::IrNecTx,
+        QQOwner::WifiReporter,
+        QQOwner::MqttClient,
+        QQOwner::HttpServer,
+        QQOwner::Watchdog,
+        QQOwner::Button,
+        QQOwner::LedStrip,
+        QQOwner::Display,
+        QQOwner::Ventilation,
+        QQOwner::SdcSecondary,
+    ];
+
+    pub fn tag(self) -> OwnerTag {
+        self as OwnerTag
+    }
+
+    pub fn from_tag(tag: OwnerTag) -> Option<QQOwner> {
+        Self::ALL.get(tag as usize).copied()
+    }
 }
 
 
+/// Qq alarm queue as seen by machines, alarms are added on behalf of the owner given to `TaggedQQAlarmQueue::owned`.
+///
+/// Queue has fixed capacity and never evicts alarms, machines can check `len` / `capacity` before adding
+/// and they have to handle `QQAlarmError::QueueFull` by trying again later.
 pub trait QQAlarmQueue {
+    /// When queue is full, caller should try again later (see `machines::Delay`), alarm is not lost, only delayed.
     fn add(&mut self, wake_at: u64) -> Result<usize, QQAlarmError>;
+    /// Alarm fires at `phase + k * period` (in system timer ticks) until it is removed, id is reported once per period
+    /// (see `AlarmTable::add_periodic`).
+    fn add_periodic(&mut self, period: u64, phase: u64) -> Result<usize, QQAlarmError>;
     // fn debug_add(&mut self, wake_at: u64, uw: &mut impl Write) -> Result<usize, QQAlarmError>;
     fn remove(&mut self, id: usize) -> Result<(), QQAlarmError>;
+
+    /// number of alarms in queue (waiting and pending)
+    fn len(&self) -> usize;
+    fn capacity(&self) -> usize;
+    fn is_full(&self) -> bool {
+        self.len() == self.capacity()
+    }
+    /// number of `add` calls which failed because the queue was full
+    fn overflow_count(&self) -> u32;
+}
+
+/// Qq alarm queue storing owner of each alarm, machines get it wrapped by `owned`.
+pub trait TaggedQQAlarmQueue {
+    fn add_owned(&mut self, owner: QQOwner, wake_at: u64) -> Result<usize, QQAlarmError>;
+    fn add_periodic_owned(&mut self, owner: QQOwner, period: u64, phase: u64) -> Result<usize, QQAlarmError>;
+    fn remove(&mut self, id: usize) -> Result<(), QQAlarmError>;
+    fn len(&self) -> usize;
+    fn capacity(&self) -> usize;
+    fn overflow_count(&self) -> u32;
+    /// Checks the hardware alarm, returns `true` when some alarms became pending.
+    fn update(&mut self) -> bool;
+    /// Pending alarms with their owners, `None` when no alarm is pending.
+    fn consume_pending(&mut self) -> Option<impl Iterator<Item = (Option<QQOwner>, usize)> + '_>;
+
+    /// Alarms added through returned queue are owned by `owner`.
+    fn owned(&mut self, owner: QQOwner) -> OwnedQQ<'_, Self> where Self: Sized {
+        OwnedQQ { qq: self, owner }
+    }
 }
 
+pub struct OwnedQQ<'a, Q> {
+    qq: &'a mut Q,
+    owner: QQOwner,
+}
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum QQAlarmState { Waiting, Pending }
+impl<Q> QQAlarmQueue for OwnedQQ<'_, Q> where Q: TaggedQQAlarmQueue {
+    fn add(&mut self, wake_at: u64) -> Result<usize, QQAlarmError> {
+        self.qq.add_owned(self.owner, wake_at)
+    }
+
+    fn add_periodic(&mut self, period: u64, phase: u64) -> Result<usize, QQAlarmError> {
+        self.qq.add_periodic_owned(self.owner, period, phase)
+    }
+
+    fn remove(&mut self, id: usize) -> Result<(), QQAlarmError> {
+        self.qq.remove(id)
+    }
+
+    fn len(&self) -> usize {
+        self.qq.len()
+    }
 
-#[derive(Debug, Clone, Copy)]
-struct QQAlarm {
-    id: usize,
-    wake_at: u64,
-    state: QQAlarmState,
+    fn capacity(&self) -> usize {
+        self.qq.capacity()
+    }
+
+    fn overflow_count(&self) -> u32 {
+        self.qq.overflow_count()
+    }
 }
 
 
-/// simple QQ alarm queue, no algorithms are used for optimaztion (e.g.: priority queues, ...)
+/// QQ alarm queue driving the timer alarm, bookkeeping of alarms is done by `B` (`AlarmTable` with linear scans of
+/// alarms or `AlarmHeap`, see `HeapQQAlarmQueue`)
 // #[derive(Debug)]
-pub struct DumbQQAlarmQueue<const N: usize> {
+pub struct SystimerQQAlarmQueue<B> {
     alarm: Alarm<Target, Blocking, 0>,
-    queue: [Option<QQAlarm>; N],
-    next_wakeup: Option<u64>,
-    next_id: usize,
-    any_pending: bool,
+    table: B,
 }
 
-impl<const N: usize> DumbQQAlarmQueue<N> {
+/// QQ alarm queue with alarms kept in binary heap, better for larger number of alarms
+pub type HeapQQAlarmQueue<const N: usize> = SystimerQQAlarmQueue<AlarmHeap<N>>;
+
+impl<B> SystimerQQAlarmQueue<B> where B: AlarmBook {
     pub fn new(alarm: Alarm<Target, Blocking, 0>) -> Self {
-        DumbQQAlarmQueue {
+        SystimerQQAlarmQueue {
             alarm,
-            queue: [None; N],
-            next_wakeup: None,
-            next_id: 0,
-            any_pending: false,
+            table: B::default(),
         }
     }
 
-    pub fn enable_interrupt(&mut self) {
-        interrupts::systimer_target0_interrupt_enable(Some(Priority::Priority10));
+    fn apply(&mut self, target_change: TargetChange) {
+        match target_change {
+            TargetChange::Keep => {},
+            TargetChange::Enable(target) => {
+                self.alarm.clear_interrupt();
+                self.alarm.enable_interrupt(true);
+                self.alarm.set_target(target);
+            },
+            TargetChange::Set(target) => self.alarm.set_target(target),
+            TargetChange::Disable => self.alarm.enable_interrupt(false),
+        }
     }
 
     pub fn update(&mut self) -> bool {
@@ -67,141 +191,65 @@ impl<const N: usize> DumbQQAlarmQueue<N> {
             return false;
         }
 
-        let now = SystemTimer::now();
-
-        let mut min_wake_at = None;
-
-        for qq_alarm in self.queue.iter_mut().filter_map(|qq_alarm| qq_alarm.as_mut()).filter(|qq_alarm| qq_alarm.state == QQAlarmState::Waiting) {
-            let wake_at = qq_alarm.wake_at;
-
-            if wake_at <= now {
-                self.any_pending = true;
-                qq_alarm.state = QQAlarmState::Pending;
-            } else {
-                min_wake_at = Some(min_wake_at.map_or(wake_at, |min_wake_at| cmp::min(min_wake_at, wake_at)));
-            }
-        }
-
-        self.next_wakeup = min_wake_at;
-
-        if let Some(min_wake_at) = min_wake_at {
-             // TODO: in documentation is written that you can set target walue lower then `now`, but it doesn't seem to be working here
-             //       (it worked in separate test)
-            let now = SystemTimer::now();
-            self.alarm.set_target(cmp::max(now + 250, min_wake_at));
-        } else {
-            self.alarm.enable_interrupt(false);
+        match self.table.on_timer(SystemTimer::now()) {
+            TargetChange::Set(min_wake_at) => {
+                // TODO: in documentation is written that you can set target walue lower then `now`, but it doesn't seem to be working here
+                //       (it worked in separate test)
+                let now = SystemTimer::now();
+                self.alarm.set_target(cmp::max(now + 250, min_wake_at));
+            },
+            target_change => self.apply(target_change),
         }
 
         true
     }
 
-    /// returned iterator should be fully consumed to free up space in queue
-    /// e.g. `queue.consume_pending().unwrap().take(3)` will cause problems, because fourth pending alarm in iterator will never be consumed and therefore not freed
-    /// if you do not consume whole iterator at one time, be sure to call `consume_pending` again
-    pub fn consume_pending<'a>(&'a mut self) -> Option<impl Iterator<Item = usize> + 'a> {
-        if !self.any_pending {
-            return None;
-        }
-
-        Some(self.queue.iter_mut()
-            .map(|qq_alarm_opt| {
-                if let Some(qq_alarm) = qq_alarm_opt && qq_alarm.state == QQAlarmState::Pending {
-                    let id = qq_alarm.id;
-                    *qq_alarm_opt = None;
-                    Some(id)
-                } else {
-                    None
-                }
-            })
-            .chain(iter::once_with(|| {
-                // sets `any_pending` to false after all pending alarms are set to `None`
-                self.any_pending = false;
-                None
-            }))
-            .filter_map(|id| id)
-        )
+    /// Pending alarms with their owners (`None` for unknown tag),
+    /// returned iterator should be fully consumed to free up space in queue (see `AlarmTable::consume_pending`)
+    pub fn consume_pending(&mut self) -> Option<impl Iterator<Item = (Option<QQOwner>, usize)> + '_> {
+        self.table.consume_pending().map(|pending| pending.map(|(tag, id)| (QQOwner::from_tag(tag), id)))
     }
 }
 
-impl<const N: usize> QQAlarmQueue for DumbQQAlarmQueue<N> {
-    fn add(&mut self, wake_at: u64) -> Result<usize, QQAlarmError> {
-        // assuming wake_at is less than now (if it is not it is ok alarm will cause interrupt instantly)
-        let id = self.next_id;
-        self.next_id += 1;
-    
-        let empty_alarm = self.queue.iter_mut().find(|alarm| alarm.is_none()).ok_or(QQAlarmError::QueueFull)?;
-        *empty_alarm = Some(QQAlarm {
-            id,
-            wake_at,
-            state: QQAlarmState::Waiting,
-        });
-    
-        let set_target = match self.next_wakeup {
-            Some(next_wakeup) => wake_at < next_wakeup,
-            None => {
-                self.alarm.clear_interrupt();
-                self.alarm.enable_interrupt(true);
-                true
-            }
-        };
-    
-        if set_target {
-            self.alarm.set_target(wake_at);
-            self.next_wakeup = Some(wake_at);
-        }
-    
+impl<B> TaggedQQAlarmQueue for SystimerQQAlarmQueue<B> where B: AlarmBook {
+    fn add_owned(&mut self, owner: QQOwner, wake_at: u64) -> Result<usize, QQAlarmError> {
+        let (id, target_change) = self.table.add(owner.tag(), wake_at)?;
+        self.apply(target_change);
+
+        Ok(id)
+    }
+
+    fn add_periodic_owned(&mut self, owner: QQOwner, period: u64, phase: u64) -> Result<usize, QQAlarmError> {
+        let (id, target_change) = self.table.add_periodic(owner.tag(), SystemTimer::now(), period, phase)?;
+        self.apply(target_change);
+
         Ok(id)
     }
 
     fn remove(&mut self, id: usize) -> Result<(), QQAlarmError> {
-        let mut id_found = false;
-    
-        let mut min_wake_at = None;
-        let mut any_pending = false;
-
-        for qq_alarm_opt in self.queue.iter_mut() {
-            if let Some(qq_alarm) = qq_alarm_opt {
-                if qq_alarm.id == id {
-                    id_found = true;
-                    *qq_alarm_opt = None
-                } else {
-                    match qq_alarm.state {
-                        QQAlarmState::Waiting => {
-                            let wake_at = qq_alarm.wake_at;
-                            min_wake_at = Some(min_wake_at.map_or(wake_at, |min_wake_at| cmp::min(min_wake_at, wake_at)));
-                        },
-                        QQAlarmState::Pending => {
-                            any_pending = true;
-                        }
-                    }
-                }
-            }
-        }
+        let target_change = self.table.remove(id)?;
+        self.apply(target_change);
 
-        if !id_found {
-            return Err(QQAlarmError::IdNotFound);
-        }
+        Ok(())
+    }
 
-        match min_wake_at {
-            None => {
-                if self.next_wakeup.is_some() {
-                    self.alarm.enable_interrupt(false);
-                    self.next_wakeup = None;
-                }
-            },
-            Some(min_wake_at) => {
-                // `alarm_queue.next_wakeup` cannot be `None` because we found some waiting alarms
-                if min_wake_at != self.next_wakeup.unwrap() {
-                    self.alarm.set_target(min_wake_at);
-                    self.next_wakeup = Some(min_wake_at);
-                }
-            },
-        }
+    fn len(&self) -> usize {
+        self.table.len()
+    }
 
-        // update to `any_pending` is needed when deleted alarm was pending alarm and all other alarms were not pending (`any_pending` is changed from `true` to `false`)
-        self.any_pending = any_pending;
+    fn capacity(&self) -> usize {
+        self.table.capacity()
+    }
 
-        Ok(())
+    fn overflow_count(&self) -> u32 {
+        self.table.overflow_count()
+    }
+
+    fn update(&mut self) -> bool {
+        Self::update(self)
+    }
+
+    fn consume_pending(&mut self) -> Option<impl Iterator<Item = (Option<QQOwner>, usize)> + '_> {
+        Self::consume_pending(self)
     }
 }
\ No newline at end of file
@@ -0,0 +1,34 @@
+/* software reset of the chip (`reboot` command), optionally into rom download mode, so deployed devices can be
+   re-flashed without pressing the boot button */
+
+use esp_hal::{peripherals::LP_AON, reset};
+
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RebootMode {
+    /// firmware starts again
+    Normal,
+    /// rom bootloader waits for esptool on usb serial (or uart0)
+    Download,
+}
+
+
+/// Resets whole chip (digital system, peripherals included), output which was not flushed is lost.
+///
+/// Esptool does not need this for flashing over usb, usb serial jtag peripheral resets the chip into download mode
+/// itself on the RTS / DTR sequence of esptool. The command is for hosts which only have the console (e.g. ser2net).
+pub fn reboot(mode: RebootMode) -> ! {
+    if mode == RebootMode::Download {
+        // SAFETY: only force download flag is set, it is read by rom bootloader after the reset
+        let lp_aon = unsafe { LP_AON::steal() };
+        lp_aon.sys_cfg().modify(|_, w| w.force_download_boot().set_bit());
+    }
+
+    reset::software_reset();
+
+    // reset takes effect right away
+    loop {
+        core::hint::spin_loop();
+    }
+}
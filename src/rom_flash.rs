@@ -0,0 +1,178 @@
+/* flash regions accessed by esp32c6 rom spiflash functions (linked by `rom_functions.x`) */
+
+use esp_hal::macros::ram;
+
+use crate::{error_registry::ErrorCode, flash::{SectorFlash, SECTOR_SIZE}};
+
+
+
+/// Config store region (two sectors), this is `nvs` partition of default espflash partition table (not used otherwise).
+pub const CONFIG_FLASH_OFFSET: u32 = 0x9000;
+pub const CONFIG_FLASH_SECTORS: u32 = 2;
+
+/// Measurment log region (64 KiB), right after `factory` partition (1 MiB app) of default espflash partition table.
+pub const LOG_FLASH_OFFSET: u32 = 0x11_0000;
+pub const LOG_FLASH_SECTORS: u32 = 16;
+
+/// Learned ir codes region (one sector per code), right after measurment log region.
+pub const IR_FLASH_OFFSET: u32 = 0x12_0000;
+pub const IR_FLASH_SECTORS: u32 = 8;
+
+// app image is in `factory` partition of default espflash partition table (1 MiB at 0x1_0000), espflash refuses image
+// which does not fit into it
+const _: () = assert!(LOG_FLASH_OFFSET >= 0x1_0000 + 0x10_0000, "measurment log region overlaps app image");
+const _: () = assert!(IR_FLASH_OFFSET >= 0x1_0000 + 0x10_0000, "ir codes region overlaps app image");
+const _: () = assert!(IR_FLASH_OFFSET >= LOG_FLASH_OFFSET + LOG_FLASH_SECTORS * SECTOR_SIZE, "ir codes region overlaps measurment log");
+
+
+extern "C" {
+    fn esp_rom_spiflash_read(src_addr: u32, data: *mut u32, len: u32) -> i32;
+    fn esp_rom_spiflash_erase_sector(sector_number: u32) -> i32;
+    fn esp_rom_spiflash_write(dest_addr: u32, data: *const u32, len: u32) -> i32;
+    fn esp_rom_spiflash_unlock() -> i32;
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RomFlashError {
+    /// rom function returned non zero status
+    Rom(i32),
+    /// offset outside of the region or length not multiple of 4
+    OutOfBounds,
+}
+
+impl ErrorCode for RomFlashError {
+    fn error_code(&self) -> u16 {
+        match self {
+            RomFlashError::Rom(_) => 0x60,
+            RomFlashError::OutOfBounds => 0x61,
+        }
+    }
+}
+
+
+fn rom_status(status: i32) -> Result<(), RomFlashError> {
+    match status {
+        0 => Ok(()),
+        status => Err(RomFlashError::Rom(status)),
+    }
+}
+
+// flash cannot be read through cache while rom functions talk to it, code has to run from ram with interrupts disabled
+// (handlers are in flash), so sector erase delays interrupts for its whole duration (tens of ms, see `FlashLogger`)
+//
+// Whole call chain between acquire and release of the critical section is in ram or rom: no closure (it would be
+// a separate function placed in flash), pointers and lengths are computed before the critical section is acquired.
+
+#[ram]
+fn read_words(addr: u32, words: &mut [u32]) -> i32 {
+    let (data, len) = (words.as_mut_ptr(), (words.len() * 4) as u32);
+
+    // SAFETY: restore state is from the acquire right above, rom function does not use the critical section
+    unsafe {
+        let restore_state = critical_section::acquire();
+        let status = esp_rom_spiflash_read(addr, data, len);
+        critical_section::release(restore_state);
+
+        status
+    }
+}
+
+#[ram]
+fn erase_sector(sector_number: u32) -> i32 {
+    // SAFETY: same as `read_words`
+    unsafe {
+        let restore_state = critical_section::acquire();
+        let status = match esp_rom_spiflash_unlock() {
+            0 => esp_rom_spiflash_erase_sector(sector_number),
+            status => status,
+        };
+        critical_section::release(restore_state);
+
+        status
+    }
+}
+
+#[ram]
+fn write_words(addr: u32, words: &[u32]) -> i32 {
+    let (data, len) = (words.as_ptr(), (words.len() * 4) as u32);
+
+    // SAFETY: same as `read_words`
+    unsafe {
+        let restore_state = critical_section::acquire();
+        let status = match esp_rom_spiflash_unlock() {
+            0 => esp_rom_spiflash_write(addr, data, len),
+            status => status,
+        };
+        critical_section::release(restore_state);
+
+        status
+    }
+}
+
+
+
+/// Region of `sectors` sectors at `offset` (sector aligned), rom functions need word aligned buffers, so bytes are copied in chunks.
+///
+/// Regions must not overlap, each one has to be owned by a single user (config store, log).
+pub struct RomFlash {
+    offset: u32,
+    sectors: u32,
+}
+
+impl RomFlash {
+    const CHUNK_WORDS: usize = 8;
+
+
+    pub fn new(offset: u32, sectors: u32) -> Self {
+        Self { offset, sectors }
+    }
+
+    fn check(&self, offset: u32, len: usize) -> Result<u32, RomFlashError> {
+        if len % 4 != 0 || offset as usize + len > (self.sectors * SECTOR_SIZE) as usize {
+            return Err(RomFlashError::OutOfBounds);
+        }
+
+        Ok(self.offset + offset)
+    }
+}
+
+impl SectorFlash for RomFlash {
+    type Error = RomFlashError;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), RomFlashError> {
+        let addr = self.check(offset, bytes.len())?;
+
+        for (i, chunk) in bytes.chunks_mut(Self::CHUNK_WORDS * 4).enumerate() {
+            let mut words = [0u32; Self::CHUNK_WORDS];
+            let words = &mut words[..(chunk.len() / 4)];
+
+            rom_status(read_words(addr + (i * Self::CHUNK_WORDS * 4) as u32, words))?;
+
+            chunk.chunks_exact_mut(4).zip(words.iter()).for_each(|(bytes, word)| bytes.copy_from_slice(&word.to_le_bytes()));
+        }
+
+        Ok(())
+    }
+
+    fn erase_sector(&mut self, sector: u32) -> Result<(), RomFlashError> {
+        let addr = self.check(sector * SECTOR_SIZE, SECTOR_SIZE as usize)?;
+
+        rom_status(erase_sector(addr / SECTOR_SIZE))
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), RomFlashError> {
+        let addr = self.check(offset, bytes.len())?;
+
+        for (i, chunk) in bytes.chunks(Self::CHUNK_WORDS * 4).enumerate() {
+            let mut words = [0u32; Self::CHUNK_WORDS];
+            let words = &mut words[..(chunk.len() / 4)];
+
+            chunk.chunks_exact(4).zip(words.iter_mut()).for_each(|(bytes, word)| *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+
+            rom_status(write_words(addr + (i * Self::CHUNK_WORDS * 4) as u32, words))?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,532 @@
+/* main loop, uniform interface of machines driven by it, their updates, alarm and command dispatch and execution time
+   statistics (of machines and main loop iterations) */
+
+use core::fmt::Write;
+
+use esp_hal::timer::systimer::SystemTimer;
+
+use heapless::Vec;
+
+use crate::{
+    commands,
+    config_store::{Config, ConfigStore},
+    error_registry::{self, Subsystem},
+    event_bus::{Event, EventBus, Subscriber},
+    i2c_bus::I2CBus,
+    i2c_trace,
+    interrupts,
+    invariants::invariant,
+    ir_learning::IrCodeStore,
+    log::{self, log_line, warn, Module},
+    machines::{console::ConsoleCommand, controller::Controller},
+    power::{self, IdleMode},
+    qq_alarm_queue::{QQOwner, TaggedQQAlarmQueue},
+    rom_flash::RomFlash,
+    sinks::Sink,
+    usb_writer::UsbWriter,
+};
+#[cfg(feature = "async-sdc")]
+use crate::executor;
+
+
+
+/// Maximum number of machines with statistics (scheduled machines, usb writer and controller).
+pub const MAX_MACHINES: usize = 24;
+/// Maximum number of sinks of scheduled machines (see `Machine::sink`).
+pub const MAX_SINKS: usize = 4;
+
+
+/// Resources owned by `Scheduler` and lent to machines in `Context` for each update.
+/// `'b` is lifetime of i2c peripheral held by the bus.
+pub struct Resources<'b, W, Q, const N: usize> {
+    pub usb_writer: W,
+    pub qq: Q,
+    pub i2c_bus: I2CBus<'b>,
+    pub events: EventBus,
+    pub controller: Controller<N>,
+    /// current configuration, stored by `config save` (see `commands`)
+    pub config: Config,
+    pub config_store: ConfigStore<RomFlash>,
+    /// learned ir codes, written by ir reciever and read by ir transmitter
+    pub ir_codes: IrCodeStore<RomFlash>,
+}
+
+
+/// Resources shared by scheduled machines, borrowed from `Resources` for one pass of `Scheduler::run`.
+/// `'b` is lifetime of i2c peripheral held by the bus.
+pub struct Context<'a, 'b, W, Q, const N: usize> {
+    pub usb_writer: &'a mut W,
+    /// machines add alarms through `qq.owned(QQOwner::..)`
+    pub qq: &'a mut Q,
+    pub i2c_bus: &'a mut I2CBus<'b>,
+    pub events: &'a mut EventBus,
+    /// updated by scheduler after `inputs` and before `outputs` (see `Scheduler`)
+    pub controller: &'a mut Controller<N>,
+    pub config: &'a mut Config,
+    pub config_store: &'a mut ConfigStore<RomFlash>,
+    pub ir_codes: &'a mut IrCodeStore<RomFlash>,
+    /// statistics before this pass
+    pub stats: StatsSummary,
+}
+
+
+/// Machine driven by `Scheduler`, `C` is context of its update (see `Context`).
+///
+/// Implementations forward to inherent `update` methods of machines, which take only the resources they use.
+pub trait Machine<C> {
+    /// Short name used in statistics.
+    fn name(&self) -> &'static str;
+    /// Returns `true` when machine did something (main loop does not go idle).
+    fn update(&mut self, context: &mut C) -> bool;
+
+    /// Qq alarms of `owner` tag were added by machine, pending alarms of its owners are passed to `on_alarm`.
+    fn owns(&self, _owner: QQOwner) -> bool {
+        false
+    }
+
+    /// Returns `true` when alarm was claimed by machine.
+    fn on_alarm(&mut self, _qq_alarm_id: usize) -> bool {
+        false
+    }
+
+    /// Command of console, ir remote or button (see `Event::Command`), passed to all machines after it was accepted
+    /// by `commands::handle`. Returns `true` when machine did something.
+    fn on_command(&mut self, _command: ConsoleCommand, _context: &mut C) -> bool {
+        false
+    }
+
+    /// Sink passed to `Controller::update` (e.g. queue of flash logger).
+    fn sink(&mut self) -> Option<&mut dyn Sink> {
+        None
+    }
+}
+
+
+/// Machine disabled at runtime (e.g. `Network` without wifi), `None` does nothing.
+impl<C, M: Machine<C>> Machine<C> for Option<M> {
+    fn name(&self) -> &'static str {
+        self.as_ref().map_or("disabled", |machine| machine.name())
+    }
+
+    fn update(&mut self, context: &mut C) -> bool {
+        self.as_mut().is_some_and(|machine| machine.update(context))
+    }
+
+    fn owns(&self, owner: QQOwner) -> bool {
+        self.as_ref().is_some_and(|machine| machine.owns(owner))
+    }
+
+    fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        self.as_mut().is_some_and(|machine| machine.on_alarm(qq_alarm_id))
+    }
+
+    fn on_command(&mut self, command: ConsoleCommand, context: &mut C) -> bool {
+        self.as_mut().is_some_and(|machine| machine.on_command(command, context))
+    }
+
+    fn sink(&mut self) -> Option<&mut dyn Sink> {
+        self.as_mut().and_then(|machine| machine.sink())
+    }
+}
+
+
+/// Machines owned by `Scheduler`, implemented for tuples of machines (machines are updated in order of the tuple).
+pub trait Machines<C> {
+    /// Calls `f` for each machine.
+    fn for_each(&mut self, f: &mut dyn FnMut(&mut dyn Machine<C>));
+    /// Appends sinks of machines to `sinks`.
+    fn sinks<'s>(&'s mut self, sinks: &mut Vec<&'s mut dyn Sink, MAX_SINKS>);
+}
+
+macro_rules! impl_machines {
+    ($($machine:ident),+) => {
+        impl<C, $($machine: Machine<C>),+> Machines<C> for ($($machine,)+) {
+            #[allow(non_snake_case)]
+            fn for_each(&mut self, f: &mut dyn FnMut(&mut dyn Machine<C>)) {
+                let ($($machine,)+) = self;
+                $(f($machine);)+
+            }
+
+            #[allow(non_snake_case)]
+            fn sinks<'s>(&'s mut self, sinks: &mut Vec<&'s mut dyn Sink, MAX_SINKS>) {
+                let ($($machine,)+) = self;
+                $(
+                    if let Some(sink) = $machine.sink() {
+                        invariant!(sinks.push(sink).is_ok(), "more sinks than MAX_SINKS");
+                    }
+                )+
+            }
+        }
+    };
+}
+
+impl_machines!(A);
+impl_machines!(A, B);
+impl_machines!(A, B, C1);
+impl_machines!(A, B, C1, D);
+impl_machines!(A, B, C1, D, E);
+impl_machines!(A, B, C1, D, E, F);
+impl_machines!(A, B, C1, D, E, F, G);
+impl_machines!(A, B, C1, D, E, F, G, H);
+impl_machines!(A, B, C1, D, E, F, G, H, I);
+impl_machines!(A, B, C1, D, E, F, G, H, I, J);
+impl_machines!(A, B, C1, D, E, F, G, H, I, J, K);
+impl_machines!(A, B, C1, D, E, F, G, H, I, J, K, L);
+impl_machines!(A, B, C1, D, E, F, G, H, I, J, K, L, M);
+impl_machines!(A, B, C1, D, E, F, G, H, I, J, K, L, M, N1);
+impl_machines!(A, B, C1, D, E, F, G, H, I, J, K, L, M, N1, O);
+impl_machines!(A, B, C1, D, E, F, G, H, I, J, K, L, M, N1, O, P);
+
+
+/// Execution times in system timer ticks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeStats {
+    pub count: u32,
+    pub total: u64,
+    pub max: u64,
+}
+
+impl TimeStats {
+    const fn new() -> Self {
+        Self { count: 0, total: 0, max: 0 }
+    }
+
+    fn record(&mut self, ticks: u64) {
+        self.count = self.count.saturating_add(1);
+        self.total = self.total.saturating_add(ticks);
+        self.max = self.max.max(ticks);
+    }
+
+    /// in system timer ticks, zero when nothing was recorded
+    pub fn average(&self) -> u64 {
+        if self.count != 0 { self.total / self.count as u64 } else { 0 }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy)]
+struct MachineStats {
+    name: &'static str,
+    updates: TimeStats,
+    /// updates which returned `true`
+    active: u32,
+}
+
+impl MachineStats {
+    const fn new(name: &'static str) -> Self {
+        Self { name, updates: TimeStats::new(), active: 0 }
+    }
+
+    fn record(&mut self, ticks: u64, active: bool) {
+        self.updates.record(ticks);
+        if active {
+            self.active = self.active.saturating_add(1);
+        }
+    }
+}
+
+
+/// Short summary of statistics (since the last `stats` command), periodically printed by `DebugPrint`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatsSummary {
+    /// processing time of main loop iterations (without idle)
+    pub iterations: TimeStats,
+    /// machine with the longest update and its duration (in system timer ticks)
+    pub slowest: Option<(&'static str, u64)>,
+    /// number of times the core was woken up from idle since boot
+    pub wakeups: u32,
+}
+
+
+/// System timer ticks to us (ticks per second is a multiple of 10^6, so nothing overflows for totals).
+pub fn ticks_to_us(ticks: u64) -> u64 {
+    const TICKS_PER_US: u64 = SystemTimer::TICKS_PER_SECOND / 1_000_000;
+    const _: () = assert!(TICKS_PER_US * 1_000_000 == SystemTimer::TICKS_PER_SECOND, "system timer ticks are not whole us");
+
+    ticks / TICKS_PER_US
+}
+
+
+/// Execution time of machines and main loop iterations, kept by machine name.
+struct Stats {
+    machines: [MachineStats; MAX_MACHINES],
+    len: usize,
+    iterations: TimeStats,
+    iteration_start: Option<u64>,
+    wakeups: u32,
+}
+
+impl Stats {
+    const fn new() -> Self {
+        Self {
+            machines: [MachineStats::new(""); MAX_MACHINES],
+            len: 0,
+            iterations: TimeStats::new(),
+            iteration_start: None,
+            wakeups: 0,
+        }
+    }
+
+    /// Slot of machine `name`, new slot is added for unknown name, `None` when all slots are used.
+    fn machine_stats(&mut self, name: &'static str) -> Option<&mut MachineStats> {
+        let index = match self.machines[..self.len].iter().position(|stats| stats.name == name) {
+            Some(index) => index,
+            None => {
+                if !invariant!(self.len < MAX_MACHINES, "more measured machines than statistics slots") {
+                    return None;
+                }
+
+                self.machines[self.len] = MachineStats::new(name);
+                self.len += 1;
+                self.len - 1
+            },
+        };
+
+        Some(&mut self.machines[index])
+    }
+
+    /// Runs `update` (its return value is returned) and records its execution time under `name`.
+    fn measure(&mut self, name: &'static str, update: impl FnOnce() -> bool) -> bool {
+        let start = SystemTimer::now();
+        let active = update();
+        let ticks = SystemTimer::now().wrapping_sub(start);
+
+        if let Some(stats) = self.machine_stats(name) {
+            stats.record(ticks, active);
+        }
+
+        active
+    }
+
+    /// Main loop iteration starts, it ends by `end_iteration` (before the core goes idle).
+    fn start_iteration(&mut self) {
+        self.iteration_start = Some(SystemTimer::now());
+    }
+
+    fn end_iteration(&mut self) {
+        if let Some(start) = self.iteration_start.take() {
+            self.iterations.record(SystemTimer::now().wrapping_sub(start));
+        }
+    }
+
+    fn summary(&self) -> StatsSummary {
+        let slowest = self.machines[..self.len]
+            .iter()
+            .filter(|stats| stats.updates.count != 0)
+            .max_by_key(|stats| stats.updates.max)
+            .map(|stats| (stats.name, stats.updates.max));
+
+        StatsSummary { iterations: self.iterations, slowest, wakeups: self.wakeups }
+    }
+
+    /// Writes execution time of each machine and of main loop iterations (since the last call) and resets statistics.
+    fn write(&mut self, usb_writer: &mut impl Write) {
+        for stats in &mut self.machines[..self.len] {
+            log_line!(
+                usb_writer,
+                "stats",
+                "{:<18} {} updates ({} active), avg {} us, max {} us, total {} us",
+                stats.name,
+                stats.updates.count,
+                stats.active,
+                ticks_to_us(stats.updates.average()),
+                ticks_to_us(stats.updates.max),
+                ticks_to_us(stats.updates.total),
+            );
+
+            *stats = MachineStats::new(stats.name);
+        }
+
+        log_line!(
+            usb_writer,
+            "stats",
+            "loop {} iterations, avg {} us, max {} us",
+            self.iterations.count,
+            ticks_to_us(self.iterations.average()),
+            ticks_to_us(self.iterations.max),
+        );
+
+        self.iterations = TimeStats::new();
+    }
+}
+
+
+/// Cooperative scheduler of the main loop, owns all machines and resources they share (see `Resources`).
+///
+/// Each iteration dispatches pending qq alarms to their owners, updates usb writer, `inputs` (sensors, controls), the
+/// controller (with sinks of machines), `outputs` (machines reading the controller) and handles commands published on
+/// the event bus (see `commands::handle` and `Machine::on_command`). Core goes idle when nothing was done and no
+/// interrupt is pending.
+///
+/// Statistics are kept by machine name, usb writer and controller are measured too.
+pub struct Scheduler<'b, W, Q, I, O, const N: usize> {
+    resources: Resources<'b, W, Q, N>,
+    /// updated before controller
+    inputs: I,
+    /// updated after controller
+    outputs: O,
+    stats: Stats,
+}
+
+impl<'b, W, Q, I, O, const N: usize> Scheduler<'b, W, Q, I, O, N>
+where
+    W: Write + UsbWriter,
+    Q: TaggedQQAlarmQueue,
+    I: for<'a> Machines<Context<'a, 'b, W, Q, N>>,
+    O: for<'a> Machines<Context<'a, 'b, W, Q, N>>,
+{
+    /// Machines are started before (their `start` methods need resources before they are moved here).
+    pub fn new(resources: Resources<'b, W, Q, N>, inputs: I, outputs: O) -> Self {
+        Self {
+            resources,
+            inputs,
+            outputs,
+            stats: Stats::new(),
+        }
+    }
+
+    /// Main loop, `idle_mode` is used when there is nothing to do.
+    pub fn run(mut self, idle_mode: IdleMode) -> ! {
+        let mut sleeping = false;
+
+        loop {
+            self.stats.start_iteration();
+
+            let did_something = self.iteration();
+
+            self.stats.end_iteration();
+
+            // critcal section disables interrupts, interrupt raised after the check wakes the core up from `power::idle` (see its docs)
+            // interrupts
+            // `systimer_target0` - always awaited
+            // `usb` - managed (on/off) by usb task, when on always awaited
+            // `i2c` - managed by i2c bus owner (sdc or ambient sensor task)
+            //         always on and only selected relevant subinterrupts enabled
+            //         (not always awaited, but) when interrupt can happen bus owner is always waiting on it
+            // `gpio` - not working, awaited when not needed (maybe ???), see interrupt statistics of debug print
+            // `uart0` - (feature `uart-output`) txfifo empty, enabled by uart writer only while it has buffered output
+            critical_section::with(|cs| {
+                let no_interrupts = interrupts::systimer_target0_interrupt_get().is_empty()
+                    && interrupts::usb_interrupt_get().is_empty()
+                    && interrupts::i2c_interrupt_get().is_empty()
+                    && interrupts::gpio_interrupt_get().is_empty()
+                    && interrupts::rmt_interrupt_get().is_empty();
+
+                // handler disables txfifo empty interrupt, pending flag is the only trace of it
+                #[cfg(feature = "uart-output")]
+                let no_interrupts = no_interrupts && interrupts::uart0_interrupt_get().is_empty();
+
+                // woken task can be polled only in the next iteration
+                #[cfg(feature = "async-sdc")]
+                let no_interrupts = no_interrupts && !executor::has_ready_tasks();
+
+                if no_interrupts && !did_something {
+                    sleeping = true;
+
+                    power::idle(cs, idle_mode);
+                } else {
+                    if sleeping {
+                        self.stats.wakeups = self.stats.wakeups.wrapping_add(1);
+                    }
+
+                    sleeping = false;
+                }
+            })
+        }
+    }
+
+    /// One pass of the main loop, returns `true` when anything did something.
+    fn iteration(&mut self) -> bool {
+        let mut did_something = false;
+
+        let Resources { usb_writer, qq, i2c_bus, events, controller, config, config_store, ir_codes } = &mut self.resources;
+        let (inputs, outputs, stats) = (&mut self.inputs, &mut self.outputs, &mut self.stats);
+
+        did_something |= qq.update();
+
+        if let Some(qq_pending_alarms) = qq.consume_pending() {
+            qq_pending_alarms.for_each(|(owner, qq_alarm_id)| {
+                let claimed = match owner {
+                    Some(QQOwner::UsbWriter) => usb_writer.on_alarm(qq_alarm_id),
+                    // owners of machines disabled by features (e.g. `Display`) are not found
+                    Some(owner) => Self::on_alarm(inputs, owner, qq_alarm_id) || Self::on_alarm(outputs, owner, qq_alarm_id),
+                    None => false,
+                };
+
+                // alarm can fire while its owner is stopping it (the owner already moved on), which is not a bug
+                if !claimed {
+                    warn!(usb_writer, Module::Main, "qq alarm {} not claimed by its owner ({:?})", qq_alarm_id, owner);
+                }
+            });
+        }
+
+        i2c_bus.update();
+
+        did_something |= stats.measure("usb writer", || usb_writer.update(&mut qq.owned(QQOwner::UsbWriter)));
+
+        let summary = stats.summary();
+        let mut context = Context { usb_writer, qq, i2c_bus, events, controller, config, config_store, ir_codes, stats: summary };
+
+        inputs.for_each(&mut |machine| did_something |= stats.measure(machine.name(), || machine.update(&mut context)));
+
+        {
+            // network outputs send only the latest record (see `WifiReporter`, `MqttClient`), they are not sinks
+            let mut sinks = Vec::new();
+            inputs.sinks(&mut sinks);
+            outputs.sinks(&mut sinks);
+
+            let Context { usb_writer, events, controller, .. } = &mut context;
+            did_something |= stats.measure("controller", || controller.update(*usb_writer, events, &mut sinks));
+        }
+
+        outputs.for_each(&mut |machine| did_something |= stats.measure(machine.name(), || machine.update(&mut context)));
+
+        // sticky flag of log macros, reported in both output modes (error frames, last errors in text mode)
+        if log::take_output_dropped() {
+            error_registry::record(Subsystem::Usb, log::ERROR_CODE_OUTPUT_DROPPED);
+        }
+
+        did_something |= error_registry::write_error_frames(context.usb_writer);
+
+        // ir remote and button commands are handled same as console commands
+        while let Some(event) = context.events.poll(Subscriber::Commands) {
+            let Event::Command(command) = event else {
+                continue;
+            };
+
+            did_something = true;
+
+            if let ConsoleCommand::Stats = command {
+                stats.write(context.usb_writer);
+                continue;
+            }
+
+            if commands::handle(command, &mut context) {
+                inputs.for_each(&mut |machine| { machine.on_command(command, &mut context); });
+                outputs.for_each(&mut |machine| { machine.on_command(command, &mut context); });
+            }
+        }
+
+        did_something |= i2c_trace::write_pending(context.usb_writer);
+
+        did_something
+    }
+
+    /// Passes alarm to machine which is its `owner`, `false` when owner is not one of `machines`.
+    fn on_alarm<'a>(machines: &mut impl Machines<Context<'a, 'b, W, Q, N>>, owner: QQOwner, qq_alarm_id: usize) -> bool
+    where
+        'b: 'a,
+        W: 'a,
+        Q: 'a,
+    {
+        let mut claimed = false;
+
+        machines.for_each(&mut |machine| {
+            if !claimed && machine.owns(owner) {
+                claimed = machine.on_alarm(qq_alarm_id);
+            }
+        });
+
+        claimed
+    }
+}
@@ -1,138 +1,164 @@
-use esp_hal::{peripheral::PeripheralRef, peripherals::I2C0, timer::systimer::SystemTimer};
+use esp_hal::timer::systimer::SystemTimer;
 
 use crate::{
-    interrupts::{self, I2CInterruptStatus},
-    machines::Delay,
+    bus_timing,
+    error_registry::ErrorCode,
+    i2c_bus::{I2CBusUser, TransactionBus},
+    machines::{Delay, State},
     qq_alarm_queue::QQAlarmQueue,
-    sdc::{self, SDCGetCommand, SDCSetCommand},
+    sdc::{self, FirmwareVersion, SDCGetCommand, SDCReadResponseError, SDCSetCommand},
+    sensirion_common::machines::{Command, DelayedRead, DelayedReadError},
     pac_utils::i2c::I2CTransmissionError
 };
 
 
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum State<T> {
-    Active(bool),
-    Done(T)
-}
-
+/// Set command (see `sensirion_common::machines::Command`).
+#[derive(Debug)]
+pub struct Set(Command);
 
+impl Set {
+    pub fn start(bus: &mut impl TransactionBus, address: u8, command: SDCSetCommand) -> Set {
+        let (code, argument) = command.code_and_argument();
+        Set(Command::start(bus, address, code, argument))
+    }
 
-#[derive(Debug, Clone, Copy)]
-pub enum SetState {
-    AwaitingInterrupt,
-    Done,
+    pub fn update(&mut self, bus: &mut impl TransactionBus) -> State<Result<(), I2CTransmissionError>> {
+        self.0.update(bus)
+    }
 }
 
+
+pub type DelayedGetError = DelayedReadError;
+
+/// Get command (see `sensirion_common::machines::DelayedRead`), response is read by `sdc::read_response_*`.
 #[derive(Debug)]
-pub struct Set {
-    state: SetState,
-}
+pub struct DelayedGet(DelayedRead);
 
-impl Set {
-    pub fn start(i2c: PeripheralRef<I2C0>, command: SDCSetCommand) -> Set {
-        sdc::set_command_write(i2c, command);
-        Set {
-            state: SetState::AwaitingInterrupt,
-        }
+impl DelayedGet {
+    pub fn start(bus: &mut impl TransactionBus, address: u8, command: SDCGetCommand, delta: u64) -> DelayedGet {
+        DelayedGet(DelayedRead::start(bus, address, command.code(), command.response_words(), delta))
     }
 
-    pub fn update(&mut self) -> State<Result<(), I2CTransmissionError>> {
-        match self.state {
-            SetState::AwaitingInterrupt => {
-                let pending_interrupts = interrupts::i2c_interrupt_get_and_clear(I2CInterruptStatus::all());
-    
-                if pending_interrupts.is_empty() {
-                    State::Active(false)
-                } else {
-                    self.state = SetState::Done;
-                    let maybe_err = I2CTransmissionError::from_interrupt_flags(pending_interrupts);
-                    State::Done(if let Some(err) = maybe_err { Err(err) } else { Ok(()) })
-                }
-            },
-            SetState::Done => State::Done(Ok(())),
-        }
+    pub fn update(&mut self, qq: &mut impl QQAlarmQueue, bus: &mut impl TransactionBus) -> State<Result<(), DelayedGetError>> {
+        self.0.update(qq, bus)
+    }
+
+    pub fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        self.0.on_alarm(qq_alarm_id)
     }
 }
 
 
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum DelayedGetError {
-    Write(I2CTransmissionError),
-    Read(I2CTransmissionError),
+pub enum ResetError {
+    SoftReset(I2CTransmissionError),
+    FirmwareVersion(DelayedGetError),
+    Response(SDCReadResponseError),
 }
 
-#[derive(Debug, Clone, Copy)]
-enum DelayedGetState {
-    WriteAwaitingInterrupt,
-    Delay(Delay),
-    ReadAwaitingInterrupt,
+impl ResetError {
+    /// `None` when firmware version response was invalid (transactions succeeded).
+    pub fn transmission_error(&self) -> Option<I2CTransmissionError> {
+        match self {
+            ResetError::SoftReset(err) => Some(*err),
+            ResetError::FirmwareVersion(err) => Some(err.transmission_error()),
+            ResetError::Response(_) => None,
+        }
+    }
+}
+
+impl ErrorCode for ResetError {
+    fn error_code(&self) -> u16 {
+        match self {
+            ResetError::SoftReset(err) => err.error_code(),
+            ResetError::FirmwareVersion(err) => err.error_code(),
+            ResetError::Response(err) => err.error_code(),
+        }
+    }
+}
+
+#[derive(Debug)]
+enum ResetState {
+    SoftReset(Set),
+    BootDelay(Delay),
+    FirmwareVersion(DelayedGet),
     Done,
 }
 
+/// Soft reset, wait until sensor boots, read firmware version.
+/// Successfully read firmware version means that the sensor is present and responds.
+///
+/// Started with the bus owned by `user`, bus is released during boot delay (longer than `I2CBus::HEARTBEAT_TIMEOUT`) and
+/// acquired again before reading firmware version, it is owned when the result is returned.
 #[derive(Debug)]
-pub struct DelayedGet {
-    state: DelayedGetState,
-    command: SDCGetCommand,
-    delta: u64, // TODO: unit
+pub struct Reset {
+    state: ResetState,
+    address: u8,
+    user: I2CBusUser,
+    delayed_get_delta: u64, // TODO: unit
 }
 
-impl DelayedGet {
-    pub fn start(i2c: PeripheralRef<I2C0>, command: SDCGetCommand, delta: u64) -> DelayedGet {
-        sdc::get_command_write(i2c, command);
+impl Reset {
+    /// from sdc documentation: sensor boots in less than 2 s (see `bus_timing`)
+    pub const BOOT_DELAY: u64 = SystemTimer::TICKS_PER_SECOND / 1_000 * bus_timing::SDC_BOOT_DELAY_MS;
 
-        DelayedGet {
-            state: DelayedGetState::WriteAwaitingInterrupt,
-            command,
-            delta,
+
+    pub fn start(bus: &mut impl TransactionBus, address: u8, user: I2CBusUser, delayed_get_delta: u64) -> Reset {
+        Reset {
+            state: ResetState::SoftReset(Set::start(bus, address, SDCSetCommand::SoftReset)),
+            address,
+            user,
+            delayed_get_delta,
         }
     }
 
-    pub fn update(&mut self, qq: &mut impl QQAlarmQueue, i2c: PeripheralRef<I2C0>) -> State<Result<(), DelayedGetError>> {
-        match self.state {
-            DelayedGetState::WriteAwaitingInterrupt => {
-                let pending_interrupts = interrupts::i2c_interrupt_get_and_clear(I2CInterruptStatus::all());
-    
-                if pending_interrupts.is_empty() {
-                    State::Active(false)
-                } else {
-                    if let Some(err) = I2CTransmissionError::from_interrupt_flags(pending_interrupts) {
-                        self.state = DelayedGetState::Done;
-                        State::Done(Err(DelayedGetError::Write(err)))
-                    } else {
-                        let wake_at = SystemTimer::now() + self.delta;
-                        let qq_alarm_id = qq.add(wake_at).unwrap();
-                        self.state = DelayedGetState::Delay(Delay::new(qq_alarm_id));
-
+    pub fn update(&mut self, qq: &mut impl QQAlarmQueue, bus: &mut impl TransactionBus) -> State<Result<FirmwareVersion, ResetError>> {
+        match &mut self.state {
+            ResetState::SoftReset(sdc_write) => {
+                match sdc_write.update(bus) {
+                    State::Done(Ok(())) => {
+                        bus.release(self.user);
+                        self.state = ResetState::BootDelay(Delay::start(qq, SystemTimer::now() + Self::BOOT_DELAY));
                         State::Active(true)
-                    }
+                    },
+                    State::Done(Err(err)) => {
+                        self.state = ResetState::Done;
+                        State::Done(Err(ResetError::SoftReset(err)))
+                    },
+                    State::Active(did_something) => State::Active(did_something),
                 }
             },
-            DelayedGetState::Delay(Delay::Done) => {
-                sdc::get_command_read(i2c, self.command);
-                self.state = DelayedGetState::ReadAwaitingInterrupt;
+            ResetState::BootDelay(Delay::Done) => {
+                if !bus.try_acquire(self.user) {
+                    return State::Active(false);
+                }
 
+                self.state = ResetState::FirmwareVersion(DelayedGet::start(bus, self.address, SDCGetCommand::FirmwareVersion, self.delayed_get_delta));
                 State::Active(true)
             },
-            DelayedGetState::ReadAwaitingInterrupt => {
-                let pending_interrupts = interrupts::i2c_interrupt_get_and_clear(I2CInterruptStatus::all());
-    
-                if pending_interrupts.is_empty() {
-                    State::Active(false)
-                } else {
-                    self.state = DelayedGetState::Done;
-                    let maybe_err = I2CTransmissionError::from_interrupt_flags(pending_interrupts);
-                    State::Done(if let Some(err) = maybe_err { Err(DelayedGetError::Read(err)) } else { Ok(()) })
+            ResetState::BootDelay(delay) => State::Active(delay.retry(qq)),
+            ResetState::FirmwareVersion(sdc_delayed_get) => {
+                match sdc_delayed_get.update(qq, bus) {
+                    State::Done(result) => {
+                        self.state = ResetState::Done;
+                        State::Done(result
+                            .map_err(ResetError::FirmwareVersion)
+                            .and_then(|()| sdc::read_response_firmware_version(bus).map_err(ResetError::Response)))
+                    },
+                    State::Active(did_something) => State::Active(did_something),
                 }
             },
-            DelayedGetState::Done => State::Done(Ok(())),
-            DelayedGetState::Delay(Delay::Waiting { .. }) => State::Active(false),
+            // result was already returned
+            ResetState::Done => State::Active(false),
         }
     }
 
     pub fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
         match &mut self.state {
-            DelayedGetState::Delay(delay) => delay.on_alarm(qq_alarm_id),
+            ResetState::BootDelay(delay) => delay.on_alarm(qq_alarm_id),
+            ResetState::FirmwareVersion(sdc_delayed_get) => sdc_delayed_get.on_alarm(qq_alarm_id),
             _ => false,
         }
     }
@@ -0,0 +1,62 @@
+/* word protocol shared by sensirion sensors (scd30, sht3x, ...) on `TransactionBus`, encoding of words is in `sensirion_word` */
+
+use crate::{error_registry::ErrorCode, i2c_bus::TransactionBus, pac_utils::i2c::ShortRead, sensirion_word::{self, CommandBytes, CRCCheckFailed}};
+
+
+
+pub mod machines;
+
+pub use crate::sensirion_word::WORD_LEN;
+
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WordReadError {
+    CRCCheckFailed,
+    /// fewer bytes were received than the response has
+    ShortRead(ShortRead),
+}
+
+impl ErrorCode for WordReadError {
+    fn error_code(&self) -> u16 {
+        match self {
+            WordReadError::CRCCheckFailed => 0x01,
+            WordReadError::ShortRead(err) => err.error_code(),
+        }
+    }
+}
+
+impl From<CRCCheckFailed> for WordReadError {
+    fn from(CRCCheckFailed: CRCCheckFailed) -> Self {
+        WordReadError::CRCCheckFailed
+    }
+}
+
+
+/// Starts write of command, optionally followed by one argument word (command itself has no crc, argument has).
+pub fn command_write(bus: &mut impl TransactionBus, address: u8, command: u16, argument: Option<u16>) {
+    bus.start_write(address, CommandBytes::new(command, argument).as_bytes());
+}
+
+/// Starts read of `words` words (at most 10, response has to fit into rx fifo), words are taken by `read_word`.
+pub fn start_read_words(bus: &mut impl TransactionBus, address: u8, words: u8) {
+    bus.start_read(address, words * WORD_LEN);
+}
+
+/// Takes next word of finished read.
+pub fn read_word(bus: &mut impl TransactionBus) -> Result<u16, WordReadError> {
+    let bytes = bus.read_response::<{ WORD_LEN as usize }>().map_err(WordReadError::ShortRead)?;
+
+    Ok(sensirion_word::decode_word(bytes)?)
+}
+
+/// Takes next `N` words of finished read, stops at first invalid word.
+pub fn read_words<const N: usize>(bus: &mut impl TransactionBus) -> Result<[u16; N], WordReadError> {
+    let mut words = [0; N];
+
+    for word in words.iter_mut() {
+        *word = read_word(bus)?;
+    }
+
+    Ok(words)
+}
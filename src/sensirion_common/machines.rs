@@ -0,0 +1,141 @@
+use esp_hal::timer::systimer::SystemTimer;
+
+use crate::{
+    error_registry::ErrorCode,
+    i2c_bus::TransactionBus,
+    machines::{Delay, State},
+    qq_alarm_queue::QQAlarmQueue,
+    pac_utils::i2c::I2CTransmissionError
+};
+
+use super::{command_write, start_read_words};
+
+
+
+/// Command write (with optional argument), done when the transaction is finished. Bus has to be owned.
+#[derive(Debug)]
+pub struct Command {
+    done: bool,
+}
+
+impl Command {
+    pub fn start(bus: &mut impl TransactionBus, address: u8, command: u16, argument: Option<u16>) -> Command {
+        command_write(bus, address, command, argument);
+
+        Command { done: false }
+    }
+
+    pub fn update(&mut self, bus: &mut impl TransactionBus) -> State<Result<(), I2CTransmissionError>> {
+        if self.done {
+            return State::Done(Ok(()));
+        }
+
+        match bus.transaction_result() {
+            None => State::Active(false),
+            Some(result) => {
+                self.done = true;
+                State::Done(result)
+            },
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DelayedReadError {
+    Write(I2CTransmissionError),
+    Read(I2CTransmissionError),
+}
+
+impl DelayedReadError {
+    pub fn transmission_error(&self) -> I2CTransmissionError {
+        match self {
+            DelayedReadError::Write(err) | DelayedReadError::Read(err) => *err,
+        }
+    }
+}
+
+impl ErrorCode for DelayedReadError {
+    fn error_code(&self) -> u16 {
+        match self {
+            DelayedReadError::Write(err) => 0x10 | err.flags_nibble(),
+            DelayedReadError::Read(err) => 0x20 | err.flags_nibble(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum DelayedReadState {
+    WriteAwaitingInterrupt,
+    Delay(Delay),
+    ReadAwaitingInterrupt,
+    Done,
+}
+
+/// Command write, wait `delta` (sensor prepares the response), read `words` words. Bus has to be owned.
+///
+/// After it is done, response is taken by `sensirion_common::read_word` / `read_words`.
+#[derive(Debug)]
+pub struct DelayedRead {
+    state: DelayedReadState,
+    address: u8,
+    words: u8,
+    delta: u64, // TODO: unit
+}
+
+impl DelayedRead {
+    pub fn start(bus: &mut impl TransactionBus, address: u8, command: u16, words: u8, delta: u64) -> DelayedRead {
+        command_write(bus, address, command, None);
+
+        DelayedRead {
+            state: DelayedReadState::WriteAwaitingInterrupt,
+            address,
+            words,
+            delta,
+        }
+    }
+
+    pub fn update(&mut self, qq: &mut impl QQAlarmQueue, bus: &mut impl TransactionBus) -> State<Result<(), DelayedReadError>> {
+        match self.state {
+            DelayedReadState::WriteAwaitingInterrupt => {
+                match bus.transaction_result() {
+                    None => State::Active(false),
+                    Some(Err(err)) => {
+                        self.state = DelayedReadState::Done;
+                        State::Done(Err(DelayedReadError::Write(err)))
+                    },
+                    Some(Ok(())) => {
+                        let wake_at = SystemTimer::now() + self.delta;
+                        self.state = DelayedReadState::Delay(Delay::start(qq, wake_at));
+
+                        State::Active(true)
+                    },
+                }
+            },
+            DelayedReadState::Delay(Delay::Done) => {
+                start_read_words(bus, self.address, self.words);
+                self.state = DelayedReadState::ReadAwaitingInterrupt;
+
+                State::Active(true)
+            },
+            DelayedReadState::ReadAwaitingInterrupt => {
+                match bus.transaction_result() {
+                    None => State::Active(false),
+                    Some(result) => {
+                        self.state = DelayedReadState::Done;
+                        State::Done(result.map_err(DelayedReadError::Read))
+                    },
+                }
+            },
+            DelayedReadState::Done => State::Done(Ok(())),
+            DelayedReadState::Delay(ref mut delay) => State::Active(delay.retry(qq)),
+        }
+    }
+
+    pub fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        match &mut self.state {
+            DelayedReadState::Delay(delay) => delay.on_alarm(qq_alarm_id),
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,114 @@
+/* outputs (sinks) of measurments, each sink has its own encoding (see `record`) and its own buffer */
+
+use core::fmt::Write;
+
+use esp_hal::timer::systimer::SystemTimer;
+
+use heapless::Vec;
+
+use crate::{
+    framing::FrameType,
+    record::{BinaryEncoding, CsvEncoding, Encoding, JsonLinesEncoding, Record, RecordFormat, TextEncoding},
+    ring_buffer::{Ignore, RingBuffer},
+    usb_writer::{UsbOutputMode, UsbWriter}
+};
+
+
+
+/// Output of records.
+///
+/// `push` must never block, when sink cannot accept the record (its consumer is stalled), the record is dropped by this sink only.
+/// Record is either accepted whole or not at all, so stalled sink never outputs partial records.
+pub trait Sink {
+    /// Returns `false` when the record was dropped.
+    fn push(&mut self, record: &Record) -> bool;
+}
+
+
+/// Sink writing directly into usb writer, encoding depends on usb output mode.
+/// Text mode uses encoding given by `format`, framed mode uses `BinaryEncoding` in `FrameType::Measurment` frame.
+///
+/// Record is written using single write, so with `RingBufferUsbWriter` it is never split (see `OverflowPolicy`).
+pub struct UsbSink<'w, W> {
+    usb_writer: &'w mut W,
+    format: RecordFormat,
+}
+
+impl<'w, W: Write + UsbWriter> UsbSink<'w, W> {
+    pub fn new(usb_writer: &'w mut W, format: RecordFormat) -> Self {
+        Self { usb_writer, format }
+    }
+}
+
+impl<'w, W: Write + UsbWriter> Sink for UsbSink<'w, W> {
+    fn push(&mut self, record: &Record) -> bool {
+        let mut encoded = Vec::new();
+
+        match self.usb_writer.output_mode() {
+            UsbOutputMode::Text => {
+                match self.format {
+                    RecordFormat::Text => TextEncoding.encode(record, &mut encoded),
+                    RecordFormat::Csv => CsvEncoding { ticks_per_second: SystemTimer::TICKS_PER_SECOND }.encode(record, &mut encoded),
+                    RecordFormat::JsonLines => JsonLinesEncoding { ticks_per_second: SystemTimer::TICKS_PER_SECOND }.encode(record, &mut encoded),
+                }
+
+                // text encodings produce only valid utf8
+                match core::str::from_utf8(&encoded) {
+                    Ok(text) => self.usb_writer.write_str(text).is_ok(),
+                    Err(_) => false,
+                }
+            },
+            UsbOutputMode::Framed => {
+                BinaryEncoding.encode(record, &mut encoded);
+                self.usb_writer.write_frame(FrameType::Measurment, &encoded).is_ok()
+            },
+        }
+    }
+}
+
+
+/// Sink with its own byte queue, drained by a transport (network, flash) at its own pace.
+///
+/// Queue is private for this sink, so stalled transport fills only this queue and does not affect other sinks.
+pub struct QueueSink<E, const N: usize> {
+    encoding: E,
+    queue: RingBuffer<u8, N, Ignore>,
+    dropped_records: u32,
+}
+
+impl<E: Encoding, const N: usize> QueueSink<E, N> {
+    pub fn new(encoding: E) -> Self {
+        Self {
+            encoding,
+            queue: RingBuffer::new(),
+            dropped_records: 0,
+        }
+    }
+
+    /// Number of records dropped because the queue was full.
+    pub fn dropped_records(&self) -> u32 {
+        self.dropped_records
+    }
+
+    /// Moves up to `out.len()` queued bytes into `out`, returns number of moved bytes.
+    pub fn drain_into(&mut self, out: &mut [u8]) -> usize {
+        out.iter_mut().map_while(|byte| self.queue.pop_front().map(|b| *byte = b)).count()
+    }
+}
+
+impl<E: Encoding, const N: usize> Sink for QueueSink<E, N> {
+    fn push(&mut self, record: &Record) -> bool {
+        let mut encoded = Vec::new();
+        self.encoding.encode(record, &mut encoded);
+
+        if self.queue.capacity() - self.queue.len() < encoded.len() {
+            self.dropped_records = self.dropped_records.saturating_add(1);
+            return false;
+        }
+
+        // cannot fail, there is enough free space
+        let _ = self.queue.extend_from_slice(&encoded);
+
+        true
+    }
+}
@@ -0,0 +1,168 @@
+/* bit-banged i2c controller for device which cannot share i2c0 (second scd30, address of scd30 is fixed) */
+
+use esp_hal::{
+    gpio::{any_pin::AnyPin, CreateErasedPin, InputPin, Level, OutputOpenDrain, OutputPin, Pull},
+    peripheral::Peripheral,
+    timer::systimer::SystemTimer
+};
+
+use crate::{
+    i2c_bus::{I2CBusUser, TransactionBus},
+    interrupts::I2CInterruptStatus,
+    invariants::invariant,
+    pac_utils::i2c::{self as i2c_utils, I2CTransmissionError, ShortRead},
+};
+
+// transfer state machine is tested on the host, this module only drives the pins
+use rust_esp_logic::soft_i2c::{self as transfer, Lines, SoftI2CError, Transfer};
+
+
+
+/// Open drain scl and sda pins (external pull ups).
+struct Pins<'a> {
+    scl: OutputOpenDrain<'a, AnyPin<'a>>,
+    sda: OutputOpenDrain<'a, AnyPin<'a>>,
+}
+
+impl Lines for Pins<'_> {
+    fn set_scl(&mut self, high: bool) {
+        self.scl.set_level(high.into());
+    }
+
+    fn set_sda(&mut self, high: bool) {
+        self.sda.set_level(high.into());
+    }
+
+    fn scl(&self) -> bool {
+        self.scl.is_high()
+    }
+
+    fn sda(&self) -> bool {
+        self.sda.is_high()
+    }
+
+    fn wait_half_period(&mut self) {
+        i2c_utils::wait_half_period();
+    }
+}
+
+
+/// Bus of one device on two gpio pins (open drain, external pull ups), ~100 kHz.
+///
+/// Transactions are not blocking, transaction started by `start_write` / `start_read` progresses on each
+/// `transaction_result` (see `soft_i2c::Transfer`), clock stretched by the device is waited for by later calls up to
+/// `STRETCH_TIMEOUT`. Machine using the bus keeps main loop running while a transaction is in progress (see `needs_poll`).
+///
+/// Bus is owned by a single machine, acquiring is kept only for the same usage as `I2CBus`.
+pub struct SoftI2CBus<'a> {
+    pins: Pins<'a>,
+    owner: Option<I2CBusUser>,
+    /// the last transaction, result is taken by `transaction_result`
+    transfer: Option<Transfer>,
+    result_taken: bool,
+    /// read bytes of the last transaction are taken from `response_pos` (same as rx fifo)
+    response_pos: usize,
+}
+
+impl<'a> SoftI2CBus<'a> {
+    /// scd30 stretches clock up to 30 ms (while measurment is being read)
+    pub const STRETCH_TIMEOUT: u64 = SystemTimer::TICKS_PER_SECOND / 20;
+
+
+    pub fn new<SCL, SDA>(scl_pin: impl Peripheral<P = SCL> + 'a, sda_pin: impl Peripheral<P = SDA> + 'a) -> Self
+    where
+        SCL: OutputPin + InputPin + CreateErasedPin,
+        SDA: OutputPin + InputPin + CreateErasedPin,
+    {
+        Self {
+            pins: Pins {
+                scl: OutputOpenDrain::new(AnyPin::new(scl_pin), Level::High, Pull::None),
+                sda: OutputOpenDrain::new(AnyPin::new(sda_pin), Level::High, Pull::None),
+            },
+            owner: None,
+            transfer: None,
+            result_taken: false,
+            response_pos: 0,
+        }
+    }
+
+    fn start(&mut self, transfer: Transfer) {
+        self.response_pos = 0;
+        self.result_taken = false;
+        self.transfer = Some(transfer);
+        // pulses until the first stretch are done right away, same as the blocking transaction
+        let _ = self.poll();
+    }
+
+    fn poll(&mut self) -> Option<Result<(), I2CTransmissionError>> {
+        let result = self.transfer.as_mut()?.poll(&mut self.pins, SystemTimer::now(), Self::STRETCH_TIMEOUT)?;
+
+        Some(result.map_err(|err| match err {
+            SoftI2CError::Nack => I2CTransmissionError::Nack(I2CInterruptStatus::NACK),
+            SoftI2CError::Timeout => I2CTransmissionError::Timeout(I2CInterruptStatus::TIME_OUT),
+        }))
+    }
+}
+
+impl TransactionBus for SoftI2CBus<'_> {
+    fn try_acquire(&mut self, user: I2CBusUser) -> bool {
+        match self.owner {
+            None => {
+                self.owner = Some(user);
+                true
+            },
+            Some(owner) => owner == user,
+        }
+    }
+
+    fn release(&mut self, user: I2CBusUser) {
+        if invariant!(self.owner == Some(user), "soft i2c bus released by user which does not own it") {
+            self.owner = None;
+        }
+    }
+
+    /// Clocks scl until the device releases sda (same as `i2c_utils::recover_bus`) and sends stop condition, transaction
+    /// in progress is abandoned.
+    fn recover(&mut self) -> bool {
+        self.transfer = None;
+
+        transfer::recover(&mut self.pins)
+    }
+
+    fn start_write(&mut self, address: u8, bytes: &[u8]) {
+        self.start(Transfer::write(address, bytes));
+    }
+
+    fn start_read(&mut self, address: u8, len: u8) {
+        self.start(Transfer::read(address, len as usize));
+    }
+
+    fn transaction_result(&mut self) -> Option<Result<(), I2CTransmissionError>> {
+        if self.result_taken {
+            return None;
+        }
+
+        let result = self.poll()?;
+        self.result_taken = true;
+
+        Some(result)
+    }
+
+    fn needs_poll(&self) -> bool {
+        self.transfer.is_some() && !self.result_taken
+    }
+
+    fn read_response_into(&mut self, buffer: &mut [u8]) -> Result<(), ShortRead> {
+        let response = self.transfer.as_ref().and_then(Transfer::response).unwrap_or(&[]);
+
+        let available = response.len() - self.response_pos;
+        if buffer.len() > available {
+            return Err(ShortRead { expected: buffer.len(), available });
+        }
+
+        buffer.copy_from_slice(&response[self.response_pos..][..buffer.len()]);
+        self.response_pos += buffer.len();
+
+        Ok(())
+    }
+}
@@ -0,0 +1,42 @@
+/* global wall clock, synchronized by console command `time <unix ms>` (network synchronization can use `set_unix_ms` too) */
+
+use core::cell::Cell;
+
+use critical_section::Mutex;
+use esp_hal::timer::systimer::SystemTimer;
+
+use crate::wall_clock::WallClock;
+
+
+
+static CLOCK: Mutex<Cell<WallClock>> = Mutex::new(Cell::new(WallClock::new(SystemTimer::TICKS_PER_SECOND)));
+
+
+fn with_clock<R>(f: impl FnOnce(&mut WallClock) -> R) -> R {
+    critical_section::with(|cs| {
+        let cell = CLOCK.borrow(cs);
+        let mut clock = cell.get();
+        let result = f(&mut clock);
+        cell.set(clock);
+        result
+    })
+}
+
+/// Current unix time in ms, `None` until the clock is synchronized.
+/// Clock has to be read at least once per system timer rollover (~ 8.9 years), measurments do that.
+pub fn now_unix_ms() -> Option<u64> {
+    with_clock(|clock| clock.unix_ms(SystemTimer::now()))
+}
+
+/// Unix time in ms of past system timer value `at` (e.g. timestamp of gpio edge), `None` until the clock is synchronized.
+pub fn unix_ms_at(at: u64) -> Option<u64> {
+    let now = SystemTimer::now();
+    // clock has to be read in non decreasing order, so the age is subtracted from current time
+    let age_ms = now.saturating_sub(at) / (SystemTimer::TICKS_PER_SECOND / 1_000);
+
+    with_clock(|clock| clock.unix_ms(now)).map(|unix_ms| unix_ms.saturating_sub(age_ms))
+}
+
+pub fn set_unix_ms(unix_ms: u64) {
+    with_clock(|clock| clock.set_unix_ms(SystemTimer::now(), unix_ms));
+}
@@ -0,0 +1,243 @@
+use core::fmt::Write;
+
+
+use esp_hal::{
+    clock::Clocks,
+    gpio::OutputPin,
+    peripheral::Peripheral,
+    peripherals::UART0,
+    timer::systimer::SystemTimer,
+    uart::{self, config::Config, Instance, UartTx},
+    Blocking,
+};
+
+
+use crate::{
+    framing::FrameType,
+    interrupts::{self, UARTInterruptStatus},
+    output_buffer::OutputBuffer,
+    qq_alarm_queue::QQAlarmQueue,
+    ring_buffer::RingBufferError,
+    usb_writer::{ByteSink, OverflowPolicy, UsbOutputMode, UsbWriter},
+};
+
+
+
+/// hardware tx fifo of uart0
+const FIFO_SIZE: u8 = 128;
+
+
+/// Writes `bytes` directly into the fifo of uart0 by spinning, bypassing the writer (panic message, see
+/// `panic::PanicOutput`).
+///
+/// Returns `false` when the fifo does not take any data for `timeout` (in system timer ticks).
+pub fn write_blocking(bytes: &[u8], timeout: u64) -> bool {
+    let uart = UART0::register_block();
+    let mut progress_at = SystemTimer::now();
+
+    for &byte in bytes {
+        while uart.status().read().txfifo_cnt().bits() >= FIFO_SIZE {
+            if SystemTimer::now() >= progress_at + timeout {
+                return false;
+            }
+        }
+
+        uart.fifo().write(|w| unsafe { w.rxfifo_rd_byte().bits(byte) }); // TODO: safety
+        progress_at = SystemTimer::now();
+    }
+
+    true
+}
+
+
+#[derive(Clone, Copy, Debug)]
+pub struct UartWriterConfig {
+    pub baudrate: u32,
+    pub overflow_policy: OverflowPolicy,
+    /// minimal delay between two "[n bytes dropped]" markers, in system timer ticks
+    pub drop_marker_period: Option<u64>,
+    pub output_mode: UsbOutputMode,
+}
+
+
+/// Host output over uart0 tx pin (plain uart adapter instead of usb serial jtag), buffered same as `RingBufferUsbWriter`.
+///
+/// Has the same interface as `RingBufferUsbWriter`, so it can replace it without changing machines (feature `uart-output`).
+/// Uart has no flow control, fifo is always drained at the baudrate, so the writer never timeouts and qq is not used.
+pub struct UartWriter<'a, const BUFFER_SIZE: usize> {
+    // keeps uart0 configured (baudrate, tx pin), registers are accessed directly
+    _uart: UartTx<'a, UART0, Blocking>,
+    output: OutputBuffer<BUFFER_SIZE>,
+}
+
+impl<'a, const BUFFER_SIZE: usize> UartWriter<'a, BUFFER_SIZE> {
+    pub fn new<TX: OutputPin>(
+        uart: impl Peripheral<P = UART0> + 'a,
+        tx: impl Peripheral<P = TX> + 'a,
+        clocks: &Clocks,
+        config: UartWriterConfig,
+    ) -> Result<Self, uart::Error> {
+        let uart = UartTx::new_with_config(uart, Config::default().baudrate(config.baudrate), clocks, None, tx)?;
+
+        Ok(Self {
+            _uart: uart,
+            output: OutputBuffer::new(config.overflow_policy, config.drop_marker_period, config.output_mode),
+        })
+    }
+
+    fn set_txfifo_empty_interrupt(enabled: bool) {
+        UART0::register_block().int_ena().modify(|_, w| w.txfifo_empty().bit(enabled));
+    }
+
+    /// Writes into the output buffer using `write`, starts sending when the buffer was empty before.
+    fn buffered<R>(&mut self, write: impl FnOnce(&mut OutputBuffer<BUFFER_SIZE>) -> R) -> R {
+        let empty_before = self.output.is_empty();
+
+        self.output.emit_drop_marker();
+        let result = write(&mut self.output);
+
+        if empty_before && !self.output.is_empty() {
+            // raised right away, fifo is below threshold
+            Self::set_txfifo_empty_interrupt(true);
+        }
+
+        result
+    }
+
+    /// Moves bytes into the fifo while there is free space, returns number of moved bytes.
+    fn fill_fifo(&mut self) -> usize {
+        let uart = UART0::register_block();
+        let mut count = 0;
+
+        while uart.status().read().txfifo_cnt().bits() < FIFO_SIZE && let Some(byte) = self.output.pop_front() {
+            uart.fifo().write(|w| unsafe { w.rxfifo_rd_byte().bits(byte) }); // TODO: safety
+            count += 1;
+        }
+
+        count
+    }
+
+    pub fn update(&mut self, _qq: &mut impl QQAlarmQueue) -> bool {
+        let pending_interrupts = interrupts::uart0_interrupt_get_and_clear(UARTInterruptStatus::TXFIFO_EMPTY);
+
+        if pending_interrupts.is_empty() {
+            return false;
+        }
+
+        self.fill_fifo();
+        self.output.emit_drop_marker();
+
+        // interrupt was disabled by the handler
+        if !self.output.is_empty() {
+            Self::set_txfifo_empty_interrupt(true);
+        }
+
+        true
+    }
+
+    pub fn on_alarm(&mut self, _qq_alarm_id: usize) -> bool {
+        false
+    }
+
+    /// Sends all buffered data (including incomplete log line) by spinning, without interrupts (panic and fatal paths).
+    ///
+    /// Returns `false` when the fifo does not take any data for `timeout` (in system timer ticks), which happens only
+    /// when uart is stopped.
+    pub fn flush_blocking(&mut self, timeout: u64) -> bool {
+        let _ = self.output.flush();
+
+        let mut progress_at = SystemTimer::now();
+
+        while !self.output.is_empty() {
+            if self.fill_fifo() != 0 {
+                progress_at = SystemTimer::now();
+            } else if SystemTimer::now() >= progress_at + timeout {
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+impl<'a, const BUFFER_SIZE: usize> ByteSink for UartWriter<'a, BUFFER_SIZE> {
+    fn write(&mut self, bytes: &[u8]) -> Result<(), RingBufferError> {
+        self.buffered(|output| output.write(bytes))
+    }
+
+    fn dropped_bytes(&self) -> u64 {
+        self.output.dropped_bytes()
+    }
+
+    fn free_space(&self) -> usize {
+        self.output.free_space()
+    }
+
+    fn buffered_len(&self) -> usize {
+        self.output.buffered_len()
+    }
+
+    fn high_water_mark(&self) -> usize {
+        self.output.high_water_mark()
+    }
+
+    fn reset_high_water_mark(&mut self) {
+        self.output.reset_high_water_mark();
+    }
+}
+
+impl<'a, const BUFFER_SIZE: usize> UsbWriter for UartWriter<'a, BUFFER_SIZE> {
+    fn is_timeouted(&self) -> bool {
+        false
+    }
+
+    fn timeout_count(&self) -> u32 {
+        0
+    }
+
+    fn output_mode(&self) -> UsbOutputMode {
+        self.output.output_mode()
+    }
+
+    fn set_output_mode(&mut self, output_mode: UsbOutputMode) {
+        self.buffered(|output| output.set_output_mode(output_mode));
+    }
+
+    fn set_overflow_policy(&mut self, overflow_policy: OverflowPolicy) {
+        self.output.set_overflow_policy(overflow_policy);
+    }
+
+    fn set_text_suppressed(&mut self, suppressed: bool) {
+        self.output.set_text_suppressed(suppressed);
+    }
+
+    fn write_frame(&mut self, frame_type: FrameType, payload: &[u8]) -> Result<(), RingBufferError> {
+        self.buffered(|output| output.write_frame(frame_type, payload))
+    }
+
+    fn flush(&mut self, _qq: &mut impl QQAlarmQueue) -> Result<(), RingBufferError> {
+        let result = self.buffered(|output| output.flush());
+        // no timeout bookkeeping, fifo interrupt handles whatever does not fit
+        self.fill_fifo();
+
+        result
+    }
+
+    fn update(&mut self, qq: &mut impl QQAlarmQueue) -> bool {
+        Self::update(self, qq)
+    }
+
+    fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        Self::on_alarm(self, qq_alarm_id)
+    }
+
+    fn flush_blocking(&mut self, timeout: u64) -> bool {
+        Self::flush_blocking(self, timeout)
+    }
+}
+
+impl<'a, const BUFFER_SIZE: usize> Write for UartWriter<'a, BUFFER_SIZE> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.buffered(|output| output.write_text(s)).map_err(|_| core::fmt::Error)
+    }
+}
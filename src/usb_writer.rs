@@ -1,70 +1,293 @@
 use core::fmt::Write;
 
 
-use esp_hal::{interrupt::Priority, peripheral::{Peripheral, PeripheralRef}, peripherals::USB_DEVICE, timer::systimer::SystemTimer};
+use esp_hal::{peripheral::{Peripheral, PeripheralRef}, peripherals::USB_DEVICE, timer::systimer::SystemTimer};
 
 
 use crate::{
+    error_registry::{self, Subsystem},
+    framing::FrameType,
     interrupts::{self, USBInterruptStatus},
-    qq_alarm_queue::QQAlarmQueue,
-    ring_buffer::{Ignore, RingBuffer, RingBufferError}
+    invariants::invariant,
+    output_buffer::OutputBuffer,
+    pac_utils::usb_serial,
+    qq_alarm_queue::{QQAlarmError, QQAlarmQueue},
+    ring_buffer::RingBufferError
 };
 
 
 
 
-pub trait UsbWriter {
+/// Buffered byte output to the host, independent of the hardware behind it.
+pub trait ByteSink {
     fn write(&mut self, bytes: &[u8]) -> Result<(), RingBufferError>;
-    fn is_timeouted(&self) -> bool; // TODO: should this be in this trait
+    /// total number of bytes dropped because of buffer overflow
+    fn dropped_bytes(&self) -> u64;
+    /// number of bytes which can be written without overflow
+    fn free_space(&self) -> usize;
+    fn buffered_len(&self) -> usize;
+    /// maximum number of buffered bytes since last `reset_high_water_mark`
+    fn high_water_mark(&self) -> usize;
+    fn reset_high_water_mark(&mut self);
+}
+
+/// Host output used by machines (text, frames, output mode), implemented by all backends (`RingBufferUsbWriter`, `UartWriter`).
+pub trait UsbWriter: ByteSink {
+    /// host stopped reading (always `false` for backends without flow control)
+    fn is_timeouted(&self) -> bool;
+    /// total number of timeouts (host did not read the data in time)
+    fn timeout_count(&self) -> u32;
+    fn output_mode(&self) -> UsbOutputMode;
+    fn set_output_mode(&mut self, output_mode: UsbOutputMode);
+    fn set_overflow_policy(&mut self, overflow_policy: OverflowPolicy);
+    /// Text output is dropped while it is suppressed, bytes and frames are still written (raw data of `MeasurmentDump`
+    /// would be broken by text of other machines).
+    fn set_text_suppressed(&mut self, suppressed: bool);
+    /// Writes whole frame (see `framing`), frame is written even in text mode.
+    fn write_frame(&mut self, frame_type: FrameType, payload: &[u8]) -> Result<(), RingBufferError>;
+    /// Sends incomplete line of text which is held back in framed mode, then moves buffered bytes (incomplete line of
+    /// text mode too) into the hardware right away when it is free, without waiting for the next `update`.
+    fn flush(&mut self, qq: &mut impl QQAlarmQueue) -> Result<(), RingBufferError>;
+    /// Moves buffered data to the hardware, called in each main loop iteration (see `Scheduler`).
+    fn update(&mut self, qq: &mut impl QQAlarmQueue) -> bool;
+    /// Alarms of `QQOwner::UsbWriter`, returns `true` when alarm was claimed.
+    fn on_alarm(&mut self, qq_alarm_id: usize) -> bool;
+    /// Sends all buffered data by spinning (paths which do not return to the main loop), returns `false` when the host
+    /// did not take the data in `timeout` (in system timer ticks).
+    fn flush_blocking(&mut self, timeout: u64) -> bool;
 }
 
 
 
+/// How is text output (`core::fmt::Write`) sent to the host.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum UsbOutputMode {
+    /// plain text
+    Text,
+    /// each line of text is sent as `FrameType::Log` frame, so it can be mixed with other frames
+    Framed,
+}
+
+/// What to do with data which do not fit into the buffer.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum OverflowPolicy {
+    /// oldest buffered bytes are dropped to make space for new data
+    DropOldest,
+    /// whole new write is rejected, buffered data are kept
+    RejectNewest,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RingBufferUsbWriterConfig {
+    /// in system timer ticks
+    pub timeout_delay: Option<u64>,
+    pub overflow_policy: OverflowPolicy,
+    /// minimal delay between two "[n bytes dropped]" markers, in system timer ticks
+    pub drop_marker_period: Option<u64>,
+    pub output_mode: UsbOutputMode,
+}
+
+
 #[derive(Clone, Copy, PartialEq, Eq, Debug)]
 enum TimeoutState {
     None,
-    Pending(u64), // start at
+    /// `qq_full` - adding of the alarm failed already (error is recorded once for each pending state)
+    Pending { start_at: u64, qq_full: bool },
     Active(usize), // qq alarm id
     Timeout,
 }
 
 
+/// Output buffer of the writer, with `usb-irq-refill` it is shared with usb handler in a static of fixed size
+/// (`interrupts::USB_OUTPUT_LEN`).
+#[cfg(not(feature = "usb-irq-refill"))]
+type Output<const BUFFER_SIZE: usize> = OutputBuffer<BUFFER_SIZE>;
+#[cfg(feature = "usb-irq-refill")]
+type Output<const BUFFER_SIZE: usize> = OutputBuffer<{ interrupts::USB_OUTPUT_LEN }>;
+
+
 /// usb writer, which uses ring buffer to buffer data
+///
+/// By default the main loop moves buffered bytes into the serial fifo after each `SERIAL_IN_EMPTY` interrupt (`update`).
+/// With `usb-irq-refill` the buffer is a static shared with usb handler (`interrupts::USB_OUTPUT`), writes go into it
+/// in critical sections and the handler moves next packet into the fifo itself. Main loop is then woken only when the
+/// buffer runs empty, timeout alarm is re-armed while the handler makes progress (`interrupts::usb_packet_count`).
+/// Both modes can be compared by `UsbBench` (throughput and main loop wakeups).
 pub struct RingBufferUsbWriter<'a, const BUFFER_SIZE: usize> {
     usb: PeripheralRef<'a, USB_DEVICE>,
-    buffer: RingBuffer<u8, BUFFER_SIZE, Ignore>,
+    #[cfg(not(feature = "usb-irq-refill"))]
+    output: OutputBuffer<BUFFER_SIZE>,
     timeout_state: TimeoutState,
     timeout_delay: u64,
+    timeout_count: u32,
+    /// `interrupts::usb_packet_count` when timeout alarm was armed
+    #[cfg(feature = "usb-irq-refill")]
+    packets_at_arm: u32,
+    /// last seen `interrupts::is_host_connected`
+    host_connected: bool,
 }
 
 impl<'a, const BUFFER_SIZE: usize> RingBufferUsbWriter<'a, BUFFER_SIZE> {
     const DEFAULT_TIMEOUT_DELAY: u64 = SystemTimer::TICKS_PER_SECOND / 1_000; // 1ms
 
+    /// size of the shared buffer is fixed, `BUFFER_SIZE` has to match it
+    #[cfg(feature = "usb-irq-refill")]
+    const SHARED_OUTPUT_CHECK: () = assert!(BUFFER_SIZE == interrupts::USB_OUTPUT_LEN, "usb writer buffer size differs from `interrupts::USB_OUTPUT_LEN`");
+
 
-    pub fn new(usb: impl Peripheral<P = USB_DEVICE> + 'a, timeout_delay: Option<u64>) -> Self {
-        Self::new_from_ref(usb.into_ref(), timeout_delay)
+    pub fn new(usb: impl Peripheral<P = USB_DEVICE> + 'a, config: RingBufferUsbWriterConfig) -> Self {
+        Self::new_from_ref(usb.into_ref(), config)
     }
 
-    pub fn new_from_ref(usb: PeripheralRef<'a, USB_DEVICE>, timeout_delay: Option<u64>) -> Self {
+    pub fn new_from_ref(usb: PeripheralRef<'a, USB_DEVICE>, config: RingBufferUsbWriterConfig) -> Self {
+        usb.int_ena().modify(|_, w| w.usb_bus_reset().set_bit());
+
+        let output = OutputBuffer::new(config.overflow_policy, config.drop_marker_period, config.output_mode);
+        #[cfg(feature = "usb-irq-refill")]
+        {
+            #[allow(clippy::let_unit_value)]
+            let () = Self::SHARED_OUTPUT_CHECK;
+            critical_section::with(|cs| interrupts::USB_OUTPUT.borrow_ref_mut(cs).replace(output));
+        }
+
         Self {
             usb,
-            buffer: RingBuffer::new(),
+            #[cfg(not(feature = "usb-irq-refill"))]
+            output,
             timeout_state: TimeoutState::None,
-            timeout_delay: timeout_delay.unwrap_or(Self::DEFAULT_TIMEOUT_DELAY),
+            timeout_delay: config.timeout_delay.unwrap_or(Self::DEFAULT_TIMEOUT_DELAY),
+            timeout_count: 0,
+            #[cfg(feature = "usb-irq-refill")]
+            packets_at_arm: 0,
+            host_connected: true,
+        }
+    }
+
+    /// Runs `f` with the output buffer (in critical section with `usb-irq-refill`, usb handler takes bytes from it).
+    fn with_output<R>(&mut self, f: impl FnOnce(&mut Output<BUFFER_SIZE>) -> R) -> R {
+        #[cfg(not(feature = "usb-irq-refill"))]
+        return f(&mut self.output);
+        #[cfg(feature = "usb-irq-refill")]
+        critical_section::with(|cs| f(interrupts::USB_OUTPUT.borrow_ref_mut(cs).as_mut().expect("usb output is set by usb writer")))
+    }
+
+    fn read_output<R>(&self, f: impl FnOnce(&Output<BUFFER_SIZE>) -> R) -> R {
+        #[cfg(not(feature = "usb-irq-refill"))]
+        return f(&self.output);
+        #[cfg(feature = "usb-irq-refill")]
+        critical_section::with(|cs| f(interrupts::USB_OUTPUT.borrow_ref(cs).as_ref().expect("usb output is set by usb writer")))
+    }
+
+    /// While host is not connected (see `interrupts::is_host_connected`) output is only counted as dropped by `discard`,
+    /// so the buffer does not fill up and time out again, "[n bytes dropped]" marker is written when the host connects.
+    fn write_output(
+        &mut self,
+        discard: impl FnOnce(&mut Output<BUFFER_SIZE>),
+        write: impl FnOnce(&mut Output<BUFFER_SIZE>) -> Result<(), RingBufferError>,
+    ) -> Result<(), RingBufferError> {
+        if !self.host_connected {
+            self.with_output(discard);
+            return Err(RingBufferError::Overflow);
+        }
+
+        let result = self.buffered(write);
+
+        // short stalls of the host only time out, full buffer means nobody reads the output, whole buffer would be
+        // stale when the host connects
+        if result.is_err() && self.timeout_state == TimeoutState::Timeout {
+            interrupts::usb_host_lost();
+            self.with_output(|output| output.discard_buffered());
+        }
+
+        result
+    }
+
+    fn update_host_connected(&mut self) -> bool {
+        let host_connected = interrupts::is_host_connected();
+        if host_connected == self.host_connected {
+            return false;
+        }
+
+        self.host_connected = host_connected;
+        // IN token comes with each poll of the host, it is needed only to detect the host
+        // (usb handler modifies enabled interrupts too with `usb-irq-refill`)
+        critical_section::with(|_| self.usb.int_ena().modify(|_, w| w.in_token_rec_in_ep1().bit(!host_connected)));
+
+        if host_connected {
+            self.buffered(|output| output.emit_drop_marker());
+        }
+
+        true
+    }
+
+    fn enable_fifo_interrupt(&self) {
+        // usb handler disables it with `usb-irq-refill`
+        critical_section::with(|_| self.usb.int_ena().modify(|_, w| w.serial_in_empty().set_bit())); // enable interupt
+    }
+
+    /// Writes into the output buffer using `write`, starts sending when the buffer was empty before.
+    fn buffered<R>(&mut self, write: impl FnOnce(&mut Output<BUFFER_SIZE>) -> R) -> R {
+        let (result, started) = self.with_output(|output| {
+            let empty_before = output.is_empty();
+            output.emit_drop_marker();
+            let result = write(output);
+
+            (result, empty_before && !output.is_empty())
+        });
+
+        if started {
+            // with `usb-irq-refill` the handler can empty the buffer before the writer handles its notification
+            #[cfg(not(feature = "usb-irq-refill"))]
+            invariant!(
+                matches!(self.timeout_state, TimeoutState::None | TimeoutState::Timeout),
+                "usb buffer empty but timeout is pending or active"
+            );
+
+            if self.timeout_state == TimeoutState::None {
+                self.timeout_state = TimeoutState::Pending { start_at: SystemTimer::now(), qq_full: false };
+            }
+
+            self.enable_fifo_interrupt();
         }
+
+        result
     }
 
-    pub fn enable_interrupt(&mut self) {
-        interrupts::usb_interrupt_enable(Some(Priority::Priority9));
+    fn arm_timeout(&mut self, qq: &mut impl QQAlarmQueue, at: u64) -> Result<usize, QQAlarmError> {
+        #[cfg(feature = "usb-irq-refill")]
+        {
+            self.packets_at_arm = interrupts::usb_packet_count();
+        }
+
+        qq.add(at)
     }
 
     pub fn update(&mut self, qq: &mut impl QQAlarmQueue) -> bool {
-        // currently only serial_in_empty interupt is possible
+        if self.update_host_connected() {
+            return true;
+        }
+
+        // host reads again after timeout, it is not notified until the buffer is empty
+        #[cfg(feature = "usb-irq-refill")]
+        if self.timeout_state == TimeoutState::Timeout && interrupts::usb_packet_count() != self.packets_at_arm {
+            self.timeout_state = TimeoutState::Pending { start_at: SystemTimer::now(), qq_full: false };
+        }
+
         let pending_interrupts = interrupts::usb_interrupt_get_and_clear(USBInterruptStatus::SERIAL_IN_EMPTY);
 
         if pending_interrupts.is_empty() {
-            if let TimeoutState::Pending(timeout_start) = self.timeout_state {
-                let qq_alarm_id = qq.add(timeout_start + self.timeout_delay).unwrap();
+            if let TimeoutState::Pending { start_at, qq_full } = self.timeout_state {
+                // when qq is full, state stays pending and adding is tried again in next update
+                let qq_alarm_id = match self.arm_timeout(qq, start_at + self.timeout_delay) {
+                    Ok(qq_alarm_id) => qq_alarm_id,
+                    Err(err) => {
+                        if !qq_full {
+                            error_registry::record_error(Subsystem::Qq, &err);
+                            self.timeout_state = TimeoutState::Pending { start_at, qq_full: true };
+                        }
+                        return false;
+                    },
+                };
                 self.timeout_state = TimeoutState::Active(qq_alarm_id);
 
                 true
@@ -72,72 +295,200 @@ impl<'a, const BUFFER_SIZE: usize> RingBufferUsbWriter<'a, BUFFER_SIZE> {
                 false
             }
         } else {
-            while self.usb.ep1_conf().read().serial_in_ep_data_free().bit_is_set() {
-                match self.buffer.pop_front() {
-                    Some(byte) => {
-                        self.usb.ep1().write(|w| unsafe { w.rdwr_byte().bits(byte) }); // TODO: safety
-                    },
-                    None => {
-                        self.usb.ep1_conf().write(|w| w.wr_done().set_bit()); // flush
-                        break;
-                    }
-                }
-            }
+            // with `usb-irq-refill` the handler already moved all buffered bytes into the fifo
+            #[cfg(not(feature = "usb-irq-refill"))]
+            usb_serial::write_packet(&self.usb, self.output.buffer_mut());
 
-            // TODO: cannot be None
-            if let TimeoutState::Active(qq_alarm_id) = self.timeout_state {
-                qq.remove(qq_alarm_id).unwrap();
-            }
+            self.after_packet(qq);
 
-            if self.buffer.len() == 0 {
-                self.usb.int_ena().modify(|_, w| w.serial_in_empty().clear_bit()); // disable interupt
+            true
+        }
+    }
 
-                self.timeout_state = TimeoutState::None;
-            } else {
-                let qq_alarm_id = qq.add(SystemTimer::now()).unwrap();
-                self.timeout_state = TimeoutState::Active(qq_alarm_id);
-            }
+    /// Timeout and fifo interrupt bookkeeping after packet was moved into the fifo.
+    fn after_packet(&mut self, qq: &mut impl QQAlarmQueue) {
+        // interrupt is enabled only while buffer is non empty, so timeout state was set by `write`
+        invariant!(self.timeout_state != TimeoutState::None, "usb interrupt while timeout state is None");
 
-            true
+        if let TimeoutState::Active(qq_alarm_id) = self.timeout_state {
+            invariant!(qq.remove(qq_alarm_id).is_ok(), "usb timeout alarm not found in qq");
         }
+
+        let empty = self.with_output(|output| {
+            output.emit_drop_marker();
+            output.is_empty()
+        });
+
+        if empty {
+            // usb handler disabled it already with `usb-irq-refill`
+            #[cfg(not(feature = "usb-irq-refill"))]
+            self.usb.int_ena().modify(|_, w| w.serial_in_empty().clear_bit()); // disable interupt
+
+            self.timeout_state = TimeoutState::None;
+        } else {
+            // drop marker was written after the handler disabled the interrupt (`usb-irq-refill`)
+            #[cfg(feature = "usb-irq-refill")]
+            self.enable_fifo_interrupt();
+
+            self.timeout_state = match self.arm_timeout(qq, SystemTimer::now()) {
+                Ok(qq_alarm_id) => TimeoutState::Active(qq_alarm_id),
+                Err(err) => {
+                    error_registry::record_error(Subsystem::Qq, &err);
+                    TimeoutState::Pending { start_at: SystemTimer::now(), qq_full: true }
+                },
+            };
+        }
+    }
+
+    /// See `UsbWriter::flush`.
+    pub fn flush(&mut self, qq: &mut impl QQAlarmQueue) -> Result<(), RingBufferError> {
+        let result = self.buffered(|output| output.flush());
+
+        // with `usb-irq-refill` the handler moves packets into the fifo as soon as it is free
+        #[cfg(not(feature = "usb-irq-refill"))]
+        if self.host_connected && usb_serial::write_packet(&self.usb, self.output.buffer_mut()) != 0 {
+            // fifo was free, its pending interrupt is handled here (fifo is busy now, so it is not raised again)
+            let _ = interrupts::usb_interrupt_get_and_clear(USBInterruptStatus::SERIAL_IN_EMPTY);
+            self.after_packet(qq);
+        }
+        #[cfg(feature = "usb-irq-refill")]
+        let _ = qq;
+
+        result
     }
 
     pub fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
         if let TimeoutState::Active(id) = self.timeout_state && id == qq_alarm_id {
+            // handler moved packets into the fifo since the alarm was armed, host is reading
+            #[cfg(feature = "usb-irq-refill")]
+            if interrupts::usb_packet_count() != self.packets_at_arm {
+                self.timeout_state = TimeoutState::Pending { start_at: SystemTimer::now(), qq_full: false };
+                return true;
+            }
+
             self.timeout_state = TimeoutState::Timeout;
+            self.timeout_count = self.timeout_count.saturating_add(1);
 
             true
         } else {
             false
         }
     }
+
+    /// Moves up to one packet into the fifo when it is free, returns number of moved bytes.
+    fn fill_fifo(&mut self) -> usize {
+        let usb = &self.usb;
+
+        #[cfg(not(feature = "usb-irq-refill"))]
+        return usb_serial::write_packet(usb, self.output.buffer_mut());
+        #[cfg(feature = "usb-irq-refill")]
+        critical_section::with(|cs| {
+            interrupts::USB_OUTPUT.borrow_ref_mut(cs).as_mut().map_or(0, |output| usb_serial::write_packet(usb, output.buffer_mut()))
+        })
+    }
+
+    /// Sends all buffered data (including incomplete log line) by spinning, without interrupts and qq (panic and fatal paths).
+    ///
+    /// Gives up when the host does not take any data for `timeout` (in system timer ticks), the writer is then timeouted
+    /// same as after timeout alarm. When the writer is already timeouted, only what fits into the fifo is sent (no waiting).
+    /// Returns `true` when the buffer was emptied. Meant for paths which do not return to the main loop (timeout alarm is not removed).
+    pub fn flush_blocking(&mut self, timeout: u64) -> bool {
+        let _ = self.with_output(|output| output.flush());
+
+        let wait = self.timeout_state != TimeoutState::Timeout;
+        let mut progress_at = SystemTimer::now();
+
+        while !self.read_output(|output| output.is_empty()) {
+            if self.fill_fifo() != 0 {
+                progress_at = SystemTimer::now();
+            } else if !wait || SystemTimer::now() >= progress_at + timeout {
+                if self.timeout_state != TimeoutState::Timeout {
+                    self.timeout_state = TimeoutState::Timeout;
+                    self.timeout_count = self.timeout_count.saturating_add(1);
+                }
+
+                return false;
+            }
+        }
+
+        true
+    }
 }
 
-impl<'a, const BUFFER_SIZE: usize> UsbWriter for RingBufferUsbWriter<'a, BUFFER_SIZE> {
+impl<'a, const BUFFER_SIZE: usize> ByteSink for RingBufferUsbWriter<'a, BUFFER_SIZE> {
     fn write(&mut self, bytes: &[u8]) -> Result<(), RingBufferError> {
-        let empty_before = self.buffer.len() == 0;
+        self.write_output(|output| output.discard(bytes.len()), |output| output.write(bytes))
+    }
 
-        self.buffer.extend_from_slice(bytes)?;
+    fn dropped_bytes(&self) -> u64 {
+        self.read_output(|output| output.dropped_bytes())
+    }
 
-        if empty_before {
-            // TODO: must be None or Timeout before
-            if self.timeout_state == TimeoutState::None {
-                self.timeout_state = TimeoutState::Pending(SystemTimer::now());
-            }
+    fn free_space(&self) -> usize {
+        self.read_output(|output| output.free_space())
+    }
 
-            self.usb.int_ena().modify(|_, w| w.serial_in_empty().set_bit()); // enable interupt
-        }
+    fn buffered_len(&self) -> usize {
+        self.read_output(|output| output.buffered_len())
+    }
+
+    fn high_water_mark(&self) -> usize {
+        self.read_output(|output| output.high_water_mark())
+    }
 
-        Ok(())
+    fn reset_high_water_mark(&mut self) {
+        self.with_output(|output| output.reset_high_water_mark());
     }
+}
 
+impl<'a, const BUFFER_SIZE: usize> UsbWriter for RingBufferUsbWriter<'a, BUFFER_SIZE> {
     fn is_timeouted(&self) -> bool {
         self.timeout_state == TimeoutState::Timeout
     }
+
+    fn timeout_count(&self) -> u32 {
+        self.timeout_count
+    }
+
+    fn output_mode(&self) -> UsbOutputMode {
+        self.read_output(|output| output.output_mode())
+    }
+
+    fn set_output_mode(&mut self, output_mode: UsbOutputMode) {
+        self.buffered(|output| output.set_output_mode(output_mode));
+    }
+
+    fn set_overflow_policy(&mut self, overflow_policy: OverflowPolicy) {
+        self.with_output(|output| output.set_overflow_policy(overflow_policy));
+    }
+
+    fn set_text_suppressed(&mut self, suppressed: bool) {
+        self.with_output(|output| output.set_text_suppressed(suppressed));
+    }
+
+    fn write_frame(&mut self, frame_type: FrameType, payload: &[u8]) -> Result<(), RingBufferError> {
+        self.write_output(|output| output.discard_frame(frame_type, payload), |output| output.write_frame(frame_type, payload))
+    }
+
+    fn flush(&mut self, qq: &mut impl QQAlarmQueue) -> Result<(), RingBufferError> {
+        Self::flush(self, qq)
+    }
+
+    fn update(&mut self, qq: &mut impl QQAlarmQueue) -> bool {
+        Self::update(self, qq)
+    }
+
+    fn on_alarm(&mut self, qq_alarm_id: usize) -> bool {
+        Self::on_alarm(self, qq_alarm_id)
+    }
+
+    fn flush_blocking(&mut self, timeout: u64) -> bool {
+        Self::flush_blocking(self, timeout)
+    }
 }
 
 impl<'a, const BUFFER_SIZE: usize> Write for RingBufferUsbWriter<'a, BUFFER_SIZE> {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
-        self.write(s.as_bytes()).map_err(|_| core::fmt::Error)
+        self.write_output(|output| output.discard(s.len()), |output| output.write_text(s)).map_err(|_| core::fmt::Error)
     }
 }
\ No newline at end of file